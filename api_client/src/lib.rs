@@ -5,10 +5,13 @@
 
 use std::fmt;
 use std::io::{Read, Write};
+use std::os::unix::io::RawFd;
+use vmm_sys_util::sock_ctrl_msg::ScmSocket;
 
 #[derive(Debug)]
 pub enum Error {
     Socket(std::io::Error),
+    SocketSendFds(vmm_sys_util::errno::Error),
     StatusCodeParsing(std::num::ParseIntError),
     MissingProtocol,
     ContentLengthParsing(std::num::ParseIntError),
@@ -20,6 +23,7 @@ impl fmt::Display for Error {
         use Error::*;
         match self {
             Socket(e) => write!(f, "Error writing to or reading from HTTP socket: {}", e),
+            SocketSendFds(e) => write!(f, "Error writing to HTTP socket with fds: {}", e),
             StatusCodeParsing(e) => write!(f, "Error parsing HTTP status code: {}", e),
             MissingProtocol => write!(f, "HTTP output is missing protocol statement"),
             ContentLengthParsing(e) => write!(f, "Error parsing HTTP Content-Length field: {}", e),
@@ -133,22 +137,58 @@ fn parse_http_response(socket: &mut dyn Read) -> Result<Option<String>, Error> {
     }
 }
 
+fn request_line(method: &str, full_command: &str) -> String {
+    format!(
+        "{} /api/v1/{} HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n",
+        method, full_command
+    )
+}
+
 pub fn simple_api_command<T: Read + Write>(
     socket: &mut T,
     method: &str,
     c: &str,
     request_body: Option<&str>,
+) -> Result<(), Error> {
+    simple_api_full_command(socket, method, &format!("vm.{}", c), request_body)
+}
+
+pub fn simple_api_full_command<T: Read + Write>(
+    socket: &mut T,
+    method: &str,
+    full_command: &str,
+    request_body: Option<&str>,
 ) -> Result<(), Error> {
     socket
-        .write_all(
-            format!(
-                "{} /api/v1/vm.{} HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n",
-                method, c
-            )
-            .as_bytes(),
-        )
+        .write_all(request_line(method, full_command).as_bytes())
         .map_err(Error::Socket)?;
 
+    complete_request(socket, request_body)
+}
+
+/// Send the request along with the given file descriptors, which the server
+/// receives with the beginning of the request.
+pub fn simple_api_full_command_with_fds<T: Read + Write + ScmSocket>(
+    socket: &mut T,
+    method: &str,
+    full_command: &str,
+    request_body: Option<&str>,
+    request_fds: &[RawFd],
+) -> Result<(), Error> {
+    socket
+        .send_with_fds(
+            &[request_line(method, full_command).as_bytes()],
+            request_fds,
+        )
+        .map_err(Error::SocketSendFds)?;
+
+    complete_request(socket, request_body)
+}
+
+fn complete_request<T: Read + Write>(
+    socket: &mut T,
+    request_body: Option<&str>,
+) -> Result<(), Error> {
     if let Some(request_body) = request_body {
         socket
             .write_all(format!("Content-Length: {}\r\n", request_body.len()).as_bytes())
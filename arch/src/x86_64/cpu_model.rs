@@ -0,0 +1,602 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Named CPU models and CPUID feature masking.
+//!
+//! A CPU model is a baseline of CPUID features a guest can rely on, whatever
+//! the host generation it runs on, as long as the host supports the baseline.
+//! Exposing the same model on hosts from different generations allows guests
+//! to be migrated between them.
+//!
+//! Hiding a CPUID feature also hides the MSRs it enumerates, as KVM refuses
+//! guest accesses to MSRs whose feature isn't exposed through CPUID (e.g.
+//! `IA32_SPEC_CTRL`, `IA32_ARCH_CAPABILITIES`, `IA32_XSS` or `IA32_PKRS`).
+
+use super::{CpuidPatch, CpuidReg, Error};
+use hypervisor::CpuId;
+
+struct CpuFeature {
+    name: &'static str,
+    function: u32,
+    index: u32,
+    reg: CpuidReg,
+    bit: u8,
+}
+
+macro_rules! features {
+    ($($name:expr => ($function:expr, $index:expr, $reg:ident, $bit:expr),)*) => {
+        &[$(CpuFeature {
+            name: $name,
+            function: $function,
+            index: $index,
+            reg: CpuidReg::$reg,
+            bit: $bit,
+        },)*]
+    };
+}
+
+// Features of the CPUID registers being masked according to the CPU model.
+// Any bit of these registers that isn't listed here is hidden from the guest
+// as soon as a model is selected.
+const CPU_FEATURES: &[CpuFeature] = features![
+    "sse3" => (0x1, 0, ECX, 0),
+    "pclmulqdq" => (0x1, 0, ECX, 1),
+    "vmx" => (0x1, 0, ECX, 5),
+    "ssse3" => (0x1, 0, ECX, 9),
+    "fma" => (0x1, 0, ECX, 12),
+    "cx16" => (0x1, 0, ECX, 13),
+    "pdcm" => (0x1, 0, ECX, 15),
+    "pcid" => (0x1, 0, ECX, 17),
+    "sse4_1" => (0x1, 0, ECX, 19),
+    "sse4_2" => (0x1, 0, ECX, 20),
+    "x2apic" => (0x1, 0, ECX, 21),
+    "movbe" => (0x1, 0, ECX, 22),
+    "popcnt" => (0x1, 0, ECX, 23),
+    "tsc_deadline_timer" => (0x1, 0, ECX, 24),
+    "aes" => (0x1, 0, ECX, 25),
+    "xsave" => (0x1, 0, ECX, 26),
+    "osxsave" => (0x1, 0, ECX, 27),
+    "avx" => (0x1, 0, ECX, 28),
+    "f16c" => (0x1, 0, ECX, 29),
+    "rdrand" => (0x1, 0, ECX, 30),
+    "hypervisor" => (0x1, 0, ECX, 31),
+    "fpu" => (0x1, 0, EDX, 0),
+    "vme" => (0x1, 0, EDX, 1),
+    "de" => (0x1, 0, EDX, 2),
+    "pse" => (0x1, 0, EDX, 3),
+    "tsc" => (0x1, 0, EDX, 4),
+    "msr" => (0x1, 0, EDX, 5),
+    "pae" => (0x1, 0, EDX, 6),
+    "mce" => (0x1, 0, EDX, 7),
+    "cx8" => (0x1, 0, EDX, 8),
+    "apic" => (0x1, 0, EDX, 9),
+    "sep" => (0x1, 0, EDX, 11),
+    "mtrr" => (0x1, 0, EDX, 12),
+    "pge" => (0x1, 0, EDX, 13),
+    "mca" => (0x1, 0, EDX, 14),
+    "cmov" => (0x1, 0, EDX, 15),
+    "pat" => (0x1, 0, EDX, 16),
+    "pse36" => (0x1, 0, EDX, 17),
+    "clflush" => (0x1, 0, EDX, 19),
+    "mmx" => (0x1, 0, EDX, 23),
+    "fxsr" => (0x1, 0, EDX, 24),
+    "sse" => (0x1, 0, EDX, 25),
+    "sse2" => (0x1, 0, EDX, 26),
+    "ss" => (0x1, 0, EDX, 27),
+    "ht" => (0x1, 0, EDX, 28),
+    "fsgsbase" => (0x7, 0, EBX, 0),
+    "tsc_adjust" => (0x7, 0, EBX, 1),
+    "sgx" => (0x7, 0, EBX, 2),
+    "bmi1" => (0x7, 0, EBX, 3),
+    "hle" => (0x7, 0, EBX, 4),
+    "avx2" => (0x7, 0, EBX, 5),
+    "smep" => (0x7, 0, EBX, 7),
+    "bmi2" => (0x7, 0, EBX, 8),
+    "erms" => (0x7, 0, EBX, 9),
+    "invpcid" => (0x7, 0, EBX, 10),
+    "rtm" => (0x7, 0, EBX, 11),
+    "mpx" => (0x7, 0, EBX, 14),
+    "avx512f" => (0x7, 0, EBX, 16),
+    "avx512dq" => (0x7, 0, EBX, 17),
+    "rdseed" => (0x7, 0, EBX, 18),
+    "adx" => (0x7, 0, EBX, 19),
+    "smap" => (0x7, 0, EBX, 20),
+    "avx512ifma" => (0x7, 0, EBX, 21),
+    "clflushopt" => (0x7, 0, EBX, 23),
+    "clwb" => (0x7, 0, EBX, 24),
+    "intel_pt" => (0x7, 0, EBX, 25),
+    "avx512pf" => (0x7, 0, EBX, 26),
+    "avx512er" => (0x7, 0, EBX, 27),
+    "avx512cd" => (0x7, 0, EBX, 28),
+    "sha_ni" => (0x7, 0, EBX, 29),
+    "avx512bw" => (0x7, 0, EBX, 30),
+    "avx512vl" => (0x7, 0, EBX, 31),
+    "avx512vbmi" => (0x7, 0, ECX, 1),
+    "umip" => (0x7, 0, ECX, 2),
+    "pku" => (0x7, 0, ECX, 3),
+    "ospke" => (0x7, 0, ECX, 4),
+    "waitpkg" => (0x7, 0, ECX, 5),
+    "avx512_vbmi2" => (0x7, 0, ECX, 6),
+    "gfni" => (0x7, 0, ECX, 8),
+    "vaes" => (0x7, 0, ECX, 9),
+    "vpclmulqdq" => (0x7, 0, ECX, 10),
+    "avx512_vnni" => (0x7, 0, ECX, 11),
+    "avx512_bitalg" => (0x7, 0, ECX, 12),
+    "avx512_vpopcntdq" => (0x7, 0, ECX, 14),
+    "la57" => (0x7, 0, ECX, 16),
+    "rdpid" => (0x7, 0, ECX, 22),
+    "cldemote" => (0x7, 0, ECX, 25),
+    "movdiri" => (0x7, 0, ECX, 27),
+    "movdir64b" => (0x7, 0, ECX, 28),
+    "sgx_lc" => (0x7, 0, ECX, 30),
+    "avx512_4vnniw" => (0x7, 0, EDX, 2),
+    "avx512_4fmaps" => (0x7, 0, EDX, 3),
+    "fsrm" => (0x7, 0, EDX, 4),
+    "avx512_vp2intersect" => (0x7, 0, EDX, 8),
+    "md_clear" => (0x7, 0, EDX, 10),
+    "serialize" => (0x7, 0, EDX, 14),
+    "tsxldtrk" => (0x7, 0, EDX, 16),
+    "amx_bf16" => (0x7, 0, EDX, 22),
+    "avx512_fp16" => (0x7, 0, EDX, 23),
+    "amx_tile" => (0x7, 0, EDX, 24),
+    "amx_int8" => (0x7, 0, EDX, 25),
+    "spec_ctrl" => (0x7, 0, EDX, 26),
+    "intel_stibp" => (0x7, 0, EDX, 27),
+    "flush_l1d" => (0x7, 0, EDX, 28),
+    "arch_capabilities" => (0x7, 0, EDX, 29),
+    "spec_ctrl_ssbd" => (0x7, 0, EDX, 31),
+    "xsaveopt" => (0xd, 1, EAX, 0),
+    "xsavec" => (0xd, 1, EAX, 1),
+    "xgetbv1" => (0xd, 1, EAX, 2),
+    "xsaves" => (0xd, 1, EAX, 3),
+    "lahf_lm" => (0x8000_0001, 0, ECX, 0),
+    "abm" => (0x8000_0001, 0, ECX, 5),
+    "3dnowprefetch" => (0x8000_0001, 0, ECX, 8),
+    "syscall" => (0x8000_0001, 0, EDX, 11),
+    "nx" => (0x8000_0001, 0, EDX, 20),
+    "pdpe1gb" => (0x8000_0001, 0, EDX, 26),
+    "rdtscp" => (0x8000_0001, 0, EDX, 27),
+    "lm" => (0x8000_0001, 0, EDX, 29),
+];
+
+// Features exposed whenever the host supports them, whatever the model.
+// They are either forced by the VMM, reflect the guest state, depend on
+// other VMM options, or are vulnerability mitigations the guest must not
+// lose. A migration is still refused if the destination lacks one of them.
+const HOST_FEATURES: &[&str] = &[
+    "tsc_deadline_timer",
+    "hypervisor",
+    "mtrr",
+    "osxsave",
+    "ospke",
+    "vmx",
+    "pdcm",
+    "ht",
+    "tsc_adjust",
+    "sgx",
+    "sgx_lc",
+    "md_clear",
+    "spec_ctrl",
+    "intel_stibp",
+    "flush_l1d",
+    "arch_capabilities",
+    "spec_ctrl_ssbd",
+];
+
+struct CpuModel {
+    name: &'static str,
+    base: Option<&'static str>,
+    features: &'static [&'static str],
+}
+
+const CPU_MODELS: &[CpuModel] = &[
+    CpuModel {
+        name: "sandybridge",
+        base: None,
+        features: &[
+            "fpu",
+            "vme",
+            "de",
+            "pse",
+            "tsc",
+            "msr",
+            "pae",
+            "mce",
+            "cx8",
+            "apic",
+            "sep",
+            "pge",
+            "mca",
+            "cmov",
+            "pat",
+            "pse36",
+            "clflush",
+            "mmx",
+            "fxsr",
+            "sse",
+            "sse2",
+            "sse3",
+            "pclmulqdq",
+            "ssse3",
+            "cx16",
+            "pcid",
+            "sse4_1",
+            "sse4_2",
+            "x2apic",
+            "popcnt",
+            "aes",
+            "xsave",
+            "avx",
+            "xsaveopt",
+            "lahf_lm",
+            "syscall",
+            "nx",
+            "pdpe1gb",
+            "rdtscp",
+            "lm",
+        ],
+    },
+    CpuModel {
+        name: "haswell",
+        base: Some("sandybridge"),
+        features: &[
+            "fma", "movbe", "f16c", "rdrand", "fsgsbase", "bmi1", "avx2", "smep", "bmi2", "erms",
+            "invpcid", "abm",
+        ],
+    },
+    CpuModel {
+        name: "skylake",
+        base: Some("haswell"),
+        features: &[
+            "3dnowprefetch",
+            "rdseed",
+            "adx",
+            "smap",
+            "clflushopt",
+            "clwb",
+            "avx512f",
+            "avx512dq",
+            "avx512cd",
+            "avx512bw",
+            "avx512vl",
+            "pku",
+            "xsavec",
+            "xgetbv1",
+        ],
+    },
+    CpuModel {
+        name: "cascadelake",
+        base: Some("skylake"),
+        features: &["avx512_vnni"],
+    },
+    CpuModel {
+        name: "icelake",
+        base: Some("cascadelake"),
+        features: &[
+            "avx512vbmi",
+            "umip",
+            "avx512_vbmi2",
+            "gfni",
+            "vaes",
+            "vpclmulqdq",
+            "avx512_bitalg",
+            "avx512_vpopcntdq",
+            "rdpid",
+            "sha_ni",
+            "fsrm",
+        ],
+    },
+];
+
+// XSAVE state components enabled by a feature, reported through CPUID leaf
+// 0xd, subleaf 0, EAX.
+const XSAVE_COMPONENTS: &[(&str, u32)] = &[
+    ("avx", 1 << 2),
+    ("mpx", (1 << 3) | (1 << 4)),
+    ("avx512f", (1 << 5) | (1 << 6) | (1 << 7)),
+    ("pku", 1 << 9),
+    ("amx_tile", (1 << 17) | (1 << 18)),
+];
+
+fn find_feature(name: &str) -> Option<&'static CpuFeature> {
+    CPU_FEATURES.iter().find(|f| f.name == name)
+}
+
+fn find_model(name: &str) -> Option<&'static CpuModel> {
+    CPU_MODELS.iter().find(|m| m.name == name)
+}
+
+/// Check whether `name` is a known CPU model.
+pub fn is_cpu_model(name: &str) -> bool {
+    find_model(name).is_some()
+}
+
+/// Check whether `name` is a known CPU feature.
+pub fn is_cpu_feature(name: &str) -> bool {
+    find_feature(name).is_some()
+}
+
+fn model_features(model: &CpuModel) -> Vec<&'static str> {
+    let mut features = model
+        .base
+        .and_then(find_model)
+        .map(model_features)
+        .unwrap_or_default();
+    features.extend_from_slice(model.features);
+    features
+}
+
+fn feature_enabled(cpuid: &CpuId, feature: &CpuFeature) -> bool {
+    CpuidPatch::is_feature_enabled(
+        cpuid,
+        feature.function,
+        feature.index,
+        feature.reg,
+        feature.bit as usize,
+    )
+}
+
+fn reg_matches(feature: &CpuFeature, function: u32, index: u32, reg: CpuidReg) -> bool {
+    feature.function == function
+        && feature.index == index
+        && std::mem::discriminant(&feature.reg) == std::mem::discriminant(&reg)
+}
+
+/// Mask the CPUID features according to a CPU model and a list of features
+/// to explicitly enable (`+<feature>`) or disable (`-<feature>`).
+///
+/// Without a model, only the disabled features are hidden from the guest.
+/// With a model, every feature that is neither part of the model nor
+/// explicitly enabled is hidden, and an error is returned if the host doesn't
+/// support all the expected features.
+pub fn apply_cpu_model(
+    cpuid: &mut CpuId,
+    model: Option<&str>,
+    features: &[String],
+) -> Result<(), Error> {
+    let mut enabled = Vec::new();
+    let mut disabled = Vec::new();
+    for feature in features {
+        let (list, name) = if let Some(name) = feature.strip_prefix('+') {
+            (&mut enabled, name)
+        } else if let Some(name) = feature.strip_prefix('-') {
+            (&mut disabled, name)
+        } else {
+            return Err(Error::UnknownCpuFeature(feature.clone()));
+        };
+        let feature =
+            find_feature(name).ok_or_else(|| Error::UnknownCpuFeature(name.to_owned()))?;
+        list.push(feature.name);
+    }
+
+    let mut expected = match model {
+        Some(name) => {
+            model_features(find_model(name).ok_or_else(|| Error::UnknownCpuModel(name.to_owned()))?)
+        }
+        None => Vec::new(),
+    };
+    expected.extend_from_slice(&enabled);
+    expected.retain(|name| !disabled.contains(name));
+
+    // Features allowed to be exposed to the guest.
+    let allowed = |feature: &CpuFeature| {
+        if disabled.contains(&feature.name) {
+            false
+        } else if model.is_some() {
+            expected.contains(&feature.name) || HOST_FEATURES.contains(&feature.name)
+        } else {
+            true
+        }
+    };
+
+    for entry in cpuid.as_mut_slice().iter_mut() {
+        let (function, index) = (entry.function, entry.index);
+        for (reg, value) in [
+            (CpuidReg::EAX, &mut entry.eax),
+            (CpuidReg::EBX, &mut entry.ebx),
+            (CpuidReg::ECX, &mut entry.ecx),
+            (CpuidReg::EDX, &mut entry.edx),
+        ]
+        .iter_mut()
+        {
+            let reg_features: Vec<&CpuFeature> = CPU_FEATURES
+                .iter()
+                .filter(|f| reg_matches(f, function, index, *reg))
+                .collect();
+            if reg_features.is_empty() {
+                continue;
+            }
+
+            let mask = reg_features
+                .iter()
+                .filter(|f| allowed(f))
+                .fold(0u32, |mask, f| mask | (1 << f.bit));
+            if model.is_some() {
+                **value &= mask;
+            } else {
+                let hidden = reg_features
+                    .iter()
+                    .fold(0u32, |hidden, f| hidden | (1 << f.bit))
+                    & !mask;
+                **value &= !hidden;
+            }
+        }
+    }
+
+    // Don't report XSAVE state components of hidden features.
+    let xsave_components = XSAVE_COMPONENTS
+        .iter()
+        .filter(|(name, _)| !feature_enabled(cpuid, find_feature(name).unwrap()))
+        .fold(0u32, |components, (_, c)| components | c);
+    for entry in cpuid.as_mut_slice().iter_mut() {
+        if entry.function == 0xd && entry.index == 0 {
+            entry.eax &= !xsave_components;
+        }
+    }
+
+    let missing: Vec<String> = expected
+        .iter()
+        .filter(|name| !feature_enabled(cpuid, find_feature(name).unwrap()))
+        .map(|name| (*name).to_owned())
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::MissingCpuFeatures(missing));
+    }
+
+    Ok(())
+}
+
+/// Check that every CPUID feature exposed to the guest on the source host
+/// can also be exposed on the destination host of a migration.
+pub fn check_cpuid_compatibility(source: &CpuId, destination: &CpuId) -> Result<(), Error> {
+    let missing: Vec<String> = CPU_FEATURES
+        .iter()
+        .filter(|f| feature_enabled(source, f) && !feature_enabled(destination, f))
+        .map(|f| f.name.to_owned())
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::MissingCpuFeatures(missing));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hypervisor::CpuIdEntry;
+
+    fn host_cpuid(model: &str) -> CpuId {
+        let mut cpuid = CpuId::from_entries(&[
+            CpuIdEntry {
+                function: 0x1,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x7,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0xd,
+                // x87, SSE, AVX and AVX-512 state components
+                eax: 0xe7,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0xd,
+                index: 1,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x8000_0001,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let mut features = model_features(find_model(model).unwrap());
+        features.extend_from_slice(HOST_FEATURES);
+        // Not part of any model
+        features.push("rtm");
+        for feature in features.iter().map(|name| find_feature(name).unwrap()) {
+            let value = get_reg(&cpuid, feature) | (1 << feature.bit);
+            CpuidPatch::set_cpuid_reg(
+                &mut cpuid,
+                feature.function,
+                Some(feature.index),
+                feature.reg,
+                value,
+            );
+        }
+
+        cpuid
+    }
+
+    fn get_reg(cpuid: &CpuId, feature: &CpuFeature) -> u32 {
+        let entry = cpuid
+            .as_slice()
+            .iter()
+            .find(|e| e.function == feature.function && e.index == feature.index)
+            .unwrap();
+        match feature.reg {
+            CpuidReg::EAX => entry.eax,
+            CpuidReg::EBX => entry.ebx,
+            CpuidReg::ECX => entry.ecx,
+            CpuidReg::EDX => entry.edx,
+        }
+    }
+
+    fn enabled(cpuid: &CpuId, name: &str) -> bool {
+        feature_enabled(cpuid, find_feature(name).unwrap())
+    }
+
+    #[test]
+    fn test_cpu_model() {
+        // Host features are left untouched without any model.
+        let mut cpuid = host_cpuid("icelake");
+        apply_cpu_model(&mut cpuid, None, &[]).unwrap();
+        assert!(enabled(&cpuid, "rtm"));
+        assert!(enabled(&cpuid, "avx512_vnni"));
+
+        // Features are masked down to the model.
+        let mut cpuid = host_cpuid("icelake");
+        apply_cpu_model(&mut cpuid, Some("haswell"), &[]).unwrap();
+        assert!(enabled(&cpuid, "avx2"));
+        assert!(enabled(&cpuid, "sse2"));
+        assert!(enabled(&cpuid, "spec_ctrl"));
+        assert!(!enabled(&cpuid, "rtm"));
+        assert!(!enabled(&cpuid, "avx512f"));
+        assert!(!enabled(&cpuid, "xsavec"));
+        // AVX-512 state components must be hidden along with AVX-512.
+        let xsave = cpuid
+            .as_slice()
+            .iter()
+            .find(|e| e.function == 0xd && e.index == 0)
+            .unwrap()
+            .eax;
+        assert_eq!(xsave, 0x7);
+
+        // Explicit features are applied on top of the model.
+        let mut cpuid = host_cpuid("icelake");
+        apply_cpu_model(
+            &mut cpuid,
+            Some("skylake"),
+            &["-avx512f".to_owned(), "+rtm".to_owned()],
+        )
+        .unwrap();
+        assert!(!enabled(&cpuid, "avx512f"));
+        assert!(enabled(&cpuid, "avx512bw"));
+        assert!(enabled(&cpuid, "rtm"));
+
+        // The host must support the whole model.
+        let mut cpuid = host_cpuid("haswell");
+        match apply_cpu_model(&mut cpuid, Some("skylake"), &[]) {
+            Err(Error::MissingCpuFeatures(missing)) => {
+                assert!(missing.contains(&"avx512f".to_owned()))
+            }
+            _ => panic!("Expected missing features"),
+        }
+
+        // Unknown models and features are refused.
+        let mut cpuid = host_cpuid("haswell");
+        assert!(apply_cpu_model(&mut cpuid, Some("pentium"), &[]).is_err());
+        assert!(apply_cpu_model(&mut cpuid, None, &["-avx1024".to_owned()]).is_err());
+        assert!(apply_cpu_model(&mut cpuid, None, &["avx2".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn test_check_cpuid_compatibility() {
+        let mut source = host_cpuid("icelake");
+        apply_cpu_model(&mut source, Some("haswell"), &[]).unwrap();
+        let mut destination = host_cpuid("haswell");
+        apply_cpu_model(&mut destination, Some("haswell"), &[]).unwrap();
+        assert!(check_cpuid_compatibility(&source, &destination).is_ok());
+
+        let source = host_cpuid("icelake");
+        let destination = host_cpuid("haswell");
+        assert!(check_cpuid_compatibility(&source, &destination).is_err());
+        assert!(check_cpuid_compatibility(&destination, &source).is_ok());
+    }
+}
@@ -7,6 +7,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE-BSD-3-Clause file.
 use std::sync::Arc;
+pub mod cpu_model;
 pub mod interrupts;
 pub mod layout;
 mod mpspec;
@@ -154,6 +155,15 @@ pub enum Error {
 
     /// Missing SGX_LC CPU feature
     MissingSgxLaunchControlFeature,
+
+    /// Unknown CPU model
+    UnknownCpuModel(String),
+
+    /// Unknown CPU feature
+    UnknownCpuFeature(String),
+
+    /// CPU features not supported by the host
+    MissingCpuFeatures(Vec<String>),
 }
 
 impl From<Error> for super::Error {
@@ -309,7 +319,7 @@ impl CpuidPatch {
 
 pub fn configure_vcpu(
     fd: &Arc<dyn hypervisor::Vcpu>,
-    id: u8,
+    id: u32,
     kernel_entry_point: Option<EntryPoint>,
     vm_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
     cpuid: CpuId,
@@ -317,8 +327,9 @@ pub fn configure_vcpu(
 ) -> super::Result<()> {
     // Per vCPU CPUID changes; common are handled via CpuManager::generate_common_cpuid()
     let mut cpuid = cpuid;
-    CpuidPatch::set_cpuid_reg(&mut cpuid, 0xb, None, CpuidReg::EDX, u32::from(id));
-    CpuidPatch::set_cpuid_reg(&mut cpuid, 0x1f, None, CpuidReg::EDX, u32::from(id));
+    // The x2APIC ID matches the vCPU ID, which can exceed the 8-bit APIC IDs
+    CpuidPatch::set_cpuid_reg(&mut cpuid, 0xb, None, CpuidReg::EDX, id);
+    CpuidPatch::set_cpuid_reg(&mut cpuid, 0x1f, None, CpuidReg::EDX, id);
 
     fd.set_cpuid2(&cpuid)
         .map_err(|e| Error::SetSupportedCpusFailed(e.into()))?;
@@ -399,7 +410,7 @@ pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
     cmdline_addr: GuestAddress,
     initramfs: &Option<InitramfsConfig>,
-    _num_cpus: u8,
+    num_cpus: u32,
     rsdp_addr: Option<GuestAddress>,
     sgx_epc_region: Option<SgxEpcRegion>,
 ) -> super::Result<()> {
@@ -408,7 +419,16 @@ pub fn configure_system(
     // Place the MP table after the SMIOS table aligned to 16 bytes
     let offset = GuestAddress(layout::SMBIOS_START).unchecked_add(size);
     let offset = GuestAddress((offset.0 + 16) & !0xf);
-    mptable::setup_mptable(offset, guest_mem, _num_cpus).map_err(Error::MpTableSetup)?;
+    // The MP table can only describe xAPIC IDs, guests with more vCPUs rely
+    // on the x2APIC entries from the ACPI MADT instead.
+    if num_cpus <= mptable::MAX_SUPPORTED_CPUS {
+        mptable::setup_mptable(offset, guest_mem, num_cpus as u8).map_err(Error::MpTableSetup)?;
+    } else {
+        warn!(
+            "Skipping mptable creation as {} vCPUs exceed the xAPIC limit",
+            num_cpus
+        );
+    }
 
     // Check that the RAM is not smaller than the RSDP start address
     if let Some(rsdp_addr) = rsdp_addr {
@@ -622,6 +642,10 @@ pub fn update_cpuid_topology(
     let core_width = (8 - (cores_per_die - 1).leading_zeros()) + thread_width;
     let die_width = (8 - (dies_per_package - 1).leading_zeros()) + core_width;
 
+    // The number of logical processors can exceed 255 with x2APIC
+    let threads_per_die = u32::from(cores_per_die) * u32::from(threads_per_core);
+    let threads_per_package = u32::from(dies_per_package) * threads_per_die;
+
     // CPU Topology leaf 0xb
     CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(0), CpuidReg::EAX, thread_width);
     CpuidPatch::set_cpuid_reg(
@@ -634,13 +658,7 @@ pub fn update_cpuid_topology(
     CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(0), CpuidReg::ECX, 1 << 8);
 
     CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(1), CpuidReg::EAX, die_width);
-    CpuidPatch::set_cpuid_reg(
-        cpuid,
-        0xb,
-        Some(1),
-        CpuidReg::EBX,
-        u32::from(dies_per_package * cores_per_die * threads_per_core),
-    );
+    CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(1), CpuidReg::EBX, threads_per_package);
     CpuidPatch::set_cpuid_reg(cpuid, 0xb, Some(1), CpuidReg::ECX, 2 << 8);
 
     // CPU Topology leaf 0x1f
@@ -655,23 +673,11 @@ pub fn update_cpuid_topology(
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(0), CpuidReg::ECX, 1 << 8);
 
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(1), CpuidReg::EAX, core_width);
-    CpuidPatch::set_cpuid_reg(
-        cpuid,
-        0x1f,
-        Some(1),
-        CpuidReg::EBX,
-        u32::from(cores_per_die * threads_per_core),
-    );
+    CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(1), CpuidReg::EBX, threads_per_die);
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(1), CpuidReg::ECX, 2 << 8);
 
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(2), CpuidReg::EAX, die_width);
-    CpuidPatch::set_cpuid_reg(
-        cpuid,
-        0x1f,
-        Some(2),
-        CpuidReg::EBX,
-        u32::from(dies_per_package * cores_per_die * threads_per_core),
-    );
+    CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(2), CpuidReg::EBX, threads_per_package);
     CpuidPatch::set_cpuid_reg(cpuid, 0x1f, Some(2), CpuidReg::ECX, 5 << 8);
 }
 
@@ -792,6 +798,9 @@ mod tests {
         configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None).unwrap();
 
         configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None).unwrap();
+
+        // The MP table is skipped when the vCPUs don't fit in xAPIC IDs.
+        configure_system(&gm, GuestAddress(0), &None, 384, None, None).unwrap();
     }
 
     #[test]
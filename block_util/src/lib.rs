@@ -40,10 +40,19 @@ use virtio_bindings::bindings::virtio_blk::*;
 use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap};
 use vm_virtio::DescriptorChain;
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::write_zeroes::{PunchHole, WriteZeroesAt};
 
 const SECTOR_SHIFT: u8 = 9;
 pub const SECTOR_SIZE: u64 = 0x01 << SECTOR_SHIFT;
 
+// Discard and write zeroes definitions from the virtio specification, which
+// are not part of the virtio_blk bindings.
+pub const VIRTIO_BLK_F_DISCARD: u32 = 13;
+pub const VIRTIO_BLK_F_WRITE_ZEROES: u32 = 14;
+const VIRTIO_BLK_T_DISCARD: u32 = 11;
+const VIRTIO_BLK_T_WRITE_ZEROES: u32 = 13;
+const VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP: u32 = 1 << 0;
+
 #[derive(Debug)]
 pub enum Error {
     /// Guest gave us bad memory addresses.
@@ -64,6 +73,10 @@ pub enum Error {
     InvalidOffset,
     /// The requested operation does not support multiple descriptors.
     TooManyDescriptors,
+    /// Guest gave us discard or write zeroes segments with unknown flags.
+    InvalidSegmentFlags(u32),
+    /// Guest gave us a descriptor not holding a whole number of segments.
+    InvalidSegmentsLength(u32),
 }
 
 fn build_device_id(disk_path: &Path) -> result::Result<String, Error> {
@@ -105,6 +118,8 @@ pub enum ExecuteError {
     Read(GuestMemoryError),
     Seek(io::Error),
     Write(GuestMemoryError),
+    Discard(io::Error),
+    WriteZeroes(io::Error),
     Unsupported(u32),
     SubmitIoUring(io::Error),
     GetHostAddress(GuestMemoryError),
@@ -121,6 +136,8 @@ impl ExecuteError {
             ExecuteError::Read(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Seek(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Write(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::Discard(_) => VIRTIO_BLK_S_IOERR,
+            ExecuteError::WriteZeroes(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::Unsupported(_) => VIRTIO_BLK_S_UNSUPP,
             ExecuteError::SubmitIoUring(_) => VIRTIO_BLK_S_IOERR,
             ExecuteError::GetHostAddress(_) => VIRTIO_BLK_S_IOERR,
@@ -137,6 +154,8 @@ pub enum RequestType {
     Out,
     Flush,
     GetDeviceId,
+    Discard,
+    WriteZeroes,
     Unsupported(u32),
 }
 
@@ -150,6 +169,8 @@ pub fn request_type(
         VIRTIO_BLK_T_OUT => Ok(RequestType::Out),
         VIRTIO_BLK_T_FLUSH => Ok(RequestType::Flush),
         VIRTIO_BLK_T_GET_ID => Ok(RequestType::GetDeviceId),
+        VIRTIO_BLK_T_DISCARD => Ok(RequestType::Discard),
+        VIRTIO_BLK_T_WRITE_ZEROES => Ok(RequestType::WriteZeroes),
         t => Ok(RequestType::Unsupported(t)),
     }
 }
@@ -164,6 +185,18 @@ fn sector(mem: &GuestMemoryMmap, desc_addr: GuestAddress) -> result::Result<u64,
     mem.read_obj(addr).map_err(Error::GuestMemory)
 }
 
+/// Segment describing a range of sectors to be discarded or zeroed.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+struct DiscardWriteZeroesSegment {
+    sector: u64,
+    num_sectors: u32,
+    flags: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for DiscardWriteZeroesSegment {}
+
 #[derive(Debug)]
 pub struct Request {
     pub request_type: RequestType,
@@ -209,7 +242,11 @@ impl Request {
             }
         } else {
             while desc.has_next() {
-                if desc.is_write_only() && req.request_type == RequestType::Out {
+                if desc.is_write_only()
+                    && (req.request_type == RequestType::Out
+                        || req.request_type == RequestType::Discard
+                        || req.request_type == RequestType::WriteZeroes)
+                {
                     return Err(Error::UnexpectedWriteOnlyDescriptor);
                 }
                 if !desc.is_write_only() && req.request_type == RequestType::In {
@@ -245,13 +282,19 @@ impl Request {
     }
 
     #[allow(clippy::ptr_arg)]
-    pub fn execute<T: Seek + Read + Write>(
+    pub fn execute<T: Seek + Read + Write + PunchHole + WriteZeroesAt + ?Sized>(
         &self,
         disk: &mut T,
         disk_nsectors: u64,
         mem: &GuestMemoryMmap,
         disk_id: &Vec<u8>,
     ) -> result::Result<u32, ExecuteError> {
+        if self.request_type == RequestType::Discard
+            || self.request_type == RequestType::WriteZeroes
+        {
+            return self.execute_discard_write_zeroes(disk, disk_nsectors, mem);
+        }
+
         disk.seek(SeekFrom::Start(self.sector << SECTOR_SHIFT))
             .map_err(ExecuteError::Seek)?;
         let mut len = 0;
@@ -288,12 +331,87 @@ impl Request {
                     mem.write_slice(&disk_id.as_slice(), *data_addr)
                         .map_err(ExecuteError::Write)?;
                 }
+                RequestType::Discard | RequestType::WriteZeroes => unreachable!(),
                 RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
             };
         }
         Ok(len)
     }
 
+    fn execute_discard_write_zeroes<T: PunchHole + WriteZeroesAt + ?Sized>(
+        &self,
+        disk: &mut T,
+        disk_nsectors: u64,
+        mem: &GuestMemoryMmap,
+    ) -> result::Result<u32, ExecuteError> {
+        let segment_size = std::mem::size_of::<DiscardWriteZeroesSegment>() as u64;
+
+        for (data_addr, data_len) in &self.data_descriptors {
+            if u64::from(*data_len) % segment_size != 0 {
+                return Err(ExecuteError::BadRequest(Error::InvalidSegmentsLength(
+                    *data_len,
+                )));
+            }
+
+            // Each data descriptor can contain several segments.
+            for i in 0..(u64::from(*data_len) / segment_size) {
+                let segment_offset = (i * segment_size) as usize;
+                let addr = mem.checked_offset(*data_addr, segment_offset).ok_or(
+                    ExecuteError::BadRequest(Error::CheckedOffset(*data_addr, segment_offset)),
+                )?;
+                let segment: DiscardWriteZeroesSegment =
+                    mem.read_obj(addr).map_err(ExecuteError::Read)?;
+
+                // Copy the fields out of the packed structure.
+                let sector = segment.sector;
+                let num_sectors = u64::from(segment.num_sectors);
+                let flags = segment.flags;
+
+                let top = sector
+                    .checked_add(num_sectors)
+                    .ok_or(ExecuteError::BadRequest(Error::InvalidOffset))?;
+                if top > disk_nsectors {
+                    return Err(ExecuteError::BadRequest(Error::InvalidOffset));
+                }
+
+                let offset = sector << SECTOR_SHIFT;
+                let length = num_sectors << SECTOR_SHIFT;
+
+                match self.request_type {
+                    RequestType::Discard => {
+                        // The unmap flag is reserved for discard requests.
+                        if flags != 0 {
+                            return Err(ExecuteError::BadRequest(Error::InvalidSegmentFlags(
+                                flags,
+                            )));
+                        }
+                        disk.punch_hole(offset, length)
+                            .map_err(ExecuteError::Discard)?;
+                    }
+                    _ => {
+                        if flags & !VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0 {
+                            return Err(ExecuteError::BadRequest(Error::InvalidSegmentFlags(
+                                flags,
+                            )));
+                        }
+                        // Deallocating the range is allowed when the driver
+                        // sets the unmap flag, as long as reading it back
+                        // returns zeroes.
+                        if flags & VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0 {
+                            disk.punch_hole(offset, length)
+                                .map_err(ExecuteError::WriteZeroes)?;
+                        } else {
+                            disk.write_all_zeroes_at(offset, length as usize)
+                                .map_err(ExecuteError::WriteZeroes)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(0)
+    }
+
     pub fn execute_async(
         &self,
         mem: &GuestMemoryMmap,
@@ -360,6 +478,10 @@ impl Request {
                     .map_err(ExecuteError::Write)?;
                 return Ok(false);
             }
+            RequestType::Discard => return Err(ExecuteError::Unsupported(VIRTIO_BLK_T_DISCARD)),
+            RequestType::WriteZeroes => {
+                return Err(ExecuteError::Unsupported(VIRTIO_BLK_T_WRITE_ZEROES))
+            }
             RequestType::Unsupported(t) => return Err(ExecuteError::Unsupported(t)),
         }
 
@@ -620,3 +742,170 @@ pub fn detect_image_type(f: &mut File) -> std::io::Result<ImageType> {
 
     Ok(image_type)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qcow::{QcowFile, RawFile};
+    use vmm_sys_util::tempfile::TempFile;
+
+    const DISK_NSECTORS: u64 = 0x100;
+    const SEGMENTS_ADDR: GuestAddress = GuestAddress(0x1000);
+
+    fn create_mem() -> GuestMemoryMmap {
+        GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap()
+    }
+
+    fn create_raw_disk() -> RawFile {
+        let mut disk = RawFile::new(TempFile::new().unwrap().into_file(), false);
+        disk.write_all(&[0xffu8; (DISK_NSECTORS * SECTOR_SIZE) as usize])
+            .unwrap();
+        disk
+    }
+
+    fn create_qcow_disk() -> QcowFile {
+        let raw = RawFile::new(TempFile::new().unwrap().into_file(), false);
+        let mut disk = QcowFile::new(raw, 3, DISK_NSECTORS * SECTOR_SIZE).unwrap();
+        disk.write_all(&[0xffu8; (DISK_NSECTORS * SECTOR_SIZE) as usize])
+            .unwrap();
+        disk
+    }
+
+    // Write the segments to the guest memory, and build the request pointing
+    // to them.
+    fn create_request(
+        mem: &GuestMemoryMmap,
+        request_type: RequestType,
+        segments: &[(u64, u32, u32)],
+    ) -> Request {
+        for (i, (sector, num_sectors, flags)) in segments.iter().enumerate() {
+            let segment = DiscardWriteZeroesSegment {
+                sector: *sector,
+                num_sectors: *num_sectors,
+                flags: *flags,
+            };
+            mem.write_obj(
+                segment,
+                SEGMENTS_ADDR.unchecked_add((i * std::mem::size_of_val(&segment)) as u64),
+            )
+            .unwrap();
+        }
+
+        Request {
+            request_type,
+            sector: 0,
+            data_descriptors: vec![(
+                SEGMENTS_ADDR,
+                (segments.len() * std::mem::size_of::<DiscardWriteZeroesSegment>()) as u32,
+            )],
+            status_addr: GuestAddress(0),
+            writeback: true,
+        }
+    }
+
+    fn execute<T: Seek + Read + Write + PunchHole + WriteZeroesAt>(
+        disk: &mut T,
+        request: &Request,
+        mem: &GuestMemoryMmap,
+    ) -> result::Result<u32, ExecuteError> {
+        request.execute(disk, DISK_NSECTORS, mem, &vec![])
+    }
+
+    // Check the given sectors read as zeroes, and their neighbours still
+    // hold the original data.
+    fn check_zeroed<T: Seek + Read>(disk: &mut T, sector: u64, num_sectors: u64) {
+        let mut data = vec![0u8; ((num_sectors + 2) * SECTOR_SIZE) as usize];
+        disk.seek(SeekFrom::Start((sector - 1) * SECTOR_SIZE))
+            .unwrap();
+        disk.read_exact(&mut data).unwrap();
+
+        let (before, data) = data.split_at(SECTOR_SIZE as usize);
+        let (zeroes, after) = data.split_at((num_sectors * SECTOR_SIZE) as usize);
+        assert!(before.iter().all(|b| *b == 0xff));
+        assert!(zeroes.iter().all(|b| *b == 0));
+        assert!(after.iter().all(|b| *b == 0xff));
+    }
+
+    fn test_discard_write_zeroes<T: Seek + Read + Write + PunchHole + WriteZeroesAt>(disk: &mut T) {
+        let mem = create_mem();
+
+        let request = create_request(&mem, RequestType::WriteZeroes, &[(0x10, 8, 0)]);
+        assert_eq!(execute(disk, &request, &mem).unwrap(), 0);
+        check_zeroed(disk, 0x10, 8);
+
+        let request = create_request(
+            &mem,
+            RequestType::WriteZeroes,
+            &[(0x20, 8, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP)],
+        );
+        assert_eq!(execute(disk, &request, &mem).unwrap(), 0);
+        check_zeroed(disk, 0x20, 8);
+
+        let request = create_request(&mem, RequestType::Discard, &[(0x30, 8, 0), (0x40, 4, 0)]);
+        assert_eq!(execute(disk, &request, &mem).unwrap(), 0);
+        check_zeroed(disk, 0x40, 4);
+    }
+
+    fn test_invalid_segments<T: Seek + Read + Write + PunchHole + WriteZeroesAt>(disk: &mut T) {
+        let mem = create_mem();
+
+        // Out of range, or overflowing sectors.
+        for (sector, num_sectors) in &[
+            (DISK_NSECTORS, 1),
+            (DISK_NSECTORS - 1, 2),
+            (u64::MAX, u32::MAX),
+        ] {
+            for request_type in &[RequestType::Discard, RequestType::WriteZeroes] {
+                let request = create_request(&mem, *request_type, &[(*sector, *num_sectors, 0)]);
+                assert!(matches!(
+                    execute(disk, &request, &mem),
+                    Err(ExecuteError::BadRequest(Error::InvalidOffset))
+                ));
+            }
+        }
+
+        // Length not holding a whole number of segments.
+        let mut request = create_request(&mem, RequestType::WriteZeroes, &[(0x10, 1, 0)]);
+        request.data_descriptors[0].1 += 1;
+        assert!(matches!(
+            execute(disk, &request, &mem),
+            Err(ExecuteError::BadRequest(Error::InvalidSegmentsLength(17)))
+        ));
+
+        // The unmap flag is only valid for write zeroes requests.
+        let request = create_request(
+            &mem,
+            RequestType::Discard,
+            &[(0x10, 1, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP)],
+        );
+        assert!(matches!(
+            execute(disk, &request, &mem),
+            Err(ExecuteError::BadRequest(Error::InvalidSegmentFlags(1)))
+        ));
+        let request = create_request(&mem, RequestType::WriteZeroes, &[(0x10, 1, 1 << 1)]);
+        assert!(matches!(
+            execute(disk, &request, &mem),
+            Err(ExecuteError::BadRequest(Error::InvalidSegmentFlags(2)))
+        ));
+    }
+
+    #[test]
+    fn test_raw_discard_write_zeroes() {
+        test_discard_write_zeroes(&mut create_raw_disk());
+    }
+
+    #[test]
+    fn test_raw_invalid_segments() {
+        test_invalid_segments(&mut create_raw_disk());
+    }
+
+    #[test]
+    fn test_qcow_discard_write_zeroes() {
+        test_discard_write_zeroes(&mut create_qcow_disk());
+    }
+
+    #[test]
+    fn test_qcow_invalid_segments() {
+        test_invalid_segments(&mut create_qcow_disk());
+    }
+}
@@ -73,6 +73,12 @@ fn destination_field(entry: RedirectionTableEntry) -> u8 {
     // retrieve the destination field based on bits 56-63.
     ((entry >> 56) & 0xffu64) as u8
 }
+fn extended_destination_field(entry: RedirectionTableEntry) -> u8 {
+    // Bits 49-55 are reserved in the IOAPIC specification, but guests aware
+    // of the KVM extended destination ID use them to carry bits 8-14 of the
+    // APIC ID, so that interrupts can target x2APIC IDs above 255.
+    ((entry >> 49) & 0x7fu64) as u8
+}
 fn set_delivery_status(entry: &mut RedirectionTableEntry, val: u8) {
     // Clear bit 12
     *entry &= 0xffff_ffff_ffff_efff;
@@ -306,6 +312,7 @@ impl Ioapic {
         // Validate Destination Mode value, and retrieve Destination ID
         let destination_mode = destination_mode(entry);
         let destination_id = destination_field(entry);
+        let extended_destination_id = extended_destination_field(entry);
 
         // When this bit is set, the message is directed to the processor with
         // the lowest interrupt priority among processors that can receive the
@@ -315,6 +322,7 @@ impl Ioapic {
         // Generate MSI message address
         let low_addr: u32 = self.apic_address.0 as u32
             | u32::from(destination_id) << 12
+            | u32::from(extended_destination_id) << 5
             | u32::from(redirection_hint) << 3
             | u32::from(destination_mode) << 2;
 
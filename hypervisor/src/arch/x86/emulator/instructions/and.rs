@@ -0,0 +1,224 @@
+//
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+#![allow(non_camel_case_types)]
+
+//
+// AND - Logical AND
+// SDM Volume 2, Chapter 3.2
+//   Performs a bitwise AND operation on the destination (first) and source (second)
+//   operands and stores the result in the destination operand location.
+//
+
+use crate::arch::emulator::{EmulationError, PlatformEmulator};
+use crate::arch::x86::emulator::instructions::*;
+use crate::arch::x86::regs::*;
+use crate::arch::x86::Exception;
+
+// AND clears OF and CF, and sets SF, ZF and PF according to the result.
+// AF is undefined and left untouched.
+const FLAGS_MASK: u64 = CF | PF | ZF | SF | OF;
+
+macro_rules! and_rm {
+    ($dest_op_size:ty, $src_op_size:ty) => {
+        fn emulate(
+            &self,
+            insn: &Instruction,
+            state: &mut T,
+            platform: &mut dyn PlatformEmulator<CpuState = T>,
+        ) -> Result<(), EmulationError<Exception>> {
+            let op_size = std::mem::size_of::<$dest_op_size>();
+            let op0_value = get_op(&insn, 0, op_size, state, platform)
+                .map_err(EmulationError::PlatformEmulationError)?;
+            let op1_value = get_op(
+                &insn,
+                1,
+                std::mem::size_of::<$src_op_size>(),
+                state,
+                platform,
+            )
+            .map_err(EmulationError::PlatformEmulationError)?;
+
+            let result = op0_value & op1_value;
+
+            set_op(&insn, 0, op_size, state, platform, result)
+                .map_err(EmulationError::PlatformEmulationError)?;
+
+            state.set_flags((state.flags() & !FLAGS_MASK) | calc_rflags_logical(result, op_size));
+
+            Ok(())
+        }
+    };
+}
+
+pub struct And_rm8_r8;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm8_r8 {
+    and_rm!(u8, u8);
+}
+
+pub struct And_rm16_r16;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm16_r16 {
+    and_rm!(u16, u16);
+}
+
+pub struct And_rm32_r32;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm32_r32 {
+    and_rm!(u32, u32);
+}
+
+pub struct And_rm64_r64;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm64_r64 {
+    and_rm!(u64, u64);
+}
+
+pub struct And_r8_rm8;
+impl<T: CpuStateManager> InstructionHandler<T> for And_r8_rm8 {
+    and_rm!(u8, u8);
+}
+
+pub struct And_r16_rm16;
+impl<T: CpuStateManager> InstructionHandler<T> for And_r16_rm16 {
+    and_rm!(u16, u16);
+}
+
+pub struct And_r32_rm32;
+impl<T: CpuStateManager> InstructionHandler<T> for And_r32_rm32 {
+    and_rm!(u32, u32);
+}
+
+pub struct And_r64_rm64;
+impl<T: CpuStateManager> InstructionHandler<T> for And_r64_rm64 {
+    and_rm!(u64, u64);
+}
+
+pub struct And_rm8_imm8;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm8_imm8 {
+    and_rm!(u8, u8);
+}
+
+pub struct And_rm16_imm16;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm16_imm16 {
+    and_rm!(u16, u16);
+}
+
+pub struct And_rm32_imm32;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm32_imm32 {
+    and_rm!(u32, u32);
+}
+
+pub struct And_rm64_imm32;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm64_imm32 {
+    and_rm!(u64, u32);
+}
+
+pub struct And_rm16_imm8;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm16_imm8 {
+    and_rm!(u16, u8);
+}
+
+pub struct And_rm32_imm8;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm32_imm8 {
+    and_rm!(u32, u8);
+}
+
+pub struct And_rm64_imm8;
+impl<T: CpuStateManager> InstructionHandler<T> for And_rm64_imm8 {
+    and_rm!(u64, u8);
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(unused_mut)]
+    use super::*;
+    use crate::arch::x86::emulator::mock_vmm::*;
+
+    #[test]
+    // and dword ptr [rax],ebx
+    fn test_and_rm32_r32() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 4] = 0xffff_00ffu32.to_le_bytes();
+        let insn = [0x21, 0x18]; // and dword ptr [rax],ebx
+        let mut data = [0u8; 4];
+        let mut vmm = MockVmm::new(
+            ip,
+            vec![(Register::RAX, rax), (Register::RBX, 0x1234_5678)],
+            Some((rax, &memory)),
+        );
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        vmm.read_memory(rax, &mut data).unwrap();
+        assert_eq!(0x1234_0078, <u32>::from_le_bytes(data));
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b100, rflags);
+    }
+
+    #[test]
+    // and byte ptr [rax],0x0f
+    fn test_and_rm8_imm8() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 1] = [0xf0];
+        let insn = [0x80, 0x20, 0x0f]; // and byte ptr [rax],0x0f
+        let mut data = [0u8; 1];
+        let mut vmm = MockVmm::new(ip, vec![(Register::RAX, rax)], Some((rax, &memory)));
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        vmm.read_memory(rax, &mut data).unwrap();
+        assert_eq!(0x0, data[0]);
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b100_0100, rflags);
+    }
+
+    #[test]
+    // and rbx,qword ptr [rax]
+    fn test_and_r64_rm64() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 8] = 0x8000_0000_0000_00ffu64.to_le_bytes();
+        let insn = [0x48, 0x23, 0x18]; // and rbx,qword ptr [rax]
+        let mut vmm = MockVmm::new(
+            ip,
+            vec![(Register::RAX, rax), (Register::RBX, 0xffff_ffff_0000_0001)],
+            Some((rax, &memory)),
+        );
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        let rbx: u64 = vmm
+            .cpu_state(cpu_id)
+            .unwrap()
+            .read_reg(Register::RBX)
+            .unwrap();
+        assert_eq!(0x8000_0000_0000_0001, rbx);
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b1000_0000, rflags);
+    }
+
+    #[test]
+    // and qword ptr [rax],-16
+    fn test_and_rm64_imm8() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 8] = 0x1234u64.to_le_bytes();
+        let insn = [0x48, 0x83, 0x20, 0xf0]; // and qword ptr [rax],-16
+        let mut data = [0u8; 8];
+        let mut vmm = MockVmm::new(ip, vec![(Register::RAX, rax)], Some((rax, &memory)));
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        vmm.read_memory(rax, &mut data).unwrap();
+        assert_eq!(0x1230, <u64>::from_le_bytes(data));
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b100, rflags);
+    }
+}
@@ -6,12 +6,18 @@
 
 use crate::arch::emulator::{EmulationError, PlatformEmulator, PlatformError};
 use crate::arch::x86::emulator::CpuStateManager;
+use crate::arch::x86::regs::*;
 use crate::arch::x86::Exception;
 use iced_x86::*;
 
+pub mod and;
 pub mod cmp;
 pub mod mov;
 pub mod movs;
+pub mod or;
+pub mod stos;
+pub mod test;
+pub mod xchg;
 
 fn get_op<T: CpuStateManager>(
     insn: &Instruction,
@@ -135,6 +141,42 @@ fn memory_operand_address<T: CpuStateManager>(
     state.linearize(insn.memory_segment(), address, write)
 }
 
+// Returns the count, source index and destination index registers of a
+// string instruction, whose sizes depend on the instruction address size.
+fn string_op_registers(
+    insn: &Instruction,
+) -> Result<(Register, Register, Register), PlatformError> {
+    match insn
+        .try_op_kind(0)
+        .map_err(|e| PlatformError::InvalidOperand(e.into()))?
+    {
+        OpKind::MemoryESRDI => Ok((Register::RCX, Register::RSI, Register::RDI)),
+        OpKind::MemoryESEDI => Ok((Register::ECX, Register::ESI, Register::EDI)),
+        OpKind::MemoryESDI => Ok((Register::CX, Register::SI, Register::DI)),
+        k => Err(PlatformError::InvalidOperand(anyhow!("{:?}", k))),
+    }
+}
+
+// Returns the PF, ZF and SF flags for the result of a logical instruction
+// (AND, OR, TEST), which clears OF and CF, and leaves AF undefined.
+fn calc_rflags_logical(result: u64, op_size: usize) -> u64 {
+    let msb_shift = op_size * 8 - 1;
+
+    // PF only needs the least significant byte. XOR its higher 4 bits with its lower 4 bits then
+    // use the value directly.
+    let pf = ((0x9669 >> ((result ^ (result >> 4)) & 0xf)) & 0x1) << PF_SHIFT;
+
+    let zf = if result & (!0u64 >> (63 - msb_shift)) == 0 {
+        1
+    } else {
+        0
+    } << ZF_SHIFT;
+
+    let sf = ((result >> msb_shift) & 0x1) << SF_SHIFT;
+
+    pf | zf | sf
+}
+
 pub trait InstructionHandler<T: CpuStateManager> {
     fn emulate(
         &self,
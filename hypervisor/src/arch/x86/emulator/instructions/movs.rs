@@ -15,73 +15,98 @@ use crate::arch::x86::emulator::instructions::*;
 use crate::arch::x86::regs::DF;
 use crate::arch::x86::Exception;
 
-pub struct Movsd_m32_m32;
-impl<T: CpuStateManager> InstructionHandler<T> for Movsd_m32_m32 {
-    fn emulate(
-        &self,
-        insn: &Instruction,
-        state: &mut T,
-        platform: &mut dyn PlatformEmulator<CpuState = T>,
-    ) -> Result<(), EmulationError<Exception>> {
-        let mut count: u64 = if insn.has_rep_prefix() {
-            state
-                .read_reg(Register::ECX)
-                .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?
-        } else {
-            1
-        };
-
-        let mut rsi = state
-            .read_reg(Register::RSI)
-            .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
-        let mut rdi = state
-            .read_reg(Register::RDI)
-            .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
-
-        let df = (state.flags() & DF) != 0;
-        let len = std::mem::size_of::<u32>();
-
-        while count > 0 {
-            let mut memory: [u8; 4] = [0; 4];
-
-            let src = state
-                .linearize(Register::DS, rsi, false)
+macro_rules! movs {
+    ($bound:ty) => {
+        fn emulate(
+            &self,
+            insn: &Instruction,
+            state: &mut T,
+            platform: &mut dyn PlatformEmulator<CpuState = T>,
+        ) -> Result<(), EmulationError<Exception>> {
+            let (count_reg, src_reg, dst_reg) =
+                string_op_registers(insn).map_err(EmulationError::PlatformEmulationError)?;
+
+            let mut count: u64 = if insn.has_rep_prefix() {
+                state
+                    .read_reg(count_reg)
+                    .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?
+            } else {
+                1
+            };
+
+            let mut rsi = state
+                .read_reg(src_reg)
                 .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
-            let dst = state
-                .linearize(Register::ES, rdi, true)
+            let mut rdi = state
+                .read_reg(dst_reg)
                 .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
 
-            platform
-                .read_memory(src, &mut memory[0..len])
-                .map_err(EmulationError::PlatformEmulationError)?;
-            platform
-                .write_memory(dst, &memory[0..len])
-                .map_err(EmulationError::PlatformEmulationError)?;
+            let df = (state.flags() & DF) != 0;
+            let len = std::mem::size_of::<$bound>();
 
-            if df {
-                rsi = rsi.wrapping_sub(len as u64);
-                rdi = rdi.wrapping_sub(len as u64);
-            } else {
-                rsi = rsi.wrapping_add(len as u64);
-                rdi = rdi.wrapping_add(len as u64);
+            while count > 0 {
+                let mut memory: [u8; 8] = [0; 8];
+
+                // The source segment can be overridden, not the destination one.
+                let src = state
+                    .linearize(insn.memory_segment(), rsi, false)
+                    .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
+                let dst = state
+                    .linearize(Register::ES, rdi, true)
+                    .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
+
+                platform
+                    .read_memory(src, &mut memory[0..len])
+                    .map_err(EmulationError::PlatformEmulationError)?;
+                platform
+                    .write_memory(dst, &memory[0..len])
+                    .map_err(EmulationError::PlatformEmulationError)?;
+
+                if df {
+                    rsi = rsi.wrapping_sub(len as u64);
+                    rdi = rdi.wrapping_sub(len as u64);
+                } else {
+                    rsi = rsi.wrapping_add(len as u64);
+                    rdi = rdi.wrapping_add(len as u64);
+                }
+                count -= 1;
             }
-            count -= 1;
-        }
 
-        state
-            .write_reg(Register::RSI, rsi)
-            .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
-        state
-            .write_reg(Register::RDI, rdi)
-            .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
-        if insn.has_rep_prefix() {
             state
-                .write_reg(Register::ECX, 0)
+                .write_reg(src_reg, rsi)
+                .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
+            state
+                .write_reg(dst_reg, rdi)
                 .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
+            if insn.has_rep_prefix() {
+                state
+                    .write_reg(count_reg, 0)
+                    .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
+            }
+
+            Ok(())
         }
+    };
+}
 
-        Ok(())
-    }
+pub struct Movsb_m8_m8;
+impl<T: CpuStateManager> InstructionHandler<T> for Movsb_m8_m8 {
+    movs!(u8);
+}
+
+pub struct Movsw_m16_m16;
+impl<T: CpuStateManager> InstructionHandler<T> for Movsw_m16_m16 {
+    movs!(u16);
+}
+
+pub struct Movsd_m32_m32;
+impl<T: CpuStateManager> InstructionHandler<T> for Movsd_m32_m32 {
+    movs!(u32);
+}
+
+pub struct Movsq_m64_m64;
+impl<T: CpuStateManager> InstructionHandler<T> for Movsq_m64_m64 {
+    movs!(u64);
 }
 
 #[cfg(test)]
@@ -142,4 +167,105 @@ mod tests {
         vmm.read_memory(0x8 + 8, &mut data).unwrap();
         assert_eq!(0x0, <u32>::from_le_bytes(data));
     }
+
+    #[test]
+    fn test_rep_movsb_m8_m8() {
+        let ip: u64 = 0x1000;
+        let memory: [u8; 4] = [0x11, 0x22, 0x33, 0x44];
+        let insn = [0xf3, 0xa4]; // rep movsb
+        let regs = vec![
+            (Register::RCX, 3),
+            (Register::RSI, 0),
+            (Register::RDI, 0x10),
+        ];
+        let mut data = [0u8; 4];
+
+        let mut vmm = MockVmm::new(ip, regs, Some((0, &memory)));
+
+        assert!(vmm.emulate_first_insn(0, &insn).is_ok());
+
+        vmm.read_memory(0x10, &mut data).unwrap();
+        assert_eq!([0x11, 0x22, 0x33, 0x00], data);
+
+        let state = vmm.cpu_state(0).unwrap();
+        assert_eq!(0, state.read_reg(Register::RCX).unwrap());
+        assert_eq!(0x3, state.read_reg(Register::RSI).unwrap());
+        assert_eq!(0x13, state.read_reg(Register::RDI).unwrap());
+    }
+
+    #[test]
+    fn test_rep_movsb_m8_m8_no_count() {
+        let ip: u64 = 0x1000;
+        let memory: [u8; 4] = [0x11, 0x22, 0x33, 0x44];
+        let insn = [0xf3, 0xa4]; // rep movsb
+        let regs = vec![
+            (Register::RCX, 0),
+            (Register::RSI, 0),
+            (Register::RDI, 0x10),
+        ];
+        let mut data = [0u8; 4];
+
+        let mut vmm = MockVmm::new(ip, regs, Some((0, &memory)));
+
+        assert!(vmm.emulate_first_insn(0, &insn).is_ok());
+
+        vmm.read_memory(0x10, &mut data).unwrap();
+        assert_eq!(0x0, <u32>::from_le_bytes(data));
+
+        let state = vmm.cpu_state(0).unwrap();
+        assert_eq!(0x0, state.read_reg(Register::RSI).unwrap());
+        assert_eq!(0x10, state.read_reg(Register::RDI).unwrap());
+    }
+
+    #[test]
+    fn test_rep_movsw_m16_m16_backward() {
+        let ip: u64 = 0x1000;
+        let memory: [u8; 4] = [
+            0x11, 0x11, // 0x1111
+            0x22, 0x22, // 0x2222
+        ];
+        let insn = [0x66, 0xf3, 0xa5]; // rep movsw
+        let regs = vec![
+            (Register::RCX, 2),
+            (Register::RSI, 0x2),
+            (Register::RDI, 0x12),
+        ];
+        let mut data = [0u8; 2];
+
+        let mut vmm = MockVmm::new(ip, regs, Some((0, &memory)));
+        let mut state = vmm.cpu_state(0).unwrap();
+        state.set_flags(DF);
+        vmm.set_cpu_state(0, state).unwrap();
+
+        assert!(vmm.emulate_first_insn(0, &insn).is_ok());
+
+        vmm.read_memory(0x10, &mut data).unwrap();
+        assert_eq!(0x1111, <u16>::from_le_bytes(data));
+        vmm.read_memory(0x12, &mut data).unwrap();
+        assert_eq!(0x2222, <u16>::from_le_bytes(data));
+
+        let state = vmm.cpu_state(0).unwrap();
+        assert_eq!(0u64.wrapping_sub(2), state.read_reg(Register::RSI).unwrap());
+        assert_eq!(0xe, state.read_reg(Register::RDI).unwrap());
+    }
+
+    #[test]
+    fn test_movsq_m64_m64() {
+        let ip: u64 = 0x1000;
+        let memory: [u8; 8] = 0x1122334455667788u64.to_le_bytes();
+        let insn = [0x48, 0xa5]; // movsq
+        let regs = vec![(Register::RSI, 0), (Register::RDI, 0x10)];
+        let mut data = [0u8; 8];
+
+        let mut vmm = MockVmm::new(ip, regs, Some((0, &memory)));
+
+        assert!(vmm.emulate_first_insn(0, &insn).is_ok());
+
+        vmm.read_memory(0x10, &mut data).unwrap();
+        assert_eq!(0x1122334455667788, <u64>::from_le_bytes(data));
+
+        let state = vmm.cpu_state(0).unwrap();
+        assert_eq!(0x8, state.read_reg(Register::RSI).unwrap());
+        assert_eq!(0x18, state.read_reg(Register::RDI).unwrap());
+    }
 }
@@ -0,0 +1,216 @@
+//
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+#![allow(non_camel_case_types)]
+
+//
+// OR - Logical Inclusive OR
+// SDM Volume 2, Chapter 4.3
+//   Performs a bitwise inclusive OR operation between the destination (first) and
+//   source (second) operands and stores the result in the destination operand location.
+//
+
+use crate::arch::emulator::{EmulationError, PlatformEmulator};
+use crate::arch::x86::emulator::instructions::*;
+use crate::arch::x86::regs::*;
+use crate::arch::x86::Exception;
+
+// OR clears OF and CF, and sets SF, ZF and PF according to the result.
+// AF is undefined and left untouched.
+const FLAGS_MASK: u64 = CF | PF | ZF | SF | OF;
+
+macro_rules! or_rm {
+    ($dest_op_size:ty, $src_op_size:ty) => {
+        fn emulate(
+            &self,
+            insn: &Instruction,
+            state: &mut T,
+            platform: &mut dyn PlatformEmulator<CpuState = T>,
+        ) -> Result<(), EmulationError<Exception>> {
+            let op_size = std::mem::size_of::<$dest_op_size>();
+            let op0_value = get_op(&insn, 0, op_size, state, platform)
+                .map_err(EmulationError::PlatformEmulationError)?;
+            let op1_value = get_op(
+                &insn,
+                1,
+                std::mem::size_of::<$src_op_size>(),
+                state,
+                platform,
+            )
+            .map_err(EmulationError::PlatformEmulationError)?;
+
+            let result = op0_value | op1_value;
+
+            set_op(&insn, 0, op_size, state, platform, result)
+                .map_err(EmulationError::PlatformEmulationError)?;
+
+            state.set_flags((state.flags() & !FLAGS_MASK) | calc_rflags_logical(result, op_size));
+
+            Ok(())
+        }
+    };
+}
+
+pub struct Or_rm8_r8;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm8_r8 {
+    or_rm!(u8, u8);
+}
+
+pub struct Or_rm16_r16;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm16_r16 {
+    or_rm!(u16, u16);
+}
+
+pub struct Or_rm32_r32;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm32_r32 {
+    or_rm!(u32, u32);
+}
+
+pub struct Or_rm64_r64;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm64_r64 {
+    or_rm!(u64, u64);
+}
+
+pub struct Or_r8_rm8;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_r8_rm8 {
+    or_rm!(u8, u8);
+}
+
+pub struct Or_r16_rm16;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_r16_rm16 {
+    or_rm!(u16, u16);
+}
+
+pub struct Or_r32_rm32;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_r32_rm32 {
+    or_rm!(u32, u32);
+}
+
+pub struct Or_r64_rm64;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_r64_rm64 {
+    or_rm!(u64, u64);
+}
+
+pub struct Or_rm8_imm8;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm8_imm8 {
+    or_rm!(u8, u8);
+}
+
+pub struct Or_rm16_imm16;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm16_imm16 {
+    or_rm!(u16, u16);
+}
+
+pub struct Or_rm32_imm32;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm32_imm32 {
+    or_rm!(u32, u32);
+}
+
+pub struct Or_rm64_imm32;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm64_imm32 {
+    or_rm!(u64, u32);
+}
+
+pub struct Or_rm16_imm8;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm16_imm8 {
+    or_rm!(u16, u8);
+}
+
+pub struct Or_rm32_imm8;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm32_imm8 {
+    or_rm!(u32, u8);
+}
+
+pub struct Or_rm64_imm8;
+impl<T: CpuStateManager> InstructionHandler<T> for Or_rm64_imm8 {
+    or_rm!(u64, u8);
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(unused_mut)]
+    use super::*;
+    use crate::arch::x86::emulator::mock_vmm::*;
+
+    #[test]
+    // or dword ptr [rax],ebx
+    fn test_or_rm32_r32() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 4] = 0xff00u32.to_le_bytes();
+        let insn = [0x09, 0x18]; // or dword ptr [rax],ebx
+        let mut data = [0u8; 4];
+        let mut vmm = MockVmm::new(
+            ip,
+            vec![(Register::RAX, rax), (Register::RBX, 0x8000_0001)],
+            Some((rax, &memory)),
+        );
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        vmm.read_memory(rax, &mut data).unwrap();
+        assert_eq!(0x8000_ff01, <u32>::from_le_bytes(data));
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b1000_0000, rflags);
+    }
+
+    #[test]
+    // or byte ptr [rax],0x0
+    fn test_or_rm8_imm8() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let insn = [0x80, 0x08, 0x00]; // or byte ptr [rax],0x0
+        let mut vmm = MockVmm::new(ip, vec![(Register::RAX, rax)], None);
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b100_0100, rflags);
+    }
+
+    #[test]
+    // or ax,word ptr [rax]
+    fn test_or_r16_rm16() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 2] = 0x1234u16.to_le_bytes();
+        let insn = [0x66, 0x0b, 0x00]; // or ax,word ptr [rax]
+        let mut vmm = MockVmm::new(ip, vec![(Register::RAX, rax)], Some((rax, &memory)));
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        let rax: u64 = vmm
+            .cpu_state(cpu_id)
+            .unwrap()
+            .read_reg(Register::RAX)
+            .unwrap();
+        assert_eq!(0x1334, rax);
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b0, rflags);
+    }
+
+    #[test]
+    // or dword ptr [rax],-128
+    fn test_or_rm32_imm8() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 8] = 0x1u64.to_le_bytes();
+        let insn = [0x83, 0x08, 0x80]; // or dword ptr [rax],-128
+        let mut data = [0u8; 8];
+        let mut vmm = MockVmm::new(ip, vec![(Register::RAX, rax)], Some((rax, &memory)));
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        // Only the operand size is written
+        vmm.read_memory(rax, &mut data).unwrap();
+        assert_eq!(0xffff_ff81, <u64>::from_le_bytes(data));
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b1000_0100, rflags);
+    }
+}
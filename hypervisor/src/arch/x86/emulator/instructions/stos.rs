@@ -0,0 +1,174 @@
+//
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+#![allow(non_camel_case_types, clippy::upper_case_acronyms)]
+
+//
+// STOS - Store String
+// SDM Volume 2, Chapter 4.3
+//   Stores a byte, word, doubleword or quadword from the AL, AX, EAX or RAX register
+//   into the destination operand, addressed by ES:rDI.
+//
+
+use crate::arch::emulator::{EmulationError, PlatformEmulator};
+use crate::arch::x86::emulator::instructions::*;
+use crate::arch::x86::regs::DF;
+use crate::arch::x86::Exception;
+
+macro_rules! stos {
+    ($bound:ty) => {
+        fn emulate(
+            &self,
+            insn: &Instruction,
+            state: &mut T,
+            platform: &mut dyn PlatformEmulator<CpuState = T>,
+        ) -> Result<(), EmulationError<Exception>> {
+            let (count_reg, _, dst_reg) =
+                string_op_registers(insn).map_err(EmulationError::PlatformEmulationError)?;
+
+            let mut count: u64 = if insn.has_rep_prefix() {
+                state
+                    .read_reg(count_reg)
+                    .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?
+            } else {
+                1
+            };
+
+            let len = std::mem::size_of::<$bound>();
+            let value = get_op(&insn, 1, len, state, platform)
+                .map_err(EmulationError::PlatformEmulationError)?;
+            let mut rdi = state
+                .read_reg(dst_reg)
+                .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
+
+            let df = (state.flags() & DF) != 0;
+
+            while count > 0 {
+                let dst = state
+                    .linearize(Register::ES, rdi, true)
+                    .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
+
+                platform
+                    .write_memory(dst, &value.to_le_bytes()[0..len])
+                    .map_err(EmulationError::PlatformEmulationError)?;
+
+                if df {
+                    rdi = rdi.wrapping_sub(len as u64);
+                } else {
+                    rdi = rdi.wrapping_add(len as u64);
+                }
+                count -= 1;
+            }
+
+            state
+                .write_reg(dst_reg, rdi)
+                .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
+            if insn.has_rep_prefix() {
+                state
+                    .write_reg(count_reg, 0)
+                    .map_err(|e| EmulationError::InvalidOperand(anyhow!(e)))?;
+            }
+
+            Ok(())
+        }
+    };
+}
+
+pub struct Stosb_m8_AL;
+impl<T: CpuStateManager> InstructionHandler<T> for Stosb_m8_AL {
+    stos!(u8);
+}
+
+pub struct Stosw_m16_AX;
+impl<T: CpuStateManager> InstructionHandler<T> for Stosw_m16_AX {
+    stos!(u16);
+}
+
+pub struct Stosd_m32_EAX;
+impl<T: CpuStateManager> InstructionHandler<T> for Stosd_m32_EAX {
+    stos!(u32);
+}
+
+pub struct Stosq_m64_RAX;
+impl<T: CpuStateManager> InstructionHandler<T> for Stosq_m64_RAX {
+    stos!(u64);
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(unused_mut)]
+    use super::*;
+    use crate::arch::x86::emulator::mock_vmm::*;
+
+    #[test]
+    fn test_rep_stosb_m8_al() {
+        let ip: u64 = 0x1000;
+        let insn = [0xf3, 0xaa]; // rep stosb
+        let regs = vec![
+            (Register::RAX, 0x12ab),
+            (Register::RCX, 4),
+            (Register::RDI, 0x10),
+        ];
+        let mut data = [0u8; 8];
+
+        let mut vmm = MockVmm::new(ip, regs, None);
+
+        assert!(vmm.emulate_first_insn(0, &insn).is_ok());
+
+        vmm.read_memory(0x10, &mut data).unwrap();
+        assert_eq!([0xab, 0xab, 0xab, 0xab, 0x0, 0x0, 0x0, 0x0], data);
+
+        let state = vmm.cpu_state(0).unwrap();
+        assert_eq!(0, state.read_reg(Register::RCX).unwrap());
+        assert_eq!(0x14, state.read_reg(Register::RDI).unwrap());
+    }
+
+    #[test]
+    fn test_stosd_m32_eax() {
+        let ip: u64 = 0x1000;
+        let insn = [0xab]; // stosd
+        let regs = vec![(Register::RAX, 0x12345678), (Register::RDI, 0x20)];
+        let mut data = [0u8; 4];
+
+        let mut vmm = MockVmm::new(ip, regs, None);
+
+        assert!(vmm.emulate_first_insn(0, &insn).is_ok());
+
+        vmm.read_memory(0x20, &mut data).unwrap();
+        assert_eq!(0x12345678, <u32>::from_le_bytes(data));
+        // The rest should be default value 0 from MockVmm
+        vmm.read_memory(0x24, &mut data).unwrap();
+        assert_eq!(0x0, <u32>::from_le_bytes(data));
+
+        let state = vmm.cpu_state(0).unwrap();
+        assert_eq!(0x24, state.read_reg(Register::RDI).unwrap());
+    }
+
+    #[test]
+    fn test_rep_stosq_m64_rax() {
+        let ip: u64 = 0x1000;
+        let insn = [0xf3, 0x48, 0xab]; // rep stosq
+        let regs = vec![
+            (Register::RAX, 0x1122334455667788),
+            (Register::RCX, 2),
+            (Register::RDI, 0x40),
+        ];
+        let mut data = [0u8; 8];
+
+        let mut vmm = MockVmm::new(ip, regs, None);
+
+        assert!(vmm.emulate_first_insn(0, &insn).is_ok());
+
+        vmm.read_memory(0x40, &mut data).unwrap();
+        assert_eq!(0x1122334455667788, <u64>::from_le_bytes(data));
+        vmm.read_memory(0x48, &mut data).unwrap();
+        assert_eq!(0x1122334455667788, <u64>::from_le_bytes(data));
+
+        let state = vmm.cpu_state(0).unwrap();
+        assert_eq!(0, state.read_reg(Register::RCX).unwrap());
+        assert_eq!(0x50, state.read_reg(Register::RDI).unwrap());
+    }
+}
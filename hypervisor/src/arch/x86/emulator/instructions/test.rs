@@ -0,0 +1,157 @@
+//
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+#![allow(non_camel_case_types)]
+
+//
+// TEST - Logical Compare
+// SDM Volume 2, Chapter 4.3
+//   Computes the bit-wise logical AND of the first and second operands, and sets the
+//   flags according to the result, which is then discarded.
+//
+
+use crate::arch::emulator::{EmulationError, PlatformEmulator};
+use crate::arch::x86::emulator::instructions::*;
+use crate::arch::x86::regs::*;
+use crate::arch::x86::Exception;
+
+// TEST clears OF and CF, and sets SF, ZF and PF according to the result.
+// AF is undefined and left untouched.
+const FLAGS_MASK: u64 = CF | PF | ZF | SF | OF;
+
+macro_rules! test_rm {
+    ($dest_op_size:ty, $src_op_size:ty) => {
+        fn emulate(
+            &self,
+            insn: &Instruction,
+            state: &mut T,
+            platform: &mut dyn PlatformEmulator<CpuState = T>,
+        ) -> Result<(), EmulationError<Exception>> {
+            let op_size = std::mem::size_of::<$dest_op_size>();
+            let op0_value = get_op(&insn, 0, op_size, state, platform)
+                .map_err(EmulationError::PlatformEmulationError)?;
+            let op1_value = get_op(
+                &insn,
+                1,
+                std::mem::size_of::<$src_op_size>(),
+                state,
+                platform,
+            )
+            .map_err(EmulationError::PlatformEmulationError)?;
+
+            let result = op0_value & op1_value;
+
+            state.set_flags((state.flags() & !FLAGS_MASK) | calc_rflags_logical(result, op_size));
+
+            Ok(())
+        }
+    };
+}
+
+pub struct Test_rm8_r8;
+impl<T: CpuStateManager> InstructionHandler<T> for Test_rm8_r8 {
+    test_rm!(u8, u8);
+}
+
+pub struct Test_rm16_r16;
+impl<T: CpuStateManager> InstructionHandler<T> for Test_rm16_r16 {
+    test_rm!(u16, u16);
+}
+
+pub struct Test_rm32_r32;
+impl<T: CpuStateManager> InstructionHandler<T> for Test_rm32_r32 {
+    test_rm!(u32, u32);
+}
+
+pub struct Test_rm64_r64;
+impl<T: CpuStateManager> InstructionHandler<T> for Test_rm64_r64 {
+    test_rm!(u64, u64);
+}
+
+pub struct Test_rm8_imm8;
+impl<T: CpuStateManager> InstructionHandler<T> for Test_rm8_imm8 {
+    test_rm!(u8, u8);
+}
+
+pub struct Test_rm16_imm16;
+impl<T: CpuStateManager> InstructionHandler<T> for Test_rm16_imm16 {
+    test_rm!(u16, u16);
+}
+
+pub struct Test_rm32_imm32;
+impl<T: CpuStateManager> InstructionHandler<T> for Test_rm32_imm32 {
+    test_rm!(u32, u32);
+}
+
+pub struct Test_rm64_imm32;
+impl<T: CpuStateManager> InstructionHandler<T> for Test_rm64_imm32 {
+    test_rm!(u64, u32);
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(unused_mut)]
+    use super::*;
+    use crate::arch::x86::emulator::mock_vmm::*;
+
+    #[test]
+    // test dword ptr [rax],ebx
+    fn test_test_rm32_r32() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 4] = 0xf0f0u32.to_le_bytes();
+        let insn = [0x85, 0x18]; // test dword ptr [rax],ebx
+        let mut data = [0u8; 4];
+        let mut vmm = MockVmm::new(
+            ip,
+            vec![(Register::RAX, rax), (Register::RBX, 0x0f0f)],
+            Some((rax, &memory)),
+        );
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b100_0100, rflags);
+
+        // The operands are left untouched
+        vmm.read_memory(rax, &mut data).unwrap();
+        assert_eq!(0xf0f0, <u32>::from_le_bytes(data));
+    }
+
+    #[test]
+    // test byte ptr [rax],0x80
+    fn test_test_rm8_imm8() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 1] = [0x81];
+        let insn = [0xf6, 0x00, 0x80]; // test byte ptr [rax],0x80
+        let mut vmm = MockVmm::new(ip, vec![(Register::RAX, rax)], Some((rax, &memory)));
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b1000_0000, rflags);
+    }
+
+    #[test]
+    // test qword ptr [rax],rbx
+    fn test_test_rm64_r64() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 8] = 0x1_0000_0000u64.to_le_bytes();
+        let insn = [0x48, 0x85, 0x18]; // test qword ptr [rax],rbx
+        let mut vmm = MockVmm::new(
+            ip,
+            vec![(Register::RAX, rax), (Register::RBX, 0x1_0000_0003)],
+            Some((rax, &memory)),
+        );
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        let rflags: u64 = vmm.cpu_state(cpu_id).unwrap().flags() & FLAGS_MASK;
+        assert_eq!(0b100, rflags);
+    }
+}
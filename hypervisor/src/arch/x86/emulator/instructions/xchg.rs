@@ -0,0 +1,148 @@
+//
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+#![allow(non_camel_case_types)]
+
+//
+// XCHG - Exchange Register/Memory with Register
+//   Exchanges the contents of the destination (first) and source (second) operands.
+//
+
+use crate::arch::emulator::{EmulationError, PlatformEmulator};
+use crate::arch::x86::emulator::instructions::*;
+use crate::arch::x86::Exception;
+
+macro_rules! xchg_rm_r {
+    ($bound:ty) => {
+        fn emulate(
+            &self,
+            insn: &Instruction,
+            state: &mut T,
+            platform: &mut dyn PlatformEmulator<CpuState = T>,
+        ) -> Result<(), EmulationError<Exception>> {
+            let op_size = std::mem::size_of::<$bound>();
+            let op0_value = get_op(&insn, 0, op_size, state, platform)
+                .map_err(EmulationError::PlatformEmulationError)?;
+            let op1_value = get_op(&insn, 1, op_size, state, platform)
+                .map_err(EmulationError::PlatformEmulationError)?;
+
+            set_op(&insn, 0, op_size, state, platform, op1_value)
+                .map_err(EmulationError::PlatformEmulationError)?;
+            set_op(&insn, 1, op_size, state, platform, op0_value)
+                .map_err(EmulationError::PlatformEmulationError)?;
+
+            Ok(())
+        }
+    };
+}
+
+pub struct Xchg_rm8_r8;
+impl<T: CpuStateManager> InstructionHandler<T> for Xchg_rm8_r8 {
+    xchg_rm_r!(u8);
+}
+
+pub struct Xchg_rm16_r16;
+impl<T: CpuStateManager> InstructionHandler<T> for Xchg_rm16_r16 {
+    xchg_rm_r!(u16);
+}
+
+pub struct Xchg_rm32_r32;
+impl<T: CpuStateManager> InstructionHandler<T> for Xchg_rm32_r32 {
+    xchg_rm_r!(u32);
+}
+
+pub struct Xchg_rm64_r64;
+impl<T: CpuStateManager> InstructionHandler<T> for Xchg_rm64_r64 {
+    xchg_rm_r!(u64);
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(unused_mut)]
+    use super::*;
+    use crate::arch::x86::emulator::mock_vmm::*;
+
+    #[test]
+    // xchg byte ptr [rax],cl
+    fn test_xchg_rm8_r8() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 1] = [0x11];
+        let insn = [0x86, 0x08]; // xchg byte ptr [rax],cl
+        let mut data = [0u8; 1];
+        let mut vmm = MockVmm::new(
+            ip,
+            vec![(Register::RAX, rax), (Register::RCX, 0xff22)],
+            Some((rax, &memory)),
+        );
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        vmm.read_memory(rax, &mut data).unwrap();
+        assert_eq!(0x22, data[0]);
+
+        let rcx: u64 = vmm
+            .cpu_state(cpu_id)
+            .unwrap()
+            .read_reg(Register::RCX)
+            .unwrap();
+        assert_eq!(0xff11, rcx);
+    }
+
+    #[test]
+    // xchg dword ptr [rax],ebx
+    fn test_xchg_rm32_r32() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 4] = 0x1234_5678u32.to_le_bytes();
+        let insn = [0x87, 0x18]; // xchg dword ptr [rax],ebx
+        let mut data = [0u8; 4];
+        let mut vmm = MockVmm::new(
+            ip,
+            vec![(Register::RAX, rax), (Register::RBX, 0xaabb_ccdd)],
+            Some((rax, &memory)),
+        );
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        vmm.read_memory(rax, &mut data).unwrap();
+        assert_eq!(0xaabb_ccdd, <u32>::from_le_bytes(data));
+
+        let rbx: u64 = vmm
+            .cpu_state(cpu_id)
+            .unwrap()
+            .read_reg(Register::RBX)
+            .unwrap();
+        assert_eq!(0x1234_5678, rbx);
+    }
+
+    #[test]
+    // xchg qword ptr [rax],rbx
+    fn test_xchg_rm64_r64() {
+        let rax: u64 = 0x100;
+        let ip: u64 = 0x1000;
+        let cpu_id = 0;
+        let memory: [u8; 8] = 0x1122_3344_5566_7788u64.to_le_bytes();
+        let insn = [0x48, 0x87, 0x18]; // xchg qword ptr [rax],rbx
+        let mut data = [0u8; 8];
+        let mut vmm = MockVmm::new(
+            ip,
+            vec![(Register::RAX, rax), (Register::RBX, 0x8877_6655_4433_2211)],
+            Some((rax, &memory)),
+        );
+        assert!(vmm.emulate_first_insn(cpu_id, &insn).is_ok());
+
+        vmm.read_memory(rax, &mut data).unwrap();
+        assert_eq!(0x8877_6655_4433_2211, <u64>::from_le_bytes(data));
+
+        let rbx: u64 = vmm
+            .cpu_state(cpu_id)
+            .unwrap()
+            .read_reg(Register::RBX)
+            .unwrap();
+        assert_eq!(0x1122_3344_5566_7788, rbx);
+    }
+}
@@ -495,6 +495,22 @@ impl<'a, T: CpuStateManager> Emulator<'a, T> {
     fn get_handler(code: Code) -> Option<Box<dyn InstructionHandler<T>>> {
         let handler: Option<Box<dyn InstructionHandler<T>>> = gen_handler_match!(
             code,
+            // AND
+            (and, And_rm8_r8),
+            (and, And_rm16_r16),
+            (and, And_rm32_r32),
+            (and, And_rm64_r64),
+            (and, And_r8_rm8),
+            (and, And_r16_rm16),
+            (and, And_r32_rm32),
+            (and, And_r64_rm64),
+            (and, And_rm8_imm8),
+            (and, And_rm16_imm16),
+            (and, And_rm32_imm32),
+            (and, And_rm64_imm32),
+            (and, And_rm16_imm8),
+            (and, And_rm32_imm8),
+            (and, And_rm64_imm8),
             // CMP
             (cmp, Cmp_rm32_r32),
             (cmp, Cmp_rm8_r8),
@@ -524,7 +540,45 @@ impl<'a, T: CpuStateManager> Emulator<'a, T> {
             (mov, Movzx_r32_rm16),
             (mov, Movzx_r64_rm16),
             // MOVS
-            (movs, Movsd_m32_m32)
+            (movs, Movsb_m8_m8),
+            (movs, Movsw_m16_m16),
+            (movs, Movsd_m32_m32),
+            (movs, Movsq_m64_m64),
+            // OR
+            (or, Or_rm8_r8),
+            (or, Or_rm16_r16),
+            (or, Or_rm32_r32),
+            (or, Or_rm64_r64),
+            (or, Or_r8_rm8),
+            (or, Or_r16_rm16),
+            (or, Or_r32_rm32),
+            (or, Or_r64_rm64),
+            (or, Or_rm8_imm8),
+            (or, Or_rm16_imm16),
+            (or, Or_rm32_imm32),
+            (or, Or_rm64_imm32),
+            (or, Or_rm16_imm8),
+            (or, Or_rm32_imm8),
+            (or, Or_rm64_imm8),
+            // STOS
+            (stos, Stosb_m8_AL),
+            (stos, Stosw_m16_AX),
+            (stos, Stosd_m32_EAX),
+            (stos, Stosq_m64_RAX),
+            // TEST
+            (test, Test_rm8_r8),
+            (test, Test_rm16_r16),
+            (test, Test_rm32_r32),
+            (test, Test_rm64_r64),
+            (test, Test_rm8_imm8),
+            (test, Test_rm16_imm16),
+            (test, Test_rm32_imm32),
+            (test, Test_rm64_imm32),
+            // XCHG
+            (xchg, Xchg_rm8_r8),
+            (xchg, Xchg_rm16_r16),
+            (xchg, Xchg_rm32_r32),
+            (xchg, Xchg_rm64_r64)
         );
 
         handler
@@ -206,6 +206,11 @@ pub enum HypervisorCpuError {
     #[error("Failed to translate GVA: {0}")]
     TranslateVirtualAddress(#[source] anyhow::Error),
     ///
+    /// Setting guest debug error
+    ///
+    #[error("Failed to set guest debug: {0}")]
+    SetGuestDebug(#[source] anyhow::Error),
+    ///
     /// Failed to initialize TDX on CPU
     ///
     #[cfg(feature = "tdx")]
@@ -223,6 +228,8 @@ pub enum VmExit<'a> {
     IoapicEoi(u8 /* vector */),
     MmioRead(u64 /* address */, &'a mut [u8]),
     MmioWrite(u64 /* address */, &'a [u8]),
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    Debug,
     Ignore,
     Reset,
     Shutdown,
@@ -416,6 +423,24 @@ pub trait Vcpu: Send + Sync {
     /// Translate guest virtual address to guest physical address
     ///
     fn translate_gva(&self, gva: u64, flags: u64) -> Result<(u64, hv_translate_gva_result)>;
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    ///
+    /// Translate guest virtual address to guest physical address using the
+    /// vCPU current paging mode.
+    ///
+    fn translate_gva_to_gpa(&self, gva: u64) -> Result<u64>;
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    ///
+    /// Enable guest debugging on the vCPU, with up to 4 hardware execution
+    /// breakpoints, optional interception of software breakpoints and
+    /// optional single-stepping.
+    ///
+    fn set_guest_debug(
+        &self,
+        hw_breakpoints: &[u64],
+        sw_breakpoints: bool,
+        single_step: bool,
+    ) -> Result<()>;
     ///
     /// Initialize TDX support on the vCPU
     ///
@@ -39,7 +39,10 @@ use crate::arch::x86::NUM_IOAPIC_PINS;
 use aarch64::{RegList, Register, StandardRegisters};
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
-    kvm_enable_cap, kvm_msr_entry, MsrList, KVM_CAP_HYPERV_SYNIC, KVM_CAP_SPLIT_IRQCHIP,
+    kvm_enable_cap, kvm_guest_debug, kvm_msr_entry, kvm_translation, MsrList, KVM_CAP_HYPERV_SYNIC,
+    KVM_CAP_SPLIT_IRQCHIP, KVM_CAP_X2APIC_API, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP,
+    KVM_GUESTDBG_USE_HW_BP, KVM_GUESTDBG_USE_SW_BP, KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK,
+    KVM_X2APIC_API_USE_32BIT_IDS,
 };
 #[cfg(target_arch = "x86_64")]
 use x86_64::{
@@ -54,7 +57,7 @@ pub use x86_64::{
 #[cfg(target_arch = "aarch64")]
 pub mod aarch64;
 pub use kvm_bindings;
-#[cfg(feature = "tdx")]
+#[cfg(any(feature = "tdx", target_arch = "x86_64"))]
 use kvm_bindings::KVMIO;
 pub use kvm_bindings::{
     kvm_create_device, kvm_device_type_KVM_DEV_TYPE_VFIO, kvm_irq_routing, kvm_irq_routing_entry,
@@ -72,7 +75,13 @@ pub use kvm_ioctls::{Cap, Kvm};
 use std::mem;
 use thiserror::Error;
 #[cfg(feature = "tdx")]
-use vmm_sys_util::{ioctl::ioctl_with_val, ioctl_expr, ioctl_ioc_nr, ioctl_iowr_nr};
+use vmm_sys_util::ioctl::ioctl_with_val;
+#[cfg(target_arch = "x86_64")]
+use vmm_sys_util::ioctl::{ioctl_with_mut_ref, ioctl_with_ref};
+#[cfg(target_arch = "x86_64")]
+use vmm_sys_util::ioctl_iow_nr;
+#[cfg(any(feature = "tdx", target_arch = "x86_64"))]
+use vmm_sys_util::{ioctl_expr, ioctl_ioc_nr, ioctl_iowr_nr};
 ///
 /// Export generically-named wrappers of kvm-bindings for Unix-based platforms
 ///
@@ -88,6 +97,16 @@ pub use {
 #[cfg(feature = "tdx")]
 ioctl_iowr_nr!(KVM_MEMORY_ENCRYPT_OP, KVMIO, 0xba, std::os::raw::c_ulong);
 
+// Not wrapped by kvm-ioctls yet.
+#[cfg(target_arch = "x86_64")]
+ioctl_iowr_nr!(KVM_TRANSLATE, KVMIO, 0x85, kvm_translation);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_SET_GUEST_DEBUG, KVMIO, 0x9b, kvm_guest_debug);
+
+// Number of x86 debug address registers (DR0-DR3).
+#[cfg(target_arch = "x86_64")]
+const NUM_HW_BREAKPOINTS: usize = 4;
+
 #[cfg(feature = "tdx")]
 #[repr(u32)]
 enum TdxCommand {
@@ -160,7 +179,7 @@ impl vm::Vm for KvmVm {
     ///
     fn create_vcpu(
         &self,
-        id: u8,
+        id: u32,
         vmmops: Option<Arc<Box<dyn VmmOps>>>,
     ) -> vm::Result<Arc<dyn cpu::Vcpu>> {
         let vc = self
@@ -307,6 +326,22 @@ impl vm::Vm for KvmVm {
             .map_err(|e| vm::HypervisorVmError::EnableSplitIrq(e.into()))?;
         Ok(())
     }
+    ///
+    /// Enable 32-bit x2APIC IDs, both in the local APIC state and in the MSI
+    /// routes, which then carry the extended destination ID.
+    ///
+    #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> vm::Result<()> {
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_X2APIC_API,
+            ..Default::default()
+        };
+        cap.args[0] =
+            (KVM_X2APIC_API_USE_32BIT_IDS | KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK) as u64;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::EnableX2ApicApi(e.into()))
+    }
     /// Retrieve guest clock.
     #[cfg(target_arch = "x86_64")]
     fn get_clock(&self) -> vm::Result<ClockData> {
@@ -855,6 +890,8 @@ impl cpu::Vcpu for KvmVcpu {
                     Ok(cpu::VmExit::MmioWrite(addr, data))
                 }
                 VcpuExit::Hyperv => Ok(cpu::VmExit::Hyperv),
+                #[cfg(target_arch = "x86_64")]
+                VcpuExit::Debug { .. } => Ok(cpu::VmExit::Debug),
 
                 r => Err(cpu::HypervisorCpuError::RunVcpu(anyhow!(
                     "Unexpected exit reason on vcpu run: {:?}",
@@ -901,6 +938,87 @@ impl cpu::Vcpu for KvmVcpu {
             .kvmclock_ctrl()
             .map_err(|e| cpu::HypervisorCpuError::NotifyGuestClockPaused(e.into()))
     }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Translate guest virtual address to guest physical address using the
+    /// `KVM_TRANSLATE` ioctl.
+    ///
+    fn translate_gva_to_gpa(&self, gva: u64) -> cpu::Result<u64> {
+        let mut tr = kvm_translation {
+            linear_address: gva,
+            ..Default::default()
+        };
+
+        // Safe because we know that our file is a vCPU fd, we know the kernel
+        // will only write the correct amount of memory to our pointer, and
+        // we verify the return result.
+        let ret = unsafe { ioctl_with_mut_ref(&self.fd, KVM_TRANSLATE(), &mut tr) };
+        if ret != 0 {
+            return Err(cpu::HypervisorCpuError::TranslateVirtualAddress(
+                std::io::Error::last_os_error().into(),
+            ));
+        }
+        if tr.valid == 0 {
+            return Err(cpu::HypervisorCpuError::TranslateVirtualAddress(anyhow!(
+                "Unmapped guest virtual address 0x{:x}",
+                gva
+            )));
+        }
+
+        Ok(tr.physical_address)
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Configure guest debugging through the `KVM_SET_GUEST_DEBUG` ioctl.
+    /// Hardware breakpoints are programmed as execution breakpoints in the
+    /// debug address registers DR0 to DR3.
+    ///
+    fn set_guest_debug(
+        &self,
+        hw_breakpoints: &[u64],
+        sw_breakpoints: bool,
+        single_step: bool,
+    ) -> cpu::Result<()> {
+        if hw_breakpoints.len() > NUM_HW_BREAKPOINTS {
+            return Err(cpu::HypervisorCpuError::SetGuestDebug(anyhow!(
+                "Only {} hardware breakpoints are supported",
+                NUM_HW_BREAKPOINTS
+            )));
+        }
+
+        // Guest debugging is left disabled when there is nothing to debug,
+        // so that the guest keeps control of its own debug registers.
+        let mut dbg = kvm_guest_debug::default();
+        if !hw_breakpoints.is_empty() || sw_breakpoints || single_step {
+            dbg.control |= KVM_GUESTDBG_ENABLE;
+        }
+        if !hw_breakpoints.is_empty() {
+            dbg.control |= KVM_GUESTDBG_USE_HW_BP;
+        }
+        if sw_breakpoints {
+            dbg.control |= KVM_GUESTDBG_USE_SW_BP;
+        }
+        if single_step {
+            dbg.control |= KVM_GUESTDBG_SINGLESTEP;
+        }
+        for (i, addr) in hw_breakpoints.iter().enumerate() {
+            dbg.arch.debugreg[i] = *addr;
+            // Local enable bit, with RW and LEN left to 0 for an
+            // instruction execution breakpoint.
+            dbg.arch.debugreg[7] |= 1 << (i * 2);
+        }
+
+        // Safe because we know that our file is a vCPU fd, the kernel only
+        // reads from the structure and we verify the return result.
+        let ret = unsafe { ioctl_with_ref(&self.fd, KVM_SET_GUEST_DEBUG(), &dbg) };
+        if ret != 0 {
+            return Err(cpu::HypervisorCpuError::SetGuestDebug(
+                std::io::Error::last_os_error().into(),
+            ));
+        }
+
+        Ok(())
+    }
     #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
     fn vcpu_init(&self, kvi: &VcpuInit) -> cpu::Result<()> {
         self.fd
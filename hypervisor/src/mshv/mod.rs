@@ -16,6 +16,7 @@ pub use mshv_bindings::*;
 pub use mshv_ioctls::IoEventAddress;
 use mshv_ioctls::{set_registers_64, Mshv, NoDatamatch, VcpuFd, VmFd};
 use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::sync::Arc;
 use vm::DataMatch;
 // x86_64 dependencies
@@ -730,16 +731,17 @@ impl vm::Vm for MshvVm {
     ///
     fn create_vcpu(
         &self,
-        id: u8,
+        id: u32,
         vmmops: Option<Arc<Box<dyn VmmOps>>>,
     ) -> vm::Result<Arc<dyn cpu::Vcpu>> {
+        let vp_index = u8::try_from(id).map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
         let vcpu_fd = self
             .fd
-            .create_vcpu(id)
+            .create_vcpu(vp_index)
             .map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
         let vcpu = MshvVcpu {
             fd: vcpu_fd,
-            vp_index: id,
+            vp_index,
             cpuid: CpuId::new(1).unwrap(),
             msrs: self.msrs.clone(),
             hv_state: self.hv_state.clone(),
@@ -751,6 +753,10 @@ impl vm::Vm for MshvVm {
     fn enable_split_irq(&self) -> vm::Result<()> {
         Ok(())
     }
+    #[cfg(target_arch = "x86_64")]
+    fn enable_x2apic_api(&self) -> vm::Result<()> {
+        Ok(())
+    }
     fn register_ioevent(
         &self,
         fd: &EventFd,
@@ -0,0 +1,55 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// bindgen /usr/include/linux/vhost_types.h --no-layout-tests
+// --allowlist-type 'vhost_(vring_state|vring_file|vring_addr|memory.*)'
+// --allowlist-var 'VHOST_(VRING_F_LOG|NET_F_VIRTIO_NET_HDR|F_LOG_ALL|VIRTIO)'
+// Manual fixup to reuse __IncompleteArrayField from if_tun.
+
+/* automatically generated by rust-bindgen 0.58.1 */
+
+use crate::if_tun::__IncompleteArrayField;
+
+pub const VHOST_VRING_F_LOG: u32 = 0;
+pub const VHOST_F_LOG_ALL: u32 = 26;
+pub const VHOST_NET_F_VIRTIO_NET_HDR: u32 = 27;
+pub const VHOST_VIRTIO: u32 = 175;
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct vhost_vring_state {
+    pub index: ::std::os::raw::c_uint,
+    pub num: ::std::os::raw::c_uint,
+}
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct vhost_vring_file {
+    pub index: ::std::os::raw::c_uint,
+    pub fd: ::std::os::raw::c_int,
+}
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct vhost_vring_addr {
+    pub index: ::std::os::raw::c_uint,
+    pub flags: ::std::os::raw::c_uint,
+    pub desc_user_addr: ::std::os::raw::c_ulonglong,
+    pub used_user_addr: ::std::os::raw::c_ulonglong,
+    pub avail_user_addr: ::std::os::raw::c_ulonglong,
+    pub log_guest_addr: ::std::os::raw::c_ulonglong,
+}
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct vhost_memory_region {
+    pub guest_phys_addr: ::std::os::raw::c_ulonglong,
+    pub memory_size: ::std::os::raw::c_ulonglong,
+    pub userspace_addr: ::std::os::raw::c_ulonglong,
+    pub flags_padding: ::std::os::raw::c_ulonglong,
+}
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct vhost_memory {
+    pub nregions: ::std::os::raw::c_uint,
+    pub padding: ::std::os::raw::c_uint,
+    pub regions: __IncompleteArrayField<vhost_memory_region>,
+}
@@ -2,24 +2,34 @@
 //
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
-use crate::Tap;
+use crate::rss::{VIRTIO_NET_CTRL_MQ_HASH_CONFIG, VIRTIO_NET_CTRL_MQ_RSS_CONFIG};
+use crate::{MacAddr, Rss, RxFilter, RxMode, Tap, MAC_ADDR_LEN};
 use libc::c_uint;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
 use virtio_bindings::bindings::virtio_net::{
-    VIRTIO_NET_CTRL_GUEST_OFFLOADS, VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET, VIRTIO_NET_CTRL_MQ,
-    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
-    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_ERR, VIRTIO_NET_F_GUEST_CSUM,
-    VIRTIO_NET_F_GUEST_ECN, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_TSO6,
-    VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_OK,
+    VIRTIO_NET_CTRL_ANNOUNCE, VIRTIO_NET_CTRL_ANNOUNCE_ACK, VIRTIO_NET_CTRL_GUEST_OFFLOADS,
+    VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET, VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET,
+    VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX,
+    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_RX,
+    VIRTIO_NET_CTRL_RX_ALLMULTI, VIRTIO_NET_CTRL_RX_ALLUNI, VIRTIO_NET_CTRL_RX_NOBCAST,
+    VIRTIO_NET_CTRL_RX_NOMULTI, VIRTIO_NET_CTRL_RX_NOUNI, VIRTIO_NET_CTRL_RX_PROMISC,
+    VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_ADD, VIRTIO_NET_CTRL_VLAN_DEL, VIRTIO_NET_ERR,
+    VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_ECN, VIRTIO_NET_F_GUEST_TSO4,
+    VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_OK, VIRTIO_NET_S_ANNOUNCE,
 };
 use vm_memory::{ByteValued, Bytes, GuestMemoryError, GuestMemoryMmap};
 use vm_virtio::Queue;
 
+// Upper bound on the command data the guest can provide, which is enough
+// for MAC tables much larger than what the device keeps track of.
+const MAX_CTRL_DATA_LEN: usize = 64 << 10;
+
 #[derive(Debug)]
 pub enum Error {
     /// Read queue failed.
     GuestMemory(GuestMemoryError),
-    /// No queue pairs number.
-    NoQueuePairsDescriptor,
     /// No status descriptor
     NoStatusDescriptor,
 }
@@ -37,11 +47,26 @@ unsafe impl ByteValued for ControlHeader {}
 
 pub struct CtrlQueue {
     pub taps: Vec<Tap>,
+    pub rx_filter: RxFilter,
+    // Status field of the device configuration, when the device lets the
+    // guest announce itself.
+    pub status: Option<Arc<AtomicU16>>,
+    pub rss: Rss,
 }
 
 impl CtrlQueue {
-    pub fn new(taps: Vec<Tap>) -> Self {
-        CtrlQueue { taps }
+    pub fn new(
+        taps: Vec<Tap>,
+        rx_filter: RxFilter,
+        status: Option<Arc<AtomicU16>>,
+        rss: Rss,
+    ) -> Self {
+        CtrlQueue {
+            taps,
+            rx_filter,
+            status,
+            rss,
+        }
     }
 
     pub fn process(&mut self, mem: &GuestMemoryMmap, queue: &mut Queue) -> Result<bool> {
@@ -49,22 +74,67 @@ impl CtrlQueue {
         for avail_desc in queue.iter(&mem) {
             let ctrl_hdr: ControlHeader =
                 mem.read_obj(avail_desc.addr).map_err(Error::GuestMemory)?;
-            let data_desc = avail_desc
-                .next_descriptor()
-                .ok_or(Error::NoQueuePairsDescriptor)?;
-            let status_desc = data_desc
-                .next_descriptor()
-                .ok_or(Error::NoStatusDescriptor)?;
-
-            let ok = match u32::from(ctrl_hdr.class) {
-                VIRTIO_NET_CTRL_MQ => {
-                    let queue_pairs = mem
-                        .read_obj::<u16>(data_desc.addr)
+
+            // The command data can be spread over several descriptors, as
+            // it is the case for the MAC tables, and is followed by the
+            // device writable status.
+            let mut data = Vec::new();
+            let mut data_too_large = false;
+            let mut status_desc = None;
+            let mut next_desc = avail_desc.next_descriptor();
+            while let Some(desc) = next_desc {
+                if desc.is_write_only() {
+                    status_desc = Some(desc);
+                    break;
+                }
+                if data.len() + desc.len as usize <= MAX_CTRL_DATA_LEN {
+                    let mut buf = vec![0u8; desc.len as usize];
+                    mem.read_slice(&mut buf, desc.addr)
                         .map_err(Error::GuestMemory)?;
-                    if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET {
-                        warn!("Unsupported command: {}", ctrl_hdr.cmd);
-                        false
-                    } else if (queue_pairs < VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN as u16)
+                    data.extend_from_slice(&buf);
+                } else {
+                    data_too_large = true;
+                }
+                next_desc = desc.next_descriptor();
+            }
+            let status_desc = status_desc.ok_or(Error::NoStatusDescriptor)?;
+
+            // The announce acknowledgement is the only command without data.
+            let ok = if u32::from(ctrl_hdr.class) == VIRTIO_NET_CTRL_ANNOUNCE {
+                self.process_announce(ctrl_hdr)
+            } else if data.is_empty() || data_too_large {
+                warn!("Invalid data for command {:?}", ctrl_hdr);
+                false
+            } else {
+                self.process_command(ctrl_hdr, &data)
+            };
+
+            mem.write_obj(
+                if ok { VIRTIO_NET_OK } else { VIRTIO_NET_ERR } as u8,
+                status_desc.addr,
+            )
+            .map_err(Error::GuestMemory)?;
+            used_desc_heads.push((avail_desc.index, avail_desc.len));
+        }
+
+        for (desc_index, len) in used_desc_heads.iter() {
+            queue.add_used(&mem, *desc_index, *len);
+            queue.update_avail_event(&mem);
+        }
+
+        Ok(!used_desc_heads.is_empty())
+    }
+
+    fn process_command(&mut self, ctrl_hdr: ControlHeader, data: &[u8]) -> bool {
+        let cmd = u32::from(ctrl_hdr.cmd);
+        match u32::from(ctrl_hdr.class) {
+            VIRTIO_NET_CTRL_MQ => match cmd {
+                VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET => {
+                    let queue_pairs = match data.get(..2) {
+                        Some(data) => u16::from_le_bytes(data.try_into().unwrap()),
+                        None => return false,
+                    };
+                    if (queue_pairs < VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN as u16)
                         || (queue_pairs > VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX as u16)
                     {
                         warn!("Number of MQ pairs out of range: {}", queue_pairs);
@@ -74,50 +144,170 @@ impl CtrlQueue {
                         true
                     }
                 }
-                VIRTIO_NET_CTRL_GUEST_OFFLOADS => {
-                    let features = mem
-                        .read_obj::<u64>(data_desc.addr)
-                        .map_err(Error::GuestMemory)?;
-                    if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET {
+                VIRTIO_NET_CTRL_MQ_RSS_CONFIG => {
+                    let ok = self.rss.set_rss_config(data);
+                    if !ok {
+                        warn!("Invalid RSS configuration");
+                    }
+                    ok
+                }
+                VIRTIO_NET_CTRL_MQ_HASH_CONFIG => {
+                    let ok = self.rss.set_hash_config(data);
+                    if !ok {
+                        warn!("Invalid hash reporting configuration");
+                    }
+                    ok
+                }
+                _ => {
+                    warn!("Unsupported command: {}", ctrl_hdr.cmd);
+                    false
+                }
+            },
+            VIRTIO_NET_CTRL_GUEST_OFFLOADS => {
+                let features = match data.get(..8) {
+                    Some(data) => u64::from_le_bytes(data.try_into().unwrap()),
+                    None => return false,
+                };
+                if cmd != VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET {
+                    warn!("Unsupported command: {}", ctrl_hdr.cmd);
+                    false
+                } else {
+                    let mut ok = true;
+                    for tap in self.taps.iter_mut() {
+                        info!("Reprogramming tap offload with features: {}", features);
+                        tap.set_offload(virtio_features_to_tap_offload(features))
+                            .map_err(|e| {
+                                error!("Error programming tap offload: {:?}", e);
+                                ok = false
+                            })
+                            .ok();
+                    }
+                    ok
+                }
+            }
+            VIRTIO_NET_CTRL_RX => {
+                let mode = match cmd {
+                    VIRTIO_NET_CTRL_RX_PROMISC => RxMode::Promisc,
+                    VIRTIO_NET_CTRL_RX_ALLMULTI => RxMode::AllMulti,
+                    VIRTIO_NET_CTRL_RX_ALLUNI => RxMode::AllUni,
+                    VIRTIO_NET_CTRL_RX_NOMULTI => RxMode::NoMulti,
+                    VIRTIO_NET_CTRL_RX_NOUNI => RxMode::NoUni,
+                    VIRTIO_NET_CTRL_RX_NOBCAST => RxMode::NoBcast,
+                    _ => {
                         warn!("Unsupported command: {}", ctrl_hdr.cmd);
-                        false
-                    } else {
-                        let mut ok = true;
-                        for tap in self.taps.iter_mut() {
-                            info!("Reprogramming tap offload with features: {}", features);
-                            tap.set_offload(virtio_features_to_tap_offload(features))
-                                .map_err(|e| {
-                                    error!("Error programming tap offload: {:?}", e);
-                                    ok = false
-                                })
-                                .ok();
-                        }
-                        ok
+                        return false;
                     }
+                };
+                let on = data[0] != 0;
+                info!("Setting RX mode {:?}: {}", mode, on);
+                self.rx_filter.set_rx_mode(mode, on);
+                self.update_tap_filter();
+                true
+            }
+            VIRTIO_NET_CTRL_MAC => match cmd {
+                VIRTIO_NET_CTRL_MAC_TABLE_SET => {
+                    // Unicast table followed by the multicast one, each made
+                    // of a 32 bits number of entries and the addresses.
+                    let (uni_macs, data) = match parse_mac_table(data) {
+                        Some(table) => table,
+                        None => return false,
+                    };
+                    let (multi_macs, _) = match parse_mac_table(data) {
+                        Some(table) => table,
+                        None => return false,
+                    };
+                    debug!(
+                        "Setting MAC tables: unicast {:?}, multicast {:?}",
+                        uni_macs, multi_macs
+                    );
+                    self.rx_filter.set_mac_tables(uni_macs, multi_macs);
+                    self.update_tap_filter();
+                    true
+                }
+                VIRTIO_NET_CTRL_MAC_ADDR_SET => {
+                    let mac = match data.get(..MAC_ADDR_LEN) {
+                        Some(data) => MacAddr::from_bytes_unchecked(data),
+                        None => return false,
+                    };
+                    info!("Setting MAC address: {}", mac);
+                    self.rx_filter.set_mac(mac);
+                    self.update_tap_filter();
+                    true
                 }
                 _ => {
-                    warn!("Unsupported command {:?}", ctrl_hdr);
+                    warn!("Unsupported command: {}", ctrl_hdr.cmd);
                     false
                 }
-            };
+            },
+            VIRTIO_NET_CTRL_VLAN => {
+                let vid = match data.get(..2) {
+                    Some(data) => u16::from_le_bytes(data.try_into().unwrap()),
+                    None => return false,
+                };
+                let ok = match cmd {
+                    VIRTIO_NET_CTRL_VLAN_ADD => self.rx_filter.add_vlan(vid),
+                    VIRTIO_NET_CTRL_VLAN_DEL => self.rx_filter.del_vlan(vid),
+                    _ => {
+                        warn!("Unsupported command: {}", ctrl_hdr.cmd);
+                        return false;
+                    }
+                };
+                if !ok {
+                    warn!("VLAN out of range: {}", vid);
+                }
+                ok
+            }
+            _ => {
+                warn!("Unsupported command {:?}", ctrl_hdr);
+                false
+            }
+        }
+    }
 
-            mem.write_obj(
-                if ok { VIRTIO_NET_OK } else { VIRTIO_NET_ERR } as u8,
-                status_desc.addr,
-            )
-            .map_err(Error::GuestMemory)?;
-            used_desc_heads.push((avail_desc.index, avail_desc.len));
+    fn process_announce(&mut self, ctrl_hdr: ControlHeader) -> bool {
+        if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_ANNOUNCE_ACK {
+            warn!("Unsupported command: {}", ctrl_hdr.cmd);
+            return false;
         }
 
-        for (desc_index, len) in used_desc_heads.iter() {
-            queue.add_used(&mem, *desc_index, *len);
-            queue.update_avail_event(&mem);
+        match &self.status {
+            Some(status) => {
+                info!("Guest announce acknowledged");
+                status.fetch_and(!(VIRTIO_NET_S_ANNOUNCE as u16), Ordering::AcqRel);
+                true
+            }
+            None => {
+                warn!("Unsupported command {:?}", ctrl_hdr);
+                false
+            }
         }
+    }
 
-        Ok(!used_desc_heads.is_empty())
+    // The RX filter is enforced when the frames are copied to the guest, but
+    // letting the TAP interface drop them saves the copy. This is best
+    // effort as not all the TAP devices support it.
+    fn update_tap_filter(&mut self) {
+        let (macs, all_multi) = self.rx_filter.tap_filter();
+        for tap in self.taps.iter() {
+            if let Err(e) = tap.set_tx_filter(&macs, all_multi) {
+                debug!("Error programming tap filter: {:?}", e);
+            }
+        }
     }
 }
 
+fn parse_mac_table(data: &[u8]) -> Option<(Vec<MacAddr>, &[u8])> {
+    let entries = u32::from_le_bytes(data.get(..4)?.try_into().unwrap()) as usize;
+    let table_len = entries.checked_mul(MAC_ADDR_LEN)?;
+    let table = data.get(4..4usize.checked_add(table_len)?)?;
+    let macs = table
+        .chunks_exact(MAC_ADDR_LEN)
+        .map(MacAddr::from_bytes_unchecked)
+        .collect();
+
+    Some((macs, &data[4 + table_len..]))
+}
+
 pub fn virtio_features_to_tap_offload(features: u64) -> c_uint {
     let mut tap_offloads: c_uint = 0;
     if features & (1 << VIRTIO_NET_F_GUEST_CSUM) != 0 {
@@ -138,3 +328,223 @@ pub fn virtio_features_to_tap_offload(features: u64) -> c_uint {
 
     tap_offloads
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_memory::{GuestAddress, GuestMemoryMmap};
+    use vm_virtio::queue::testing::VirtQueue;
+
+    const VIRTQ_DESC_F_NEXT: u16 = 0x1;
+    const VIRTQ_DESC_F_WRITE: u16 = 0x2;
+
+    // Submit a command whose data is split over as many descriptors as
+    // there are items in `data`, and return the status.
+    fn send_command(
+        ctrl: &mut CtrlQueue,
+        mem: &GuestMemoryMmap,
+        vq: &VirtQueue,
+        class: u32,
+        cmd: u32,
+        data: &[&[u8]],
+    ) -> u8 {
+        let mut queue = vq.create_queue();
+        let hdr = ControlHeader {
+            class: class as u8,
+            cmd: cmd as u8,
+        };
+        mem.write_obj(hdr, GuestAddress(0x1000)).unwrap();
+        vq.dtable[0].set(0x1000, 2, VIRTQ_DESC_F_NEXT, 1);
+        for (i, data) in data.iter().enumerate() {
+            let addr = 0x2000 + (i as u64) * 0x1000;
+            mem.write_slice(data, GuestAddress(addr)).unwrap();
+            vq.dtable[i + 1].set(addr, data.len() as u32, VIRTQ_DESC_F_NEXT, i as u16 + 2);
+        }
+        let status = data.len() + 1;
+        vq.dtable[status].set(0x8000, 1, VIRTQ_DESC_F_WRITE, 0);
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+
+        assert!(ctrl.process(mem, &mut queue).unwrap());
+        mem.read_obj(GuestAddress(0x8000)).unwrap()
+    }
+
+    fn mac_table(macs: &[&str]) -> Vec<u8> {
+        let mut table = (macs.len() as u32).to_le_bytes().to_vec();
+        for mac in macs {
+            table.extend_from_slice(MacAddr::parse_str(mac).unwrap().get_bytes());
+        }
+        table
+    }
+
+    #[test]
+    fn test_ctrl_queue_rx_filter() {
+        let mem = &GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), mem, 16);
+        let mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let rx_filter = RxFilter::new(Some(mac));
+        rx_filter.reset(true);
+        let mut ctrl = CtrlQueue::new(Vec::new(), rx_filter.clone(), None, Rss::default());
+
+        let mut frame = vec![0u8; 60];
+        frame[..6].copy_from_slice(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbd]);
+        assert!(rx_filter.accept_frame(&frame));
+
+        let status = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_RX,
+            VIRTIO_NET_CTRL_RX_PROMISC,
+            &[&[0]],
+        );
+        assert_eq!(u32::from(status), VIRTIO_NET_OK);
+        assert!(!rx_filter.accept_frame(&frame));
+
+        let uni = mac_table(&["12:34:56:78:9a:bd"]);
+        let multi = mac_table(&["01:00:5e:00:00:01", "33:33:00:00:00:01"]);
+        let status = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_MAC,
+            VIRTIO_NET_CTRL_MAC_TABLE_SET,
+            &[&uni, &multi],
+        );
+        assert_eq!(u32::from(status), VIRTIO_NET_OK);
+        assert!(rx_filter.accept_frame(&frame));
+
+        // The multicast table is missing.
+        let status = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_MAC,
+            VIRTIO_NET_CTRL_MAC_TABLE_SET,
+            &[&uni],
+        );
+        assert_eq!(u32::from(status), VIRTIO_NET_ERR);
+
+        let new_mac = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbe];
+        let status = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_MAC,
+            VIRTIO_NET_CTRL_MAC_ADDR_SET,
+            &[&new_mac],
+        );
+        assert_eq!(u32::from(status), VIRTIO_NET_OK);
+        assert_eq!(
+            rx_filter.mac(),
+            Some(MacAddr::from_bytes_unchecked(&new_mac))
+        );
+
+        // Tag the frame with VLAN 42.
+        frame[12..16].copy_from_slice(&[0x81, 0x00, 0x00, 42]);
+        assert!(!rx_filter.accept_frame(&frame));
+        let status = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_VLAN,
+            VIRTIO_NET_CTRL_VLAN_ADD,
+            &[&42u16.to_le_bytes()],
+        );
+        assert_eq!(u32::from(status), VIRTIO_NET_OK);
+        assert!(rx_filter.accept_frame(&frame));
+        let status = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_VLAN,
+            VIRTIO_NET_CTRL_VLAN_ADD,
+            &[&4096u16.to_le_bytes()],
+        );
+        assert_eq!(u32::from(status), VIRTIO_NET_ERR);
+    }
+
+    #[test]
+    fn test_ctrl_queue_rss() {
+        let mem = &GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), mem, 16);
+        let rss = Rss::new(2).unwrap();
+        rss.reset(2, true, false);
+        let mut ctrl = CtrlQueue::new(Vec::new(), RxFilter::default(), None, rss.clone());
+
+        // Hash types, indirection table of 2 entries, max_tx_vq and key,
+        // split the way the Linux driver does.
+        let mut header = 1u32.to_le_bytes().to_vec();
+        header.extend_from_slice(&[1, 0, 0, 0]);
+        let table = [0, 0, 1, 0];
+        let key = [2, 0, 4, 1, 2, 3, 4];
+        let status = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_MQ,
+            VIRTIO_NET_CTRL_MQ_RSS_CONFIG,
+            &[&header, &table, &key],
+        );
+        assert_eq!(u32::from(status), VIRTIO_NET_OK);
+        assert!(rss.is_active());
+
+        // The queue pair 2 doesn't exist.
+        let table = [0, 0, 2, 0];
+        let status = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_MQ,
+            VIRTIO_NET_CTRL_MQ_RSS_CONFIG,
+            &[&header, &table, &key],
+        );
+        assert_eq!(u32::from(status), VIRTIO_NET_ERR);
+
+        // Hash reporting has not been negotiated.
+        let status = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_MQ,
+            VIRTIO_NET_CTRL_MQ_HASH_CONFIG,
+            &[&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]],
+        );
+        assert_eq!(u32::from(status), VIRTIO_NET_ERR);
+    }
+
+    #[test]
+    fn test_ctrl_queue_announce() {
+        let mem = &GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), mem, 16);
+        let status = Arc::new(AtomicU16::new(1 | VIRTIO_NET_S_ANNOUNCE as u16));
+
+        let mut ctrl = CtrlQueue::new(Vec::new(), RxFilter::default(), None, Rss::default());
+        let ret = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_ANNOUNCE,
+            VIRTIO_NET_CTRL_ANNOUNCE_ACK,
+            &[],
+        );
+        assert_eq!(u32::from(ret), VIRTIO_NET_ERR);
+
+        let mut ctrl = CtrlQueue::new(
+            Vec::new(),
+            RxFilter::default(),
+            Some(status.clone()),
+            Rss::default(),
+        );
+        let ret = send_command(
+            &mut ctrl,
+            mem,
+            &vq,
+            VIRTIO_NET_CTRL_ANNOUNCE,
+            VIRTIO_NET_CTRL_ANNOUNCE_ACK,
+            &[],
+        );
+        assert_eq!(u32::from(ret), VIRTIO_NET_OK);
+        assert_eq!(status.load(Ordering::Acquire), 1);
+    }
+}
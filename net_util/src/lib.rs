@@ -15,9 +15,15 @@ extern crate log;
 
 mod ctrl_queue;
 mod mac;
+mod netlink;
 mod open_tap;
+mod pcap;
 mod queue_pair;
+mod rss;
+mod rx_filter;
 mod tap;
+mod user_net;
+mod vhost_net;
 
 use std::io::Error as IoError;
 use std::os::unix::io::{FromRawFd, RawFd};
@@ -26,8 +32,16 @@ use std::{io, mem, net};
 pub use ctrl_queue::{CtrlQueue, Error as CtrlQueueError};
 pub use mac::{MacAddr, MAC_ADDR_LEN};
 pub use open_tap::{open_tap, Error as OpenTapError};
+pub use pcap::{Error as PacketCaptureError, PacketCapture};
 pub use queue_pair::{NetCounters, NetQueuePair, NetQueuePairError, RxVirtio, TxVirtio};
+pub use rss::{
+    Rss, RSS_MAX_INDIRECTION_TABLE_LEN, RSS_MAX_KEY_SIZE, RSS_SUPPORTED_HASH_TYPES,
+    VIRTIO_NET_F_HASH_REPORT, VIRTIO_NET_F_RSS,
+};
+pub use rx_filter::{RxFilter, RxFilterSnapshot, RxMode};
 pub use tap::{Error as TapError, Tap};
+pub use user_net::{Error as UserNetError, PortForward, UserNet, UserNetConfig};
+pub use vhost_net::{Error as VhostNetError, VhostNet, VHOST_NET_RX_QUEUE, VHOST_NET_TX_QUEUE};
 
 #[derive(Debug)]
 pub enum Error {
@@ -0,0 +1,215 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Minimal rtnetlink client, used to configure the addresses of an
+//! interface, which the socket ioctls can only do for a single IPv4 address.
+
+use std::fs::File;
+use std::io;
+use std::mem::size_of;
+use std::net::IpAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+const NLMSG_HDR_LEN: usize = 16;
+const IFADDRMSG_LEN: usize = 8;
+const RTA_HDR_LEN: usize = 4;
+const NLMSG_ERROR_LEN: usize = NLMSG_HDR_LEN + 4;
+
+const NLMSG_ERROR: u16 = 2;
+const RTM_NEWADDR: u16 = 20;
+
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_REPLACE: u16 = 0x100;
+const NLM_F_CREATE: u16 = 0x400;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+
+const IFA_F_PERMANENT: u8 = 0x80;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+
+fn push_attr(buf: &mut Vec<u8>, attr_type: u16, data: &[u8]) {
+    let len = RTA_HDR_LEN + data.len();
+    buf.extend_from_slice(&(len as u16).to_ne_bytes());
+    buf.extend_from_slice(&attr_type.to_ne_bytes());
+    buf.extend_from_slice(data);
+    // Attributes are aligned on 4 bytes.
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+/// Build a RTM_NEWADDR request adding `addr` to the interface, replacing it
+/// if it is already set, so that the request can be issued again after a
+/// reboot of the VM.
+fn new_addr_request(seq: u32, if_index: u32, addr: IpAddr, prefix_len: u8) -> Vec<u8> {
+    let (family, octets) = match addr {
+        IpAddr::V4(addr) => (libc::AF_INET, addr.octets().to_vec()),
+        IpAddr::V6(addr) => (libc::AF_INET6, addr.octets().to_vec()),
+    };
+
+    let mut buf = Vec::with_capacity(NLMSG_HDR_LEN + IFADDRMSG_LEN + 2 * (RTA_HDR_LEN + 16));
+    // struct nlmsghdr, the length being filled once the message is built.
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    buf.extend_from_slice(&RTM_NEWADDR.to_ne_bytes());
+    buf.extend_from_slice(
+        &(NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE).to_ne_bytes(),
+    );
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    // struct ifaddrmsg
+    buf.push(family as u8);
+    buf.push(prefix_len);
+    buf.push(IFA_F_PERMANENT);
+    buf.push(RT_SCOPE_UNIVERSE);
+    buf.extend_from_slice(&if_index.to_ne_bytes());
+
+    push_attr(&mut buf, IFA_LOCAL, &octets);
+    push_attr(&mut buf, IFA_ADDRESS, &octets);
+
+    let len = buf.len() as u32;
+    buf[..4].copy_from_slice(&len.to_ne_bytes());
+
+    buf
+}
+
+/// Extract the error code from the acknowledgement of a request.
+fn parse_ack(buf: &[u8], seq: u32) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid netlink answer");
+
+    if buf.len() < NLMSG_ERROR_LEN {
+        return Err(invalid());
+    }
+
+    let msg_type = u16::from_ne_bytes([buf[4], buf[5]]);
+    let msg_seq = u32::from_ne_bytes([buf[8], buf[9], buf[10], buf[11]]);
+    if msg_type != NLMSG_ERROR || msg_seq != seq {
+        return Err(invalid());
+    }
+
+    let error = i32::from_ne_bytes([buf[16], buf[17], buf[18], buf[19]]);
+    if error != 0 {
+        return Err(io::Error::from_raw_os_error(-error));
+    }
+
+    Ok(())
+}
+
+struct NetlinkSocket {
+    sock: File,
+}
+
+impl NetlinkSocket {
+    fn new() -> io::Result<Self> {
+        // This is safe since we check the return value.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // This is safe; nothing else will use or hold onto the raw sock fd.
+        Ok(NetlinkSocket {
+            sock: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    fn request(&self, msg: &[u8], seq: u32) -> io::Result<()> {
+        // The kernel is the destination, with a port id of 0.
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as u16;
+
+        // This is safe since the message and the address outlive the call,
+        // and we check the return value.
+        let ret = unsafe {
+            libc::sendto(
+                self.sock.as_raw_fd(),
+                msg.as_ptr() as *const libc::c_void,
+                msg.len(),
+                0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The acknowledgement holds the error code followed by the header of
+        // the request.
+        let mut buf = [0u8; 256];
+        // This is safe since the buffer outlives the call, and we check the
+        // return value.
+        let ret = unsafe {
+            libc::recv(
+                self.sock.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        parse_ack(&buf[..ret as usize], seq)
+    }
+}
+
+/// Add an IPv4 or IPv6 address to the interface with the given index.
+pub(crate) fn add_address(if_index: u32, addr: IpAddr, prefix_len: u8) -> io::Result<()> {
+    let sock = NetlinkSocket::new()?;
+    sock.request(&new_addr_request(1, if_index, addr, prefix_len), 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_addr_request() {
+        let msg = new_addr_request(7, 3, "10.0.0.1".parse().unwrap(), 24);
+        // Header, ifaddrmsg and two attributes holding an IPv4 address.
+        assert_eq!(msg.len(), 16 + 8 + 2 * 8);
+        assert_eq!(u32::from_ne_bytes([msg[0], msg[1], msg[2], msg[3]]), 40);
+        assert_eq!(u16::from_ne_bytes([msg[4], msg[5]]), RTM_NEWADDR);
+        assert_eq!(u32::from_ne_bytes([msg[8], msg[9], msg[10], msg[11]]), 7);
+        assert_eq!(msg[16], libc::AF_INET as u8);
+        assert_eq!(msg[17], 24);
+        assert_eq!(u32::from_ne_bytes([msg[20], msg[21], msg[22], msg[23]]), 3);
+        assert_eq!(u16::from_ne_bytes([msg[24], msg[25]]), 8);
+        assert_eq!(u16::from_ne_bytes([msg[26], msg[27]]), IFA_LOCAL);
+        assert_eq!(&msg[28..32], &[10, 0, 0, 1]);
+        assert_eq!(u16::from_ne_bytes([msg[34], msg[35]]), IFA_ADDRESS);
+
+        let msg = new_addr_request(1, 3, "fd00::1".parse().unwrap(), 64);
+        assert_eq!(msg.len(), 16 + 8 + 2 * 20);
+        assert_eq!(msg[16], libc::AF_INET6 as u8);
+        assert_eq!(msg[17], 64);
+        assert_eq!(u16::from_ne_bytes([msg[24], msg[25]]), 20);
+        assert_eq!(msg[28], 0xfd);
+        assert_eq!(msg[43], 1);
+    }
+
+    #[test]
+    fn test_parse_ack() {
+        let mut ack = vec![0u8; 36];
+        ack[..4].copy_from_slice(&36u32.to_ne_bytes());
+        ack[4..6].copy_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        ack[8..12].copy_from_slice(&5u32.to_ne_bytes());
+        assert!(parse_ack(&ack, 5).is_ok());
+        assert!(parse_ack(&ack, 6).is_err());
+        assert!(parse_ack(&ack[..12], 5).is_err());
+
+        ack[16..20].copy_from_slice(&(-libc::EPERM).to_ne_bytes());
+        assert_eq!(
+            parse_ack(&ack, 5).unwrap_err().raw_os_error(),
+            Some(libc::EPERM)
+        );
+    }
+}
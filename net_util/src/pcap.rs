@@ -0,0 +1,225 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Capture of the frames going through a network device, in the pcap
+//! format understood by tcpdump and wireshark.
+
+use super::vnet_hdr_len;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 65535;
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+const PCAP_HEADER_LEN: u64 = 24;
+const PCAP_RECORD_HEADER_LEN: u64 = 16;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to create the capture file.
+    CreateFile(io::Error),
+    /// Failed to write the capture file header.
+    WriteHeader(io::Error),
+    /// The size limit can't even hold the capture file header.
+    SizeLimitTooSmall(u64),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+struct PcapWriter {
+    writer: BufWriter<File>,
+    size: u64,
+    max_size: Option<u64>,
+}
+
+impl PcapWriter {
+    fn new(path: &Path, max_size: Option<u64>) -> Result<Self> {
+        if let Some(max_size) = max_size {
+            if max_size < PCAP_HEADER_LEN {
+                return Err(Error::SizeLimitTooSmall(max_size));
+            }
+        }
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(Error::CreateFile)?;
+        let mut writer = BufWriter::new(file);
+
+        let mut header = Vec::with_capacity(PCAP_HEADER_LEN as usize);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+        // Time zone offset and timestamps accuracy.
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+        writer.write_all(&header).map_err(Error::WriteHeader)?;
+
+        Ok(PcapWriter {
+            writer,
+            size: PCAP_HEADER_LEN,
+            max_size,
+        })
+    }
+
+    // Returns false when the size limit has been reached, meaning the
+    // capture is over.
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<bool> {
+        let orig_len = frame.len() as u32;
+        let incl_len = std::cmp::min(orig_len, PCAP_SNAPLEN);
+
+        let record_size = PCAP_RECORD_HEADER_LEN + u64::from(incl_len);
+        if let Some(max_size) = self.max_size {
+            if self.size + record_size > max_size {
+                return Ok(false);
+            }
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut record = Vec::with_capacity(record_size as usize);
+        record.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&now.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&incl_len.to_le_bytes());
+        record.extend_from_slice(&orig_len.to_le_bytes());
+        record.extend_from_slice(&frame[..incl_len as usize]);
+        self.writer.write_all(&record)?;
+        self.size += record_size;
+
+        Ok(true)
+    }
+}
+
+/// Packet capture shared by all the queue pairs of a network device. It is
+/// inactive until started, in which case the frames are only checked
+/// against an atomic flag.
+#[derive(Clone, Default)]
+pub struct PacketCapture {
+    active: Arc<AtomicBool>,
+    writer: Arc<Mutex<Option<PcapWriter>>>,
+}
+
+impl PacketCapture {
+    /// Start capturing the frames into the file at `path`, replacing any
+    /// ongoing capture. The capture stops by itself once the file reaches
+    /// `max_size` bytes.
+    pub fn start(&self, path: &Path, max_size: Option<u64>) -> Result<()> {
+        let writer = PcapWriter::new(path, max_size)?;
+        *self.writer.lock().unwrap() = Some(writer);
+        self.active.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Stop the ongoing capture, if any.
+    pub fn stop(&self) {
+        self.active.store(false, Ordering::Release);
+        if let Some(mut writer) = self.writer.lock().unwrap().take() {
+            if let Err(e) = writer.writer.flush() {
+                error!("Failed flushing packet capture: {}", e);
+            }
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Record a frame, which is made of the first `len` bytes of the buffers
+    /// described by `iovecs`, the virtio-net header excluded.
+    pub(crate) fn capture_iovecs(&self, iovecs: &[libc::iovec], len: usize) {
+        if !self.is_active() || len <= vnet_hdr_len() {
+            return;
+        }
+
+        let mut frame = Vec::with_capacity(len);
+        let mut remaining = len;
+        for iovec in iovecs {
+            if remaining == 0 {
+                break;
+            }
+            let count = std::cmp::min(iovec.iov_len, remaining);
+            // This is safe since the iovecs describe valid guest memory, which
+            // has just been used for the same transfer.
+            let buf = unsafe { std::slice::from_raw_parts(iovec.iov_base as *const u8, count) };
+            frame.extend_from_slice(buf);
+            remaining -= count;
+        }
+
+        self.capture_frame(&frame[vnet_hdr_len()..]);
+    }
+
+    /// Record an Ethernet frame.
+    pub fn capture_frame(&self, frame: &[u8]) {
+        let mut writer = self.writer.lock().unwrap();
+        let done = match writer.as_mut().map(|w| w.write_frame(frame)) {
+            Some(Ok(true)) => false,
+            Some(Ok(false)) => {
+                info!("Packet capture size limit reached");
+                true
+            }
+            Some(Err(e)) => {
+                error!("Failed writing packet capture: {}", e);
+                true
+            }
+            None => return,
+        };
+
+        if done {
+            self.active.store(false, Ordering::Release);
+            if let Some(mut writer) = writer.take() {
+                let _ = writer.writer.flush();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use vmm_sys_util::tempfile::TempFile;
+
+    #[test]
+    fn test_packet_capture() {
+        let file = TempFile::new().unwrap();
+        let capture = PacketCapture::default();
+        let frame = [0xaau8; 60];
+
+        // Frames are ignored until the capture is started.
+        capture.capture_frame(&frame);
+        assert!(!capture.is_active());
+
+        let max_size = PCAP_HEADER_LEN + 2 * (PCAP_RECORD_HEADER_LEN + frame.len() as u64);
+        capture.start(file.as_path(), Some(max_size)).unwrap();
+        assert!(capture.is_active());
+        capture.capture_frame(&frame);
+        capture.capture_frame(&frame);
+        assert!(capture.is_active());
+        // The third frame doesn't fit, ending the capture.
+        capture.capture_frame(&frame);
+        assert!(!capture.is_active());
+
+        let content = fs::read(file.as_path()).unwrap();
+        assert_eq!(content.len() as u64, max_size);
+        assert_eq!(content[0..4], PCAP_MAGIC.to_le_bytes());
+        assert_eq!(content[20..24], PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+        let record = &content[PCAP_HEADER_LEN as usize..];
+        assert_eq!(record[8..12], 60u32.to_le_bytes());
+        assert_eq!(record[12..16], 60u32.to_le_bytes());
+        assert_eq!(record[16..76], frame[..]);
+
+        assert!(capture.start(file.as_path(), Some(10)).is_err());
+    }
+}
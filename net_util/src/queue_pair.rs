@@ -2,16 +2,58 @@
 //
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
-use super::{unregister_listener, vnet_hdr_len, Tap};
+use super::rss::{hash_report_fields, HASH_REPORT_LEN};
+use super::{unregister_listener, vnet_hdr_len, PacketCapture, Rss, RxFilter, Tap};
 use rate_limiter::{RateLimiter, TokenType};
-use std::io;
+use std::io::{self, Read};
 use std::num::Wrapping;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use vm_memory::{Bytes, GuestAddressSpace, GuestMemory, GuestMemoryAtomic, GuestMemoryMmap};
 use vm_virtio::Queue;
 
+// Large enough for the biggest frame the TAP interface can hand over, with
+// the segmentation offloads enabled.
+const RX_BUFFER_LEN: usize = 65562;
+
+// With VIRTIO_NET_F_HASH_REPORT, the guest uses a virtio-net header extended
+// with the hash fields, which the TAP interface doesn't know about. They are
+// left out of the buffers given to the TAP interface.
+fn skip_hash_fields(iovecs: &mut Vec<libc::iovec>) {
+    let (start, end) = (vnet_hdr_len(), vnet_hdr_len() + HASH_REPORT_LEN);
+    let mut offset = 0;
+    let mut i = 0;
+    while i < iovecs.len() && offset < end {
+        let iovec = iovecs[i];
+        let iovec_end = offset + iovec.iov_len;
+        let (skip_start, skip_end) = (offset.max(start), iovec_end.min(end));
+        if skip_start < skip_end {
+            let mut remaining = Vec::new();
+            if skip_start > offset {
+                remaining.push(libc::iovec {
+                    iov_base: iovec.iov_base,
+                    iov_len: skip_start - offset,
+                });
+            }
+            if iovec_end > skip_end {
+                remaining.push(libc::iovec {
+                    // This is safe since the pointer stays within the buffer.
+                    iov_base: unsafe { (iovec.iov_base as *mut u8).add(skip_end - offset) }
+                        as *mut libc::c_void,
+                    iov_len: iovec_end - skip_end,
+                });
+            }
+            let count = remaining.len();
+            iovecs.splice(i..=i, remaining);
+            i += count;
+        } else {
+            i += 1;
+        }
+        offset = iovec_end;
+    }
+}
+
 #[derive(Clone)]
 pub struct TxVirtio {
     pub counter_bytes: Wrapping<u64>,
@@ -32,13 +74,30 @@ impl TxVirtio {
         }
     }
 
+    /// Drop the frames sent by the guest, while the link is down.
+    pub fn drop_desc_chain(&mut self, mem: &GuestMemoryMmap, queue: &mut Queue) {
+        while let Some(avail_desc) = queue.iter(&mem).next() {
+            let head_index = avail_desc.index;
+            queue.add_used(&mem, head_index, 0);
+            queue.update_avail_event(&mem);
+        }
+    }
+
     pub fn process_desc_chain(
         &mut self,
         mem: &GuestMemoryMmap,
         tap: &mut Tap,
         queue: &mut Queue,
         rate_limiter: &mut Option<RateLimiter>,
+        capture: &PacketCapture,
+        hash_report: bool,
     ) -> Result<(), NetQueuePairError> {
+        let hdr_len = if hash_report {
+            vnet_hdr_len() + HASH_REPORT_LEN
+        } else {
+            vnet_hdr_len()
+        };
+
         while let Some(avail_desc) = queue.iter(&mem).next() {
             let head_index = avail_desc.index;
             let mut next_desc = Some(avail_desc);
@@ -57,7 +116,7 @@ impl TxVirtio {
                     }
                     tmp_next_desc = desc.next_descriptor();
                 }
-                bytes -= Wrapping(vnet_hdr_len() as u64);
+                bytes -= Wrapping(hdr_len as u64);
                 if !rate_limiter.consume(bytes.0, TokenType::Bytes) {
                     // Revert the OPS consume().
                     rate_limiter.manual_replenish(1, TokenType::Ops);
@@ -81,6 +140,9 @@ impl TxVirtio {
                 }
                 next_desc = desc.next_descriptor();
             }
+            if hash_report {
+                skip_hash_fields(&mut iovecs);
+            }
 
             if !iovecs.is_empty() {
                 let result = unsafe {
@@ -103,6 +165,8 @@ impl TxVirtio {
                     return Err(NetQueuePairError::WriteTap(e));
                 }
 
+                capture.capture_iovecs(&iovecs, result as usize);
+
                 self.counter_bytes += Wrapping(result as u64 - vnet_hdr_len() as u64);
                 self.counter_frames += Wrapping(1);
             }
@@ -119,6 +183,9 @@ impl TxVirtio {
 pub struct RxVirtio {
     pub counter_bytes: Wrapping<u64>,
     pub counter_frames: Wrapping<u64>,
+    // Intermediate buffer the frames are read into when they need to be
+    // hashed, only allocated once needed.
+    buffer: Vec<u8>,
 }
 
 impl Default for RxVirtio {
@@ -132,6 +199,7 @@ impl RxVirtio {
         RxVirtio {
             counter_bytes: Wrapping(0),
             counter_frames: Wrapping(0),
+            buffer: Vec::new(),
         }
     }
 
@@ -141,6 +209,9 @@ impl RxVirtio {
         tap: &mut Tap,
         queue: &mut Queue,
         rate_limiter: &mut Option<RateLimiter>,
+        capture: &PacketCapture,
+        rx_filter: &RxFilter,
+        hash_report: bool,
     ) -> Result<bool, NetQueuePairError> {
         let mut exhausted_descs = true;
         let mut rate_limit_reached = false;
@@ -154,6 +225,7 @@ impl RxVirtio {
 
             let head_index = avail_desc.index;
             let num_buffers_addr = mem.checked_offset(avail_desc.addr, 10).unwrap();
+            let hash_addr = mem.checked_offset(avail_desc.addr, vnet_hdr_len()).unwrap();
             let mut next_desc = Some(avail_desc);
 
             let mut iovecs = Vec::new();
@@ -171,6 +243,9 @@ impl RxVirtio {
                 }
                 next_desc = desc.next_descriptor();
             }
+            if hash_report {
+                skip_hash_fields(&mut iovecs);
+            }
 
             let len = if !iovecs.is_empty() {
                 let result = unsafe {
@@ -194,15 +269,31 @@ impl RxVirtio {
                     return Err(NetQueuePairError::ReadTap(e));
                 }
 
+                // The frame is dropped if the guest doesn't want it, and the
+                // same descriptor chain is used for the next one.
+                if !rx_filter.accept_iovecs(&iovecs, result as usize) {
+                    queue.go_to_previous_position();
+                    continue;
+                }
+
                 // Write num_buffers to guest memory. We simply write 1 as we
                 // never spread the frame over more than one descriptor chain.
                 mem.write_obj(1u16, num_buffers_addr)
                     .map_err(NetQueuePairError::GuestMemory)?;
 
+                capture.capture_iovecs(&iovecs, result as usize);
+
                 self.counter_bytes += Wrapping(result as u64 - vnet_hdr_len() as u64);
                 self.counter_frames += Wrapping(1);
 
-                result as u32
+                // No hash is reported until the guest configures it.
+                if hash_report {
+                    mem.write_obj(hash_report_fields(None), hash_addr)
+                        .map_err(NetQueuePairError::GuestMemory)?;
+                    result as u32 + HASH_REPORT_LEN as u32
+                } else {
+                    result as u32
+                }
             } else {
                 0
             };
@@ -222,6 +313,147 @@ impl RxVirtio {
 
         Ok(exhausted_descs)
     }
+
+    /// Read and drop the frames pending on the TAP interface, while the link
+    /// is down.
+    pub fn drop_frames(&mut self, tap: &mut Tap) -> Result<(), NetQueuePairError> {
+        if self.buffer.is_empty() {
+            self.buffer.resize(RX_BUFFER_LEN, 0);
+        }
+
+        loop {
+            match tap.read(&mut self.buffer) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                /* EAGAIN */
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => {
+                    error!("net: rx: failed reading from tap: {}", e);
+                    return Err(NetQueuePairError::ReadTap(e));
+                }
+            }
+        }
+    }
+
+    /// Receive the frames through an intermediate buffer, so that they can
+    /// be hashed and steered to the queue pair selected by the guest RSS
+    /// configuration. The frames steered to `queue_pair` by the other queue
+    /// pairs are received first.
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_rss(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        tap: &mut Tap,
+        queue: &mut Queue,
+        rate_limiter: &mut Option<RateLimiter>,
+        capture: &PacketCapture,
+        rx_filter: &RxFilter,
+        rss: &Rss,
+        queue_pair: usize,
+    ) -> Result<bool, NetQueuePairError> {
+        let mut exhausted_descs = true;
+        let mut rate_limit_reached = false;
+        let hdr_len = vnet_hdr_len();
+        let hash_report = rss.hash_report();
+        let guest_hdr_len = if hash_report {
+            hdr_len + HASH_REPORT_LEN
+        } else {
+            hdr_len
+        };
+        if self.buffer.is_empty() {
+            self.buffer.resize(RX_BUFFER_LEN, 0);
+        }
+
+        while let Some(avail_desc) = queue.iter(&mem).next() {
+            if rate_limit_reached {
+                exhausted_descs = false;
+                queue.go_to_previous_position();
+                break;
+            }
+
+            let mut frame = match rss.pop_frame(queue_pair) {
+                Some(frame) => frame,
+                None => {
+                    let len = match tap.read(&mut self.buffer) {
+                        Ok(len) => len,
+                        Err(e) => {
+                            exhausted_descs = false;
+                            queue.go_to_previous_position();
+
+                            /* EAGAIN */
+                            if e.kind() == std::io::ErrorKind::WouldBlock {
+                                break;
+                            }
+
+                            error!("net: rx: failed reading from tap: {}", e);
+                            return Err(NetQueuePairError::ReadTap(e));
+                        }
+                    };
+
+                    // The frame is dropped if the guest doesn't want it, and
+                    // the same descriptor chain is used for the next one.
+                    let (header, payload) = self.buffer[..len].split_at(hdr_len.min(len));
+                    if payload.is_empty() || !rx_filter.accept_frame(payload) {
+                        queue.go_to_previous_position();
+                        continue;
+                    }
+                    if capture.is_active() {
+                        capture.capture_frame(payload);
+                    }
+
+                    let (target, hash) = rss.classify(payload);
+                    let mut frame = Vec::with_capacity(guest_hdr_len + payload.len());
+                    frame.extend_from_slice(header);
+                    if hash_report {
+                        frame.extend_from_slice(&hash_report_fields(hash));
+                    }
+                    frame.extend_from_slice(payload);
+
+                    match target {
+                        Some(target) if target != queue_pair => {
+                            rss.push_frame(target, frame);
+                            queue.go_to_previous_position();
+                            continue;
+                        }
+                        _ => frame,
+                    }
+                }
+            };
+
+            // Set num_buffers to 1 as we never spread the frame over more
+            // than one descriptor chain.
+            frame[10..12].copy_from_slice(&1u16.to_le_bytes());
+
+            let head_index = avail_desc.index;
+            let mut written = 0;
+            let mut next_desc = Some(avail_desc);
+            while let Some(desc) = next_desc {
+                if written == frame.len() {
+                    break;
+                }
+                if desc.is_write_only() {
+                    let count = std::cmp::min(desc.len as usize, frame.len() - written);
+                    mem.write_slice(&frame[written..written + count], desc.addr)
+                        .map_err(NetQueuePairError::GuestMemory)?;
+                    written += count;
+                }
+                next_desc = desc.next_descriptor();
+            }
+
+            self.counter_bytes += Wrapping((frame.len() - guest_hdr_len) as u64);
+            self.counter_frames += Wrapping(1);
+
+            queue.add_used(&mem, head_index, written as u32);
+            queue.update_avail_event(&mem);
+
+            if let Some(rate_limiter) = rate_limiter {
+                rate_limit_reached = !rate_limiter.consume(1, TokenType::Ops)
+                    || !rate_limiter.consume(written as u64, TokenType::Bytes);
+            }
+        }
+
+        Ok(exhausted_descs)
+    }
 }
 
 #[derive(Default, Clone)]
@@ -260,6 +492,12 @@ pub struct NetQueuePair {
     pub rx_desc_avail: bool,
     pub rx_rate_limiter: Option<RateLimiter>,
     pub tx_rate_limiter: Option<RateLimiter>,
+    pub capture: PacketCapture,
+    pub rx_filter: RxFilter,
+    pub rss: Rss,
+    pub queue_pair_index: usize,
+    // Cleared while the link is down, the frames being dropped both ways.
+    pub link_up: Arc<AtomicBool>,
 }
 
 impl NetQueuePair {
@@ -270,8 +508,19 @@ impl NetQueuePair {
             .ok_or(NetQueuePairError::NoMemoryConfigured)
             .map(|m| m.memory())?;
 
-        self.tx
-            .process_desc_chain(&mem, &mut self.tap, &mut queue, &mut self.tx_rate_limiter)?;
+        if !self.link_up.load(Ordering::Acquire) {
+            self.tx.drop_desc_chain(&mem, &mut queue);
+            return Ok(queue.needs_notification(&mem, queue.next_used));
+        }
+
+        self.tx.process_desc_chain(
+            &mem,
+            &mut self.tap,
+            &mut queue,
+            &mut self.tx_rate_limiter,
+            &self.capture,
+            self.rss.hash_report(),
+        )?;
 
         self.counters
             .tx_bytes
@@ -292,12 +541,38 @@ impl NetQueuePair {
             .ok_or(NetQueuePairError::NoMemoryConfigured)
             .map(|m| m.memory())?;
 
-        self.rx_desc_avail = !self.rx.process_desc_chain(
-            &mem,
-            &mut self.tap,
-            &mut queue,
-            &mut self.rx_rate_limiter,
-        )?;
+        // Nothing is delivered to the guest while the link is down.
+        if !self.link_up.load(Ordering::Acquire) {
+            self.rx.drop_frames(&mut self.tap)?;
+            return Ok(false);
+        }
+
+        // The frames steered from the other queue pairs may still have to
+        // be received after RSS has been disabled.
+        let exhausted_descs = if self.rss.is_active() || self.rss.has_frames(self.queue_pair_index)
+        {
+            self.rx.process_rss(
+                &mem,
+                &mut self.tap,
+                &mut queue,
+                &mut self.rx_rate_limiter,
+                &self.capture,
+                &self.rx_filter,
+                &self.rss,
+                self.queue_pair_index,
+            )?
+        } else {
+            self.rx.process_desc_chain(
+                &mem,
+                &mut self.tap,
+                &mut queue,
+                &mut self.rx_rate_limiter,
+                &self.capture,
+                &self.rx_filter,
+                self.rss.hash_report(),
+            )?
+        };
+        self.rx_desc_avail = !exhausted_descs;
         let rate_limit_reached = self
             .rx_rate_limiter
             .as_ref()
@@ -329,3 +604,54 @@ impl NetQueuePair {
         Ok(queue.needs_notification(&mem, queue.next_used))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iovec(buf: &mut [u8]) -> libc::iovec {
+        libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        }
+    }
+
+    #[test]
+    fn test_skip_hash_fields() {
+        let hdr_len = vnet_hdr_len();
+        let mut buf = vec![0u8; 100];
+        let base = buf.as_ptr() as usize;
+
+        // Header, hash fields and frame in a single buffer.
+        let mut iovecs = vec![iovec(&mut buf)];
+        skip_hash_fields(&mut iovecs);
+        assert_eq!(iovecs.len(), 2);
+        assert_eq!(
+            (iovecs[0].iov_base as usize, iovecs[0].iov_len),
+            (base, hdr_len)
+        );
+        assert_eq!(
+            (iovecs[1].iov_base as usize, iovecs[1].iov_len),
+            (
+                base + hdr_len + HASH_REPORT_LEN,
+                100 - hdr_len - HASH_REPORT_LEN
+            )
+        );
+
+        // Header and hash fields split over two buffers, ending with the
+        // hash fields.
+        let (head, tail) = buf.split_at_mut(hdr_len + 2);
+        let (hash, frame) = tail.split_at_mut(HASH_REPORT_LEN - 2);
+        let mut iovecs = vec![iovec(head), iovec(hash), iovec(frame)];
+        skip_hash_fields(&mut iovecs);
+        assert_eq!(iovecs.len(), 2);
+        assert_eq!(
+            (iovecs[0].iov_base as usize, iovecs[0].iov_len),
+            (base, hdr_len)
+        );
+        assert_eq!(
+            iovecs[1].iov_base as usize,
+            base + hdr_len + HASH_REPORT_LEN
+        );
+    }
+}
@@ -0,0 +1,563 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Receive side scaling and hash reporting of a virtio-net device, as
+//! programmed by the guest through the VIRTIO_NET_CTRL_MQ_RSS_CONFIG and
+//! VIRTIO_NET_CTRL_MQ_HASH_CONFIG control queue commands.
+//!
+//! Each queue pair reads the frames from its own TAP queue, the host kernel
+//! deciding which queue a frame lands on. Once the guest enables RSS, the
+//! queue pairs compute the Toeplitz hash of the frames they read, and hand
+//! them over to the queue pair selected by the indirection table through
+//! its backlog.
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use vmm_sys_util::eventfd::EventFd;
+
+// Definitions from include/uapi/linux/virtio_net.h which virtio-bindings
+// doesn't provide yet.
+pub const VIRTIO_NET_F_HASH_REPORT: u32 = 57;
+pub const VIRTIO_NET_F_RSS: u32 = 60;
+pub const VIRTIO_NET_CTRL_MQ_RSS_CONFIG: u32 = 1;
+pub const VIRTIO_NET_CTRL_MQ_HASH_CONFIG: u32 = 2;
+
+const VIRTIO_NET_RSS_HASH_TYPE_IPV4: u32 = 1 << 0;
+const VIRTIO_NET_RSS_HASH_TYPE_TCPV4: u32 = 1 << 1;
+const VIRTIO_NET_RSS_HASH_TYPE_UDPV4: u32 = 1 << 2;
+const VIRTIO_NET_RSS_HASH_TYPE_IPV6: u32 = 1 << 3;
+const VIRTIO_NET_RSS_HASH_TYPE_TCPV6: u32 = 1 << 4;
+const VIRTIO_NET_RSS_HASH_TYPE_UDPV6: u32 = 1 << 5;
+
+const VIRTIO_NET_HASH_REPORT_IPV4: u16 = 1;
+const VIRTIO_NET_HASH_REPORT_TCPV4: u16 = 2;
+const VIRTIO_NET_HASH_REPORT_UDPV4: u16 = 3;
+const VIRTIO_NET_HASH_REPORT_IPV6: u16 = 4;
+const VIRTIO_NET_HASH_REPORT_TCPV6: u16 = 5;
+const VIRTIO_NET_HASH_REPORT_UDPV6: u16 = 6;
+
+/// Hash types the device can compute. The ones relying on the IPv6
+/// extension headers aren't supported.
+pub const RSS_SUPPORTED_HASH_TYPES: u32 = VIRTIO_NET_RSS_HASH_TYPE_IPV4
+    | VIRTIO_NET_RSS_HASH_TYPE_TCPV4
+    | VIRTIO_NET_RSS_HASH_TYPE_UDPV4
+    | VIRTIO_NET_RSS_HASH_TYPE_IPV6
+    | VIRTIO_NET_RSS_HASH_TYPE_TCPV6
+    | VIRTIO_NET_RSS_HASH_TYPE_UDPV6;
+/// Longest hash key the guest can provide.
+pub const RSS_MAX_KEY_SIZE: u8 = 40;
+/// Largest indirection table the guest can provide.
+pub const RSS_MAX_INDIRECTION_TABLE_LEN: u16 = 128;
+/// Size of the hash value and report following the virtio-net header when
+/// VIRTIO_NET_F_HASH_REPORT is negotiated.
+pub const HASH_REPORT_LEN: usize = 8;
+
+// Frames waiting for a queue pair to receive them, beyond which the frames
+// steered to it are dropped.
+const MAX_BACKLOG_FRAMES: usize = 256;
+
+const ETH_HEADER_LEN: usize = 14;
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86dd;
+const ETH_P_8021Q: u16 = 0x8100;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// Hash of a frame, along with the kind of fields it has been computed on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameHash {
+    pub value: u32,
+    pub report: u16,
+}
+
+/// Hash fields following the virtio-net header, which are left to zero
+/// when the frame has no hash.
+pub fn hash_report_fields(hash: Option<FrameHash>) -> [u8; HASH_REPORT_LEN] {
+    let mut fields = [0u8; HASH_REPORT_LEN];
+    if let Some(hash) = hash {
+        fields[..4].copy_from_slice(&hash.value.to_le_bytes());
+        fields[4..6].copy_from_slice(&hash.report.to_le_bytes());
+    }
+    fields
+}
+
+/// Compute the Toeplitz hash of `input`, as defined by the Microsoft RSS
+/// specification. The key is considered as padded with zeroes when shorter
+/// than the input.
+fn toeplitz_hash(key: &[u8], input: &[u8]) -> u32 {
+    let key_byte = |i: usize| key.get(i).copied().unwrap_or(0);
+    // The 32 bits of the key starting at the current input bit.
+    let mut window = u32::from_be_bytes([key_byte(0), key_byte(1), key_byte(2), key_byte(3)]);
+    let mut hash = 0;
+    for (i, byte) in input.iter().enumerate() {
+        let next = key_byte(i + 4);
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                hash ^= window;
+            }
+            window = (window << 1) | u32::from((next >> (7 - bit)) & 1);
+        }
+    }
+    hash
+}
+
+// Extract the fields the hash is computed on, according to the enabled hash
+// types, along with the matching report type. The ports are only used when
+// the frame isn't an IP fragment, and the IPv6 extension headers aren't
+// parsed.
+fn hash_input(frame: &[u8], hash_types: u32) -> Option<(Vec<u8>, u16)> {
+    let mut offset = ETH_HEADER_LEN;
+    let mut ether_type = u16::from_be_bytes(frame.get(12..14)?.try_into().unwrap());
+    if ether_type == ETH_P_8021Q {
+        ether_type = u16::from_be_bytes(frame.get(16..18)?.try_into().unwrap());
+        offset += 4;
+    }
+    let ip = frame.get(offset..)?;
+
+    let (mut input, protocol, l4, ip_report, tcp_report, udp_report, types) = match ether_type {
+        ETH_P_IP if ip.len() >= IPV4_HEADER_LEN => {
+            let header_len = usize::from(ip[0] & 0xf) * 4;
+            let fragment = u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff != 0;
+            let l4 = if fragment { None } else { ip.get(header_len..) };
+            (
+                ip[12..20].to_vec(),
+                ip[9],
+                l4,
+                VIRTIO_NET_HASH_REPORT_IPV4,
+                VIRTIO_NET_HASH_REPORT_TCPV4,
+                VIRTIO_NET_HASH_REPORT_UDPV4,
+                [
+                    VIRTIO_NET_RSS_HASH_TYPE_IPV4,
+                    VIRTIO_NET_RSS_HASH_TYPE_TCPV4,
+                    VIRTIO_NET_RSS_HASH_TYPE_UDPV4,
+                ],
+            )
+        }
+        ETH_P_IPV6 if ip.len() >= IPV6_HEADER_LEN => (
+            ip[8..40].to_vec(),
+            ip[6],
+            ip.get(IPV6_HEADER_LEN..),
+            VIRTIO_NET_HASH_REPORT_IPV6,
+            VIRTIO_NET_HASH_REPORT_TCPV6,
+            VIRTIO_NET_HASH_REPORT_UDPV6,
+            [
+                VIRTIO_NET_RSS_HASH_TYPE_IPV6,
+                VIRTIO_NET_RSS_HASH_TYPE_TCPV6,
+                VIRTIO_NET_RSS_HASH_TYPE_UDPV6,
+            ],
+        ),
+        _ => return None,
+    };
+
+    let ports = l4.and_then(|l4| l4.get(..4));
+    let l4_report = match protocol {
+        IPPROTO_TCP if hash_types & types[1] != 0 => Some(tcp_report),
+        IPPROTO_UDP if hash_types & types[2] != 0 => Some(udp_report),
+        _ => None,
+    };
+    match (ports, l4_report) {
+        (Some(ports), Some(report)) => {
+            input.extend_from_slice(ports);
+            Some((input, report))
+        }
+        _ if hash_types & types[0] != 0 => Some((input, ip_report)),
+        _ => None,
+    }
+}
+
+struct RssState {
+    // Number of queue pairs in use, which the frames can be steered to.
+    queue_pairs: usize,
+    rss_enabled: bool,
+    hash_report: bool,
+    hash_types: u32,
+    key: Vec<u8>,
+    // Empty when the frames are not steered, which is the case until the
+    // guest provides a RSS configuration.
+    indirection_table: Vec<u16>,
+    unclassified_queue: u16,
+}
+
+impl RssState {
+    fn new(queue_pairs: usize, rss_enabled: bool, hash_report: bool) -> Self {
+        RssState {
+            queue_pairs,
+            rss_enabled,
+            hash_report,
+            hash_types: 0,
+            key: Vec::new(),
+            indirection_table: Vec::new(),
+            unclassified_queue: 0,
+        }
+    }
+
+    fn active(&self) -> bool {
+        !self.indirection_table.is_empty() || (self.hash_report && self.hash_types != 0)
+    }
+}
+
+struct Backlog {
+    frames: Mutex<VecDeque<Vec<u8>>>,
+    evt: EventFd,
+}
+
+/// RSS and hash reporting state shared by the control queue and all the
+/// queue pairs of a network device. It is inactive until the guest
+/// configures it, in which case the frames are hashed and steered.
+#[derive(Clone)]
+pub struct Rss {
+    active: Arc<AtomicBool>,
+    state: Arc<RwLock<RssState>>,
+    backlogs: Arc<Vec<Backlog>>,
+}
+
+impl Default for Rss {
+    fn default() -> Self {
+        Rss {
+            active: Arc::new(AtomicBool::new(false)),
+            state: Arc::new(RwLock::new(RssState::new(0, false, false))),
+            backlogs: Arc::new(Vec::new()),
+        }
+    }
+}
+
+impl Rss {
+    /// Create the state for a device with up to `num_queue_pairs` queue
+    /// pairs, each one having its own backlog.
+    pub fn new(num_queue_pairs: usize) -> io::Result<Self> {
+        let mut backlogs = Vec::new();
+        for _ in 0..num_queue_pairs {
+            backlogs.push(Backlog {
+                frames: Mutex::new(VecDeque::new()),
+                evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            });
+        }
+
+        Ok(Rss {
+            backlogs: Arc::new(backlogs),
+            ..Default::default()
+        })
+    }
+
+    fn update<F: FnOnce(&mut RssState) -> bool>(&self, f: F) -> bool {
+        let mut state = self.state.write().unwrap();
+        let ok = f(&mut state);
+        self.active.store(state.active(), Ordering::Release);
+        ok
+    }
+
+    /// Disable RSS and hash reporting until the guest configures them,
+    /// forgetting about the frames waiting in the backlogs. `rss_enabled`
+    /// and `hash_report` tell which of VIRTIO_NET_F_RSS and
+    /// VIRTIO_NET_F_HASH_REPORT have been negotiated.
+    pub fn reset(&self, queue_pairs: usize, rss_enabled: bool, hash_report: bool) {
+        self.update(|state| {
+            *state = RssState::new(queue_pairs, rss_enabled, hash_report);
+            true
+        });
+        for backlog in self.backlogs.iter() {
+            backlog.frames.lock().unwrap().clear();
+        }
+    }
+
+    /// Whether the hash fields follow the virtio-net header.
+    pub fn hash_report(&self) -> bool {
+        self.state.read().unwrap().hash_report
+    }
+
+    /// Whether the frames need to be hashed, either to be steered or to
+    /// get their hash reported.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Apply the data of a VIRTIO_NET_CTRL_MQ_RSS_CONFIG command. Returns
+    /// false if the configuration is invalid.
+    pub fn set_rss_config(&self, data: &[u8]) -> bool {
+        let le16 = |offset: usize| -> Option<u16> {
+            Some(u16::from_le_bytes(
+                data.get(offset..offset + 2)?.try_into().unwrap(),
+            ))
+        };
+        let parse = || -> Option<(u32, Vec<u16>, u16, Vec<u8>)> {
+            let hash_types = u32::from_le_bytes(data.get(..4)?.try_into().unwrap());
+            let table_len = usize::from(le16(4)?) + 1;
+            let unclassified_queue = le16(6)?;
+            let table = (0..table_len)
+                .map(|i| le16(8 + i * 2))
+                .collect::<Option<Vec<u16>>>()?;
+            // The max_tx_vq field is followed by the key.
+            let key_offset = 8 + table_len * 2 + 2;
+            let key_len = usize::from(*data.get(key_offset)?);
+            let key = data.get(key_offset + 1..key_offset + 1 + key_len)?;
+            Some((hash_types, table, unclassified_queue, key.to_vec()))
+        };
+        let (hash_types, table, unclassified_queue, key) = match parse() {
+            Some(config) => config,
+            None => return false,
+        };
+
+        let ok = self.update(|state| {
+            let queue_valid = |queue: u16| usize::from(queue) < state.queue_pairs;
+            if !state.rss_enabled
+                || hash_types & !RSS_SUPPORTED_HASH_TYPES != 0
+                || !table.len().is_power_of_two()
+                || table.len() > usize::from(RSS_MAX_INDIRECTION_TABLE_LEN)
+                || !table.iter().all(|q| queue_valid(*q))
+                || !queue_valid(unclassified_queue)
+                || key.len() > usize::from(RSS_MAX_KEY_SIZE)
+            {
+                return false;
+            }
+
+            state.hash_types = hash_types;
+            state.key = key;
+            state.indirection_table = table;
+            state.unclassified_queue = unclassified_queue;
+            true
+        });
+        if ok {
+            info!("RSS configured with hash types {:#x}", hash_types);
+        }
+        ok
+    }
+
+    /// Apply the data of a VIRTIO_NET_CTRL_MQ_HASH_CONFIG command, which
+    /// enables hash reporting without steering the frames. Returns false if
+    /// the configuration is invalid.
+    pub fn set_hash_config(&self, data: &[u8]) -> bool {
+        // The hash types are followed by reserved fields and the key.
+        let parse = || -> Option<(u32, Vec<u8>)> {
+            let hash_types = u32::from_le_bytes(data.get(..4)?.try_into().unwrap());
+            let key_len = usize::from(*data.get(12)?);
+            let key = data.get(13..13 + key_len)?;
+            Some((hash_types, key.to_vec()))
+        };
+        let (hash_types, key) = match parse() {
+            Some(config) => config,
+            None => return false,
+        };
+
+        self.update(|state| {
+            if !state.hash_report
+                || hash_types & !RSS_SUPPORTED_HASH_TYPES != 0
+                || key.len() > usize::from(RSS_MAX_KEY_SIZE)
+            {
+                return false;
+            }
+
+            state.hash_types = hash_types;
+            state.key = key;
+            state.indirection_table = Vec::new();
+            true
+        })
+    }
+
+    /// Compute the hash of the Ethernet frame `frame`, and tell which queue
+    /// pair should receive it, if it has to be steered.
+    pub fn classify(&self, frame: &[u8]) -> (Option<usize>, Option<FrameHash>) {
+        let state = self.state.read().unwrap();
+        let hash = hash_input(frame, state.hash_types).map(|(input, report)| FrameHash {
+            value: toeplitz_hash(&state.key, &input),
+            report,
+        });
+
+        let queue_pair = if state.indirection_table.is_empty() {
+            None
+        } else {
+            let queue = match hash {
+                Some(hash) => {
+                    let mask = state.indirection_table.len() - 1;
+                    state.indirection_table[hash.value as usize & mask]
+                }
+                None => state.unclassified_queue,
+            };
+            Some(usize::from(queue))
+        };
+
+        (queue_pair, if state.hash_report { hash } else { None })
+    }
+
+    /// Hand over `frame`, virtio-net header included, to the queue pair
+    /// `queue_pair`. The frame is dropped if its backlog is full.
+    pub fn push_frame(&self, queue_pair: usize, frame: Vec<u8>) {
+        let backlog = &self.backlogs[queue_pair];
+        let mut frames = backlog.frames.lock().unwrap();
+        if frames.len() >= MAX_BACKLOG_FRAMES {
+            debug!("Dropping frame steered to full queue pair {}", queue_pair);
+            return;
+        }
+        frames.push_back(frame);
+        if let Err(e) = backlog.evt.write(1) {
+            error!("Failed signalling queue pair {}: {}", queue_pair, e);
+        }
+    }
+
+    /// Next frame steered to the queue pair `queue_pair`.
+    pub fn pop_frame(&self, queue_pair: usize) -> Option<Vec<u8>> {
+        self.backlogs
+            .get(queue_pair)
+            .and_then(|backlog| backlog.frames.lock().unwrap().pop_front())
+    }
+
+    pub fn has_frames(&self, queue_pair: usize) -> bool {
+        self.backlogs
+            .get(queue_pair)
+            .map_or(false, |backlog| !backlog.frames.lock().unwrap().is_empty())
+    }
+
+    /// Event signalled when frames are steered to the queue pair
+    /// `queue_pair`.
+    pub fn backlog_evt(&self, queue_pair: usize) -> Option<&EventFd> {
+        self.backlogs.get(queue_pair).map(|backlog| &backlog.evt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Key and results from the Microsoft RSS verification suite.
+    const KEY: [u8; 40] = [
+        0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f,
+        0xb0, 0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30,
+        0xf2, 0x0c, 0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+    ];
+
+    fn tcp4_frame() -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&ETH_P_IP.to_be_bytes());
+        let mut ip = vec![0u8; IPV4_HEADER_LEN];
+        ip[0] = 0x45;
+        ip[9] = IPPROTO_TCP;
+        ip[12..16].copy_from_slice(&[66, 9, 149, 187]);
+        ip[16..20].copy_from_slice(&[161, 142, 100, 80]);
+        frame.extend_from_slice(&ip);
+        frame.extend_from_slice(&2794u16.to_be_bytes());
+        frame.extend_from_slice(&1766u16.to_be_bytes());
+        frame.resize(80, 0);
+        frame
+    }
+
+    fn rss_config(hash_types: u32, table: &[u16], key: &[u8]) -> Vec<u8> {
+        let mut data = hash_types.to_le_bytes().to_vec();
+        data.extend_from_slice(&(table.len() as u16 - 1).to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        for queue in table {
+            data.extend_from_slice(&queue.to_le_bytes());
+        }
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.push(key.len() as u8);
+        data.extend_from_slice(key);
+        data
+    }
+
+    #[test]
+    fn test_toeplitz_hash() {
+        let frame = tcp4_frame();
+        let (input, report) = hash_input(&frame, VIRTIO_NET_RSS_HASH_TYPE_IPV4).unwrap();
+        assert_eq!(report, VIRTIO_NET_HASH_REPORT_IPV4);
+        assert_eq!(toeplitz_hash(&KEY, &input), 0x323e_8fc2);
+        let (input, report) = hash_input(&frame, RSS_SUPPORTED_HASH_TYPES).unwrap();
+        assert_eq!(report, VIRTIO_NET_HASH_REPORT_TCPV4);
+        assert_eq!(toeplitz_hash(&KEY, &input), 0x51cc_c178);
+        assert!(hash_input(&frame, VIRTIO_NET_RSS_HASH_TYPE_IPV6).is_none());
+
+        let mut input = Vec::new();
+        input.extend_from_slice(&[0x3f, 0xfe, 0x25, 0x01, 0x02, 0x00, 0x1f, 0xff]);
+        input.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 7]);
+        input.extend_from_slice(&[0x3f, 0xfe, 0x25, 0x01, 0x02, 0x00, 0x00, 0x03]);
+        input.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(toeplitz_hash(&KEY, &input), 0x2cc1_8cd5);
+        input.extend_from_slice(&2794u16.to_be_bytes());
+        input.extend_from_slice(&1766u16.to_be_bytes());
+        assert_eq!(toeplitz_hash(&KEY, &input), 0x4020_7d3d);
+    }
+
+    #[test]
+    fn test_rss_steering() {
+        let rss = Rss::new(4).unwrap();
+        let frame = tcp4_frame();
+        assert!(!rss.is_active());
+        assert_eq!(rss.classify(&frame), (None, None));
+
+        // Not negotiated.
+        rss.reset(4, false, true);
+        assert!(!rss.set_rss_config(&rss_config(RSS_SUPPORTED_HASH_TYPES, &[0, 1], &KEY)));
+
+        rss.reset(4, true, true);
+        // The table length must be a power of two, and the queues in use.
+        assert!(!rss.set_rss_config(&rss_config(RSS_SUPPORTED_HASH_TYPES, &[0, 1, 2], &KEY)));
+        assert!(!rss.set_rss_config(&rss_config(RSS_SUPPORTED_HASH_TYPES, &[0, 4], &KEY)));
+        assert!(!rss.set_rss_config(&rss_config(1 << 6, &[0, 1], &KEY)));
+
+        // Steer the frame to the queue pair 2, and any other to 3.
+        let mut table = [3u16; 16];
+        table[0x51cc_c178 & 0xf] = 2;
+        assert!(rss.set_rss_config(&rss_config(RSS_SUPPORTED_HASH_TYPES, &table, &KEY)));
+        assert!(rss.is_active());
+        assert_eq!(
+            rss.classify(&frame),
+            (
+                Some(2),
+                Some(FrameHash {
+                    value: 0x51cc_c178,
+                    report: VIRTIO_NET_HASH_REPORT_TCPV4,
+                })
+            )
+        );
+        // Frames which can't be hashed go to the unclassified queue.
+        assert_eq!(rss.classify(&[0u8; 60]), (Some(0), None));
+
+        rss.push_frame(1, vec![1]);
+        rss.push_frame(1, vec![2]);
+        assert!(rss.has_frames(1));
+        assert_eq!(rss.backlog_evt(1).unwrap().read().unwrap(), 2);
+        assert_eq!(rss.pop_frame(1), Some(vec![1]));
+        assert_eq!(rss.pop_frame(1), Some(vec![2]));
+        assert_eq!(rss.pop_frame(1), None);
+
+        rss.push_frame(0, vec![1]);
+        rss.reset(4, true, false);
+        assert!(!rss.is_active());
+        assert!(!rss.has_frames(0));
+    }
+
+    #[test]
+    fn test_hash_report() {
+        let rss = Rss::new(1).unwrap();
+        rss.reset(1, false, true);
+
+        let mut data = VIRTIO_NET_RSS_HASH_TYPE_IPV4.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0u8; 8]);
+        data.push(KEY.len() as u8);
+        data.extend_from_slice(&KEY);
+        assert!(rss.set_hash_config(&data));
+        assert!(rss.is_active());
+
+        let hash = rss.classify(&tcp4_frame());
+        assert_eq!(
+            hash,
+            (
+                None,
+                Some(FrameHash {
+                    value: 0x323e_8fc2,
+                    report: VIRTIO_NET_HASH_REPORT_IPV4,
+                })
+            )
+        );
+        assert_eq!(
+            hash_report_fields(hash.1),
+            [0xc2, 0x8f, 0x3e, 0x32, 1, 0, 0, 0]
+        );
+
+        // The key is truncated.
+        assert!(!rss.set_hash_config(&data[..20]));
+    }
+}
@@ -0,0 +1,509 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Receive filter of a virtio-net device, as programmed by the guest through
+//! the control queue commands of the VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_MAC
+//! and VIRTIO_NET_CTRL_VLAN classes.
+
+use super::{vnet_hdr_len, MacAddr, MAC_ADDR_LEN};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+/// Number of addresses each MAC table can hold. Beyond that, the table is
+/// considered as overflowing and all the frames of its kind are accepted.
+pub const MAC_TABLE_ENTRIES: usize = 64;
+/// Number of VLAN identifiers.
+pub const MAX_VLAN: u16 = 4096;
+
+const ETH_HEADER_LEN: usize = 14;
+const ETH_VLAN_HEADER_LEN: usize = ETH_HEADER_LEN + 4;
+const ETH_P_8021Q: u16 = 0x8100;
+const BROADCAST_MAC: [u8; MAC_ADDR_LEN] = [0xff; MAC_ADDR_LEN];
+
+/// Reception modes from the VIRTIO_NET_CTRL_RX class.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RxMode {
+    Promisc,
+    AllMulti,
+    AllUni,
+    NoMulti,
+    NoUni,
+    NoBcast,
+}
+
+/// Filter as programmed by the guest, saved along with the device so that it
+/// still applies once the device is restored.
+#[derive(Clone, Debug, Default, PartialEq, Versionize)]
+pub struct RxFilterSnapshot {
+    pub mac: Option<[u8; MAC_ADDR_LEN]>,
+    pub promisc: bool,
+    pub all_multi: bool,
+    pub all_uni: bool,
+    pub no_multi: bool,
+    pub no_uni: bool,
+    pub no_bcast: bool,
+    pub uni_macs: Vec<[u8; MAC_ADDR_LEN]>,
+    pub uni_overflow: bool,
+    pub multi_macs: Vec<[u8; MAC_ADDR_LEN]>,
+    pub multi_overflow: bool,
+    pub vlan_filtering: bool,
+    pub vlans: Vec<u64>,
+}
+
+fn mac_bytes(mac: &MacAddr) -> [u8; MAC_ADDR_LEN] {
+    let mut bytes = [0u8; MAC_ADDR_LEN];
+    bytes.copy_from_slice(mac.get_bytes());
+    bytes
+}
+
+struct RxFilterState {
+    default_mac: Option<MacAddr>,
+    mac: Option<MacAddr>,
+    promisc: bool,
+    all_multi: bool,
+    all_uni: bool,
+    no_multi: bool,
+    no_uni: bool,
+    no_bcast: bool,
+    uni_macs: Vec<MacAddr>,
+    uni_overflow: bool,
+    multi_macs: Vec<MacAddr>,
+    multi_overflow: bool,
+    vlan_filtering: bool,
+    vlans: [u64; (MAX_VLAN / 64) as usize],
+}
+
+impl RxFilterState {
+    fn new(mac: Option<MacAddr>) -> Self {
+        RxFilterState {
+            default_mac: mac,
+            mac,
+            // Until the guest says otherwise, everything gets through, as
+            // for a device not offering VIRTIO_NET_F_CTRL_RX.
+            promisc: true,
+            all_multi: false,
+            all_uni: false,
+            no_multi: false,
+            no_uni: false,
+            no_bcast: false,
+            uni_macs: Vec::new(),
+            uni_overflow: false,
+            multi_macs: Vec::new(),
+            multi_overflow: false,
+            vlan_filtering: false,
+            vlans: [0; (MAX_VLAN / 64) as usize],
+        }
+    }
+
+    fn vlan_allowed(&self, vid: u16) -> bool {
+        self.vlans[(vid / 64) as usize] & (1 << (vid % 64)) != 0
+    }
+
+    fn accept(&self, frame: &[u8]) -> bool {
+        if self.promisc {
+            return true;
+        }
+
+        // Let the guest deal with runt frames.
+        if frame.len() < ETH_HEADER_LEN {
+            return true;
+        }
+
+        if self.vlan_filtering
+            && frame.len() >= ETH_VLAN_HEADER_LEN
+            && u16::from_be_bytes([frame[12], frame[13]]) == ETH_P_8021Q
+        {
+            let vid = u16::from_be_bytes([frame[14], frame[15]]) & (MAX_VLAN - 1);
+            if !self.vlan_allowed(vid) {
+                return false;
+            }
+        }
+
+        let dst = &frame[0..MAC_ADDR_LEN];
+        if dst[0] & 0x1 != 0 {
+            if dst == BROADCAST_MAC {
+                !self.no_bcast
+            } else if self.no_multi {
+                false
+            } else if self.all_multi || self.multi_overflow {
+                true
+            } else {
+                self.multi_macs.iter().any(|mac| mac.get_bytes() == dst)
+            }
+        } else if self.no_uni {
+            false
+        } else if self.all_uni || self.uni_overflow {
+            true
+        } else {
+            match self.mac {
+                // Without knowing its own address, the device can't tell
+                // which unicast frames are meant for the guest.
+                None => true,
+                Some(mac) if mac.get_bytes() == dst => true,
+                Some(_) => self.uni_macs.iter().any(|mac| mac.get_bytes() == dst),
+            }
+        }
+    }
+}
+
+/// Receive filter shared by the control queue and all the queue pairs of a
+/// network device. It lets everything through until the guest turns the
+/// promiscuous mode off, in which case the frames are checked against the
+/// MAC tables and the VLAN filter.
+#[derive(Clone)]
+pub struct RxFilter {
+    enabled: Arc<AtomicBool>,
+    state: Arc<RwLock<RxFilterState>>,
+}
+
+impl Default for RxFilter {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl RxFilter {
+    /// Create a filter for a device whose primary address is `mac`, when
+    /// known.
+    pub fn new(mac: Option<MacAddr>) -> Self {
+        RxFilter {
+            enabled: Arc::new(AtomicBool::new(false)),
+            state: Arc::new(RwLock::new(RxFilterState::new(mac))),
+        }
+    }
+
+    fn update<F: FnOnce(&mut RxFilterState)>(&self, f: F) {
+        let mut state = self.state.write().unwrap();
+        f(&mut state);
+        self.enabled.store(!state.promisc, Ordering::Release);
+    }
+
+    /// Restore the state the filter had when the device was created. When
+    /// `vlan_filtering` is set, no VLAN is allowed until added through
+    /// `add_vlan()`, otherwise tagged frames aren't filtered.
+    pub fn reset(&self, vlan_filtering: bool) {
+        self.update(|state| {
+            *state = RxFilterState::new(state.default_mac);
+            state.vlan_filtering = vlan_filtering;
+        });
+    }
+
+    pub fn set_rx_mode(&self, mode: RxMode, on: bool) {
+        self.update(|state| match mode {
+            RxMode::Promisc => state.promisc = on,
+            RxMode::AllMulti => state.all_multi = on,
+            RxMode::AllUni => state.all_uni = on,
+            RxMode::NoMulti => state.no_multi = on,
+            RxMode::NoUni => state.no_uni = on,
+            RxMode::NoBcast => state.no_bcast = on,
+        });
+    }
+
+    /// Replace the unicast and multicast address tables.
+    pub fn set_mac_tables(&self, uni_macs: Vec<MacAddr>, multi_macs: Vec<MacAddr>) {
+        self.update(|state| {
+            state.uni_overflow = uni_macs.len() > MAC_TABLE_ENTRIES;
+            state.uni_macs = if state.uni_overflow {
+                Vec::new()
+            } else {
+                uni_macs
+            };
+            state.multi_overflow = multi_macs.len() > MAC_TABLE_ENTRIES;
+            state.multi_macs = if state.multi_overflow {
+                Vec::new()
+            } else {
+                multi_macs
+            };
+        });
+    }
+
+    /// Change the primary address of the device.
+    pub fn set_mac(&self, mac: MacAddr) {
+        self.update(|state| state.mac = Some(mac));
+    }
+
+    pub fn mac(&self) -> Option<MacAddr> {
+        self.state.read().unwrap().mac
+    }
+
+    pub fn snapshot(&self) -> RxFilterSnapshot {
+        let state = self.state.read().unwrap();
+
+        RxFilterSnapshot {
+            mac: state.mac.as_ref().map(mac_bytes),
+            promisc: state.promisc,
+            all_multi: state.all_multi,
+            all_uni: state.all_uni,
+            no_multi: state.no_multi,
+            no_uni: state.no_uni,
+            no_bcast: state.no_bcast,
+            uni_macs: state.uni_macs.iter().map(mac_bytes).collect(),
+            uni_overflow: state.uni_overflow,
+            multi_macs: state.multi_macs.iter().map(mac_bytes).collect(),
+            multi_overflow: state.multi_overflow,
+            vlan_filtering: state.vlan_filtering,
+            vlans: state.vlans.to_vec(),
+        }
+    }
+
+    /// Program the filter as it was when `snapshot` was taken.
+    pub fn restore(&self, snapshot: &RxFilterSnapshot) {
+        let from_bytes = |macs: &[[u8; MAC_ADDR_LEN]]| -> Vec<MacAddr> {
+            macs.iter()
+                .map(|mac| MacAddr::from_bytes_unchecked(mac))
+                .collect()
+        };
+
+        self.update(|state| {
+            state.mac = snapshot.mac.map(|mac| MacAddr::from_bytes_unchecked(&mac));
+            state.promisc = snapshot.promisc;
+            state.all_multi = snapshot.all_multi;
+            state.all_uni = snapshot.all_uni;
+            state.no_multi = snapshot.no_multi;
+            state.no_uni = snapshot.no_uni;
+            state.no_bcast = snapshot.no_bcast;
+            state.uni_macs = from_bytes(&snapshot.uni_macs);
+            state.uni_overflow = snapshot.uni_overflow;
+            state.multi_macs = from_bytes(&snapshot.multi_macs);
+            state.multi_overflow = snapshot.multi_overflow;
+            state.vlan_filtering = snapshot.vlan_filtering;
+            state.vlans = [0; (MAX_VLAN / 64) as usize];
+            for (dst, src) in state.vlans.iter_mut().zip(snapshot.vlans.iter()) {
+                *dst = *src;
+            }
+        });
+    }
+
+    /// Allow the frames tagged with VLAN `vid`. Returns false if `vid` is
+    /// out of range.
+    pub fn add_vlan(&self, vid: u16) -> bool {
+        if vid >= MAX_VLAN {
+            return false;
+        }
+        self.update(|state| state.vlans[(vid / 64) as usize] |= 1 << (vid % 64));
+        true
+    }
+
+    /// Stop allowing the frames tagged with VLAN `vid`. Returns false if
+    /// `vid` is out of range.
+    pub fn del_vlan(&self, vid: u16) -> bool {
+        if vid >= MAX_VLAN {
+            return false;
+        }
+        self.update(|state| state.vlans[(vid / 64) as usize] &= !(1 << (vid % 64)));
+        true
+    }
+
+    /// Addresses the TAP interface should restrict the frames it hands over
+    /// to, along with whether all the multicast frames should get through.
+    /// An empty list means no restriction at all.
+    pub fn tap_filter(&self) -> (Vec<MacAddr>, bool) {
+        let state = self.state.read().unwrap();
+        let mac = match state.mac {
+            Some(mac) if !state.promisc && !state.all_uni && !state.uni_overflow => mac,
+            _ => return (Vec::new(), false),
+        };
+
+        let mut macs = vec![mac];
+        macs.extend_from_slice(&state.uni_macs);
+        if !state.no_bcast {
+            macs.push(MacAddr::from_bytes_unchecked(&BROADCAST_MAC));
+        }
+        let all_multi = !state.no_multi && (state.all_multi || state.multi_overflow);
+        if !state.no_multi && !all_multi {
+            macs.extend_from_slice(&state.multi_macs);
+        }
+
+        (macs, all_multi)
+    }
+
+    /// Whether the Ethernet frame `frame` should be handed over to the guest.
+    pub fn accept_frame(&self, frame: &[u8]) -> bool {
+        if !self.enabled.load(Ordering::Acquire) {
+            return true;
+        }
+
+        self.state.read().unwrap().accept(frame)
+    }
+
+    /// Whether the frame made of the first `len` bytes of the buffers
+    /// described by `iovecs`, the virtio-net header included, should be
+    /// handed over to the guest.
+    pub(crate) fn accept_iovecs(&self, iovecs: &[libc::iovec], len: usize) -> bool {
+        if !self.enabled.load(Ordering::Acquire) {
+            return true;
+        }
+
+        // Only the Ethernet header, VLAN tag included, is needed.
+        let mut header = [0u8; ETH_VLAN_HEADER_LEN];
+        let mut header_len = 0;
+        let mut skip = vnet_hdr_len();
+        let mut remaining = len.saturating_sub(skip);
+        for iovec in iovecs {
+            if remaining == 0 || header_len == header.len() {
+                break;
+            }
+            if iovec.iov_len <= skip {
+                skip -= iovec.iov_len;
+                continue;
+            }
+            let count = std::cmp::min(
+                std::cmp::min(iovec.iov_len - skip, remaining),
+                header.len() - header_len,
+            );
+            // This is safe since the iovecs describe valid guest memory, which
+            // has just been used for the same transfer.
+            let buf = unsafe {
+                std::slice::from_raw_parts((iovec.iov_base as *const u8).add(skip), count)
+            };
+            header[header_len..header_len + count].copy_from_slice(buf);
+            header_len += count;
+            remaining -= count;
+            skip = 0;
+        }
+
+        self.state.read().unwrap().accept(&header[..header_len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(dst: &str, vlan: Option<u16>) -> Vec<u8> {
+        let mut frame = MacAddr::parse_str(dst).unwrap().get_bytes().to_vec();
+        frame.extend_from_slice(&[0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        if let Some(vid) = vlan {
+            frame.extend_from_slice(&ETH_P_8021Q.to_be_bytes());
+            frame.extend_from_slice(&vid.to_be_bytes());
+        }
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.resize(60, 0);
+        frame
+    }
+
+    #[test]
+    fn test_rx_filter_mac() {
+        let mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let filter = RxFilter::new(Some(mac));
+        let own = frame("12:34:56:78:9a:bc", None);
+        let other = frame("12:34:56:78:9a:bd", None);
+        let bcast = frame("ff:ff:ff:ff:ff:ff", None);
+        let multi = frame("01:00:5e:00:00:01", None);
+
+        // Promiscuous by default.
+        assert!(filter.accept_frame(&other));
+        assert_eq!(filter.tap_filter(), (Vec::new(), false));
+
+        filter.set_rx_mode(RxMode::Promisc, false);
+        assert!(filter.accept_frame(&own));
+        assert!(!filter.accept_frame(&other));
+        assert!(filter.accept_frame(&bcast));
+        assert!(!filter.accept_frame(&multi));
+
+        filter.set_mac_tables(
+            vec![MacAddr::parse_str("12:34:56:78:9a:bd").unwrap()],
+            vec![MacAddr::parse_str("01:00:5e:00:00:01").unwrap()],
+        );
+        assert!(filter.accept_frame(&other));
+        assert!(filter.accept_frame(&multi));
+        let (macs, all_multi) = filter.tap_filter();
+        assert_eq!(macs.len(), 4);
+        assert!(!all_multi);
+
+        filter.set_rx_mode(RxMode::NoBcast, true);
+        filter.set_rx_mode(RxMode::NoMulti, true);
+        assert!(!filter.accept_frame(&bcast));
+        assert!(!filter.accept_frame(&multi));
+
+        filter.set_rx_mode(RxMode::NoMulti, false);
+        filter.set_mac_tables(Vec::new(), Vec::new());
+        filter.set_rx_mode(RxMode::AllMulti, true);
+        assert!(filter.accept_frame(&multi));
+        assert!(!filter.accept_frame(&other));
+        assert_eq!(filter.tap_filter(), (vec![mac], true));
+
+        let new_mac = MacAddr::parse_str("12:34:56:78:9a:bd").unwrap();
+        filter.set_mac(new_mac);
+        assert!(!filter.accept_frame(&own));
+        assert!(filter.accept_frame(&other));
+
+        filter.reset(false);
+        assert_eq!(filter.mac(), Some(mac));
+        assert!(filter.accept_frame(&other));
+    }
+
+    #[test]
+    fn test_rx_filter_vlan() {
+        let filter = RxFilter::new(None);
+        filter.reset(true);
+        filter.set_rx_mode(RxMode::Promisc, false);
+
+        let untagged = frame("12:34:56:78:9a:bc", None);
+        let tagged = frame("12:34:56:78:9a:bc", Some(100));
+        assert!(filter.accept_frame(&untagged));
+        assert!(!filter.accept_frame(&tagged));
+
+        assert!(filter.add_vlan(100));
+        assert!(filter.accept_frame(&tagged));
+        assert!(filter.del_vlan(100));
+        assert!(!filter.accept_frame(&tagged));
+        assert!(!filter.add_vlan(MAX_VLAN));
+    }
+
+    #[test]
+    fn test_rx_filter_snapshot() {
+        let mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let filter = RxFilter::new(Some(mac));
+        filter.reset(true);
+        filter.set_rx_mode(RxMode::Promisc, false);
+        filter.set_rx_mode(RxMode::NoBcast, true);
+        filter.set_mac_tables(
+            vec![MacAddr::parse_str("12:34:56:78:9a:bd").unwrap()],
+            vec![MacAddr::parse_str("01:00:5e:00:00:01").unwrap()],
+        );
+        assert!(filter.add_vlan(100));
+        let snapshot = filter.snapshot();
+
+        let restored = RxFilter::new(Some(mac));
+        assert!(restored.accept_frame(&frame("ff:ff:ff:ff:ff:ff", None)));
+        restored.restore(&snapshot);
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.tap_filter(), filter.tap_filter());
+        assert!(!restored.accept_frame(&frame("ff:ff:ff:ff:ff:ff", None)));
+        assert!(restored.accept_frame(&frame("12:34:56:78:9a:bd", Some(100))));
+        assert!(!restored.accept_frame(&frame("12:34:56:78:9a:bd", Some(101))));
+        assert!(restored.accept_frame(&frame("01:00:5e:00:00:01", None)));
+    }
+
+    #[test]
+    fn test_rx_filter_iovecs() {
+        let filter = RxFilter::new(Some(MacAddr::parse_str("12:34:56:78:9a:bc").unwrap()));
+        filter.set_rx_mode(RxMode::Promisc, false);
+
+        // Split the buffer in the middle of the destination address.
+        let accept = |dst: &str| {
+            let mut buf = vec![0u8; vnet_hdr_len()];
+            buf.extend_from_slice(&frame(dst, None));
+            let len = buf.len();
+            let (head, tail) = buf.split_at_mut(vnet_hdr_len() + 3);
+            let iovecs = [
+                libc::iovec {
+                    iov_base: head.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: head.len(),
+                },
+                libc::iovec {
+                    iov_base: tail.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: tail.len(),
+                },
+            ];
+            filter.accept_iovecs(&iovecs, len)
+        };
+
+        assert!(accept("12:34:56:78:9a:bc"));
+        assert!(!accept("12:34:56:78:9a:bd"));
+    }
+}
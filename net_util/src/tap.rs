@@ -5,14 +5,16 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
-use super::{create_sockaddr, create_socket, vnet_hdr_len, Error as NetUtilError, MacAddr};
+use super::{
+    create_sockaddr, create_socket, netlink, vnet_hdr_len, Error as NetUtilError, MacAddr,
+};
 use crate::mac::MAC_ADDR_LEN;
 use std::fs::File;
 use std::io::{Error as IoError, Read, Result as IoResult, Write};
 use std::net;
 use std::os::raw::*;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use vmm_sys_util::ioctl::{ioctl_with_mut_ref, ioctl_with_ref, ioctl_with_val};
+use vmm_sys_util::ioctl::{ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref, ioctl_with_val};
 
 #[derive(Debug)]
 pub enum Error {
@@ -31,6 +33,8 @@ pub enum Error {
     InvalidIfname,
     /// Error parsing MAC data
     MacParsing(IoError),
+    /// Failed to configure the interface through netlink.
+    Netlink(IoError),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -194,6 +198,15 @@ impl Tap {
         Ok(tap)
     }
 
+    /// Wrap the device end of the userspace network backend, which exchanges
+    /// the same frames as a TAP device, but has no interface name.
+    pub(crate) fn from_user_net(file: File) -> Tap {
+        Tap {
+            tap_file: file,
+            if_name: Vec::new(),
+        }
+    }
+
     /// Set the host-side IP address for the tap interface.
     pub fn set_ip_addr(&self, ip_addr: net::Ipv4Addr) -> Result<()> {
         let sock = create_socket().map_err(Error::NetUtil)?;
@@ -297,6 +310,47 @@ impl Tap {
         Ok(())
     }
 
+    /// Add an IPv4 or IPv6 address to the tap interface, on top of the one
+    /// set with `set_ip_addr()`.
+    pub fn add_ip_addr(&self, ip_addr: net::IpAddr, prefix_len: u8) -> Result<()> {
+        netlink::add_address(self.get_if_index()?, ip_addr, prefix_len).map_err(Error::Netlink)
+    }
+
+    /// Set the MTU of the tap interface.
+    pub fn set_mtu(&self, mtu: u16) -> Result<()> {
+        let sock = create_socket().map_err(Error::NetUtil)?;
+
+        let mut ifreq = self.get_ifreq();
+
+        ifreq.ifr_ifru.ifru_mtu = c_int::from(mtu);
+
+        // ioctl is safe. Called with a valid sock fd, and we check the return.
+        #[allow(clippy::cast_lossless)]
+        let ret = unsafe { ioctl_with_ref(&sock, net_gen::sockios::SIOCSIFMTU as c_ulong, &ifreq) };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        Ok(())
+    }
+
+    fn get_if_index(&self) -> Result<u32> {
+        let sock = create_socket().map_err(Error::NetUtil)?;
+
+        let ifreq = self.get_ifreq();
+
+        // ioctl is safe. Called with a valid sock fd, and we check the return.
+        #[allow(clippy::cast_lossless)]
+        let ret =
+            unsafe { ioctl_with_ref(&sock, net_gen::sockios::SIOCGIFINDEX as c_ulong, &ifreq) };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        // We only access one field of the ifru union, hence this is safe.
+        Ok(unsafe { ifreq.ifr_ifru.ifru_ivalue } as u32)
+    }
+
     /// Set the offload flags for the tap interface.
     pub fn set_offload(&self, flags: c_uint) -> Result<()> {
         // ioctl is safe. Called with a valid tap fd, and we check the return.
@@ -310,6 +364,40 @@ impl Tap {
         Ok(())
     }
 
+    /// Restrict the frames read from the tap interface to the ones sent to
+    /// `macs`, and to any multicast address if `all_multi` is set. An empty
+    /// `macs` removes the restriction.
+    pub fn set_tx_filter(&self, macs: &[MacAddr], all_multi: bool) -> Result<()> {
+        let flags = if all_multi && !macs.is_empty() {
+            net_gen::TUN_FLT_ALLMULTI as u16
+        } else {
+            0
+        };
+
+        // Build a struct tun_filter followed by the list of addresses.
+        let mut filter = Vec::with_capacity(4 + macs.len() * MAC_ADDR_LEN);
+        filter.extend_from_slice(&flags.to_ne_bytes());
+        filter.extend_from_slice(&(macs.len() as u16).to_ne_bytes());
+        for mac in macs {
+            filter.extend_from_slice(mac.get_bytes());
+        }
+
+        // ioctl is safe. Called with a valid tap fd and a buffer holding as
+        // many addresses as announced, and we check the return.
+        let ret = unsafe {
+            ioctl_with_ptr(
+                &self.tap_file,
+                net_gen::TUNSETTXFILTER(),
+                filter.as_ptr() as *const net_gen::tun_filter,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::IoctlError(IoError::last_os_error()));
+        }
+
+        Ok(())
+    }
+
     /// Enable the tap interface.
     pub fn enable(&self) -> Result<()> {
         let sock = create_socket().map_err(Error::NetUtil)?;
@@ -593,6 +681,19 @@ mod tests {
         assert!(ret.is_ok());
     }
 
+    #[test]
+    fn test_tap_add_ip_addr_and_mtu() {
+        let _tap_ip_guard = TAP_IP_LOCK.lock().unwrap();
+
+        let tap = Tap::new(1).unwrap();
+        tap.add_ip_addr("192.168.243.1".parse().unwrap(), 24)
+            .unwrap();
+        tap.add_ip_addr("fd00:243::1".parse().unwrap(), 64).unwrap();
+        // Adding the same address again is not an error.
+        tap.add_ip_addr("fd00:243::1".parse().unwrap(), 64).unwrap();
+        tap.set_mtu(1400).unwrap();
+    }
+
     #[test]
     fn test_set_options() {
         let _tap_ip_guard = TAP_IP_LOCK.lock().unwrap();
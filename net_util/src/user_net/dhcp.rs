@@ -0,0 +1,202 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tiny DHCP server handing out the single address reserved for the guest.
+
+use std::net::Ipv4Addr;
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+const BOOTP_REQUEST: u8 = 1;
+const BOOTP_REPLY: u8 = 2;
+const BOOTP_LEN: usize = 236;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+
+const DHCP_OPT_PAD: u8 = 0;
+const DHCP_OPT_SUBNET_MASK: u8 = 1;
+const DHCP_OPT_ROUTER: u8 = 3;
+const DHCP_OPT_DNS: u8 = 6;
+const DHCP_OPT_REQUESTED_IP: u8 = 50;
+const DHCP_OPT_LEASE_TIME: u8 = 51;
+const DHCP_OPT_MSG_TYPE: u8 = 53;
+const DHCP_OPT_SERVER_ID: u8 = 54;
+const DHCP_OPT_END: u8 = 255;
+
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+
+const DHCP_LEASE_TIME: u32 = 24 * 60 * 60;
+
+pub struct DhcpServer {
+    server_ip: Ipv4Addr,
+    netmask: Ipv4Addr,
+    lease_ip: Ipv4Addr,
+}
+
+impl DhcpServer {
+    pub fn new(server_ip: Ipv4Addr, netmask: Ipv4Addr, lease_ip: Ipv4Addr) -> Self {
+        DhcpServer {
+            server_ip,
+            netmask,
+            lease_ip,
+        }
+    }
+
+    /// Process a DHCP message from the guest and return the reply to send
+    /// back, if any.
+    pub fn handle_message(&self, msg: &[u8]) -> Option<Vec<u8>> {
+        if msg.len() < BOOTP_LEN + DHCP_MAGIC_COOKIE.len()
+            || msg[0] != BOOTP_REQUEST
+            || msg[BOOTP_LEN..BOOTP_LEN + 4] != DHCP_MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut msg_type = None;
+        let mut requested_ip = None;
+        let mut options = &msg[BOOTP_LEN + 4..];
+        while let Some(&code) = options.first() {
+            match code {
+                DHCP_OPT_END => break,
+                DHCP_OPT_PAD => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    let value = options.get(2..2 + len)?;
+                    match code {
+                        DHCP_OPT_MSG_TYPE if len == 1 => msg_type = Some(value[0]),
+                        DHCP_OPT_REQUESTED_IP if len == 4 => {
+                            requested_ip =
+                                Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+                        }
+                        _ => {}
+                    }
+                    options = &options[2 + len..];
+                }
+            }
+        }
+
+        let reply_type = match msg_type? {
+            DHCP_DISCOVER => DHCP_OFFER,
+            DHCP_REQUEST => {
+                // The address can either be requested through the option,
+                // or through the client address when renewing the lease.
+                let ciaddr = Ipv4Addr::new(msg[12], msg[13], msg[14], msg[15]);
+                match requested_ip {
+                    Some(ip) if ip != self.lease_ip => DHCP_NAK,
+                    None if ciaddr != self.lease_ip => DHCP_NAK,
+                    _ => DHCP_ACK,
+                }
+            }
+            _ => return None,
+        };
+
+        Some(self.build_reply(msg, reply_type))
+    }
+
+    fn build_reply(&self, request: &[u8], reply_type: u8) -> Vec<u8> {
+        let mut reply = vec![0u8; BOOTP_LEN];
+        reply[0] = BOOTP_REPLY;
+        // Hardware type, hardware address length, transaction ID, flags and
+        // client hardware address are copied from the request.
+        reply[1..8].copy_from_slice(&request[1..8]);
+        reply[10..12].copy_from_slice(&request[10..12]);
+        reply[28..44].copy_from_slice(&request[28..44]);
+        if reply_type != DHCP_NAK {
+            reply[16..20].copy_from_slice(&self.lease_ip.octets());
+            reply[20..24].copy_from_slice(&self.server_ip.octets());
+        }
+
+        reply.extend_from_slice(&DHCP_MAGIC_COOKIE);
+        reply.extend_from_slice(&[DHCP_OPT_MSG_TYPE, 1, reply_type]);
+        reply.extend_from_slice(&[DHCP_OPT_SERVER_ID, 4]);
+        reply.extend_from_slice(&self.server_ip.octets());
+        if reply_type != DHCP_NAK {
+            reply.extend_from_slice(&[DHCP_OPT_LEASE_TIME, 4]);
+            reply.extend_from_slice(&DHCP_LEASE_TIME.to_be_bytes());
+            reply.extend_from_slice(&[DHCP_OPT_SUBNET_MASK, 4]);
+            reply.extend_from_slice(&self.netmask.octets());
+            reply.extend_from_slice(&[DHCP_OPT_ROUTER, 4]);
+            reply.extend_from_slice(&self.server_ip.octets());
+            // The gateway also forwards the DNS queries to the host.
+            reply.extend_from_slice(&[DHCP_OPT_DNS, 4]);
+            reply.extend_from_slice(&self.server_ip.octets());
+        }
+        reply.push(DHCP_OPT_END);
+
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_request(msg_type: u8, requested_ip: Option<Ipv4Addr>) -> Vec<u8> {
+        let mut msg = vec![0u8; BOOTP_LEN];
+        msg[0] = BOOTP_REQUEST;
+        msg[1] = 1;
+        msg[2] = 6;
+        msg[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        msg[28..34].copy_from_slice(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+        msg.extend_from_slice(&DHCP_MAGIC_COOKIE);
+        msg.extend_from_slice(&[DHCP_OPT_MSG_TYPE, 1, msg_type]);
+        if let Some(ip) = requested_ip {
+            msg.extend_from_slice(&[DHCP_OPT_REQUESTED_IP, 4]);
+            msg.extend_from_slice(&ip.octets());
+        }
+        msg.push(DHCP_OPT_END);
+        msg
+    }
+
+    fn reply_type(reply: &[u8]) -> u8 {
+        assert_eq!(reply[0], BOOTP_REPLY);
+        assert_eq!(reply[BOOTP_LEN..BOOTP_LEN + 4], DHCP_MAGIC_COOKIE);
+        assert_eq!(reply[BOOTP_LEN + 4], DHCP_OPT_MSG_TYPE);
+        reply[BOOTP_LEN + 6]
+    }
+
+    #[test]
+    fn test_dhcp_server() {
+        let server = DhcpServer::new(
+            Ipv4Addr::new(10, 0, 2, 2),
+            Ipv4Addr::new(255, 255, 255, 0),
+            Ipv4Addr::new(10, 0, 2, 15),
+        );
+
+        let offer = server
+            .handle_message(&build_request(DHCP_DISCOVER, None))
+            .unwrap();
+        assert_eq!(reply_type(&offer), DHCP_OFFER);
+        // Same transaction and client hardware address.
+        assert_eq!(offer[4..8], [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(offer[28..34], [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+        assert_eq!(offer[16..20], [10, 0, 2, 15]);
+
+        let ack = server
+            .handle_message(&build_request(
+                DHCP_REQUEST,
+                Some(Ipv4Addr::new(10, 0, 2, 15)),
+            ))
+            .unwrap();
+        assert_eq!(reply_type(&ack), DHCP_ACK);
+
+        let nak = server
+            .handle_message(&build_request(
+                DHCP_REQUEST,
+                Some(Ipv4Addr::new(10, 0, 2, 16)),
+            ))
+            .unwrap();
+        assert_eq!(reply_type(&nak), DHCP_NAK);
+        assert_eq!(nak[16..20], [0, 0, 0, 0]);
+
+        // Truncated messages are ignored.
+        assert!(server
+            .handle_message(&build_request(DHCP_DISCOVER, None)[..BOOTP_LEN])
+            .is_none());
+    }
+}
@@ -0,0 +1,1051 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Userspace network backend.
+//!
+//! The guest is connected to a virtual network made of a single gateway,
+//! emulated by this module. The gateway answers ARP and DHCP requests,
+//! forwards DNS queries to the host resolver, and translates the guest TCP
+//! and UDP traffic into host sockets. Traffic sent to the gateway address
+//! reaches the host loopback interface.
+//!
+//! The frames are exchanged with the virtio-net device through a socket
+//! pair, which behaves as a TAP device: each packet is an Ethernet frame
+//! prefixed with a virtio-net header. No privilege is required.
+
+mod dhcp;
+mod packet;
+mod tcp;
+
+use self::dhcp::{DhcpServer, DHCP_CLIENT_PORT, DHCP_SERVER_PORT};
+use self::packet::*;
+use self::tcp::{TcpConnection, TcpConnectionKey};
+use crate::{vnet_hdr_len, MacAddr, Tap};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use vmm_sys_util::eventfd::EventFd;
+
+// Locally administered address used by the gateway.
+const GATEWAY_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x35, 0x02];
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+const DNS_PORT: u16 = 53;
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+// Ports used by the gateway for the connections coming from port forwards.
+const FIRST_FORWARD_PORT: u16 = 49152;
+
+const KILL_EVENT: u64 = 0;
+const GUEST_EVENT: u64 = 1;
+const FIRST_SOCKET_EVENT: u64 = 2;
+
+const EPOLL_EVENTS_LEN: usize = 64;
+const EPOLL_TIMEOUT_MS: i32 = 1000;
+
+const UDP_TIMEOUT: Duration = Duration::from_secs(60);
+const DNS_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Frames queued for the guest beyond this limit are dropped, unless they
+// belong to a TCP connection, which is limited by its window.
+const MAX_GUEST_BACKLOG: usize = 1024;
+const MAX_FRAME_SIZE: usize = 65562;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid network configuration.
+    InvalidNetwork(Ipv4Addr, Ipv4Addr),
+    /// Failed to create the socket pair.
+    CreateSocketPair(io::Error),
+    /// Failed to create the epoll file descriptor.
+    CreateEpoll(io::Error),
+    /// Failed to create the kill eventfd.
+    CreateKillEventFd(io::Error),
+    /// Failed to bind the host port of a port forward.
+    BindPortForward(u16, io::Error),
+    /// Failed to register a file descriptor with epoll.
+    RegisterListener(io::Error),
+    /// Failed to wait for events.
+    EpollWait(io::Error),
+    /// Failed to read a frame from the guest.
+    ReadGuest(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Forward of a host port to a guest port.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PortForward {
+    pub host_addr: Ipv4Addr,
+    pub host_port: u16,
+    pub guest_port: u16,
+}
+
+pub struct UserNetConfig {
+    /// Address of the gateway, the guest being given the next one.
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub tcp_forwards: Vec<PortForward>,
+    pub udp_forwards: Vec<PortForward>,
+}
+
+/// UDP "connection" from the guest, translated into a host socket.
+struct UdpNat {
+    socket: UdpSocket,
+    key: UdpNatKey,
+    timeout: Duration,
+    last_activity: Instant,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct UdpNatKey {
+    guest_ip: Ipv4Addr,
+    guest_port: u16,
+    remote_ip: Ipv4Addr,
+    remote_port: u16,
+}
+
+/// Host peer sending datagrams through a UDP port forward. It is seen by
+/// the guest as a port of the gateway.
+struct UdpForwardSession {
+    token: u64,
+    peer: SocketAddr,
+    last_activity: Instant,
+}
+
+enum Socket {
+    TcpForward {
+        listener: TcpListener,
+        guest_port: u16,
+    },
+    UdpForward {
+        socket: UdpSocket,
+        guest_port: u16,
+    },
+    Tcp(TcpConnection),
+    Udp(UdpNat),
+}
+
+pub struct UserNet {
+    epoll_fd: RawFd,
+    guest: File,
+    kill_evt: EventFd,
+    gateway_ip: Ipv4Addr,
+    gateway_mac: MacAddr,
+    netmask: Ipv4Addr,
+    guest_ip: Ipv4Addr,
+    guest_mac: Option<MacAddr>,
+    dns_server: Ipv4Addr,
+    dhcp: DhcpServer,
+    to_guest: VecDeque<Vec<u8>>,
+    guest_epollout: bool,
+    sockets: HashMap<u64, Socket>,
+    next_token: u64,
+    tcp_connections: HashMap<TcpConnectionKey, u64>,
+    udp_nat: HashMap<UdpNatKey, u64>,
+    udp_forward_sessions: HashMap<(u16, u16), UdpForwardSession>,
+    next_forward_port: u16,
+    isn: u32,
+    last_expiry: Instant,
+}
+
+// Read the first IPv4 name server from the host configuration, falling back
+// onto a local resolver.
+fn host_dns_server() -> Ipv4Addr {
+    fs::read_to_string(RESOLV_CONF)
+        .ok()
+        .and_then(|conf| {
+            conf.lines().find_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next()) {
+                    (Some("nameserver"), Some(addr)) => addr.parse().ok(),
+                    _ => None,
+                }
+            })
+        })
+        .unwrap_or(Ipv4Addr::LOCALHOST)
+}
+
+fn tcp_connect_nonblocking(addr: SocketAddrV4) -> io::Result<TcpStream> {
+    // This is safe since we check the return value.
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // This is safe; nothing else will use or hold onto the raw fd.
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    let sockaddr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*addr.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    // This is safe since the address structure is valid for the duration of
+    // the call, and we check the return value.
+    let ret = unsafe {
+        libc::connect(
+            fd,
+            &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(e);
+        }
+    }
+
+    Ok(stream)
+}
+
+impl UserNet {
+    /// Create the userspace network stack, along with the TAP-like device
+    /// to be used by the virtio-net device to exchange frames with it.
+    pub fn new(config: &UserNetConfig) -> Result<(Self, Tap)> {
+        let network = u32::from(config.ip) & u32::from(config.netmask);
+        let broadcast = network | !u32::from(config.netmask);
+        let guest_ip = u32::from(config.ip).wrapping_add(1);
+        if guest_ip & u32::from(config.netmask) != network || guest_ip == broadcast {
+            return Err(Error::InvalidNetwork(config.ip, config.netmask));
+        }
+        let guest_ip = Ipv4Addr::from(guest_ip);
+
+        let mut fds = [0; 2];
+        // This is safe since we check the return value.
+        let ret = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::CreateSocketPair(io::Error::last_os_error()));
+        }
+        // This is safe; nothing else will use or hold onto the raw fds.
+        let (guest, device) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+        let epoll_fd = epoll::create(true).map_err(Error::CreateEpoll)?;
+        let kill_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::CreateKillEventFd)?;
+
+        let mut user_net = UserNet {
+            epoll_fd,
+            guest,
+            kill_evt,
+            gateway_ip: config.ip,
+            gateway_mac: MacAddr::from_bytes_unchecked(&GATEWAY_MAC),
+            netmask: config.netmask,
+            guest_ip,
+            guest_mac: None,
+            dns_server: host_dns_server(),
+            dhcp: DhcpServer::new(config.ip, config.netmask, guest_ip),
+            to_guest: VecDeque::new(),
+            guest_epollout: false,
+            sockets: HashMap::new(),
+            next_token: FIRST_SOCKET_EVENT,
+            tcp_connections: HashMap::new(),
+            udp_nat: HashMap::new(),
+            udp_forward_sessions: HashMap::new(),
+            next_forward_port: FIRST_FORWARD_PORT,
+            isn: 0,
+            last_expiry: Instant::now(),
+        };
+
+        user_net.register(
+            user_net.kill_evt.as_raw_fd(),
+            epoll::Events::EPOLLIN,
+            KILL_EVENT,
+        )?;
+        user_net.register(
+            user_net.guest.as_raw_fd(),
+            epoll::Events::EPOLLIN,
+            GUEST_EVENT,
+        )?;
+
+        // The host sockets of the port forwards are created right away so
+        // that any error is reported to the user.
+        for forward in config.tcp_forwards.iter() {
+            let listener = TcpListener::bind((forward.host_addr, forward.host_port))
+                .and_then(|l| l.set_nonblocking(true).map(|_| l))
+                .map_err(|e| Error::BindPortForward(forward.host_port, e))?;
+            let fd = listener.as_raw_fd();
+            let token = user_net.add_socket(Socket::TcpForward {
+                listener,
+                guest_port: forward.guest_port,
+            });
+            user_net.register(fd, epoll::Events::EPOLLIN, token)?;
+        }
+        for forward in config.udp_forwards.iter() {
+            let socket = UdpSocket::bind((forward.host_addr, forward.host_port))
+                .and_then(|s| s.set_nonblocking(true).map(|_| s))
+                .map_err(|e| Error::BindPortForward(forward.host_port, e))?;
+            let fd = socket.as_raw_fd();
+            let token = user_net.add_socket(Socket::UdpForward {
+                socket,
+                guest_port: forward.guest_port,
+            });
+            user_net.register(fd, epoll::Events::EPOLLIN, token)?;
+        }
+
+        Ok((user_net, Tap::from_user_net(device)))
+    }
+
+    /// Event used to stop the stack.
+    pub fn kill_evt(&self) -> &EventFd {
+        &self.kill_evt
+    }
+
+    fn register(&self, fd: RawFd, events: epoll::Events, token: u64) -> Result<()> {
+        epoll::ctl(
+            self.epoll_fd,
+            epoll::ControlOptions::EPOLL_CTL_ADD,
+            fd,
+            epoll::Event::new(events, token),
+        )
+        .map_err(Error::RegisterListener)
+    }
+
+    fn add_socket(&mut self, socket: Socket) -> u64 {
+        let token = self.next_token;
+        self.next_token += 1;
+        self.sockets.insert(token, socket);
+        token
+    }
+
+    fn allocate_forward_port(&mut self) -> u16 {
+        let port = self.next_forward_port;
+        self.next_forward_port = port.checked_add(1).unwrap_or(FIRST_FORWARD_PORT);
+        port
+    }
+
+    // Initial sequence number for a new TCP connection, which only needs to
+    // differ from the previous ones.
+    fn next_isn(&mut self) -> u32 {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        self.isn = self.isn.wrapping_add(nanos).wrapping_add(0x1_0000);
+        self.isn
+    }
+
+    /// Run the stack until the kill event is triggered, or the virtio-net
+    /// device is gone.
+    pub fn run(&mut self) -> Result<()> {
+        let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); EPOLL_EVENTS_LEN];
+
+        loop {
+            let num_events = match epoll::wait(self.epoll_fd, EPOLL_TIMEOUT_MS, &mut events[..]) {
+                Ok(num_events) => num_events,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::EpollWait(e)),
+            };
+
+            for event in events.iter().take(num_events) {
+                let evset = epoll::Events::from_bits_truncate(event.events);
+                let token = event.data;
+                match token {
+                    KILL_EVENT => {
+                        debug!("user-net: kill event received");
+                        return Ok(());
+                    }
+                    GUEST_EVENT => {
+                        if evset.contains(epoll::Events::EPOLLIN) && !self.process_guest()? {
+                            debug!("user-net: virtio-net device closed");
+                            return Ok(());
+                        }
+                    }
+                    token => self.process_socket(token, evset),
+                }
+            }
+
+            self.expire_udp();
+            self.flush_guest();
+        }
+    }
+
+    // Returns false if the virtio-net device side has been closed.
+    fn process_guest(&mut self) -> Result<bool> {
+        let mut buf = vec![0u8; MAX_FRAME_SIZE];
+        loop {
+            match self.guest.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(len) => {
+                    if len > vnet_hdr_len() {
+                        self.process_guest_frame(&buf[vnet_hdr_len()..len]);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::ReadGuest(e)),
+            }
+        }
+    }
+
+    fn process_guest_frame(&mut self, frame: &[u8]) {
+        let frame = match EthernetFrame::parse(frame) {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        // Unicast frames which are not addressed to the gateway are dropped.
+        if frame.dst != self.gateway_mac && frame.dst.get_bytes()[0] & 0x1 == 0 {
+            return;
+        }
+
+        // Frames are sent to the guest with the most recent address it used.
+        self.guest_mac = Some(frame.src);
+
+        match frame.ethertype {
+            ETH_P_ARP => self.process_arp(frame.payload),
+            ETH_P_IP => self.process_ipv4(frame.payload),
+            _ => {}
+        }
+    }
+
+    fn process_arp(&mut self, payload: &[u8]) {
+        if let Some(arp) = ArpPacket::parse(payload) {
+            if arp.is_request() && arp.target_ip == self.gateway_ip {
+                let frame = build_ethernet_frame(
+                    vnet_hdr_len(),
+                    arp.sender_mac,
+                    self.gateway_mac,
+                    ETH_P_ARP,
+                    &arp.build_reply(self.gateway_mac),
+                );
+                self.queue_frame(frame, false);
+            }
+        }
+    }
+
+    fn process_ipv4(&mut self, payload: &[u8]) {
+        let ip = match Ipv4Packet::parse(payload) {
+            Some(ip) => ip,
+            None => return,
+        };
+
+        match ip.protocol {
+            IPPROTO_ICMP if ip.dst == self.gateway_ip => {
+                if let Some(reply) = build_icmp_echo_reply(ip.payload) {
+                    let packet = build_ipv4_packet(ip.dst, ip.src, IPPROTO_ICMP, &reply);
+                    self.queue_packet(packet, false);
+                }
+            }
+            IPPROTO_UDP => {
+                if let Some(udp) = UdpDatagram::parse(ip.payload) {
+                    self.process_udp(&ip, &udp);
+                }
+            }
+            IPPROTO_TCP => {
+                if let Some(tcp) = TcpSegment::parse(ip.payload) {
+                    self.process_tcp(&ip, &tcp);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn is_broadcast_or_multicast(&self, addr: Ipv4Addr) -> bool {
+        let subnet_broadcast = u32::from(self.gateway_ip) | !u32::from(self.netmask);
+        addr.is_broadcast() || addr.is_multicast() || u32::from(addr) == subnet_broadcast
+    }
+
+    // Address of the host socket matching a destination from the guest.
+    fn host_destination(&self, dst: Ipv4Addr, dst_port: u16) -> SocketAddrV4 {
+        if dst == self.gateway_ip {
+            if dst_port == DNS_PORT {
+                SocketAddrV4::new(self.dns_server, DNS_PORT)
+            } else {
+                SocketAddrV4::new(Ipv4Addr::LOCALHOST, dst_port)
+            }
+        } else {
+            SocketAddrV4::new(dst, dst_port)
+        }
+    }
+
+    fn process_udp(&mut self, ip: &Ipv4Packet, udp: &UdpDatagram) {
+        if udp.dst_port == DHCP_SERVER_PORT {
+            if let Some(reply) = self.dhcp.handle_message(udp.payload) {
+                let packet = build_udp_datagram(
+                    self.gateway_ip,
+                    DHCP_SERVER_PORT,
+                    Ipv4Addr::BROADCAST,
+                    DHCP_CLIENT_PORT,
+                    &reply,
+                );
+                let frame = build_ethernet_frame(
+                    vnet_hdr_len(),
+                    MacAddr::from_bytes_unchecked(&BROADCAST_MAC),
+                    self.gateway_mac,
+                    ETH_P_IP,
+                    &packet,
+                );
+                self.queue_frame(frame, false);
+            }
+            return;
+        }
+
+        if self.is_broadcast_or_multicast(ip.dst) {
+            return;
+        }
+
+        // Replies to a host peer of a UDP port forward.
+        if ip.dst == self.gateway_ip {
+            if let Some(session) = self
+                .udp_forward_sessions
+                .get_mut(&(udp.src_port, udp.dst_port))
+            {
+                session.last_activity = Instant::now();
+                if let Some(Socket::UdpForward { socket, .. }) = self.sockets.get(&session.token) {
+                    if let Err(e) = socket.send_to(udp.payload, session.peer) {
+                        debug!("user-net: failed forwarding datagram: {}", e);
+                    }
+                }
+                return;
+            }
+        }
+
+        let key = UdpNatKey {
+            guest_ip: ip.src,
+            guest_port: udp.src_port,
+            remote_ip: ip.dst,
+            remote_port: udp.dst_port,
+        };
+        let token = match self.udp_nat.get(&key) {
+            Some(token) => *token,
+            None => match self.create_udp_nat(key) {
+                Ok(token) => token,
+                Err(e) => {
+                    debug!("user-net: failed creating UDP socket: {}", e);
+                    return;
+                }
+            },
+        };
+
+        if let Some(Socket::Udp(nat)) = self.sockets.get_mut(&token) {
+            nat.last_activity = Instant::now();
+            if let Err(e) = nat.socket.send(udp.payload) {
+                debug!("user-net: failed sending datagram: {}", e);
+            }
+        }
+    }
+
+    fn create_udp_nat(&mut self, key: UdpNatKey) -> io::Result<u64> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_nonblocking(true)?;
+        socket.connect(self.host_destination(key.remote_ip, key.remote_port))?;
+
+        let fd = socket.as_raw_fd();
+        let timeout = if key.remote_ip == self.gateway_ip && key.remote_port == DNS_PORT {
+            DNS_TIMEOUT
+        } else {
+            UDP_TIMEOUT
+        };
+        let token = self.add_socket(Socket::Udp(UdpNat {
+            socket,
+            key,
+            timeout,
+            last_activity: Instant::now(),
+        }));
+        if let Err(Error::RegisterListener(e)) = self.register(fd, epoll::Events::EPOLLIN, token) {
+            self.sockets.remove(&token);
+            return Err(e);
+        }
+        self.udp_nat.insert(key, token);
+
+        Ok(token)
+    }
+
+    fn process_tcp(&mut self, ip: &Ipv4Packet, segment: &TcpSegment) {
+        let key = TcpConnectionKey {
+            guest_ip: ip.src,
+            guest_port: segment.src_port,
+            remote_ip: ip.dst,
+            remote_port: segment.dst_port,
+        };
+
+        if let Some(token) = self.tcp_connections.get(&key).copied() {
+            let mut out = Vec::new();
+            if let Some(Socket::Tcp(conn)) = self.sockets.get_mut(&token) {
+                conn.process_guest_segment(segment, &mut out);
+            }
+            self.queue_tcp_segments(out);
+            self.update_tcp_connection(token);
+            return;
+        }
+
+        if segment.has(TCP_RST) {
+            return;
+        }
+
+        if !segment.has(TCP_SYN) || segment.has(TCP_ACK) || self.is_broadcast_or_multicast(ip.dst) {
+            self.reset_unknown_segment(&key, segment);
+            return;
+        }
+
+        let stream = match tcp_connect_nonblocking(self.host_destination(ip.dst, segment.dst_port))
+        {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("user-net: failed connecting for {:?}: {}", key, e);
+                self.reset_unknown_segment(&key, segment);
+                return;
+            }
+        };
+
+        let isn = self.next_isn();
+        let conn = TcpConnection::new_outbound(stream, key, segment, isn);
+        let token = self.add_socket(Socket::Tcp(conn));
+        self.tcp_connections.insert(key, token);
+        self.update_tcp_connection(token);
+    }
+
+    // Reply with a reset to a segment which doesn't belong to any connection.
+    fn reset_unknown_segment(&mut self, key: &TcpConnectionKey, segment: &TcpSegment) {
+        let (seq, ack, flags) = if segment.has(TCP_ACK) {
+            (segment.ack, 0, TCP_RST)
+        } else {
+            let mut len = segment.payload.len() as u32;
+            if segment.has(TCP_SYN) {
+                len += 1;
+            }
+            if segment.has(TCP_FIN) {
+                len += 1;
+            }
+            (0, segment.seq.wrapping_add(len), TCP_RST | TCP_ACK)
+        };
+
+        let packet = build_tcp_segment(
+            key.remote_ip,
+            key.remote_port,
+            key.guest_ip,
+            key.guest_port,
+            seq,
+            ack,
+            flags,
+            0,
+            None,
+            &[],
+        );
+        self.queue_packet(packet, true);
+    }
+
+    fn queue_tcp_segments(&mut self, segments: Vec<Vec<u8>>) {
+        for segment in segments {
+            self.queue_packet(segment, true);
+        }
+    }
+
+    // Update the epoll registration of a TCP connection after it has been
+    // processed, or drop it if it is over.
+    fn update_tcp_connection(&mut self, token: u64) {
+        let epoll_fd = self.epoll_fd;
+        let closed = match self.sockets.get_mut(&token) {
+            Some(Socket::Tcp(conn)) => {
+                conn.is_closed()
+                    || conn
+                        .update_registration(epoll_fd, token)
+                        .map_err(|e| error!("user-net: failed updating registration: {}", e))
+                        .is_err()
+            }
+            _ => return,
+        };
+
+        if closed {
+            if let Some(Socket::Tcp(conn)) = self.sockets.remove(&token) {
+                debug!("user-net: connection {:?} closed", conn.key());
+                self.tcp_connections.remove(&conn.key());
+            }
+        }
+    }
+
+    fn process_socket(&mut self, token: u64, evset: epoll::Events) {
+        match self.sockets.get_mut(&token) {
+            Some(Socket::Tcp(conn)) => {
+                let mut out = Vec::new();
+                conn.process_host_event(evset, &mut out);
+                self.queue_tcp_segments(out);
+                self.update_tcp_connection(token);
+            }
+            Some(Socket::Udp(_)) => self.process_udp_nat(token),
+            Some(Socket::TcpForward { .. }) => self.process_tcp_forward(token),
+            Some(Socket::UdpForward { .. }) => self.process_udp_forward(token),
+            None => {}
+        }
+    }
+
+    fn process_udp_nat(&mut self, token: u64) {
+        let mut buf = vec![0u8; MAX_FRAME_SIZE];
+        loop {
+            let (len, key) = match self.sockets.get_mut(&token) {
+                Some(Socket::Udp(nat)) => match nat.socket.recv(&mut buf) {
+                    Ok(len) => {
+                        nat.last_activity = Instant::now();
+                        (len, nat.key)
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return,
+                },
+                _ => return,
+            };
+
+            let packet = build_udp_datagram(
+                key.remote_ip,
+                key.remote_port,
+                key.guest_ip,
+                key.guest_port,
+                &buf[..len],
+            );
+            self.queue_packet(packet, false);
+        }
+    }
+
+    fn process_tcp_forward(&mut self, token: u64) {
+        loop {
+            let (stream, guest_port) = match self.sockets.get(&token) {
+                Some(Socket::TcpForward {
+                    listener,
+                    guest_port,
+                }) => match listener.accept() {
+                    Ok((stream, _)) => (stream, *guest_port),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return,
+                },
+                _ => return,
+            };
+
+            // The guest must have been configured through DHCP to be
+            // reachable, otherwise the connection is simply dropped.
+            if self.guest_mac.is_none() || stream.set_nonblocking(true).is_err() {
+                continue;
+            }
+
+            let key = TcpConnectionKey {
+                guest_ip: self.guest_ip,
+                guest_port,
+                remote_ip: self.gateway_ip,
+                remote_port: self.allocate_forward_port(),
+            };
+            if self.tcp_connections.contains_key(&key) {
+                continue;
+            }
+
+            let isn = self.next_isn();
+            let mut out = Vec::new();
+            let conn = TcpConnection::new_inbound(stream, key, isn, &mut out);
+            self.queue_tcp_segments(out);
+            let token = self.add_socket(Socket::Tcp(conn));
+            self.tcp_connections.insert(key, token);
+            self.update_tcp_connection(token);
+        }
+    }
+
+    fn process_udp_forward(&mut self, token: u64) {
+        let mut buf = vec![0u8; MAX_FRAME_SIZE];
+        loop {
+            let (len, peer, guest_port) = match self.sockets.get(&token) {
+                Some(Socket::UdpForward { socket, guest_port }) => {
+                    match socket.recv_from(&mut buf) {
+                        Ok((len, peer)) => (len, peer, *guest_port),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(_) => return,
+                    }
+                }
+                _ => return,
+            };
+
+            // Each peer is given its own gateway port, so that the replies
+            // from the guest can be sent back to it.
+            let existing = self
+                .udp_forward_sessions
+                .iter_mut()
+                .find(|((port, _), session)| {
+                    *port == guest_port && session.token == token && session.peer == peer
+                })
+                .map(|((_, gateway_port), session)| {
+                    session.last_activity = Instant::now();
+                    *gateway_port
+                });
+            let gateway_port = match existing {
+                Some(gateway_port) => gateway_port,
+                None => {
+                    let gateway_port = self.allocate_forward_port();
+                    self.udp_forward_sessions.insert(
+                        (guest_port, gateway_port),
+                        UdpForwardSession {
+                            token,
+                            peer,
+                            last_activity: Instant::now(),
+                        },
+                    );
+                    gateway_port
+                }
+            };
+
+            let packet = build_udp_datagram(
+                self.gateway_ip,
+                gateway_port,
+                self.guest_ip,
+                guest_port,
+                &buf[..len],
+            );
+            self.queue_packet(packet, false);
+        }
+    }
+
+    fn expire_udp(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.last_expiry) < Duration::from_secs(1) {
+            return;
+        }
+        self.last_expiry = now;
+
+        let expired: Vec<(u64, UdpNatKey)> = self
+            .sockets
+            .iter()
+            .filter_map(|(token, socket)| match socket {
+                Socket::Udp(nat) if now.duration_since(nat.last_activity) > nat.timeout => {
+                    Some((*token, nat.key))
+                }
+                _ => None,
+            })
+            .collect();
+        for (token, key) in expired {
+            self.sockets.remove(&token);
+            self.udp_nat.remove(&key);
+        }
+
+        self.udp_forward_sessions
+            .retain(|_, session| now.duration_since(session.last_activity) <= UDP_TIMEOUT);
+    }
+
+    fn queue_packet(&mut self, packet: Vec<u8>, reliable: bool) {
+        if let Some(guest_mac) = self.guest_mac {
+            let frame = build_ethernet_frame(
+                vnet_hdr_len(),
+                guest_mac,
+                self.gateway_mac,
+                ETH_P_IP,
+                &packet,
+            );
+            self.queue_frame(frame, reliable);
+        }
+    }
+
+    // Frames which are not reliable can be dropped when the guest is not
+    // processing them fast enough.
+    fn queue_frame(&mut self, frame: Vec<u8>, reliable: bool) {
+        if reliable || self.to_guest.len() < MAX_GUEST_BACKLOG {
+            self.to_guest.push_back(frame);
+        }
+    }
+
+    fn flush_guest(&mut self) {
+        while let Some(frame) = self.to_guest.front() {
+            match self.guest.write(frame) {
+                Ok(_) => {
+                    self.to_guest.pop_front();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("user-net: failed writing to the guest: {}", e);
+                    self.to_guest.pop_front();
+                }
+            }
+        }
+
+        // Wait for the guest to make some room if some frames are pending.
+        let epollout = !self.to_guest.is_empty();
+        if epollout != self.guest_epollout {
+            let mut events = epoll::Events::EPOLLIN;
+            if epollout {
+                events |= epoll::Events::EPOLLOUT;
+            }
+            match epoll::ctl(
+                self.epoll_fd,
+                epoll::ControlOptions::EPOLL_CTL_MOD,
+                self.guest.as_raw_fd(),
+                epoll::Event::new(events, GUEST_EVENT),
+            ) {
+                Ok(()) => self.guest_epollout = epollout,
+                Err(e) => error!("user-net: failed updating registration: {}", e),
+            }
+        }
+    }
+}
+
+impl Drop for UserNet {
+    fn drop(&mut self) {
+        // This is safe since the epoll file descriptor is owned by the stack.
+        unsafe { libc::close(self.epoll_fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const GUEST_MAC: [u8; 6] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc];
+
+    fn send_to_stack(tap: &mut Tap, packet: &[u8]) {
+        let frame = build_ethernet_frame(
+            vnet_hdr_len(),
+            MacAddr::from_bytes_unchecked(&GATEWAY_MAC),
+            MacAddr::from_bytes_unchecked(&GUEST_MAC),
+            ETH_P_IP,
+            packet,
+        );
+        tap.write_all(&frame).unwrap();
+    }
+
+    fn receive_from_stack(tap: &mut Tap) -> Vec<u8> {
+        let mut buf = vec![0u8; MAX_FRAME_SIZE];
+        for _ in 0..500 {
+            match tap.read(&mut buf) {
+                Ok(len) => {
+                    let frame = EthernetFrame::parse(&buf[vnet_hdr_len()..len]).unwrap();
+                    assert_eq!(frame.dst.get_bytes(), GUEST_MAC);
+                    return frame.payload.to_vec();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+        panic!("nothing received from the stack");
+    }
+
+    #[test]
+    fn test_user_net_udp_loopback() {
+        let config = UserNetConfig {
+            ip: Ipv4Addr::new(10, 0, 2, 2),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            tcp_forwards: Vec::new(),
+            udp_forwards: Vec::new(),
+        };
+        let (mut user_net, mut tap) = UserNet::new(&config).unwrap();
+        let kill_evt = user_net.kill_evt().try_clone().unwrap();
+        let stack = thread::spawn(move || user_net.run().unwrap());
+
+        // Echo server on the host loopback, reached through the gateway.
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let server_port = server.local_addr().unwrap().port();
+        let guest_ip = Ipv4Addr::new(10, 0, 2, 3);
+
+        send_to_stack(
+            &mut tap,
+            &build_udp_datagram(guest_ip, 4000, config.ip, server_port, b"ping"),
+        );
+
+        let mut buf = [0u8; 16];
+        let (len, peer) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        server.send_to(b"pong", peer).unwrap();
+
+        let reply = receive_from_stack(&mut tap);
+        let ip = Ipv4Packet::parse(&reply).unwrap();
+        assert_eq!(ip.src, config.ip);
+        assert_eq!(ip.dst, guest_ip);
+        let udp = UdpDatagram::parse(ip.payload).unwrap();
+        assert_eq!(udp.src_port, server_port);
+        assert_eq!(udp.dst_port, 4000);
+        assert_eq!(udp.payload, b"pong");
+
+        kill_evt.write(1).unwrap();
+        stack.join().unwrap();
+    }
+
+    #[test]
+    fn test_user_net_tcp_loopback() {
+        let config = UserNetConfig {
+            ip: Ipv4Addr::new(10, 0, 2, 2),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            tcp_forwards: Vec::new(),
+            udp_forwards: Vec::new(),
+        };
+        let (mut user_net, mut tap) = UserNet::new(&config).unwrap();
+        let kill_evt = user_net.kill_evt().try_clone().unwrap();
+        let stack = thread::spawn(move || user_net.run().unwrap());
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let server_port = listener.local_addr().unwrap().port();
+        let guest_ip = Ipv4Addr::new(10, 0, 2, 3);
+        let tcp = |seq, ack, flags, payload: &[u8]| {
+            build_tcp_segment(
+                guest_ip,
+                5000,
+                config.ip,
+                server_port,
+                seq,
+                ack,
+                flags,
+                65535,
+                None,
+                payload,
+            )
+        };
+
+        // Three way handshake.
+        send_to_stack(&mut tap, &tcp(100, 0, TCP_SYN, &[]));
+        let (mut stream, _) = listener.accept().unwrap();
+        let reply = receive_from_stack(&mut tap);
+        let syn_ack = TcpSegment::parse(Ipv4Packet::parse(&reply).unwrap().payload)
+            .map(|s| (s.seq, s.ack, s.flags))
+            .unwrap();
+        assert_eq!(syn_ack.1, 101);
+        assert_eq!(syn_ack.2, TCP_SYN | TCP_ACK);
+        let mut seq = syn_ack.0.wrapping_add(1);
+        send_to_stack(&mut tap, &tcp(101, seq, TCP_ACK, &[]));
+
+        // Data from the guest to the host.
+        send_to_stack(&mut tap, &tcp(101, seq, TCP_ACK | TCP_PSH, b"hello"));
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        let reply = receive_from_stack(&mut tap);
+        let ack = TcpSegment::parse(Ipv4Packet::parse(&reply).unwrap().payload)
+            .map(|s| s.ack)
+            .unwrap();
+        assert_eq!(ack, 106);
+
+        // Data from the host to the guest.
+        stream.write_all(b"world").unwrap();
+        let reply = receive_from_stack(&mut tap);
+        let ip = Ipv4Packet::parse(&reply).unwrap();
+        let segment = TcpSegment::parse(ip.payload).unwrap();
+        assert_eq!(segment.seq, seq);
+        assert_eq!(segment.payload, b"world");
+        seq = seq.wrapping_add(5);
+        send_to_stack(&mut tap, &tcp(106, seq, TCP_ACK, &[]));
+
+        // The host closes the connection.
+        drop(stream);
+        let reply = receive_from_stack(&mut tap);
+        let fin = TcpSegment::parse(Ipv4Packet::parse(&reply).unwrap().payload)
+            .map(|s| s.flags)
+            .unwrap();
+        assert_eq!(fin & TCP_FIN, TCP_FIN);
+
+        kill_evt.write(1).unwrap();
+        stack.join().unwrap();
+    }
+}
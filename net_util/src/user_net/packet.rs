@@ -0,0 +1,523 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Minimal parsing and building of the Ethernet, ARP, IPv4, UDP and TCP
+//! headers handled by the userspace network stack.
+
+use crate::MacAddr;
+use std::net::Ipv4Addr;
+
+pub const ETH_HLEN: usize = 14;
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_ARP: u16 = 0x0806;
+
+pub const IPV4_HLEN: usize = 20;
+pub const IPPROTO_ICMP: u8 = 1;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+
+pub const UDP_HLEN: usize = 8;
+pub const TCP_HLEN: usize = 20;
+
+pub const ARP_LEN: usize = 28;
+const ARP_HTYPE_ETHERNET: u16 = 1;
+const ARP_OP_REQUEST: u16 = 1;
+const ARP_OP_REPLY: u16 = 2;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+const TCP_OPT_END: u8 = 0;
+const TCP_OPT_NOP: u8 = 1;
+const TCP_OPT_MSS: u8 = 2;
+
+const IP_DEFAULT_TTL: u8 = 64;
+// Don't fragment flag, as the stack never fragments the packets it builds.
+const IP_DF: u16 = 0x4000;
+const IP_MF: u16 = 0x2000;
+const IP_OFFSET_MASK: u16 = 0x1fff;
+
+fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn get_ip(buf: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    )
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Sum the buffer as a sequence of 16 bits big endian words, as needed by
+/// the Internet checksum.
+fn checksum_add(mut sum: u32, buf: &[u8]) -> u32 {
+    let mut chunks = buf.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u32::from(u16::from_be_bytes([chunk[0], chunk[1]]));
+    }
+    if let [last] = chunks.remainder() {
+        sum += u32::from(*last) << 8;
+    }
+    sum
+}
+
+fn checksum_fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Compute the Internet checksum (RFC 1071) of a buffer.
+pub fn checksum(buf: &[u8]) -> u16 {
+    checksum_fold(checksum_add(0, buf))
+}
+
+fn pseudo_header_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, segment: &[u8]) -> u16 {
+    let mut sum = checksum_add(0, &src.octets());
+    sum = checksum_add(sum, &dst.octets());
+    sum += u32::from(protocol);
+    sum += segment.len() as u32;
+    checksum_fold(checksum_add(sum, segment))
+}
+
+pub struct EthernetFrame<'a> {
+    pub dst: MacAddr,
+    pub src: MacAddr,
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < ETH_HLEN {
+            return None;
+        }
+
+        Some(EthernetFrame {
+            dst: MacAddr::from_bytes_unchecked(&buf[0..6]),
+            src: MacAddr::from_bytes_unchecked(&buf[6..12]),
+            ethertype: get_u16(buf, 12),
+            payload: &buf[ETH_HLEN..],
+        })
+    }
+}
+
+/// Build an Ethernet frame, prefixed with `headroom` bytes left zeroed for
+/// the caller to fill, e.g. with a virtio-net header.
+pub fn build_ethernet_frame(
+    headroom: usize,
+    dst: MacAddr,
+    src: MacAddr,
+    ethertype: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut frame = vec![0u8; headroom + ETH_HLEN + payload.len()];
+    let buf = &mut frame[headroom..];
+    buf[0..6].copy_from_slice(dst.get_bytes());
+    buf[6..12].copy_from_slice(src.get_bytes());
+    put_u16(buf, 12, ethertype);
+    buf[ETH_HLEN..].copy_from_slice(payload);
+    frame
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ArpPacket {
+    pub op: u16,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < ARP_LEN
+            || get_u16(buf, 0) != ARP_HTYPE_ETHERNET
+            || get_u16(buf, 2) != ETH_P_IP
+            || buf[4] != 6
+            || buf[5] != 4
+        {
+            return None;
+        }
+
+        Some(ArpPacket {
+            op: get_u16(buf, 6),
+            sender_mac: MacAddr::from_bytes_unchecked(&buf[8..14]),
+            sender_ip: get_ip(buf, 14),
+            target_mac: MacAddr::from_bytes_unchecked(&buf[18..24]),
+            target_ip: get_ip(buf, 24),
+        })
+    }
+
+    pub fn is_request(&self) -> bool {
+        self.op == ARP_OP_REQUEST
+    }
+
+    /// Build the reply to this request, announcing `mac` as the owner of
+    /// the requested address.
+    pub fn build_reply(&self, mac: MacAddr) -> Vec<u8> {
+        let mut buf = vec![0u8; ARP_LEN];
+        put_u16(&mut buf, 0, ARP_HTYPE_ETHERNET);
+        put_u16(&mut buf, 2, ETH_P_IP);
+        buf[4] = 6;
+        buf[5] = 4;
+        put_u16(&mut buf, 6, ARP_OP_REPLY);
+        buf[8..14].copy_from_slice(mac.get_bytes());
+        buf[14..18].copy_from_slice(&self.target_ip.octets());
+        buf[18..24].copy_from_slice(self.sender_mac.get_bytes());
+        buf[24..28].copy_from_slice(&self.sender_ip.octets());
+        buf
+    }
+}
+
+pub struct Ipv4Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// Parse an IPv4 packet. Fragmented packets are not supported and are
+    /// reported as invalid.
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < IPV4_HLEN || buf[0] >> 4 != 4 {
+            return None;
+        }
+
+        let header_len = ((buf[0] & 0xf) as usize) * 4;
+        let total_len = get_u16(buf, 2) as usize;
+        if header_len < IPV4_HLEN || total_len < header_len || total_len > buf.len() {
+            return None;
+        }
+
+        let frag = get_u16(buf, 6);
+        if frag & IP_MF != 0 || frag & IP_OFFSET_MASK != 0 {
+            return None;
+        }
+
+        Some(Ipv4Packet {
+            src: get_ip(buf, 12),
+            dst: get_ip(buf, 16),
+            protocol: buf[9],
+            payload: &buf[header_len..total_len],
+        })
+    }
+}
+
+pub fn build_ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0u8; IPV4_HLEN + payload.len()];
+    buf[0] = 0x45;
+    put_u16(&mut buf, 2, (IPV4_HLEN + payload.len()) as u16);
+    put_u16(&mut buf, 6, IP_DF);
+    buf[8] = IP_DEFAULT_TTL;
+    buf[9] = protocol;
+    buf[12..16].copy_from_slice(&src.octets());
+    buf[16..20].copy_from_slice(&dst.octets());
+    let csum = checksum(&buf[..IPV4_HLEN]);
+    put_u16(&mut buf, 10, csum);
+    buf[IPV4_HLEN..].copy_from_slice(payload);
+    buf
+}
+
+/// Build the reply to an ICMP echo request, or return None if the message
+/// isn't an echo request.
+pub fn build_icmp_echo_reply(request: &[u8]) -> Option<Vec<u8>> {
+    if request.len() < 8 || request[0] != ICMP_ECHO_REQUEST {
+        return None;
+    }
+
+    let mut reply = request.to_vec();
+    reply[0] = ICMP_ECHO_REPLY;
+    put_u16(&mut reply, 2, 0);
+    let csum = checksum(&reply);
+    put_u16(&mut reply, 2, csum);
+    Some(reply)
+}
+
+pub struct UdpDatagram<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < UDP_HLEN {
+            return None;
+        }
+
+        let len = get_u16(buf, 4) as usize;
+        if len < UDP_HLEN || len > buf.len() {
+            return None;
+        }
+
+        Some(UdpDatagram {
+            src_port: get_u16(buf, 0),
+            dst_port: get_u16(buf, 2),
+            payload: &buf[UDP_HLEN..len],
+        })
+    }
+}
+
+pub fn build_udp_datagram(
+    src: Ipv4Addr,
+    src_port: u16,
+    dst: Ipv4Addr,
+    dst_port: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut buf = vec![0u8; UDP_HLEN + payload.len()];
+    put_u16(&mut buf, 0, src_port);
+    put_u16(&mut buf, 2, dst_port);
+    put_u16(&mut buf, 4, (UDP_HLEN + payload.len()) as u16);
+    buf[UDP_HLEN..].copy_from_slice(payload);
+    let csum = match pseudo_header_checksum(src, dst, IPPROTO_UDP, &buf) {
+        // A computed checksum of zero is transmitted as all ones.
+        0 => 0xffff,
+        csum => csum,
+    };
+    put_u16(&mut buf, 6, csum);
+    build_ipv4_packet(src, dst, IPPROTO_UDP, &buf)
+}
+
+pub struct TcpSegment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        if buf.len() < TCP_HLEN {
+            return None;
+        }
+
+        let header_len = ((buf[12] >> 4) as usize) * 4;
+        if header_len < TCP_HLEN || header_len > buf.len() {
+            return None;
+        }
+
+        Some(TcpSegment {
+            src_port: get_u16(buf, 0),
+            dst_port: get_u16(buf, 2),
+            seq: get_u32(buf, 4),
+            ack: get_u32(buf, 8),
+            flags: buf[13],
+            window: get_u16(buf, 14),
+            mss: Self::parse_mss(&buf[TCP_HLEN..header_len]),
+            payload: &buf[header_len..],
+        })
+    }
+
+    fn parse_mss(mut options: &[u8]) -> Option<u16> {
+        while let Some(&kind) = options.first() {
+            match kind {
+                TCP_OPT_END => break,
+                TCP_OPT_NOP => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        return None;
+                    }
+                    if kind == TCP_OPT_MSS && len == 4 {
+                        return Some(get_u16(options, 2));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        None
+    }
+
+    pub fn has(&self, flags: u8) -> bool {
+        self.flags & flags == flags
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn build_tcp_segment(
+    src: Ipv4Addr,
+    src_port: u16,
+    dst: Ipv4Addr,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &[u8],
+) -> Vec<u8> {
+    let header_len = if mss.is_some() {
+        TCP_HLEN + 4
+    } else {
+        TCP_HLEN
+    };
+    let mut buf = vec![0u8; header_len + payload.len()];
+    put_u16(&mut buf, 0, src_port);
+    put_u16(&mut buf, 2, dst_port);
+    put_u32(&mut buf, 4, seq);
+    put_u32(&mut buf, 8, ack);
+    buf[12] = ((header_len / 4) as u8) << 4;
+    buf[13] = flags;
+    put_u16(&mut buf, 14, window);
+    if let Some(mss) = mss {
+        buf[TCP_HLEN] = TCP_OPT_MSS;
+        buf[TCP_HLEN + 1] = 4;
+        put_u16(&mut buf, TCP_HLEN + 2, mss);
+    }
+    buf[header_len..].copy_from_slice(payload);
+    let csum = pseudo_header_checksum(src, dst, IPPROTO_TCP, &buf);
+    put_u16(&mut buf, 16, csum);
+    build_ipv4_packet(src, dst, IPPROTO_TCP, &buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        // Example from RFC 1071.
+        let buf = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(checksum(&buf), !0xddf2);
+
+        // A buffer including its own checksum sums up to zero.
+        let packet = build_ipv4_packet(
+            Ipv4Addr::new(10, 0, 2, 2),
+            Ipv4Addr::new(10, 0, 2, 15),
+            IPPROTO_UDP,
+            &[],
+        );
+        assert_eq!(checksum(&packet[..IPV4_HLEN]), 0);
+    }
+
+    #[test]
+    fn test_arp_reply() {
+        let guest_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let gateway_mac = MacAddr::parse_str("52:54:00:12:35:02").unwrap();
+        let request = ArpPacket {
+            op: ARP_OP_REQUEST,
+            sender_mac: guest_mac,
+            sender_ip: Ipv4Addr::new(10, 0, 2, 15),
+            target_mac: MacAddr::from_bytes_unchecked(&[0; 6]),
+            target_ip: Ipv4Addr::new(10, 0, 2, 2),
+        };
+
+        let reply = ArpPacket::parse(&request.build_reply(gateway_mac)).unwrap();
+        assert!(!reply.is_request());
+        assert_eq!(reply.sender_mac, gateway_mac);
+        assert_eq!(reply.sender_ip, Ipv4Addr::new(10, 0, 2, 2));
+        assert_eq!(reply.target_mac, guest_mac);
+        assert_eq!(reply.target_ip, Ipv4Addr::new(10, 0, 2, 15));
+    }
+
+    #[test]
+    fn test_udp_roundtrip() {
+        let src = Ipv4Addr::new(10, 0, 2, 15);
+        let dst = Ipv4Addr::new(10, 0, 2, 2);
+        let packet = build_udp_datagram(src, 1234, dst, 53, b"hello");
+
+        let ip = Ipv4Packet::parse(&packet).unwrap();
+        assert_eq!(ip.src, src);
+        assert_eq!(ip.dst, dst);
+        assert_eq!(ip.protocol, IPPROTO_UDP);
+        assert_eq!(pseudo_header_checksum(src, dst, IPPROTO_UDP, ip.payload), 0);
+
+        let udp = UdpDatagram::parse(ip.payload).unwrap();
+        assert_eq!(udp.src_port, 1234);
+        assert_eq!(udp.dst_port, 53);
+        assert_eq!(udp.payload, b"hello");
+    }
+
+    #[test]
+    fn test_tcp_roundtrip() {
+        let src = Ipv4Addr::new(10, 0, 2, 2);
+        let dst = Ipv4Addr::new(10, 0, 2, 15);
+        let packet = build_tcp_segment(
+            src,
+            80,
+            dst,
+            40000,
+            1000,
+            2000,
+            TCP_SYN | TCP_ACK,
+            65535,
+            Some(1460),
+            &[],
+        );
+
+        let ip = Ipv4Packet::parse(&packet).unwrap();
+        assert_eq!(ip.protocol, IPPROTO_TCP);
+        assert_eq!(pseudo_header_checksum(src, dst, IPPROTO_TCP, ip.payload), 0);
+
+        let tcp = TcpSegment::parse(ip.payload).unwrap();
+        assert_eq!(tcp.src_port, 80);
+        assert_eq!(tcp.dst_port, 40000);
+        assert_eq!(tcp.seq, 1000);
+        assert_eq!(tcp.ack, 2000);
+        assert!(tcp.has(TCP_SYN | TCP_ACK));
+        assert!(!tcp.has(TCP_FIN));
+        assert_eq!(tcp.window, 65535);
+        assert_eq!(tcp.mss, Some(1460));
+        assert!(tcp.payload.is_empty());
+    }
+
+    #[test]
+    fn test_icmp_echo_reply() {
+        let mut request = vec![ICMP_ECHO_REQUEST, 0, 0, 0, 0, 1, 0, 1, 0xaa, 0xbb];
+        let csum = checksum(&request);
+        put_u16(&mut request, 2, csum);
+
+        let reply = build_icmp_echo_reply(&request).unwrap();
+        assert_eq!(reply[0], ICMP_ECHO_REPLY);
+        assert_eq!(checksum(&reply), 0);
+        assert_eq!(&reply[4..], &request[4..]);
+
+        assert!(build_icmp_echo_reply(&reply).is_none());
+    }
+
+    #[test]
+    fn test_fragmented_ipv4_rejected() {
+        let mut packet = build_ipv4_packet(
+            Ipv4Addr::new(10, 0, 2, 15),
+            Ipv4Addr::new(10, 0, 2, 2),
+            IPPROTO_UDP,
+            &[0; 8],
+        );
+        put_u16(&mut packet, 6, IP_MF);
+        assert!(Ipv4Packet::parse(&packet).is_none());
+    }
+}
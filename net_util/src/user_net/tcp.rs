@@ -0,0 +1,379 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Termination of the guest TCP connections, which are spliced to host
+//! sockets.
+//!
+//! The link between the guest and the userspace stack never drops any
+//! segment, which is why no retransmission is implemented. The flow of data
+//! is still controlled in both directions through the TCP windows.
+
+use super::packet::{build_tcp_segment, TcpSegment, TCP_ACK, TCP_FIN, TCP_PSH, TCP_RST, TCP_SYN};
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+// Maximum segment size for an Ethernet MTU of 1500 bytes.
+const TCP_MSS: u16 = 1460;
+// Default maximum segment size when the peer doesn't provide one.
+const TCP_DEFAULT_MSS: u16 = 536;
+// Amount of data received from the guest, and not yet written to the host
+// socket, that can be buffered.
+const TO_HOST_CAPACITY: usize = 65535;
+
+// Sequence numbers comparison, taking the wrap around into account.
+fn seq_le(a: u32, b: u32) -> bool {
+    b.wrapping_sub(a) as i32 >= 0
+}
+
+#[derive(Debug, PartialEq)]
+enum TcpState {
+    // The guest initiated the connection, and the host socket is connecting.
+    Connecting,
+    // The host socket is connected, waiting for the guest to acknowledge
+    // the SYN.
+    SynAckSent,
+    // A host peer initiated the connection through a port forward, waiting
+    // for the guest to reply.
+    SynSent,
+    Established,
+    Closed,
+}
+
+/// Addresses and ports identifying a connection, as seen by the guest.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TcpConnectionKey {
+    pub guest_ip: Ipv4Addr,
+    pub guest_port: u16,
+    pub remote_ip: Ipv4Addr,
+    pub remote_port: u16,
+}
+
+pub struct TcpConnection {
+    stream: TcpStream,
+    key: TcpConnectionKey,
+    state: TcpState,
+    // Oldest sequence number sent to the guest and not acknowledged yet.
+    snd_una: u32,
+    // Next sequence number to send to the guest.
+    snd_nxt: u32,
+    // Next sequence number expected from the guest.
+    rcv_nxt: u32,
+    guest_window: u16,
+    mss: u16,
+    to_host: Vec<u8>,
+    host_eof: bool,
+    host_shutdown: bool,
+    guest_fin: bool,
+    registered_events: Option<epoll::Events>,
+}
+
+impl TcpConnection {
+    /// Create a connection initiated by the guest, from its SYN segment.
+    /// The host socket must be connecting to the actual destination.
+    pub fn new_outbound(
+        stream: TcpStream,
+        key: TcpConnectionKey,
+        syn: &TcpSegment,
+        isn: u32,
+    ) -> Self {
+        TcpConnection {
+            stream,
+            key,
+            state: TcpState::Connecting,
+            snd_una: isn,
+            snd_nxt: isn,
+            rcv_nxt: syn.seq.wrapping_add(1),
+            guest_window: syn.window,
+            mss: cmp::min(syn.mss.unwrap_or(TCP_DEFAULT_MSS), TCP_MSS),
+            to_host: Vec::new(),
+            host_eof: false,
+            host_shutdown: false,
+            guest_fin: false,
+            registered_events: None,
+        }
+    }
+
+    /// Create a connection initiated by a host peer, sending the SYN
+    /// segment to the guest.
+    pub fn new_inbound(
+        stream: TcpStream,
+        key: TcpConnectionKey,
+        isn: u32,
+        out: &mut Vec<Vec<u8>>,
+    ) -> Self {
+        let mut conn = TcpConnection {
+            stream,
+            key,
+            state: TcpState::SynSent,
+            snd_una: isn,
+            snd_nxt: isn,
+            rcv_nxt: 0,
+            guest_window: 0,
+            mss: TCP_DEFAULT_MSS,
+            to_host: Vec::new(),
+            host_eof: false,
+            host_shutdown: false,
+            guest_fin: false,
+            registered_events: None,
+        };
+        out.push(conn.build_segment(TCP_SYN, Some(TCP_MSS), &[]));
+        conn.snd_nxt = isn.wrapping_add(1);
+        conn
+    }
+
+    pub fn key(&self) -> TcpConnectionKey {
+        self.key
+    }
+
+    fn window(&self) -> u16 {
+        cmp::min(TO_HOST_CAPACITY - self.to_host.len(), u16::MAX as usize) as u16
+    }
+
+    fn build_segment(&self, flags: u8, mss: Option<u16>, payload: &[u8]) -> Vec<u8> {
+        self.build_segment_at(self.snd_nxt, flags, mss, payload)
+    }
+
+    fn build_segment_at(&self, seq: u32, flags: u8, mss: Option<u16>, payload: &[u8]) -> Vec<u8> {
+        build_tcp_segment(
+            self.key.remote_ip,
+            self.key.remote_port,
+            self.key.guest_ip,
+            self.key.guest_port,
+            seq,
+            self.rcv_nxt,
+            flags,
+            self.window(),
+            mss,
+            payload,
+        )
+    }
+
+    fn reset(&mut self, out: &mut Vec<Vec<u8>>) {
+        out.push(self.build_segment(TCP_RST | TCP_ACK, None, &[]));
+        self.state = TcpState::Closed;
+    }
+
+    /// Whether the connection is over and can be dropped.
+    pub fn is_closed(&self) -> bool {
+        self.state == TcpState::Closed
+            || (self.guest_fin
+                && self.host_eof
+                && self.snd_una == self.snd_nxt
+                && self.to_host.is_empty())
+    }
+
+    fn in_flight(&self) -> u32 {
+        self.snd_nxt.wrapping_sub(self.snd_una)
+    }
+
+    /// Process the events from the host socket.
+    pub fn process_host_event(&mut self, evset: epoll::Events, out: &mut Vec<Vec<u8>>) {
+        if self.state == TcpState::Connecting {
+            if evset.intersects(epoll::Events::EPOLLERR | epoll::Events::EPOLLHUP) {
+                debug!("user-net: connection to {:?} failed", self.key);
+                self.reset(out);
+            } else if evset.contains(epoll::Events::EPOLLOUT) {
+                out.push(self.build_segment(TCP_SYN | TCP_ACK, Some(TCP_MSS), &[]));
+                self.snd_nxt = self.snd_una.wrapping_add(1);
+                self.state = TcpState::SynAckSent;
+            }
+            return;
+        }
+
+        if evset.contains(epoll::Events::EPOLLOUT) {
+            self.flush_to_host(out);
+        }
+        if evset.intersects(epoll::Events::EPOLLIN | epoll::Events::EPOLLHUP) {
+            self.read_from_host(out);
+        }
+        if evset.contains(epoll::Events::EPOLLERR) && self.state != TcpState::Closed {
+            self.reset(out);
+        }
+    }
+
+    /// Process a segment received from the guest.
+    pub fn process_guest_segment(&mut self, segment: &TcpSegment, out: &mut Vec<Vec<u8>>) {
+        if segment.has(TCP_RST) {
+            self.state = TcpState::Closed;
+            return;
+        }
+
+        match self.state {
+            TcpState::Connecting | TcpState::Closed => return,
+            TcpState::SynSent => {
+                if segment.has(TCP_SYN | TCP_ACK) && segment.ack == self.snd_nxt {
+                    self.snd_una = segment.ack;
+                    self.rcv_nxt = segment.seq.wrapping_add(1);
+                    self.guest_window = segment.window;
+                    self.mss = cmp::min(segment.mss.unwrap_or(TCP_DEFAULT_MSS), TCP_MSS);
+                    self.state = TcpState::Established;
+                    out.push(self.build_segment(TCP_ACK, None, &[]));
+                    self.read_from_host(out);
+                }
+                return;
+            }
+            TcpState::SynAckSent => {
+                if segment.has(TCP_SYN) {
+                    // The SYN was retransmitted, reply again.
+                    out.push(self.build_segment_at(
+                        self.snd_una,
+                        TCP_SYN | TCP_ACK,
+                        Some(TCP_MSS),
+                        &[],
+                    ));
+                    return;
+                }
+                if !segment.has(TCP_ACK) || segment.ack != self.snd_nxt {
+                    return;
+                }
+                self.state = TcpState::Established;
+            }
+            TcpState::Established => {}
+        }
+
+        if segment.has(TCP_ACK)
+            && seq_le(self.snd_una, segment.ack)
+            && seq_le(segment.ack, self.snd_nxt)
+        {
+            self.snd_una = segment.ack;
+            self.guest_window = segment.window;
+        }
+
+        let fin = segment.has(TCP_FIN);
+        if !segment.payload.is_empty() || fin {
+            if segment.seq == self.rcv_nxt && !self.guest_fin {
+                let accepted = cmp::min(segment.payload.len(), self.window() as usize);
+                self.to_host.extend_from_slice(&segment.payload[..accepted]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(accepted as u32);
+                if fin && accepted == segment.payload.len() {
+                    self.guest_fin = true;
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                }
+                self.flush_to_host(out);
+            }
+            // Acknowledge what has been received, which is also how the
+            // guest learns about segments that were out of order.
+            if self.state != TcpState::Closed {
+                out.push(self.build_segment(TCP_ACK, None, &[]));
+            }
+        }
+
+        // Some window might have been made available by the guest.
+        self.read_from_host(out);
+    }
+
+    fn flush_to_host(&mut self, out: &mut Vec<Vec<u8>>) {
+        while !self.to_host.is_empty() {
+            match self.stream.write(&self.to_host) {
+                Ok(n) => {
+                    self.to_host.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!("user-net: failed writing to {:?}: {}", self.key, e);
+                    self.reset(out);
+                    return;
+                }
+            }
+        }
+
+        if self.guest_fin && self.to_host.is_empty() && !self.host_shutdown {
+            let _ = self.stream.shutdown(Shutdown::Write);
+            self.host_shutdown = true;
+        }
+    }
+
+    fn read_from_host(&mut self, out: &mut Vec<Vec<u8>>) {
+        let mut buf = [0u8; TCP_MSS as usize];
+
+        while self.state == TcpState::Established
+            && !self.host_eof
+            && self.in_flight() < u32::from(self.guest_window)
+        {
+            let len = cmp::min(
+                u32::from(self.guest_window) - self.in_flight(),
+                u32::from(self.mss),
+            ) as usize;
+            match self.stream.read(&mut buf[..len]) {
+                Ok(0) => {
+                    out.push(self.build_segment(TCP_FIN | TCP_ACK, None, &[]));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    self.host_eof = true;
+                }
+                Ok(n) => {
+                    out.push(self.build_segment(TCP_PSH | TCP_ACK, None, &buf[..n]));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(n as u32);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!("user-net: failed reading from {:?}: {}", self.key, e);
+                    self.reset(out);
+                }
+            }
+        }
+    }
+
+    fn desired_events(&self) -> epoll::Events {
+        let mut events = epoll::Events::empty();
+        match self.state {
+            TcpState::Connecting => events |= epoll::Events::EPOLLOUT,
+            TcpState::Established => {
+                if !self.host_eof && self.in_flight() < u32::from(self.guest_window) {
+                    events |= epoll::Events::EPOLLIN;
+                }
+                if !self.to_host.is_empty() {
+                    events |= epoll::Events::EPOLLOUT;
+                }
+            }
+            _ => {}
+        }
+        events
+    }
+
+    /// Update the events the host socket is monitored for. The socket is
+    /// removed from the epoll set when no event is expected, since errors
+    /// and hang ups would otherwise be reported continuously.
+    pub fn update_registration(&mut self, epoll_fd: RawFd, token: u64) -> io::Result<()> {
+        let events = self.desired_events();
+        let op = match (self.registered_events, events.is_empty()) {
+            (Some(registered), false) if registered == events => return Ok(()),
+            (Some(_), false) => epoll::ControlOptions::EPOLL_CTL_MOD,
+            (Some(_), true) => epoll::ControlOptions::EPOLL_CTL_DEL,
+            (None, false) => epoll::ControlOptions::EPOLL_CTL_ADD,
+            (None, true) => return Ok(()),
+        };
+
+        epoll::ctl(
+            epoll_fd,
+            op,
+            self.stream.as_raw_fd(),
+            epoll::Event::new(events, token),
+        )?;
+        self.registered_events = if events.is_empty() {
+            None
+        } else {
+            Some(events)
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seq_le() {
+        assert!(seq_le(1, 1));
+        assert!(seq_le(1, 2));
+        assert!(!seq_le(2, 1));
+        assert!(seq_le(u32::MAX, 0));
+        assert!(!seq_le(0, u32::MAX));
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Handle on the kernel vhost-net driver, which processes the virtqueues of
+//! a queue pair directly from the host kernel, using a TAP interface as its
+//! backend.
+
+use super::Tap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use vm_memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref};
+
+const VHOST_NET_PATH: &str = "/dev/vhost-net";
+
+/// Index of the RX virtqueue for a vhost-net instance.
+pub const VHOST_NET_RX_QUEUE: usize = 0;
+/// Index of the TX virtqueue for a vhost-net instance.
+pub const VHOST_NET_TX_QUEUE: usize = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to open /dev/vhost-net.
+    Open(io::Error),
+    /// Failed to set the current process as the owner.
+    SetOwner(io::Error),
+    /// Failed to get the features supported by the driver.
+    GetFeatures(io::Error),
+    /// Failed to set the features used by the driver.
+    SetFeatures(io::Error),
+    /// Failed to set the memory table.
+    SetMemTable(io::Error),
+    /// Failed to set the size of a virtqueue.
+    SetVringNum(io::Error),
+    /// Failed to set the addresses of a virtqueue.
+    SetVringAddr(io::Error),
+    /// Failed to set the next available index of a virtqueue.
+    SetVringBase(io::Error),
+    /// Failed to set the eventfd notified by the guest.
+    SetVringKick(io::Error),
+    /// Failed to set the eventfd used to interrupt the guest.
+    SetVringCall(io::Error),
+    /// Failed to set the TAP backend of a virtqueue.
+    SetBackend(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn check_ioctl(ret: i32, err: fn(io::Error) -> Error) -> Result<()> {
+    if ret < 0 {
+        Err(err(io::Error::last_os_error()))
+    } else {
+        Ok(())
+    }
+}
+
+/// One vhost-net instance drives the RX and TX virtqueues of a single queue
+/// pair, hence a multiqueue device needs one instance per queue pair.
+///
+/// The addresses given to the driver are host virtual addresses, as the
+/// kernel accesses the guest memory through the VMM mappings.
+#[derive(Debug)]
+pub struct VhostNet {
+    file: File,
+}
+
+impl VhostNet {
+    /// Open a new vhost-net instance, owned by the current process.
+    pub fn new() -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC | libc::O_NONBLOCK)
+            .open(VHOST_NET_PATH)
+            .map_err(Error::Open)?;
+
+        let vhost_net = VhostNet { file };
+        // ioctl is safe. Called with a valid vhost-net fd, and we check the return.
+        let ret = unsafe { ioctl(&vhost_net.file, net_gen::VHOST_SET_OWNER()) };
+        check_ioctl(ret, Error::SetOwner)?;
+
+        Ok(vhost_net)
+    }
+
+    pub fn get_features(&self) -> Result<u64> {
+        let mut features = 0u64;
+        // ioctl is safe. Called with a valid vhost-net fd, and we check the return.
+        let ret =
+            unsafe { ioctl_with_mut_ref(&self.file, net_gen::VHOST_GET_FEATURES(), &mut features) };
+        check_ioctl(ret, Error::GetFeatures)?;
+
+        Ok(features)
+    }
+
+    pub fn set_features(&self, features: u64) -> Result<()> {
+        // ioctl is safe. Called with a valid vhost-net fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_SET_FEATURES(), &features) };
+        check_ioctl(ret, Error::SetFeatures)
+    }
+
+    /// Describe the whole guest memory to the driver, which has to be done
+    /// again every time a region is added.
+    pub fn set_mem_table(&self, mem: &GuestMemoryMmap) -> Result<()> {
+        let regions: Vec<net_gen::vhost_memory_region> = mem
+            .iter()
+            .map(|region| net_gen::vhost_memory_region {
+                guest_phys_addr: region.start_addr().raw_value(),
+                memory_size: region.len() as u64,
+                userspace_addr: region.as_ptr() as u64,
+                flags_padding: 0,
+            })
+            .collect();
+
+        // Build a struct vhost_memory followed by the list of regions, the
+        // u64 buffer providing the required alignment.
+        let size = size_of::<net_gen::vhost_memory>()
+            + regions.len() * size_of::<net_gen::vhost_memory_region>();
+        let mut buf = vec![0u64; (size + size_of::<u64>() - 1) / size_of::<u64>()];
+        let memory = buf.as_mut_ptr() as *mut net_gen::vhost_memory;
+        // Safe because the buffer is large enough to hold the header and the
+        // regions.
+        unsafe {
+            (*memory).nregions = regions.len() as u32;
+            (*memory)
+                .regions
+                .as_mut_slice(regions.len())
+                .copy_from_slice(&regions);
+        }
+
+        // ioctl is safe. Called with a valid vhost-net fd, and we check the return.
+        let ret = unsafe { ioctl_with_ptr(&self.file, net_gen::VHOST_SET_MEM_TABLE(), memory) };
+        check_ioctl(ret, Error::SetMemTable)
+    }
+
+    pub fn set_vring_num(&self, index: usize, num: u16) -> Result<()> {
+        let state = net_gen::vhost_vring_state {
+            index: index as u32,
+            num: u32::from(num),
+        };
+        // ioctl is safe. Called with a valid vhost-net fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_SET_VRING_NUM(), &state) };
+        check_ioctl(ret, Error::SetVringNum)
+    }
+
+    /// Set the host virtual addresses of the descriptor table, the used
+    /// ring and the available ring.
+    pub fn set_vring_addr(
+        &self,
+        index: usize,
+        desc_table_addr: u64,
+        used_ring_addr: u64,
+        avail_ring_addr: u64,
+    ) -> Result<()> {
+        let addr = net_gen::vhost_vring_addr {
+            index: index as u32,
+            flags: 0,
+            desc_user_addr: desc_table_addr,
+            used_user_addr: used_ring_addr,
+            avail_user_addr: avail_ring_addr,
+            log_guest_addr: 0,
+        };
+        // ioctl is safe. Called with a valid vhost-net fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_SET_VRING_ADDR(), &addr) };
+        check_ioctl(ret, Error::SetVringAddr)
+    }
+
+    pub fn set_vring_base(&self, index: usize, base: u16) -> Result<()> {
+        let state = net_gen::vhost_vring_state {
+            index: index as u32,
+            num: u32::from(base),
+        };
+        // ioctl is safe. Called with a valid vhost-net fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_SET_VRING_BASE(), &state) };
+        check_ioctl(ret, Error::SetVringBase)
+    }
+
+    pub fn set_vring_kick(&self, index: usize, fd: &EventFd) -> Result<()> {
+        self.set_vring_file(
+            net_gen::VHOST_SET_VRING_KICK(),
+            index,
+            fd.as_raw_fd(),
+            Error::SetVringKick,
+        )
+    }
+
+    pub fn set_vring_call(&self, index: usize, fd: &EventFd) -> Result<()> {
+        self.set_vring_file(
+            net_gen::VHOST_SET_VRING_CALL(),
+            index,
+            fd.as_raw_fd(),
+            Error::SetVringCall,
+        )
+    }
+
+    /// Start processing the virtqueue with `tap` as its backend, or stop
+    /// processing it if no TAP is provided.
+    pub fn set_backend(&self, index: usize, tap: Option<&Tap>) -> Result<()> {
+        let fd = tap.map_or(-1, |tap| tap.as_raw_fd());
+        self.set_vring_file(
+            net_gen::VHOST_NET_SET_BACKEND(),
+            index,
+            fd,
+            Error::SetBackend,
+        )
+    }
+
+    fn set_vring_file(
+        &self,
+        req: libc::c_ulong,
+        index: usize,
+        fd: RawFd,
+        err: fn(io::Error) -> Error,
+    ) -> Result<()> {
+        let file = net_gen::vhost_vring_file {
+            index: index as u32,
+            fd,
+        };
+        // ioctl is safe. Called with a valid vhost-net fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, req, &file) };
+        check_ioctl(ret, err)
+    }
+}
+
+impl AsRawFd for VhostNet {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
@@ -8,9 +8,10 @@ use crate::{
     PciBarConfiguration, PciBarRegionType, PciCapabilityId, PciClassCode, PciConfiguration,
     PciDevice, PciDeviceError, PciHeaderType, PciSubclass, MSIX_TABLE_ENTRY_SIZE,
 };
+use anyhow::anyhow;
 use byteorder::{ByteOrder, LittleEndian};
 use std::any::Any;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::ptr::null_mut;
 use std::sync::{Arc, Barrier};
 use std::{fmt, io, result};
@@ -29,9 +30,9 @@ pub enum VfioPciError {
     AllocateGsi,
     DmaMap(VfioError),
     DmaUnmap(VfioError),
-    EnableIntx(VfioError),
-    EnableMsi(VfioError),
-    EnableMsix(VfioError),
+    EnableIntx(anyhow::Error),
+    EnableMsi(anyhow::Error),
+    EnableMsix(anyhow::Error),
     EventFd(io::Error),
     InterruptSourceGroupCreate(io::Error),
     IrqFd(hypervisor::HypervisorVmError),
@@ -242,79 +243,97 @@ pub struct MmioRegion {
     index: u32,
     mem_slot: Option<u32>,
     host_addr: Option<u64>,
+    mmap_offset: u64,
     mmap_size: Option<usize>,
 }
 
-struct VfioPciConfig {
-    device: Arc<VfioDevice>,
+/// Part of a region which can be mapped into the guest address space.
+pub(crate) struct VfioRegionMmap {
+    /// Region flags, using the `VFIO_REGION_INFO_FLAG_*` definitions.
+    pub(crate) flags: u32,
+    /// File descriptor the region is mapped from.
+    pub(crate) fd: RawFd,
+    /// Offset into the file descriptor.
+    pub(crate) file_offset: u64,
+    /// Offset of the mappable part within the region.
+    pub(crate) offset: u64,
+    /// Size of the mappable part.
+    pub(crate) size: u64,
 }
 
-impl VfioPciConfig {
-    fn new(device: Arc<VfioDevice>) -> Self {
-        VfioPciConfig { device }
-    }
+/// Accesses to a device, either passed through with VFIO or emulated by a
+/// vfio-user server, needed by the code common to both.
+pub(crate) trait Vfio {
+    fn region_read(&self, index: u32, offset: u64, data: &mut [u8]);
+
+    fn region_write(&self, index: u32, offset: u64, data: &[u8]);
+
+    /// Returns the content of the BAR register, which the BAR flags are
+    /// taken from, along with the size of the BAR. A size of 0 means the
+    /// BAR is not implemented.
+    fn bar_info(&self, bar_id: u32, bar_offset: u32) -> (u32, u64);
+
+    /// Returns how the region can be mapped, if it can.
+    fn region_mmap(&self, index: u32) -> Option<VfioRegionMmap>;
+
+    /// Returns the number of interrupts for the index, 0 meaning the device
+    /// doesn't support it.
+    fn irq_count(&self, irq_index: u32) -> u32;
+
+    fn enable_irq(&self, irq_index: u32, event_fds: Vec<&EventFd>) -> anyhow::Result<()>;
+
+    fn disable_irq(&self, irq_index: u32) -> anyhow::Result<()>;
+
+    fn unmask_irq(&self, irq_index: u32) -> anyhow::Result<()>;
 
     fn read_config_byte(&self, offset: u32) -> u8 {
         let mut data: [u8; 1] = [0];
-        self.device
-            .region_read(VFIO_PCI_CONFIG_REGION_INDEX, data.as_mut(), offset.into());
+        self.region_read(VFIO_PCI_CONFIG_REGION_INDEX, offset.into(), data.as_mut());
 
         data[0]
     }
 
     fn read_config_word(&self, offset: u32) -> u16 {
         let mut data: [u8; 2] = [0, 0];
-        self.device
-            .region_read(VFIO_PCI_CONFIG_REGION_INDEX, data.as_mut(), offset.into());
+        self.region_read(VFIO_PCI_CONFIG_REGION_INDEX, offset.into(), data.as_mut());
 
         u16::from_le_bytes(data)
     }
 
     fn read_config_dword(&self, offset: u32) -> u32 {
         let mut data: [u8; 4] = [0, 0, 0, 0];
-        self.device
-            .region_read(VFIO_PCI_CONFIG_REGION_INDEX, data.as_mut(), offset.into());
+        self.region_read(VFIO_PCI_CONFIG_REGION_INDEX, offset.into(), data.as_mut());
 
         u32::from_le_bytes(data)
     }
 
     fn write_config_dword(&self, buf: u32, offset: u32) {
         let data: [u8; 4] = buf.to_le_bytes();
-        self.device
-            .region_write(VFIO_PCI_CONFIG_REGION_INDEX, &data, offset.into())
+        self.region_write(VFIO_PCI_CONFIG_REGION_INDEX, offset.into(), &data)
     }
 }
 
-/// VfioPciDevice represents a VFIO PCI device.
-/// This structure implements the BusDevice and PciDevice traits.
-///
-/// A VfioPciDevice is bound to a VfioDevice and is also a PCI device.
-/// The VMM creates a VfioDevice, then assigns it to a VfioPciDevice,
-/// which then gets added to the PCI bus.
-pub struct VfioPciDevice {
+fn is_io_bar(bar_id: u32, flags: u32) -> bool {
+    bar_id != VFIO_PCI_ROM_REGION_INDEX && flags & PCI_CONFIG_IO_BAR == PCI_CONFIG_IO_BAR
+}
+
+fn is_64bit_bar(bar_id: u32, flags: u32) -> bool {
+    bar_id != VFIO_PCI_ROM_REGION_INDEX
+        && flags & PCI_CONFIG_MEMORY_BAR_64BIT == PCI_CONFIG_MEMORY_BAR_64BIT
+}
+
+/// The PCI configuration space, interrupts and BARs handling shared by the
+/// VFIO and vfio-user PCI devices.
+pub(crate) struct VfioCommon {
     vm: Arc<dyn hypervisor::Vm>,
-    device: Arc<VfioDevice>,
-    container: Arc<VfioContainer>,
-    vfio_pci_configuration: VfioPciConfig,
     configuration: PciConfiguration,
     mmio_regions: Vec<MmioRegion>,
     interrupt: Interrupt,
-    iommu_attached: bool,
 }
 
-impl VfioPciDevice {
-    /// Constructs a new Vfio Pci device for the given Vfio device
-    pub fn new(
-        vm: &Arc<dyn hypervisor::Vm>,
-        device: VfioDevice,
-        container: Arc<VfioContainer>,
-        msi_interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>>,
-        legacy_interrupt_group: Option<Arc<Box<dyn InterruptSourceGroup>>>,
-        iommu_attached: bool,
-    ) -> Result<Self> {
-        let device = Arc::new(device);
-        device.reset();
-
+impl VfioCommon {
+    pub(crate) fn new(vm: &Arc<dyn hypervisor::Vm>) -> Self {
+        // This is used for the BAR and capabilities only
         let configuration = PciConfiguration::new(
             0,
             0,
@@ -328,40 +347,23 @@ impl VfioPciDevice {
             None,
         );
 
-        let vfio_pci_configuration = VfioPciConfig::new(Arc::clone(&device));
-
-        let mut vfio_pci_device = VfioPciDevice {
+        VfioCommon {
             vm: vm.clone(),
-            device,
-            container,
             configuration,
-            vfio_pci_configuration,
             mmio_regions: Vec::new(),
             interrupt: Interrupt {
                 intx: None,
                 msi: None,
                 msix: None,
             },
-            iommu_attached,
-        };
-
-        vfio_pci_device.parse_capabilities(msi_interrupt_manager);
-
-        vfio_pci_device.initialize_legacy_interrupt(legacy_interrupt_group)?;
-
-        Ok(vfio_pci_device)
-    }
-
-    pub fn iommu_attached(&self) -> bool {
-        self.iommu_attached
+        }
     }
 
-    fn enable_intx(&mut self) -> Result<()> {
+    fn enable_intx(&mut self, vfio: &dyn Vfio) -> Result<()> {
         if let Some(intx) = &mut self.interrupt.intx {
             if !intx.enabled {
                 if let Some(eventfd) = intx.interrupt_source_group.notifier(0) {
-                    self.device
-                        .enable_irq(VFIO_PCI_INTX_IRQ_INDEX, vec![&eventfd])
+                    vfio.enable_irq(VFIO_PCI_INTX_IRQ_INDEX, vec![&eventfd])
                         .map_err(VfioPciError::EnableIntx)?;
 
                     intx.enabled = true;
@@ -374,10 +376,10 @@ impl VfioPciDevice {
         Ok(())
     }
 
-    fn disable_intx(&mut self) {
+    fn disable_intx(&mut self, vfio: &dyn Vfio) {
         if let Some(intx) = &mut self.interrupt.intx {
             if intx.enabled {
-                if let Err(e) = self.device.disable_irq(VFIO_PCI_INTX_IRQ_INDEX) {
+                if let Err(e) = vfio.disable_irq(VFIO_PCI_INTX_IRQ_INDEX) {
                     error!("Could not disable INTx: {}", e);
                 } else {
                     intx.enabled = false;
@@ -386,7 +388,7 @@ impl VfioPciDevice {
         }
     }
 
-    fn enable_msi(&self) -> Result<()> {
+    fn enable_msi(&self, vfio: &dyn Vfio) -> Result<()> {
         if let Some(msi) = &self.interrupt.msi {
             let mut irq_fds: Vec<EventFd> = Vec::new();
             for i in 0..msi.cfg.num_enabled_vectors() {
@@ -397,21 +399,20 @@ impl VfioPciDevice {
                 }
             }
 
-            self.device
-                .enable_msi(irq_fds.iter().collect())
+            vfio.enable_irq(VFIO_PCI_MSI_IRQ_INDEX, irq_fds.iter().collect())
                 .map_err(VfioPciError::EnableMsi)?;
         }
 
         Ok(())
     }
 
-    fn disable_msi(&self) {
-        if let Err(e) = self.device.disable_msi() {
+    fn disable_msi(&self, vfio: &dyn Vfio) {
+        if let Err(e) = vfio.disable_irq(VFIO_PCI_MSI_IRQ_INDEX) {
             error!("Could not disable MSI: {}", e);
         }
     }
 
-    fn enable_msix(&self) -> Result<()> {
+    fn enable_msix(&self, vfio: &dyn Vfio) -> Result<()> {
         if let Some(msix) = &self.interrupt.msix {
             let mut irq_fds: Vec<EventFd> = Vec::new();
             for i in 0..msix.bar.table_entries.len() {
@@ -422,30 +423,46 @@ impl VfioPciDevice {
                 }
             }
 
-            self.device
-                .enable_msix(irq_fds.iter().collect())
+            vfio.enable_irq(VFIO_PCI_MSIX_IRQ_INDEX, irq_fds.iter().collect())
                 .map_err(VfioPciError::EnableMsix)?;
         }
 
         Ok(())
     }
 
-    fn disable_msix(&self) {
-        if let Err(e) = self.device.disable_msix() {
+    fn disable_msix(&self, vfio: &dyn Vfio) {
+        if let Err(e) = vfio.disable_irq(VFIO_PCI_MSIX_IRQ_INDEX) {
             error!("Could not disable MSI-X: {}", e);
         }
     }
 
-    fn initialize_legacy_interrupt(
+    pub(crate) fn disable_interrupts(&mut self, vfio: &dyn Vfio) {
+        if let Some(msix) = &self.interrupt.msix {
+            if msix.bar.enabled() {
+                self.disable_msix(vfio);
+            }
+        }
+
+        if let Some(msi) = &self.interrupt.msi {
+            if msi.cfg.enabled() {
+                self.disable_msi(vfio);
+            }
+        }
+
+        if self.interrupt.intx_in_use() {
+            self.disable_intx(vfio);
+        }
+    }
+
+    pub(crate) fn initialize_legacy_interrupt(
         &mut self,
         legacy_interrupt_group: Option<Arc<Box<dyn InterruptSourceGroup>>>,
+        vfio: &dyn Vfio,
     ) -> Result<()> {
-        if let Some(irq_info) = self.device.get_irq_info(VFIO_PCI_INTX_IRQ_INDEX) {
-            if irq_info.count == 0 {
-                // A count of 0 means the INTx IRQ is not supported, therefore
-                // it shouldn't be initialized.
-                return Ok(());
-            }
+        // A count of 0 means the INTx IRQ is not supported, therefore
+        // it shouldn't be initialized.
+        if vfio.irq_count(VFIO_PCI_INTX_IRQ_INDEX) == 0 {
+            return Ok(());
         }
 
         if let Some(interrupt_source_group) = legacy_interrupt_group {
@@ -454,7 +471,7 @@ impl VfioPciDevice {
                 enabled: false,
             });
 
-            self.enable_intx()?;
+            self.enable_intx(vfio)?;
         }
 
         Ok(())
@@ -464,18 +481,13 @@ impl VfioPciDevice {
         &mut self,
         cap: u8,
         interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>>,
+        vfio: &dyn Vfio,
     ) {
-        let msg_ctl = self
-            .vfio_pci_configuration
-            .read_config_word((cap + 2).into());
+        let msg_ctl = vfio.read_config_word((cap + 2).into());
 
-        let table = self
-            .vfio_pci_configuration
-            .read_config_dword((cap + 4).into());
+        let table = vfio.read_config_dword((cap + 4).into());
 
-        let pba = self
-            .vfio_pci_configuration
-            .read_config_dword((cap + 8).into());
+        let pba = vfio.read_config_dword((cap + 8).into());
 
         let msix_cap = MsixCap {
             msg_ctl,
@@ -504,10 +516,9 @@ impl VfioPciDevice {
         &mut self,
         cap: u8,
         interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>>,
+        vfio: &dyn Vfio,
     ) {
-        let msg_ctl = self
-            .vfio_pci_configuration
-            .read_config_word((cap + 2).into());
+        let msg_ctl = vfio.read_config_word((cap + 2).into());
 
         let interrupt_source_group = interrupt_manager
             .create_group(MsiIrqGroupConfig {
@@ -525,58 +536,47 @@ impl VfioPciDevice {
         });
     }
 
-    fn parse_capabilities(
+    pub(crate) fn parse_capabilities(
         &mut self,
         interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>>,
+        vfio: &dyn Vfio,
     ) {
-        let mut cap_next = self
-            .vfio_pci_configuration
-            .read_config_byte(PCI_CONFIG_CAPABILITY_OFFSET);
+        let mut cap_next = vfio.read_config_byte(PCI_CONFIG_CAPABILITY_OFFSET);
 
         while cap_next != 0 {
-            let cap_id = self
-                .vfio_pci_configuration
-                .read_config_byte(cap_next.into());
+            let cap_id = vfio.read_config_byte(cap_next.into());
 
             match PciCapabilityId::from(cap_id) {
                 PciCapabilityId::MessageSignalledInterrupts => {
-                    if let Some(irq_info) = self.device.get_irq_info(VFIO_PCI_MSI_IRQ_INDEX) {
-                        if irq_info.count > 0 {
-                            // Parse capability only if the VFIO device
-                            // supports MSI.
-                            self.parse_msi_capabilities(cap_next, interrupt_manager);
-                        }
+                    // Parse capability only if the device supports MSI.
+                    if vfio.irq_count(VFIO_PCI_MSI_IRQ_INDEX) > 0 {
+                        self.parse_msi_capabilities(cap_next, interrupt_manager, vfio);
                     }
                 }
                 PciCapabilityId::MsiX => {
-                    if let Some(irq_info) = self.device.get_irq_info(VFIO_PCI_MSIX_IRQ_INDEX) {
-                        if irq_info.count > 0 {
-                            // Parse capability only if the VFIO device
-                            // supports MSI-X.
-                            self.parse_msix_capabilities(cap_next, interrupt_manager);
-                        }
+                    // Parse capability only if the device supports MSI-X.
+                    if vfio.irq_count(VFIO_PCI_MSIX_IRQ_INDEX) > 0 {
+                        self.parse_msix_capabilities(cap_next, interrupt_manager, vfio);
                     }
                 }
                 _ => {}
             };
 
-            cap_next = self
-                .vfio_pci_configuration
-                .read_config_byte((cap_next + 1).into());
+            cap_next = vfio.read_config_byte((cap_next + 1).into());
         }
     }
 
-    fn update_msi_capabilities(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+    fn update_msi_capabilities(&mut self, offset: u64, data: &[u8], vfio: &dyn Vfio) -> Result<()> {
         match self.interrupt.update_msi(offset, data) {
             Some(InterruptUpdateAction::EnableMsi) => {
                 // Disable INTx before we can enable MSI
-                self.disable_intx();
-                self.enable_msi()?;
+                self.disable_intx(vfio);
+                self.enable_msi(vfio)?;
             }
             Some(InterruptUpdateAction::DisableMsi) => {
                 // Fallback onto INTx when disabling MSI
-                self.disable_msi();
-                self.enable_intx()?;
+                self.disable_msi(vfio);
+                self.enable_intx(vfio)?;
             }
             _ => {}
         }
@@ -584,17 +584,22 @@ impl VfioPciDevice {
         Ok(())
     }
 
-    fn update_msix_capabilities(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+    fn update_msix_capabilities(
+        &mut self,
+        offset: u64,
+        data: &[u8],
+        vfio: &dyn Vfio,
+    ) -> Result<()> {
         match self.interrupt.update_msix(offset, data) {
             Some(InterruptUpdateAction::EnableMsix) => {
                 // Disable INTx before we can enable MSI-X
-                self.disable_intx();
-                self.enable_msix()?;
+                self.disable_intx(vfio);
+                self.enable_msix(vfio)?;
             }
             Some(InterruptUpdateAction::DisableMsix) => {
                 // Fallback onto INTx when disabling MSI-X
-                self.disable_msix();
-                self.enable_intx()?;
+                self.disable_msix(vfio);
+                self.enable_intx(vfio)?;
             }
             _ => {}
         }
@@ -613,20 +618,15 @@ impl VfioPciDevice {
         None
     }
 
-    /// Map MMIO regions into the guest, and avoid VM exits when the guest tries
-    /// to reach those regions.
-    ///
-    /// # Arguments
-    ///
-    /// * `vm` - The VM object. It is used to set the VFIO MMIO regions
-    ///          as user memory regions.
-    /// * `mem_slot` - The closure to return a memory slot.
-    pub fn map_mmio_regions<F>(&mut self, vm: &Arc<dyn hypervisor::Vm>, mem_slot: F) -> Result<()>
+    pub(crate) fn map_mmio_regions<F>(
+        &mut self,
+        vm: &Arc<dyn hypervisor::Vm>,
+        mem_slot: F,
+        vfio: &dyn Vfio,
+    ) -> Result<()>
     where
         F: Fn() -> u32,
     {
-        let fd = self.device.as_raw_fd();
-
         for region in self.mmio_regions.iter_mut() {
             // We want to skip the mapping of the BAR containing the MSI-X
             // table even if it is mappable. The reason is we need to trap
@@ -638,73 +638,73 @@ impl VfioPciDevice {
                 }
             }
 
-            let region_flags = self.device.get_region_flags(region.index);
-            if region_flags & VFIO_REGION_INFO_FLAG_MMAP != 0 {
-                let mut prot = 0;
-                if region_flags & VFIO_REGION_INFO_FLAG_READ != 0 {
-                    prot |= libc::PROT_READ;
-                }
-                if region_flags & VFIO_REGION_INFO_FLAG_WRITE != 0 {
-                    prot |= libc::PROT_WRITE;
-                }
-                let (mmap_offset, mmap_size) = self.device.get_region_mmap(region.index);
-                let offset = self.device.get_region_offset(region.index) + mmap_offset;
-
-                let host_addr = unsafe {
-                    libc::mmap(
-                        null_mut(),
-                        mmap_size as usize,
-                        prot,
-                        libc::MAP_SHARED,
-                        fd,
-                        offset as libc::off_t,
-                    )
-                };
-
-                if host_addr == libc::MAP_FAILED {
-                    error!(
-                        "Could not mmap regions, error:{}",
-                        io::Error::last_os_error()
-                    );
-                    continue;
-                }
-
-                let slot = mem_slot();
-                let mem_region = vm.make_user_memory_region(
-                    slot,
-                    region.start.raw_value() + mmap_offset,
-                    mmap_size as u64,
-                    host_addr as u64,
-                    false,
-                    false,
-                );
+            let region_mmap = match vfio.region_mmap(region.index) {
+                Some(region_mmap) => region_mmap,
+                None => continue,
+            };
 
-                vm.set_user_memory_region(mem_region)
-                    .map_err(|e| VfioPciError::MapRegionGuest(e.into()))?;
+            let mut prot = 0;
+            if region_mmap.flags & VFIO_REGION_INFO_FLAG_READ != 0 {
+                prot |= libc::PROT_READ;
+            }
+            if region_mmap.flags & VFIO_REGION_INFO_FLAG_WRITE != 0 {
+                prot |= libc::PROT_WRITE;
+            }
+            let mmap_size = region_mmap.size as usize;
+
+            let host_addr = unsafe {
+                libc::mmap(
+                    null_mut(),
+                    mmap_size,
+                    prot,
+                    libc::MAP_SHARED,
+                    region_mmap.fd,
+                    region_mmap.file_offset as libc::off_t,
+                )
+            };
 
-                // Update the region with memory mapped info.
-                region.mem_slot = Some(slot);
-                region.host_addr = Some(host_addr as u64);
-                region.mmap_size = Some(mmap_size as usize);
+            if host_addr == libc::MAP_FAILED {
+                error!(
+                    "Could not mmap regions, error:{}",
+                    io::Error::last_os_error()
+                );
+                continue;
             }
+
+            let slot = mem_slot();
+            let mem_region = vm.make_user_memory_region(
+                slot,
+                region.start.raw_value() + region_mmap.offset,
+                mmap_size as u64,
+                host_addr as u64,
+                false,
+                false,
+            );
+
+            vm.set_user_memory_region(mem_region)
+                .map_err(|e| VfioPciError::MapRegionGuest(e.into()))?;
+
+            // Update the region with memory mapped info.
+            region.mem_slot = Some(slot);
+            region.host_addr = Some(host_addr as u64);
+            region.mmap_offset = region_mmap.offset;
+            region.mmap_size = Some(mmap_size);
         }
 
         Ok(())
     }
 
-    pub fn unmap_mmio_regions(&mut self) {
+    pub(crate) fn unmap_mmio_regions(&mut self) {
         for region in self.mmio_regions.iter() {
             if let (Some(host_addr), Some(mmap_size), Some(mem_slot)) =
                 (region.host_addr, region.mmap_size, region.mem_slot)
             {
-                let (mmap_offset, _) = self.device.get_region_mmap(region.index);
-
                 // Remove region
                 let r = self.vm.make_user_memory_region(
                     mem_slot,
-                    region.start.raw_value() + mmap_offset,
+                    region.start.raw_value() + region.mmap_offset,
                     0,
-                    host_addr as u64,
+                    host_addr,
                     false,
                     false,
                 );
@@ -725,88 +725,14 @@ impl VfioPciDevice {
         }
     }
 
-    pub fn dma_map(&self, iova: u64, size: u64, user_addr: u64) -> Result<()> {
-        if !self.iommu_attached {
-            self.container
-                .vfio_dma_map(iova, size, user_addr)
-                .map_err(VfioPciError::DmaMap)?;
-        }
-
-        Ok(())
-    }
-
-    pub fn dma_unmap(&self, iova: u64, size: u64) -> Result<()> {
-        if !self.iommu_attached {
-            self.container
-                .vfio_dma_unmap(iova, size)
-                .map_err(VfioPciError::DmaUnmap)?;
-        }
-
-        Ok(())
-    }
-
-    pub fn mmio_regions(&self) -> Vec<MmioRegion> {
+    pub(crate) fn mmio_regions(&self) -> Vec<MmioRegion> {
         self.mmio_regions.clone()
     }
-}
-
-impl Drop for VfioPciDevice {
-    fn drop(&mut self) {
-        self.unmap_mmio_regions();
-
-        if let Some(msix) = &self.interrupt.msix {
-            if msix.bar.enabled() {
-                self.disable_msix();
-            }
-        }
-
-        if let Some(msi) = &self.interrupt.msi {
-            if msi.cfg.enabled() {
-                self.disable_msi();
-            }
-        }
-
-        if self.interrupt.intx_in_use() {
-            self.disable_intx();
-        }
-    }
-}
 
-impl BusDevice for VfioPciDevice {
-    fn read(&mut self, base: u64, offset: u64, data: &mut [u8]) {
-        self.read_bar(base, offset, data)
-    }
-
-    fn write(&mut self, base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
-        self.write_bar(base, offset, data)
-    }
-}
-
-// First BAR offset in the PCI config space.
-const PCI_CONFIG_BAR_OFFSET: u32 = 0x10;
-// Capability register offset in the PCI config space.
-const PCI_CONFIG_CAPABILITY_OFFSET: u32 = 0x34;
-// IO BAR when first BAR bit is 1.
-const PCI_CONFIG_IO_BAR: u32 = 0x1;
-// Memory BAR flags (lower 4 bits).
-const PCI_CONFIG_MEMORY_BAR_FLAG_MASK: u32 = 0xf;
-// 64-bit memory bar flag.
-const PCI_CONFIG_MEMORY_BAR_64BIT: u32 = 0x4;
-// PCI config register size (4 bytes).
-const PCI_CONFIG_REGISTER_SIZE: usize = 4;
-// Number of BARs for a PCI device
-const BAR_NUMS: usize = 6;
-// PCI Header Type register index
-const PCI_HEADER_TYPE_REG_INDEX: usize = 3;
-// First BAR register index
-const PCI_CONFIG_BAR0_INDEX: usize = 4;
-// PCI ROM expansion BAR register index
-const PCI_ROM_EXP_BAR_INDEX: usize = 12;
-
-impl PciDevice for VfioPciDevice {
-    fn allocate_bars(
+    pub(crate) fn allocate_bars(
         &mut self,
         allocator: &mut SystemAllocator,
+        vfio: &dyn Vfio,
     ) -> std::result::Result<Vec<(GuestAddress, GuestUsize, PciBarRegionType)>, PciDeviceError>
     {
         let mut ranges = Vec::new();
@@ -817,62 +743,27 @@ impl PciDevice for VfioPciDevice {
         // are going to allocate a guest address for each BAR and write
         // that new address back.
         while bar_id < VFIO_PCI_CONFIG_REGION_INDEX {
-            let mut lsb_size: u32 = 0xffff_ffff;
-            let mut msb_size = 0;
-            let mut region_size: u64;
-            let bar_addr: GuestAddress;
-
-            // Read the BAR size (Starts by all 1s to the BAR)
             let bar_offset = if bar_id == VFIO_PCI_ROM_REGION_INDEX {
                 (PCI_ROM_EXP_BAR_INDEX * 4) as u32
             } else {
                 PCI_CONFIG_BAR_OFFSET + bar_id * 4
             };
 
-            self.vfio_pci_configuration
-                .write_config_dword(lsb_size, bar_offset);
-            lsb_size = self.vfio_pci_configuration.read_config_dword(bar_offset);
-
-            // We've just read the BAR size back. Or at least its LSB.
-            let lsb_flag = lsb_size & PCI_CONFIG_MEMORY_BAR_FLAG_MASK;
-
-            if lsb_size == 0 {
+            let (bar_reg, region_size) = vfio.bar_info(bar_id, bar_offset);
+            if region_size == 0 {
                 bar_id += 1;
                 continue;
             }
 
-            // Is this an IO BAR?
-            let io_bar = if bar_id != VFIO_PCI_ROM_REGION_INDEX {
-                matches!(lsb_flag & PCI_CONFIG_IO_BAR, PCI_CONFIG_IO_BAR)
-            } else {
-                false
-            };
-
-            // Is this a 64-bit BAR?
-            let is_64bit_bar = if bar_id != VFIO_PCI_ROM_REGION_INDEX {
-                matches!(
-                    lsb_flag & PCI_CONFIG_MEMORY_BAR_64BIT,
-                    PCI_CONFIG_MEMORY_BAR_64BIT
-                )
-            } else {
-                false
-            };
+            let lsb_flag = bar_reg & PCI_CONFIG_MEMORY_BAR_FLAG_MASK;
+            let is_64bit_bar = is_64bit_bar(bar_id, lsb_flag);
 
-            // By default, the region type is 32 bits memory BAR.
-            let mut region_type = PciBarRegionType::Memory32BitRegion;
-
-            if io_bar {
+            let region_type;
+            let bar_addr;
+            if is_io_bar(bar_id, lsb_flag) {
                 #[cfg(target_arch = "x86_64")]
                 {
-                    // IO BAR
                     region_type = PciBarRegionType::IoRegion;
-
-                    // Clear first bit.
-                    lsb_size &= 0xffff_fffc;
-
-                    // Find the first bit that's set to 1.
-                    let first_bit = lsb_size.trailing_zeros();
-                    region_size = 2u64.pow(first_bit);
                     // We need to allocate a guest PIO address range for that BAR.
                     // The address needs to be 4 bytes aligned.
                     bar_addr = allocator
@@ -881,43 +772,18 @@ impl PciDevice for VfioPciDevice {
                 }
                 #[cfg(target_arch = "aarch64")]
                 unimplemented!()
+            } else if is_64bit_bar {
+                region_type = PciBarRegionType::Memory64BitRegion;
+                // BAR allocation needs to be naturally aligned
+                bar_addr = allocator
+                    .allocate_mmio_addresses(None, region_size, Some(region_size))
+                    .ok_or(PciDeviceError::IoAllocationFailed(region_size))?;
             } else {
-                if is_64bit_bar {
-                    // 64 bits Memory BAR
-                    region_type = PciBarRegionType::Memory64BitRegion;
-
-                    msb_size = 0xffff_ffff;
-                    let msb_bar_offset: u32 = PCI_CONFIG_BAR_OFFSET + (bar_id + 1) * 4;
-
-                    self.vfio_pci_configuration
-                        .write_config_dword(msb_size, msb_bar_offset);
-
-                    msb_size = self
-                        .vfio_pci_configuration
-                        .read_config_dword(msb_bar_offset);
-                }
-
-                // Clear the first four bytes from our LSB.
-                lsb_size &= 0xffff_fff0;
-
-                region_size = u64::from(msb_size);
-                region_size <<= 32;
-                region_size |= u64::from(lsb_size);
-
-                // Find the first that's set to 1.
-                let first_bit = region_size.trailing_zeros();
-                region_size = 2u64.pow(first_bit);
-
+                region_type = PciBarRegionType::Memory32BitRegion;
                 // BAR allocation needs to be naturally aligned
-                if is_64bit_bar {
-                    bar_addr = allocator
-                        .allocate_mmio_addresses(None, region_size, Some(region_size))
-                        .ok_or(PciDeviceError::IoAllocationFailed(region_size))?;
-                } else {
-                    bar_addr = allocator
-                        .allocate_mmio_hole_addresses(None, region_size, Some(region_size))
-                        .ok_or(PciDeviceError::IoAllocationFailed(region_size))?;
-                }
+                bar_addr = allocator
+                    .allocate_mmio_hole_addresses(None, region_size, Some(region_size))
+                    .ok_or(PciDeviceError::IoAllocationFailed(region_size))?;
             }
 
             let reg_idx = if bar_id == VFIO_PCI_ROM_REGION_INDEX {
@@ -951,6 +817,7 @@ impl PciDevice for VfioPciDevice {
                 index: bar_id as u32,
                 mem_slot: None,
                 host_addr: None,
+                mmap_offset: 0,
                 mmap_size: None,
             });
 
@@ -963,7 +830,7 @@ impl PciDevice for VfioPciDevice {
         Ok(ranges)
     }
 
-    fn free_bars(
+    pub(crate) fn free_bars(
         &mut self,
         allocator: &mut SystemAllocator,
     ) -> std::result::Result<(), PciDeviceError> {
@@ -986,15 +853,16 @@ impl PciDevice for VfioPciDevice {
         Ok(())
     }
 
-    fn write_config_register(
+    pub(crate) fn write_config_register(
         &mut self,
         reg_idx: usize,
         offset: u64,
         data: &[u8],
+        vfio: &dyn Vfio,
     ) -> Option<Arc<Barrier>> {
         // When the guest wants to write to a BAR, we trap it into
         // our local configuration space. We're not reprogramming
-        // VFIO device.
+        // the device.
         if (PCI_CONFIG_BAR0_INDEX..PCI_CONFIG_BAR0_INDEX + BAR_NUMS).contains(&reg_idx)
             || reg_idx == PCI_ROM_EXP_BAR_INDEX
         {
@@ -1011,17 +879,17 @@ impl PciDevice for VfioPciDevice {
         // If the MSI or MSI-X capabilities are accessed, we need to
         // update our local cache accordingly.
         // Depending on how the capabilities are modified, this could
-        // trigger a VFIO MSI or MSI-X toggle.
+        // trigger a MSI or MSI-X toggle.
         if let Some((cap_id, cap_base)) = self.interrupt.accessed(reg) {
             let cap_offset: u64 = reg - cap_base + offset;
             match cap_id {
                 PciCapabilityId::MessageSignalledInterrupts => {
-                    if let Err(e) = self.update_msi_capabilities(cap_offset, data) {
+                    if let Err(e) = self.update_msi_capabilities(cap_offset, data, vfio) {
                         error!("Could not update MSI capabilities: {}", e);
                     }
                 }
                 PciCapabilityId::MsiX => {
-                    if let Err(e) = self.update_msix_capabilities(cap_offset, data) {
+                    if let Err(e) = self.update_msix_capabilities(cap_offset, data, vfio) {
                         error!("Could not update MSI-X capabilities: {}", e);
                     }
                 }
@@ -1037,16 +905,15 @@ impl PciDevice for VfioPciDevice {
         // enabling this bit, we first need to enable the MSI interrupts with
         // VFIO through VFIO_DEVICE_SET_IRQS ioctl, and only after we can write
         // to the device region to update the MSI Enable bit.
-        self.device
-            .region_write(VFIO_PCI_CONFIG_REGION_INDEX, data, reg + offset);
+        vfio.region_write(VFIO_PCI_CONFIG_REGION_INDEX, reg + offset, data);
 
         None
     }
 
-    fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+    pub(crate) fn read_config_register(&mut self, reg_idx: usize, vfio: &dyn Vfio) -> u32 {
         // When reading the BARs, we trap it and return what comes
         // from our local configuration space. We want the guest to
-        // use that and not the VFIO device BARs as it does not map
+        // use that and not the device BARs as it does not map
         // with the guest address space.
         if (PCI_CONFIG_BAR0_INDEX..PCI_CONFIG_BAR0_INDEX + BAR_NUMS).contains(&reg_idx)
             || reg_idx == PCI_ROM_EXP_BAR_INDEX
@@ -1063,13 +930,11 @@ impl PciDevice for VfioPciDevice {
             0xffff_ffff
         };
 
-        // The config register read comes from the VFIO device itself.
-        self.vfio_pci_configuration
-            .read_config_dword((reg_idx * 4) as u32)
-            & mask
+        // The config register read comes from the device itself.
+        vfio.read_config_dword((reg_idx * 4) as u32) & mask
     }
 
-    fn detect_bar_reprogramming(
+    pub(crate) fn detect_bar_reprogramming(
         &mut self,
         reg_idx: usize,
         data: &[u8],
@@ -1077,7 +942,7 @@ impl PciDevice for VfioPciDevice {
         self.configuration.detect_bar_reprogramming(reg_idx, data)
     }
 
-    fn read_bar(&mut self, base: u64, offset: u64, data: &mut [u8]) {
+    pub(crate) fn read_bar(&mut self, base: u64, offset: u64, data: &mut [u8], vfio: &dyn Vfio) {
         let addr = base + offset;
         if let Some(region) = self.find_region(addr) {
             let offset = addr - region.start.raw_value();
@@ -1085,7 +950,7 @@ impl PciDevice for VfioPciDevice {
             if self.interrupt.msix_table_accessed(region.index, offset) {
                 self.interrupt.msix_read_table(offset, data);
             } else {
-                self.device.region_read(region.index, data, offset);
+                vfio.region_read(region.index, offset, data);
             }
         }
 
@@ -1093,13 +958,19 @@ impl PciDevice for VfioPciDevice {
         // The guest reading from the BAR potentially means the interrupt has
         // been received and can be acknowledged.
         if self.interrupt.intx_in_use() {
-            if let Err(e) = self.device.unmask_irq(VFIO_PCI_INTX_IRQ_INDEX) {
+            if let Err(e) = vfio.unmask_irq(VFIO_PCI_INTX_IRQ_INDEX) {
                 error!("Failed unmasking INTx IRQ: {}", e);
             }
         }
     }
 
-    fn write_bar(&mut self, base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+    pub(crate) fn write_bar(
+        &mut self,
+        base: u64,
+        offset: u64,
+        data: &[u8],
+        vfio: &dyn Vfio,
+    ) -> Option<Arc<Barrier>> {
         let addr = base + offset;
         if let Some(region) = self.find_region(addr) {
             let offset = addr - region.start.raw_value();
@@ -1108,7 +979,7 @@ impl PciDevice for VfioPciDevice {
             if self.interrupt.msix_table_accessed(region.index, offset) {
                 self.interrupt.msix_write_table(offset, data);
             } else {
-                self.device.region_write(region.index, data, offset);
+                vfio.region_write(region.index, offset, data);
             }
         }
 
@@ -1116,7 +987,7 @@ impl PciDevice for VfioPciDevice {
         // The guest writing to the BAR potentially means the interrupt has
         // been received and can be acknowledged.
         if self.interrupt.intx_in_use() {
-            if let Err(e) = self.device.unmask_irq(VFIO_PCI_INTX_IRQ_INDEX) {
+            if let Err(e) = vfio.unmask_irq(VFIO_PCI_INTX_IRQ_INDEX) {
                 error!("Failed unmasking INTx IRQ: {}", e);
             }
         }
@@ -1124,49 +995,339 @@ impl PciDevice for VfioPciDevice {
         None
     }
 
-    fn move_bar(&mut self, old_base: u64, new_base: u64) -> result::Result<(), io::Error> {
+    pub(crate) fn move_bar(
+        &mut self,
+        old_base: u64,
+        new_base: u64,
+    ) -> result::Result<(), io::Error> {
         for region in self.mmio_regions.iter_mut() {
             if region.start.raw_value() == old_base {
                 region.start = GuestAddress(new_base);
 
-                if let Some(mem_slot) = region.mem_slot {
-                    if let Some(host_addr) = region.host_addr {
-                        let (mmap_offset, mmap_size) = self.device.get_region_mmap(region.index);
-
-                        // Remove old region
-                        let old_mem_region = self.vm.make_user_memory_region(
-                            mem_slot,
-                            old_base + mmap_offset,
-                            0,
-                            host_addr as u64,
-                            false,
-                            false,
-                        );
-
-                        self.vm
-                            .set_user_memory_region(old_mem_region)
-                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-                        // Insert new region
-                        let new_mem_region = self.vm.make_user_memory_region(
-                            mem_slot,
-                            new_base + mmap_offset,
-                            mmap_size as u64,
-                            host_addr as u64,
-                            false,
-                            false,
-                        );
-
-                        self.vm
-                            .set_user_memory_region(new_mem_region)
-                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-                    }
+                if let (Some(mem_slot), Some(host_addr), Some(mmap_size)) =
+                    (region.mem_slot, region.host_addr, region.mmap_size)
+                {
+                    // Remove old region
+                    let old_mem_region = self.vm.make_user_memory_region(
+                        mem_slot,
+                        old_base + region.mmap_offset,
+                        0,
+                        host_addr,
+                        false,
+                        false,
+                    );
+
+                    self.vm
+                        .set_user_memory_region(old_mem_region)
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+                    // Insert new region
+                    let new_mem_region = self.vm.make_user_memory_region(
+                        mem_slot,
+                        new_base + region.mmap_offset,
+                        mmap_size as u64,
+                        host_addr,
+                        false,
+                        false,
+                    );
+
+                    self.vm
+                        .set_user_memory_region(new_mem_region)
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
                 }
             }
         }
 
         Ok(())
     }
+}
+
+struct VfioDeviceWrapper {
+    device: Arc<VfioDevice>,
+}
+
+impl VfioDeviceWrapper {
+    fn new(device: Arc<VfioDevice>) -> Self {
+        VfioDeviceWrapper { device }
+    }
+}
+
+impl Vfio for VfioDeviceWrapper {
+    fn region_read(&self, index: u32, offset: u64, data: &mut [u8]) {
+        self.device.region_read(index, data, offset)
+    }
+
+    fn region_write(&self, index: u32, offset: u64, data: &[u8]) {
+        self.device.region_write(index, data, offset)
+    }
+
+    fn bar_info(&self, bar_id: u32, bar_offset: u32) -> (u32, u64) {
+        // Read the BAR size (Starts by all 1s to the BAR)
+        self.write_config_dword(0xffff_ffff, bar_offset);
+        let mut lsb_size = self.read_config_dword(bar_offset);
+
+        // We've just read the BAR size back. Or at least its LSB.
+        let lsb_flag = lsb_size & PCI_CONFIG_MEMORY_BAR_FLAG_MASK;
+
+        if lsb_size == 0 {
+            return (lsb_flag, 0);
+        }
+
+        if is_io_bar(bar_id, lsb_flag) {
+            // Clear first bit.
+            lsb_size &= 0xffff_fffc;
+
+            // Find the first bit that's set to 1.
+            let first_bit = lsb_size.trailing_zeros();
+            return (lsb_flag, 2u64.pow(first_bit));
+        }
+
+        let mut msb_size = 0;
+        if is_64bit_bar(bar_id, lsb_flag) {
+            msb_size = 0xffff_ffff;
+            let msb_bar_offset: u32 = PCI_CONFIG_BAR_OFFSET + (bar_id + 1) * 4;
+
+            self.write_config_dword(msb_size, msb_bar_offset);
+
+            msb_size = self.read_config_dword(msb_bar_offset);
+        }
+
+        // Clear the first four bytes from our LSB.
+        lsb_size &= 0xffff_fff0;
+
+        let mut region_size = u64::from(msb_size);
+        region_size <<= 32;
+        region_size |= u64::from(lsb_size);
+
+        // Find the first that's set to 1.
+        let first_bit = region_size.trailing_zeros();
+        (lsb_flag, 2u64.pow(first_bit))
+    }
+
+    fn region_mmap(&self, index: u32) -> Option<VfioRegionMmap> {
+        let flags = self.device.get_region_flags(index);
+        if flags & VFIO_REGION_INFO_FLAG_MMAP == 0 {
+            return None;
+        }
+
+        let (mmap_offset, mmap_size) = self.device.get_region_mmap(index);
+
+        Some(VfioRegionMmap {
+            flags,
+            fd: self.device.as_raw_fd(),
+            file_offset: self.device.get_region_offset(index) + mmap_offset,
+            offset: mmap_offset,
+            size: mmap_size as u64,
+        })
+    }
+
+    fn irq_count(&self, irq_index: u32) -> u32 {
+        self.device
+            .get_irq_info(irq_index)
+            .map(|irq_info| irq_info.count)
+            .unwrap_or(0)
+    }
+
+    fn enable_irq(&self, irq_index: u32, event_fds: Vec<&EventFd>) -> anyhow::Result<()> {
+        self.device
+            .enable_irq(irq_index, event_fds)
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    fn disable_irq(&self, irq_index: u32) -> anyhow::Result<()> {
+        self.device
+            .disable_irq(irq_index)
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    fn unmask_irq(&self, irq_index: u32) -> anyhow::Result<()> {
+        self.device
+            .unmask_irq(irq_index)
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+/// VfioPciDevice represents a VFIO PCI device.
+/// This structure implements the BusDevice and PciDevice traits.
+///
+/// A VfioPciDevice is bound to a VfioDevice and is also a PCI device.
+/// The VMM creates a VfioDevice, then assigns it to a VfioPciDevice,
+/// which then gets added to the PCI bus.
+pub struct VfioPciDevice {
+    container: Arc<VfioContainer>,
+    vfio_wrapper: VfioDeviceWrapper,
+    common: VfioCommon,
+    iommu_attached: bool,
+}
+
+impl VfioPciDevice {
+    /// Constructs a new Vfio Pci device for the given Vfio device
+    pub fn new(
+        vm: &Arc<dyn hypervisor::Vm>,
+        device: VfioDevice,
+        container: Arc<VfioContainer>,
+        msi_interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>>,
+        legacy_interrupt_group: Option<Arc<Box<dyn InterruptSourceGroup>>>,
+        iommu_attached: bool,
+    ) -> Result<Self> {
+        let device = Arc::new(device);
+        device.reset();
+
+        let vfio_wrapper = VfioDeviceWrapper::new(device);
+
+        let mut common = VfioCommon::new(vm);
+
+        common.parse_capabilities(msi_interrupt_manager, &vfio_wrapper);
+
+        common.initialize_legacy_interrupt(legacy_interrupt_group, &vfio_wrapper)?;
+
+        Ok(VfioPciDevice {
+            container,
+            vfio_wrapper,
+            common,
+            iommu_attached,
+        })
+    }
+
+    pub fn iommu_attached(&self) -> bool {
+        self.iommu_attached
+    }
+
+    /// Map MMIO regions into the guest, and avoid VM exits when the guest tries
+    /// to reach those regions.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm` - The VM object. It is used to set the VFIO MMIO regions
+    ///          as user memory regions.
+    /// * `mem_slot` - The closure to return a memory slot.
+    pub fn map_mmio_regions<F>(&mut self, vm: &Arc<dyn hypervisor::Vm>, mem_slot: F) -> Result<()>
+    where
+        F: Fn() -> u32,
+    {
+        self.common
+            .map_mmio_regions(vm, mem_slot, &self.vfio_wrapper)
+    }
+
+    pub fn unmap_mmio_regions(&mut self) {
+        self.common.unmap_mmio_regions()
+    }
+
+    pub fn dma_map(&self, iova: u64, size: u64, user_addr: u64) -> Result<()> {
+        if !self.iommu_attached {
+            self.container
+                .vfio_dma_map(iova, size, user_addr)
+                .map_err(VfioPciError::DmaMap)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn dma_unmap(&self, iova: u64, size: u64) -> Result<()> {
+        if !self.iommu_attached {
+            self.container
+                .vfio_dma_unmap(iova, size)
+                .map_err(VfioPciError::DmaUnmap)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn mmio_regions(&self) -> Vec<MmioRegion> {
+        self.common.mmio_regions()
+    }
+}
+
+impl Drop for VfioPciDevice {
+    fn drop(&mut self) {
+        self.unmap_mmio_regions();
+
+        self.common.disable_interrupts(&self.vfio_wrapper);
+    }
+}
+
+impl BusDevice for VfioPciDevice {
+    fn read(&mut self, base: u64, offset: u64, data: &mut [u8]) {
+        self.read_bar(base, offset, data)
+    }
+
+    fn write(&mut self, base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        self.write_bar(base, offset, data)
+    }
+}
+
+// First BAR offset in the PCI config space.
+const PCI_CONFIG_BAR_OFFSET: u32 = 0x10;
+// Capability register offset in the PCI config space.
+const PCI_CONFIG_CAPABILITY_OFFSET: u32 = 0x34;
+// IO BAR when first BAR bit is 1.
+const PCI_CONFIG_IO_BAR: u32 = 0x1;
+// Memory BAR flags (lower 4 bits).
+const PCI_CONFIG_MEMORY_BAR_FLAG_MASK: u32 = 0xf;
+// 64-bit memory bar flag.
+const PCI_CONFIG_MEMORY_BAR_64BIT: u32 = 0x4;
+// PCI config register size (4 bytes).
+const PCI_CONFIG_REGISTER_SIZE: usize = 4;
+// Number of BARs for a PCI device
+const BAR_NUMS: usize = 6;
+// PCI Header Type register index
+const PCI_HEADER_TYPE_REG_INDEX: usize = 3;
+// First BAR register index
+const PCI_CONFIG_BAR0_INDEX: usize = 4;
+// PCI ROM expansion BAR register index
+const PCI_ROM_EXP_BAR_INDEX: usize = 12;
+
+impl PciDevice for VfioPciDevice {
+    fn allocate_bars(
+        &mut self,
+        allocator: &mut SystemAllocator,
+    ) -> std::result::Result<Vec<(GuestAddress, GuestUsize, PciBarRegionType)>, PciDeviceError>
+    {
+        self.common.allocate_bars(allocator, &self.vfio_wrapper)
+    }
+
+    fn free_bars(
+        &mut self,
+        allocator: &mut SystemAllocator,
+    ) -> std::result::Result<(), PciDeviceError> {
+        self.common.free_bars(allocator)
+    }
+
+    fn write_config_register(
+        &mut self,
+        reg_idx: usize,
+        offset: u64,
+        data: &[u8],
+    ) -> Option<Arc<Barrier>> {
+        self.common
+            .write_config_register(reg_idx, offset, data, &self.vfio_wrapper)
+    }
+
+    fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+        self.common
+            .read_config_register(reg_idx, &self.vfio_wrapper)
+    }
+
+    fn detect_bar_reprogramming(
+        &mut self,
+        reg_idx: usize,
+        data: &[u8],
+    ) -> Option<BarReprogrammingParams> {
+        self.common.detect_bar_reprogramming(reg_idx, data)
+    }
+
+    fn read_bar(&mut self, base: u64, offset: u64, data: &mut [u8]) {
+        self.common.read_bar(base, offset, data, &self.vfio_wrapper)
+    }
+
+    fn write_bar(&mut self, base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        self.common
+            .write_bar(base, offset, data, &self.vfio_wrapper)
+    }
+
+    fn move_bar(&mut self, old_base: u64, new_base: u64) -> result::Result<(), io::Error> {
+        self.common.move_bar(old_base, new_base)
+    }
 
     fn as_any(&mut self) -> &mut dyn Any {
         self
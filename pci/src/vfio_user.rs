@@ -0,0 +1,324 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+//
+
+use crate::vfio::{MmioRegion, Vfio, VfioCommon, VfioPciError, VfioRegionMmap};
+use crate::{BarReprogrammingParams, PciBarRegionType, PciDevice, PciDeviceError};
+use std::any::Any;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Barrier, Mutex};
+use std::{fmt, io, result};
+use vfio_bindings::bindings::vfio::*;
+use vfio_user::Client;
+use vm_allocator::SystemAllocator;
+use vm_device::interrupt::{InterruptManager, InterruptSourceGroup, MsiIrqGroupConfig};
+use vm_device::BusDevice;
+use vm_memory::{Address, GuestAddress, GuestMemoryRegion, GuestRegionMmap, GuestUsize};
+use vmm_sys_util::eventfd::EventFd;
+
+#[derive(Debug)]
+pub enum VfioUserPciError {
+    DmaMap(vfio_user::Error),
+    DmaUnmap(vfio_user::Error),
+    InitializeLegacyInterrupt(VfioPciError),
+    MapRegions(VfioPciError),
+    MissingFileOffset,
+    Reset(vfio_user::Error),
+}
+pub type Result<T> = std::result::Result<T, VfioUserPciError>;
+
+impl fmt::Display for VfioUserPciError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VfioUserPciError::DmaMap(e) => write!(f, "failed to DMA map: {}", e),
+            VfioUserPciError::DmaUnmap(e) => write!(f, "failed to DMA unmap: {}", e),
+            VfioUserPciError::InitializeLegacyInterrupt(e) => {
+                write!(f, "failed to initialize legacy interrupt: {}", e)
+            }
+            VfioUserPciError::MapRegions(e) => write!(f, "failed to map regions: {}", e),
+            VfioUserPciError::MissingFileOffset => {
+                write!(f, "guest memory region is not backed by a file")
+            }
+            VfioUserPciError::Reset(e) => write!(f, "failed to reset device: {}", e),
+        }
+    }
+}
+
+struct VfioUserClientWrapper {
+    client: Arc<Mutex<Client>>,
+}
+
+impl Vfio for VfioUserClientWrapper {
+    fn region_read(&self, index: u32, offset: u64, data: &mut [u8]) {
+        if let Err(e) = self.client.lock().unwrap().region_read(index, offset, data) {
+            error!("Failed reading region {}: {}", index, e);
+        }
+    }
+
+    fn region_write(&self, index: u32, offset: u64, data: &[u8]) {
+        if let Err(e) = self
+            .client
+            .lock()
+            .unwrap()
+            .region_write(index, offset, data)
+        {
+            error!("Failed writing region {}: {}", index, e);
+        }
+    }
+
+    fn bar_info(&self, bar_id: u32, bar_offset: u32) -> (u32, u64) {
+        // Unlike with VFIO, the size of each BAR is directly provided by
+        // the region information, which means there is no need to probe
+        // the BARs through the config space. Only the flags of each BAR
+        // are retrieved from there.
+        let region_size = self
+            .client
+            .lock()
+            .unwrap()
+            .region(bar_id)
+            .map(|region| region.size)
+            .unwrap_or(0);
+
+        if region_size == 0 {
+            return (0, 0);
+        }
+
+        // BARs must be a power of 2 in size.
+        (
+            self.read_config_dword(bar_offset),
+            region_size.next_power_of_two(),
+        )
+    }
+
+    fn region_mmap(&self, index: u32) -> Option<VfioRegionMmap> {
+        let client = self.client.lock().unwrap();
+        let region = client.region(index)?;
+
+        if region.flags & VFIO_REGION_INFO_FLAG_MMAP == 0 {
+            return None;
+        }
+
+        // The file descriptor remains owned by the client, which outlives
+        // any mapping made from it.
+        let file_offset = region.file_offset.as_ref()?;
+        Some(VfioRegionMmap {
+            flags: region.flags,
+            fd: file_offset.file().as_raw_fd(),
+            file_offset: file_offset.start(),
+            offset: 0,
+            size: region.size,
+        })
+    }
+
+    fn irq_count(&self, irq_index: u32) -> u32 {
+        self.client
+            .lock()
+            .unwrap()
+            .irq_info(irq_index)
+            .map(|irq_info| irq_info.count)
+            .unwrap_or(0)
+    }
+
+    fn enable_irq(&self, irq_index: u32, event_fds: Vec<&EventFd>) -> anyhow::Result<()> {
+        let fds: Vec<RawFd> = event_fds.iter().map(|e| e.as_raw_fd()).collect();
+        self.client
+            .lock()
+            .unwrap()
+            .enable_irqs(irq_index, &fds)
+            .map_err(|e| e.into())
+    }
+
+    fn disable_irq(&self, irq_index: u32) -> anyhow::Result<()> {
+        self.client
+            .lock()
+            .unwrap()
+            .disable_irqs(irq_index)
+            .map_err(|e| e.into())
+    }
+
+    fn unmask_irq(&self, irq_index: u32) -> anyhow::Result<()> {
+        self.client
+            .lock()
+            .unwrap()
+            .unmask_irq(irq_index, 0)
+            .map_err(|e| e.into())
+    }
+}
+
+/// VfioUserPciDevice represents a PCI device emulated by a separate
+/// process, and reached through the vfio-user protocol.
+/// This structure implements the BusDevice and PciDevice traits.
+///
+/// Accesses to the PCI configuration space and to the BARs are forwarded
+/// to the remote process, except for the BARs it lets us map directly into
+/// the guest address space.
+pub struct VfioUserPciDevice {
+    client: Arc<Mutex<Client>>,
+    vfio_wrapper: VfioUserClientWrapper,
+    common: VfioCommon,
+}
+
+impl VfioUserPciDevice {
+    /// Constructs a new vfio-user PCI device for the given client
+    pub fn new(
+        vm: &Arc<dyn hypervisor::Vm>,
+        client: Arc<Mutex<Client>>,
+        msi_interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>>,
+        legacy_interrupt_group: Option<Arc<Box<dyn InterruptSourceGroup>>>,
+    ) -> Result<Self> {
+        {
+            let mut client = client.lock().unwrap();
+            if client.resettable() {
+                client.reset().map_err(VfioUserPciError::Reset)?;
+            }
+        }
+
+        let vfio_wrapper = VfioUserClientWrapper {
+            client: client.clone(),
+        };
+
+        let mut common = VfioCommon::new(vm);
+
+        common.parse_capabilities(msi_interrupt_manager, &vfio_wrapper);
+
+        common
+            .initialize_legacy_interrupt(legacy_interrupt_group, &vfio_wrapper)
+            .map_err(VfioUserPciError::InitializeLegacyInterrupt)?;
+
+        Ok(VfioUserPciDevice {
+            client,
+            vfio_wrapper,
+            common,
+        })
+    }
+
+    /// Map the regions the server shared a file descriptor for into the
+    /// guest, and avoid VM exits when the guest tries to reach those regions.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm` - The VM object. It is used to set the mappable regions
+    ///          as user memory regions.
+    /// * `mem_slot` - The closure to return a memory slot.
+    pub fn map_mmio_regions<F>(&mut self, vm: &Arc<dyn hypervisor::Vm>, mem_slot: F) -> Result<()>
+    where
+        F: Fn() -> u32,
+    {
+        self.common
+            .map_mmio_regions(vm, mem_slot, &self.vfio_wrapper)
+            .map_err(VfioUserPciError::MapRegions)
+    }
+
+    pub fn unmap_mmio_regions(&mut self) {
+        self.common.unmap_mmio_regions()
+    }
+
+    /// Share the guest memory region with the remote device, so that it
+    /// can perform DMA to and from it.
+    pub fn dma_map(&self, region: &GuestRegionMmap) -> Result<()> {
+        let file_offset = region
+            .file_offset()
+            .ok_or(VfioUserPciError::MissingFileOffset)?;
+
+        self.client
+            .lock()
+            .unwrap()
+            .dma_map(
+                file_offset.start(),
+                region.start_addr().raw_value(),
+                region.len(),
+                file_offset.file().as_raw_fd(),
+            )
+            .map_err(VfioUserPciError::DmaMap)
+    }
+
+    pub fn dma_unmap(&self, region: &GuestRegionMmap) -> Result<()> {
+        self.client
+            .lock()
+            .unwrap()
+            .dma_unmap(region.start_addr().raw_value(), region.len())
+            .map_err(VfioUserPciError::DmaUnmap)
+    }
+
+    pub fn mmio_regions(&self) -> Vec<MmioRegion> {
+        self.common.mmio_regions()
+    }
+}
+
+impl Drop for VfioUserPciDevice {
+    fn drop(&mut self) {
+        self.unmap_mmio_regions();
+
+        self.common.disable_interrupts(&self.vfio_wrapper);
+
+        self.client.lock().unwrap().shutdown();
+    }
+}
+
+impl BusDevice for VfioUserPciDevice {
+    fn read(&mut self, base: u64, offset: u64, data: &mut [u8]) {
+        self.read_bar(base, offset, data)
+    }
+
+    fn write(&mut self, base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        self.write_bar(base, offset, data)
+    }
+}
+
+impl PciDevice for VfioUserPciDevice {
+    fn allocate_bars(
+        &mut self,
+        allocator: &mut SystemAllocator,
+    ) -> std::result::Result<Vec<(GuestAddress, GuestUsize, PciBarRegionType)>, PciDeviceError>
+    {
+        self.common.allocate_bars(allocator, &self.vfio_wrapper)
+    }
+
+    fn free_bars(
+        &mut self,
+        allocator: &mut SystemAllocator,
+    ) -> std::result::Result<(), PciDeviceError> {
+        self.common.free_bars(allocator)
+    }
+
+    fn write_config_register(
+        &mut self,
+        reg_idx: usize,
+        offset: u64,
+        data: &[u8],
+    ) -> Option<Arc<Barrier>> {
+        self.common
+            .write_config_register(reg_idx, offset, data, &self.vfio_wrapper)
+    }
+
+    fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+        self.common
+            .read_config_register(reg_idx, &self.vfio_wrapper)
+    }
+
+    fn detect_bar_reprogramming(
+        &mut self,
+        reg_idx: usize,
+        data: &[u8],
+    ) -> Option<BarReprogrammingParams> {
+        self.common.detect_bar_reprogramming(reg_idx, data)
+    }
+
+    fn read_bar(&mut self, base: u64, offset: u64, data: &mut [u8]) {
+        self.common.read_bar(base, offset, data, &self.vfio_wrapper)
+    }
+
+    fn write_bar(&mut self, base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        self.common
+            .write_bar(base, offset, data, &self.vfio_wrapper)
+    }
+
+    fn move_bar(&mut self, old_base: u64, new_base: u64) -> result::Result<(), io::Error> {
+        self.common.move_bar(old_base, new_base)
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -16,6 +16,8 @@ time which cargo-audit || cargo install cargo-audit
 time cargo clippy --all-targets --all-features -- -D warnings
 time cargo rustc --bin cloud-hypervisor -- -D warnings
 time cargo rustc -p vhost_user_net --bin vhost_user_net -- -D warnings
+time cargo rustc -p vhost_user_console --bin vhost_user_console -- -D warnings
+time cargo rustc -p vhost_user_rng --bin vhost_user_rng -- -D warnings
 time cargo test
 time cargo audit
 time cargo clippy --all-targets --no-default-features --features "acpi,$hypervisor" -- -D warnings
@@ -6,28 +6,37 @@
 #[macro_use(crate_authors)]
 extern crate clap;
 
-use api_client::simple_api_command;
 use api_client::Error as ApiClientError;
+use api_client::{simple_api_command, simple_api_full_command, simple_api_full_command_with_fds};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use option_parser::{ByteSized, ByteSizedParseError};
 use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
 use std::process;
 
 #[derive(Debug)]
 enum Error {
     Connect(std::io::Error),
+    OpenFile(PathBuf, std::io::Error),
+    InvalidFd(std::num::ParseIntError),
     ApiClient(ApiClientError),
     InvalidCpuCount(std::num::ParseIntError),
     InvalidMemorySize(ByteSizedParseError),
     InvalidBalloonSize(ByteSizedParseError),
+    InvalidCaptureSize(ByteSizedParseError),
     AddDeviceConfig(vmm::config::Error),
     AddDiskConfig(vmm::config::Error),
     AddFsConfig(vmm::config::Error),
+    AddGenericVhostUserConfig(vmm::config::Error),
     AddPmemConfig(vmm::config::Error),
     AddNetConfig(vmm::config::Error),
     AddVsockConfig(vmm::config::Error),
+    AddUserDeviceConfig(vmm::config::Error),
     Restore(vmm::config::Error),
+    InvalidCpuAffinity(vmm::config::Error),
 }
 
 impl fmt::Display for Error {
@@ -36,16 +45,24 @@ impl fmt::Display for Error {
         match self {
             ApiClient(e) => e.fmt(f),
             Connect(e) => write!(f, "Error opening HTTP socket: {}", e),
+            OpenFile(path, e) => write!(f, "Error opening {:?}: {}", path, e),
+            InvalidFd(e) => write!(f, "Error parsing file descriptor: {}", e),
             InvalidCpuCount(e) => write!(f, "Error parsing CPU count: {}", e),
             InvalidMemorySize(e) => write!(f, "Error parsing memory size: {:?}", e),
             InvalidBalloonSize(e) => write!(f, "Error parsing balloon size: {:?}", e),
+            InvalidCaptureSize(e) => write!(f, "Error parsing capture size: {:?}", e),
             AddDeviceConfig(e) => write!(f, "Error parsing device syntax: {}", e),
             AddDiskConfig(e) => write!(f, "Error parsing disk syntax: {}", e),
             AddFsConfig(e) => write!(f, "Error parsing filesystem syntax: {}", e),
+            AddGenericVhostUserConfig(e) => {
+                write!(f, "Error parsing generic vhost-user syntax: {}", e)
+            }
             AddPmemConfig(e) => write!(f, "Error parsing persistent memory syntax: {}", e),
             AddNetConfig(e) => write!(f, "Error parsing network syntax: {}", e),
             AddVsockConfig(e) => write!(f, "Error parsing vsock syntax: {}", e),
+            AddUserDeviceConfig(e) => write!(f, "Error parsing user device syntax: {}", e),
             Restore(e) => write!(f, "Error parsing restore syntax: {}", e),
+            InvalidCpuAffinity(e) => write!(f, "Error parsing CPU affinity: {}", e),
         }
     }
 }
@@ -56,7 +73,7 @@ fn resize_api_command(
     memory: Option<&str>,
     balloon: Option<&str>,
 ) -> Result<(), Error> {
-    let desired_vcpus: Option<u8> = if let Some(cpus) = cpus {
+    let desired_vcpus: Option<u32> = if let Some(cpus) = cpus {
         Some(cpus.parse().map_err(Error::InvalidCpuCount)?)
     } else {
         None
@@ -117,6 +134,68 @@ fn resize_zone_api_command(socket: &mut UnixStream, id: &str, size: &str) -> Res
     .map_err(Error::ApiClient)
 }
 
+fn net_capture_api_command(
+    socket: &mut UnixStream,
+    id: &str,
+    path: Option<&str>,
+    max_size: Option<&str>,
+) -> Result<(), Error> {
+    let max_size: Option<u64> = if let Some(max_size) = max_size {
+        Some(
+            max_size
+                .parse::<ByteSized>()
+                .map_err(Error::InvalidCaptureSize)?
+                .0,
+        )
+    } else {
+        None
+    };
+
+    let net_capture = vmm::api::VmNetCaptureData {
+        id: id.to_owned(),
+        path: path.map(PathBuf::from),
+        max_size,
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "net-capture",
+        Some(&serde_json::to_string(&net_capture).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn net_link_api_command(socket: &mut UnixStream, id: &str, state: &str) -> Result<(), Error> {
+    let net_link = vmm::api::VmNetLinkData {
+        id: id.to_owned(),
+        up: state == "up",
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "net-link",
+        Some(&serde_json::to_string(&net_link).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn cpu_affinity_api_command(socket: &mut UnixStream, affinity: &str) -> Result<(), Error> {
+    let cpu_affinity = vmm::api::VmCpuAffinityData {
+        affinity: vmm::config::CpuAffinity::parse_list(affinity)
+            .map_err(Error::InvalidCpuAffinity)?,
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "cpu-affinity",
+        Some(&serde_json::to_string(&cpu_affinity).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
 fn add_device_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Error> {
     let device_config = vmm::config::DeviceConfig::parse(config).map_err(Error::AddDeviceConfig)?;
 
@@ -129,6 +208,19 @@ fn add_device_api_command(socket: &mut UnixStream, config: &str) -> Result<(), E
     .map_err(Error::ApiClient)
 }
 
+fn add_user_device_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Error> {
+    let device_config =
+        vmm::config::UserDeviceConfig::parse(config).map_err(Error::AddUserDeviceConfig)?;
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "add-user-device",
+        Some(&serde_json::to_string(&device_config).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
 fn remove_device_api_command(socket: &mut UnixStream, id: &str) -> Result<(), Error> {
     let remove_device_data = vmm::api::VmRemoveDeviceData { id: id.to_owned() };
 
@@ -165,6 +257,19 @@ fn add_fs_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Error
     .map_err(Error::ApiClient)
 }
 
+fn add_generic_vhost_user_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Error> {
+    let generic_vhost_user_config = vmm::config::GenericVhostUserConfig::parse(config)
+        .map_err(Error::AddGenericVhostUserConfig)?;
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "add-generic-vhost-user",
+        Some(&serde_json::to_string(&generic_vhost_user_config).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
 fn add_pmem_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Error> {
     let pmem_config = vmm::config::PmemConfig::parse(config).map_err(Error::AddPmemConfig)?;
 
@@ -215,6 +320,20 @@ fn snapshot_api_command(socket: &mut UnixStream, url: &str) -> Result<(), Error>
     .map_err(Error::ApiClient)
 }
 
+fn coredump_api_command(socket: &mut UnixStream, url: &str) -> Result<(), Error> {
+    let coredump_data = vmm::api::VmCoredumpData {
+        destination_url: String::from(url),
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "coredump",
+        Some(&serde_json::to_string(&coredump_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
 fn restore_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Error> {
     let restore_config = vmm::config::RestoreConfig::parse(config).map_err(Error::Restore)?;
 
@@ -253,6 +372,41 @@ fn send_migration_api_command(socket: &mut UnixStream, url: &str) -> Result<(),
     .map_err(Error::ApiClient)
 }
 
+fn send_fds_command(socket: &mut UnixStream, paths: &[&str]) -> Result<(), Error> {
+    // Files are passed read-write when possible, the VMM reopening them
+    // read-only through /proc/self/fd/<fd> if needed.
+    let files = paths
+        .iter()
+        .map(|path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .or_else(|_| File::open(path))
+                .map_err(|e| Error::OpenFile(PathBuf::from(path), e))
+        })
+        .collect::<Result<Vec<File>, Error>>()?;
+    let fds: Vec<RawFd> = files.iter().map(|file| file.as_raw_fd()).collect();
+
+    simple_api_full_command_with_fds(socket, "PUT", "vmm.send-fds", None, &fds)
+        .map_err(Error::ApiClient)
+}
+
+fn close_fds_command(socket: &mut UnixStream, fds: &[&str]) -> Result<(), Error> {
+    let fds = fds
+        .iter()
+        .map(|fd| fd.parse::<RawFd>().map_err(Error::InvalidFd))
+        .collect::<Result<Vec<RawFd>, Error>>()?;
+
+    simple_api_full_command(
+        socket,
+        "PUT",
+        "vmm.close-fds",
+        Some(&serde_json::to_string(&fds).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
 fn do_command(matches: &ArgMatches) -> Result<(), Error> {
     let mut socket =
         UnixStream::connect(matches.value_of("api-socket").unwrap()).map_err(Error::Connect)?;
@@ -264,6 +418,9 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
         Some("counters") => {
             simple_api_command(&mut socket, "GET", "counters", None).map_err(Error::ApiClient)
         }
+        Some("vcpu-counters") => {
+            simple_api_command(&mut socket, "GET", "vcpu-counters", None).map_err(Error::ApiClient)
+        }
         Some("resize") => resize_api_command(
             &mut socket,
             matches
@@ -292,6 +449,43 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .value_of("size")
                 .unwrap(),
         ),
+        Some("net-capture") => net_capture_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("net-capture")
+                .unwrap()
+                .value_of("id")
+                .unwrap(),
+            matches
+                .subcommand_matches("net-capture")
+                .unwrap()
+                .value_of("path"),
+            matches
+                .subcommand_matches("net-capture")
+                .unwrap()
+                .value_of("max_size"),
+        ),
+        Some("net-link") => net_link_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("net-link")
+                .unwrap()
+                .value_of("id")
+                .unwrap(),
+            matches
+                .subcommand_matches("net-link")
+                .unwrap()
+                .value_of("state")
+                .unwrap(),
+        ),
+        Some("cpu-affinity") => cpu_affinity_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("cpu-affinity")
+                .unwrap()
+                .value_of("affinity")
+                .unwrap(),
+        ),
         Some("add-device") => add_device_api_command(
             &mut socket,
             matches
@@ -300,6 +494,14 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .value_of("device_config")
                 .unwrap(),
         ),
+        Some("add-user-device") => add_user_device_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("add-user-device")
+                .unwrap()
+                .value_of("device_config")
+                .unwrap(),
+        ),
         Some("remove-device") => remove_device_api_command(
             &mut socket,
             matches
@@ -324,6 +526,14 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .value_of("fs_config")
                 .unwrap(),
         ),
+        Some("add-generic-vhost-user") => add_generic_vhost_user_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("add-generic-vhost-user")
+                .unwrap()
+                .value_of("generic_vhost_user_config")
+                .unwrap(),
+        ),
         Some("add-pmem") => add_pmem_api_command(
             &mut socket,
             matches
@@ -332,6 +542,24 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .value_of("pmem_config")
                 .unwrap(),
         ),
+        Some("send-fds") => send_fds_command(
+            &mut socket,
+            &matches
+                .subcommand_matches("send-fds")
+                .unwrap()
+                .values_of("files")
+                .unwrap()
+                .collect::<Vec<&str>>(),
+        ),
+        Some("close-fds") => close_fds_command(
+            &mut socket,
+            &matches
+                .subcommand_matches("close-fds")
+                .unwrap()
+                .values_of("fds")
+                .unwrap()
+                .collect::<Vec<&str>>(),
+        ),
         Some("add-net") => add_net_api_command(
             &mut socket,
             matches
@@ -356,6 +584,14 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .value_of("snapshot_config")
                 .unwrap(),
         ),
+        Some("coredump") => coredump_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("coredump")
+                .unwrap()
+                .value_of("coredump_config")
+                .unwrap(),
+        ),
         Some("restore") => restore_api_command(
             &mut socket,
             matches
@@ -398,6 +634,28 @@ fn main() {
                 .number_of_values(1)
                 .required(true),
         )
+        .subcommand(
+            SubCommand::with_name("send-fds")
+                .about("Pass file descriptors to the VMM")
+                .arg(
+                    Arg::with_name("files")
+                        .index(1)
+                        .multiple(true)
+                        .required(true)
+                        .help("<file> <file>..."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("close-fds")
+                .about("Close file descriptors passed to the VMM")
+                .arg(
+                    Arg::with_name("fds")
+                        .index(1)
+                        .multiple(true)
+                        .required(true)
+                        .help("<fd> <fd>..."),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("add-device")
                 .about("Add VFIO device")
@@ -407,6 +665,15 @@ fn main() {
                         .help(vmm::config::DeviceConfig::SYNTAX),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("add-user-device")
+                .about("Add vfio-user device")
+                .arg(
+                    Arg::with_name("device_config")
+                        .index(1)
+                        .help(vmm::config::UserDeviceConfig::SYNTAX),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("add-disk")
                 .about("Add block device")
@@ -425,6 +692,15 @@ fn main() {
                         .help(vmm::config::FsConfig::SYNTAX),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("add-generic-vhost-user")
+                .about("Add generic vhost-user device")
+                .arg(
+                    Arg::with_name("generic_vhost_user_config")
+                        .index(1)
+                        .help(vmm::config::GenericVhostUserConfig::SYNTAX),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("add-pmem")
                 .about("Add persistent memory device")
@@ -459,6 +735,7 @@ fn main() {
         )
         .subcommand(SubCommand::with_name("info").about("Info on the VM"))
         .subcommand(SubCommand::with_name("counters").about("Counters from the VM"))
+        .subcommand(SubCommand::with_name("vcpu-counters").about("Exit counters from the VM vCPUs"))
         .subcommand(SubCommand::with_name("pause").about("Pause the VM"))
         .subcommand(SubCommand::with_name("reboot").about("Reboot the VM"))
         .subcommand(SubCommand::with_name("power-button").about("Trigger a power button in the VM"))
@@ -505,6 +782,62 @@ fn main() {
                         .number_of_values(1),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("net-capture")
+                .about("Start or stop capturing the traffic of a network device")
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .help("Network device identifier")
+                        .takes_value(true)
+                        .number_of_values(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .long("path")
+                        .help("pcap file to capture into, stopping the capture if omitted")
+                        .takes_value(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("max_size")
+                        .long("max-size")
+                        .help("Maximum size of the capture file in bytes (supports K/M/G suffix)")
+                        .takes_value(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("net-link")
+                .about("Set the link of a network device up or down")
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .help("Network device identifier")
+                        .takes_value(true)
+                        .number_of_values(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("state")
+                        .long("state")
+                        .help("Link state")
+                        .takes_value(true)
+                        .number_of_values(1)
+                        .possible_values(&["up", "down"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cpu-affinity")
+                .about("Change the host CPUs vCPUs are allowed to run on")
+                .arg(
+                    Arg::with_name("affinity")
+                        .index(1)
+                        .help("<vcpu>@<host_cpus>:..."),
+                ),
+        )
         .subcommand(SubCommand::with_name("resume").about("Resume the VM"))
         .subcommand(SubCommand::with_name("shutdown").about("Shutdown the VM"))
         .subcommand(
@@ -516,6 +849,15 @@ fn main() {
                         .help("<destination_url>"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("coredump")
+                .about("Write an ELF coredump of the VM")
+                .arg(
+                    Arg::with_name("coredump_config")
+                        .index(1)
+                        .help("<destination_url>"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("restore")
                 .about("Restore VM from a snapshot")
@@ -0,0 +1,737 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Built-in jailer.
+//!
+//! The VMM enters new mount, PID and network namespaces, in which only the
+//! resources used by the VM are bind mounted into an otherwise empty root
+//! filesystem, before dropping to an unprivileged user and to a minimal set
+//! of capabilities. This must happen before any thread is spawned, as the
+//! VMM forks into its new PID namespace, the original process only waiting
+//! for it and forwarding it the termination signals.
+
+use option_parser::{OptionParser, OptionParserError, StringList};
+use std::collections::BTreeSet;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Component, Path, PathBuf};
+use std::ptr::null;
+use std::result;
+use thiserror::Error;
+use vmm::config::{
+    ConsoleOutputMode, LandlockAccess, LandlockConfig, RestoreConfig, VhostMode, VmConfig,
+};
+use vmm::landlock::{LandlockPaths, LandlockRule};
+use vmm::migration::{get_vm_snapshot, recv_vm_snapshot, url_to_path};
+
+// Highest capability known to the kernel (CAP_CHECKPOINT_RESTORE as of 5.9).
+const CAP_LAST_CAP: u32 = 40;
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+// Capabilities which may be useful to keep, depending on the VM.
+const CAPABILITIES: &[(&str, u32)] = &[
+    ("chown", 0),
+    ("dac_override", 1),
+    ("fowner", 3),
+    ("setgid", 6),
+    ("setuid", 7),
+    ("net_admin", 12),
+    ("net_raw", 13),
+    ("ipc_lock", 14),
+    ("sys_rawio", 17),
+    ("sys_admin", 21),
+    ("sys_nice", 23),
+    ("sys_resource", 24),
+    ("mknod", 27),
+];
+
+// Always needed by the VMM, independently of the VM configuration.
+const DEFAULT_PATHS: &[&str] = &["/dev/null", "/dev/urandom"];
+
+// The ptys are allocated from the devpts instance next to the multiplexer.
+const PTS_PATH: &str = "/dev/pts";
+
+// Signals the original process forwards to the jailed VMM.
+const FORWARDED_SIGNALS: &[libc::c_int] = &[libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+#[repr(C)]
+#[allow(dead_code)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Errors associated with the jail
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Error parsing --jail: {0}")]
+    Parse(#[source] OptionParserError),
+    #[error("Error parsing --jail: root missing")]
+    RootMissing,
+    #[error("Error parsing --jail: unknown capability {0}")]
+    UnknownCapability(String),
+    #[error("Error reading the snapshot to restore: {0}")]
+    Snapshot(#[source] anyhow::Error),
+    #[error("Error opening network namespace {0:?}: {1}")]
+    OpenNetns(PathBuf, #[source] io::Error),
+    #[error("Error entering network namespace: {0}")]
+    Setns(#[source] io::Error),
+    #[error("Error creating namespaces: {0}")]
+    Unshare(#[source] io::Error),
+    #[error("Error forking into the PID namespace: {0}")]
+    Fork(#[source] io::Error),
+    #[error("Error creating mount point {0:?}: {1}")]
+    MountPoint(PathBuf, #[source] io::Error),
+    #[error("Error mounting {0:?}: {1}")]
+    Mount(PathBuf, #[source] io::Error),
+    #[error("Error changing the root to the jail: {0}")]
+    PivotRoot(#[source] io::Error),
+    #[error("Error dropping capabilities: {0}")]
+    DropCapabilities(#[source] io::Error),
+    #[error("Error changing user and group: {0}")]
+    SetUser(#[source] io::Error),
+}
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JailConfig {
+    pub root: PathBuf,
+    pub uid: Option<libc::uid_t>,
+    pub gid: Option<libc::gid_t>,
+    pub netns: Option<PathBuf>,
+    pub caps: u64,
+}
+
+impl JailConfig {
+    pub const SYNTAX: &'static str = "Jail parameters \
+        \"root=<jail_directory>,uid=<user_id>,gid=<group_id>,netns=<netns_path>,\
+        caps=<capability>:<capability>\"";
+
+    pub fn parse(jail: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("root")
+            .add("uid")
+            .add("gid")
+            .add("netns")
+            .add("caps");
+        parser.parse(jail).map_err(Error::Parse)?;
+
+        let root = parser
+            .get("root")
+            .map(PathBuf::from)
+            .ok_or(Error::RootMissing)?;
+        let uid = parser.convert("uid").map_err(Error::Parse)?;
+        let gid = parser.convert("gid").map_err(Error::Parse)?;
+        let netns = parser.get("netns").map(PathBuf::from);
+        let mut caps = 0;
+        for cap in parser
+            .convert::<StringList>("caps")
+            .map_err(Error::Parse)?
+            .map(|caps| caps.0)
+            .unwrap_or_default()
+        {
+            let (_, bit) = CAPABILITIES
+                .iter()
+                .find(|(name, _)| cap.eq_ignore_ascii_case(name))
+                .ok_or_else(|| Error::UnknownCapability(cap.clone()))?;
+            caps |= 1 << bit;
+        }
+
+        Ok(JailConfig {
+            root,
+            uid,
+            gid,
+            netns,
+            caps,
+        })
+    }
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Paths to make available in the jail for a file created by the VMM, or a
+/// socket it connects to. The whole directory is needed so that the file
+/// remains reachable when it is created or recreated.
+pub fn parent_paths(path: &Path) -> LandlockConfig {
+    LandlockConfig {
+        path: parent_dir(path),
+        access: LandlockAccess::ReadWrite,
+    }
+}
+
+/// Paths to make available in the jail for the given VM, which are the ones
+/// the Landlock sandbox would allow, along with the sockets of the backends
+/// the VMM connects to.
+pub fn vm_paths(config: &VmConfig) -> Vec<LandlockConfig> {
+    let mut paths: Vec<LandlockConfig> = config
+        .landlock_paths()
+        .into_iter()
+        .map(|rule| match rule {
+            LandlockRule::Path(config) => config,
+            LandlockRule::Output(path) => LandlockConfig {
+                path,
+                access: LandlockAccess::Write,
+            },
+            LandlockRule::Socket(path) => parent_paths(&path),
+        })
+        .collect();
+
+    if config.serial.mode == ConsoleOutputMode::Pty || config.console.mode == ConsoleOutputMode::Pty
+    {
+        paths.push(LandlockConfig {
+            path: PathBuf::from(PTS_PATH),
+            access: LandlockAccess::ReadWrite,
+        });
+    }
+
+    for disk in config.disks.iter().flatten() {
+        if let Some(socket) = &disk.vhost_socket {
+            paths.push(parent_paths(Path::new(socket)));
+        }
+    }
+    for net in config.net.iter().flatten() {
+        if let (Some(socket), VhostMode::Client) = (&net.vhost_socket, &net.vhost_mode) {
+            paths.push(parent_paths(Path::new(socket)));
+        }
+    }
+    for fs in config.fs.iter().flatten() {
+        paths.push(parent_paths(&fs.socket));
+    }
+    for user_device in config.user_devices.iter().flatten() {
+        paths.push(parent_paths(&user_device.socket));
+    }
+    for generic_vhost_user in config.generic_vhost_user.iter().flatten() {
+        paths.push(parent_paths(&generic_vhost_user.socket));
+    }
+
+    paths
+}
+
+/// Paths to make available in the jail for the VM to restore, read from the
+/// snapshot.
+pub fn restore_paths(config: &RestoreConfig) -> Result<Vec<LandlockConfig>> {
+    let source_url = config.source_url.to_string_lossy();
+    let snapshot = recv_vm_snapshot(&source_url).map_err(|e| Error::Snapshot(e.into()))?;
+    let vm_snapshot = get_vm_snapshot(&snapshot).map_err(|e| Error::Snapshot(e.into()))?;
+
+    let mut paths = vm_paths(&vm_snapshot.config.lock().unwrap());
+    paths.push(LandlockConfig {
+        path: url_to_path(&source_url).map_err(|e| Error::Snapshot(e.into()))?,
+        access: LandlockAccess::Read,
+    });
+
+    Ok(paths)
+}
+
+fn c_path(path: &Path) -> CString {
+    // Paths coming from the command line cannot contain a NUL byte.
+    CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
+fn mount(
+    source: Option<&Path>,
+    target: &Path,
+    fstype: Option<&str>,
+    flags: libc::c_ulong,
+) -> Result<()> {
+    let source = source.map(c_path);
+    let c_target = c_path(target);
+    let fstype = fstype.map(|fstype| CString::new(fstype).unwrap());
+
+    // Safe because all the strings are NUL terminated and outlive the call.
+    let ret = unsafe {
+        libc::mount(
+            source.as_ref().map_or(null(), |s| s.as_ptr()),
+            c_target.as_ptr(),
+            fstype.as_ref().map_or(null(), |s| s.as_ptr()),
+            flags,
+            null(),
+        )
+    };
+    if ret < 0 {
+        return Err(Error::Mount(
+            target.to_path_buf(),
+            io::Error::last_os_error(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Paths to bind mount, along with whether they are read-only, the ones which
+// do not exist yet being mounted through their parent directory. The paths are
+// sorted from the shortest to the longest, a path already visible with the
+// right access through a previous mount being skipped.
+fn mount_paths(cwd: &Path, paths: &[LandlockConfig]) -> Vec<(PathBuf, bool)> {
+    let mut mounts: Vec<(PathBuf, bool)> = paths
+        .iter()
+        .map(|p| {
+            let path = cwd.join(&p.path);
+            let path = if path.exists() {
+                path
+            } else {
+                parent_dir(&path)
+            };
+            (path, p.access == LandlockAccess::Read)
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    mounts.sort_by_key(|(path, _)| path.components().count());
+
+    let mut mounted: Vec<(PathBuf, bool)> = Vec::new();
+    for (path, readonly) in mounts {
+        if !mounted
+            .iter()
+            .any(|(p, ro)| path.starts_with(p) && (!ro || readonly))
+        {
+            mounted.push((path, readonly));
+        }
+    }
+
+    mounted
+}
+
+// Bind mount the given paths at the same location under the jail root.
+fn bind_mount(root: &Path, cwd: &Path, paths: &[LandlockConfig]) -> Result<()> {
+    for (path, readonly) in mount_paths(cwd, paths) {
+        let target: PathBuf = root.join(
+            path.components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .collect::<PathBuf>(),
+        );
+        if !target.exists() {
+            if path.is_dir() {
+                fs::create_dir_all(&target)
+            } else {
+                fs::create_dir_all(parent_dir(&target))
+                    .and_then(|_| OpenOptions::new().write(true).create(true).open(&target))
+                    .map(|_| ())
+            }
+            .map_err(|e| Error::MountPoint(target.clone(), e))?;
+        }
+
+        mount(Some(&path), &target, None, libc::MS_BIND | libc::MS_REC)?;
+        if readonly {
+            mount(
+                None,
+                &target,
+                None,
+                libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn pivot_root(root: &Path, cwd: &Path) -> Result<()> {
+    let c_root = c_path(root);
+    let dot = CString::new(".").unwrap();
+
+    // Safe because the paths are NUL terminated. Pivoting the root onto
+    // itself stacks the old root on top of the new one, which can then be
+    // detached.
+    unsafe {
+        if libc::chdir(c_root.as_ptr()) < 0
+            || libc::syscall(libc::SYS_pivot_root, dot.as_ptr(), dot.as_ptr()) < 0
+            || libc::umount2(dot.as_ptr(), libc::MNT_DETACH) < 0
+        {
+            return Err(Error::PivotRoot(io::Error::last_os_error()));
+        }
+    }
+
+    // Relative paths from the configuration keep working from the same
+    // working directory, which exists in the jail as a parent of them.
+    std::env::set_current_dir(cwd)
+        .or_else(|_| std::env::set_current_dir("/"))
+        .map_err(Error::PivotRoot)
+}
+
+fn set_root(root: &Path, cwd: &Path, paths: &[LandlockConfig]) -> Result<()> {
+    // Nothing done in the jail must propagate back to the host.
+    mount(None, Path::new("/"), None, libc::MS_REC | libc::MS_PRIVATE)?;
+    mount(
+        Some(Path::new("tmpfs")),
+        root,
+        Some("tmpfs"),
+        libc::MS_NOSUID,
+    )?;
+
+    // The mount points must be reachable once the user is changed, whereas
+    // the files created by the VMM only by itself.
+    // Safe because this only affects the calling process.
+    let umask = unsafe { libc::umask(0o022) };
+    let proc_path = root.join("proc");
+    let result = bind_mount(root, cwd, paths).and_then(|_| {
+        fs::create_dir_all(&proc_path).map_err(|e| Error::MountPoint(proc_path.clone(), e))
+    });
+    // Safe because this only affects the calling process.
+    unsafe { libc::umask(umask) };
+    result?;
+
+    mount(
+        Some(Path::new("proc")),
+        &proc_path,
+        Some("proc"),
+        libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+    )?;
+
+    pivot_root(root, cwd)
+}
+
+fn drop_privileges(config: &JailConfig) -> Result<()> {
+    // The bounding set can only be reduced with CAP_SETPCAP, hence before
+    // changing the user.
+    for cap in 0..=CAP_LAST_CAP {
+        if config.caps & (1 << cap) != 0 {
+            continue;
+        }
+        // Safe because this only affects the calling process. Capabilities
+        // unknown to the running kernel are reported as invalid.
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::EINVAL) {
+                return Err(Error::DropCapabilities(e));
+            }
+        }
+    }
+
+    if config.uid.is_some() || config.gid.is_some() {
+        // Safe because these only affect the calling process and their
+        // return values are checked.
+        unsafe {
+            if libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) < 0 {
+                return Err(Error::SetUser(io::Error::last_os_error()));
+            }
+            if let Some(gid) = config.gid {
+                if libc::setgroups(1, &gid) < 0 || libc::setresgid(gid, gid, gid) < 0 {
+                    return Err(Error::SetUser(io::Error::last_os_error()));
+                }
+            }
+            if let Some(uid) = config.uid {
+                if libc::setresuid(uid, uid, uid) < 0 {
+                    return Err(Error::SetUser(io::Error::last_os_error()));
+                }
+            }
+            if libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) < 0 {
+                return Err(Error::SetUser(io::Error::last_os_error()));
+            }
+        }
+    }
+
+    let header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapUserData::default(); 2];
+    for (i, data) in data.iter_mut().enumerate() {
+        let caps = (config.caps >> (32 * i)) as u32;
+        data.effective = caps;
+        data.permitted = caps;
+    }
+    // Safe because the header and the two data structures expected by the
+    // version 3 of the capabilities ABI are valid.
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } < 0 {
+        return Err(Error::DropCapabilities(io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+fn enter_namespaces(config: &JailConfig) -> Result<()> {
+    if let Some(netns) = &config.netns {
+        let file = File::open(netns).map_err(|e| Error::OpenNetns(netns.clone(), e))?;
+        // Safe because the file descriptor is valid and owned by the file.
+        if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+            return Err(Error::Setns(io::Error::last_os_error()));
+        }
+    } else {
+        // Safe because this only affects the calling process.
+        if unsafe { libc::unshare(libc::CLONE_NEWNET) } < 0 {
+            return Err(Error::Unshare(io::Error::last_os_error()));
+        }
+    }
+
+    // Safe because this only affects the calling process, and its children
+    // for the PID namespace.
+    if unsafe { libc::unshare(libc::CLONE_NEWNS | libc::CLONE_NEWPID) } < 0 {
+        return Err(Error::Unshare(io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+// Signals are only delivered to the init process of a PID namespace if it
+// has a handler for them, even when it waits for them with sigwait().
+extern "C" fn ignore_signal(_: libc::c_int) {}
+
+// Wait for the given child to exit, forwarding it the termination signals,
+// and exit with the same status. Any other child, which can be left behind
+// in the PID namespace, is reaped along the way.
+fn wait_child(child: libc::pid_t, sigset: &libc::sigset_t) -> ! {
+    loop {
+        let mut signal = 0;
+        // Safe because the signal set is initialized and all the signals it
+        // contains are blocked.
+        if unsafe { libc::sigwait(sigset, &mut signal) } != 0 {
+            continue;
+        }
+
+        if signal != libc::SIGCHLD {
+            // Safe because the child is still waited for, and thus the PID
+            // cannot have been reused.
+            unsafe { libc::kill(child, signal) };
+            continue;
+        }
+
+        loop {
+            let mut status = 0;
+            // Safe because the status is a valid location to write to.
+            let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+            if pid <= 0 {
+                break;
+            }
+            if pid == child {
+                let code = if libc::WIFEXITED(status) {
+                    libc::WEXITSTATUS(status)
+                } else {
+                    128 + libc::WTERMSIG(status)
+                };
+                std::process::exit(code);
+            }
+        }
+    }
+}
+
+// Set the handler of the signals waited for by the init process of the PID
+// namespace.
+fn set_signal_handlers(handler: libc::sighandler_t) {
+    for signal in FORWARDED_SIGNALS.iter().chain(&[libc::SIGCHLD]) {
+        // Safe because the handler is either a default disposition or a
+        // function which does nothing.
+        unsafe { libc::signal(*signal, handler) };
+    }
+}
+
+fn fork() -> Result<libc::pid_t> {
+    // Safe because no other thread exists yet.
+    match unsafe { libc::fork() } {
+        -1 => Err(Error::Fork(io::Error::last_os_error())),
+        pid => Ok(pid),
+    }
+}
+
+// Kill the calling process when its parent dies. This must be done after
+// changing the credentials, which reset it.
+fn set_parent_death_signal() {
+    // Safe because this only affects the calling process.
+    unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) };
+}
+
+/// Jail the VMM, only returning in the jailed process. The paths used by the
+/// VMM are made available in the jail at the same location.
+///
+/// The original process waits for a minimal init process, which is PID 1 of
+/// the new PID namespace and itself waits for the jailed VMM. Both forward
+/// the termination signals, which the kernel would otherwise drop when sent
+/// directly to a namespace init not handling them.
+pub fn enter(config: &JailConfig, paths: &[LandlockConfig]) -> Result<()> {
+    let cwd = std::env::current_dir().map_err(Error::PivotRoot)?;
+    let mut paths = paths.to_vec();
+    paths.extend(DEFAULT_PATHS.iter().map(|path| LandlockConfig {
+        path: PathBuf::from(path),
+        access: LandlockAccess::ReadWrite,
+    }));
+
+    enter_namespaces(config)?;
+
+    // The termination signals are blocked until the waiting processes are
+    // ready to forward them, so that the jailed VMM cannot be left behind.
+    // Safe because the signal sets are initialized before being used.
+    let (sigset, old_sigset) = unsafe {
+        let mut sigset = std::mem::zeroed();
+        let mut old_sigset = std::mem::zeroed();
+        libc::sigemptyset(&mut sigset);
+        libc::sigaddset(&mut sigset, libc::SIGCHLD);
+        for signal in FORWARDED_SIGNALS {
+            libc::sigaddset(&mut sigset, *signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &sigset, &mut old_sigset);
+        (sigset, old_sigset)
+    };
+
+    let init = fork()?;
+    if init > 0 {
+        wait_child(init, &sigset);
+    }
+
+    // The mount namespace, and thus the new root, is shared with the VMM.
+    set_root(&config.root, &cwd, &paths)?;
+    drop_privileges(config)?;
+    set_parent_death_signal();
+    set_signal_handlers(ignore_signal as libc::sighandler_t);
+
+    let vmm = fork()?;
+    if vmm > 0 {
+        wait_child(vmm, &sigset);
+    }
+
+    set_signal_handlers(libc::SIG_DFL);
+    // Safe because the old signal set was filled in when blocking the
+    // signals.
+    unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, &old_sigset, std::ptr::null_mut()) };
+    set_parent_death_signal();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_app, prepare_default_values};
+    use vmm::config::VmParams;
+    use vmm_sys_util::tempdir::TempDir;
+
+    fn get_vm_config_from_vec(args: &[&str]) -> VmConfig {
+        let (default_vcpus, default_memory, default_rng) = prepare_default_values();
+        let cmd_arguments =
+            create_app(&default_vcpus, &default_memory, &default_rng).get_matches_from(args);
+
+        VmConfig::parse(VmParams::from_arg_matches(&cmd_arguments)).unwrap()
+    }
+
+    #[test]
+    fn test_jail_parsing() -> Result<()> {
+        // root is required
+        assert!(JailConfig::parse("uid=1000").is_err());
+        assert!(JailConfig::parse("root=/jail,caps=net_admin:unknown").is_err());
+        assert_eq!(
+            JailConfig::parse("root=/jail")?,
+            JailConfig {
+                root: PathBuf::from("/jail"),
+                uid: None,
+                gid: None,
+                netns: None,
+                caps: 0,
+            }
+        );
+        assert_eq!(
+            JailConfig::parse(
+                "root=/jail,uid=1000,gid=1000,netns=/var/run/netns/vm0,caps=net_admin:sys_nice"
+            )?,
+            JailConfig {
+                root: PathBuf::from("/jail"),
+                uid: Some(1000),
+                gid: Some(1000),
+                netns: Some(PathBuf::from("/var/run/netns/vm0")),
+                caps: 1 << 12 | 1 << 23,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parent_paths() {
+        assert_eq!(
+            parent_paths(Path::new("/run/ch/vhost-user-net.sock")),
+            LandlockConfig {
+                path: PathBuf::from("/run/ch"),
+                access: LandlockAccess::ReadWrite,
+            }
+        );
+        assert_eq!(parent_paths(Path::new("api.sock")).path, PathBuf::from("."));
+    }
+
+    #[test]
+    fn test_mount_paths() {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let images = dir.as_path().join("images");
+        let run = dir.as_path().join("run");
+        let snapshots = dir.as_path().join("snapshots");
+        fs::create_dir_all(snapshots.join("vm0")).unwrap();
+        fs::create_dir(&images).unwrap();
+        fs::create_dir(&run).unwrap();
+        fs::write(images.join("disk.raw"), b"").unwrap();
+
+        let rule = |path: PathBuf, access| LandlockConfig { path, access };
+        let paths = vec![
+            rule(images.join("disk.raw"), LandlockAccess::Read),
+            rule(images.clone(), LandlockAccess::ReadWrite),
+            rule(snapshots.clone(), LandlockAccess::Read),
+            rule(snapshots.join("vm0"), LandlockAccess::ReadWrite),
+            rule(run.join("api.sock"), LandlockAccess::ReadWrite),
+        ];
+
+        // A read-only path is covered by a read-write parent, but not the
+        // other way around. A missing path is mounted through its parent.
+        assert_eq!(
+            mount_paths(Path::new("/"), &paths),
+            vec![
+                (images, false),
+                (run, false),
+                (snapshots.clone(), true),
+                (snapshots.join("vm0"), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_vm_mount_paths() {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let images = dir.as_path().join("images");
+        let run = dir.as_path().join("run");
+        fs::create_dir(&images).unwrap();
+        fs::create_dir(&run).unwrap();
+        let kernel = images.join("vmlinux");
+        let disk = images.join("disk.raw");
+        fs::write(&kernel, b"").unwrap();
+        fs::write(&disk, b"").unwrap();
+
+        let config = get_vm_config_from_vec(&[
+            "cloud-hypervisor",
+            "--kernel",
+            kernel.to_str().unwrap(),
+            "--disk",
+            &format!("path={},readonly=on", disk.to_str().unwrap()),
+            "--vsock",
+            &format!("cid=3,socket={}", run.join("vsock.sock").to_str().unwrap()),
+            "--serial",
+            &format!("file={}", run.join("serial.log").to_str().unwrap()),
+            "--console",
+            "off",
+        ]);
+
+        // The socket and the serial output file, which don't exist yet, are
+        // both made available through their parent directory.
+        assert_eq!(
+            mount_paths(Path::new("/"), &vm_paths(&config)),
+            vec![
+                (PathBuf::from("/dev/urandom"), true),
+                (run, false),
+                (disk, true),
+                (kernel, true),
+            ]
+        );
+    }
+}
@@ -17,16 +17,23 @@ use signal_hook::{
     consts::SIGSYS,
     iterator::{exfiltrator::WithRawSiginfo, SignalsInfo},
 };
+use std::convert::TryInto;
 use std::env;
 use std::fs::File;
+use std::io::Read;
 use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use thiserror::Error;
 use vmm::config;
+use vmm::seccomp_filters::seccomp_profile::{self, SeccompProfile};
 use vmm_sys_util::eventfd::EventFd;
 
+mod jail;
+
 #[derive(Error, Debug)]
 enum Error {
     #[error("Failed to create API EventFd: {0}")]
@@ -68,6 +75,132 @@ enum Error {
     LogFileCreation(std::io::Error),
     #[error("Error setting up logger: {0}")]
     LoggerSetup(log::SetLoggerError),
+    #[cfg(feature = "gdb")]
+    #[error("Error parsing --gdb: {0}")]
+    ParsingGdb(option_parser::OptionParserError),
+    #[cfg(feature = "gdb")]
+    #[error("Error parsing --gdb: path required")]
+    BareGdb,
+    #[error("Error loading --seccomp-profile: {0:?}")]
+    SeccompProfile(seccomp_profile::Error),
+    #[error("Error parsing --seccomp-record: seccomp filtering must be enabled")]
+    SeccompRecordAction,
+    #[error("Error setting up --seccomp-record: {0}")]
+    SeccompRecord(#[source] std::io::Error),
+    #[error("Error setting up the jail: {0}")]
+    Jail(#[source] jail::Error),
+}
+
+// SIGSYS specific layout of siginfo_t, see include/uapi/asm-generic/siginfo.h
+// in the kernel code.
+#[repr(C)]
+#[allow(dead_code)]
+struct SigsysInfo {
+    signo: libc::c_int,
+    errno: libc::c_int,
+    code: libc::c_int,
+    call_addr: *mut libc::c_void,
+    syscall: libc::c_int,
+    arch: libc::c_uint,
+}
+
+// si_code of the SIGSYS raised by a seccomp filter
+const SYS_SECCOMP: libc::c_int = 1;
+
+// Write end of the pipe the SIGSYS handler reports the recorded syscalls to
+static SECCOMP_RECORD_FD: AtomicI32 = AtomicI32::new(-1);
+
+// Report the trap tag and the number of the denied syscall, and make the
+// syscall fail with ENOSYS. The kernel otherwise leaves the syscall number in
+// the return register, which the caller would mistake for a success.
+extern "C" fn seccomp_record_handler(
+    _signo: libc::c_int,
+    info: *mut libc::siginfo_t,
+    ucontext: *mut libc::c_void,
+) {
+    // Safe because the kernel hands a SA_SIGINFO handler a valid siginfo_t
+    // and ucontext_t, and write() is async-signal-safe.
+    unsafe {
+        let info = &*(info as *const SigsysInfo);
+        if info.code != SYS_SECCOMP {
+            return;
+        }
+        let report: [libc::c_int; 2] = [info.errno, info.syscall];
+        libc::write(
+            SECCOMP_RECORD_FD.load(Ordering::Relaxed),
+            report.as_ptr() as *const libc::c_void,
+            std::mem::size_of_val(&report),
+        );
+
+        let mcontext = &mut (*(ucontext as *mut libc::ucontext_t)).uc_mcontext;
+        #[cfg(target_arch = "x86_64")]
+        {
+            mcontext.gregs[libc::REG_RAX as usize] = -libc::ENOSYS as i64;
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            mcontext.regs[0] = -libc::ENOSYS as u64;
+        }
+    }
+}
+
+fn syscall_name(syscall: libc::c_int) -> String {
+    seccomp_profile::syscall_name(syscall as i64)
+        .map(String::from)
+        .unwrap_or_else(|| syscall.to_string())
+}
+
+// Record the syscalls denied by the seccomp filters to the profile at `path`.
+// Rather than being killed, the VMM goes on with the denied syscall failing,
+// which records as many syscalls as possible in a single run.
+fn start_seccomp_recorder(path: String) -> std::io::Result<()> {
+    let mut fds: [RawFd; 2] = [-1; 2];
+    // Safe because fds is large enough to hold both ends of the pipe.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // Safe because the read end was just created and isn't owned elsewhere.
+    let mut reports = unsafe { File::from_raw_fd(fds[0]) };
+    SECCOMP_RECORD_FD.store(fds[1], Ordering::Relaxed);
+
+    // Safe because the handler only accesses the siginfo_t and ucontext_t it
+    // is given, and the pipe it reports to is never closed.
+    let ret = unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = seccomp_record_handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigaction(SIGSYS, &action, std::ptr::null_mut())
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    thread::Builder::new()
+        .name("seccomp_recorder".to_string())
+        .spawn(move || {
+            let mut report = [0u8; std::mem::size_of::<[libc::c_int; 2]>()];
+            while reports.read_exact(&mut report).is_ok() {
+                let (data, syscall) = report.split_at(std::mem::size_of::<libc::c_int>());
+                let data = libc::c_int::from_ne_bytes(data.try_into().unwrap());
+                let syscall = syscall_name(libc::c_int::from_ne_bytes(syscall.try_into().unwrap()));
+
+                // The filters tag their traps with the thread type
+                match seccomp_profile::trap_thread(data) {
+                    Some(thread) => {
+                        match seccomp_profile::record_syscall(Path::new(&path), thread, &syscall) {
+                            Ok(()) => eprintln!(
+                                "Recorded the `{}` syscall of thread type `{}` to {}",
+                                syscall, thread, path
+                            ),
+                            Err(e) => eprintln!("Error recording to {}: {:?}", path, e),
+                        }
+                    }
+                    None => eprintln!("Denied the `{}` syscall of an unknown thread type", syscall),
+                }
+            }
+        })?;
+
+    Ok(())
 }
 
 struct Logger {
@@ -148,7 +281,11 @@ fn create_app<'a, 'b>(
                 .help(
                     "boot=<boot_vcpus>,max=<max_vcpus>,\
                     topology=<threads_per_core>:<cores_per_die>:<dies_per_package>:<packages>,\
-                    kvm_hyperv=on|off,max_phys_bits=<maximum_number_of_physical_bits>",
+                    kvm_hyperv=on|off,max_phys_bits=<maximum_number_of_physical_bits>,\
+                    affinity=<vcpu>@<host_cpus>:...,vmm_affinity=<host_cpus>,\
+                    io_affinity=<host_cpus>,fifo_priority=<sched_fifo_priority>,model=<cpu_model>,\
+                    features=[+-]<cpu_feature>:...,exit_stats=on|off,\
+                    exit_trace=<trace_file>",
                 )
                 .default_value(&default_vcpus)
                 .group("vm-config"),
@@ -279,6 +416,22 @@ fn create_app<'a, 'b>(
                 .min_values(1)
                 .group("vm-config"),
         )
+        .arg(
+            Arg::with_name("user-device")
+                .long("user-device")
+                .help(config::UserDeviceConfig::SYNTAX)
+                .takes_value(true)
+                .min_values(1)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::with_name("generic-vhost-user")
+                .long("generic-vhost-user")
+                .help(config::GenericVhostUserConfig::SYNTAX)
+                .takes_value(true)
+                .min_values(1)
+                .group("vm-config"),
+        )
         .arg(
             Arg::with_name("vsock")
                 .long("vsock")
@@ -302,6 +455,21 @@ fn create_app<'a, 'b>(
                 .takes_value(false)
                 .group("vm-config"),
         )
+        .arg(
+            Arg::with_name("landlock")
+                .long("landlock")
+                .help("Restrict the VMM filesystem accesses to the paths used by the VM")
+                .takes_value(false)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::with_name("landlock-rules")
+                .long("landlock-rules")
+                .help(config::LandlockConfig::SYNTAX)
+                .takes_value(true)
+                .min_values(1)
+                .group("vm-config"),
+        )
         .arg(
             Arg::with_name("v")
                 .short("v")
@@ -325,6 +493,13 @@ fn create_app<'a, 'b>(
                 .min_values(1)
                 .group("vmm-config"),
         )
+        .arg(
+            Arg::with_name("jail")
+                .long("jail")
+                .help(jail::JailConfig::SYNTAX)
+                .takes_value(true)
+                .group("vmm-config"),
+        )
         .arg(
             Arg::with_name("event-monitor")
                 .long("event-monitor")
@@ -347,6 +522,18 @@ fn create_app<'a, 'b>(
                 .takes_value(true)
                 .possible_values(&["true", "false", "log"])
                 .default_value("true"),
+        )
+        .arg(
+            Arg::with_name("seccomp-profile")
+                .long("seccomp-profile")
+                .help("JSON profile extending or replacing the seccomp filters")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seccomp-record")
+                .long("seccomp-record")
+                .help("Record the syscalls denied by the seccomp filters to a JSON profile, instead of being killed")
+                .takes_value(true),
         );
 
     #[cfg(target_arch = "x86_64")]
@@ -361,6 +548,17 @@ fn create_app<'a, 'b>(
         );
     }
 
+    #[cfg(feature = "gdb")]
+    {
+        app = app.arg(
+            Arg::with_name("gdb")
+                .long("gdb")
+                .help("GDB socket (UNIX domain socket): path=</path/to/a/file>")
+                .takes_value(true)
+                .group("vmm-config"),
+        );
+    }
+
     #[cfg(feature = "tdx")]
     {
         app = app.arg(
@@ -446,6 +644,39 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         event_monitor::set_monitor(file).map_err(Error::EventMonitorIo)?;
     }
 
+    #[cfg(feature = "gdb")]
+    let gdb_socket_path = if let Some(gdb_config) = cmd_arguments.value_of("gdb") {
+        let mut parser = OptionParser::new();
+        parser.add("path");
+        parser.parse(gdb_config).map_err(Error::ParsingGdb)?;
+
+        if parser.is_set("path") {
+            Some(std::path::PathBuf::from(parser.get("path").unwrap()))
+        } else {
+            return Err(Error::BareGdb);
+        }
+    } else {
+        None
+    };
+
+    // Can't test for "vm-config" group as some have default values. The kernel
+    // is the only required option for booting the VM.
+    let vm_config = if cmd_arguments.is_present("kernel") || cmd_arguments.is_present("tdx") {
+        let vm_params = config::VmParams::from_arg_matches(&cmd_arguments);
+        Some(config::VmConfig::parse(vm_params).map_err(Error::ParsingConfig)?)
+    } else {
+        None
+    };
+    let restore_config = if vm_config.is_none() {
+        cmd_arguments
+            .value_of("restore")
+            .map(config::RestoreConfig::parse)
+            .transpose()
+            .map_err(Error::ParsingRestore)?
+    } else {
+        None
+    };
+
     let (api_request_sender, api_request_receiver) = channel();
     let api_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::CreateApiEventFd)?;
 
@@ -464,8 +695,47 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         SeccompAction::Trap
     };
 
+    // The profile must be set before any thread applies its seccomp filter.
+    if let Some(path) = cmd_arguments.value_of("seccomp-profile") {
+        let profile = SeccompProfile::from_file(Path::new(path)).map_err(Error::SeccompProfile)?;
+        seccomp_profile::set_profile(profile);
+    }
+    let seccomp_record = cmd_arguments.value_of("seccomp-record").map(String::from);
+    // Only trapping lets the VMM know which syscalls were denied.
+    if seccomp_record.is_some() && seccomp_action != SeccompAction::Trap {
+        return Err(Error::SeccompRecordAction);
+    }
+
+    // The hypervisor device is not made available in the jail.
+    let hypervisor = hypervisor::new().map_err(Error::CreateHypervisor)?;
+
+    // The jail forks the VMM into a new PID namespace, which must happen
+    // before any thread is spawned.
+    if let Some(jail_config) = cmd_arguments.value_of("jail") {
+        let jail_config = jail::JailConfig::parse(jail_config).map_err(Error::Jail)?;
+        let mut paths = if let Some(vm_config) = &vm_config {
+            jail::vm_paths(vm_config)
+        } else if let Some(restore_config) = &restore_config {
+            jail::restore_paths(restore_config).map_err(Error::Jail)?
+        } else {
+            Vec::new()
+        };
+        // The files created by the VMM itself, granted through their parent
+        // directory as they may not exist yet.
+        let mut vmm_files: Vec<PathBuf> = Vec::new();
+        vmm_files.extend(api_socket_path.iter().map(PathBuf::from));
+        #[cfg(feature = "gdb")]
+        vmm_files.extend(gdb_socket_path.iter().cloned());
+        vmm_files.extend(seccomp_record.iter().map(PathBuf::from));
+        paths.extend(vmm_files.iter().map(|path| jail::parent_paths(path)));
+
+        jail::enter(&jail_config, &paths).map_err(Error::Jail)?;
+    }
+
     // See https://github.com/rust-lang/libc/issues/716 why we can't get the details from siginfo_t
-    if seccomp_action == SeccompAction::Trap {
+    if let Some(path) = seccomp_record {
+        start_seccomp_recorder(path).map_err(Error::SeccompRecord)?;
+    } else if seccomp_action == SeccompAction::Trap {
         thread::Builder::new()
             .name("seccomp_signal_handler".to_string())
             .spawn(move || {
@@ -473,12 +743,21 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
                     .unwrap()
                     .forever()
                 {
-                    /* SYS_SECCOMP */
-                    if si.si_code == 1 {
+                    if si.si_code == SYS_SECCOMP {
+                        // The filters tag their traps with the thread type
+                        let thread = seccomp_profile::trap_thread(si.si_errno);
+                        // Safe because siginfo_t is large enough to hold the
+                        // SIGSYS specific fields the kernel fills in.
+                        let syscall = unsafe { (*(&si as *const _ as *const SigsysInfo)).syscall };
+                        let syscall = syscall_name(syscall);
+
                         eprint!(
                             "\n==== seccomp violation ====\n\
+                            Thread type `{}` attempted the `{}` syscall.\n\
                             Try running with `strace -ff` to identify the cause and open an issue: \
-                            https://github.com/cloud-hypervisor/cloud-hypervisor/issues/new\n"
+                            https://github.com/cloud-hypervisor/cloud-hypervisor/issues/new\n",
+                            thread.unwrap_or("unknown"),
+                            syscall
                         );
 
                         signal_hook::low_level::emulate_default_handler(SIGSYS).unwrap();
@@ -490,7 +769,6 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
 
     event!("vmm", "starting");
 
-    let hypervisor = hypervisor::new().map_err(Error::CreateHypervisor)?;
     let vmm_thread = vmm::start_vmm_thread(
         env!("CARGO_PKG_VERSION").to_string(),
         &api_socket_path,
@@ -500,15 +778,12 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         api_request_receiver,
         &seccomp_action,
         hypervisor,
+        #[cfg(feature = "gdb")]
+        gdb_socket_path,
     )
     .map_err(Error::StartVmmThread)?;
 
-    // Can't test for "vm-config" group as some have default values. The kernel
-    // is the only required option for booting the VM.
-    if cmd_arguments.is_present("kernel") || cmd_arguments.is_present("tdx") {
-        let vm_params = config::VmParams::from_arg_matches(&cmd_arguments);
-        let vm_config = config::VmConfig::parse(vm_params).map_err(Error::ParsingConfig)?;
-
+    if let Some(vm_config) = vm_config {
         // Create and boot the VM based off the VM config we just built.
         let sender = api_request_sender.clone();
         vmm::api::vm_create(
@@ -518,11 +793,11 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         )
         .map_err(Error::VmCreate)?;
         vmm::api::vm_boot(api_evt.try_clone().unwrap(), sender).map_err(Error::VmBoot)?;
-    } else if let Some(restore_params) = cmd_arguments.value_of("restore") {
+    } else if let Some(restore_config) = restore_config {
         vmm::api::vm_restore(
             api_evt.try_clone().unwrap(),
             api_request_sender,
-            Arc::new(config::RestoreConfig::parse(restore_params).map_err(Error::ParsingRestore)?),
+            Arc::new(restore_config),
         )
         .map_err(Error::VmRestore)?;
     }
@@ -617,6 +892,14 @@ mod unit_tests {
                     topology: None,
                     kvm_hyperv: false,
                     max_phys_bits: None,
+                    affinity: None,
+                    vmm_affinity: None,
+                    io_affinity: None,
+                    fifo_priority: None,
+                    model: None,
+                    features: None,
+                    exit_stats: false,
+                    exit_trace: None,
                 },
                 memory: MemoryConfig {
                     size: 536_870_912,
@@ -656,6 +939,8 @@ mod unit_tests {
                     iommu: false,
                 },
                 devices: None,
+                user_devices: None,
+                generic_vhost_user: None,
                 vsock: None,
                 iommu: false,
                 #[cfg(target_arch = "x86_64")]
@@ -664,6 +949,10 @@ mod unit_tests {
                 watchdog: false,
                 #[cfg(feature = "tdx")]
                 tdx: None,
+                #[cfg(feature = "gdb")]
+                gdb: false,
+                landlock_enable: false,
+                landlock_rules: None,
             };
 
             aver_eq!(tb, expected_vm_config, result_vm_config);
@@ -0,0 +1,193 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Sample vfio-user server emulating a trivial PCI GPIO controller.
+//!
+//! The device exposes a PCI configuration space and a single 32-bit memory
+//! BAR holding one register. Writing to the register updates the state of
+//! the pins and raises the INTx interrupt, while reading it returns the
+//! current state of the pins.
+//!
+//! Usage: gpio <socket_path>
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use vfio_bindings::bindings::vfio::*;
+use vfio_user::{IrqInfo, Region, Server, ServerBackend};
+
+const CONFIG_SPACE_SIZE: usize = 256;
+const BAR0_SIZE: u64 = 0x1000;
+
+const VENDOR_ID: u16 = 0x494f;
+const DEVICE_ID: u16 = 0x0dc8;
+// Class "Other" (0xff) and subclass "Other" (0x80).
+const CLASS_CODE: u32 = 0xff80_0000;
+// Interrupt pin INTA#.
+const INTERRUPT_PIN: u8 = 1;
+
+struct GpioDevice {
+    config: [u8; CONFIG_SPACE_SIZE],
+    pins: u32,
+    intx: Option<File>,
+}
+
+impl GpioDevice {
+    fn new() -> Self {
+        let mut config = [0u8; CONFIG_SPACE_SIZE];
+        config[0x0..0x2].copy_from_slice(&VENDOR_ID.to_le_bytes());
+        config[0x2..0x4].copy_from_slice(&DEVICE_ID.to_le_bytes());
+        config[0x8..0xc].copy_from_slice(&CLASS_CODE.to_le_bytes());
+        config[0x3d] = INTERRUPT_PIN;
+
+        GpioDevice {
+            config,
+            pins: 0,
+            intx: None,
+        }
+    }
+
+    fn trigger_intx(&mut self) -> io::Result<()> {
+        if let Some(intx) = &mut self.intx {
+            intx.write_all(&1u64.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check_access(offset: u64, len: usize, size: u64) -> io::Result<usize> {
+    if offset + len as u64 > size {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+
+    Ok(offset as usize)
+}
+
+impl ServerBackend for GpioDevice {
+    fn region_read(&mut self, region: u32, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        match region {
+            VFIO_PCI_CONFIG_REGION_INDEX => {
+                let offset = check_access(offset, data.len(), CONFIG_SPACE_SIZE as u64)?;
+                data.copy_from_slice(&self.config[offset..offset + data.len()]);
+            }
+            VFIO_PCI_BAR0_REGION_INDEX => {
+                check_access(offset, data.len(), BAR0_SIZE)?;
+                let pins = self.pins.to_le_bytes();
+                for (i, byte) in data.iter_mut().enumerate() {
+                    *byte = pins.get(offset as usize + i).copied().unwrap_or(0);
+                }
+            }
+            _ => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+
+        Ok(())
+    }
+
+    fn region_write(&mut self, region: u32, offset: u64, data: &[u8]) -> io::Result<()> {
+        match region {
+            VFIO_PCI_CONFIG_REGION_INDEX => {
+                let offset = check_access(offset, data.len(), CONFIG_SPACE_SIZE as u64)?;
+                self.config[offset..offset + data.len()].copy_from_slice(data);
+            }
+            VFIO_PCI_BAR0_REGION_INDEX => {
+                check_access(offset, data.len(), BAR0_SIZE)?;
+                let mut pins = self.pins.to_le_bytes();
+                for (i, byte) in data.iter().enumerate() {
+                    if let Some(pin) = pins.get_mut(offset as usize + i) {
+                        *pin = *byte;
+                    }
+                }
+                self.pins = u32::from_le_bytes(pins);
+                println!("GPIO pins: {:#034b}", self.pins);
+                self.trigger_intx()?;
+            }
+            _ => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+
+        Ok(())
+    }
+
+    fn dma_map(
+        &mut self,
+        _flags: u32,
+        _offset: u64,
+        _address: u64,
+        _size: u64,
+        _fd: Option<File>,
+    ) -> io::Result<()> {
+        // The device never performs DMA, the memory is simply ignored.
+        Ok(())
+    }
+
+    fn dma_unmap(&mut self, _flags: u32, _address: u64, _size: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.pins = 0;
+        Ok(())
+    }
+
+    fn set_irqs(
+        &mut self,
+        index: u32,
+        flags: u32,
+        _start: u32,
+        _count: u32,
+        mut fds: Vec<File>,
+    ) -> io::Result<()> {
+        if index != VFIO_PCI_INTX_IRQ_INDEX {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        if flags & VFIO_IRQ_SET_ACTION_TRIGGER != 0 {
+            self.intx = fds.pop();
+        }
+
+        Ok(())
+    }
+}
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: gpio <socket_path>");
+            std::process::exit(1);
+        }
+    };
+
+    let regions = vec![
+        Region {
+            flags: VFIO_REGION_INFO_FLAG_READ | VFIO_REGION_INFO_FLAG_WRITE,
+            index: VFIO_PCI_CONFIG_REGION_INDEX,
+            size: CONFIG_SPACE_SIZE as u64,
+            file_offset: None,
+        },
+        Region {
+            flags: VFIO_REGION_INFO_FLAG_READ | VFIO_REGION_INFO_FLAG_WRITE,
+            index: VFIO_PCI_BAR0_REGION_INDEX,
+            size: BAR0_SIZE,
+            file_offset: None,
+        },
+    ];
+    let irqs = vec![IrqInfo {
+        index: VFIO_PCI_INTX_IRQ_INDEX,
+        flags: VFIO_IRQ_INFO_EVENTFD,
+        count: 1,
+    }];
+
+    let server = Server::new(Path::new(&path), true, regions, irqs).unwrap_or_else(|e| {
+        eprintln!("Error creating the server: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut device = GpioDevice::new();
+    if let Err(e) = server.run(&mut device) {
+        eprintln!("Error running the server: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,950 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Implementation of the vfio-user protocol, allowing a VMM to drive a PCI
+//! device emulated by a separate process, connected through a UNIX socket.
+//!
+//! The `Client` is used from the VMM side, while the `Server` can be used to
+//! implement device emulators speaking the same protocol.
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::num::Wrapping;
+use std::os::unix::io::RawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use thiserror::Error;
+use vfio_bindings::bindings::vfio::*;
+use vm_memory::{ByteValued, FileOffset};
+use vmm_sys_util::sock_ctrl_msg::ScmSocket;
+
+mod server;
+
+pub use server::{Server, ServerBackend};
+
+// Commands defined by the vfio-user protocol specification.
+const COMMAND_VERSION: u16 = 1;
+const COMMAND_DMA_MAP: u16 = 2;
+const COMMAND_DMA_UNMAP: u16 = 3;
+const COMMAND_DEVICE_GET_INFO: u16 = 4;
+const COMMAND_DEVICE_GET_REGION_INFO: u16 = 5;
+const COMMAND_DEVICE_GET_IRQ_INFO: u16 = 7;
+const COMMAND_DEVICE_SET_IRQS: u16 = 8;
+const COMMAND_REGION_READ: u16 = 9;
+const COMMAND_REGION_WRITE: u16 = 10;
+const COMMAND_DEVICE_RESET: u16 = 13;
+
+// Message header flags.
+const HEADER_FLAGS_COMMAND: u32 = 0;
+const HEADER_FLAGS_REPLY: u32 = 1;
+const HEADER_FLAGS_TYPE_MASK: u32 = 0xf;
+const HEADER_FLAGS_ERROR: u32 = 1 << 5;
+
+// Version of the protocol implemented by this crate.
+const PROTOCOL_MAJOR: u16 = 0;
+const PROTOCOL_MINOR: u16 = 1;
+
+// Maximum number of file descriptors carried by a single message.
+const MAX_MESSAGE_FDS: usize = 16;
+// Maximum amount of data transferred by a single region access.
+const MAX_DATA_TRANSFER_SIZE: u64 = 1 << 20;
+// Maximum size of a message, leaving room for the header and the fields
+// accompanying the largest region access, or for the information about a
+// region along with its capabilities.
+const MAX_MESSAGE_SIZE: u64 = MAX_DATA_TRANSFER_SIZE + 4096;
+
+/// DMA mapping is readable by the device.
+pub const DMA_MAP_FLAG_READ: u32 = 1;
+/// DMA mapping is writable by the device.
+pub const DMA_MAP_FLAG_WRITE: u32 = 1 << 1;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Error connecting to the server: {0}")]
+    Connect(#[source] std::io::Error),
+    #[error("Error accepting connection from the client: {0}")]
+    Accept(#[source] std::io::Error),
+    #[error("Error binding the server socket: {0}")]
+    Bind(#[source] std::io::Error),
+    #[error("Error sending message: {0}")]
+    SendMessage(#[source] std::io::Error),
+    #[error("Error receiving message: {0}")]
+    ReceiveMessage(#[source] std::io::Error),
+    #[error("Connection closed by the peer")]
+    ConnectionClosed,
+    #[error("Unexpected reply for message {0} (command {1})")]
+    UnexpectedReply(u16, u16),
+    #[error("Error returned by the server: {0}")]
+    Server(#[source] std::io::Error),
+    #[error("Invalid message size: {0}")]
+    InvalidMessageSize(u32),
+    #[error("Error serializing capabilities: {0}")]
+    SerializeCapabilities(#[source] serde_json::Error),
+    #[error("Error deserializing capabilities: {0}")]
+    DeserializeCapabilities(#[source] serde_json::Error),
+    #[error("Unsupported protocol version {0}.{1}")]
+    UnsupportedVersion(u16, u16),
+    #[error("Missing file descriptor for mappable region {0}")]
+    MissingRegionFd(u32),
+    #[error("Invalid region index {0}")]
+    InvalidRegion(u32),
+    #[error("Region access too large: {0} bytes")]
+    RegionAccessTooLarge(usize),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+struct Header {
+    message_id: u16,
+    command: u16,
+    message_size: u32,
+    flags: u32,
+    error: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for Header {}
+
+impl Header {
+    fn command(message_id: u16, command: u16, message_size: usize) -> Self {
+        Header {
+            message_id,
+            command,
+            message_size: message_size as u32,
+            flags: HEADER_FLAGS_COMMAND,
+            error: 0,
+        }
+    }
+
+    fn reply(&self, message_size: usize) -> Self {
+        Header {
+            message_id: self.message_id,
+            command: self.command,
+            message_size: message_size as u32,
+            flags: HEADER_FLAGS_REPLY,
+            error: 0,
+        }
+    }
+
+    fn is_reply(&self) -> bool {
+        self.flags & HEADER_FLAGS_TYPE_MASK == HEADER_FLAGS_REPLY
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+struct Version {
+    header: Header,
+    major: u16,
+    minor: u16,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for Version {}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Capabilities {
+    max_msg_fds: u32,
+    max_data_xfer_size: u64,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            max_msg_fds: MAX_MESSAGE_FDS as u32,
+            max_data_xfer_size: MAX_DATA_TRANSFER_SIZE,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct VersionCapabilities {
+    capabilities: Capabilities,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+struct DmaMap {
+    header: Header,
+    argsz: u32,
+    flags: u32,
+    offset: u64,
+    address: u64,
+    size: u64,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for DmaMap {}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+struct DmaUnmap {
+    header: Header,
+    argsz: u32,
+    flags: u32,
+    address: u64,
+    size: u64,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for DmaUnmap {}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+struct DeviceGetInfo {
+    header: Header,
+    argsz: u32,
+    flags: u32,
+    num_regions: u32,
+    num_irqs: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for DeviceGetInfo {}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+struct DeviceGetRegionInfo {
+    header: Header,
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    cap_offset: u32,
+    size: u64,
+    offset: u64,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for DeviceGetRegionInfo {}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+struct DeviceGetIrqInfo {
+    header: Header,
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    count: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for DeviceGetIrqInfo {}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+struct DeviceSetIrqs {
+    header: Header,
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    start: u32,
+    count: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for DeviceSetIrqs {}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+struct RegionAccess {
+    header: Header,
+    offset: u64,
+    region: u32,
+    count: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for RegionAccess {}
+
+/// Description of a region exposed by the device.
+#[derive(Clone, Debug)]
+pub struct Region {
+    /// Region flags, using the `VFIO_REGION_INFO_FLAG_*` definitions.
+    pub flags: u32,
+    /// Region index, using the `VFIO_PCI_*_REGION_INDEX` definitions.
+    pub index: u32,
+    /// Size of the region.
+    pub size: u64,
+    /// File descriptor and offset the region can be mapped from, only set
+    /// if the region is mappable.
+    pub file_offset: Option<FileOffset>,
+}
+
+/// Description of an interrupt index exposed by the device.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IrqInfo {
+    /// Interrupt index, using the `VFIO_PCI_*_IRQ_INDEX` definitions.
+    pub index: u32,
+    /// Interrupt flags, using the `VFIO_IRQ_INFO_*` definitions.
+    pub flags: u32,
+    /// Number of interrupts for this index.
+    pub count: u32,
+}
+
+// Receive a full message header from the stream, along with any file
+// descriptor passed with it. The header is received first so that file
+// descriptors are never lost, as they are attached to the first byte of
+// the message.
+fn recv_header(stream: &mut UnixStream, fds: &mut Vec<File>) -> Result<Header> {
+    let mut header = Header::default();
+    let mut raw_fds = [0; MAX_MESSAGE_FDS];
+    let buf = header.as_mut_slice();
+    let mut iovecs = [libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    }];
+
+    // Safe because the iovec points to the header buffer, which is valid
+    // for the whole duration of the call.
+    let (len, fd_count) = unsafe {
+        stream
+            .recv_with_fds(&mut iovecs[..], &mut raw_fds[..])
+            .map_err(|e| Error::ReceiveMessage(e.into()))?
+    };
+
+    for fd in raw_fds.iter().take(fd_count) {
+        // Safe because the file descriptors were received with the message
+        // and therefore are owned by us.
+        fds.push(unsafe { std::os::unix::io::FromRawFd::from_raw_fd(*fd) });
+    }
+
+    if len == 0 {
+        return Err(Error::ConnectionClosed);
+    }
+
+    stream
+        .read_exact(&mut header.as_mut_slice()[len..])
+        .map_err(Error::ReceiveMessage)?;
+
+    Ok(header)
+}
+
+// Receive the payload of the message the header was received for. The size
+// is checked before allocating anything, as it comes from the peer.
+fn recv_payload(stream: &mut UnixStream, header: &Header) -> Result<Vec<u8>> {
+    let message_size = header.message_size as usize;
+    if message_size < std::mem::size_of::<Header>()
+        || u64::from(header.message_size) > MAX_MESSAGE_SIZE
+    {
+        return Err(Error::InvalidMessageSize(header.message_size));
+    }
+
+    let mut payload = vec![0u8; message_size - std::mem::size_of::<Header>()];
+    stream
+        .read_exact(&mut payload)
+        .map_err(Error::ReceiveMessage)?;
+
+    Ok(payload)
+}
+
+fn send_message(stream: &mut UnixStream, bufs: &[&[u8]], fds: &[RawFd]) -> Result<()> {
+    if fds.is_empty() {
+        for buf in bufs {
+            stream.write_all(buf).map_err(Error::SendMessage)?;
+        }
+    } else {
+        // File descriptors are sent along with the first buffer. The
+        // remaining data is written as a regular stream of bytes.
+        let sent = stream
+            .send_with_fds(&bufs[..1], fds)
+            .map_err(|e| Error::SendMessage(e.into()))?;
+        stream
+            .write_all(&bufs[0][sent..])
+            .map_err(Error::SendMessage)?;
+        for buf in &bufs[1..] {
+            stream.write_all(buf).map_err(Error::SendMessage)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Client side of the vfio-user protocol, used by the VMM to access the
+/// device emulated by the server.
+pub struct Client {
+    stream: UnixStream,
+    next_message_id: Wrapping<u16>,
+    flags: u32,
+    regions: Vec<Region>,
+    irqs: Vec<IrqInfo>,
+}
+
+impl Client {
+    /// Connect to the vfio-user server listening on `path`, and retrieve
+    /// the description of the device.
+    pub fn new(path: &Path) -> Result<Client> {
+        let stream = UnixStream::connect(path).map_err(Error::Connect)?;
+
+        let mut client = Client {
+            stream,
+            next_message_id: Wrapping(0),
+            flags: 0,
+            regions: Vec::new(),
+            irqs: Vec::new(),
+        };
+
+        client.negotiate_version()?;
+        client.get_device_info()?;
+
+        Ok(client)
+    }
+
+    fn next_header(&mut self, command: u16, message_size: usize) -> Header {
+        let header = Header::command(self.next_message_id.0, command, message_size);
+        self.next_message_id += Wrapping(1);
+        header
+    }
+
+    // Wait for the reply matching the command described by `header`, and
+    // fill `reply` with it. Any payload following the reply structure is
+    // returned, along with the file descriptors received.
+    fn recv_reply<T: ByteValued>(
+        &mut self,
+        header: &Header,
+        reply: &mut T,
+    ) -> Result<(Vec<u8>, Vec<File>)> {
+        let mut fds = Vec::new();
+        let reply_header = recv_header(&mut self.stream, &mut fds)?;
+
+        if !reply_header.is_reply()
+            || reply_header.message_id != header.message_id
+            || reply_header.command != header.command
+        {
+            return Err(Error::UnexpectedReply(
+                reply_header.message_id,
+                reply_header.command,
+            ));
+        }
+
+        let header_size = std::mem::size_of::<Header>();
+        let message_size = reply_header.message_size as usize;
+        let mut payload = recv_payload(&mut self.stream, &reply_header)?;
+
+        if reply_header.flags & HEADER_FLAGS_ERROR != 0 {
+            return Err(Error::Server(std::io::Error::from_raw_os_error(
+                reply_header.error as i32,
+            )));
+        }
+
+        let reply_size = std::mem::size_of::<T>();
+        if message_size < reply_size {
+            return Err(Error::InvalidMessageSize(reply_header.message_size));
+        }
+
+        let reply_slice = reply.as_mut_slice();
+        reply_slice[..header_size].copy_from_slice(reply_header.as_slice());
+        reply_slice[header_size..].copy_from_slice(&payload[..reply_size - header_size]);
+
+        Ok((payload.split_off(reply_size - header_size), fds))
+    }
+
+    fn negotiate_version(&mut self) -> Result<()> {
+        let caps = serde_json::to_string(&VersionCapabilities::default())
+            .map_err(Error::SerializeCapabilities)?;
+        // The capabilities are sent as a NUL terminated string.
+        let mut caps = caps.into_bytes();
+        caps.push(0);
+
+        let version = Version {
+            header: self.next_header(COMMAND_VERSION, std::mem::size_of::<Version>() + caps.len()),
+            major: PROTOCOL_MAJOR,
+            minor: PROTOCOL_MINOR,
+        };
+        debug!("Command: {:?}", version);
+        send_message(&mut self.stream, &[version.as_slice(), &caps], &[])?;
+
+        let mut reply = Version::default();
+        let (server_caps, _) = self.recv_reply(&version.header, &mut reply)?;
+        debug!("Reply: {:?}", reply);
+
+        if reply.major != PROTOCOL_MAJOR {
+            return Err(Error::UnsupportedVersion(reply.major, reply.minor));
+        }
+
+        let server_caps = String::from_utf8_lossy(&server_caps);
+        let server_caps: VersionCapabilities =
+            serde_json::from_str(server_caps.trim_end_matches(char::from(0)))
+                .map_err(Error::DeserializeCapabilities)?;
+        debug!("Server capabilities: {:?}", server_caps);
+
+        Ok(())
+    }
+
+    fn get_device_info(&mut self) -> Result<()> {
+        let get_info = DeviceGetInfo {
+            header: self.next_header(
+                COMMAND_DEVICE_GET_INFO,
+                std::mem::size_of::<DeviceGetInfo>(),
+            ),
+            argsz: (std::mem::size_of::<DeviceGetInfo>() - std::mem::size_of::<Header>()) as u32,
+            ..Default::default()
+        };
+        debug!("Command: {:?}", get_info);
+        send_message(&mut self.stream, &[get_info.as_slice()], &[])?;
+
+        let mut reply = DeviceGetInfo::default();
+        self.recv_reply(&get_info.header, &mut reply)?;
+        debug!("Reply: {:?}", reply);
+
+        self.flags = reply.flags;
+        self.regions = self.get_regions(reply.num_regions)?;
+        self.irqs = self.get_irqs(reply.num_irqs)?;
+
+        Ok(())
+    }
+
+    fn get_regions(&mut self, num_regions: u32) -> Result<Vec<Region>> {
+        let mut regions = Vec::new();
+
+        for index in 0..num_regions {
+            let get_region_info = DeviceGetRegionInfo {
+                header: self.next_header(
+                    COMMAND_DEVICE_GET_REGION_INFO,
+                    std::mem::size_of::<DeviceGetRegionInfo>(),
+                ),
+                argsz: (std::mem::size_of::<DeviceGetRegionInfo>() - std::mem::size_of::<Header>())
+                    as u32,
+                index,
+                ..Default::default()
+            };
+            debug!("Command: {:?}", get_region_info);
+            send_message(&mut self.stream, &[get_region_info.as_slice()], &[])?;
+
+            let mut reply = DeviceGetRegionInfo::default();
+            let (_, mut fds) = self.recv_reply(&get_region_info.header, &mut reply)?;
+            debug!("Reply: {:?}", reply);
+
+            // Skip the indexes which are not backed by any region.
+            if reply.size == 0 {
+                continue;
+            }
+
+            let file_offset = if reply.flags & VFIO_REGION_INFO_FLAG_MMAP != 0 {
+                if fds.is_empty() {
+                    return Err(Error::MissingRegionFd(index));
+                }
+                Some(FileOffset::new(fds.remove(0), reply.offset))
+            } else {
+                None
+            };
+
+            regions.push(Region {
+                flags: reply.flags,
+                index: reply.index,
+                size: reply.size,
+                file_offset,
+            });
+        }
+
+        Ok(regions)
+    }
+
+    fn get_irqs(&mut self, num_irqs: u32) -> Result<Vec<IrqInfo>> {
+        let mut irqs = Vec::new();
+
+        for index in 0..num_irqs {
+            let get_irq_info = DeviceGetIrqInfo {
+                header: self.next_header(
+                    COMMAND_DEVICE_GET_IRQ_INFO,
+                    std::mem::size_of::<DeviceGetIrqInfo>(),
+                ),
+                argsz: (std::mem::size_of::<DeviceGetIrqInfo>() - std::mem::size_of::<Header>())
+                    as u32,
+                index,
+                ..Default::default()
+            };
+            debug!("Command: {:?}", get_irq_info);
+            send_message(&mut self.stream, &[get_irq_info.as_slice()], &[])?;
+
+            let mut reply = DeviceGetIrqInfo::default();
+            self.recv_reply(&get_irq_info.header, &mut reply)?;
+            debug!("Reply: {:?}", reply);
+
+            irqs.push(IrqInfo {
+                index: reply.index,
+                flags: reply.flags,
+                count: reply.count,
+            });
+        }
+
+        Ok(irqs)
+    }
+
+    /// Share the memory range starting at `offset` in the file `fd` with
+    /// the server, making it available for DMA at `address`.
+    pub fn dma_map(&mut self, offset: u64, address: u64, size: u64, fd: RawFd) -> Result<()> {
+        let dma_map = DmaMap {
+            header: self.next_header(COMMAND_DMA_MAP, std::mem::size_of::<DmaMap>()),
+            argsz: (std::mem::size_of::<DmaMap>() - std::mem::size_of::<Header>()) as u32,
+            flags: DMA_MAP_FLAG_READ | DMA_MAP_FLAG_WRITE,
+            offset,
+            address,
+            size,
+        };
+        debug!("Command: {:?}", dma_map);
+        send_message(&mut self.stream, &[dma_map.as_slice()], &[fd])?;
+
+        let mut reply = Header::default();
+        self.recv_reply(&dma_map.header, &mut reply)?;
+
+        Ok(())
+    }
+
+    /// Remove the DMA mapping previously created at `address`.
+    pub fn dma_unmap(&mut self, address: u64, size: u64) -> Result<()> {
+        let dma_unmap = DmaUnmap {
+            header: self.next_header(COMMAND_DMA_UNMAP, std::mem::size_of::<DmaUnmap>()),
+            argsz: (std::mem::size_of::<DmaUnmap>() - std::mem::size_of::<Header>()) as u32,
+            address,
+            size,
+            ..Default::default()
+        };
+        debug!("Command: {:?}", dma_unmap);
+        send_message(&mut self.stream, &[dma_unmap.as_slice()], &[])?;
+
+        let mut reply = DmaUnmap::default();
+        self.recv_reply(&dma_unmap.header, &mut reply)?;
+
+        Ok(())
+    }
+
+    /// Read `data.len()` bytes at `offset` from the region `region`.
+    pub fn region_read(&mut self, region: u32, offset: u64, data: &mut [u8]) -> Result<()> {
+        if data.len() as u64 > MAX_DATA_TRANSFER_SIZE {
+            return Err(Error::RegionAccessTooLarge(data.len()));
+        }
+
+        let region_read = RegionAccess {
+            header: self.next_header(COMMAND_REGION_READ, std::mem::size_of::<RegionAccess>()),
+            offset,
+            region,
+            count: data.len() as u32,
+        };
+        send_message(&mut self.stream, &[region_read.as_slice()], &[])?;
+
+        let mut reply = RegionAccess::default();
+        let (payload, _) = self.recv_reply(&region_read.header, &mut reply)?;
+        if payload.len() < data.len() {
+            return Err(Error::InvalidMessageSize(reply.header.message_size));
+        }
+        data.copy_from_slice(&payload[..data.len()]);
+
+        Ok(())
+    }
+
+    /// Write `data` at `offset` into the region `region`.
+    pub fn region_write(&mut self, region: u32, offset: u64, data: &[u8]) -> Result<()> {
+        if data.len() as u64 > MAX_DATA_TRANSFER_SIZE {
+            return Err(Error::RegionAccessTooLarge(data.len()));
+        }
+
+        let region_write = RegionAccess {
+            header: self.next_header(
+                COMMAND_REGION_WRITE,
+                std::mem::size_of::<RegionAccess>() + data.len(),
+            ),
+            offset,
+            region,
+            count: data.len() as u32,
+        };
+        send_message(&mut self.stream, &[region_write.as_slice(), data], &[])?;
+
+        let mut reply = RegionAccess::default();
+        self.recv_reply(&region_write.header, &mut reply)?;
+
+        Ok(())
+    }
+
+    /// Configure the interrupts `start..start + count` from the index
+    /// `index`, following the semantics of `VFIO_DEVICE_SET_IRQS`.
+    pub fn set_irqs(
+        &mut self,
+        index: u32,
+        flags: u32,
+        start: u32,
+        count: u32,
+        fds: &[RawFd],
+    ) -> Result<()> {
+        let set_irqs = DeviceSetIrqs {
+            header: self.next_header(
+                COMMAND_DEVICE_SET_IRQS,
+                std::mem::size_of::<DeviceSetIrqs>(),
+            ),
+            argsz: (std::mem::size_of::<DeviceSetIrqs>() - std::mem::size_of::<Header>()) as u32,
+            flags,
+            index,
+            start,
+            count,
+        };
+        debug!("Command: {:?}", set_irqs);
+        send_message(&mut self.stream, &[set_irqs.as_slice()], fds)?;
+
+        let mut reply = Header::default();
+        self.recv_reply(&set_irqs.header, &mut reply)?;
+
+        Ok(())
+    }
+
+    /// Enable the interrupts from `index`, each one of them being
+    /// triggered through the matching eventfd from `fds`.
+    pub fn enable_irqs(&mut self, index: u32, fds: &[RawFd]) -> Result<()> {
+        self.set_irqs(
+            index,
+            VFIO_IRQ_SET_DATA_EVENTFD | VFIO_IRQ_SET_ACTION_TRIGGER,
+            0,
+            fds.len() as u32,
+            fds,
+        )
+    }
+
+    /// Disable all the interrupts from `index`.
+    pub fn disable_irqs(&mut self, index: u32) -> Result<()> {
+        self.set_irqs(
+            index,
+            VFIO_IRQ_SET_DATA_NONE | VFIO_IRQ_SET_ACTION_TRIGGER,
+            0,
+            0,
+            &[],
+        )
+    }
+
+    /// Unmask the interrupt `start` from `index`.
+    pub fn unmask_irq(&mut self, index: u32, start: u32) -> Result<()> {
+        self.set_irqs(
+            index,
+            VFIO_IRQ_SET_DATA_NONE | VFIO_IRQ_SET_ACTION_UNMASK,
+            start,
+            1,
+            &[],
+        )
+    }
+
+    /// Reset the device.
+    pub fn reset(&mut self) -> Result<()> {
+        let reset = self.next_header(COMMAND_DEVICE_RESET, std::mem::size_of::<Header>());
+        debug!("Command: {:?}", reset);
+        send_message(&mut self.stream, &[reset.as_slice()], &[])?;
+
+        let mut reply = Header::default();
+        self.recv_reply(&reset, &mut reply)?;
+
+        Ok(())
+    }
+
+    /// Whether the device supports being reset.
+    pub fn resettable(&self) -> bool {
+        self.flags & VFIO_DEVICE_FLAGS_RESET != 0
+    }
+
+    /// Regions exposed by the device.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Look up the description of the region `index`.
+    pub fn region(&self, index: u32) -> Option<&Region> {
+        self.regions.iter().find(|r| r.index == index)
+    }
+
+    /// Look up the description of the interrupt index `index`.
+    pub fn irq_info(&self, index: u32) -> Option<IrqInfo> {
+        self.irqs.iter().find(|i| i.index == index).copied()
+    }
+
+    /// Close the connection with the server.
+    pub fn shutdown(&self) {
+        if let Err(e) = self.stream.shutdown(std::net::Shutdown::Both) {
+            error!("Error shutting down vfio-user connection: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[derive(Default)]
+    struct TestBackend {
+        config: Vec<u8>,
+        dma_maps: Arc<Mutex<Vec<(u64, u64, u64)>>>,
+        resets: Arc<Mutex<u32>>,
+    }
+
+    impl ServerBackend for TestBackend {
+        fn region_read(
+            &mut self,
+            region: u32,
+            offset: u64,
+            data: &mut [u8],
+        ) -> std::result::Result<(), std::io::Error> {
+            if region != VFIO_PCI_CONFIG_REGION_INDEX {
+                return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            let offset = offset as usize;
+            data.copy_from_slice(&self.config[offset..offset + data.len()]);
+            Ok(())
+        }
+
+        fn region_write(
+            &mut self,
+            region: u32,
+            offset: u64,
+            data: &[u8],
+        ) -> std::result::Result<(), std::io::Error> {
+            if region != VFIO_PCI_CONFIG_REGION_INDEX {
+                return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            let offset = offset as usize;
+            self.config[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn dma_map(
+            &mut self,
+            _flags: u32,
+            offset: u64,
+            address: u64,
+            size: u64,
+            _fd: Option<File>,
+        ) -> std::result::Result<(), std::io::Error> {
+            self.dma_maps.lock().unwrap().push((offset, address, size));
+            Ok(())
+        }
+
+        fn dma_unmap(
+            &mut self,
+            _flags: u32,
+            address: u64,
+            size: u64,
+        ) -> std::result::Result<(), std::io::Error> {
+            self.dma_maps
+                .lock()
+                .unwrap()
+                .retain(|m| m.1 != address || m.2 != size);
+            Ok(())
+        }
+
+        fn reset(&mut self) -> std::result::Result<(), std::io::Error> {
+            *self.resets.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        fn set_irqs(
+            &mut self,
+            _index: u32,
+            _flags: u32,
+            _start: u32,
+            _count: u32,
+            _fds: Vec<File>,
+        ) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_recv_payload_size() {
+        let (mut sender, mut receiver) = UnixStream::pair().unwrap();
+
+        let header = Header::command(0, COMMAND_VERSION, MAX_MESSAGE_SIZE as usize + 1);
+        assert!(matches!(
+            recv_payload(&mut receiver, &header),
+            Err(Error::InvalidMessageSize(_))
+        ));
+
+        let header = Header::command(0, COMMAND_VERSION, std::mem::size_of::<Header>() - 1);
+        assert!(matches!(
+            recv_payload(&mut receiver, &header),
+            Err(Error::InvalidMessageSize(_))
+        ));
+
+        let header = Header::command(0, COMMAND_VERSION, std::mem::size_of::<Header>() + 4);
+        sender.write_all(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(
+            recv_payload(&mut receiver, &header).unwrap(),
+            vec![1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_client_server() {
+        let tmp_dir = TempDir::new_with_prefix("/tmp/vfio_user_test").unwrap();
+        let socket = tmp_dir.as_path().join("vfio-user.sock");
+
+        let regions = vec![Region {
+            flags: VFIO_REGION_INFO_FLAG_READ | VFIO_REGION_INFO_FLAG_WRITE,
+            index: VFIO_PCI_CONFIG_REGION_INDEX,
+            size: 256,
+            file_offset: None,
+        }];
+        let irqs = vec![IrqInfo {
+            index: VFIO_PCI_INTX_IRQ_INDEX,
+            flags: VFIO_IRQ_INFO_EVENTFD,
+            count: 1,
+        }];
+        let server = Server::new(&socket, true, regions, irqs).unwrap();
+
+        let backend = TestBackend {
+            config: vec![0u8; 256],
+            ..Default::default()
+        };
+        let dma_maps = backend.dma_maps.clone();
+        let resets = backend.resets.clone();
+        let server_thread = thread::spawn(move || {
+            let mut backend = backend;
+            server.run(&mut backend).unwrap();
+        });
+
+        let mut client = Client::new(&socket).unwrap();
+        assert!(client.resettable());
+        assert_eq!(client.regions().len(), 1);
+        assert_eq!(
+            client.region(VFIO_PCI_CONFIG_REGION_INDEX).unwrap().size,
+            256
+        );
+        assert_eq!(client.irq_info(VFIO_PCI_INTX_IRQ_INDEX).unwrap().count, 1);
+        assert!(client.irq_info(VFIO_PCI_MSIX_IRQ_INDEX).is_none());
+
+        client
+            .region_write(VFIO_PCI_CONFIG_REGION_INDEX, 0x10, &[1, 2, 3, 4])
+            .unwrap();
+        let mut data = [0u8; 4];
+        client
+            .region_read(VFIO_PCI_CONFIG_REGION_INDEX, 0x10, &mut data)
+            .unwrap();
+        assert_eq!(data, [1, 2, 3, 4]);
+        assert!(client
+            .region_read(VFIO_PCI_BAR0_REGION_INDEX, 0, &mut data)
+            .is_err());
+
+        let file = vmm_sys_util::tempfile::TempFile::new().unwrap().into_file();
+        client
+            .dma_map(
+                0,
+                0x1000,
+                0x1000,
+                std::os::unix::io::AsRawFd::as_raw_fd(&file),
+            )
+            .unwrap();
+        assert_eq!(*dma_maps.lock().unwrap(), vec![(0, 0x1000, 0x1000)]);
+        client.dma_unmap(0x1000, 0x1000).unwrap();
+        assert!(dma_maps.lock().unwrap().is_empty());
+
+        client.reset().unwrap();
+        assert_eq!(*resets.lock().unwrap(), 1);
+
+        client.shutdown();
+        server_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,358 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::{
+    recv_header, recv_payload, send_message, DeviceGetInfo, DeviceGetIrqInfo, DeviceGetRegionInfo,
+    DeviceSetIrqs, DmaMap, DmaUnmap, Error, Header, IrqInfo, Region, RegionAccess, Result, Version,
+    VersionCapabilities, COMMAND_DEVICE_GET_INFO, COMMAND_DEVICE_GET_IRQ_INFO,
+    COMMAND_DEVICE_GET_REGION_INFO, COMMAND_DEVICE_RESET, COMMAND_DEVICE_SET_IRQS, COMMAND_DMA_MAP,
+    COMMAND_DMA_UNMAP, COMMAND_REGION_READ, COMMAND_REGION_WRITE, COMMAND_VERSION,
+    HEADER_FLAGS_ERROR, MAX_DATA_TRANSFER_SIZE, PROTOCOL_MAJOR, PROTOCOL_MINOR,
+};
+use std::fs::File;
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use vfio_bindings::bindings::vfio::*;
+use vm_memory::ByteValued;
+
+/// Trait implemented by the device emulated behind a vfio-user `Server`.
+pub trait ServerBackend {
+    /// Read `data.len()` bytes at `offset` from the region `region`.
+    fn region_read(
+        &mut self,
+        region: u32,
+        offset: u64,
+        data: &mut [u8],
+    ) -> std::result::Result<(), std::io::Error>;
+
+    /// Write `data` at `offset` into the region `region`.
+    fn region_write(
+        &mut self,
+        region: u32,
+        offset: u64,
+        data: &[u8],
+    ) -> std::result::Result<(), std::io::Error>;
+
+    /// Make the memory found at `offset` in `fd` available for DMA at
+    /// `address`.
+    fn dma_map(
+        &mut self,
+        flags: u32,
+        offset: u64,
+        address: u64,
+        size: u64,
+        fd: Option<File>,
+    ) -> std::result::Result<(), std::io::Error>;
+
+    /// Remove the DMA mapping found at `address`.
+    fn dma_unmap(
+        &mut self,
+        flags: u32,
+        address: u64,
+        size: u64,
+    ) -> std::result::Result<(), std::io::Error>;
+
+    /// Reset the device.
+    fn reset(&mut self) -> std::result::Result<(), std::io::Error>;
+
+    /// Configure the device interrupts, following the semantics of
+    /// `VFIO_DEVICE_SET_IRQS`.
+    fn set_irqs(
+        &mut self,
+        index: u32,
+        flags: u32,
+        start: u32,
+        count: u32,
+        fds: Vec<File>,
+    ) -> std::result::Result<(), std::io::Error>;
+}
+
+/// Server side of the vfio-user protocol, dispatching the requests from
+/// the VMM to a `ServerBackend`.
+pub struct Server {
+    listener: UnixListener,
+    resettable: bool,
+    regions: Vec<Region>,
+    irqs: Vec<IrqInfo>,
+}
+
+impl Server {
+    /// Create a server listening on `path`, exposing a PCI device made of
+    /// the given regions and interrupts.
+    pub fn new(
+        path: &Path,
+        resettable: bool,
+        regions: Vec<Region>,
+        irqs: Vec<IrqInfo>,
+    ) -> Result<Server> {
+        let listener = UnixListener::bind(path).map_err(Error::Bind)?;
+
+        Ok(Server {
+            listener,
+            resettable,
+            regions,
+            irqs,
+        })
+    }
+
+    /// Wait for a client to connect and process its requests until the
+    /// connection is closed.
+    pub fn run(&self, backend: &mut dyn ServerBackend) -> Result<()> {
+        let (mut stream, _) = self.listener.accept().map_err(Error::Accept)?;
+
+        loop {
+            let mut fds = Vec::new();
+            let header = match recv_header(&mut stream, &mut fds) {
+                Ok(header) => header,
+                Err(Error::ConnectionClosed) => {
+                    info!("vfio-user connection closed");
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+
+            let payload = recv_payload(&mut stream, &header)?;
+
+            self.handle_message(&mut stream, backend, header, &payload, fds)?;
+        }
+    }
+
+    fn handle_message(
+        &self,
+        stream: &mut UnixStream,
+        backend: &mut dyn ServerBackend,
+        header: Header,
+        payload: &[u8],
+        mut fds: Vec<File>,
+    ) -> Result<()> {
+        debug!("Command: {:?}", header);
+
+        match header.command {
+            COMMAND_VERSION => {
+                let request: Version = read_request(&header, payload)?;
+                if request.major != PROTOCOL_MAJOR {
+                    return send_error(stream, &header, libc::ENOTSUP);
+                }
+
+                let caps = serde_json::to_string(&VersionCapabilities::default())
+                    .map_err(Error::SerializeCapabilities)?;
+                let mut caps = caps.into_bytes();
+                caps.push(0);
+
+                let reply = Version {
+                    header: header.reply(size_of::<Version>() + caps.len()),
+                    major: PROTOCOL_MAJOR,
+                    minor: PROTOCOL_MINOR,
+                };
+                send_message(stream, &[reply.as_slice(), &caps], &[])
+            }
+            COMMAND_DMA_MAP => {
+                let request: DmaMap = read_request(&header, payload)?;
+                let fd = if fds.is_empty() {
+                    None
+                } else {
+                    Some(fds.remove(0))
+                };
+                match backend.dma_map(
+                    request.flags,
+                    request.offset,
+                    request.address,
+                    request.size,
+                    fd,
+                ) {
+                    Ok(()) => send_ack(stream, &header),
+                    Err(e) => send_io_error(stream, &header, e),
+                }
+            }
+            COMMAND_DMA_UNMAP => {
+                let request: DmaUnmap = read_request(&header, payload)?;
+                match backend.dma_unmap(request.flags, request.address, request.size) {
+                    Ok(()) => {
+                        let reply = DmaUnmap {
+                            header: header.reply(size_of::<DmaUnmap>()),
+                            ..request
+                        };
+                        send_message(stream, &[reply.as_slice()], &[])
+                    }
+                    Err(e) => send_io_error(stream, &header, e),
+                }
+            }
+            COMMAND_DEVICE_GET_INFO => {
+                let request: DeviceGetInfo = read_request(&header, payload)?;
+                let mut flags = VFIO_DEVICE_FLAGS_PCI;
+                if self.resettable {
+                    flags |= VFIO_DEVICE_FLAGS_RESET;
+                }
+                // Region and interrupt indexes follow the VFIO PCI layout,
+                // meaning the client will query every index up to the
+                // highest one.
+                let reply = DeviceGetInfo {
+                    header: header.reply(size_of::<DeviceGetInfo>()),
+                    argsz: request.argsz,
+                    flags,
+                    num_regions: self.regions.iter().map(|r| r.index + 1).max().unwrap_or(0),
+                    num_irqs: self.irqs.iter().map(|i| i.index + 1).max().unwrap_or(0),
+                };
+                send_message(stream, &[reply.as_slice()], &[])
+            }
+            COMMAND_DEVICE_GET_REGION_INFO => {
+                let request: DeviceGetRegionInfo = read_request(&header, payload)?;
+                let region = match self.regions.iter().find(|r| r.index == request.index) {
+                    Some(region) => region,
+                    None => {
+                        // Unused region indexes are reported as empty.
+                        let reply = DeviceGetRegionInfo {
+                            header: header.reply(size_of::<DeviceGetRegionInfo>()),
+                            argsz: request.argsz,
+                            index: request.index,
+                            ..Default::default()
+                        };
+                        return send_message(stream, &[reply.as_slice()], &[]);
+                    }
+                };
+
+                let reply = DeviceGetRegionInfo {
+                    header: header.reply(size_of::<DeviceGetRegionInfo>()),
+                    argsz: request.argsz,
+                    flags: region.flags,
+                    index: region.index,
+                    cap_offset: 0,
+                    size: region.size,
+                    offset: region
+                        .file_offset
+                        .as_ref()
+                        .map(|f| f.start())
+                        .unwrap_or_default(),
+                };
+                match &region.file_offset {
+                    Some(file_offset) => send_message(
+                        stream,
+                        &[reply.as_slice()],
+                        &[file_offset.file().as_raw_fd()],
+                    ),
+                    None => send_message(stream, &[reply.as_slice()], &[]),
+                }
+            }
+            COMMAND_DEVICE_GET_IRQ_INFO => {
+                let request: DeviceGetIrqInfo = read_request(&header, payload)?;
+                // Unused interrupt indexes are reported with no interrupt.
+                let irq = self
+                    .irqs
+                    .iter()
+                    .find(|i| i.index == request.index)
+                    .copied()
+                    .unwrap_or(IrqInfo {
+                        index: request.index,
+                        ..Default::default()
+                    });
+
+                let reply = DeviceGetIrqInfo {
+                    header: header.reply(size_of::<DeviceGetIrqInfo>()),
+                    argsz: request.argsz,
+                    flags: irq.flags,
+                    index: irq.index,
+                    count: irq.count,
+                };
+                send_message(stream, &[reply.as_slice()], &[])
+            }
+            COMMAND_DEVICE_SET_IRQS => {
+                let request: DeviceSetIrqs = read_request(&header, payload)?;
+                match backend.set_irqs(
+                    request.index,
+                    request.flags,
+                    request.start,
+                    request.count,
+                    fds,
+                ) {
+                    Ok(()) => send_ack(stream, &header),
+                    Err(e) => send_io_error(stream, &header, e),
+                }
+            }
+            COMMAND_REGION_READ => {
+                let request: RegionAccess = read_request(&header, payload)?;
+                if u64::from(request.count) > MAX_DATA_TRANSFER_SIZE {
+                    return send_error(stream, &header, libc::EINVAL);
+                }
+
+                let mut data = vec![0u8; request.count as usize];
+                match backend.region_read(request.region, request.offset, &mut data) {
+                    Ok(()) => {
+                        let reply = RegionAccess {
+                            header: header.reply(size_of::<RegionAccess>() + data.len()),
+                            ..request
+                        };
+                        send_message(stream, &[reply.as_slice(), &data], &[])
+                    }
+                    Err(e) => send_io_error(stream, &header, e),
+                }
+            }
+            COMMAND_REGION_WRITE => {
+                let request: RegionAccess = read_request(&header, payload)?;
+                let data = &payload[size_of::<RegionAccess>() - size_of::<Header>()..];
+                if data.len() < request.count as usize {
+                    return send_error(stream, &header, libc::EINVAL);
+                }
+
+                match backend.region_write(
+                    request.region,
+                    request.offset,
+                    &data[..request.count as usize],
+                ) {
+                    Ok(()) => {
+                        let reply = RegionAccess {
+                            header: header.reply(size_of::<RegionAccess>()),
+                            ..request
+                        };
+                        send_message(stream, &[reply.as_slice()], &[])
+                    }
+                    Err(e) => send_io_error(stream, &header, e),
+                }
+            }
+            COMMAND_DEVICE_RESET => match backend.reset() {
+                Ok(()) => send_ack(stream, &header),
+                Err(e) => send_io_error(stream, &header, e),
+            },
+            _ => {
+                warn!("Unsupported vfio-user command {}", header.command);
+                send_error(stream, &header, libc::ENOTSUP)
+            }
+        }
+    }
+}
+
+// Rebuild the full request structure from the header and the payload
+// which has been received separately.
+fn read_request<T: ByteValued>(header: &Header, payload: &[u8]) -> Result<T> {
+    let header_size = size_of::<Header>();
+    if payload.len() + header_size < size_of::<T>() {
+        return Err(Error::InvalidMessageSize(header.message_size));
+    }
+
+    let mut request = T::default();
+    let request_slice = request.as_mut_slice();
+    request_slice[..header_size].copy_from_slice(header.as_slice());
+    request_slice[header_size..].copy_from_slice(&payload[..size_of::<T>() - header_size]);
+
+    Ok(request)
+}
+
+fn send_ack(stream: &mut UnixStream, header: &Header) -> Result<()> {
+    let reply = header.reply(size_of::<Header>());
+    send_message(stream, &[reply.as_slice()], &[])
+}
+
+fn send_error(stream: &mut UnixStream, header: &Header, errno: i32) -> Result<()> {
+    let mut reply = header.reply(size_of::<Header>());
+    reply.flags |= HEADER_FLAGS_ERROR;
+    reply.error = errno as u32;
+    send_message(stream, &[reply.as_slice()], &[])
+}
+
+fn send_io_error(stream: &mut UnixStream, header: &Header, e: std::io::Error) -> Result<()> {
+    error!("Error handling vfio-user command {}: {}", header.command, e);
+    send_error(stream, header, e.raw_os_error().unwrap_or(libc::EIO))
+}
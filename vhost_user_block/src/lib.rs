@@ -8,7 +8,10 @@
 //
 // SPDX-License-Identifier: (Apache-2.0 AND BSD-3-Clause)
 
-use block_util::{build_disk_image_id, Request, VirtioBlockConfig};
+use block_util::{
+    build_disk_image_id, Request, VirtioBlockConfig, VIRTIO_BLK_F_DISCARD,
+    VIRTIO_BLK_F_WRITE_ZEROES,
+};
 use libc::EFD_NONBLOCK;
 use log::*;
 use option_parser::{OptionParser, OptionParserError, Toggle};
@@ -36,17 +39,23 @@ use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use vm_memory::ByteValued;
 use vm_memory::{Bytes, GuestMemoryMmap};
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::write_zeroes::{PunchHole, WriteZeroesAt};
 
 const SECTOR_SHIFT: u8 = 9;
 const SECTOR_SIZE: u64 = 0x01 << SECTOR_SHIFT;
 const BLK_SIZE: u32 = 512;
+const SIZE_MAX: u32 = 65535;
+// Drivers put all the discard and write zeroes segments of a request in a
+// single data descriptor, holding at most SIZE_MAX bytes of 16 bytes long
+// segments.
+const MAX_DISCARD_WRITE_ZEROES_SEG: u32 = SIZE_MAX / 16;
 // Current (2020) enterprise SSDs have a latency lower than 30us.
 // Polling for 50us should be enough to cover for the device latency
 // and the overhead of the emulation layer.
 const POLL_QUEUE_US: u128 = 50;
 
-trait DiskFile: Read + Seek + Write + Send + Sync {}
-impl<D: Read + Seek + Write + Send + Sync> DiskFile for D {}
+trait DiskFile: Read + Seek + Write + PunchHole + WriteZeroesAt + Send + Sync {}
+impl<D: Read + Seek + Write + PunchHole + WriteZeroesAt + Send + Sync> DiskFile for D {}
 
 type Result<T> = std::result::Result<T, Error>;
 type VhostUserBackendResult<T> = std::result::Result<T, std::io::Error>;
@@ -129,7 +138,7 @@ impl VhostUserBlkThread {
                     debug!("element is a valid request");
                     request.set_writeback(self.writeback.load(Ordering::Acquire));
                     let status = match request.execute(
-                        &mut self.disk_image.lock().unwrap().deref_mut(),
+                        self.disk_image.lock().unwrap().deref_mut(),
                         self.disk_nsectors,
                         mem,
                         &self.disk_image_id,
@@ -216,12 +225,19 @@ impl VhostUserBlkBackend {
         let config = VirtioBlockConfig {
             capacity: nsectors,
             blk_size: BLK_SIZE,
-            size_max: 65535,
+            size_max: SIZE_MAX,
             seg_max: 128 - 2,
             min_io_size: 1,
             opt_io_size: 1,
             num_queues: num_queues as u16,
             writeback: 1,
+            // Discard and write zeroes requests can cover the whole disk.
+            max_discard_sectors: u32::MAX,
+            max_discard_seg: MAX_DISCARD_WRITE_ZEROES_SEG,
+            discard_sector_alignment: 1,
+            max_write_zeroes_sectors: u32::MAX,
+            max_write_zeroes_seg: MAX_DISCARD_WRITE_ZEROES_SEG,
+            write_zeroes_may_unmap: 1,
             ..Default::default()
         };
 
@@ -292,6 +308,8 @@ impl VhostUserBackend for VhostUserBlkBackend {
 
         if self.rdonly {
             avail_features |= 1 << VIRTIO_BLK_F_RO;
+        } else {
+            avail_features |= 1 << VIRTIO_BLK_F_DISCARD | 1 << VIRTIO_BLK_F_WRITE_ZEROES;
         }
         avail_features
     }
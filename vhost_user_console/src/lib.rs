@@ -0,0 +1,461 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use libc::EFD_NONBLOCK;
+use log::*;
+use option_parser::{OptionParser, OptionParserError, Toggle};
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::num::Wrapping;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex, RwLock};
+use std::{convert, error, fmt, io};
+use vhost::vhost_user::message::*;
+use vhost::vhost_user::Listener;
+use vhost_user_backend::{VhostUserBackend, VhostUserDaemon, Vring, VringWorker};
+use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use vm_memory::{ByteValued, Bytes, GuestMemoryMmap};
+use vmm_sys_util::eventfd::EventFd;
+
+const QUEUE_SIZE: usize = 256;
+const NUM_QUEUES: usize = 2;
+
+const VIRTIO_F_VERSION_1: u32 = 32;
+
+// Receive and transmit queues of the only port.
+const RECEIVE_QUEUE_EVENT: u16 = 0;
+const TRANSMIT_QUEUE_EVENT: u16 = 1;
+// Some input is ready to be read from stdin.
+const INPUT_EVENT: u16 = 2;
+// The exit event is placed after all the other ones.
+const EXIT_EVENT: u16 = 3;
+
+type Result<T> = std::result::Result<T, Error>;
+type VhostUserBackendResult<T> = std::result::Result<T, std::io::Error>;
+
+#[derive(Debug)]
+enum Error {
+    /// Failed to create kill eventfd
+    CreateKillEventFd(io::Error),
+    /// Failed to create the output file
+    CreateOutputFile(io::Error),
+    /// Failed to parse configuration string
+    FailedConfigParse(OptionParserError),
+    /// Failed to signal used queue.
+    FailedSignalingUsedQueue(io::Error),
+    /// Failed to handle event other than input event.
+    HandleEventNotEpollIn,
+    /// Failed to handle unknown event.
+    HandleEventUnknownEvent,
+    /// Failed to read the input
+    ReadInput(io::Error),
+    /// No socket provided
+    SocketParameterMissing,
+}
+
+pub const SYNTAX: &str = "vhost-user-console backend parameters \
+\"socket=<socket_path>,file=<output_file>,client=on|off\"";
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "vhost_user_console_error: {:?}", self)
+    }
+}
+
+impl error::Error for Error {}
+
+impl convert::From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+struct VirtioConsoleConfig {
+    cols: u16,
+    rows: u16,
+    max_nr_ports: u32,
+    emerg_wr: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioConsoleConfig {}
+
+struct VhostUserConsoleThread {
+    mem: Option<GuestMemoryMmap>,
+    in_buffer: VecDeque<u8>,
+    out: Box<dyn io::Write + Send + Sync>,
+    input: Option<File>,
+    vring_worker: Option<Arc<VringWorker>>,
+    event_idx: bool,
+    kill_evt: EventFd,
+}
+
+impl VhostUserConsoleThread {
+    fn new(out: Box<dyn io::Write + Send + Sync>, input: Option<File>) -> Result<Self> {
+        Ok(VhostUserConsoleThread {
+            mem: None,
+            in_buffer: VecDeque::new(),
+            out,
+            input,
+            vring_worker: None,
+            event_idx: false,
+            kill_evt: EventFd::new(EFD_NONBLOCK).map_err(Error::CreateKillEventFd)?,
+        })
+    }
+
+    fn set_vring_worker(&mut self, vring_worker: Arc<VringWorker>) {
+        if let Some(input) = &self.input {
+            if let Err(e) = vring_worker.register_listener(
+                input.as_raw_fd(),
+                epoll::Events::EPOLLIN,
+                u64::from(INPUT_EVENT),
+            ) {
+                error!("Failed registering input listener: {:?}", e);
+            }
+        }
+        self.vring_worker = Some(vring_worker);
+    }
+
+    // Stop listening for input, which is needed once the end of the input
+    // has been reached since the epoll loop would otherwise keep spinning.
+    fn close_input(&mut self) {
+        if let (Some(input), Some(vring_worker)) = (self.input.take(), &self.vring_worker) {
+            if let Err(e) = vring_worker.unregister_listener(
+                input.as_raw_fd(),
+                epoll::Events::EPOLLIN,
+                u64::from(INPUT_EVENT),
+            ) {
+                error!("Failed unregistering input listener: {:?}", e);
+            }
+        }
+    }
+
+    fn read_input(&mut self) -> Result<()> {
+        let mut out = [0u8; 64];
+        let count = match self.input.as_mut() {
+            Some(input) => input.read(&mut out).map_err(Error::ReadInput)?,
+            None => return Ok(()),
+        };
+
+        if count == 0 {
+            self.close_input();
+        } else {
+            self.in_buffer.extend(&out[..count]);
+        }
+
+        Ok(())
+    }
+
+    fn signal_used_queue(&self, vring: &mut Vring, used_idx: u16) -> Result<()> {
+        let mem = self.mem.as_ref().unwrap();
+        if !self.event_idx
+            || vring
+                .mut_queue()
+                .needs_notification(mem, Wrapping(used_idx))
+        {
+            vring
+                .signal_used_queue()
+                .map_err(Error::FailedSignalingUsedQueue)?;
+        }
+
+        Ok(())
+    }
+
+    // Place the input data into the empty buffers provided by the driver
+    // through the receive queue.
+    fn process_receive_queue(&mut self, vring: &mut Vring) -> Result<bool> {
+        let mut used_any = false;
+        let mem = match self.mem.as_ref() {
+            Some(m) => m.clone(),
+            None => return Ok(false),
+        };
+
+        while !self.in_buffer.is_empty() {
+            let avail_desc = match vring.mut_queue().iter(&mem).next() {
+                Some(avail_desc) => avail_desc,
+                None => break,
+            };
+
+            let len = cmp::min(avail_desc.len as u32, self.in_buffer.len() as u32);
+            let source_slice = self.in_buffer.drain(..len as usize).collect::<Vec<u8>>();
+            if let Err(e) = mem.write_slice(&source_slice[..], avail_desc.addr) {
+                error!("Failed to write slice: {:?}", e);
+                vring.mut_queue().go_to_previous_position();
+                break;
+            }
+
+            if let Some(used_idx) = vring.mut_queue().add_used(&mem, avail_desc.index, len) {
+                self.signal_used_queue(vring, used_idx)?;
+                used_any = true;
+            }
+        }
+
+        Ok(used_any)
+    }
+
+    // Write the data the driver placed into the transmit queue to the
+    // output.
+    fn process_transmit_queue(&mut self, vring: &mut Vring) -> Result<bool> {
+        let mut used_any = false;
+        let mem = match self.mem.as_ref() {
+            Some(m) => m.clone(),
+            None => return Ok(false),
+        };
+
+        while let Some(avail_desc) = vring.mut_queue().iter(&mem).next() {
+            let _ = mem.write_to(avail_desc.addr, &mut self.out, avail_desc.len as usize);
+            let _ = self.out.flush();
+
+            if let Some(used_idx) =
+                vring
+                    .mut_queue()
+                    .add_used(&mem, avail_desc.index, avail_desc.len)
+            {
+                self.signal_used_queue(vring, used_idx)?;
+                used_any = true;
+            }
+        }
+
+        Ok(used_any)
+    }
+
+    fn process_queue(&mut self, device_event: u16, vring: &mut Vring) -> Result<bool> {
+        if device_event == RECEIVE_QUEUE_EVENT {
+            self.process_receive_queue(vring)
+        } else {
+            self.process_transmit_queue(vring)
+        }
+    }
+}
+
+struct VhostUserConsoleBackend {
+    thread: Mutex<VhostUserConsoleThread>,
+    config: VirtioConsoleConfig,
+}
+
+impl VhostUserConsoleBackend {
+    fn new(file: Option<PathBuf>) -> Result<Self> {
+        // The input is only read from stdin when the output is also going
+        // to stdout, which makes the daemon behave as a terminal.
+        let (out, input): (Box<dyn io::Write + Send + Sync>, Option<File>) = match file {
+            Some(path) => (
+                Box::new(File::create(path).map_err(Error::CreateOutputFile)?),
+                None,
+            ),
+            None => {
+                // Safe because stdin is valid for the whole life of the
+                // process, and the duplicated descriptor is owned by the
+                // newly created File.
+                let stdin = unsafe { libc::dup(libc::STDIN_FILENO) };
+                let input = if stdin >= 0 {
+                    Some(unsafe { File::from_raw_fd(stdin) })
+                } else {
+                    None
+                };
+                (Box::new(io::stdout()), input)
+            }
+        };
+
+        Ok(VhostUserConsoleBackend {
+            thread: Mutex::new(VhostUserConsoleThread::new(out, input)?),
+            config: VirtioConsoleConfig {
+                max_nr_ports: 1,
+                ..Default::default()
+            },
+        })
+    }
+}
+
+impl VhostUserBackend for VhostUserConsoleBackend {
+    fn num_queues(&self) -> usize {
+        NUM_QUEUES
+    }
+
+    fn max_queue_size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    fn features(&self) -> u64 {
+        1 << VIRTIO_F_VERSION_1
+            | 1 << VIRTIO_RING_F_EVENT_IDX
+            | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits()
+    }
+
+    fn protocol_features(&self) -> VhostUserProtocolFeatures {
+        VhostUserProtocolFeatures::CONFIG
+            | VhostUserProtocolFeatures::MQ
+            | VhostUserProtocolFeatures::REPLY_ACK
+            | VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS
+    }
+
+    fn set_event_idx(&mut self, enabled: bool) {
+        self.thread.lock().unwrap().event_idx = enabled;
+    }
+
+    fn update_memory(&mut self, mem: GuestMemoryMmap) -> VhostUserBackendResult<()> {
+        self.thread.lock().unwrap().mem = Some(mem);
+        Ok(())
+    }
+
+    fn handle_event(
+        &self,
+        device_event: u16,
+        evset: epoll::Events,
+        vrings: &[Arc<RwLock<Vring>>],
+        _thread_id: usize,
+    ) -> VhostUserBackendResult<bool> {
+        if evset != epoll::Events::EPOLLIN {
+            return Err(Error::HandleEventNotEpollIn.into());
+        }
+
+        let mut thread = self.thread.lock().unwrap();
+        let (queue_event, mut vring) = match device_event {
+            RECEIVE_QUEUE_EVENT => (RECEIVE_QUEUE_EVENT, vrings[0].write().unwrap()),
+            TRANSMIT_QUEUE_EVENT => (TRANSMIT_QUEUE_EVENT, vrings[1].write().unwrap()),
+            INPUT_EVENT => {
+                thread.read_input()?;
+                (RECEIVE_QUEUE_EVENT, vrings[0].write().unwrap())
+            }
+            _ => return Err(Error::HandleEventUnknownEvent.into()),
+        };
+
+        if thread.mem.is_none() {
+            return Ok(false);
+        }
+
+        if thread.event_idx {
+            // vm-virtio's Queue implementation only checks avail_index
+            // once, so to properly support EVENT_IDX we need to keep
+            // calling process_queue() until it stops finding new
+            // requests on the queue.
+            loop {
+                vring
+                    .mut_queue()
+                    .update_avail_event(thread.mem.as_ref().unwrap());
+                if !thread.process_queue(queue_event, &mut vring)? {
+                    break;
+                }
+            }
+        } else {
+            // Without EVENT_IDX, a single call is enough.
+            thread.process_queue(queue_event, &mut vring)?;
+        }
+
+        Ok(false)
+    }
+
+    fn get_config(&self, _offset: u32, _size: u32) -> Vec<u8> {
+        self.config.as_slice().to_vec()
+    }
+
+    fn exit_event(&self, _thread_index: usize) -> Option<(EventFd, Option<u16>)> {
+        Some((
+            self.thread.lock().unwrap().kill_evt.try_clone().unwrap(),
+            Some(EXIT_EVENT),
+        ))
+    }
+}
+
+struct VhostUserConsoleBackendConfig {
+    socket: String,
+    file: Option<PathBuf>,
+    client: bool,
+}
+
+impl VhostUserConsoleBackendConfig {
+    fn parse(backend: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("socket").add("file").add("client");
+        parser.parse(backend).map_err(Error::FailedConfigParse)?;
+
+        let socket = parser.get("socket").ok_or(Error::SocketParameterMissing)?;
+        let file = parser.get("file").map(PathBuf::from);
+        let client = parser
+            .convert::<Toggle>("client")
+            .map_err(Error::FailedConfigParse)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(VhostUserConsoleBackendConfig {
+            socket,
+            file,
+            client,
+        })
+    }
+}
+
+pub fn start_console_backend(backend_command: &str) {
+    let backend_config = match VhostUserConsoleBackendConfig::parse(backend_command) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed parsing parameters {:?}", e);
+            process::exit(1);
+        }
+    };
+
+    let console_backend = Arc::new(RwLock::new(
+        match VhostUserConsoleBackend::new(backend_config.file) {
+            Ok(backend) => backend,
+            Err(e) => {
+                eprintln!("Failed creating the backend: {:?}", e);
+                process::exit(1);
+            }
+        },
+    ));
+
+    let mut console_daemon = VhostUserDaemon::new(
+        "vhost-user-console-backend".to_string(),
+        console_backend.clone(),
+    )
+    .unwrap();
+
+    let mut vring_workers = console_daemon.get_vring_workers();
+
+    if vring_workers.len() != 1 {
+        error!("Number of vring workers must be identical to the number of backend threads");
+        process::exit(1);
+    }
+
+    console_backend
+        .read()
+        .unwrap()
+        .thread
+        .lock()
+        .unwrap()
+        .set_vring_worker(vring_workers.remove(0));
+
+    if let Err(e) = if backend_config.client {
+        console_daemon.start_client(&backend_config.socket)
+    } else {
+        console_daemon.start_server(Listener::new(&backend_config.socket, true).unwrap())
+    } {
+        error!(
+            "Failed to start daemon for vhost-user-console with error: {:?}",
+            e
+        );
+        process::exit(1);
+    }
+
+    if let Err(e) = console_daemon.wait() {
+        error!("Error from the main thread: {:?}", e);
+    }
+
+    if let Err(e) = console_backend
+        .read()
+        .unwrap()
+        .thread
+        .lock()
+        .unwrap()
+        .kill_evt
+        .write(1)
+    {
+        error!("Error shutting down worker thread: {:?}", e)
+    }
+}
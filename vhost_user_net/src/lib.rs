@@ -10,7 +10,8 @@ use libc::{self, EFD_NONBLOCK};
 use log::*;
 use net_util::CtrlQueue;
 use net_util::{
-    open_tap, MacAddr, NetCounters, NetQueuePair, OpenTapError, RxVirtio, Tap, TxVirtio,
+    open_tap, MacAddr, NetCounters, NetQueuePair, OpenTapError, PacketCapture, Rss, RxFilter,
+    RxVirtio, Tap, TxVirtio,
 };
 use option_parser::Toggle;
 use option_parser::{OptionParser, OptionParserError};
@@ -19,6 +20,7 @@ use std::io::{self};
 use std::net::Ipv4Addr;
 use std::os::unix::io::AsRawFd;
 use std::process;
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex, RwLock};
 use std::vec::Vec;
 use vhost::vhost_user::message::*;
@@ -81,7 +83,7 @@ struct VhostUserNetThread {
 
 impl VhostUserNetThread {
     /// Create a new virtio network device with the given TAP interface.
-    fn new(tap: Tap) -> Result<Self> {
+    fn new(tap: Tap, rx_filter: RxFilter) -> Result<Self> {
         Ok(VhostUserNetThread {
             kill_evt: EventFd::new(EFD_NONBLOCK).map_err(Error::CreateKillEventFd)?,
             net: NetQueuePair {
@@ -96,6 +98,11 @@ impl VhostUserNetThread {
                 rx_desc_avail: false,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                capture: PacketCapture::default(),
+                rx_filter,
+                rss: Rss::default(),
+                queue_pair_index: 0,
+                link_up: Arc::new(AtomicBool::new(true)),
             },
         })
     }
@@ -113,9 +120,9 @@ struct VhostUserNetCtrlThread {
 }
 
 impl VhostUserNetCtrlThread {
-    fn new(taps: Vec<Tap>, id: usize) -> Result<Self> {
+    fn new(taps: Vec<Tap>, rx_filter: RxFilter, id: usize) -> Result<Self> {
         Ok(VhostUserNetCtrlThread {
-            ctrl: CtrlQueue::new(taps),
+            ctrl: CtrlQueue::new(taps, rx_filter, None, Rss::default()),
             kill_evt: EventFd::new(EFD_NONBLOCK).map_err(Error::CreateKillEventFd)?,
             id,
             mem: None,
@@ -150,16 +157,25 @@ impl VhostUserNetBackend {
         )
         .map_err(Error::OpenTap)?;
 
+        // The guest address isn't known from the backend, which is why the
+        // receive filtering features aren't offered, leaving the filter
+        // inactive.
+        let rx_filter = RxFilter::default();
+
         let mut queues_per_thread = Vec::new();
         let mut threads = Vec::new();
         for (i, tap) in taps.clone().drain(..).enumerate() {
-            let thread = Mutex::new(VhostUserNetThread::new(tap)?);
+            let thread = Mutex::new(VhostUserNetThread::new(tap, rx_filter.clone())?);
             threads.push(thread);
             queues_per_thread.push(0b11 << (i * 2));
         }
 
         // Create a dedicated thread for the control queue.
-        let ctrl_thread = Mutex::new(VhostUserNetCtrlThread::new(taps.clone(), taps.len())?);
+        let ctrl_thread = Mutex::new(VhostUserNetCtrlThread::new(
+            taps.clone(),
+            rx_filter,
+            taps.len(),
+        )?);
         queues_per_thread.push(1 << num_queues);
 
         // Increase num_queues by 1 because of the control queue.
@@ -0,0 +1,307 @@
+// Copyright © 2021 Intel Corporation
+//
+// Portions Copyright 2017 The Chromium OS Authors. All rights reserved.
+//
+// SPDX-License-Identifier: (Apache-2.0 AND BSD-3-Clause)
+
+use libc::EFD_NONBLOCK;
+use log::*;
+use option_parser::{OptionParser, OptionParserError, Toggle};
+use std::fs::File;
+use std::num::Wrapping;
+use std::process;
+use std::sync::{Arc, Mutex, RwLock};
+use std::{convert, error, fmt, io};
+use vhost::vhost_user::message::*;
+use vhost::vhost_user::Listener;
+use vhost_user_backend::{VhostUserBackend, VhostUserDaemon, Vring};
+use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use vm_memory::{Bytes, GuestMemoryMmap};
+use vmm_sys_util::eventfd::EventFd;
+
+const QUEUE_SIZE: usize = 256;
+const NUM_QUEUES: usize = 1;
+
+const VIRTIO_F_VERSION_1: u32 = 32;
+
+type Result<T> = std::result::Result<T, Error>;
+type VhostUserBackendResult<T> = std::result::Result<T, std::io::Error>;
+
+#[derive(Debug)]
+enum Error {
+    /// Failed to create kill eventfd
+    CreateKillEventFd(io::Error),
+    /// Failed to parse configuration string
+    FailedConfigParse(OptionParserError),
+    /// Failed to signal used queue.
+    FailedSignalingUsedQueue(io::Error),
+    /// Failed to handle event other than input event.
+    HandleEventNotEpollIn,
+    /// Failed to handle unknown event.
+    HandleEventUnknownEvent,
+    /// Failed to open the entropy source
+    OpenEntropySource(io::Error),
+    /// No socket provided
+    SocketParameterMissing,
+}
+
+pub const SYNTAX: &str = "vhost-user-rng backend parameters \
+\"socket=<socket_path>,src=<entropy_source>,client=on|off,\
+queue_size=<size_of_queue>\"";
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "vhost_user_rng_error: {:?}", self)
+    }
+}
+
+impl error::Error for Error {}
+
+impl convert::From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+}
+
+struct VhostUserRngThread {
+    mem: Option<GuestMemoryMmap>,
+    random_file: File,
+    event_idx: bool,
+    kill_evt: EventFd,
+}
+
+impl VhostUserRngThread {
+    fn new(random_file: File) -> Result<Self> {
+        Ok(VhostUserRngThread {
+            mem: None,
+            random_file,
+            event_idx: false,
+            kill_evt: EventFd::new(EFD_NONBLOCK).map_err(Error::CreateKillEventFd)?,
+        })
+    }
+
+    fn process_queue(&mut self, vring: &mut Vring) -> Result<bool> {
+        let mut used_any = false;
+        let mem = match self.mem.as_ref() {
+            Some(m) => m,
+            None => return Ok(false),
+        };
+
+        while let Some(avail_desc) = vring.mut_queue().iter(mem).next() {
+            let mut len = 0;
+
+            // Drivers can only read from the random device.
+            if avail_desc.is_write_only() {
+                // Fill the read with data from the random device on the host.
+                if mem
+                    .read_from(
+                        avail_desc.addr,
+                        &mut self.random_file,
+                        avail_desc.len as usize,
+                    )
+                    .is_ok()
+                {
+                    len = avail_desc.len;
+                }
+            }
+
+            let queue = vring.mut_queue();
+            if let Some(used_idx) = queue.add_used(mem, avail_desc.index, len) {
+                if !self.event_idx || queue.needs_notification(mem, Wrapping(used_idx)) {
+                    vring
+                        .signal_used_queue()
+                        .map_err(Error::FailedSignalingUsedQueue)?;
+                }
+                used_any = true;
+            }
+        }
+
+        Ok(used_any)
+    }
+}
+
+struct VhostUserRngBackend {
+    thread: Mutex<VhostUserRngThread>,
+    queue_size: usize,
+}
+
+impl VhostUserRngBackend {
+    fn new(src: &str, queue_size: usize) -> Result<Self> {
+        let random_file = File::open(src).map_err(Error::OpenEntropySource)?;
+
+        Ok(VhostUserRngBackend {
+            thread: Mutex::new(VhostUserRngThread::new(random_file)?),
+            queue_size,
+        })
+    }
+}
+
+impl VhostUserBackend for VhostUserRngBackend {
+    fn num_queues(&self) -> usize {
+        NUM_QUEUES
+    }
+
+    fn max_queue_size(&self) -> usize {
+        self.queue_size
+    }
+
+    fn features(&self) -> u64 {
+        1 << VIRTIO_F_VERSION_1
+            | 1 << VIRTIO_RING_F_EVENT_IDX
+            | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits()
+    }
+
+    fn protocol_features(&self) -> VhostUserProtocolFeatures {
+        VhostUserProtocolFeatures::MQ
+            | VhostUserProtocolFeatures::REPLY_ACK
+            | VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS
+    }
+
+    fn set_event_idx(&mut self, enabled: bool) {
+        self.thread.lock().unwrap().event_idx = enabled;
+    }
+
+    fn update_memory(&mut self, mem: GuestMemoryMmap) -> VhostUserBackendResult<()> {
+        self.thread.lock().unwrap().mem = Some(mem);
+        Ok(())
+    }
+
+    fn handle_event(
+        &self,
+        device_event: u16,
+        evset: epoll::Events,
+        vrings: &[Arc<RwLock<Vring>>],
+        _thread_id: usize,
+    ) -> VhostUserBackendResult<bool> {
+        if evset != epoll::Events::EPOLLIN {
+            return Err(Error::HandleEventNotEpollIn.into());
+        }
+
+        let mut thread = self.thread.lock().unwrap();
+        match device_event {
+            0 => {
+                let mut vring = vrings[0].write().unwrap();
+
+                if thread.event_idx {
+                    // vm-virtio's Queue implementation only checks avail_index
+                    // once, so to properly support EVENT_IDX we need to keep
+                    // calling process_queue() until it stops finding new
+                    // requests on the queue.
+                    loop {
+                        vring
+                            .mut_queue()
+                            .update_avail_event(thread.mem.as_ref().unwrap());
+                        if !thread.process_queue(&mut vring)? {
+                            break;
+                        }
+                    }
+                } else {
+                    // Without EVENT_IDX, a single call is enough.
+                    thread.process_queue(&mut vring)?;
+                }
+
+                Ok(false)
+            }
+            _ => Err(Error::HandleEventUnknownEvent.into()),
+        }
+    }
+
+    fn exit_event(&self, _thread_index: usize) -> Option<(EventFd, Option<u16>)> {
+        // The exit event is placed after the queue, which is event index 1.
+        Some((
+            self.thread.lock().unwrap().kill_evt.try_clone().unwrap(),
+            Some(1),
+        ))
+    }
+}
+
+struct VhostUserRngBackendConfig {
+    socket: String,
+    src: String,
+    client: bool,
+    queue_size: usize,
+}
+
+impl VhostUserRngBackendConfig {
+    fn parse(backend: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("socket")
+            .add("src")
+            .add("client")
+            .add("queue_size");
+        parser.parse(backend).map_err(Error::FailedConfigParse)?;
+
+        let socket = parser.get("socket").ok_or(Error::SocketParameterMissing)?;
+        let src = parser
+            .get("src")
+            .unwrap_or_else(|| String::from("/dev/urandom"));
+        let client = parser
+            .convert::<Toggle>("client")
+            .map_err(Error::FailedConfigParse)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let queue_size = parser
+            .convert("queue_size")
+            .map_err(Error::FailedConfigParse)?
+            .unwrap_or(QUEUE_SIZE);
+
+        Ok(VhostUserRngBackendConfig {
+            socket,
+            src,
+            client,
+            queue_size,
+        })
+    }
+}
+
+pub fn start_rng_backend(backend_command: &str) {
+    let backend_config = match VhostUserRngBackendConfig::parse(backend_command) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed parsing parameters {:?}", e);
+            process::exit(1);
+        }
+    };
+
+    let rng_backend = Arc::new(RwLock::new(
+        match VhostUserRngBackend::new(&backend_config.src, backend_config.queue_size) {
+            Ok(backend) => backend,
+            Err(e) => {
+                eprintln!("Failed creating the backend: {:?}", e);
+                process::exit(1);
+            }
+        },
+    ));
+
+    let mut rng_daemon =
+        VhostUserDaemon::new("vhost-user-rng-backend".to_string(), rng_backend.clone()).unwrap();
+
+    if let Err(e) = if backend_config.client {
+        rng_daemon.start_client(&backend_config.socket)
+    } else {
+        rng_daemon.start_server(Listener::new(&backend_config.socket, true).unwrap())
+    } {
+        error!(
+            "Failed to start daemon for vhost-user-rng with error: {:?}",
+            e
+        );
+        process::exit(1);
+    }
+
+    if let Err(e) = rng_daemon.wait() {
+        error!("Error from the main thread: {:?}", e);
+    }
+
+    if let Err(e) = rng_backend
+        .read()
+        .unwrap()
+        .thread
+        .lock()
+        .unwrap()
+        .kill_evt
+        .write(1)
+    {
+        error!("Error shutting down worker thread: {:?}", e)
+    }
+}
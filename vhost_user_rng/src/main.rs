@@ -0,0 +1,27 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#[macro_use(crate_version, crate_authors)]
+extern crate clap;
+
+use clap::{App, Arg};
+use vhost_user_rng::start_rng_backend;
+
+fn main() {
+    let cmd_arguments = App::new("vhost-user-rng backend")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Launch a vhost-user-rng backend.")
+        .arg(
+            Arg::with_name("rng-backend")
+                .long("rng-backend")
+                .help(vhost_user_rng::SYNTAX)
+                .takes_value(true)
+                .min_values(1),
+        )
+        .get_matches();
+
+    let backend_command = cmd_arguments.value_of("rng-backend").unwrap();
+    start_rng_backend(backend_command);
+}
@@ -13,6 +13,8 @@
 #[macro_use]
 extern crate event_monitor;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 #[macro_use]
 extern crate serde_derive;
@@ -33,6 +35,7 @@ pub mod net_util;
 mod pmem;
 mod rng;
 pub mod seccomp_filters;
+pub mod seccomp_profile;
 pub mod transport;
 pub mod vhost_user;
 pub mod vsock;
@@ -81,12 +84,18 @@ pub enum ActivateError {
     VhostUserNetSetup(vhost_user::Error),
     /// Failed to setup vhost-user-blk daemon.
     VhostUserBlkSetup(vhost_user::Error),
+    /// Failed to setup generic vhost-user daemon.
+    VhostUserGenericSetup(vhost_user::Error),
     /// Failed to reset vhost-user daemon.
     VhostUserReset(vhost_user::Error),
     /// Cannot create seccomp filter
     CreateSeccompFilter(seccomp::SeccompError),
     /// Cannot create rate limiter
     CreateRateLimiter(std::io::Error),
+    /// Failed to setup the kernel vhost-net driver.
+    VhostNetSetup(net::Error),
+    /// Failed to setup the kernel vhost-vsock driver.
+    VhostVsockSetup(vsock::VhostVsockError),
 }
 
 pub type ActivateResult = std::result::Result<(), ActivateError>;
@@ -99,6 +108,8 @@ pub enum Error {
     IoError(io::Error),
     VhostUserUpdateMemory(vhost_user::Error),
     VhostUserAddMemoryRegion(vhost_user::Error),
+    VhostNetUpdateMemory(net::Error),
+    VhostVsockUpdateMemory(vsock::VhostVsockError),
     SetShmRegionsNotSupported,
     NetQueuePair(::net_util::NetQueuePairError),
     ApplySeccompFilter(seccomp::Error),
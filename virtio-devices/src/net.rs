@@ -16,16 +16,23 @@ use super::{
     EPOLL_HELPER_EVENT_LAST,
 };
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::vhost_user::vu_common_ctrl::vring_config_data;
+use crate::vhost_user::Error as VhostUserError;
 use crate::VirtioInterrupt;
+use anyhow::anyhow;
 use net_util::{
-    open_tap, MacAddr, NetCounters, NetQueuePair, OpenTapError, RxVirtio, Tap, TapError, TxVirtio,
+    open_tap, MacAddr, NetCounters, NetQueuePair, OpenTapError, PacketCapture, Rss, RxFilter,
+    RxFilterSnapshot, RxVirtio, Tap, TapError, TxVirtio, UserNet, UserNetConfig, UserNetError,
+    VhostNet, VhostNetError, RSS_MAX_INDIRECTION_TABLE_LEN, RSS_MAX_KEY_SIZE,
+    RSS_SUPPORTED_HASH_TYPES, VHOST_NET_RX_QUEUE, VHOST_NET_TX_QUEUE, VIRTIO_NET_F_HASH_REPORT,
+    VIRTIO_NET_F_RSS,
 };
 use seccomp::{SeccompAction, SeccompFilter};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::num::Wrapping;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::result;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::{Arc, Barrier};
 use std::thread;
 use std::vec::Vec;
@@ -34,7 +41,9 @@ use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_bindings::bindings::virtio_net::*;
 use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
-use vm_memory::{ByteValued, GuestAddressSpace, GuestMemoryAtomic, GuestMemoryMmap};
+use vm_memory::{
+    ByteValued, GuestAddressSpace, GuestMemoryAtomic, GuestMemoryMmap, GuestRegionMmap,
+};
 use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
 use vmm_sys_util::eventfd::EventFd;
@@ -49,6 +58,10 @@ pub const RX_TAP_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
 pub const RX_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
 // New 'wake up' event from the tx rate limiter
 pub const TX_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
+// Frames have been steered to this queue pair by another one.
+pub const RX_RSS_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 6;
+// vhost-net has used buffers of the queue whose index is added to this event.
+const VHOST_NET_CALL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 
 #[derive(Debug)]
 pub enum Error {
@@ -60,6 +73,33 @@ pub enum Error {
 
     // Error calling dup() on tap fd
     DuplicateTapFd(std::io::Error),
+
+    /// Failed to create the userspace network backend.
+    UserNet(UserNetError),
+
+    /// Failed to clone the userspace network backend kill eventfd.
+    CloneUserNetKillEvt(std::io::Error),
+
+    /// Failed to create the seccomp filter of the userspace network backend.
+    CreateSeccompFilter(seccomp::SeccompError),
+
+    /// Failed to spawn the userspace network backend thread.
+    SpawnUserNetThread(std::io::Error),
+
+    /// Failed to signal the configuration change to the guest.
+    FailedSignalingConfigChange(std::io::Error),
+
+    /// Failed to configure the kernel vhost-net driver.
+    VhostNet(VhostNetError),
+
+    /// Invalid virtqueue address given to the kernel vhost-net driver.
+    VhostNetVringAddress(VhostUserError),
+
+    /// Failed to create the eventfd vhost-net signals used buffers through.
+    VhostNetCallEventFd(std::io::Error),
+
+    /// Failed to create the receive side scaling backlogs.
+    CreateRss(std::io::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -114,6 +154,31 @@ impl NetEpollHandler {
             self.net.rx_tap_listening = true;
         }
 
+        // Frames steered to this queue pair may be waiting for buffers.
+        if !rate_limit_reached && self.net.rss.has_frames(self.net.queue_pair_index) {
+            self.handle_rx_tap_event()?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_rx_rss_event(&mut self) -> result::Result<(), DeviceError> {
+        if let Some(evt) = self.net.rss.backlog_evt(self.net.queue_pair_index) {
+            if let Err(e) = evt.read() {
+                error!("Failed to get RSS backlog event: {:?}", e);
+            }
+        }
+
+        let rate_limit_reached = self
+            .net
+            .rx_rate_limiter
+            .as_ref()
+            .map_or(false, |r| r.is_blocked());
+
+        if !rate_limit_reached {
+            self.handle_rx_tap_event()?;
+        }
+
         Ok(())
     }
 
@@ -180,6 +245,9 @@ impl NetEpollHandler {
         if let Some(rate_limiter) = &self.net.tx_rate_limiter {
             helper.add_event(rate_limiter.as_raw_fd(), TX_RATE_LIMITER_EVENT)?;
         }
+        if let Some(evt) = self.net.rss.backlog_evt(self.net.queue_pair_index) {
+            helper.add_event(evt.as_raw_fd(), RX_RSS_EVENT)?;
+        }
 
         // If there are some already available descriptors on the RX queue,
         // then we can start the thread while listening onto the TAP.
@@ -225,6 +293,12 @@ impl EpollHelperHandler for NetEpollHandler {
                     return true;
                 }
             }
+            RX_RSS_EVENT => {
+                if let Err(e) = self.handle_rx_rss_event() {
+                    error!("Error processing steered frames: {:?}", e);
+                    return true;
+                }
+            }
             RX_RATE_LIMITER_EVENT => {
                 if let Some(rate_limiter) = &mut self.net.rx_rate_limiter {
                     // Upon rate limiter event, call the rate limiter handler and register the
@@ -285,15 +359,108 @@ impl EpollHelperHandler for NetEpollHandler {
     }
 }
 
+// Relays the used buffer notifications of vhost-net to the guest when the
+// interrupts can't be injected directly through irqfd, which is the case
+// with legacy interrupts.
+struct VhostNetCallEpollHandler {
+    call_evts: Vec<EventFd>,
+    queues: Vec<Queue>,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+}
+
+impl VhostNetCallEpollHandler {
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        for (i, call_evt) in self.call_evts.iter().enumerate() {
+            helper.add_event(call_evt.as_raw_fd(), VHOST_NET_CALL_EVENT + i as u16)?;
+        }
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for VhostNetCallEpollHandler {
+    fn handle_event(&mut self, _helper: &mut EpollHelper, event: &epoll::Event) -> bool {
+        let ev_type = event.data as u16;
+        let index = match ev_type.checked_sub(VHOST_NET_CALL_EVENT) {
+            Some(index) if (index as usize) < self.call_evts.len() => index as usize,
+            _ => {
+                error!("Unknown event: {}", ev_type);
+                return true;
+            }
+        };
+
+        if let Err(e) = self.call_evts[index].read() {
+            error!("Failed to get call event: {:?}", e);
+            return true;
+        }
+        if let Err(e) = self
+            .interrupt_cb
+            .trigger(&VirtioInterruptType::Queue, Some(&self.queues[index]))
+        {
+            error!("Failed to signal used queue: {:?}", e);
+            return true;
+        }
+
+        false
+    }
+}
+
+// Don't offer ring features unknown to the vhost-net driver, nor the VLAN
+// filtering, RSS and hash reporting which can't be performed by the TAP
+// interface. The frames never go through the VMM either, so the RX filter
+// programmed by the guest can't apply, hence neither the RX mode nor the MAC
+// address filtering are offered.
+fn vhost_net_features(avail_features: u64, backend_features: u64) -> u64 {
+    let ring_features: u64 = 1 << VIRTIO_RING_F_EVENT_IDX | 1 << VIRTIO_F_VERSION_1;
+    let unsupported_features: u64 = 1 << VIRTIO_NET_F_CTRL_VLAN
+        | 1 << VIRTIO_NET_F_CTRL_RX
+        | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR
+        | 1 << VIRTIO_NET_F_RSS
+        | 1 << VIRTIO_NET_F_HASH_REPORT;
+
+    avail_features & !(ring_features & !backend_features) & !unsupported_features
+}
+
 pub struct Net {
     common: VirtioCommon,
     id: String,
     taps: Vec<Tap>,
     config: VirtioNetConfig,
+    // Status field of the configuration, shared with the control queue
+    // thread which acknowledges the guest announces.
+    status: Arc<AtomicU16>,
+    // Whether the link is up, shared with the queue pairs which drop the
+    // frames while it is down.
+    link_up: Arc<AtomicBool>,
+    // Set on restore, so that the guest announces itself once resumed.
+    announce_on_resume: bool,
+    // Receive filter restored from a snapshot, applied on activation
+    // instead of resetting the filter.
+    restored_rx_filter: Option<RxFilterSnapshot>,
     ctrl_queue_epoll_thread: Option<thread::JoinHandle<()>>,
     counters: NetCounters,
+    capture: PacketCapture,
+    rx_filter: RxFilter,
+    rss: Rss,
     seccomp_action: SeccompAction,
     rate_limiter_config: Option<RateLimiterConfig>,
+    // Set when the device is backed by the userspace network stack rather
+    // than by a TAP interface.
+    user_net_kill_evt: Option<EventFd>,
+    user_net_thread: Option<thread::JoinHandle<()>>,
+    // One kernel vhost-net instance per queue pair when the data path is
+    // offloaded to the host kernel, empty otherwise.
+    vhost_net: Vec<VhostNet>,
+    vhost_net_queue_pairs: usize,
+    guest_memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
 }
 
 #[derive(Versionize)]
@@ -302,6 +469,7 @@ pub struct NetState {
     pub acked_features: u64,
     pub config: VirtioNetConfig,
     pub queue_size: Vec<u16>,
+    pub rx_filter: RxFilterSnapshot,
 }
 
 impl VersionMapped for NetState {}
@@ -337,16 +505,30 @@ impl Net {
             avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
         }
 
-        avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ;
+        avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ
+            | 1 << VIRTIO_NET_F_GUEST_ANNOUNCE
+            | 1 << VIRTIO_NET_F_STATUS
+            | 1 << VIRTIO_NET_F_CTRL_RX
+            | 1 << VIRTIO_NET_F_CTRL_VLAN
+            | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR
+            | 1 << VIRTIO_NET_F_RSS
+            | 1 << VIRTIO_NET_F_HASH_REPORT;
         let queue_num = num_queues + 1;
 
-        let mut config = VirtioNetConfig::default();
+        let mut config = VirtioNetConfig {
+            rss_max_key_size: RSS_MAX_KEY_SIZE,
+            rss_max_indirection_table_length: RSS_MAX_INDIRECTION_TABLE_LEN,
+            supported_hash_types: RSS_SUPPORTED_HASH_TYPES,
+            ..Default::default()
+        };
         if let Some(mac) = guest_mac {
             build_net_config_space(&mut config, mac, num_queues, &mut avail_features);
         } else {
             build_net_config_space_with_mq(&mut config, num_queues, &mut avail_features);
         }
 
+        let rss = Rss::new(taps.len()).map_err(Error::CreateRss)?;
+
         Ok(Net {
             common: VirtioCommon {
                 device_type: VirtioDeviceType::Net as u32,
@@ -359,10 +541,22 @@ impl Net {
             id,
             taps,
             config,
+            status: Arc::new(AtomicU16::new(VIRTIO_NET_S_LINK_UP as u16)),
+            link_up: Arc::new(AtomicBool::new(true)),
+            announce_on_resume: false,
+            restored_rx_filter: None,
             ctrl_queue_epoll_thread: None,
             counters: NetCounters::default(),
+            capture: PacketCapture::default(),
+            rx_filter: RxFilter::new(guest_mac),
+            rss,
             seccomp_action,
             rate_limiter_config,
+            user_net_kill_evt: None,
+            user_net_thread: None,
+            vhost_net: Vec::new(),
+            vhost_net_queue_pairs: 0,
+            guest_memory: None,
         })
     }
 
@@ -431,12 +625,261 @@ impl Net {
         )
     }
 
+    /// Create a new virtio network device backed by the userspace network
+    /// stack, running in its own thread.
+    pub fn new_user_net(
+        id: String,
+        config: &UserNetConfig,
+        guest_mac: Option<MacAddr>,
+        iommu: bool,
+        queue_size: u16,
+        seccomp_action: SeccompAction,
+        rate_limiter_config: Option<RateLimiterConfig>,
+    ) -> Result<Self> {
+        let (mut user_net, tap) = UserNet::new(config).map_err(Error::UserNet)?;
+        let kill_evt = user_net
+            .kill_evt()
+            .try_clone()
+            .map_err(Error::CloneUserNetKillEvt)?;
+
+        let mut net = Self::new_with_tap(
+            id,
+            vec![tap],
+            guest_mac,
+            iommu,
+            2,
+            queue_size,
+            seccomp_action,
+            rate_limiter_config,
+        )?;
+
+        // The frames are not handled by the host kernel, hence no offload
+        // can be provided to the guest.
+        net.common.avail_features &= !(1 << VIRTIO_NET_F_CSUM
+            | 1 << VIRTIO_NET_F_CTRL_GUEST_OFFLOADS
+            | 1 << VIRTIO_NET_F_GUEST_CSUM
+            | 1 << VIRTIO_NET_F_GUEST_ECN
+            | 1 << VIRTIO_NET_F_GUEST_TSO4
+            | 1 << VIRTIO_NET_F_GUEST_TSO6
+            | 1 << VIRTIO_NET_F_GUEST_UFO
+            | 1 << VIRTIO_NET_F_HOST_ECN
+            | 1 << VIRTIO_NET_F_HOST_TSO4
+            | 1 << VIRTIO_NET_F_HOST_TSO6
+            | 1 << VIRTIO_NET_F_HOST_UFO);
+
+        // The thread is spawned from the VMM thread so that it can rely on
+        // its own seccomp filter.
+        let user_net_seccomp_filter =
+            get_seccomp_filter(&net.seccomp_action, Thread::VirtioNetUser)
+                .map_err(Error::CreateSeccompFilter)?;
+        let thread = thread::Builder::new()
+            .name(format!("{}_user_net", net.id))
+            .spawn(move || {
+                if let Err(e) = SeccompFilter::apply(user_net_seccomp_filter) {
+                    error!("Error applying seccomp filter: {:?}", e);
+                } else if let Err(e) = user_net.run() {
+                    error!("Error running userspace network stack: {:?}", e);
+                }
+            })
+            .map_err(Error::SpawnUserNetThread)?;
+
+        net.user_net_kill_evt = Some(kill_evt);
+        net.user_net_thread = Some(thread);
+
+        Ok(net)
+    }
+
+    /// Add the given addresses to the host TAP interface, and set the MTU
+    /// of the link on both the host and the guest sides.
+    pub fn configure_tap(&mut self, addresses: &[(IpAddr, u8)], mtu: Option<u16>) -> Result<()> {
+        // The queues of a multiqueue TAP all belong to the same interface.
+        let tap = &self.taps[0];
+        for (addr, prefix_len) in addresses {
+            tap.add_ip_addr(*addr, *prefix_len)
+                .map_err(Error::TapError)?;
+        }
+        if let Some(mtu) = mtu {
+            tap.set_mtu(mtu).map_err(Error::TapError)?;
+            self.config.mtu = mtu;
+            self.common.avail_features |= 1 << VIRTIO_NET_F_MTU;
+        }
+
+        Ok(())
+    }
+
+    /// Offload the processing of the queue pairs to the kernel vhost-net
+    /// driver, only the control queue being still handled by the VMM. The
+    /// frames never go through the VMM, hence neither the rate limiter nor
+    /// the packet capture apply.
+    pub fn enable_vhost_net(&mut self) -> Result<()> {
+        let mut vhost_net = Vec::new();
+        for _ in 0..self.taps.len() {
+            vhost_net.push(VhostNet::new().map_err(Error::VhostNet)?);
+        }
+
+        let backend_features = vhost_net[0].get_features().map_err(Error::VhostNet)?;
+        self.common.avail_features =
+            vhost_net_features(self.common.avail_features, backend_features);
+        self.vhost_net = vhost_net;
+
+        Ok(())
+    }
+
+    /// Packet capture shared by all the queue pairs of the device, which
+    /// isn't available when the frames are processed by vhost-net.
+    pub fn packet_capture(&self) -> Option<PacketCapture> {
+        if self.vhost_net.is_empty() {
+            Some(self.capture.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Report the link as up or down to the guest. No frame goes through
+    /// while the link is down, unless the queue pairs are offloaded to
+    /// vhost-net, in which case the guest is only notified.
+    pub fn set_link_up(&mut self, up: bool) -> Result<()> {
+        self.link_up.store(up, Ordering::Release);
+        if up {
+            self.status
+                .fetch_or(VIRTIO_NET_S_LINK_UP as u16, Ordering::AcqRel);
+        } else {
+            self.status
+                .fetch_and(!(VIRTIO_NET_S_LINK_UP as u16), Ordering::AcqRel);
+        }
+
+        if self.common.feature_acked(VIRTIO_NET_F_STATUS.into()) {
+            self.signal_config_change()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn link_up(&self) -> bool {
+        self.link_up.load(Ordering::Acquire)
+    }
+
+    /// Ask the guest to announce itself on the network, which lets the
+    /// switches learn where its MAC address now lives. This is a no-op if
+    /// the guest doesn't support it.
+    pub fn announce(&mut self) -> Result<()> {
+        if !self
+            .common
+            .feature_acked(VIRTIO_NET_F_GUEST_ANNOUNCE.into())
+        {
+            return Ok(());
+        }
+
+        info!("Requesting guest announce on {}", self.id);
+        self.status
+            .fetch_or(VIRTIO_NET_S_ANNOUNCE as u16, Ordering::AcqRel);
+        self.signal_config_change()
+    }
+
+    fn signal_config_change(&self) -> Result<()> {
+        if let Some(interrupt_cb) = &self.common.interrupt_cb {
+            interrupt_cb
+                .trigger(&VirtioInterruptType::Config, None)
+                .map_err(Error::FailedSignalingConfigChange)?;
+        }
+
+        Ok(())
+    }
+
+    // Returns the eventfds vhost-net signals the used buffers through, along
+    // with their queue, for the queues the interrupts of which can't be
+    // injected directly through irqfd.
+    fn setup_vhost_net(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        interrupt_cb: &Arc<dyn VirtioInterrupt>,
+        queues: &[Queue],
+        queue_evts: &[EventFd],
+    ) -> Result<(Vec<EventFd>, Vec<Queue>)> {
+        let mut call_evts = Vec::new();
+        let mut call_queues = Vec::new();
+        let queue_pairs = queues.len() / 2;
+        let vhost_net_pairs = self.vhost_net.iter().zip(self.taps.iter());
+        for (i, (vhost_net, tap)) in vhost_net_pairs.take(queue_pairs).enumerate() {
+            let backend_features = vhost_net.get_features().map_err(Error::VhostNet)?;
+            vhost_net
+                .set_features(self.common.acked_features & backend_features)
+                .map_err(Error::VhostNet)?;
+            vhost_net.set_mem_table(mem).map_err(Error::VhostNet)?;
+            tap.set_offload(virtio_features_to_tap_offload(self.common.acked_features))
+                .map_err(Error::TapError)?;
+
+            for &queue_index in &[VHOST_NET_RX_QUEUE, VHOST_NET_TX_QUEUE] {
+                let queue = &queues[i * 2 + queue_index];
+                vhost_net
+                    .set_vring_num(queue_index, queue.actual_size())
+                    .map_err(Error::VhostNet)?;
+                let config_data =
+                    vring_config_data(mem, queue).map_err(Error::VhostNetVringAddress)?;
+                vhost_net
+                    .set_vring_addr(
+                        queue_index,
+                        config_data.desc_table_addr,
+                        config_data.used_ring_addr,
+                        config_data.avail_ring_addr,
+                    )
+                    .map_err(Error::VhostNet)?;
+                vhost_net
+                    .set_vring_base(queue_index, 0)
+                    .map_err(Error::VhostNet)?;
+
+                let notifier = interrupt_cb.notifier(&VirtioInterruptType::Queue, Some(queue));
+                let call_evt = match notifier {
+                    Some(call_evt) => call_evt,
+                    None => {
+                        let call_evt =
+                            EventFd::new(libc::EFD_NONBLOCK).map_err(Error::VhostNetCallEventFd)?;
+                        call_evts.push(call_evt.try_clone().map_err(Error::VhostNetCallEventFd)?);
+                        call_queues.push(queue.clone());
+                        call_evt
+                    }
+                };
+                vhost_net
+                    .set_vring_call(queue_index, &call_evt)
+                    .map_err(Error::VhostNet)?;
+                vhost_net
+                    .set_vring_kick(queue_index, &queue_evts[i * 2 + queue_index])
+                    .map_err(Error::VhostNet)?;
+            }
+        }
+
+        self.vhost_net_queue_pairs = queue_pairs;
+        self.set_vhost_net_backends(true)?;
+
+        Ok((call_evts, call_queues))
+    }
+
+    // Attaching the TAP interfaces to the configured virtqueues starts the
+    // kernel data path, detaching them stops it.
+    fn set_vhost_net_backends(&self, attach: bool) -> Result<()> {
+        let vhost_net_pairs = self.vhost_net.iter().zip(self.taps.iter());
+        for (vhost_net, tap) in vhost_net_pairs.take(self.vhost_net_queue_pairs) {
+            let tap = if attach { Some(tap) } else { None };
+            for &queue_index in &[VHOST_NET_RX_QUEUE, VHOST_NET_TX_QUEUE] {
+                vhost_net
+                    .set_backend(queue_index, tap)
+                    .map_err(Error::VhostNet)?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn state(&self) -> NetState {
+        let mut config = self.config;
+        config.status = self.status.load(Ordering::Acquire);
+
         NetState {
             avail_features: self.common.avail_features,
             acked_features: self.common.acked_features,
-            config: self.config,
+            config,
             queue_size: self.common.queue_sizes.clone(),
+            rx_filter: self.rx_filter.snapshot(),
         }
     }
 
@@ -444,7 +887,25 @@ impl Net {
         self.common.avail_features = state.avail_features;
         self.common.acked_features = state.acked_features;
         self.config = state.config;
+        self.status.store(state.config.status, Ordering::Release);
+        self.link_up.store(
+            state.config.status & VIRTIO_NET_S_LINK_UP as u16 != 0,
+            Ordering::Release,
+        );
         self.common.queue_sizes = state.queue_size.clone();
+        self.restored_rx_filter = Some(state.rx_filter.clone());
+    }
+
+    // The TAP interfaces keep their filter across a reset of the device, and
+    // a persistent one even across VMM instances, hence it has to follow the
+    // receive filter whenever the latter isn't programmed by the guest.
+    fn update_tap_filter(&self) {
+        let (macs, all_multi) = self.rx_filter.tap_filter();
+        for tap in self.taps.iter() {
+            if let Err(e) = tap.set_tx_filter(&macs, all_multi) {
+                debug!("Error programming tap filter: {:?}", e);
+            }
+        }
     }
 }
 
@@ -454,6 +915,12 @@ impl Drop for Net {
             // Ignore the result because there is nothing we can do about it.
             let _ = kill_evt.write(1);
         }
+        if let Some(kill_evt) = self.user_net_kill_evt.take() {
+            let _ = kill_evt.write(1);
+        }
+        if let Some(thread) = self.user_net_thread.take() {
+            let _ = thread.join();
+        }
     }
 }
 
@@ -475,7 +942,9 @@ impl VirtioDevice for Net {
     }
 
     fn read_config(&self, offset: u64, data: &mut [u8]) {
-        self.read_config_from_slice(self.config.as_slice(), offset, data);
+        let mut config = self.config;
+        config.status = self.status.load(Ordering::Acquire);
+        self.read_config_from_slice(config.as_slice(), offset, data);
     }
 
     fn activate(
@@ -487,8 +956,43 @@ impl VirtioDevice for Net {
     ) -> ActivateResult {
         self.common.activate(&queues, &queue_evts, &interrupt_cb)?;
 
+        // The guest has to program the filter again after a reset, unless
+        // the device is being restored.
+        match self.restored_rx_filter.take() {
+            Some(rx_filter) => self.rx_filter.restore(&rx_filter),
+            None => self
+                .rx_filter
+                .reset(self.common.feature_acked(VIRTIO_NET_F_CTRL_VLAN.into())),
+        }
+        self.update_tap_filter();
+        self.rss.reset(
+            queues.len() / 2,
+            self.common.feature_acked(VIRTIO_NET_F_RSS.into()),
+            self.common.feature_acked(VIRTIO_NET_F_HASH_REPORT.into()),
+        );
+
         let queue_num = queues.len();
-        if self.common.feature_acked(VIRTIO_NET_F_CTRL_VQ.into()) && queue_num % 2 != 0 {
+        let has_ctrl_queue =
+            self.common.feature_acked(VIRTIO_NET_F_CTRL_VQ.into()) && queue_num % 2 != 0;
+
+        // Let's update the barrier as we need 1 for each RX/TX pair + 1 for
+        // the control queue + 1 for the main thread signalling the pause.
+        // With vhost-net, the RX/TX pairs are replaced by a single thread
+        // relaying the interrupts which can't be injected through irqfd.
+        let queue_pair_threads = if self.vhost_net.is_empty() {
+            queue_num / 2
+        } else {
+            queues[..queue_num / 2 * 2].iter().any(|q| {
+                interrupt_cb
+                    .notifier(&VirtioInterruptType::Queue, Some(q))
+                    .is_none()
+            }) as usize
+        };
+        self.common.paused_sync = Some(Arc::new(Barrier::new(
+            queue_pair_threads + has_ctrl_queue as usize + 1,
+        )));
+
+        if has_ctrl_queue {
             let cvq_queue = queues.remove(queue_num - 1);
             let cvq_queue_evt = queue_evts.remove(queue_num - 1);
 
@@ -517,14 +1021,17 @@ impl VirtioDevice for Net {
                 mem: mem.clone(),
                 kill_evt,
                 pause_evt,
-                ctrl_q: NetCtrl::new(cvq_queue, cvq_queue_evt, Some(self.taps.clone())),
+                ctrl_q: NetCtrl::new(
+                    cvq_queue,
+                    cvq_queue_evt,
+                    self.taps.clone(),
+                    self.rx_filter.clone(),
+                    self.status.clone(),
+                    self.rss.clone(),
+                ),
             };
 
             let paused = self.common.paused.clone();
-            // Let's update the barrier as we need 1 for each RX/TX pair +
-            // 1 for the control queue + 1 for the main thread signalling
-            // the pause.
-            self.common.paused_sync = Some(Arc::new(Barrier::new(self.taps.len() + 2)));
             let paused_sync = self.common.paused_sync.clone();
 
             // Retrieve seccomp filter for virtio_net_ctl thread
@@ -547,6 +1054,67 @@ impl VirtioDevice for Net {
                 })?;
         }
 
+        if !self.vhost_net.is_empty() {
+            self.guest_memory = Some(mem.clone());
+            let (call_evts, call_queues) = self
+                .setup_vhost_net(&mem.memory(), &interrupt_cb, &queues, &queue_evts)
+                .map_err(ActivateError::VhostNetSetup)?;
+
+            if !call_evts.is_empty() {
+                let kill_evt = self
+                    .common
+                    .kill_evt
+                    .as_ref()
+                    .unwrap()
+                    .try_clone()
+                    .map_err(|e| {
+                        error!("failed to clone kill_evt eventfd: {}", e);
+                        ActivateError::BadActivate
+                    })?;
+                let pause_evt = self
+                    .common
+                    .pause_evt
+                    .as_ref()
+                    .unwrap()
+                    .try_clone()
+                    .map_err(|e| {
+                        error!("failed to clone pause_evt eventfd: {}", e);
+                        ActivateError::BadActivate
+                    })?;
+
+                let mut handler = VhostNetCallEpollHandler {
+                    call_evts,
+                    queues: call_queues,
+                    interrupt_cb,
+                    kill_evt,
+                    pause_evt,
+                };
+
+                let paused = self.common.paused.clone();
+                let paused_sync = self.common.paused_sync.clone();
+                let virtio_net_seccomp_filter =
+                    get_seccomp_filter(&self.seccomp_action, Thread::VirtioNet)
+                        .map_err(ActivateError::CreateSeccompFilter)?;
+                thread::Builder::new()
+                    .name(format!("{}_vhost_call", self.id))
+                    .spawn(move || {
+                        if let Err(e) = SeccompFilter::apply(virtio_net_seccomp_filter) {
+                            error!("Error applying seccomp filter: {:?}", e);
+                        } else if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
+                            error!("Error running worker: {:?}", e);
+                        }
+                    })
+                    .map(|thread| self.common.epoll_threads = Some(vec![thread]))
+                    .map_err(|e| {
+                        error!("failed to spawn vhost-net call thread: {}", e);
+                        ActivateError::BadActivate
+                    })?;
+            }
+
+            event!("virtio-device", "activated", "id", &self.id);
+            return Ok(());
+        }
+
         let event_idx = self.common.feature_acked(VIRTIO_RING_F_EVENT_IDX.into());
 
         let mut epoll_threads = Vec::new();
@@ -596,11 +1164,13 @@ impl VirtioDevice for Net {
                 .map_err(ActivateError::CreateRateLimiter)?;
 
             let tap = taps.remove(0);
-            tap.set_offload(virtio_features_to_tap_offload(self.common.acked_features))
-                .map_err(|e| {
-                    error!("Error programming tap offload: {:?}", e);
-                    ActivateError::BadActivate
-                })?;
+            if self.user_net_kill_evt.is_none() {
+                tap.set_offload(virtio_features_to_tap_offload(self.common.acked_features))
+                    .map_err(|e| {
+                        error!("Error programming tap offload: {:?}", e);
+                        ActivateError::BadActivate
+                    })?;
+            }
 
             let mut handler = NetEpollHandler {
                 net: NetQueuePair {
@@ -611,6 +1181,11 @@ impl VirtioDevice for Net {
                     epoll_fd: None,
                     rx_tap_listening,
                     counters: self.counters.clone(),
+                    capture: self.capture.clone(),
+                    rx_filter: self.rx_filter.clone(),
+                    rss: self.rss.clone(),
+                    queue_pair_index: i,
+                    link_up: self.link_up.clone(),
                     tap_event_id: RX_TAP_EVENT,
                     rx_desc_avail: false,
                     rx_rate_limiter,
@@ -653,12 +1228,47 @@ impl VirtioDevice for Net {
     }
 
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        if let Err(e) = self.set_vhost_net_backends(false) {
+            error!("Failed to stop vhost-net: {:?}", e);
+        }
+        self.vhost_net_queue_pairs = 0;
+        self.guest_memory = None;
+
+        // Let all the frames through until the guest programs the filter
+        // again.
+        for tap in self.taps.iter() {
+            if let Err(e) = tap.set_tx_filter(&[], false) {
+                debug!("Error clearing tap filter: {:?}", e);
+            }
+        }
+
         let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
 
+    fn add_memory_region(
+        &mut self,
+        _region: &Arc<GuestRegionMmap>,
+    ) -> std::result::Result<(), crate::Error> {
+        if let Some(guest_memory) = &self.guest_memory {
+            let mem = guest_memory.memory();
+            for vhost_net in self.vhost_net.iter() {
+                vhost_net
+                    .set_mem_table(&mem)
+                    .map_err(|e| crate::Error::VhostNetUpdateMemory(Error::VhostNet(e)))?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        // The frames processed by vhost-net aren't accounted for.
+        if !self.vhost_net.is_empty() {
+            return None;
+        }
+
         let mut counters = HashMap::new();
 
         counters.insert(
@@ -684,15 +1294,29 @@ impl VirtioDevice for Net {
 
 impl Pausable for Net {
     fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.set_vhost_net_backends(false)
+            .map_err(|e| MigratableError::Pause(anyhow!("Error stopping vhost-net: {:?}", e)))?;
         self.common.pause()
     }
 
     fn resume(&mut self) -> result::Result<(), MigratableError> {
         self.common.resume()?;
+        self.set_vhost_net_backends(true)
+            .map_err(|e| MigratableError::Resume(anyhow!("Error starting vhost-net: {:?}", e)))?;
 
         if let Some(ctrl_queue_epoll_thread) = &self.ctrl_queue_epoll_thread {
             ctrl_queue_epoll_thread.thread().unpark();
         }
+
+        // The VM may now be running on another host, the guest needs to let
+        // the network know about it.
+        if self.announce_on_resume {
+            self.announce_on_resume = false;
+            if let Err(e) = self.announce() {
+                error!("Failed requesting guest announce: {:?}", e);
+            }
+        }
+
         Ok(())
     }
 }
@@ -703,13 +1327,114 @@ impl Snapshottable for Net {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        // The virtqueues state is owned by the kernel.
+        if !self.vhost_net.is_empty() {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Snapshot not supported with vhost-net: {}",
+                self.id
+            )));
+        }
+
         Snapshot::new_from_versioned_state(&self.id, &self.state())
     }
 
     fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         self.set_state(&snapshot.to_versioned_state(&self.id)?);
+        self.announce_on_resume = true;
         Ok(())
     }
 }
 impl Transportable for Net {}
 impl Migratable for Net {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    struct CountingVirtioInterrupt {
+        queue_triggers: AtomicUsize,
+    }
+
+    impl VirtioInterrupt for CountingVirtioInterrupt {
+        fn trigger(
+            &self,
+            int_type: &VirtioInterruptType,
+            _queue: Option<&Queue>,
+        ) -> std::result::Result<(), std::io::Error> {
+            if let VirtioInterruptType::Queue = int_type {
+                self.queue_triggers.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_vhost_net_features() {
+        let avail_features: u64 = 1 << VIRTIO_NET_F_CSUM
+            | 1 << VIRTIO_NET_F_MAC
+            | 1 << VIRTIO_NET_F_CTRL_VQ
+            | 1 << VIRTIO_NET_F_CTRL_VLAN
+            | 1 << VIRTIO_NET_F_CTRL_RX
+            | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR
+            | 1 << VIRTIO_NET_F_RSS
+            | 1 << VIRTIO_NET_F_HASH_REPORT
+            | 1 << VIRTIO_RING_F_EVENT_IDX
+            | 1 << VIRTIO_F_VERSION_1;
+
+        // The ring features supported by the backend are kept, the filtering
+        // and steering features never are.
+        let features = vhost_net_features(
+            avail_features,
+            1 << VIRTIO_RING_F_EVENT_IDX | 1 << VIRTIO_F_VERSION_1,
+        );
+        assert_eq!(
+            features,
+            1 << VIRTIO_NET_F_CSUM
+                | 1 << VIRTIO_NET_F_MAC
+                | 1 << VIRTIO_NET_F_CTRL_VQ
+                | 1 << VIRTIO_RING_F_EVENT_IDX
+                | 1 << VIRTIO_F_VERSION_1
+        );
+
+        // The ring features unknown to the backend are removed.
+        let features = vhost_net_features(avail_features, 1 << VIRTIO_F_VERSION_1);
+        assert_eq!(features & (1 << VIRTIO_RING_F_EVENT_IDX), 0);
+        assert_ne!(features & (1 << VIRTIO_F_VERSION_1), 0);
+        assert_eq!(features & (1 << VIRTIO_NET_F_CTRL_RX), 0);
+        assert_eq!(features & (1 << VIRTIO_NET_F_CTRL_MAC_ADDR), 0);
+    }
+
+    #[test]
+    fn test_vhost_net_call_relay() {
+        let interrupt = Arc::new(CountingVirtioInterrupt {
+            queue_triggers: AtomicUsize::new(0),
+        });
+        let mut handler = VhostNetCallEpollHandler {
+            call_evts: vec![
+                EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+                EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            ],
+            queues: vec![Queue::new(256), Queue::new(256)],
+            interrupt_cb: interrupt.clone(),
+            kill_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            pause_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        };
+        let mut epoll_helper = EpollHelper::new(&handler.kill_evt, &handler.pause_evt).unwrap();
+
+        // A used buffer notification is relayed to the guest.
+        handler.call_evts[1].write(1).unwrap();
+        let event = epoll::Event::new(epoll::Events::EPOLLIN, (VHOST_NET_CALL_EVENT + 1) as u64);
+        assert!(!handler.handle_event(&mut epoll_helper, &event));
+        assert_eq!(interrupt.queue_triggers.load(Ordering::SeqCst), 1);
+
+        // Nothing is relayed if the eventfd wasn't signalled.
+        assert!(handler.handle_event(&mut epoll_helper, &event));
+        assert_eq!(interrupt.queue_triggers.load(Ordering::SeqCst), 1);
+
+        // Events beyond the relayed queues are unknown.
+        let event = epoll::Event::new(epoll::Events::EPOLLIN, (VHOST_NET_CALL_EVENT + 2) as u64);
+        assert!(handler.handle_event(&mut epoll_helper, &event));
+        assert_eq!(interrupt.queue_triggers.load(Ordering::SeqCst), 1);
+    }
+}
@@ -3,24 +3,19 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
 use super::{EpollHelper, EpollHelperError, EpollHelperHandler, Queue, EPOLL_HELPER_EVENT_LAST};
-use net_util::MacAddr;
-use net_util::Tap;
+use net_util::{CtrlQueue, CtrlQueueError, MacAddr, Rss, RxFilter, Tap};
 use std::os::raw::c_uint;
 use std::os::unix::io::AsRawFd;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU16};
 use std::sync::{Arc, Barrier};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_bindings::bindings::virtio_net::*;
-use vm_memory::{
-    ByteValued, Bytes, GuestAddressSpace, GuestMemoryAtomic, GuestMemoryError, GuestMemoryMmap,
-};
+use vm_memory::{ByteValued, GuestAddressSpace, GuestMemoryAtomic, GuestMemoryMmap};
 use vmm_sys_util::eventfd::EventFd;
 
 type Result<T> = std::result::Result<T, Error>;
 
-const QUEUE_SIZE: usize = 256;
-
 // Event available on the control queue.
 const CTRL_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 
@@ -33,6 +28,9 @@ pub struct VirtioNetConfig {
     pub mtu: u16,
     pub speed: u32,
     pub duplex: u8,
+    pub rss_max_key_size: u8,
+    pub rss_max_indirection_table_length: u16,
+    pub supported_hash_types: u32,
 }
 
 // Safe because it only has data and has no implicit padding.
@@ -40,111 +38,36 @@ unsafe impl ByteValued for VirtioNetConfig {}
 
 #[derive(Debug)]
 pub enum Error {
-    /// Read queue failed.
-    GuestMemory(GuestMemoryError),
-    /// No queue pairs number.
-    NoQueuePairsDescriptor,
-    /// No status descriptor
-    NoStatusDescriptor,
+    /// Failed processing the control queue.
+    ProcessCtrlQueue(CtrlQueueError),
 }
 
 pub struct NetCtrl {
     pub queue_evt: EventFd,
     pub queue: Queue,
-    pub taps: Option<Vec<Tap>>,
+    pub ctrl: CtrlQueue,
 }
 
-#[repr(C, packed)]
-#[derive(Debug, Clone, Copy, Default)]
-pub struct ControlHeader {
-    pub class: u8,
-    pub cmd: u8,
-}
-
-unsafe impl ByteValued for ControlHeader {}
-
 impl NetCtrl {
-    pub fn new(queue: Queue, queue_evt: EventFd, taps: Option<Vec<Tap>>) -> Self {
+    pub fn new(
+        queue: Queue,
+        queue_evt: EventFd,
+        taps: Vec<Tap>,
+        rx_filter: RxFilter,
+        status: Arc<AtomicU16>,
+        rss: Rss,
+    ) -> Self {
         NetCtrl {
             queue_evt,
             queue,
-            taps,
+            ctrl: CtrlQueue::new(taps, rx_filter, Some(status), rss),
         }
     }
 
     pub fn process_cvq(&mut self, mem: &GuestMemoryMmap) -> Result<()> {
-        let mut used_desc_heads = [(0, 0); QUEUE_SIZE];
-        let mut used_count = 0;
-        let queue = &mut self.queue;
-        for avail_desc in queue.iter(&mem) {
-            let ctrl_hdr: ControlHeader =
-                mem.read_obj(avail_desc.addr).map_err(Error::GuestMemory)?;
-            let data_desc = avail_desc
-                .next_descriptor()
-                .ok_or(Error::NoQueuePairsDescriptor)?;
-            let status_desc = data_desc
-                .next_descriptor()
-                .ok_or(Error::NoStatusDescriptor)?;
-
-            let ok = match u32::from(ctrl_hdr.class) {
-                VIRTIO_NET_CTRL_MQ => {
-                    let queue_pairs = mem
-                        .read_obj::<u16>(data_desc.addr)
-                        .map_err(Error::GuestMemory)?;
-                    if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET {
-                        warn!("Unsupported command: {}", ctrl_hdr.cmd);
-                        false
-                    } else if (queue_pairs < VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN as u16)
-                        || (queue_pairs > VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX as u16)
-                    {
-                        warn!("Number of MQ pairs out of range: {}", queue_pairs);
-                        false
-                    } else {
-                        info!("Number of MQ pairs requested: {}", queue_pairs);
-                        true
-                    }
-                }
-                VIRTIO_NET_CTRL_GUEST_OFFLOADS => {
-                    let features = mem
-                        .read_obj::<u64>(data_desc.addr)
-                        .map_err(Error::GuestMemory)?;
-                    if u32::from(ctrl_hdr.cmd) != VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET {
-                        warn!("Unsupported command: {}", ctrl_hdr.cmd);
-                        false
-                    } else {
-                        let mut ok = true;
-                        if let Some(ref mut taps) = &mut self.taps {
-                            for tap in taps.iter_mut() {
-                                info!("Reprogramming tap offload with features: {}", features);
-                                tap.set_offload(virtio_features_to_tap_offload(features))
-                                    .map_err(|e| {
-                                        error!("Error programming tap offload: {:?}", e);
-                                        ok = false
-                                    })
-                                    .ok();
-                            }
-                        }
-                        ok
-                    }
-                }
-                _ => {
-                    warn!("Unsupported command {:?}", ctrl_hdr);
-                    false
-                }
-            };
-
-            mem.write_obj(
-                if ok { VIRTIO_NET_OK } else { VIRTIO_NET_ERR } as u8,
-                status_desc.addr,
-            )
-            .map_err(Error::GuestMemory)?;
-            used_desc_heads[used_count] = (avail_desc.index, avail_desc.len);
-            used_count += 1;
-        }
-        for &(desc_index, len) in &used_desc_heads[..used_count] {
-            self.queue.add_used(&mem, desc_index, len);
-            self.queue.update_avail_event(&mem);
-        }
+        self.ctrl
+            .process(mem, &mut self.queue)
+            .map_err(Error::ProcessCtrlQueue)?;
 
         Ok(())
     }
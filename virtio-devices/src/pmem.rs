@@ -14,6 +14,7 @@ use super::{
 };
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::{VirtioInterrupt, VirtioInterruptType};
+use libc::EFD_NONBLOCK;
 use seccomp::{SeccompAction, SeccompFilter};
 use std::fmt::{self, Display};
 use std::fs::File;
@@ -22,12 +23,13 @@ use std::mem::size_of;
 use std::os::unix::io::AsRawFd;
 use std::result;
 use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, Barrier};
+use std::sync::{Arc, Barrier, Mutex};
 use std::thread;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
+use vm_memory::mmap::MmapRegionError;
 use vm_memory::{
-    Address, ByteValued, Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic,
+    Address, ByteValued, Bytes, FileOffset, GuestAddress, GuestAddressSpace, GuestMemoryAtomic,
     GuestMemoryError, GuestMemoryMmap, MmapRegion,
 };
 use vm_migration::VersionMapped;
@@ -43,6 +45,8 @@ const VIRTIO_PMEM_RESP_TYPE_EIO: u32 = 1;
 
 // New descriptors are pending on the virtio queue.
 const QUEUE_AVAIL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+// Flush requests are pending for the flush thread.
+const FLUSH_PENDING_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 
 #[derive(Copy, Clone, Debug, Default, Versionize)]
 #[repr(C)]
@@ -154,12 +158,20 @@ impl Request {
     }
 }
 
+// A flush request which has been validated by the queue thread and is
+// waiting for the flush thread to complete it.
+struct FlushRequest {
+    desc_index: u16,
+    status_addr: GuestAddress,
+}
+
 struct PmemEpollHandler {
-    queue: Queue,
+    queue: Arc<Mutex<Queue>>,
     mem: GuestMemoryAtomic<GuestMemoryMmap>,
-    disk: File,
     interrupt_cb: Arc<dyn VirtioInterrupt>,
     queue_evt: EventFd,
+    pending_flushes: Arc<Mutex<Vec<FlushRequest>>>,
+    flush_evt: EventFd,
     kill_evt: EventFd,
     pause_evt: EventFd,
 }
@@ -168,51 +180,51 @@ impl PmemEpollHandler {
     fn process_queue(&mut self) -> bool {
         let mut used_desc_heads = [(0, 0); QUEUE_SIZE as usize];
         let mut used_count = 0;
+        let mut flush_count = 0;
         let mem = self.mem.memory();
-        for avail_desc in self.queue.iter(&mem) {
-            let len = match Request::parse(&avail_desc, &mem) {
+        let mut queue = self.queue.lock().unwrap();
+        for avail_desc in queue.iter(&mem) {
+            match Request::parse(&avail_desc, &mem) {
                 Ok(ref req) if (req.type_ == RequestType::Flush) => {
-                    let status_code = match self.disk.sync_all() {
-                        Ok(()) => VIRTIO_PMEM_RESP_TYPE_OK,
-                        Err(e) => {
-                            error!("failed flushing disk image: {}", e);
-                            VIRTIO_PMEM_RESP_TYPE_EIO
-                        }
-                    };
-
-                    let resp = VirtioPmemResp { ret: status_code };
-                    match mem.write_obj(resp, req.status_addr) {
-                        Ok(_) => size_of::<VirtioPmemResp>() as u32,
-                        Err(e) => {
-                            error!("bad guest memory address: {}", e);
-                            0
-                        }
-                    }
+                    // Flushes are completed asynchronously by the flush
+                    // thread, which is able to batch them together.
+                    self.pending_flushes.lock().unwrap().push(FlushRequest {
+                        desc_index: avail_desc.index,
+                        status_addr: req.status_addr,
+                    });
+                    flush_count += 1;
+                    continue;
                 }
                 Ok(ref req) => {
                     // Currently, there is only one virtio-pmem request, FLUSH.
                     error!("Invalid virtio request type {:?}", req.type_);
-                    0
                 }
                 Err(e) => {
                     error!("Failed to parse available descriptor chain: {:?}", e);
-                    0
                 }
             };
 
-            used_desc_heads[used_count] = (avail_desc.index, len);
+            used_desc_heads[used_count] = (avail_desc.index, 0);
             used_count += 1;
         }
 
         for &(desc_index, len) in &used_desc_heads[..used_count] {
-            self.queue.add_used(&mem, desc_index, len);
+            queue.add_used(&mem, desc_index, len);
+        }
+
+        if flush_count > 0 {
+            if let Err(e) = self.flush_evt.write(1) {
+                error!("Failed to notify flush thread: {:?}", e);
+            }
         }
+
         used_count > 0
     }
 
     fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        let queue = self.queue.lock().unwrap();
         self.interrupt_cb
-            .trigger(&VirtioInterruptType::Queue, Some(&self.queue))
+            .trigger(&VirtioInterruptType::Queue, Some(&queue))
             .map_err(|e| {
                 error!("Failed to signal used queue: {:?}", e);
                 DeviceError::FailedSignalingUsedQueue(e)
@@ -256,12 +268,131 @@ impl EpollHelperHandler for PmemEpollHandler {
     }
 }
 
+struct PmemFlushHandler {
+    queue: Arc<Mutex<Queue>>,
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    mapping: UserspaceMapping,
+    // Whether guest writes reach the backing file, which is false for
+    // readonly and discard_writes regions.
+    writeback: bool,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    pending_flushes: Arc<Mutex<Vec<FlushRequest>>>,
+    flush_evt: EventFd,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+}
+
+impl PmemFlushHandler {
+    fn flush(&self) -> io::Result<()> {
+        if !self.writeback {
+            return Ok(());
+        }
+
+        // Safe because the mapping is owned by the device and stays valid
+        // as long as the device exists. Since the region is mapped with
+        // MAP_SHARED, synchronously writing it back through msync() also
+        // persists the pages to the backing file.
+        let ret = unsafe {
+            libc::msync(
+                self.mapping.host_addr as *mut libc::c_void,
+                self.mapping.len as libc::size_t,
+                libc::MS_SYNC,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn process_flushes(&mut self) -> bool {
+        let requests = std::mem::take(&mut *self.pending_flushes.lock().unwrap());
+        if requests.is_empty() {
+            return false;
+        }
+
+        // All pending requests are completed with a single flush as each one
+        // of them covers the entire region.
+        let status_code = match self.flush() {
+            Ok(()) => VIRTIO_PMEM_RESP_TYPE_OK,
+            Err(e) => {
+                error!("failed flushing disk image: {}", e);
+                VIRTIO_PMEM_RESP_TYPE_EIO
+            }
+        };
+
+        let mem = self.mem.memory();
+        let mut queue = self.queue.lock().unwrap();
+        for req in requests.iter() {
+            let resp = VirtioPmemResp { ret: status_code };
+            let len = match mem.write_obj(resp, req.status_addr) {
+                Ok(_) => size_of::<VirtioPmemResp>() as u32,
+                Err(e) => {
+                    error!("bad guest memory address: {}", e);
+                    0
+                }
+            };
+            queue.add_used(&mem, req.desc_index, len);
+        }
+
+        true
+    }
+
+    fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        let queue = self.queue.lock().unwrap();
+        self.interrupt_cb
+            .trigger(&VirtioInterruptType::Queue, Some(&queue))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                DeviceError::FailedSignalingUsedQueue(e)
+            })
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(self.flush_evt.as_raw_fd(), FLUSH_PENDING_EVENT)?;
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for PmemFlushHandler {
+    fn handle_event(&mut self, _helper: &mut EpollHelper, event: &epoll::Event) -> bool {
+        let ev_type = event.data as u16;
+        match ev_type {
+            FLUSH_PENDING_EVENT => {
+                if let Err(e) = self.flush_evt.read() {
+                    error!("Failed to get flush event: {:?}", e);
+                    return true;
+                } else if self.process_flushes() {
+                    if let Err(e) = self.signal_used_queue() {
+                        error!("Failed to signal used queue: {:?}", e);
+                        return true;
+                    }
+                }
+            }
+            _ => {
+                error!("Unexpected event: {}", ev_type);
+                return true;
+            }
+        }
+        false
+    }
+}
+
 pub struct Pmem {
     common: VirtioCommon,
     id: String,
-    disk: Option<File>,
     config: VirtioPmemConfig,
     mapping: UserspaceMapping,
+    readonly: bool,
+    discard_writes: bool,
     seccomp_action: SeccompAction,
 
     // Hold ownership of the memory that is allocated for the device
@@ -279,13 +410,15 @@ pub struct PmemState {
 impl VersionMapped for PmemState {}
 
 impl Pmem {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
-        disk: File,
         addr: GuestAddress,
         mapping: UserspaceMapping,
         _region: MmapRegion,
         iommu: bool,
+        readonly: bool,
+        discard_writes: bool,
         seccomp_action: SeccompAction,
     ) -> io::Result<Pmem> {
         let config = VirtioPmemConfig {
@@ -303,20 +436,45 @@ impl Pmem {
             common: VirtioCommon {
                 device_type: VirtioDeviceType::Pmem as u32,
                 queue_sizes: QUEUE_SIZES.to_vec(),
-                paused_sync: Some(Arc::new(Barrier::new(2))),
+                paused_sync: Some(Arc::new(Barrier::new(3))),
                 avail_features,
                 min_queues: 1,
                 ..Default::default()
             },
             id,
-            disk: Some(disk),
             config,
             mapping,
+            readonly,
+            discard_writes,
             seccomp_action,
             _region,
         })
     }
 
+    /// Map the backing file of the device. A readonly region is mapped
+    /// without write access, and a discard_writes one privately so that the
+    /// guest writes never reach the file.
+    pub fn mmap_region(
+        file: File,
+        size: usize,
+        readonly: bool,
+        discard_writes: bool,
+    ) -> result::Result<MmapRegion, MmapRegionError> {
+        let prot = if readonly {
+            libc::PROT_READ
+        } else {
+            libc::PROT_READ | libc::PROT_WRITE
+        };
+        let flags = libc::MAP_NORESERVE
+            | if discard_writes {
+                libc::MAP_PRIVATE
+            } else {
+                libc::MAP_SHARED
+            };
+
+        MmapRegion::build(Some(FileOffset::new(file, 0)), size, prot, flags)
+    }
+
     fn state(&self) -> PmemState {
         PmemState {
             avail_features: self.common.avail_features,
@@ -390,49 +548,92 @@ impl VirtioDevice for Pmem {
                 error!("failed to clone pause_evt eventfd: {}", e);
                 ActivateError::BadActivate
             })?;
-        if let Some(disk) = self.disk.as_ref() {
-            let disk = disk.try_clone().map_err(|e| {
-                error!("failed cloning pmem disk: {}", e);
+        let flush_evt = EventFd::new(EFD_NONBLOCK).map_err(|e| {
+            error!("failed creating flush EventFd: {}", e);
+            ActivateError::BadActivate
+        })?;
+        let queue = Arc::new(Mutex::new(queues.remove(0)));
+        let pending_flushes = Arc::new(Mutex::new(Vec::new()));
+
+        let mut handler = PmemEpollHandler {
+            queue: queue.clone(),
+            mem: mem.clone(),
+            interrupt_cb: interrupt_cb.clone(),
+            queue_evt: queue_evts.remove(0),
+            pending_flushes: pending_flushes.clone(),
+            flush_evt: flush_evt.try_clone().map_err(|e| {
+                error!("failed to clone flush_evt eventfd: {}", e);
+                ActivateError::BadActivate
+            })?,
+            kill_evt: kill_evt.try_clone().map_err(|e| {
+                error!("failed to clone kill_evt eventfd: {}", e);
+                ActivateError::BadActivate
+            })?,
+            pause_evt: pause_evt.try_clone().map_err(|e| {
+                error!("failed to clone pause_evt eventfd: {}", e);
+                ActivateError::BadActivate
+            })?,
+        };
+
+        let mut flush_handler = PmemFlushHandler {
+            queue,
+            mem,
+            mapping: self.mapping.clone(),
+            writeback: !self.readonly && !self.discard_writes,
+            interrupt_cb,
+            pending_flushes,
+            flush_evt,
+            kill_evt,
+            pause_evt,
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        // Retrieve seccomp filter for virtio_pmem thread
+        let virtio_pmem_seccomp_filter =
+            get_seccomp_filter(&self.seccomp_action, Thread::VirtioPmem)
+                .map_err(ActivateError::CreateSeccompFilter)?;
+        thread::Builder::new()
+            .name(self.id.clone())
+            .spawn(move || {
+                if let Err(e) = SeccompFilter::apply(virtio_pmem_seccomp_filter) {
+                    error!("Error applying seccomp filter: {:?}", e);
+                } else if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
+                    error!("Error running worker: {:?}", e);
+                }
+            })
+            .map(|thread| epoll_threads.push(thread))
+            .map_err(|e| {
+                error!("failed to clone virtio-pmem epoll thread: {}", e);
                 ActivateError::BadActivate
             })?;
-            let mut handler = PmemEpollHandler {
-                queue: queues.remove(0),
-                mem,
-                disk,
-                interrupt_cb,
-                queue_evt: queue_evts.remove(0),
-                kill_evt,
-                pause_evt,
-            };
 
-            let paused = self.common.paused.clone();
-            let paused_sync = self.common.paused_sync.clone();
-            let mut epoll_threads = Vec::new();
-            // Retrieve seccomp filter for virtio_pmem thread
-            let virtio_pmem_seccomp_filter =
-                get_seccomp_filter(&self.seccomp_action, Thread::VirtioPmem)
-                    .map_err(ActivateError::CreateSeccompFilter)?;
-            thread::Builder::new()
-                .name(self.id.clone())
-                .spawn(move || {
-                    if let Err(e) = SeccompFilter::apply(virtio_pmem_seccomp_filter) {
-                        error!("Error applying seccomp filter: {:?}", e);
-                    } else if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
-                        error!("Error running worker: {:?}", e);
-                    }
-                })
-                .map(|thread| epoll_threads.push(thread))
-                .map_err(|e| {
-                    error!("failed to clone virtio-pmem epoll thread: {}", e);
-                    ActivateError::BadActivate
-                })?;
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        // Retrieve seccomp filter for virtio_pmem_flush thread
+        let virtio_pmem_flush_seccomp_filter =
+            get_seccomp_filter(&self.seccomp_action, Thread::VirtioPmemFlush)
+                .map_err(ActivateError::CreateSeccompFilter)?;
+        thread::Builder::new()
+            .name(format!("{}_flush", self.id))
+            .spawn(move || {
+                if let Err(e) = SeccompFilter::apply(virtio_pmem_flush_seccomp_filter) {
+                    error!("Error applying seccomp filter: {:?}", e);
+                } else if let Err(e) = flush_handler.run(paused, paused_sync.unwrap()) {
+                    error!("Error running worker: {:?}", e);
+                }
+            })
+            .map(|thread| epoll_threads.push(thread))
+            .map_err(|e| {
+                error!("failed to clone virtio-pmem flush thread: {}", e);
+                ActivateError::BadActivate
+            })?;
 
-            self.common.epoll_threads = Some(epoll_threads);
+        self.common.epoll_threads = Some(epoll_threads);
 
-            event!("virtio-device", "activated", "id", &self.id);
-            return Ok(());
-        }
-        Err(ActivateError::BadActivate)
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
     }
 
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
@@ -473,3 +674,172 @@ impl Snapshottable for Pmem {
 
 impl Transportable for Pmem {}
 impl Migratable for Pmem {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use vm_virtio::queue::testing::VirtQueue as GuestQ;
+    use vmm_sys_util::tempfile::TempFile;
+
+    const REGION_SIZE: usize = 0x1000;
+    const STATUS_ADDR: u64 = 0x8000;
+
+    struct NoopVirtioInterrupt {}
+
+    impl VirtioInterrupt for NoopVirtioInterrupt {
+        fn trigger(
+            &self,
+            _int_type: &VirtioInterruptType,
+            _queue: Option<&Queue>,
+        ) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    fn backing_file(content: &[u8]) -> File {
+        let mut file = TempFile::new().unwrap().into_file();
+        file.set_len(REGION_SIZE as u64).unwrap();
+        file.write_all(content).unwrap();
+        file
+    }
+
+    fn file_content(file: &mut File, len: usize) -> Vec<u8> {
+        let mut content = vec![0u8; len];
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_exact(&mut content).unwrap();
+        content
+    }
+
+    // Returns the permissions of the mapping starting at the given address,
+    // as displayed by /proc/self/maps.
+    fn mapping_permissions(host_addr: u64) -> String {
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let prefix = format!("{:x}-", host_addr);
+        maps.lines()
+            .find(|line| line.starts_with(&prefix))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .unwrap()
+            .to_owned()
+    }
+
+    fn flush_handler(
+        mem: &GuestMemoryMmap,
+        queue: Queue,
+        host_addr: u64,
+        writeback: bool,
+    ) -> PmemFlushHandler {
+        PmemFlushHandler {
+            queue: Arc::new(Mutex::new(queue)),
+            mem: GuestMemoryAtomic::new(mem.clone()),
+            mapping: UserspaceMapping {
+                host_addr,
+                mem_slot: 0,
+                addr: GuestAddress(0x1_0000_0000),
+                len: REGION_SIZE as u64,
+                mergeable: false,
+            },
+            writeback,
+            interrupt_cb: Arc::new(NoopVirtioInterrupt {}),
+            pending_flushes: Arc::new(Mutex::new(Vec::new())),
+            flush_evt: EventFd::new(EFD_NONBLOCK).unwrap(),
+            kill_evt: EventFd::new(EFD_NONBLOCK).unwrap(),
+            pause_evt: EventFd::new(EFD_NONBLOCK).unwrap(),
+        }
+    }
+
+    fn push_flushes(handler: &PmemFlushHandler, count: u16) {
+        let mut pending_flushes = handler.pending_flushes.lock().unwrap();
+        for i in 0..count {
+            pending_flushes.push(FlushRequest {
+                desc_index: i,
+                status_addr: GuestAddress(STATUS_ADDR + u64::from(i) * 0x10),
+            });
+        }
+    }
+
+    #[test]
+    fn test_pmem_flush() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0x1000), &mem, 16);
+
+        let mut file = backing_file(&[0u8; 4]);
+        let region =
+            Pmem::mmap_region(file.try_clone().unwrap(), REGION_SIZE, false, false).unwrap();
+        assert_eq!(mapping_permissions(region.as_ptr() as u64), "rw-s");
+        let mut handler = flush_handler(&mem, guest_q.create_queue(), region.as_ptr() as u64, true);
+
+        // Nothing to complete.
+        assert!(!handler.process_flushes());
+
+        // Safe because the region is REGION_SIZE bytes long and writable.
+        unsafe { std::ptr::copy_nonoverlapping([1u8, 2, 3, 4].as_ptr(), region.as_ptr(), 4) };
+
+        // All the pending requests are completed by a single flush, which
+        // writes the guest changes back to the file.
+        push_flushes(&handler, 2);
+        assert!(handler.process_flushes());
+        assert_eq!(guest_q.used.idx.get(), 2);
+        for i in 0..2 {
+            let resp: u32 = mem.read_obj(GuestAddress(STATUS_ADDR + i * 0x10)).unwrap();
+            assert_eq!(resp, VIRTIO_PMEM_RESP_TYPE_OK);
+        }
+        assert_eq!(file_content(&mut file, 4), vec![1, 2, 3, 4]);
+        assert!(handler.pending_flushes.lock().unwrap().is_empty());
+
+        // A failing msync() is reported to the guest, which is the case of an
+        // address which isn't page aligned.
+        handler.mapping.host_addr += 1;
+        push_flushes(&handler, 1);
+        assert!(handler.process_flushes());
+        assert_eq!(guest_q.used.idx.get(), 3);
+        let resp: u32 = mem.read_obj(GuestAddress(STATUS_ADDR)).unwrap();
+        assert_eq!(resp, VIRTIO_PMEM_RESP_TYPE_EIO);
+    }
+
+    #[test]
+    fn test_pmem_readonly() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0x1000), &mem, 16);
+
+        // The readonly region is mapped without write access, while still
+        // exposing the content of the file.
+        let file = backing_file(&[5u8, 6, 7, 8]);
+        let region = Pmem::mmap_region(file, REGION_SIZE, true, false).unwrap();
+        assert_eq!(mapping_permissions(region.as_ptr() as u64), "r--s");
+        // Safe because the region is REGION_SIZE bytes long and readable.
+        let content = unsafe { std::slice::from_raw_parts(region.as_ptr(), 4) };
+        assert_eq!(content, &[5, 6, 7, 8]);
+
+        // Flushing a readonly region always succeeds, without any msync(),
+        // which an invalid address would otherwise make fail.
+        let mut handler = flush_handler(&mem, guest_q.create_queue(), 1, false);
+        push_flushes(&handler, 1);
+        assert!(handler.process_flushes());
+        let resp: u32 = mem.read_obj(GuestAddress(STATUS_ADDR)).unwrap();
+        assert_eq!(resp, VIRTIO_PMEM_RESP_TYPE_OK);
+    }
+
+    #[test]
+    fn test_pmem_discard_writes() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let guest_q = GuestQ::new(GuestAddress(0x1000), &mem, 16);
+
+        let mut file = backing_file(&[0u8; 4]);
+        let region =
+            Pmem::mmap_region(file.try_clone().unwrap(), REGION_SIZE, false, true).unwrap();
+        assert_eq!(mapping_permissions(region.as_ptr() as u64), "rw-p");
+        let mut handler =
+            flush_handler(&mem, guest_q.create_queue(), region.as_ptr() as u64, false);
+
+        // Safe because the region is REGION_SIZE bytes long and writable.
+        unsafe { std::ptr::copy_nonoverlapping([1u8, 2, 3, 4].as_ptr(), region.as_ptr(), 4) };
+
+        // The guest writes never reach the file, even once flushed.
+        push_flushes(&handler, 1);
+        assert!(handler.process_flushes());
+        let resp: u32 = mem.read_obj(GuestAddress(STATUS_ADDR)).unwrap();
+        assert_eq!(resp, VIRTIO_PMEM_RESP_TYPE_OK);
+        assert_eq!(file_content(&mut file, 4), vec![0, 0, 0, 0]);
+    }
+}
@@ -4,6 +4,7 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::seccomp_profile::{apply_profile, tag_trap};
 use seccomp::{
     allow_syscall, allow_syscall_if, BpfProgram, Error, SeccompAction, SeccompCmpArgLen as ArgLen,
     SeccompCmpOp::Eq, SeccompCondition as Cond, SeccompError, SeccompFilter, SeccompRule,
@@ -19,13 +20,39 @@ pub enum Thread {
     VirtioMem,
     VirtioNet,
     VirtioNetCtl,
+    VirtioNetUser,
     VirtioPmem,
+    VirtioPmemFlush,
     VirtioRng,
     VirtioVhostFs,
+    VirtioVhostGeneric,
     VirtioVsock,
     VirtioWatchdog,
 }
 
+impl Thread {
+    /// Thread type the seccomp profile refers to.
+    fn name(&self) -> &'static str {
+        match self {
+            Thread::VirtioBalloon => "virtio_balloon",
+            Thread::VirtioBlock => "virtio_block",
+            Thread::VirtioConsole => "virtio_console",
+            Thread::VirtioIommu => "virtio_iommu",
+            Thread::VirtioMem => "virtio_mem",
+            Thread::VirtioNet => "virtio_net",
+            Thread::VirtioNetCtl => "virtio_net_ctl",
+            Thread::VirtioNetUser => "virtio_net_user",
+            Thread::VirtioPmem => "virtio_pmem",
+            Thread::VirtioPmemFlush => "virtio_pmem_flush",
+            Thread::VirtioRng => "virtio_rng",
+            Thread::VirtioVhostFs => "virtio_vhost_fs",
+            Thread::VirtioVhostGeneric => "virtio_vhost_generic",
+            Thread::VirtioVsock => "virtio_vsock",
+            Thread::VirtioWatchdog => "virtio_watchdog",
+        }
+    }
+}
+
 /// Shorthand for chaining `SeccompCondition`s with the `and` operator  in a `SeccompRule`.
 /// The rule will take the `Allow` action if _all_ the conditions are true.
 ///
@@ -58,6 +85,7 @@ const VFIO_IOMMU_UNMAP_DMA: u64 = 0x3b72;
 
 // See include/uapi/linux/if_tun.h in the kernel code.
 const TUNSETOFFLOAD: u64 = 0x4004_54d0;
+const TUNSETTXFILTER: u64 = 0x4004_54d1;
 
 fn create_virtio_iommu_ioctl_seccomp_rule() -> Vec<SeccompRule> {
     or![
@@ -222,6 +250,8 @@ fn virtio_net_thread_rules() -> Vec<SyscallRuleSet> {
         allow_syscall(libc::SYS_exit),
         allow_syscall(libc::SYS_futex),
         allow_syscall(libc::SYS_madvise),
+        allow_syscall(libc::SYS_mmap),
+        allow_syscall(libc::SYS_mprotect),
         allow_syscall(libc::SYS_munmap),
         allow_syscall(libc::SYS_openat),
         allow_syscall(libc::SYS_read),
@@ -235,7 +265,10 @@ fn virtio_net_thread_rules() -> Vec<SyscallRuleSet> {
 }
 
 fn create_virtio_net_ctl_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
-    Ok(or![and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETOFFLOAD)?],])
+    Ok(or![
+        and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETOFFLOAD)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETTXFILTER)?],
+    ])
 }
 
 fn virtio_net_ctl_thread_rules() -> Result<Vec<SyscallRuleSet>, Error> {
@@ -252,6 +285,8 @@ fn virtio_net_ctl_thread_rules() -> Result<Vec<SyscallRuleSet>, Error> {
         allow_syscall(libc::SYS_futex),
         allow_syscall_if(libc::SYS_ioctl, create_virtio_net_ctl_ioctl_seccomp_rule()?),
         allow_syscall(libc::SYS_madvise),
+        allow_syscall(libc::SYS_mmap),
+        allow_syscall(libc::SYS_mprotect),
         allow_syscall(libc::SYS_munmap),
         allow_syscall(libc::SYS_read),
         allow_syscall(libc::SYS_rt_sigprocmask),
@@ -260,6 +295,41 @@ fn virtio_net_ctl_thread_rules() -> Result<Vec<SyscallRuleSet>, Error> {
     ])
 }
 
+fn create_virtio_net_user_ioctl_seccomp_rule() -> Vec<SeccompRule> {
+    or![and![Cond::new(1, ArgLen::DWORD, Eq, FIONBIO,).unwrap()],]
+}
+
+fn virtio_net_user_thread_rules() -> Vec<SyscallRuleSet> {
+    vec![
+        allow_syscall(libc::SYS_accept4),
+        allow_syscall(libc::SYS_bind),
+        allow_syscall(libc::SYS_brk),
+        allow_syscall(libc::SYS_clock_gettime),
+        allow_syscall(libc::SYS_close),
+        allow_syscall(libc::SYS_connect),
+        allow_syscall(libc::SYS_epoll_ctl),
+        allow_syscall(libc::SYS_epoll_pwait),
+        #[cfg(target_arch = "x86_64")]
+        allow_syscall(libc::SYS_epoll_wait),
+        allow_syscall(libc::SYS_exit),
+        allow_syscall(libc::SYS_futex),
+        allow_syscall(libc::SYS_getrandom),
+        allow_syscall_if(libc::SYS_ioctl, create_virtio_net_user_ioctl_seccomp_rule()),
+        allow_syscall(libc::SYS_madvise),
+        allow_syscall(libc::SYS_mmap),
+        allow_syscall(libc::SYS_mremap),
+        allow_syscall(libc::SYS_munmap),
+        allow_syscall(libc::SYS_read),
+        allow_syscall(libc::SYS_recvfrom),
+        allow_syscall(libc::SYS_rt_sigprocmask),
+        allow_syscall(libc::SYS_sendto),
+        allow_syscall(libc::SYS_shutdown),
+        allow_syscall(libc::SYS_sigaltstack),
+        allow_syscall(libc::SYS_socket),
+        allow_syscall(libc::SYS_write),
+    ]
+}
+
 fn virtio_pmem_thread_rules() -> Vec<SyscallRuleSet> {
     vec![
         allow_syscall(libc::SYS_brk),
@@ -271,7 +341,6 @@ fn virtio_pmem_thread_rules() -> Vec<SyscallRuleSet> {
         #[cfg(target_arch = "x86_64")]
         allow_syscall(libc::SYS_epoll_wait),
         allow_syscall(libc::SYS_exit),
-        allow_syscall(libc::SYS_fsync),
         allow_syscall(libc::SYS_futex),
         allow_syscall(libc::SYS_madvise),
         allow_syscall(libc::SYS_munmap),
@@ -282,6 +351,28 @@ fn virtio_pmem_thread_rules() -> Vec<SyscallRuleSet> {
     ]
 }
 
+fn virtio_pmem_flush_thread_rules() -> Vec<SyscallRuleSet> {
+    vec![
+        allow_syscall(libc::SYS_brk),
+        allow_syscall(libc::SYS_close),
+        allow_syscall(libc::SYS_dup),
+        allow_syscall(libc::SYS_epoll_create1),
+        allow_syscall(libc::SYS_epoll_ctl),
+        allow_syscall(libc::SYS_epoll_pwait),
+        #[cfg(target_arch = "x86_64")]
+        allow_syscall(libc::SYS_epoll_wait),
+        allow_syscall(libc::SYS_exit),
+        allow_syscall(libc::SYS_futex),
+        allow_syscall(libc::SYS_madvise),
+        allow_syscall(libc::SYS_msync),
+        allow_syscall(libc::SYS_munmap),
+        allow_syscall(libc::SYS_read),
+        allow_syscall(libc::SYS_rt_sigprocmask),
+        allow_syscall(libc::SYS_sigaltstack),
+        allow_syscall(libc::SYS_write),
+    ]
+}
+
 fn virtio_rng_thread_rules() -> Vec<SyscallRuleSet> {
     vec![
         allow_syscall(libc::SYS_brk),
@@ -332,15 +423,45 @@ fn virtio_vhost_fs_thread_rules() -> Vec<SyscallRuleSet> {
     ]
 }
 
+fn virtio_vhost_generic_thread_rules() -> Vec<SyscallRuleSet> {
+    vec![
+        allow_syscall(libc::SYS_brk),
+        allow_syscall(libc::SYS_close),
+        allow_syscall(libc::SYS_dup),
+        allow_syscall(libc::SYS_epoll_create1),
+        allow_syscall(libc::SYS_epoll_ctl),
+        allow_syscall(libc::SYS_epoll_pwait),
+        #[cfg(target_arch = "x86_64")]
+        allow_syscall(libc::SYS_epoll_wait),
+        allow_syscall(libc::SYS_exit),
+        allow_syscall(libc::SYS_futex),
+        allow_syscall(libc::SYS_madvise),
+        allow_syscall(libc::SYS_mmap),
+        allow_syscall(libc::SYS_munmap),
+        allow_syscall(libc::SYS_read),
+        allow_syscall(libc::SYS_rt_sigprocmask),
+        allow_syscall(libc::SYS_sigaltstack),
+        allow_syscall(libc::SYS_write),
+    ]
+}
+
 fn create_vsock_ioctl_seccomp_rule() -> Vec<SeccompRule> {
     or![and![Cond::new(1, ArgLen::DWORD, Eq, FIONBIO,).unwrap()],]
 }
 
+fn create_vsock_socket_seccomp_rule() -> Vec<SeccompRule> {
+    or![
+        and![Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64).unwrap()],
+        and![Cond::new(0, ArgLen::DWORD, Eq, libc::AF_INET as u64).unwrap()],
+    ]
+}
+
 fn virtio_vsock_thread_rules() -> Vec<SyscallRuleSet> {
     vec![
         allow_syscall(libc::SYS_accept4),
         allow_syscall(libc::SYS_brk),
         allow_syscall(libc::SYS_close),
+        allow_syscall(libc::SYS_connect),
         allow_syscall(libc::SYS_dup),
         allow_syscall(libc::SYS_epoll_create1),
         allow_syscall(libc::SYS_epoll_ctl),
@@ -356,6 +477,7 @@ fn virtio_vsock_thread_rules() -> Vec<SyscallRuleSet> {
         allow_syscall(libc::SYS_recvfrom),
         allow_syscall(libc::SYS_rt_sigprocmask),
         allow_syscall(libc::SYS_sigaltstack),
+        allow_syscall_if(libc::SYS_socket, create_vsock_socket_seccomp_rule()),
         allow_syscall(libc::SYS_write),
     ]
 }
@@ -387,7 +509,7 @@ fn virtio_watchdog_thread_rules() -> Vec<SyscallRuleSet> {
     ]
 }
 
-fn get_seccomp_filter_trap(thread_type: Thread) -> Result<SeccompFilter, Error> {
+fn get_seccomp_filter_trap(thread_type: &Thread) -> Result<SeccompFilter, Error> {
     let rules = match thread_type {
         Thread::VirtioBalloon => virtio_balloon_thread_rules(),
         Thread::VirtioBlock => virtio_block_thread_rules(),
@@ -396,17 +518,23 @@ fn get_seccomp_filter_trap(thread_type: Thread) -> Result<SeccompFilter, Error>
         Thread::VirtioMem => virtio_mem_thread_rules(),
         Thread::VirtioNet => virtio_net_thread_rules(),
         Thread::VirtioNetCtl => virtio_net_ctl_thread_rules()?,
+        Thread::VirtioNetUser => virtio_net_user_thread_rules(),
         Thread::VirtioPmem => virtio_pmem_thread_rules(),
+        Thread::VirtioPmemFlush => virtio_pmem_flush_thread_rules(),
         Thread::VirtioRng => virtio_rng_thread_rules(),
         Thread::VirtioVhostFs => virtio_vhost_fs_thread_rules(),
+        Thread::VirtioVhostGeneric => virtio_vhost_generic_thread_rules(),
         Thread::VirtioVsock => virtio_vsock_thread_rules(),
         Thread::VirtioWatchdog => virtio_watchdog_thread_rules(),
     };
 
-    SeccompFilter::new(rules.into_iter().collect(), SeccompAction::Trap)
+    SeccompFilter::new(
+        apply_profile(thread_type.name(), rules)?,
+        SeccompAction::Trap,
+    )
 }
 
-fn get_seccomp_filter_log(thread_type: Thread) -> Result<SeccompFilter, Error> {
+fn get_seccomp_filter_log(thread_type: &Thread) -> Result<SeccompFilter, Error> {
     let rules = match thread_type {
         Thread::VirtioBalloon => virtio_balloon_thread_rules(),
         Thread::VirtioBlock => virtio_block_thread_rules(),
@@ -415,14 +543,20 @@ fn get_seccomp_filter_log(thread_type: Thread) -> Result<SeccompFilter, Error> {
         Thread::VirtioMem => virtio_mem_thread_rules(),
         Thread::VirtioNet => virtio_net_thread_rules(),
         Thread::VirtioNetCtl => virtio_net_ctl_thread_rules()?,
+        Thread::VirtioNetUser => virtio_net_user_thread_rules(),
         Thread::VirtioPmem => virtio_pmem_thread_rules(),
+        Thread::VirtioPmemFlush => virtio_pmem_flush_thread_rules(),
         Thread::VirtioRng => virtio_rng_thread_rules(),
         Thread::VirtioVhostFs => virtio_vhost_fs_thread_rules(),
+        Thread::VirtioVhostGeneric => virtio_vhost_generic_thread_rules(),
         Thread::VirtioVsock => virtio_vsock_thread_rules(),
         Thread::VirtioWatchdog => virtio_watchdog_thread_rules(),
     };
 
-    SeccompFilter::new(rules.into_iter().collect(), SeccompAction::Log)
+    SeccompFilter::new(
+        apply_profile(thread_type.name(), rules)?,
+        SeccompAction::Log,
+    )
 }
 
 /// Generate a BPF program based on the seccomp_action value
@@ -432,11 +566,15 @@ pub fn get_seccomp_filter(
 ) -> Result<BpfProgram, SeccompError> {
     match seccomp_action {
         SeccompAction::Allow => Ok(vec![]),
-        SeccompAction::Log => get_seccomp_filter_log(thread_type)
+        SeccompAction::Log => get_seccomp_filter_log(&thread_type)
             .and_then(|filter| filter.try_into())
             .map_err(SeccompError::SeccompFilter),
-        _ => get_seccomp_filter_trap(thread_type)
+        _ => get_seccomp_filter_trap(&thread_type)
             .and_then(|filter| filter.try_into())
+            .map(|mut program: BpfProgram| {
+                tag_trap(&mut program, thread_type.name());
+                program
+            })
             .map_err(SeccompError::SeccompFilter),
     }
 }
@@ -0,0 +1,566 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Seccomp profiles, loaded from a JSON file, which extend or replace the
+//! built-in seccomp filters of each thread type.
+//!
+//! ```json
+//! {
+//!   "vcpu": {
+//!     "syscalls": [
+//!       { "syscall": "getcpu" },
+//!       { "syscall": "ioctl", "args": [{ "index": 1, "len": "dword", "op": "eq", "value": 21505 }] }
+//!     ]
+//!   },
+//!   "virtio_rng": { "mode": "replace", "syscalls": [{ "syscall": "read" }] }
+//! }
+//! ```
+//!
+//! The filters of both the vmm and the virtio-devices crates are built
+//! against the same profile, which is why it lives in the lowest of the two.
+
+mod syscalls;
+
+use self::syscalls::SYSCALLS;
+use seccomp::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompRule,
+    SyscallRuleSet,
+};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::RwLock;
+
+/// Thread types a profile can apply to. The trap returns of the filter of a
+/// thread type are tagged with its index in this list, plus one.
+pub const THREAD_TYPES: &[&str] = &[
+    "api",
+    "gdb",
+    "signal_handler",
+    "vcpu",
+    "vmm",
+    "virtio_balloon",
+    "virtio_block",
+    "virtio_console",
+    "virtio_iommu",
+    "virtio_mem",
+    "virtio_net",
+    "virtio_net_ctl",
+    "virtio_net_user",
+    "virtio_pmem",
+    "virtio_pmem_flush",
+    "virtio_rng",
+    "virtio_vhost_fs",
+    "virtio_vhost_generic",
+    "virtio_vsock",
+    "virtio_watchdog",
+];
+
+// See include/uapi/linux/filter.h and include/uapi/linux/seccomp.h in the
+// kernel code.
+const BPF_RET_K: u16 = 0x06;
+const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+
+lazy_static! {
+    static ref PROFILE: RwLock<Option<SeccompProfile>> = RwLock::new(None);
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Cannot open the profile file
+    Open(io::Error),
+    /// Cannot parse the profile file
+    Parse(serde_json::Error),
+    /// Cannot write the profile file
+    Write(io::Error),
+    /// Cannot serialize the profile
+    Serialize(serde_json::Error),
+    /// Unknown thread type
+    UnknownThread(String),
+    /// Unknown syscall
+    UnknownSyscall(String),
+    /// Invalid syscall argument condition
+    InvalidCondition(String, seccomp::Error),
+}
+
+/// Size of the syscall argument being compared
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgLen {
+    Dword,
+    Qword,
+}
+
+impl Default for ArgLen {
+    fn default() -> Self {
+        ArgLen::Qword
+    }
+}
+
+/// Comparison of a syscall argument with the value of a condition
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Compare the argument, masked with the given mask, with the value
+    MaskedEq(u64),
+}
+
+/// Condition on a syscall argument
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArgCondition {
+    /// Index of the argument, from 0 to 5
+    pub index: u8,
+    #[serde(default)]
+    pub len: ArgLen,
+    pub op: ArgOp,
+    pub value: u64,
+}
+
+impl ArgCondition {
+    fn to_seccomp(&self) -> Result<SeccompCondition, seccomp::Error> {
+        let len = match self.len {
+            ArgLen::Dword => SeccompCmpArgLen::DWORD,
+            ArgLen::Qword => SeccompCmpArgLen::QWORD,
+        };
+        let op = match self.op {
+            ArgOp::Eq => SeccompCmpOp::Eq,
+            ArgOp::Ne => SeccompCmpOp::Ne,
+            ArgOp::Lt => SeccompCmpOp::Lt,
+            ArgOp::Le => SeccompCmpOp::Le,
+            ArgOp::Gt => SeccompCmpOp::Gt,
+            ArgOp::Ge => SeccompCmpOp::Ge,
+            ArgOp::MaskedEq(mask) => SeccompCmpOp::MaskedEq(mask),
+        };
+
+        SeccompCondition::new(self.index, len, op, self.value)
+    }
+}
+
+/// Syscall allowed when all its argument conditions are met, or
+/// unconditionally if there are none. A syscall listed several times is
+/// allowed when any of its entries matches.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyscallProfile {
+    /// Syscall name, or number
+    pub syscall: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<ArgCondition>,
+}
+
+impl SyscallProfile {
+    fn to_seccomp(&self) -> Result<SeccompRule, seccomp::Error> {
+        let conditions = self
+            .args
+            .iter()
+            .map(ArgCondition::to_seccomp)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(SeccompRule::new(conditions, SeccompAction::Allow))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileMode {
+    /// Allow the syscalls of the profile on top of the built-in rules
+    Extend,
+    /// Only allow the syscalls of the profile
+    Replace,
+}
+
+impl Default for ProfileMode {
+    fn default() -> Self {
+        ProfileMode::Extend
+    }
+}
+
+/// Profile of a thread type
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThreadProfile {
+    #[serde(default)]
+    pub mode: ProfileMode,
+    #[serde(default)]
+    pub syscalls: Vec<SyscallProfile>,
+}
+
+/// Profiles indexed by thread type
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct SeccompProfile {
+    threads: BTreeMap<String, ThreadProfile>,
+}
+
+impl SeccompProfile {
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path).map_err(Error::Open)?;
+        let profile: SeccompProfile =
+            serde_json::from_reader(BufReader::new(file)).map_err(Error::Parse)?;
+        profile.validate()?;
+
+        Ok(profile)
+    }
+
+    pub fn to_file(&self, path: &Path) -> Result<(), Error> {
+        let file = File::create(path).map_err(Error::Write)?;
+        serde_json::to_writer_pretty(file, self).map_err(Error::Serialize)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for (thread, profile) in self.threads.iter() {
+            if !THREAD_TYPES.contains(&thread.as_str()) {
+                return Err(Error::UnknownThread(thread.clone()));
+            }
+
+            for syscall in profile.syscalls.iter() {
+                if syscall_number(&syscall.syscall).is_none() {
+                    return Err(Error::UnknownSyscall(syscall.syscall.clone()));
+                }
+                syscall
+                    .to_seccomp()
+                    .map_err(|e| Error::InvalidCondition(syscall.syscall.clone(), e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn thread(&self, thread: &str) -> Option<&ThreadProfile> {
+        self.threads.get(thread)
+    }
+
+    /// Allow `syscall` unconditionally for `thread`, unless it already is.
+    pub fn allow(&mut self, thread: &str, syscall: &str) {
+        let syscalls = &mut self.threads.entry(thread.to_string()).or_default().syscalls;
+        if !syscalls
+            .iter()
+            .any(|s| s.syscall == syscall && s.args.is_empty())
+        {
+            syscalls.push(SyscallProfile {
+                syscall: syscall.to_string(),
+                args: Vec::new(),
+            });
+        }
+    }
+}
+
+/// This function must only be called once from the main process before any threads
+/// are created to avoid race conditions
+pub fn set_profile(profile: SeccompProfile) {
+    let mut current = PROFILE.write().unwrap();
+    assert!(current.is_none());
+    *current = Some(profile);
+}
+
+/// Allow `syscall` for `thread` in the profile at `path`, which is created if
+/// it doesn't exist yet.
+pub fn record_syscall(path: &Path, thread: &str, syscall: &str) -> Result<(), Error> {
+    let mut profile = if path.exists() {
+        SeccompProfile::from_file(path)?
+    } else {
+        SeccompProfile::default()
+    };
+    profile.allow(thread, syscall);
+    profile.to_file(path)
+}
+
+/// Build the rules of `thread` from its built-in `rules` and its profile.
+pub fn apply_profile(
+    thread: &str,
+    rules: Vec<SyscallRuleSet>,
+) -> Result<BTreeMap<i64, Vec<SeccompRule>>, seccomp::Error> {
+    let profile = PROFILE.read().unwrap();
+    merge_rules(profile.as_ref().and_then(|p| p.thread(thread)), rules)
+}
+
+fn merge_rules(
+    profile: Option<&ThreadProfile>,
+    rules: Vec<SyscallRuleSet>,
+) -> Result<BTreeMap<i64, Vec<SeccompRule>>, seccomp::Error> {
+    let profile = match profile {
+        Some(profile) => profile,
+        None => return Ok(rules.into_iter().collect()),
+    };
+
+    let mut rules: BTreeMap<i64, Vec<SeccompRule>> = match profile.mode {
+        ProfileMode::Extend => rules.into_iter().collect(),
+        ProfileMode::Replace => BTreeMap::new(),
+    };
+    for syscall in profile.syscalls.iter() {
+        // Unknown syscalls are rejected when the profile is loaded.
+        if let Some(number) = syscall_number(&syscall.syscall) {
+            rules
+                .entry(number)
+                .or_insert_with(Vec::new)
+                .push(syscall.to_seccomp()?);
+        }
+    }
+
+    Ok(rules)
+}
+
+/// Tag the trap returns of the filter of `thread` with its thread type. The
+/// kernel hands the data part of the return value over to the SIGSYS handler
+/// as si_errno, which tells which thread type hit a seccomp violation.
+pub fn tag_trap(program: &mut BpfProgram, thread: &str) {
+    if let Some(index) = THREAD_TYPES.iter().position(|t| *t == thread) {
+        for insn in program
+            .iter_mut()
+            .filter(|insn| insn.code == BPF_RET_K && insn.k == SECCOMP_RET_TRAP)
+        {
+            insn.k |= index as u32 + 1;
+        }
+    }
+}
+
+/// Thread type a trap was tagged with by `tag_trap()`.
+pub fn trap_thread(data: i32) -> Option<&'static str> {
+    if data > 0 {
+        THREAD_TYPES.get(data as usize - 1).copied()
+    } else {
+        None
+    }
+}
+
+fn syscalls() -> impl Iterator<Item = (&'static str, i64)> {
+    SYSCALLS
+        .iter()
+        .map(|(name, number)| (name.trim_start_matches("SYS_"), *number))
+}
+
+/// Syscall number from its name, or from its number as a string.
+pub fn syscall_number(syscall: &str) -> Option<i64> {
+    syscalls()
+        .find(|(name, _)| *name == syscall)
+        .map(|(_, number)| number)
+        .or_else(|| syscall.parse().ok())
+}
+
+pub fn syscall_name(number: i64) -> Option<&'static str> {
+    syscalls()
+        .find(|(_, n)| *n == number)
+        .map(|(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use seccomp::SeccompFilter;
+    use std::convert::TryInto;
+
+    fn builtin_rules() -> Vec<SyscallRuleSet> {
+        vec![
+            (libc::SYS_read, vec![]),
+            (
+                libc::SYS_ioctl,
+                vec![SeccompRule::new(
+                    vec![SeccompCondition::new(
+                        1,
+                        SeccompCmpArgLen::DWORD,
+                        SeccompCmpOp::Eq,
+                        0x5401,
+                    )
+                    .unwrap()],
+                    SeccompAction::Allow,
+                )],
+            ),
+        ]
+    }
+
+    fn thread_profile(mode: ProfileMode) -> ThreadProfile {
+        ThreadProfile {
+            mode,
+            syscalls: vec![
+                SyscallProfile {
+                    syscall: "getcpu".to_owned(),
+                    args: Vec::new(),
+                },
+                SyscallProfile {
+                    syscall: "ioctl".to_owned(),
+                    args: vec![ArgCondition {
+                        index: 1,
+                        len: ArgLen::Dword,
+                        op: ArgOp::Eq,
+                        value: 0x5402,
+                    }],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_profile_parsing() {
+        let profile: SeccompProfile = serde_json::from_str(
+            r#"{
+                "vcpu": {
+                    "syscalls": [
+                        { "syscall": "getcpu" },
+                        { "syscall": "ioctl", "args": [{ "index": 1, "len": "dword", "op": "eq", "value": 21505 }] }
+                    ]
+                },
+                "virtio_rng": {
+                    "mode": "replace",
+                    "syscalls": [{ "syscall": "read", "args": [{ "index": 0, "op": { "masked_eq": 255 }, "value": 3 }] }]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(profile.validate().is_ok());
+
+        let vcpu = profile.thread("vcpu").unwrap();
+        assert_eq!(vcpu.mode, ProfileMode::Extend);
+        assert_eq!(
+            vcpu.syscalls[1].args,
+            vec![ArgCondition {
+                index: 1,
+                len: ArgLen::Dword,
+                op: ArgOp::Eq,
+                value: 21505,
+            }]
+        );
+
+        let rng = profile.thread("virtio_rng").unwrap();
+        assert_eq!(rng.mode, ProfileMode::Replace);
+        assert_eq!(rng.syscalls[0].args[0].len, ArgLen::Qword);
+        assert_eq!(rng.syscalls[0].args[0].op, ArgOp::MaskedEq(255));
+    }
+
+    #[test]
+    fn test_profile_validation() {
+        let profile: SeccompProfile =
+            serde_json::from_str(r#"{ "vcpus": { "syscalls": [{ "syscall": "read" }] } }"#)
+                .unwrap();
+        assert!(matches!(profile.validate(), Err(Error::UnknownThread(_))));
+
+        let profile: SeccompProfile =
+            serde_json::from_str(r#"{ "vcpu": { "syscalls": [{ "syscall": "reed" }] } }"#).unwrap();
+        assert!(matches!(profile.validate(), Err(Error::UnknownSyscall(_))));
+
+        let profile: SeccompProfile = serde_json::from_str(
+            r#"{ "vcpu": { "syscalls": [{ "syscall": "read", "args": [{ "index": 6, "op": "eq", "value": 0 }] }] } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            profile.validate(),
+            Err(Error::InvalidCondition(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_profile_allow() {
+        let mut profile = SeccompProfile::default();
+        profile.allow("vcpu", "getcpu");
+        profile.allow("vcpu", "getcpu");
+        profile.allow("api", "getcpu");
+
+        assert_eq!(profile.thread("vcpu").unwrap().syscalls.len(), 1);
+        assert_eq!(
+            serde_json::to_string(&profile).unwrap(),
+            r#"{"api":{"mode":"extend","syscalls":[{"syscall":"getcpu"}]},"vcpu":{"mode":"extend","syscalls":[{"syscall":"getcpu"}]}}"#
+        );
+    }
+
+    #[test]
+    fn test_syscall_lookup() {
+        assert_eq!(syscall_number("write"), Some(libc::SYS_write));
+        assert_eq!(syscall_number("ioctl"), Some(libc::SYS_ioctl));
+        assert_eq!(syscall_number("1234"), Some(1234));
+        assert_eq!(syscall_number("reed"), None);
+        assert_eq!(syscall_name(libc::SYS_openat), Some("openat"));
+    }
+
+    #[test]
+    fn test_merge_rules() {
+        // Without any profile, the built-in rules are kept as they are.
+        let rules = merge_rules(None, builtin_rules()).unwrap();
+        assert_eq!(
+            rules.keys().copied().collect::<Vec<_>>(),
+            vec![libc::SYS_read, libc::SYS_ioctl]
+        );
+
+        // The rules of an extending profile are merged with the built-in
+        // ones, a syscall present in both being allowed by either rule.
+        let profile = thread_profile(ProfileMode::Extend);
+        let rules = merge_rules(Some(&profile), builtin_rules()).unwrap();
+        assert_eq!(
+            rules.keys().copied().collect::<Vec<_>>(),
+            vec![libc::SYS_read, libc::SYS_ioctl, libc::SYS_getcpu]
+        );
+        assert!(rules[&libc::SYS_read].is_empty());
+        assert_eq!(rules[&libc::SYS_ioctl].len(), 2);
+        assert_eq!(rules[&libc::SYS_getcpu].len(), 1);
+
+        // A replacing profile discards the built-in rules.
+        let profile = thread_profile(ProfileMode::Replace);
+        let rules = merge_rules(Some(&profile), builtin_rules()).unwrap();
+        assert_eq!(
+            rules.keys().copied().collect::<Vec<_>>(),
+            vec![libc::SYS_ioctl, libc::SYS_getcpu]
+        );
+        assert_eq!(rules[&libc::SYS_ioctl].len(), 1);
+    }
+
+    #[test]
+    fn test_tag_trap() {
+        let filter = |default_action| -> BpfProgram {
+            SeccompFilter::new(builtin_rules().into_iter().collect(), default_action)
+                .unwrap()
+                .try_into()
+                .unwrap()
+        };
+        let program = filter(SeccompAction::Trap);
+        assert!(program
+            .iter()
+            .any(|insn| insn.code == BPF_RET_K && insn.k == SECCOMP_RET_TRAP));
+
+        // Only the trap returns are tagged, with the index of the thread type
+        // plus one, every other instruction being left untouched.
+        let mut tagged = filter(SeccompAction::Trap);
+        tag_trap(&mut tagged, "vcpu");
+        assert_eq!(tagged.len(), program.len());
+        for (insn, tagged_insn) in program.iter().zip(tagged.iter()) {
+            assert_eq!(tagged_insn.code, insn.code);
+            if insn.code == BPF_RET_K && insn.k == SECCOMP_RET_TRAP {
+                assert_eq!(tagged_insn.k, SECCOMP_RET_TRAP | 4);
+                assert_eq!(trap_thread((tagged_insn.k & 0xffff) as i32), Some("vcpu"));
+            } else {
+                assert_eq!(tagged_insn.k, insn.k);
+            }
+        }
+
+        // Unknown thread types aren't tagged.
+        let mut untagged = filter(SeccompAction::Trap);
+        tag_trap(&mut untagged, "vcpus");
+        assert!(program
+            .iter()
+            .zip(untagged.iter())
+            .all(|(insn, untagged_insn)| insn.k == untagged_insn.k));
+
+        // Filters which never trap are left untouched.
+        let program = filter(SeccompAction::Log);
+        let mut tagged = filter(SeccompAction::Log);
+        tag_trap(&mut tagged, "vcpu");
+        assert!(program
+            .iter()
+            .zip(tagged.iter())
+            .all(|(insn, tagged_insn)| insn.k == tagged_insn.k));
+    }
+
+    #[test]
+    fn test_trap_thread() {
+        assert_eq!(trap_thread(0), None);
+        assert_eq!(trap_thread(4), Some("vcpu"));
+        assert_eq!(trap_thread(THREAD_TYPES.len() as i32 + 1), None);
+    }
+}
@@ -0,0 +1,341 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::vu_common_ctrl::{
+    add_memory_region, reset_vhost_user, setup_vhost_user, update_mem_table,
+};
+use super::{Error, Result};
+use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::vhost_user::handler::{VhostUserEpollConfig, VhostUserEpollHandler};
+use crate::{
+    ActivateError, ActivateResult, Queue, VirtioCommon, VirtioDevice, VirtioInterrupt,
+    VIRTIO_F_IOMMU_PLATFORM,
+};
+use seccomp::{SeccompAction, SeccompFilter};
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::result;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use vhost::vhost_user::message::{
+    VhostUserConfigFlags, VhostUserProtocolFeatures, VhostUserVirtioFeatures,
+    VHOST_USER_CONFIG_OFFSET, VHOST_USER_CONFIG_SIZE,
+};
+use vhost::vhost_user::{Master, MasterReqHandler, VhostUserMaster, VhostUserMasterReqHandler};
+use vhost::VhostBackend;
+use vm_memory::{GuestAddressSpace, GuestMemoryAtomic, GuestMemoryMmap, GuestRegionMmap};
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshottable, Transportable};
+use vmm_sys_util::eventfd::EventFd;
+
+struct SlaveReqHandler {}
+impl VhostUserMasterReqHandler for SlaveReqHandler {}
+
+/// Generic vhost-user device frontend.
+///
+/// The VMM has no knowledge of the device being emulated by the backend.
+/// The virtio device type and the queues are provided by the user, the
+/// virtio features are the ones offered by the backend, and accesses to
+/// the configuration space are forwarded to the backend.
+pub struct Generic {
+    common: VirtioCommon,
+    id: String,
+    vu: Master,
+    seccomp_action: SeccompAction,
+    guest_memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+    acked_protocol_features: u64,
+}
+
+impl Generic {
+    /// Create a new generic vhost-user device
+    pub fn new(
+        id: String,
+        device_type: u32,
+        path: &str,
+        queue_sizes: Vec<u16>,
+        seccomp_action: SeccompAction,
+    ) -> Result<Generic> {
+        let num_queues = queue_sizes.len();
+
+        // Connect to the vhost-user socket.
+        let mut master =
+            Master::connect(path, num_queues as u64).map_err(Error::VhostUserCreateMaster)?;
+
+        // Set vhost-user owner.
+        master.set_owner().map_err(Error::VhostUserSetOwner)?;
+
+        // The device is unknown to the VMM, hence every feature from the
+        // backend is exposed to the guest. The only exception is the
+        // IOMMU support, since these devices can't be placed behind the
+        // virtual IOMMU.
+        let backend_features = master.get_features().map_err(Error::VhostUserGetFeatures)?;
+        let avail_features = backend_features & !(1 << VIRTIO_F_IOMMU_PLATFORM);
+        // Set features back is required by the vhost crate mechanism, since the
+        // later vhost call will check if features is filled in master before execution.
+        master
+            .set_features(avail_features)
+            .map_err(Error::VhostUserSetFeatures)?;
+
+        // Identify if protocol features are supported by the slave.
+        let mut acked_features = 0;
+        let mut acked_protocol_features = 0;
+        if avail_features & VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits() != 0 {
+            acked_features |= VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits();
+
+            let mut protocol_features = master
+                .get_protocol_features()
+                .map_err(Error::VhostUserGetProtocolFeatures)?;
+            protocol_features &= VhostUserProtocolFeatures::CONFIG
+                | VhostUserProtocolFeatures::MQ
+                | VhostUserProtocolFeatures::REPLY_ACK
+                | VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS;
+            master
+                .set_protocol_features(protocol_features)
+                .map_err(Error::VhostUserSetProtocolFeatures)?;
+
+            acked_protocol_features = protocol_features.bits();
+        }
+
+        // The maximum number of queues can only be retrieved from the
+        // backend if it supports the MQ protocol feature.
+        if acked_protocol_features & VhostUserProtocolFeatures::MQ.bits() != 0 {
+            let max_queues_num = master
+                .get_queue_num()
+                .map_err(Error::VhostUserGetQueueMaxNum)?;
+
+            if num_queues > max_queues_num as usize {
+                error!("vhost-user generic device has queue number: {} larger than the max queue number: {} backend allowed",
+                    num_queues, max_queues_num);
+                return Err(Error::BadQueueNum);
+            }
+        }
+
+        Ok(Generic {
+            common: VirtioCommon {
+                device_type,
+                queue_sizes,
+                avail_features,
+                acked_features,
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                min_queues: 1,
+                ..Default::default()
+            },
+            id,
+            vu: master,
+            seccomp_action,
+            guest_memory: None,
+            acked_protocol_features,
+        })
+    }
+
+    fn config_supported(&self) -> bool {
+        self.acked_protocol_features & VhostUserProtocolFeatures::CONFIG.bits() != 0
+    }
+}
+
+impl Drop for Generic {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+    }
+}
+
+impl VirtioDevice for Generic {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        // Some devices don't have any configuration space, in which case
+        // the backend isn't expected to support the CONFIG feature.
+        if !self.config_supported() {
+            return;
+        }
+
+        if offset + data.len() as u64 > VHOST_USER_CONFIG_SIZE as u64 {
+            error!(
+                "Out-of-bound access to configuration: offset = 0x{:x} length = {}",
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        // The master is shared with the clone, which only allows the
+        // configuration to be retrieved from a non mutable context.
+        match self.vu.clone().get_config(
+            VHOST_USER_CONFIG_OFFSET + offset as u32,
+            data.len() as u32,
+            VhostUserConfigFlags::WRITABLE,
+            &vec![0u8; data.len()],
+        ) {
+            Ok((_, config)) => data.copy_from_slice(&config[..data.len()]),
+            Err(e) => error!("Failed getting vhost-user configuration: {:?}", e),
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        if !self.config_supported() {
+            warn!(
+                "vhost-user backend for {} does not expose a configuration space",
+                self.id
+            );
+            return;
+        }
+
+        if let Err(e) = self.vu.set_config(
+            VHOST_USER_CONFIG_OFFSET + offset as u32,
+            VhostUserConfigFlags::WRITABLE,
+            data,
+        ) {
+            error!("Failed setting vhost-user configuration: {:?}", e);
+        }
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        queues: Vec<Queue>,
+        queue_evts: Vec<EventFd>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &queue_evts, &interrupt_cb)?;
+
+        self.guest_memory = Some(mem.clone());
+
+        let kill_evt = self
+            .common
+            .kill_evt
+            .as_ref()
+            .unwrap()
+            .try_clone()
+            .map_err(|e| {
+                error!("failed to clone kill_evt eventfd: {}", e);
+                ActivateError::BadActivate
+            })?;
+        let pause_evt = self
+            .common
+            .pause_evt
+            .as_ref()
+            .unwrap()
+            .try_clone()
+            .map_err(|e| {
+                error!("failed to clone pause_evt eventfd: {}", e);
+                ActivateError::BadActivate
+            })?;
+
+        setup_vhost_user(
+            &mut self.vu,
+            &mem.memory(),
+            queues,
+            queue_evts,
+            &interrupt_cb,
+        )
+        .map_err(ActivateError::VhostUserGenericSetup)?;
+
+        let mut handler = VhostUserEpollHandler::new(VhostUserEpollConfig {
+            kill_evt,
+            pause_evt,
+            slave_req_handler: None::<MasterReqHandler<SlaveReqHandler>>,
+        });
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        let virtio_vhost_generic_seccomp_filter =
+            get_seccomp_filter(&self.seccomp_action, Thread::VirtioVhostGeneric)
+                .map_err(ActivateError::CreateSeccompFilter)?;
+        thread::Builder::new()
+            .name(self.id.clone())
+            .spawn(move || {
+                if let Err(e) = SeccompFilter::apply(virtio_vhost_generic_seccomp_filter) {
+                    error!("Error applying seccomp filter: {:?}", e);
+                } else if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
+                    error!("Error running worker: {:?}", e);
+                }
+            })
+            .map(|thread| epoll_threads.push(thread))
+            .map_err(|e| {
+                error!("failed to clone queue EventFd: {}", e);
+                ActivateError::BadActivate
+            })?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        // We first must resume the virtio thread if it was paused.
+        if self.common.pause_evt.take().is_some() {
+            self.common.resume().ok()?;
+        }
+
+        if let Err(e) = reset_vhost_user(&mut self.vu, self.common.queue_sizes.len()) {
+            error!("Failed to reset vhost-user daemon: {:?}", e);
+            return None;
+        }
+
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+
+        event!("virtio-device", "reset", "id", &self.id);
+
+        // Return the interrupt
+        Some(self.common.interrupt_cb.take().unwrap())
+    }
+
+    fn shutdown(&mut self) {
+        let _ = unsafe { libc::close(self.vu.as_raw_fd()) };
+    }
+
+    fn add_memory_region(
+        &mut self,
+        region: &Arc<GuestRegionMmap>,
+    ) -> std::result::Result<(), crate::Error> {
+        if self.acked_protocol_features & VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS.bits() != 0
+        {
+            add_memory_region(&mut self.vu, region).map_err(crate::Error::VhostUserAddMemoryRegion)
+        } else if let Some(guest_memory) = &self.guest_memory {
+            update_mem_table(&mut self.vu, guest_memory.memory().deref())
+                .map_err(crate::Error::VhostUserUpdateMemory)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Pausable for Generic {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Generic {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+}
+impl Transportable for Generic {}
+impl Migratable for Generic {}
@@ -68,6 +68,33 @@ pub fn add_memory_region(vu: &mut Master, region: &Arc<GuestRegionMmap>) -> Resu
         .map_err(Error::VhostUserAddMemReg)
 }
 
+/// Translate the addresses of the given queue into host virtual addresses,
+/// as expected by a vhost backend sharing the VMM mappings.
+pub fn vring_config_data(mem: &GuestMemoryMmap, queue: &Queue) -> Result<VringConfigData> {
+    let actual_size: usize = queue.actual_size().try_into().unwrap();
+
+    Ok(VringConfigData {
+        queue_max_size: queue.get_max_size(),
+        queue_size: queue.actual_size(),
+        flags: 0u32,
+        desc_table_addr: get_host_address_range(
+            mem,
+            queue.desc_table,
+            actual_size * std::mem::size_of::<Descriptor>(),
+        )
+        .ok_or(Error::DescriptorTableAddress)? as u64,
+        // The used ring is {flags: u16; idx: u16; virtq_used_elem [{id: u16, len: u16}; actual_size]},
+        // i.e. 4 + (4 + 4) * actual_size.
+        used_ring_addr: get_host_address_range(mem, queue.used_ring, 4 + actual_size * 8)
+            .ok_or(Error::UsedAddress)? as u64,
+        // The used ring is {flags: u16; idx: u16; elem [u16; actual_size]},
+        // i.e. 4 + (2) * actual_size.
+        avail_ring_addr: get_host_address_range(mem, queue.avail_ring, 4 + actual_size * 2)
+            .ok_or(Error::AvailAddress)? as u64,
+        log_addr: None,
+    })
+}
+
 pub fn setup_vhost_user(
     vu: &mut Master,
     mem: &GuestMemoryMmap,
@@ -79,32 +106,10 @@ pub fn setup_vhost_user(
     update_mem_table(vu, mem)?;
 
     for (queue_index, queue) in queues.into_iter().enumerate() {
-        let actual_size: usize = queue.actual_size().try_into().unwrap();
-
         vu.set_vring_num(queue_index, queue.actual_size())
             .map_err(Error::VhostUserSetVringNum)?;
 
-        let config_data = VringConfigData {
-            queue_max_size: queue.get_max_size(),
-            queue_size: queue.actual_size(),
-            flags: 0u32,
-            desc_table_addr: get_host_address_range(
-                mem,
-                queue.desc_table,
-                actual_size * std::mem::size_of::<Descriptor>(),
-            )
-            .ok_or(Error::DescriptorTableAddress)? as u64,
-            // The used ring is {flags: u16; idx: u16; virtq_used_elem [{id: u16, len: u16}; actual_size]},
-            // i.e. 4 + (4 + 4) * actual_size.
-            used_ring_addr: get_host_address_range(mem, queue.used_ring, 4 + actual_size * 8)
-                .ok_or(Error::UsedAddress)? as u64,
-            // The used ring is {flags: u16; idx: u16; elem [u16; actual_size]},
-            // i.e. 4 + (2) * actual_size.
-            avail_ring_addr: get_host_address_range(mem, queue.avail_ring, 4 + actual_size * 2)
-                .ok_or(Error::AvailAddress)? as u64,
-            log_addr: None,
-        };
-
+        let config_data = vring_config_data(mem, &queue)?;
         vu.set_vring_addr(queue_index, &config_data)
             .map_err(Error::VhostUserSetVringAddr)?;
         vu.set_vring_base(queue_index, 0u16)
@@ -8,6 +8,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use super::defs::uapi;
 use super::{VsockBackend, VsockPacket};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::Error as DeviceError;
@@ -45,7 +46,7 @@ use std::result;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Barrier, RwLock};
 use std::thread;
-use vm_memory::{GuestAddressSpace, GuestMemoryAtomic, GuestMemoryMmap};
+use vm_memory::{Bytes, GuestAddressSpace, GuestMemoryAtomic, GuestMemoryMmap};
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
 use vmm_sys_util::eventfd::EventFd;
 
@@ -79,6 +80,8 @@ pub const BACKEND_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
 /// - on backend event:
 ///   - forward the event to the backend; then
 ///   - again, attempt to fetch any incoming packets queued by the backend into virtio RX buffers.
+/// - on event queue event:
+///   - deliver the pending transport reset event, if any.
 ///
 pub struct VsockEpollHandler<B: VsockBackend> {
     pub mem: GuestMemoryAtomic<GuestMemoryMmap>,
@@ -88,6 +91,9 @@ pub struct VsockEpollHandler<B: VsockBackend> {
     pub pause_evt: EventFd,
     pub interrupt_cb: Arc<dyn VirtioInterrupt>,
     pub backend: Arc<RwLock<B>>,
+    /// A transport reset event must be delivered to the guest, as soon as it provides an event
+    /// queue buffer.
+    pub pending_reset: bool,
 }
 
 impl<B> VsockEpollHandler<B>
@@ -190,11 +196,53 @@ where
         }
     }
 
+    /// Deliver the pending transport reset event into the first available event queue buffer.
+    ///
+    fn process_evt(&mut self) -> result::Result<(), DeviceError> {
+        if !self.pending_reset {
+            return Ok(());
+        }
+
+        debug!("vsock: epoll_handler::process_evt()");
+
+        let mem = self.mem.memory();
+        let (desc_index, len) = match self.queues[2].iter(&mem).next() {
+            Some(avail_desc) => {
+                let event_len = std::mem::size_of::<u32>();
+                if avail_desc.is_write_only() && avail_desc.len as usize >= event_len {
+                    match mem.write_obj(uapi::VSOCK_EVENT_TRANSPORT_RESET.to_le(), avail_desc.addr)
+                    {
+                        Ok(_) => {
+                            self.pending_reset = false;
+                            (avail_desc.index, event_len as u32)
+                        }
+                        Err(e) => {
+                            warn!("vsock: failed to write transport reset event: {:?}", e);
+                            (avail_desc.index, 0)
+                        }
+                    }
+                } else {
+                    warn!("vsock: invalid event queue buffer");
+                    (avail_desc.index, 0)
+                }
+            }
+            None => return Ok(()),
+        };
+
+        self.queues[2].add_used(&mem, desc_index, len);
+        self.signal_used_queue(&self.queues[2])
+    }
+
     fn run(
         &mut self,
         paused: Arc<AtomicBool>,
         paused_sync: Arc<Barrier>,
     ) -> result::Result<(), EpollHelperError> {
+        // The guest may already have provided an event queue buffer.
+        if let Err(e) = self.process_evt() {
+            error!("Failed to process EVT queue: {:?}", e);
+        }
+
         let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
         helper.add_event(self.queue_evts[0].as_raw_fd(), RX_QUEUE_EVENT)?;
         helper.add_event(self.queue_evts[1].as_raw_fd(), TX_QUEUE_EVENT)?;
@@ -260,6 +308,9 @@ where
                 if let Err(e) = self.queue_evts[2].read() {
                     error!("Failed to get EVT queue event: {:?}", e);
                     return true;
+                } else if let Err(e) = self.process_evt() {
+                    error!("Failed to process EVT queue: {:?}", e);
+                    return true;
                 }
             }
             BACKEND_EVENT => {
@@ -299,6 +350,9 @@ pub struct Vsock<B: VsockBackend> {
     backend: Arc<RwLock<B>>,
     path: PathBuf,
     seccomp_action: SeccompAction,
+    // The connections the guest knows about were lost when it was
+    // snapshotted or migrated, and it must be told to reset them.
+    pending_reset: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -341,6 +395,7 @@ where
             backend: Arc::new(RwLock::new(backend)),
             path,
             seccomp_action,
+            pending_reset: false,
         })
     }
 
@@ -441,6 +496,7 @@ where
             pause_evt,
             interrupt_cb,
             backend: self.backend.clone(),
+            pending_reset: std::mem::take(&mut self.pending_reset),
         };
 
         let paused = self.common.paused.clone();
@@ -509,6 +565,9 @@ where
 
     fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         self.set_state(&snapshot.to_state(&self.id)?);
+        // The backend doesn't carry the connections over, so the guest has
+        // to drop them rather than waiting forever on them.
+        self.pending_reset = true;
         Ok(())
     }
 }
@@ -522,6 +581,8 @@ mod tests {
     use super::*;
     use crate::vsock::device::{BACKEND_EVENT, EVT_QUEUE_EVENT, RX_QUEUE_EVENT, TX_QUEUE_EVENT};
     use libc::EFD_NONBLOCK;
+    use vm_memory::GuestAddress;
+    use vm_virtio::queue::VIRTQ_DESC_F_WRITE;
 
     #[test]
     fn test_virtio_device() {
@@ -789,6 +850,43 @@ mod tests {
                 panic!("handle_event() should have failed");
             }
         }
+
+        // Test case:
+        // - a transport reset event is pending, e.g. after a restore; and
+        // - the driver makes an event queue buffer available.
+        {
+            const EVT_ADDR: u64 = 0x0060_0000;
+
+            let test_ctx = TestContext::new();
+            let mut ctx = test_ctx.create_epoll_handler_context();
+            ctx.handler.pending_reset = true;
+            test_ctx
+                .mem
+                .write_obj(0xffff_ffffu32, GuestAddress(EVT_ADDR))
+                .unwrap();
+            ctx.guest_evvq.dtable[0].set(EVT_ADDR, 4, VIRTQ_DESC_F_WRITE, 0);
+            ctx.guest_evvq.avail.ring[0].set(0);
+            ctx.guest_evvq.avail.idx.set(1);
+
+            ctx.handler.queue_evts[2].write(1).unwrap();
+            let events = epoll::Events::EPOLLIN;
+            let event = epoll::Event::new(events, EVT_QUEUE_EVENT as u64);
+            let mut epoll_helper =
+                EpollHelper::new(&ctx.handler.kill_evt, &ctx.handler.pause_evt).unwrap();
+            assert!(!ctx.handler.handle_event(&mut epoll_helper, &event));
+
+            // The event should have been delivered only once.
+            assert!(!ctx.handler.pending_reset);
+            assert_eq!(ctx.guest_evvq.used.idx.get(), 1);
+            assert_eq!(ctx.guest_evvq.used.ring[0].get().len, 4);
+            assert_eq!(
+                test_ctx
+                    .mem
+                    .read_obj::<u32>(GuestAddress(EVT_ADDR))
+                    .unwrap(),
+                uapi::VSOCK_EVENT_TRANSPORT_RESET
+            );
+        }
     }
 
     #[test]
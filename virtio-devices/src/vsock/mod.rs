@@ -12,10 +12,13 @@ mod csm;
 mod device;
 mod packet;
 mod unix;
+mod vhost_kern;
 
 pub use self::device::Vsock;
 pub use self::unix::VsockUnixBackend;
 pub use self::unix::VsockUnixError;
+pub use self::vhost_kern::Error as VhostVsockError;
+pub use self::vhost_kern::VhostVsock;
 
 pub use packet::VsockPacket;
 use std::os::unix::io::RawFd;
@@ -60,6 +63,12 @@ mod defs {
         pub const VSOCK_TYPE_STREAM: u16 = 1;
 
         pub const VSOCK_HOST_CID: u64 = 2;
+
+        /// Vsock event IDs.
+        /// Defined in `/include/uapi/linux/virtio_vsock.h`.
+        ///
+        /// The guest must reset all its connections, e.g. after the VM has been migrated.
+        pub const VSOCK_EVENT_TRANSPORT_RESET: u32 = 0;
     }
 }
 
@@ -326,6 +335,7 @@ mod tests {
                     pause_evt: EventFd::new(EFD_NONBLOCK).unwrap(),
                     interrupt_cb,
                     backend: Arc::new(RwLock::new(TestBackend::new())),
+                    pending_reset: false,
                 },
             }
         }
@@ -11,6 +11,7 @@
 mod muxer;
 mod muxer_killq;
 mod muxer_rxq;
+mod muxer_stream;
 
 pub use muxer::VsockMuxer as VsockUnixBackend;
 pub use Error as VsockUnixError;
@@ -48,9 +49,15 @@ pub enum Error {
     UnixConnect(std::io::Error),
     /// Error reading from host-side Unix socket.
     UnixRead(std::io::Error),
+    /// Error accepting a new connection from a host-side TCP socket.
+    TcpAccept(std::io::Error),
+    /// Error binding to a host-side TCP port.
+    TcpBind(std::io::Error),
+    /// Error connecting to a host-side TCP port.
+    TcpConnect(std::io::Error),
     /// Muxer connection limit reached.
     TooManyConnections,
 }
 
 type Result<T> = std::result::Result<T, Error>;
-type MuxerConnection = super::csm::VsockConnection<std::os::unix::net::UnixStream>;
+type MuxerConnection = super::csm::VsockConnection<muxer_stream::MuxerStream>;
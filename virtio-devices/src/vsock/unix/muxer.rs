@@ -31,9 +31,17 @@
 ///    To route all these events to their handlers, the muxer uses another `HashMap` object,
 ///    mapping `RawFd`s to `EpollListener`s.
 ///
+/// Guest ports can also be forwarded to and from host TCP ports, bound to the loopback
+/// interface. Guest connection requests for a forwarded port are routed to the matching host
+/// TCP port, instead of the `<host_sock_path>_<port>` Unix socket, and connections accepted
+/// on a listening host TCP port are forwarded to the matching guest port, without expecting
+/// any "connect" command.
+///
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, ErrorKind, Read};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 
@@ -46,6 +54,7 @@ use super::super::{
 use super::defs;
 use super::muxer_killq::MuxerKillQ;
 use super::muxer_rxq::MuxerRxQ;
+use super::muxer_stream::MuxerStream;
 use super::MuxerConnection;
 use super::{Error, Result};
 
@@ -83,6 +92,12 @@ enum EpollListener {
     /// A listener interested in reading host "connect <port>" commands from a freshly
     /// connected host socket.
     LocalStream(UnixStream),
+    /// A listener interested in new host-initiated connections on a forwarded TCP port, bound
+    /// for the guest port `peer_port`.
+    TcpSock {
+        listener: TcpListener,
+        peer_port: u32,
+    },
 }
 
 /// The vsock connection multiplexer.
@@ -115,6 +130,9 @@ pub struct VsockMuxer {
     local_port_set: HashSet<u32>,
     /// The last used host-side port.
     local_port_last: u32,
+    /// The host TCP ports to which guest connection requests are forwarded, keyed by
+    /// destination port.
+    tcp_connect: HashMap<u32, u16>,
 }
 
 impl VsockChannel for VsockMuxer {
@@ -330,7 +348,20 @@ impl VsockBackend for VsockMuxer {}
 impl VsockMuxer {
     /// Muxer constructor.
     ///
-    pub fn new(cid: u64, host_sock_path: String) -> Result<Self> {
+    /// Both `tcp_listen` and `tcp_connect` are lists of `(host TCP port, vsock port)` tuples.
+    /// Connections to the former host ports are forwarded to the guest, while guest
+    /// connection requests for the latter vsock ports are forwarded to the host.
+    ///
+    /// `restoring` is set when the muxer is created for a VM being restored, which is the only
+    /// case a stale host socket is replaced.
+    ///
+    pub fn new(
+        cid: u64,
+        host_sock_path: String,
+        tcp_listen: &[(u16, u32)],
+        tcp_connect: &[(u16, u32)],
+        restoring: bool,
+    ) -> Result<Self> {
         // Create the nested epoll FD. This FD will be added to the VMM `EpollContext`, at
         // device activation time.
         let epoll_fd = epoll::create(true).map_err(Error::EpollFdCreate)?;
@@ -339,7 +370,7 @@ impl VsockMuxer {
 
         // Open/bind/listen on the host Unix socket, so we can accept host-initiated
         // connections.
-        let host_sock = UnixListener::bind(&host_sock_path)
+        let host_sock = Self::bind_host_sock(&host_sock_path, restoring)
             .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
             .map_err(Error::UnixBind)?;
 
@@ -354,12 +385,56 @@ impl VsockMuxer {
             killq: MuxerKillQ::new(),
             local_port_last: (1u32 << 30) - 1,
             local_port_set: HashSet::with_capacity(defs::MAX_CONNECTIONS),
+            tcp_connect: tcp_connect
+                .iter()
+                .map(|(host_port, port)| (*port, *host_port))
+                .collect(),
         };
 
         muxer.add_listener(muxer.host_sock.as_raw_fd(), EpollListener::HostSock)?;
+
+        for (host_port, port) in tcp_listen {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, *host_port))
+                .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
+                .map_err(Error::TcpBind)?;
+            muxer.add_listener(
+                listener.as_raw_fd(),
+                EpollListener::TcpSock {
+                    listener,
+                    peer_port: *port,
+                },
+            )?;
+        }
+
         Ok(muxer)
     }
 
+    /// Bind the host Unix socket.
+    ///
+    /// A VM restored from a snapshot, or migrated onto the same host, reuses the host socket
+    /// path of the VMM it was saved from. If that path is a socket nothing is listening on
+    /// anymore, it is stale and gets replaced, so that host applications can connect to the
+    /// restored guest. Any other file is left untouched.
+    ///
+    fn bind_host_sock(path: &str, restoring: bool) -> std::io::Result<UnixListener> {
+        match UnixListener::bind(path) {
+            Err(e) if restoring && e.kind() == ErrorKind::AddrInUse => {
+                if !std::fs::symlink_metadata(path)?.file_type().is_socket() {
+                    return Err(e);
+                }
+                match UnixStream::connect(path) {
+                    Err(ce) if ce.kind() == ErrorKind::ConnectionRefused => {
+                        info!("vsock: replacing stale host socket {}", path);
+                        std::fs::remove_file(path)?;
+                        UnixListener::bind(path)
+                    }
+                    _ => Err(e),
+                }
+            }
+            r => r,
+        }
+    }
+
     /// Handle/dispatch an epoll event to its listener.
     ///
     fn handle_event(&mut self, fd: RawFd, evset: epoll::Events) {
@@ -428,7 +503,7 @@ impl VsockMuxer {
                                     peer_port,
                                 },
                                 MuxerConnection::new_local_init(
-                                    stream,
+                                    MuxerStream::Unix(stream),
                                     uapi::VSOCK_HOST_CID,
                                     self.cid,
                                     local_port,
@@ -442,6 +517,44 @@ impl VsockMuxer {
                 }
             }
 
+            // A new host-initiated connection is ready to be accepted on a forwarded TCP port.
+            // The destination port is given by the port map, so there's no "connect" command
+            // to wait for.
+            Some(EpollListener::TcpSock {
+                listener,
+                peer_port,
+            }) => {
+                let peer_port = *peer_port;
+                let accepted = listener.accept();
+                if self.conn_map.len() == defs::MAX_CONNECTIONS {
+                    // The accepted connection, if any, is discarded right away.
+                    warn!("vsock: connection limit reached; refusing new TCP connection");
+                    return;
+                }
+                accepted
+                    .and_then(|(stream, _)| stream.set_nonblocking(true).map(|_| stream))
+                    .map_err(Error::TcpAccept)
+                    .and_then(|stream| {
+                        let local_port = self.allocate_local_port();
+                        self.add_connection(
+                            ConnMapKey {
+                                local_port,
+                                peer_port,
+                            },
+                            MuxerConnection::new_local_init(
+                                MuxerStream::Tcp(stream),
+                                uapi::VSOCK_HOST_CID,
+                                self.cid,
+                                local_port,
+                                peer_port,
+                            ),
+                        )
+                    })
+                    .unwrap_or_else(|err| {
+                        warn!("vsock: unable to accept TCP connection: {:?}", err);
+                    });
+            }
+
             _ => {
                 info!("vsock: unexpected event: fd={:?}, evset={:?}", fd, evset);
             }
@@ -564,6 +677,7 @@ impl VsockMuxer {
             EpollListener::Connection { evset, .. } => evset,
             EpollListener::LocalStream(_) => epoll::Events::EPOLLIN,
             EpollListener::HostSock => epoll::Events::EPOLLIN,
+            EpollListener::TcpSock { .. } => epoll::Events::EPOLLIN,
         };
 
         epoll::ctl(
@@ -628,17 +742,28 @@ impl VsockMuxer {
 
     /// Handle a new connection request coming from our peer (the guest vsock driver).
     ///
-    /// This will attempt to connect to a host-side Unix socket, expected to be listening at
-    /// the file system path corresponding to the destination port. If successful, a new
-    /// connection object will be created and added to the connection pool. On failure, a new
-    /// RST packet will be scheduled for delivery to the guest.
+    /// This will attempt to connect to the host TCP port the destination port is forwarded to,
+    /// if any, or to a host-side Unix socket, expected to be listening at the file system path
+    /// corresponding to the destination port. If successful, a new connection object will be
+    /// created and added to the connection pool. On failure, a new RST packet will be
+    /// scheduled for delivery to the guest.
     ///
     fn handle_peer_request_pkt(&mut self, pkt: &VsockPacket) {
-        let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+        let stream = match self.tcp_connect.get(&pkt.dst_port()) {
+            Some(host_port) => TcpStream::connect((Ipv4Addr::LOCALHOST, *host_port))
+                .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
+                .map(MuxerStream::Tcp)
+                .map_err(Error::TcpConnect),
+            None => {
+                let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+                UnixStream::connect(port_path)
+                    .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
+                    .map(MuxerStream::Unix)
+                    .map_err(Error::UnixConnect)
+            }
+        };
 
-        UnixStream::connect(port_path)
-            .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
-            .map_err(Error::UnixConnect)
+        stream
             .and_then(|stream| {
                 self.add_connection(
                     ConnMapKey {
@@ -678,8 +803,12 @@ impl VsockMuxer {
             mut_fn(conn);
 
             // If this is a host-initiated connection that has just become established, we'll have
-            // to send an ack message to the host end.
-            if prev_state == ConnState::LocalInit && conn.state() == ConnState::Established {
+            // to send an ack message to the host end. Connections accepted on a forwarded TCP
+            // port don't go through the "connect" handshake, and aren't acked either.
+            if prev_state == ConnState::LocalInit
+                && conn.state() == ConnState::Established
+                && matches!(conn.stream(), MuxerStream::Unix(_))
+            {
                 let msg = format!("OK {}\n", key.local_port);
                 match conn.send_bytes_raw(msg.as_bytes()) {
                     Ok(written) if written == msg.len() => (),
@@ -812,6 +941,7 @@ impl VsockMuxer {
 #[cfg(test)]
 mod tests {
     use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
     use std::ops::Drop;
     use std::os::unix::net::{UnixListener, UnixStream};
     use std::path::{Path, PathBuf};
@@ -837,6 +967,14 @@ mod tests {
 
     impl MuxerTestContext {
         fn new(name: &str) -> Self {
+            Self::with_tcp_forwards(name, &[], &[])
+        }
+
+        fn with_tcp_forwards(
+            name: &str,
+            tcp_listen: &[(u16, u32)],
+            tcp_connect: &[(u16, u32)],
+        ) -> Self {
             let vsock_test_ctx = VsockTestContext::new();
             let mut handler_ctx = vsock_test_ctx.create_epoll_handler_context();
             let pkt = VsockPacket::from_rx_virtq_head(
@@ -847,7 +985,8 @@ mod tests {
             )
             .unwrap();
             let uds_path = format!("test_vsock_{}.sock", name);
-            let muxer = VsockMuxer::new(PEER_CID, uds_path).unwrap();
+            let muxer =
+                VsockMuxer::new(PEER_CID, uds_path, tcp_listen, tcp_connect, false).unwrap();
 
             Self {
                 _vsock_test_ctx: vsock_test_ctx,
@@ -1140,6 +1279,116 @@ mod tests {
         assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
     }
 
+    #[test]
+    fn test_tcp_peer_connection() {
+        const LOCAL_PORT: u32 = 1026;
+        const PEER_PORT: u32 = 1025;
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let host_port = listener.local_addr().unwrap().port();
+        let mut ctx = MuxerTestContext::with_tcp_forwards(
+            "tcp_peer_connection",
+            &[],
+            &[(host_port, LOCAL_PORT)],
+        );
+
+        // The connection request should be forwarded to the host TCP port, rather than to the
+        // Unix socket matching the destination port.
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        assert_eq!(ctx.muxer.conn_map.len(), 1);
+        let (mut stream, _) = listener.accept().unwrap();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        assert_eq!(ctx.pkt.src_port(), LOCAL_PORT);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+
+        // Test guest -> host data flow.
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(LOCAL_PORT, PEER_PORT, &data);
+        ctx.send();
+        let mut buf = vec![0; data.len()];
+        stream.read_exact(buf.as_mut_slice()).unwrap();
+        assert_eq!(buf.as_slice(), data);
+
+        // Test host -> guest data flow.
+        let data = [5u8, 6, 7, 8];
+        stream.write_all(&data).unwrap();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
+        assert_eq!(ctx.pkt.src_port(), LOCAL_PORT);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+
+        // Other ports are still routed to the Unix sockets.
+        ctx.init_pkt(LOCAL_PORT + 1, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+        assert_eq!(ctx.muxer.conn_map.len(), 1);
+    }
+
+    #[test]
+    fn test_tcp_local_connection() {
+        let peer_port = 1025;
+
+        // Find a free host port to listen on.
+        let host_port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut ctx = MuxerTestContext::with_tcp_forwards(
+            "tcp_local_connection",
+            &[(host_port, peer_port)],
+            &[],
+        );
+
+        // No "connect" command is needed, the connection request should be issued as soon as
+        // the host connection is accepted.
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, host_port)).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        ctx.notify_muxer();
+        assert_eq!(ctx.muxer.conn_map.len(), 1);
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_REQUEST);
+        assert_eq!(ctx.pkt.dst_port(), peer_port);
+        let local_port = ctx.pkt.src_port();
+        assert!(ctx.muxer.local_port_set.contains(&local_port));
+
+        ctx.init_pkt(local_port, peer_port, uapi::VSOCK_OP_RESPONSE);
+        ctx.send();
+
+        // The connection shouldn't be acked to the host end.
+        let mut buf = vec![0u8; 32];
+        assert_eq!(
+            stream.read(&mut buf[..]).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        // Test guest -> host data flow.
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(local_port, peer_port, &data);
+        ctx.send();
+        let mut buf = vec![0u8; data.len()];
+        stream.read_exact(buf.as_mut_slice()).unwrap();
+        assert_eq!(buf.as_slice(), &data);
+
+        // Test host -> guest data flow.
+        let data = [5, 6, 7, 8];
+        stream.write_all(&data).unwrap();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.pkt.src_port(), local_port);
+        assert_eq!(ctx.pkt.dst_port(), peer_port);
+        assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
+    }
+
     #[test]
     fn test_local_close() {
         let peer_port = 1025;
@@ -1413,4 +1662,35 @@ mod tests {
         // not be any pending RX in the muxer.
         assert!(!ctx.muxer.has_pending_rx());
     }
+
+    #[test]
+    fn test_stale_host_sock() {
+        let path = "test_vsock_stale_host_sock.sock";
+
+        // A socket file left behind by a VMM that is gone is only replaced
+        // when restoring.
+        drop(UnixListener::bind(path).unwrap());
+        assert!(VsockMuxer::new(PEER_CID, path.to_string(), &[], &[], false).is_err());
+        let muxer = VsockMuxer::new(PEER_CID, path.to_string(), &[], &[], true).unwrap();
+        UnixStream::connect(path).unwrap();
+
+        // A socket that is still being listened on must not be taken over.
+        assert!(VsockMuxer::new(PEER_CID, path.to_string(), &[], &[], true).is_err());
+
+        drop(muxer);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_host_sock_not_a_socket() {
+        let path = "test_vsock_host_sock_not_a_socket.sock";
+
+        // Connecting to a regular file is refused as well, but it must never
+        // be removed.
+        std::fs::write(path, b"data").unwrap();
+        assert!(VsockMuxer::new(PEER_CID, path.to_string(), &[], &[], true).is_err());
+        assert_eq!(std::fs::read(path).unwrap(), b"data");
+
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,53 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+/// `MuxerStream` is the host-side stream of a `MuxerConnection`. Most connections go through
+/// the Unix sockets derived from the muxer host socket path, but guest ports can also be
+/// forwarded to and from host TCP ports, in which case the connection is carried over a TCP
+/// stream.
+///
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+pub enum MuxerStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Read for MuxerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            MuxerStream::Unix(stream) => stream.read(buf),
+            MuxerStream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for MuxerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            MuxerStream::Unix(stream) => stream.write(buf),
+            MuxerStream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            MuxerStream::Unix(stream) => stream.flush(),
+            MuxerStream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for MuxerStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            MuxerStream::Unix(stream) => stream.as_raw_fd(),
+            MuxerStream::Tcp(stream) => stream.as_raw_fd(),
+        }
+    }
+}
@@ -0,0 +1,419 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Virtio vsock device whose RX and TX virtqueues are processed by the
+//! kernel vhost-vsock driver, letting the guest reach native host AF_VSOCK
+//! sockets. Only the event queue is left to the VMM.
+
+use crate::vhost_user::vu_common_ctrl::vring_config_data;
+use crate::vhost_user::Error as VhostUserError;
+use crate::{
+    ActivateError, ActivateResult, Queue, VirtioCommon, VirtioDevice, VirtioDeviceType,
+    VirtioInterrupt, VirtioInterruptType, VIRTIO_F_VERSION_1,
+};
+use anyhow::anyhow;
+use byteorder::{ByteOrder, LittleEndian};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::result;
+use std::sync::{Arc, Barrier};
+use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use vm_memory::{
+    Address, GuestAddressSpace, GuestMemory, GuestMemoryAtomic, GuestMemoryMmap, GuestMemoryRegion,
+    GuestRegionMmap,
+};
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref};
+
+const VHOST_VSOCK_PATH: &str = "/dev/vhost-vsock";
+
+const QUEUE_SIZE: u16 = 256;
+const NUM_QUEUES: usize = 3;
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; NUM_QUEUES];
+// The event queue isn't handled by the driver.
+const NUM_VHOST_QUEUES: usize = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to open /dev/vhost-vsock.
+    Open(io::Error),
+    /// Failed to set the current process as the owner.
+    SetOwner(io::Error),
+    /// Failed to get the features supported by the driver.
+    GetFeatures(io::Error),
+    /// Failed to set the features used by the driver.
+    SetFeatures(io::Error),
+    /// Failed to set the guest CID, which may already be in use.
+    SetGuestCid(io::Error),
+    /// Failed to set the memory table.
+    SetMemTable(io::Error),
+    /// Failed to set the size of a virtqueue.
+    SetVringNum(io::Error),
+    /// Failed to set the addresses of a virtqueue.
+    SetVringAddr(io::Error),
+    /// Invalid virtqueue address.
+    VringAddress(VhostUserError),
+    /// Failed to set the next available index of a virtqueue.
+    SetVringBase(io::Error),
+    /// Failed to set the eventfd notified by the guest.
+    SetVringKick(io::Error),
+    /// Failed to set the eventfd used to interrupt the guest.
+    SetVringCall(io::Error),
+    /// No irqfd to let the driver interrupt the guest.
+    MissingIrqFd,
+    /// Failed to start or stop the driver.
+    SetRunning(io::Error),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+fn check_ioctl(ret: i32, err: fn(io::Error) -> Error) -> Result<()> {
+    if ret < 0 {
+        Err(err(io::Error::last_os_error()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Handle on a kernel vhost-vsock instance. The addresses given to the
+/// driver are host virtual addresses, as the kernel accesses the guest
+/// memory through the VMM mappings.
+struct VhostVsockHandle {
+    file: File,
+}
+
+impl VhostVsockHandle {
+    fn new() -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC | libc::O_NONBLOCK)
+            .open(VHOST_VSOCK_PATH)
+            .map_err(Error::Open)?;
+
+        let handle = VhostVsockHandle { file };
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret = unsafe { ioctl(&handle.file, net_gen::VHOST_SET_OWNER()) };
+        check_ioctl(ret, Error::SetOwner)?;
+
+        Ok(handle)
+    }
+
+    fn get_features(&self) -> Result<u64> {
+        let mut features = 0u64;
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret =
+            unsafe { ioctl_with_mut_ref(&self.file, net_gen::VHOST_GET_FEATURES(), &mut features) };
+        check_ioctl(ret, Error::GetFeatures)?;
+
+        Ok(features)
+    }
+
+    fn set_features(&self, features: u64) -> Result<()> {
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_SET_FEATURES(), &features) };
+        check_ioctl(ret, Error::SetFeatures)
+    }
+
+    fn set_guest_cid(&self, cid: u64) -> Result<()> {
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_VSOCK_SET_GUEST_CID(), &cid) };
+        check_ioctl(ret, Error::SetGuestCid)
+    }
+
+    fn set_running(&self, running: bool) -> Result<()> {
+        let running = running as libc::c_int;
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret =
+            unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_VSOCK_SET_RUNNING(), &running) };
+        check_ioctl(ret, Error::SetRunning)
+    }
+
+    /// Describe the whole guest memory to the driver, which has to be done
+    /// again every time a region is added.
+    fn set_mem_table(&self, mem: &GuestMemoryMmap) -> Result<()> {
+        let regions: Vec<net_gen::vhost_memory_region> = mem
+            .iter()
+            .map(|region| net_gen::vhost_memory_region {
+                guest_phys_addr: region.start_addr().raw_value(),
+                memory_size: region.len() as u64,
+                userspace_addr: region.as_ptr() as u64,
+                flags_padding: 0,
+            })
+            .collect();
+
+        // Build a struct vhost_memory followed by the list of regions, the
+        // u64 buffer providing the required alignment.
+        let size = size_of::<net_gen::vhost_memory>()
+            + regions.len() * size_of::<net_gen::vhost_memory_region>();
+        let mut buf = vec![0u64; (size + size_of::<u64>() - 1) / size_of::<u64>()];
+        let memory = buf.as_mut_ptr() as *mut net_gen::vhost_memory;
+        // Safe because the buffer is large enough to hold the header and the
+        // regions.
+        unsafe {
+            (*memory).nregions = regions.len() as u32;
+            (*memory)
+                .regions
+                .as_mut_slice(regions.len())
+                .copy_from_slice(&regions);
+        }
+
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret = unsafe { ioctl_with_ptr(&self.file, net_gen::VHOST_SET_MEM_TABLE(), memory) };
+        check_ioctl(ret, Error::SetMemTable)
+    }
+
+    fn set_vring(
+        &self,
+        index: usize,
+        mem: &GuestMemoryMmap,
+        queue: &Queue,
+        kick_evt: &EventFd,
+        call_evt: &EventFd,
+    ) -> Result<()> {
+        let state = net_gen::vhost_vring_state {
+            index: index as u32,
+            num: u32::from(queue.actual_size()),
+        };
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_SET_VRING_NUM(), &state) };
+        check_ioctl(ret, Error::SetVringNum)?;
+
+        let config_data = vring_config_data(mem, queue).map_err(Error::VringAddress)?;
+        let addr = net_gen::vhost_vring_addr {
+            index: index as u32,
+            flags: 0,
+            desc_user_addr: config_data.desc_table_addr,
+            used_user_addr: config_data.used_ring_addr,
+            avail_user_addr: config_data.avail_ring_addr,
+            log_guest_addr: 0,
+        };
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_SET_VRING_ADDR(), &addr) };
+        check_ioctl(ret, Error::SetVringAddr)?;
+
+        let state = net_gen::vhost_vring_state {
+            index: index as u32,
+            num: 0,
+        };
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, net_gen::VHOST_SET_VRING_BASE(), &state) };
+        check_ioctl(ret, Error::SetVringBase)?;
+
+        self.set_vring_file(
+            net_gen::VHOST_SET_VRING_CALL(),
+            index,
+            call_evt.as_raw_fd(),
+            Error::SetVringCall,
+        )?;
+        self.set_vring_file(
+            net_gen::VHOST_SET_VRING_KICK(),
+            index,
+            kick_evt.as_raw_fd(),
+            Error::SetVringKick,
+        )
+    }
+
+    fn set_vring_file(
+        &self,
+        req: libc::c_ulong,
+        index: usize,
+        fd: RawFd,
+        err: fn(io::Error) -> Error,
+    ) -> Result<()> {
+        let file = net_gen::vhost_vring_file {
+            index: index as u32,
+            fd,
+        };
+        // ioctl is safe. Called with a valid vhost-vsock fd, and we check the return.
+        let ret = unsafe { ioctl_with_ref(&self.file, req, &file) };
+        check_ioctl(ret, err)
+    }
+}
+
+/// Virtio vsock device backed by the kernel vhost-vsock driver.
+pub struct VhostVsock {
+    common: VirtioCommon,
+    id: String,
+    cid: u64,
+    handle: VhostVsockHandle,
+    guest_memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+    running: bool,
+}
+
+impl VhostVsock {
+    /// Create a new virtio-vsock device, registering the given CID with the
+    /// host kernel.
+    pub fn new(id: String, cid: u64) -> Result<VhostVsock> {
+        let handle = VhostVsockHandle::new()?;
+        handle.set_guest_cid(cid)?;
+
+        // Only offer the ring features known to the driver.
+        let backend_features = handle.get_features()?;
+        let avail_features =
+            (1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_RING_F_EVENT_IDX) & backend_features;
+
+        Ok(VhostVsock {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Vsock as u32,
+                avail_features,
+                // There is no thread to acknowledge a pause.
+                paused_sync: Some(Arc::new(Barrier::new(1))),
+                queue_sizes: QUEUE_SIZES.to_vec(),
+                min_queues: NUM_QUEUES as u16,
+                ..Default::default()
+            },
+            id,
+            cid,
+            handle,
+            guest_memory: None,
+            running: false,
+        })
+    }
+
+    fn setup_vhost(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        interrupt_cb: &Arc<dyn VirtioInterrupt>,
+        queues: &[Queue],
+        queue_evts: &[EventFd],
+    ) -> Result<()> {
+        let backend_features = self.handle.get_features()?;
+        self.handle
+            .set_features(self.common.acked_features & backend_features)?;
+        self.handle.set_mem_table(mem)?;
+
+        for (index, (queue, queue_evt)) in queues
+            .iter()
+            .zip(queue_evts.iter())
+            .take(NUM_VHOST_QUEUES)
+            .enumerate()
+        {
+            let call_evt = interrupt_cb
+                .notifier(&VirtioInterruptType::Queue, Some(queue))
+                .ok_or(Error::MissingIrqFd)?;
+            self.handle
+                .set_vring(index, mem, queue, queue_evt, &call_evt)?;
+        }
+
+        self.set_running(true)
+    }
+
+    fn set_running(&mut self, running: bool) -> Result<()> {
+        if self.guest_memory.is_some() && self.running != running {
+            self.handle.set_running(running)?;
+            self.running = running;
+        }
+
+        Ok(())
+    }
+}
+
+impl VirtioDevice for VhostVsock {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        match offset {
+            0 if data.len() == 8 => LittleEndian::write_u64(data, self.cid),
+            0 if data.len() == 4 => LittleEndian::write_u32(data, (self.cid & 0xffff_ffff) as u32),
+            4 if data.len() == 4 => {
+                LittleEndian::write_u32(data, ((self.cid >> 32) & 0xffff_ffff) as u32)
+            }
+            _ => warn!(
+                "vsock: virtio-vsock received invalid read request of {} bytes at offset {}",
+                data.len(),
+                offset
+            ),
+        }
+    }
+
+    fn activate(
+        &mut self,
+        mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        queues: Vec<Queue>,
+        queue_evts: Vec<EventFd>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &queue_evts, &interrupt_cb)?;
+
+        self.guest_memory = Some(mem.clone());
+        self.setup_vhost(&mem.memory(), &interrupt_cb, &queues, &queue_evts)
+            .map_err(ActivateError::VhostVsockSetup)?;
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        if let Err(e) = self.set_running(false) {
+            error!("Failed to stop vhost-vsock: {:?}", e);
+        }
+        self.guest_memory = None;
+
+        let result = self.common.reset();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn add_memory_region(
+        &mut self,
+        _region: &Arc<GuestRegionMmap>,
+    ) -> result::Result<(), crate::Error> {
+        if let Some(guest_memory) = &self.guest_memory {
+            self.handle
+                .set_mem_table(&guest_memory.memory())
+                .map_err(crate::Error::VhostVsockUpdateMemory)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pausable for VhostVsock {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.set_running(false)
+            .map_err(|e| MigratableError::Pause(anyhow!("Error stopping vhost-vsock: {:?}", e)))?;
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()?;
+        self.set_running(true)
+            .map_err(|e| MigratableError::Resume(anyhow!("Error starting vhost-vsock: {:?}", e)))
+    }
+}
+
+impl Snapshottable for VhostVsock {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> result::Result<Snapshot, MigratableError> {
+        // The virtqueues state is owned by the kernel.
+        Err(MigratableError::Snapshot(anyhow!(
+            "Snapshot not supported with vhost-vsock: {}",
+            self.id
+        )))
+    }
+}
+impl Transportable for VhostVsock {}
+impl Migratable for VhostVsock {}
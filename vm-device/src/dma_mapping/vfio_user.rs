@@ -0,0 +1,94 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use crate::dma_mapping::ExternalDmaMapping;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use vfio_user::Client;
+use vm_memory::{Address, GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryRegion};
+
+/// This structure implements the ExternalDmaMapping trait. It is meant to
+/// be used when the caller tries to provide a way to update the mappings
+/// associated with a specific vfio-user device. Since the device lives in a
+/// separate process, the guest memory is shared through its file descriptor.
+pub struct VfioUserDmaMapping<M: GuestAddressSpace> {
+    client: Arc<Mutex<Client>>,
+    memory: Arc<M>,
+}
+
+impl<M: GuestAddressSpace> VfioUserDmaMapping<M> {
+    /// Create a DmaMapping object.
+    ///
+    /// # Parameters
+    /// * `client`: vfio-user client connected to the device.
+    /// * `memory`: guest memory to share.
+    pub fn new(client: Arc<Mutex<Client>>, memory: Arc<M>) -> Self {
+        VfioUserDmaMapping { client, memory }
+    }
+}
+
+impl<M: GuestAddressSpace + Sync + Send> ExternalDmaMapping for VfioUserDmaMapping<M> {
+    fn map(&self, iova: u64, gpa: u64, size: u64) -> std::result::Result<(), io::Error> {
+        let mem = self.memory.memory();
+        let guest_addr = GuestAddress(gpa);
+        let region = mem.find_region(guest_addr);
+
+        if let Some(region) = region {
+            if let Some(file_offset) = region.file_offset() {
+                let offset = region.to_region_addr(guest_addr).unwrap().raw_value();
+
+                self.client
+                    .lock()
+                    .unwrap()
+                    .dma_map(
+                        file_offset.start() + offset,
+                        iova,
+                        size,
+                        file_offset.file().as_raw_fd(),
+                    )
+                    .map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::Other,
+                            format!(
+                                "failed to map memory for vfio-user device, \
+                                 iova 0x{:x}, gpa 0x{:x}, size 0x{:x}: {:?}",
+                                iova, gpa, size, e
+                            ),
+                        )
+                    })
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "region at guest address 0x{:x} is not backed by a file",
+                        gpa
+                    ),
+                ))
+            }
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("no memory region found for guest address 0x{:x}", gpa),
+            ))
+        }
+    }
+
+    fn unmap(&self, iova: u64, size: u64) -> std::result::Result<(), io::Error> {
+        self.client
+            .lock()
+            .unwrap()
+            .dma_unmap(iova, size)
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "failed to unmap memory for vfio-user device, \
+                         iova 0x{:x}, size 0x{:x}: {:?}",
+                        iova, size, e
+                    ),
+                )
+            })
+    }
+}
@@ -3,7 +3,9 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use crate::api::http_endpoint::{VmActionHandler, VmCreate, VmInfo, VmmPing, VmmShutdown};
+use crate::api::http_endpoint::{
+    VmActionHandler, VmCreate, VmInfo, VmmCloseFds, VmmPing, VmmSendFds, VmmShutdown,
+};
 use crate::api::{ApiError, ApiRequest, VmAction};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::{Error, Result};
@@ -11,6 +13,8 @@ use micro_http::{Body, HttpServer, MediaType, Method, Request, Response, StatusC
 use seccomp::{SeccompAction, SeccompFilter};
 use serde_json::Error as SerdeError;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io;
 use std::os::unix::io::{IntoRawFd, RawFd};
 use std::os::unix::net::UnixListener;
 use std::path::PathBuf;
@@ -64,6 +68,9 @@ pub enum HttpError {
     /// Could not restore a VM
     VmRestore(ApiError),
 
+    /// Could not write a VM coredump
+    VmCoredump(ApiError),
+
     /// Could not act on a VM
     VmAction(ApiError),
 
@@ -73,9 +80,21 @@ pub enum HttpError {
     /// Could not resize a memory zone
     VmResizeZone(ApiError),
 
+    /// Could not capture the traffic of a network device
+    VmNetCapture(ApiError),
+
+    /// Could not change the link state of a network device
+    VmNetLink(ApiError),
+
+    /// Could not change the host CPU affinity of vCPUs
+    VmCpuAffinity(ApiError),
+
     /// Could not add a device to a VM
     VmAddDevice(ApiError),
 
+    /// Could not add a user device to a VM
+    VmAddUserDevice(ApiError),
+
     /// Could not remove a device from a VM
     VmRemoveDevice(ApiError),
 
@@ -85,12 +104,21 @@ pub enum HttpError {
     /// Could not handle VMM ping
     VmmPing(ApiError),
 
+    /// Could not keep the passed file descriptors
+    VmmSendFds(ApiError),
+
+    /// Could not close the passed file descriptors
+    VmmCloseFds(ApiError),
+
     /// Could not add a disk to a VM
     VmAddDisk(ApiError),
 
     /// Could not add a fs to a VM
     VmAddFs(ApiError),
 
+    /// Could not add a generic vhost-user device to a VM
+    VmAddGenericVhostUser(ApiError),
+
     /// Could not add a pmem device to a VM
     VmAddPmem(ApiError),
 
@@ -103,6 +131,9 @@ pub enum HttpError {
     /// Could not get counters from VM
     VmCounters(ApiError),
 
+    /// Could not get vCPU exit counters from VM
+    VmVcpuCounters(ApiError),
+
     /// Error setting up migration received
     VmReceiveMigration(ApiError),
 
@@ -141,8 +172,25 @@ pub trait EndpointHandler: Sync + Send {
         api_notifier: EventFd,
         api_sender: Sender<ApiRequest>,
     ) -> Response {
+        // The file descriptors passed along with the request are duplicated,
+        // the ones owned by the request being closed along with it.
+        let files = match req
+            .files
+            .iter()
+            .map(File::try_clone)
+            .collect::<io::Result<Vec<File>>>()
+        {
+            Ok(files) => files,
+            Err(_) => {
+                return error_response(
+                    HttpError::InternalServerError,
+                    StatusCode::InternalServerError,
+                )
+            }
+        };
+
         let res = match req.method() {
-            Method::Put => self.put_handler(api_notifier, api_sender, &req.body),
+            Method::Put => self.put_handler(api_notifier, api_sender, &req.body, files),
             Method::Get => self.get_handler(api_notifier, api_sender, &req.body),
             _ => return Response::new(Version::Http11, StatusCode::BadRequest),
         };
@@ -170,6 +218,7 @@ pub trait EndpointHandler: Sync + Send {
         _api_notifier: EventFd,
         _api_sender: Sender<ApiRequest>,
         _body: &Option<Body>,
+        _files: Vec<File>,
     ) -> std::result::Result<Option<Body>, HttpError> {
         Err(HttpError::BadRequest)
     }
@@ -206,14 +255,20 @@ lazy_static! {
         r.routes.insert(endpoint!("/vm.add-device"), Box::new(VmActionHandler::new(VmAction::AddDevice(Arc::default()))));
         r.routes.insert(endpoint!("/vm.add-disk"), Box::new(VmActionHandler::new(VmAction::AddDisk(Arc::default()))));
         r.routes.insert(endpoint!("/vm.add-fs"), Box::new(VmActionHandler::new(VmAction::AddFs(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.add-generic-vhost-user"), Box::new(VmActionHandler::new(VmAction::AddGenericVhostUser(Arc::default()))));
         r.routes.insert(endpoint!("/vm.add-net"), Box::new(VmActionHandler::new(VmAction::AddNet(Arc::default()))));
         r.routes.insert(endpoint!("/vm.add-pmem"), Box::new(VmActionHandler::new(VmAction::AddPmem(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.add-user-device"), Box::new(VmActionHandler::new(VmAction::AddUserDevice(Arc::default()))));
         r.routes.insert(endpoint!("/vm.add-vsock"), Box::new(VmActionHandler::new(VmAction::AddVsock(Arc::default()))));
         r.routes.insert(endpoint!("/vm.boot"), Box::new(VmActionHandler::new(VmAction::Boot)));
+        r.routes.insert(endpoint!("/vm.coredump"), Box::new(VmActionHandler::new(VmAction::Coredump(Arc::default()))));
         r.routes.insert(endpoint!("/vm.counters"), Box::new(VmActionHandler::new(VmAction::Counters)));
+        r.routes.insert(endpoint!("/vm.cpu-affinity"), Box::new(VmActionHandler::new(VmAction::CpuAffinity(Arc::default()))));
         r.routes.insert(endpoint!("/vm.create"), Box::new(VmCreate {}));
         r.routes.insert(endpoint!("/vm.delete"), Box::new(VmActionHandler::new(VmAction::Delete)));
         r.routes.insert(endpoint!("/vm.info"), Box::new(VmInfo {}));
+        r.routes.insert(endpoint!("/vm.net-capture"), Box::new(VmActionHandler::new(VmAction::NetCapture(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.net-link"), Box::new(VmActionHandler::new(VmAction::NetLink(Arc::default()))));
         r.routes.insert(endpoint!("/vm.pause"), Box::new(VmActionHandler::new(VmAction::Pause)));
         r.routes.insert(endpoint!("/vm.power-button"), Box::new(VmActionHandler::new(VmAction::PowerButton)));
         r.routes.insert(endpoint!("/vm.reboot"), Box::new(VmActionHandler::new(VmAction::Reboot)));
@@ -226,7 +281,10 @@ lazy_static! {
         r.routes.insert(endpoint!("/vm.send-migration"), Box::new(VmActionHandler::new(VmAction::SendMigration(Arc::default()))));
         r.routes.insert(endpoint!("/vm.shutdown"), Box::new(VmActionHandler::new(VmAction::Shutdown)));
         r.routes.insert(endpoint!("/vm.snapshot"), Box::new(VmActionHandler::new(VmAction::Snapshot(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.vcpu-counters"), Box::new(VmActionHandler::new(VmAction::VcpuCounters)));
+        r.routes.insert(endpoint!("/vmm.close-fds"), Box::new(VmmCloseFds {}));
         r.routes.insert(endpoint!("/vmm.ping"), Box::new(VmmPing {}));
+        r.routes.insert(endpoint!("/vmm.send-fds"), Box::new(VmmSendFds {}));
         r.routes.insert(endpoint!("/vmm.shutdown"), Box::new(VmmShutdown {}));
 
         r
@@ -5,13 +5,17 @@
 
 use crate::api::http::{error_response, EndpointHandler, HttpError};
 use crate::api::{
-    vm_add_device, vm_add_disk, vm_add_fs, vm_add_net, vm_add_pmem, vm_add_vsock, vm_boot,
-    vm_counters, vm_create, vm_delete, vm_info, vm_pause, vm_power_button, vm_reboot,
-    vm_receive_migration, vm_remove_device, vm_resize, vm_resize_zone, vm_restore, vm_resume,
-    vm_send_migration, vm_shutdown, vm_snapshot, vmm_ping, vmm_shutdown, ApiRequest, VmAction,
-    VmConfig,
+    vm_add_device, vm_add_disk, vm_add_fs, vm_add_generic_vhost_user, vm_add_net, vm_add_pmem,
+    vm_add_user_device, vm_add_vsock, vm_boot, vm_coredump, vm_counters, vm_cpu_affinity,
+    vm_create, vm_delete, vm_info, vm_net_capture, vm_net_link, vm_pause, vm_power_button,
+    vm_reboot, vm_receive_migration, vm_remove_device, vm_resize, vm_resize_zone, vm_restore,
+    vm_resume, vm_send_migration, vm_shutdown, vm_snapshot, vm_vcpu_counters, vmm_close_fds,
+    vmm_ping, vmm_send_fds, vmm_shutdown, ApiRequest, VmAction, VmConfig,
 };
+use crate::config::NetConfig;
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
+use std::fs::File;
+use std::os::unix::io::IntoRawFd;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use vmm_sys_util::eventfd::EventFd;
@@ -73,6 +77,7 @@ impl EndpointHandler for VmActionHandler {
         api_notifier: EventFd,
         api_sender: Sender<ApiRequest>,
         body: &Option<Body>,
+        files: Vec<File>,
     ) -> std::result::Result<Option<Body>, HttpError> {
         use VmAction::*;
         if let Some(body) = body {
@@ -84,6 +89,13 @@ impl EndpointHandler for VmActionHandler {
                 )
                 .map_err(HttpError::VmAddDevice),
 
+                AddUserDevice(_) => vm_add_user_device(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                )
+                .map_err(HttpError::VmAddUserDevice),
+
                 AddDisk(_) => vm_add_disk(
                     api_notifier,
                     api_sender,
@@ -98,19 +110,32 @@ impl EndpointHandler for VmActionHandler {
                 )
                 .map_err(HttpError::VmAddFs),
 
-                AddPmem(_) => vm_add_pmem(
+                AddGenericVhostUser(_) => vm_add_generic_vhost_user(
                     api_notifier,
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 )
-                .map_err(HttpError::VmAddPmem),
+                .map_err(HttpError::VmAddGenericVhostUser),
 
-                AddNet(_) => vm_add_net(
+                AddPmem(_) => vm_add_pmem(
                     api_notifier,
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 )
-                .map_err(HttpError::VmAddNet),
+                .map_err(HttpError::VmAddPmem),
+
+                AddNet(_) => {
+                    let mut net_cfg: NetConfig = serde_json::from_slice(body.raw())?;
+                    // The TAP file descriptors passed along with the request
+                    // replace the ones from the configuration, and are owned
+                    // by the network device from now on.
+                    if !files.is_empty() {
+                        let fds = files.into_iter().map(|f| f.into_raw_fd()).collect();
+                        net_cfg.fds = Some(fds);
+                    }
+                    vm_add_net(api_notifier, api_sender, Arc::new(net_cfg))
+                        .map_err(HttpError::VmAddNet)
+                }
 
                 AddVsock(_) => vm_add_vsock(
                     api_notifier,
@@ -140,6 +165,27 @@ impl EndpointHandler for VmActionHandler {
                 )
                 .map_err(HttpError::VmResizeZone),
 
+                NetCapture(_) => vm_net_capture(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                )
+                .map_err(HttpError::VmNetCapture),
+
+                NetLink(_) => vm_net_link(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                )
+                .map_err(HttpError::VmNetLink),
+
+                CpuAffinity(_) => vm_cpu_affinity(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                )
+                .map_err(HttpError::VmCpuAffinity),
+
                 Restore(_) => vm_restore(
                     api_notifier,
                     api_sender,
@@ -154,6 +200,13 @@ impl EndpointHandler for VmActionHandler {
                 )
                 .map_err(HttpError::VmSnapshot),
 
+                Coredump(_) => vm_coredump(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                )
+                .map_err(HttpError::VmCoredump),
+
                 ReceiveMigration(_) => vm_receive_migration(
                     api_notifier,
                     api_sender,
@@ -195,6 +248,9 @@ impl EndpointHandler for VmActionHandler {
         use VmAction::*;
         match self.action {
             Counters => vm_counters(api_notifier, api_sender).map_err(HttpError::VmCounters),
+            VcpuCounters => {
+                vm_vcpu_counters(api_notifier, api_sender).map_err(HttpError::VmVcpuCounters)
+            }
             _ => Err(HttpError::BadRequest),
         }
     }
@@ -273,3 +329,43 @@ impl EndpointHandler for VmmShutdown {
         }
     }
 }
+
+// /api/v1/vmm.send-fds handler
+pub struct VmmSendFds {}
+
+impl EndpointHandler for VmmSendFds {
+    fn put_handler(
+        &self,
+        api_notifier: EventFd,
+        api_sender: Sender<ApiRequest>,
+        _body: &Option<Body>,
+        files: Vec<File>,
+    ) -> std::result::Result<Option<Body>, HttpError> {
+        let fds = vmm_send_fds(api_notifier, api_sender, files).map_err(HttpError::VmmSendFds)?;
+
+        Ok(Some(Body::new(serde_json::to_vec(&fds)?)))
+    }
+}
+
+// /api/v1/vmm.close-fds handler
+pub struct VmmCloseFds {}
+
+impl EndpointHandler for VmmCloseFds {
+    fn put_handler(
+        &self,
+        api_notifier: EventFd,
+        api_sender: Sender<ApiRequest>,
+        body: &Option<Body>,
+        _files: Vec<File>,
+    ) -> std::result::Result<Option<Body>, HttpError> {
+        let body = body.as_ref().ok_or(HttpError::BadRequest)?;
+        let fds = vmm_close_fds(
+            api_notifier,
+            api_sender,
+            serde_json::from_slice(body.raw())?,
+        )
+        .map_err(HttpError::VmmCloseFds)?;
+
+        Ok(Some(Body::new(serde_json::to_vec(&fds)?)))
+    }
+}
@@ -35,13 +35,17 @@ pub mod http;
 pub mod http_endpoint;
 
 use crate::config::{
-    DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig, VmConfig, VsockConfig,
+    CpuAffinity, DeviceConfig, DiskConfig, FsConfig, GenericVhostUserConfig, NetConfig, PmemConfig,
+    RestoreConfig, UserDeviceConfig, VmConfig, VsockConfig,
 };
 use crate::device_tree::DeviceTree;
 use crate::vm::{Error as VmError, VmState};
 use micro_http::Body;
+use std::fs::File;
 use std::io;
-use std::sync::mpsc::{channel, RecvError, SendError, Sender};
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
 use vm_migration::MigratableError;
 use vmm_sys_util::eventfd::EventFd;
@@ -103,6 +107,9 @@ pub enum ApiError {
     /// The VM could not restored.
     VmRestore(VmError),
 
+    /// The VM coredump could not be written.
+    VmCoredump(VmError),
+
     /// The VMM could not shutdown.
     VmmShutdown(VmError),
 
@@ -115,6 +122,9 @@ pub enum ApiError {
     /// The device could not be added to the VM.
     VmAddDevice(VmError),
 
+    /// The user device could not be added to the VM.
+    VmAddUserDevice(VmError),
+
     /// The device could not be removed from the VM.
     VmRemoveDevice(VmError),
 
@@ -130,6 +140,9 @@ pub enum ApiError {
     /// The fs could not be added to the VM.
     VmAddFs(VmError),
 
+    /// The generic vhost-user device could not be added to the VM.
+    VmAddGenericVhostUser(VmError),
+
     /// The pmem device could not be added to the VM.
     VmAddPmem(VmError),
 
@@ -147,6 +160,18 @@ pub enum ApiError {
 
     /// Error triggering power button
     VmPowerButton(VmError),
+
+    /// The network traffic capture could not be started or stopped.
+    VmNetCapture(VmError),
+
+    /// The network link state could not be changed.
+    VmNetLink(VmError),
+
+    /// The vCPUs host CPU affinity could not be changed.
+    VmCpuAffinity(VmError),
+
+    /// The passed file descriptors could not be kept open.
+    VmmSendFds(io::Error),
 }
 pub type ApiResult<T> = std::result::Result<T, ApiError>;
 
@@ -165,7 +190,7 @@ pub struct VmmPingResponse {
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmResizeData {
-    pub desired_vcpus: Option<u8>,
+    pub desired_vcpus: Option<u32>,
     pub desired_ram: Option<u64>,
     pub desired_balloon: Option<u64>,
 }
@@ -176,6 +201,30 @@ pub struct VmResizeZoneData {
     pub desired_ram: u64,
 }
 
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmNetCaptureData {
+    /// Identifier of the network device
+    pub id: String,
+    /// Capture file, the capture being stopped if none is provided
+    pub path: Option<PathBuf>,
+    /// Maximum size of the capture file in bytes
+    pub max_size: Option<u64>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmNetLinkData {
+    /// Identifier of the network device
+    pub id: String,
+    /// Whether the link is reported as up or down to the guest
+    pub up: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmCpuAffinityData {
+    /// New host CPUs of the listed vCPUs, the other vCPUs being left untouched
+    pub affinity: Vec<CpuAffinity>,
+}
+
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmRemoveDeviceData {
     pub id: String,
@@ -187,6 +236,12 @@ pub struct VmSnapshotConfig {
     pub destination_url: String,
 }
 
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmCoredumpData {
+    /// The coredump destination file URL
+    pub destination_url: String,
+}
+
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmReceiveMigrationData {
     /// URL for the reception of migration state
@@ -211,6 +266,9 @@ pub enum ApiResponsePayload {
 
     /// Vm action response
     VmAction(Vec<u8>),
+
+    /// File descriptors passed or closed
+    VmmFds(Vec<RawFd>),
 }
 
 /// This is the response sent by the VMM API server through the mpsc channel.
@@ -251,6 +309,9 @@ pub enum ApiRequest {
     /// Get counters for a VM.
     VmCounters(Sender<ApiResponse>),
 
+    /// Get the exit counters of the vCPUs of a VM.
+    VmVcpuCounters(Sender<ApiResponse>),
+
     /// Shut the previously booted virtual machine down.
     /// If the VM was not previously booted or created, the VMM API server
     /// will send a VmShutdown error back.
@@ -266,15 +327,34 @@ pub enum ApiRequest {
     /// VMM process.
     VmmShutdown(Sender<ApiResponse>),
 
+    /// Keep the file descriptors passed along with the request open, for
+    /// the following requests to refer to them.
+    VmmSendFds(Vec<File>, Sender<ApiResponse>),
+
+    /// Close file descriptors previously passed.
+    VmmCloseFds(Vec<RawFd>, Sender<ApiResponse>),
+
     /// Resize the VM.
     VmResize(Arc<VmResizeData>, Sender<ApiResponse>),
 
     /// Resize the memory zone.
     VmResizeZone(Arc<VmResizeZoneData>, Sender<ApiResponse>),
 
+    /// Start or stop capturing the traffic of a network device.
+    VmNetCapture(Arc<VmNetCaptureData>, Sender<ApiResponse>),
+
+    /// Set the link state of a network device.
+    VmNetLink(Arc<VmNetLinkData>, Sender<ApiResponse>),
+
+    /// Change the host CPU affinity of vCPUs.
+    VmCpuAffinity(Arc<VmCpuAffinityData>, Sender<ApiResponse>),
+
     /// Add a device to the VM.
     VmAddDevice(Arc<DeviceConfig>, Sender<ApiResponse>),
 
+    /// Add a user device to the VM.
+    VmAddUserDevice(Arc<UserDeviceConfig>, Sender<ApiResponse>),
+
     /// Remove a device from the VM.
     VmRemoveDevice(Arc<VmRemoveDeviceData>, Sender<ApiResponse>),
 
@@ -284,6 +364,9 @@ pub enum ApiRequest {
     /// Add a fs to the VM.
     VmAddFs(Arc<FsConfig>, Sender<ApiResponse>),
 
+    /// Add a generic vhost-user device to the VM.
+    VmAddGenericVhostUser(Arc<GenericVhostUserConfig>, Sender<ApiResponse>),
+
     /// Add a pmem device to the VM.
     VmAddPmem(Arc<PmemConfig>, Sender<ApiResponse>),
 
@@ -299,6 +382,9 @@ pub enum ApiRequest {
     /// Restore from a VM snapshot
     VmRestore(Arc<RestoreConfig>, Sender<ApiResponse>),
 
+    /// Write an ELF coredump of the VM
+    VmCoredump(Arc<VmCoredumpData>, Sender<ApiResponse>),
+
     /// Incoming migration
     VmReceiveMigration(Arc<VmReceiveMigrationData>, Sender<ApiResponse>),
 
@@ -352,15 +438,24 @@ pub enum VmAction {
     /// Return VM counters
     Counters,
 
+    /// Return vCPU exit counters
+    VcpuCounters,
+
     /// Add VFIO device
     AddDevice(Arc<DeviceConfig>),
 
+    /// Add vfio-user device
+    AddUserDevice(Arc<UserDeviceConfig>),
+
     /// Add disk
     AddDisk(Arc<DiskConfig>),
 
     /// Add filesystem
     AddFs(Arc<FsConfig>),
 
+    /// Add generic vhost-user device
+    AddGenericVhostUser(Arc<GenericVhostUserConfig>),
+
     /// Add pmem
     AddPmem(Arc<PmemConfig>),
 
@@ -379,12 +474,24 @@ pub enum VmAction {
     /// Resize memory zone
     ResizeZone(Arc<VmResizeZoneData>),
 
+    /// Capture network traffic
+    NetCapture(Arc<VmNetCaptureData>),
+
+    /// Set network link state
+    NetLink(Arc<VmNetLinkData>),
+
+    /// Set vCPUs host CPU affinity
+    CpuAffinity(Arc<VmCpuAffinityData>),
+
     /// Restore VM
     Restore(Arc<RestoreConfig>),
 
     /// Snapshot VM
     Snapshot(Arc<VmSnapshotConfig>),
 
+    /// Coredump VM
+    Coredump(Arc<VmCoredumpData>),
+
     /// Incoming migration
     ReceiveMigration(Arc<VmReceiveMigrationData>),
 
@@ -411,17 +518,24 @@ fn vm_action(
         Pause => ApiRequest::VmPause(response_sender),
         Resume => ApiRequest::VmResume(response_sender),
         Counters => ApiRequest::VmCounters(response_sender),
+        VcpuCounters => ApiRequest::VmVcpuCounters(response_sender),
         AddDevice(v) => ApiRequest::VmAddDevice(v, response_sender),
+        AddUserDevice(v) => ApiRequest::VmAddUserDevice(v, response_sender),
         AddDisk(v) => ApiRequest::VmAddDisk(v, response_sender),
         AddFs(v) => ApiRequest::VmAddFs(v, response_sender),
+        AddGenericVhostUser(v) => ApiRequest::VmAddGenericVhostUser(v, response_sender),
         AddPmem(v) => ApiRequest::VmAddPmem(v, response_sender),
         AddNet(v) => ApiRequest::VmAddNet(v, response_sender),
         AddVsock(v) => ApiRequest::VmAddVsock(v, response_sender),
         RemoveDevice(v) => ApiRequest::VmRemoveDevice(v, response_sender),
         Resize(v) => ApiRequest::VmResize(v, response_sender),
         ResizeZone(v) => ApiRequest::VmResizeZone(v, response_sender),
+        NetCapture(v) => ApiRequest::VmNetCapture(v, response_sender),
+        NetLink(v) => ApiRequest::VmNetLink(v, response_sender),
+        CpuAffinity(v) => ApiRequest::VmCpuAffinity(v, response_sender),
         Restore(v) => ApiRequest::VmRestore(v, response_sender),
         Snapshot(v) => ApiRequest::VmSnapshot(v, response_sender),
+        Coredump(v) => ApiRequest::VmCoredump(v, response_sender),
         ReceiveMigration(v) => ApiRequest::VmReceiveMigration(v, response_sender),
         SendMigration(v) => ApiRequest::VmSendMigration(v, response_sender),
         PowerButton => ApiRequest::VmPowerButton(response_sender),
@@ -468,6 +582,13 @@ pub fn vm_counters(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResul
     vm_action(api_evt, api_sender, VmAction::Counters)
 }
 
+pub fn vm_vcpu_counters(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::VcpuCounters)
+}
+
 pub fn vm_power_button(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -499,6 +620,14 @@ pub fn vm_snapshot(
     vm_action(api_evt, api_sender, VmAction::Snapshot(data))
 }
 
+pub fn vm_coredump(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmCoredumpData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Coredump(data))
+}
+
 pub fn vm_restore(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -554,6 +683,51 @@ pub fn vmm_shutdown(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResu
     Ok(())
 }
 
+fn vmm_fds_request(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    request: ApiRequest,
+    response_receiver: Receiver<ApiResponse>,
+) -> ApiResult<Vec<RawFd>> {
+    api_sender.send(request).map_err(ApiError::RequestSend)?;
+    api_evt.write(1).map_err(ApiError::EventFdWrite)?;
+
+    match response_receiver.recv().map_err(ApiError::ResponseRecv)?? {
+        ApiResponsePayload::VmmFds(fds) => Ok(fds),
+        _ => Err(ApiError::ResponsePayloadType),
+    }
+}
+
+pub fn vmm_send_fds(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    files: Vec<File>,
+) -> ApiResult<Vec<RawFd>> {
+    let (response_sender, response_receiver) = channel();
+
+    vmm_fds_request(
+        api_evt,
+        api_sender,
+        ApiRequest::VmmSendFds(files, response_sender),
+        response_receiver,
+    )
+}
+
+pub fn vmm_close_fds(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    fds: Vec<RawFd>,
+) -> ApiResult<Vec<RawFd>> {
+    let (response_sender, response_receiver) = channel();
+
+    vmm_fds_request(
+        api_evt,
+        api_sender,
+        ApiRequest::VmmCloseFds(fds, response_sender),
+        response_receiver,
+    )
+}
+
 pub fn vm_resize(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -570,6 +744,30 @@ pub fn vm_resize_zone(
     vm_action(api_evt, api_sender, VmAction::ResizeZone(data))
 }
 
+pub fn vm_net_capture(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmNetCaptureData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::NetCapture(data))
+}
+
+pub fn vm_net_link(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmNetLinkData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::NetLink(data))
+}
+
+pub fn vm_cpu_affinity(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmCpuAffinityData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::CpuAffinity(data))
+}
+
 pub fn vm_add_device(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -578,6 +776,14 @@ pub fn vm_add_device(
     vm_action(api_evt, api_sender, VmAction::AddDevice(data))
 }
 
+pub fn vm_add_user_device(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<UserDeviceConfig>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::AddUserDevice(data))
+}
+
 pub fn vm_remove_device(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -602,6 +808,14 @@ pub fn vm_add_fs(
     vm_action(api_evt, api_sender, VmAction::AddFs(data))
 }
 
+pub fn vm_add_generic_vhost_user(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<GenericVhostUserConfig>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::AddGenericVhostUser(data))
+}
+
 pub fn vm_add_pmem(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
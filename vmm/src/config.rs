@@ -8,16 +8,16 @@ use net_util::MacAddr;
 use option_parser::{
     ByteSized, IntegerList, OptionParser, OptionParserError, StringList, Toggle, TupleTwoIntegers,
 };
-use std::convert::From;
+use std::convert::{From, TryFrom};
 use std::fmt;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
 use std::result;
 use std::str::FromStr;
 
 use virtio_devices::{RateLimiterConfig, TokenBucketConfig};
 
-pub const DEFAULT_VCPUS: u8 = 1;
+pub const DEFAULT_VCPUS: u32 = 1;
 pub const DEFAULT_MEMORY_MB: u64 = 512;
 pub const DEFAULT_RNG_SOURCE: &str = "/dev/urandom";
 pub const DEFAULT_NUM_QUEUES_VUNET: usize = 2;
@@ -25,6 +25,17 @@ pub const DEFAULT_QUEUE_SIZE_VUNET: u16 = 256;
 pub const DEFAULT_NUM_QUEUES_VUBLK: usize = 1;
 pub const DEFAULT_QUEUE_SIZE_VUBLK: u16 = 128;
 
+// Highest number of vCPUs, each of them being named "Cxxx" in the ACPI tables
+pub const MAX_VCPUS: u32 = 0xfff;
+
+// Range of the real-time priorities accepted by SCHED_FIFO
+const MIN_FIFO_PRIORITY: u8 = 1;
+const MAX_FIFO_PRIORITY: u8 = 99;
+
+// Minimum link MTU for IPv4 (RFC 791) and IPv6 (RFC 8200)
+const MIN_NET_MTU: u16 = 68;
+const MIN_NET_MTU_IPV6: u16 = 1280;
+
 /// Errors associated with VM configuration parameters.
 #[derive(Debug)]
 pub enum Error {
@@ -70,6 +81,20 @@ pub enum Error {
     ParseDevice(OptionParserError),
     /// Missing path from device,
     ParseDevicePathMissing,
+    /// Failed parsing user device parameters
+    ParseUserDevice(OptionParserError),
+    /// Missing socket from user device
+    ParseUserDeviceSocketMissing,
+    /// Failed parsing generic vhost-user parameters
+    ParseGenericVhostUser(OptionParserError),
+    /// Missing socket from generic vhost-user device
+    ParseGenericVhostUserSocketMissing,
+    /// Missing virtio device type from generic vhost-user device
+    ParseGenericVhostUserVirtioIdMissing,
+    /// Missing queue sizes from generic vhost-user device
+    ParseGenericVhostUserQueueSizesMissing,
+    /// Invalid queue size for generic vhost-user device
+    ParseGenericVhostUserInvalidQueueSize(u64),
     /// Failed to parse vsock parameters
     ParseVsock(OptionParserError),
     /// Failed to parse restore parameters
@@ -79,6 +104,12 @@ pub enum Error {
     ParseSgxEpc(OptionParserError),
     /// Failed to parse NUMA parameters
     ParseNuma(OptionParserError),
+    /// Failed to parse Landlock rules
+    ParseLandlockRules(OptionParserError),
+    /// Missing path from Landlock rules
+    ParseLandlockRulesPathMissing,
+    /// Missing access from Landlock rules
+    ParseLandlockRulesAccessMissing,
     /// Failed to validate configuration
     Validation(ValidationError),
     #[cfg(feature = "tdx")]
@@ -99,6 +130,8 @@ pub enum ValidationError {
     ConsoleFileMissing,
     /// Max is less than boot
     CpusMaxLowerThanBoot,
+    /// Max is greater than the supported number of vCPUs
+    CpusMaxTooHigh(u32),
     /// Both socket and path specified
     DiskSocketAndPath,
     /// Using vhost user requires shared memory
@@ -111,6 +144,24 @@ pub enum ValidationError {
     VfioUnsupported,
     /// CPU topology count doesn't match max
     CpuTopologyCount,
+    /// CPU affinity given for a vCPU beyond max
+    CpuAffinityInvalidVcpu(u32),
+    /// CPU affinity without any host CPU
+    CpuAffinityEmpty(u32),
+    /// Host CPU beyond the ones a CPU affinity can hold
+    CpuAffinityInvalidHostCpu(usize),
+    /// VMM thread affinity without any host CPU
+    VmmAffinityEmpty,
+    /// I/O threads affinity without any host CPU
+    IoAffinityEmpty,
+    /// SCHED_FIFO priority out of range
+    InvalidFifoPriority(u8),
+    /// Unknown CPU model
+    CpuModelUnknown(String),
+    /// Unknown CPU feature, or not prefixed with '+' or '-'
+    CpuFeatureInvalid(String),
+    /// CPU models and features are only supported on x86_64
+    CpuModelUnsupported,
     /// One part of the CPU topology was zero
     CpuTopologyZeroPart,
     /// Virtio needs a min of 2 queues
@@ -119,6 +170,21 @@ pub enum ValidationError {
     VnetQueueFdMismatch,
     /// Using reserved fd
     VnetReservedFd,
+    /// User mode networking does not rely on a TAP interface or vhost-user
+    VnetUserModeIncompatible,
+    /// User mode networking only supports a single queue pair
+    VnetUserModeMultiQueue,
+    /// Port forwards are only supported with user mode networking
+    VnetPortForwardWithoutUserMode,
+    /// Kernel vhost-net only handles TAP interfaces, without any processing
+    /// from the VMM
+    VnetVhostIncompatible,
+    /// Prefix length larger than the address
+    VnetInvalidPrefixLength(IpAddr, u8),
+    /// MTU lower than the minimum of the IP versions in use
+    VnetInvalidMtu(u16),
+    /// Host addresses and MTU can only be applied to a TAP interface
+    VnetTapSettingsWithoutTap,
     // Hugepages not turned on
     HugePageSizeWithoutHugePages,
     // Huge page size is not power of 2
@@ -131,6 +197,18 @@ pub enum ValidationError {
     TdxKernelSpecified,
     // Insuffient vCPUs for queues
     TooManyQueues,
+    // Read-only pmem cannot discard writes
+    PmemReadOnlyDiscardWrites,
+    /// Using vfio-user requires shared memory
+    UserDevicesRequireSharedMemory,
+    /// Generic vhost-user device needs at least one queue
+    GenericVhostUserNoQueues,
+    /// The vsock device needs a socket unless offloaded to vhost-vsock
+    VsockSocketMissing,
+    /// Kernel vhost-vsock is incompatible with some settings
+    VsockVhostIncompatible,
+    /// Landlock rules given without enabling Landlock
+    LandlockRulesWithoutLandlock,
 }
 
 type ValidationResult<T> = std::result::Result<T, ValidationError>;
@@ -143,6 +221,11 @@ impl fmt::Display for ValidationError {
             KernelMissing => write!(f, "No kernel specified"),
             ConsoleFileMissing => write!(f, "Path missing when using file console mode"),
             CpusMaxLowerThanBoot => write!(f, "Max CPUs greater than boot CPUs"),
+            CpusMaxTooHigh(max_vcpus) => write!(
+                f,
+                "Max CPUs {} greater than the supported {}",
+                max_vcpus, MAX_VCPUS
+            ),
             DiskSocketAndPath => write!(f, "Disk path and vhost socket both provided"),
             VhostUserRequiresSharedMemory => {
                 write!(f, "Using vhost-user requires using shared memory")
@@ -155,12 +238,65 @@ impl fmt::Display for ValidationError {
                 f,
                 "Product of CPU topology parts does not match maximum vCPUs"
             ),
+            CpuAffinityInvalidVcpu(vcpu) => {
+                write!(f, "CPU affinity given for vCPU {} beyond max vCPUs", vcpu)
+            }
+            CpuAffinityEmpty(vcpu) => write!(f, "No host CPU in the affinity of vCPU {}", vcpu),
+            CpuAffinityInvalidHostCpu(host_cpu) => write!(
+                f,
+                "Invalid host CPU {} in CPU affinity, it must be lower than {}",
+                host_cpu,
+                libc::CPU_SETSIZE
+            ),
+            VmmAffinityEmpty => write!(f, "No host CPU in the VMM thread affinity"),
+            IoAffinityEmpty => write!(f, "No host CPU in the I/O threads affinity"),
+            InvalidFifoPriority(priority) => write!(
+                f,
+                "Invalid SCHED_FIFO priority {}, it must be between {} and {}",
+                priority, MIN_FIFO_PRIORITY, MAX_FIFO_PRIORITY
+            ),
+            CpuModelUnknown(model) => write!(f, "Unknown CPU model {}", model),
+            CpuFeatureInvalid(feature) => write!(
+                f,
+                "Invalid CPU feature {}, it must be a known feature prefixed with '+' or '-'",
+                feature
+            ),
+            CpuModelUnsupported => write!(
+                f,
+                "CPU models and features are not supported on this architecture"
+            ),
             VnetQueueLowerThan2 => write!(f, "Number of queues to virtio_net less than 2"),
             VnetQueueFdMismatch => write!(
                 f,
                 "Number of queues to virtio_net does not match the number of input FDs"
             ),
             VnetReservedFd => write!(f, "Reserved fd number (<= 2)"),
+            VnetUserModeIncompatible => write!(
+                f,
+                "User mode networking is incompatible with tap, fd and vhost_user"
+            ),
+            VnetUserModeMultiQueue => {
+                write!(f, "User mode networking only supports a single queue pair")
+            }
+            VnetPortForwardWithoutUserMode => {
+                write!(f, "Port forwards require user mode networking")
+            }
+            VnetVhostIncompatible => write!(
+                f,
+                "Kernel vhost-net is incompatible with vhost_user, user mode, iommu and rate limiting"
+            ),
+            VnetInvalidPrefixLength(addr, prefix_len) => {
+                write!(f, "Invalid prefix length {} for {}", prefix_len, addr)
+            }
+            VnetInvalidMtu(mtu) => write!(
+                f,
+                "Invalid MTU {}, IPv4 requires at least {} and IPv6 at least {}",
+                mtu, MIN_NET_MTU, MIN_NET_MTU_IPV6
+            ),
+            VnetTapSettingsWithoutTap => write!(
+                f,
+                "Addresses and MTU are incompatible with vhost_user and user mode"
+            ),
             HugePageSizeWithoutHugePages => {
                 write!(f, "Huge page size specified but huge pages not enabled")
             }
@@ -178,6 +314,26 @@ impl fmt::Display for ValidationError {
             TooManyQueues => {
                 write!(f, "Number of vCPUs is insufficient for number of queues")
             }
+            PmemReadOnlyDiscardWrites => {
+                write!(
+                    f,
+                    "Persistent memory cannot be both readonly and discard_writes"
+                )
+            }
+            UserDevicesRequireSharedMemory => {
+                write!(f, "Using user devices requires using shared memory")
+            }
+            GenericVhostUserNoQueues => {
+                write!(f, "Generic vhost-user device requires at least one queue")
+            }
+            VsockSocketMissing => write!(f, "No socket provided for the vsock device"),
+            VsockVhostIncompatible => write!(
+                f,
+                "Kernel vhost-vsock is incompatible with iommu and TCP port forwarding"
+            ),
+            LandlockRulesWithoutLandlock => {
+                write!(f, "Landlock rules specified but Landlock not enabled")
+            }
         }
     }
 }
@@ -194,6 +350,27 @@ impl fmt::Display for Error {
 
             ParseDevice(o) => write!(f, "Error parsing --device: {}", o),
             ParseDevicePathMissing => write!(f, "Error parsing --device: path missing"),
+            ParseUserDevice(o) => write!(f, "Error parsing --user-device: {}", o),
+            ParseUserDeviceSocketMissing => {
+                write!(f, "Error parsing --user-device: socket missing")
+            }
+            ParseGenericVhostUser(o) => write!(f, "Error parsing --generic-vhost-user: {}", o),
+            ParseGenericVhostUserSocketMissing => {
+                write!(f, "Error parsing --generic-vhost-user: socket missing")
+            }
+            ParseGenericVhostUserVirtioIdMissing => {
+                write!(f, "Error parsing --generic-vhost-user: virtio_id missing")
+            }
+            ParseGenericVhostUserQueueSizesMissing => {
+                write!(f, "Error parsing --generic-vhost-user: queue_sizes missing")
+            }
+            ParseGenericVhostUserInvalidQueueSize(s) => {
+                write!(
+                    f,
+                    "Error parsing --generic-vhost-user: invalid queue size {}",
+                    s
+                )
+            }
             ParseFileSystem(o) => write!(f, "Error parsing --fs: {}", o),
             ParseFsSockMissing => write!(f, "Error parsing --fs: socket missing"),
             ParseFsTagMissing => write!(f, "Error parsing --fs: tag missing"),
@@ -216,6 +393,13 @@ impl fmt::Display for Error {
             #[cfg(target_arch = "x86_64")]
             ParseSgxEpc(o) => write!(f, "Error parsing --sgx-epc: {}", o),
             ParseNuma(o) => write!(f, "Error parsing --numa: {}", o),
+            ParseLandlockRules(o) => write!(f, "Error parsing --landlock-rules: {}", o),
+            ParseLandlockRulesPathMissing => {
+                write!(f, "Error parsing --landlock-rules: path missing")
+            }
+            ParseLandlockRulesAccessMissing => {
+                write!(f, "Error parsing --landlock-rules: access missing")
+            }
             ParseRestoreSourceUrlMissing => {
                 write!(f, "Error parsing --restore: source_url missing")
             }
@@ -246,6 +430,8 @@ pub struct VmParams<'a> {
     pub serial: &'a str,
     pub console: &'a str,
     pub devices: Option<Vec<&'a str>>,
+    pub user_devices: Option<Vec<&'a str>>,
+    pub generic_vhost_user: Option<Vec<&'a str>>,
     pub vsock: Option<&'a str>,
     #[cfg(target_arch = "x86_64")]
     pub sgx_epc: Option<Vec<&'a str>>,
@@ -253,6 +439,10 @@ pub struct VmParams<'a> {
     pub watchdog: bool,
     #[cfg(feature = "tdx")]
     pub tdx: Option<&'a str>,
+    #[cfg(feature = "gdb")]
+    pub gdb: bool,
+    pub landlock_enable: bool,
+    pub landlock_rules: Option<Vec<&'a str>>,
 }
 
 impl<'a> VmParams<'a> {
@@ -275,6 +465,9 @@ impl<'a> VmParams<'a> {
         let fs: Option<Vec<&str>> = args.values_of("fs").map(|x| x.collect());
         let pmem: Option<Vec<&str>> = args.values_of("pmem").map(|x| x.collect());
         let devices: Option<Vec<&str>> = args.values_of("device").map(|x| x.collect());
+        let user_devices: Option<Vec<&str>> = args.values_of("user-device").map(|x| x.collect());
+        let generic_vhost_user: Option<Vec<&str>> =
+            args.values_of("generic-vhost-user").map(|x| x.collect());
         let vsock: Option<&str> = args.value_of("vsock");
         #[cfg(target_arch = "x86_64")]
         let sgx_epc: Option<Vec<&str>> = args.values_of("sgx-epc").map(|x| x.collect());
@@ -282,6 +475,11 @@ impl<'a> VmParams<'a> {
         let watchdog = args.is_present("watchdog");
         #[cfg(feature = "tdx")]
         let tdx = args.value_of("tdx");
+        #[cfg(feature = "gdb")]
+        let gdb = args.is_present("gdb");
+        let landlock_enable = args.is_present("landlock");
+        let landlock_rules: Option<Vec<&str>> =
+            args.values_of("landlock-rules").map(|x| x.collect());
         VmParams {
             cpus,
             memory,
@@ -298,6 +496,8 @@ impl<'a> VmParams<'a> {
             serial,
             console,
             devices,
+            user_devices,
+            generic_vhost_user,
             vsock,
             #[cfg(target_arch = "x86_64")]
             sgx_epc,
@@ -305,6 +505,10 @@ impl<'a> VmParams<'a> {
             watchdog,
             #[cfg(feature = "tdx")]
             tdx,
+            #[cfg(feature = "gdb")]
+            gdb,
+            landlock_enable,
+            landlock_rules,
         }
     }
 }
@@ -379,16 +583,97 @@ impl FromStr for CpuTopology {
     }
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct CpuAffinity {
+    pub vcpu: u32,
+    pub host_cpus: Vec<usize>,
+}
+
+impl CpuAffinity {
+    /// Parse a list of `<vcpu>@<host_cpus>` entries separated with ':', where `<host_cpus>`
+    /// is either a single host CPU or an inclusive range such as `4-7`. Entries for the same
+    /// vCPU are merged, so that non-contiguous host CPU sets can be expressed.
+    pub fn parse_list(affinity: &str) -> Result<Vec<Self>> {
+        let mut list: Vec<CpuAffinity> = Vec::new();
+
+        for entry in affinity.trim().split(':') {
+            let conversion_error = || {
+                Error::ParseCpus(OptionParserError::Conversion(
+                    "affinity".to_owned(),
+                    entry.to_owned(),
+                ))
+            };
+
+            let items: Vec<&str> = entry.split('@').collect();
+            if items.len() != 2 {
+                return Err(conversion_error());
+            }
+            let vcpu = items[0].parse::<u32>().map_err(|_| conversion_error())?;
+            let host_cpus = items[1]
+                .parse::<IntegerList>()
+                .map_err(|_| conversion_error())?
+                .0;
+
+            let affinity = match list.iter_mut().find(|a| a.vcpu == vcpu) {
+                Some(affinity) => affinity,
+                None => {
+                    list.push(CpuAffinity {
+                        vcpu,
+                        host_cpus: Vec::new(),
+                    });
+                    list.last_mut().unwrap()
+                }
+            };
+            for host_cpu in host_cpus {
+                let host_cpu = usize::try_from(host_cpu).map_err(|_| conversion_error())?;
+                if !affinity.host_cpus.contains(&host_cpu) {
+                    affinity.host_cpus.push(host_cpu);
+                }
+            }
+            affinity.host_cpus.sort_unstable();
+        }
+
+        Ok(list)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct CpusConfig {
-    pub boot_vcpus: u8,
-    pub max_vcpus: u8,
+    pub boot_vcpus: u32,
+    pub max_vcpus: u32,
     #[serde(default)]
     pub topology: Option<CpuTopology>,
     #[serde(default)]
     pub kvm_hyperv: bool,
     #[serde(default)]
     pub max_phys_bits: Option<u8>,
+    /// Host CPUs each vCPU thread is allowed to run on
+    #[serde(default)]
+    pub affinity: Option<Vec<CpuAffinity>>,
+    /// Host CPUs the VMM thread, along with the device worker and I/O
+    /// threads it spawns unless `io_affinity` is given, is allowed to run on
+    #[serde(default)]
+    pub vmm_affinity: Option<Vec<usize>>,
+    /// Host CPUs the device worker and I/O threads are allowed to run on
+    #[serde(default)]
+    pub io_affinity: Option<Vec<usize>>,
+    /// Run the vCPU threads with the SCHED_FIFO policy at this priority
+    #[serde(default)]
+    pub fifo_priority: Option<u8>,
+    /// CPU model the CPUID features are masked to, instead of exposing all
+    /// the features supported by the host
+    #[serde(default)]
+    pub model: Option<String>,
+    /// CPU features to enable ("+<feature>") or disable ("-<feature>") on
+    /// top of the CPU model
+    #[serde(default)]
+    pub features: Option<Vec<String>>,
+    /// Count the port I/O and MMIO exits of each vCPU
+    #[serde(default)]
+    pub exit_stats: bool,
+    /// File every vCPU exit is logged to, for offline analysis
+    #[serde(default)]
+    pub exit_trace: Option<PathBuf>,
 }
 
 impl CpusConfig {
@@ -399,14 +684,22 @@ impl CpusConfig {
             .add("max")
             .add("topology")
             .add("kvm_hyperv")
-            .add("max_phys_bits");
+            .add("max_phys_bits")
+            .add("affinity")
+            .add("vmm_affinity")
+            .add("io_affinity")
+            .add("fifo_priority")
+            .add("model")
+            .add("features")
+            .add("exit_stats")
+            .add("exit_trace");
         parser.parse(cpus).map_err(Error::ParseCpus)?;
 
-        let boot_vcpus: u8 = parser
+        let boot_vcpus: u32 = parser
             .convert("boot")
             .map_err(Error::ParseCpus)?
             .unwrap_or(DEFAULT_VCPUS);
-        let max_vcpus: u8 = parser
+        let max_vcpus: u32 = parser
             .convert("max")
             .map_err(Error::ParseCpus)?
             .unwrap_or(boot_vcpus);
@@ -419,6 +712,32 @@ impl CpusConfig {
         let max_phys_bits = parser
             .convert::<u8>("max_phys_bits")
             .map_err(Error::ParseCpus)?;
+        let affinity = parser
+            .get("affinity")
+            .map(|affinity| CpuAffinity::parse_list(&affinity))
+            .transpose()?;
+        let vmm_affinity = parser
+            .convert::<IntegerList>("vmm_affinity")
+            .map_err(Error::ParseCpus)?
+            .map(|v| v.0.iter().map(|e| *e as usize).collect());
+        let io_affinity = parser
+            .convert::<IntegerList>("io_affinity")
+            .map_err(Error::ParseCpus)?
+            .map(|v| v.0.iter().map(|e| *e as usize).collect());
+        let fifo_priority = parser
+            .convert::<u8>("fifo_priority")
+            .map_err(Error::ParseCpus)?;
+        let model = parser.get("model");
+        let features = parser
+            .convert::<StringList>("features")
+            .map_err(Error::ParseCpus)?
+            .map(|v| v.0);
+        let exit_stats = parser
+            .convert::<Toggle>("exit_stats")
+            .map_err(Error::ParseCpus)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let exit_trace = parser.get("exit_trace").map(PathBuf::from);
 
         Ok(CpusConfig {
             boot_vcpus,
@@ -426,8 +745,93 @@ impl CpusConfig {
             topology,
             kvm_hyperv,
             max_phys_bits,
+            affinity,
+            vmm_affinity,
+            io_affinity,
+            fifo_priority,
+            model,
+            features,
+            exit_stats,
+            exit_trace,
         })
     }
+
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.max_vcpus < self.boot_vcpus {
+            return Err(ValidationError::CpusMaxLowerThanBoot);
+        }
+
+        if self.max_vcpus > MAX_VCPUS {
+            return Err(ValidationError::CpusMaxTooHigh(self.max_vcpus));
+        }
+
+        if let Some(affinity) = &self.affinity {
+            for a in affinity {
+                if a.vcpu >= self.max_vcpus {
+                    return Err(ValidationError::CpuAffinityInvalidVcpu(a.vcpu));
+                }
+                if a.host_cpus.is_empty() {
+                    return Err(ValidationError::CpuAffinityEmpty(a.vcpu));
+                }
+                Self::validate_host_cpus(&a.host_cpus)?;
+            }
+        }
+
+        if let Some(vmm_affinity) = &self.vmm_affinity {
+            if vmm_affinity.is_empty() {
+                return Err(ValidationError::VmmAffinityEmpty);
+            }
+            Self::validate_host_cpus(vmm_affinity)?;
+        }
+
+        if let Some(io_affinity) = &self.io_affinity {
+            if io_affinity.is_empty() {
+                return Err(ValidationError::IoAffinityEmpty);
+            }
+            Self::validate_host_cpus(io_affinity)?;
+        }
+
+        if let Some(priority) = self.fifo_priority {
+            if !(MIN_FIFO_PRIORITY..=MAX_FIFO_PRIORITY).contains(&priority) {
+                return Err(ValidationError::InvalidFifoPriority(priority));
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            if let Some(model) = &self.model {
+                if !arch::x86_64::cpu_model::is_cpu_model(model) {
+                    return Err(ValidationError::CpuModelUnknown(model.clone()));
+                }
+            }
+
+            for feature in self.features.iter().flatten() {
+                if !feature
+                    .strip_prefix(|c| c == '+' || c == '-')
+                    .map_or(false, arch::x86_64::cpu_model::is_cpu_feature)
+                {
+                    return Err(ValidationError::CpuFeatureInvalid(feature.clone()));
+                }
+            }
+        }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        if self.model.is_some() || self.features.is_some() {
+            return Err(ValidationError::CpuModelUnsupported);
+        }
+
+        Ok(())
+    }
+
+    fn validate_host_cpus(host_cpus: &[usize]) -> ValidationResult<()> {
+        match host_cpus
+            .iter()
+            .find(|host_cpu| **host_cpu >= libc::CPU_SETSIZE as usize)
+        {
+            Some(host_cpu) => Err(ValidationError::CpuAffinityInvalidHostCpu(*host_cpu)),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Default for CpusConfig {
@@ -438,6 +842,14 @@ impl Default for CpusConfig {
             topology: None,
             kvm_hyperv: false,
             max_phys_bits: None,
+            affinity: None,
+            vmm_affinity: None,
+            io_affinity: None,
+            fifo_priority: None,
+            model: None,
+            features: None,
+            exit_stats: false,
+            exit_trace: None,
         }
     }
 }
@@ -914,6 +1326,54 @@ impl FromStr for VhostMode {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum NetMode {
+    Tap,
+    User,
+}
+
+impl Default for NetMode {
+    fn default() -> Self {
+        NetMode::Tap
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseNetModeError {
+    InvalidValue(String),
+}
+
+impl FromStr for NetMode {
+    type Err = ParseNetModeError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tap" => Ok(NetMode::Tap),
+            "user" => Ok(NetMode::User),
+            _ => Err(ParseNetModeError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PortForwardConfig {
+    /// Host address the port is bound on, the loopback one by default
+    #[serde(default = "default_portforwardconfig_host_addr")]
+    pub host_addr: Ipv4Addr,
+    pub host_port: u16,
+    pub guest_port: u16,
+}
+
+fn default_portforwardconfig_host_addr() -> Ipv4Addr {
+    Ipv4Addr::LOCALHOST
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct IpAddrConfig {
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct NetConfig {
     #[serde(default = "default_netconfig_tap")]
@@ -933,6 +1393,8 @@ pub struct NetConfig {
     #[serde(default = "default_netconfig_queue_size")]
     pub queue_size: u16,
     #[serde(default)]
+    pub vhost: bool,
+    #[serde(default)]
     pub vhost_user: bool,
     pub vhost_socket: Option<String>,
     #[serde(default)]
@@ -943,6 +1405,16 @@ pub struct NetConfig {
     pub fds: Option<Vec<i32>>,
     #[serde(default)]
     pub rate_limiter_config: Option<RateLimiterConfig>,
+    #[serde(default)]
+    pub mode: NetMode,
+    #[serde(default)]
+    pub tcp_forward: Vec<PortForwardConfig>,
+    #[serde(default)]
+    pub udp_forward: Vec<PortForwardConfig>,
+    #[serde(default)]
+    pub addresses: Vec<IpAddrConfig>,
+    #[serde(default)]
+    pub mtu: Option<u16>,
 }
 
 fn default_netconfig_tap() -> Option<String> {
@@ -980,12 +1452,18 @@ impl Default for NetConfig {
             iommu: false,
             num_queues: default_netconfig_num_queues(),
             queue_size: default_netconfig_queue_size(),
+            vhost: false,
             vhost_user: false,
             vhost_socket: None,
             vhost_mode: VhostMode::Client,
             id: None,
             fds: None,
             rate_limiter_config: None,
+            mode: NetMode::Tap,
+            tcp_forward: Vec::new(),
+            udp_forward: Vec::new(),
+            addresses: Vec::new(),
+            mtu: None,
         }
     }
 }
@@ -993,10 +1471,86 @@ impl Default for NetConfig {
 impl NetConfig {
     pub const SYNTAX: &'static str = "Network parameters \
     \"tap=<if_name>,ip=<ip_addr>,mask=<net_mask>,mac=<mac_addr>,fd=<fd1:fd2...>,iommu=on|off,\
-    num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,id=<device_id>,\
+    num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,id=<device_id>,vhost=on|off,\
     vhost_user=<vhost_user_enable>,socket=<vhost_user_socket_path>,vhost_mode=client|server,\
     bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,\
-    ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>\"";
+    ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,mode=tap|user,\
+    tcp_forward=[<host_addr>@]<host_port>@<guest_port>:...,\
+    udp_forward=[<host_addr>@]<host_port>@<guest_port>:...,\
+    addresses=<ip_addr>/<prefix_len>:...,mtu=<mtu>\"";
+
+    // The entries are separated with ':', which can't be split upfront as
+    // IPv6 addresses contain it too. Each entry ends with the digits of the
+    // prefix length instead.
+    fn parse_addresses(parser: &OptionParser) -> Result<Vec<IpAddrConfig>> {
+        let addresses = match parser.get("addresses") {
+            Some(addresses) => addresses,
+            None => return Ok(Vec::new()),
+        };
+        let invalid = |entry: &str| {
+            Error::ParseNetwork(OptionParserError::Conversion(
+                "addresses".to_owned(),
+                entry.to_owned(),
+            ))
+        };
+
+        let mut configs = Vec::new();
+        let mut rest = addresses.as_str();
+        while !rest.is_empty() {
+            let slash = rest.find('/').ok_or_else(|| invalid(rest))?;
+            let end = rest[slash + 1..]
+                .find(':')
+                .map_or(rest.len(), |i| slash + 1 + i);
+            let entry = &rest[..end];
+
+            let addr = rest[..slash].parse().map_err(|_| invalid(entry))?;
+            let prefix_len = rest[slash + 1..end].parse().map_err(|_| invalid(entry))?;
+            configs.push(IpAddrConfig { addr, prefix_len });
+
+            rest = rest.get(end + 1..).unwrap_or("");
+        }
+
+        Ok(configs)
+    }
+
+    // Each entry is made of the host port and the guest port, optionally
+    // preceded by the host address, all separated with '@'.
+    fn parse_port_forward(forward: &str) -> Option<PortForwardConfig> {
+        let items: Vec<&str> = forward.split('@').collect();
+        let (host_addr, host_port, guest_port) = match items[..] {
+            [host_port, guest_port] => {
+                (default_portforwardconfig_host_addr(), host_port, guest_port)
+            }
+            [host_addr, host_port, guest_port] => (host_addr.parse().ok()?, host_port, guest_port),
+            _ => return None,
+        };
+
+        Some(PortForwardConfig {
+            host_addr,
+            host_port: host_port.parse().ok()?,
+            guest_port: guest_port.parse().ok()?,
+        })
+    }
+
+    fn parse_port_forwards(parser: &OptionParser, option: &str) -> Result<Vec<PortForwardConfig>> {
+        let forwards = match parser.get(option) {
+            Some(forwards) => forwards,
+            None => return Ok(Vec::new()),
+        };
+
+        forwards
+            .trim()
+            .split(':')
+            .map(|forward| {
+                Self::parse_port_forward(forward).ok_or_else(|| {
+                    Error::ParseNetwork(OptionParserError::Conversion(
+                        option.to_owned(),
+                        forward.to_owned(),
+                    ))
+                })
+            })
+            .collect()
+    }
 
     pub fn parse(net: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -1010,6 +1564,7 @@ impl NetConfig {
             .add("iommu")
             .add("queue_size")
             .add("num_queues")
+            .add("vhost")
             .add("vhost_user")
             .add("socket")
             .add("vhost_mode")
@@ -1020,7 +1575,12 @@ impl NetConfig {
             .add("bw_refill_time")
             .add("ops_size")
             .add("ops_one_time_burst")
-            .add("ops_refill_time");
+            .add("ops_refill_time")
+            .add("mode")
+            .add("tcp_forward")
+            .add("udp_forward")
+            .add("addresses")
+            .add("mtu");
         parser.parse(net).map_err(Error::ParseNetwork)?;
 
         let tap = parser.get("tap");
@@ -1050,6 +1610,11 @@ impl NetConfig {
             .convert("num_queues")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_else(default_netconfig_num_queues);
+        let vhost = parser
+            .convert::<Toggle>("vhost")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or(Toggle(false))
+            .0;
         let vhost_user = parser
             .convert::<Toggle>("vhost_user")
             .map_err(Error::ParseNetwork)?
@@ -1065,6 +1630,14 @@ impl NetConfig {
             .convert::<IntegerList>("fd")
             .map_err(Error::ParseNetwork)?
             .map(|v| v.0.iter().map(|e| *e as i32).collect());
+        let mode = parser
+            .convert("mode")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or_default();
+        let tcp_forward = Self::parse_port_forwards(&parser, "tcp_forward")?;
+        let udp_forward = Self::parse_port_forwards(&parser, "udp_forward")?;
+        let addresses = Self::parse_addresses(&parser)?;
+        let mtu = parser.convert("mtu").map_err(Error::ParseNetwork)?;
 
         let bw_size = parser
             .convert("bw_size")
@@ -1126,12 +1699,18 @@ impl NetConfig {
             iommu,
             num_queues,
             queue_size,
+            vhost,
             vhost_user,
             vhost_socket,
             vhost_mode,
             id,
             fds,
             rate_limiter_config,
+            mode,
+            tcp_forward,
+            udp_forward,
+            addresses,
+            mtu,
         };
         Ok(config)
     }
@@ -1157,6 +1736,57 @@ impl NetConfig {
             return Err(ValidationError::TooManyQueues);
         }
 
+        if self.mode == NetMode::User {
+            if self.tap.is_some() || self.fds.is_some() || self.vhost_user {
+                return Err(ValidationError::VnetUserModeIncompatible);
+            }
+            if self.num_queues != 2 {
+                return Err(ValidationError::VnetUserModeMultiQueue);
+            }
+        } else if !self.tcp_forward.is_empty() || !self.udp_forward.is_empty() {
+            return Err(ValidationError::VnetPortForwardWithoutUserMode);
+        }
+
+        if self.vhost
+            && (self.vhost_user
+                || self.mode == NetMode::User
+                || self.iommu
+                || self.rate_limiter_config.is_some())
+        {
+            return Err(ValidationError::VnetVhostIncompatible);
+        }
+
+        if (!self.addresses.is_empty() || self.mtu.is_some())
+            && (self.vhost_user || self.mode == NetMode::User)
+        {
+            return Err(ValidationError::VnetTapSettingsWithoutTap);
+        }
+
+        for address in self.addresses.iter() {
+            let max_prefix_len = match address.addr {
+                IpAddr::V4(_) => 32,
+                IpAddr::V6(_) => 128,
+            };
+            if address.prefix_len > max_prefix_len {
+                return Err(ValidationError::VnetInvalidPrefixLength(
+                    address.addr,
+                    address.prefix_len,
+                ));
+            }
+        }
+
+        if let Some(mtu) = self.mtu {
+            // The kernel disables IPv6 on links below its minimum MTU.
+            let min_mtu = if self.addresses.iter().any(|a| a.addr.is_ipv6()) {
+                MIN_NET_MTU_IPV6
+            } else {
+                MIN_NET_MTU
+            };
+            if mtu < min_mtu {
+                return Err(ValidationError::VnetInvalidMtu(mtu));
+            }
+        }
+
         Ok(())
     }
 }
@@ -1347,13 +1977,15 @@ pub struct PmemConfig {
     #[serde(default)]
     pub discard_writes: bool,
     #[serde(default)]
+    pub readonly: bool,
+    #[serde(default)]
     pub id: Option<String>,
 }
 
 impl PmemConfig {
     pub const SYNTAX: &'static str = "Persistent memory parameters \
     \"file=<backing_file_path>,size=<persistent_memory_size>,iommu=on|off,\
-    mergeable=on|off,discard_writes=on|off,id=<device_id>\"";
+    mergeable=on|off,discard_writes=on|off,readonly=on|off,id=<device_id>\"";
     pub fn parse(pmem: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
         parser
@@ -1362,6 +1994,7 @@ impl PmemConfig {
             .add("mergeable")
             .add("iommu")
             .add("discard_writes")
+            .add("readonly")
             .add("id");
         parser.parse(pmem).map_err(Error::ParsePersistentMemory)?;
 
@@ -1385,6 +2018,11 @@ impl PmemConfig {
             .map_err(Error::ParsePersistentMemory)?
             .unwrap_or(Toggle(false))
             .0;
+        let readonly = parser
+            .convert::<Toggle>("readonly")
+            .map_err(Error::ParsePersistentMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
         let id = parser.get("id");
 
         Ok(PmemConfig {
@@ -1393,9 +2031,18 @@ impl PmemConfig {
             iommu,
             mergeable,
             discard_writes,
+            readonly,
             id,
         })
     }
+
+    pub fn validate(&self, _vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.readonly && self.discard_writes {
+            return Err(ValidationError::PmemReadOnlyDiscardWrites);
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -1515,74 +2162,237 @@ impl DeviceConfig {
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct VsockConfig {
-    pub cid: u64,
+pub struct UserDeviceConfig {
     pub socket: PathBuf,
     #[serde(default)]
-    pub iommu: bool,
-    #[serde(default)]
     pub id: Option<String>,
 }
 
-impl VsockConfig {
-    pub const SYNTAX: &'static str = "Virtio VSOCK parameters \
-        \"cid=<context_id>,socket=<socket_path>,iommu=on|off,id=<device_id>\"";
-    pub fn parse(vsock: &str) -> Result<Self> {
+impl UserDeviceConfig {
+    pub const SYNTAX: &'static str =
+        "vfio-user device parameters \"socket=<socket_path>,id=<device_id>\"";
+    pub fn parse(user_device: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("socket").add("cid").add("iommu").add("id");
-        parser.parse(vsock).map_err(Error::ParseVsock)?;
+        parser.add("socket").add("id");
+        parser.parse(user_device).map_err(Error::ParseUserDevice)?;
 
         let socket = parser
             .get("socket")
             .map(PathBuf::from)
-            .ok_or(Error::ParseVsockSockMissing)?;
-        let iommu = parser
-            .convert::<Toggle>("iommu")
-            .map_err(Error::ParseVsock)?
-            .unwrap_or(Toggle(false))
-            .0;
-        let cid = parser
-            .convert("cid")
-            .map_err(Error::ParseVsock)?
-            .ok_or(Error::ParseVsockCidMissing)?;
+            .ok_or(Error::ParseUserDeviceSocketMissing)?;
         let id = parser.get("id");
-
-        Ok(VsockConfig {
-            cid,
-            socket,
-            iommu,
-            id,
-        })
+        Ok(UserDeviceConfig { socket, id })
     }
 }
 
-#[cfg(feature = "tdx")]
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct TdxConfig {
-    pub firmware: PathBuf,
+pub struct GenericVhostUserConfig {
+    pub socket: PathBuf,
+    pub virtio_id: u32,
+    pub queue_sizes: Vec<u16>,
+    #[serde(default)]
+    pub id: Option<String>,
 }
 
-#[cfg(feature = "tdx")]
-impl TdxConfig {
-    pub fn parse(tdx: &str) -> Result<Self> {
+impl GenericVhostUserConfig {
+    pub const SYNTAX: &'static str = "Generic vhost-user device parameters \
+    \"socket=<socket_path>,virtio_id=<virtio_device_type>,\
+    queue_sizes=<size_of_queue_0>:<size_of_queue_1>:...,id=<device_id>\"";
+    pub fn parse(generic_vhost_user: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("firmware");
-        parser.parse(tdx).map_err(Error::ParseTdx)?;
-        let firmware = parser
-            .get("firmware")
+        parser
+            .add("socket")
+            .add("virtio_id")
+            .add("queue_sizes")
+            .add("id");
+        parser
+            .parse(generic_vhost_user)
+            .map_err(Error::ParseGenericVhostUser)?;
+
+        let socket = parser
+            .get("socket")
             .map(PathBuf::from)
-            .ok_or(Error::FirmwarePathMissing)?;
-        Ok(TdxConfig { firmware })
+            .ok_or(Error::ParseGenericVhostUserSocketMissing)?;
+        let virtio_id = parser
+            .convert("virtio_id")
+            .map_err(Error::ParseGenericVhostUser)?
+            .ok_or(Error::ParseGenericVhostUserVirtioIdMissing)?;
+        let queue_sizes = parser
+            .convert::<IntegerList>("queue_sizes")
+            .map_err(Error::ParseGenericVhostUser)?
+            .ok_or(Error::ParseGenericVhostUserQueueSizesMissing)?
+            .0
+            .iter()
+            .map(|size| {
+                u16::try_from(*size)
+                    .map_err(|_| Error::ParseGenericVhostUserInvalidQueueSize(*size))
+            })
+            .collect::<Result<Vec<u16>>>()?;
+        let id = parser.get("id");
+
+        Ok(GenericVhostUserConfig {
+            socket,
+            virtio_id,
+            queue_sizes,
+            id,
+        })
+    }
+
+    pub fn validate(&self, _vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.queue_sizes.is_empty() {
+            return Err(ValidationError::GenericVhostUserNoQueues);
+        }
+
+        Ok(())
     }
 }
 
-#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct VsockPortForwardConfig {
+    pub host_port: u16,
+    pub vsock_port: u32,
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct SgxEpcConfig {
+pub struct VsockConfig {
+    pub cid: u64,
     #[serde(default)]
-    pub size: u64,
+    pub socket: PathBuf,
     #[serde(default)]
-    pub prefault: bool,
+    pub iommu: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub tcp_listen: Vec<VsockPortForwardConfig>,
+    #[serde(default)]
+    pub tcp_connect: Vec<VsockPortForwardConfig>,
+    #[serde(default)]
+    pub vhost: bool,
+}
+
+impl VsockConfig {
+    pub const SYNTAX: &'static str = "Virtio VSOCK parameters \
+        \"cid=<context_id>,socket=<socket_path>,iommu=on|off,id=<device_id>,\
+        tcp_listen=<host_port>@<vsock_port>:...,tcp_connect=<host_port>@<vsock_port>:...,\
+        vhost=on|off\"";
+
+    fn parse_port_forwards(
+        parser: &OptionParser,
+        option: &str,
+    ) -> Result<Vec<VsockPortForwardConfig>> {
+        let forwards = match parser
+            .convert::<TupleTwoIntegers>(option)
+            .map_err(Error::ParseVsock)?
+        {
+            Some(forwards) => forwards.0,
+            None => return Ok(Vec::new()),
+        };
+
+        forwards
+            .iter()
+            .map(|(host_port, vsock_port)| {
+                match (u16::try_from(*host_port), u32::try_from(*vsock_port)) {
+                    (Ok(host_port), Ok(vsock_port)) => Ok(VsockPortForwardConfig {
+                        host_port,
+                        vsock_port,
+                    }),
+                    _ => Err(Error::ParseVsock(OptionParserError::Conversion(
+                        option.to_owned(),
+                        format!("{}@{}", host_port, vsock_port),
+                    ))),
+                }
+            })
+            .collect()
+    }
+
+    pub fn parse(vsock: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("socket")
+            .add("cid")
+            .add("iommu")
+            .add("id")
+            .add("tcp_listen")
+            .add("tcp_connect")
+            .add("vhost");
+        parser.parse(vsock).map_err(Error::ParseVsock)?;
+
+        let vhost = parser
+            .convert::<Toggle>("vhost")
+            .map_err(Error::ParseVsock)?
+            .unwrap_or(Toggle(false))
+            .0;
+        // The host side of the connections is the host AF_VSOCK sockets
+        // with vhost-vsock, hence no socket is needed.
+        let socket = match parser.get("socket") {
+            Some(socket) => PathBuf::from(socket),
+            None if vhost => PathBuf::new(),
+            None => return Err(Error::ParseVsockSockMissing),
+        };
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseVsock)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let cid = parser
+            .convert("cid")
+            .map_err(Error::ParseVsock)?
+            .ok_or(Error::ParseVsockCidMissing)?;
+        let id = parser.get("id");
+        let tcp_listen = Self::parse_port_forwards(&parser, "tcp_listen")?;
+        let tcp_connect = Self::parse_port_forwards(&parser, "tcp_connect")?;
+
+        Ok(VsockConfig {
+            cid,
+            socket,
+            iommu,
+            id,
+            tcp_listen,
+            tcp_connect,
+            vhost,
+        })
+    }
+
+    pub fn validate(&self, _vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.vhost {
+            if self.iommu || !self.tcp_listen.is_empty() || !self.tcp_connect.is_empty() {
+                return Err(ValidationError::VsockVhostIncompatible);
+            }
+        } else if self.socket.as_os_str().is_empty() {
+            return Err(ValidationError::VsockSocketMissing);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tdx")]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct TdxConfig {
+    pub firmware: PathBuf,
+}
+
+#[cfg(feature = "tdx")]
+impl TdxConfig {
+    pub fn parse(tdx: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("firmware");
+        parser.parse(tdx).map_err(Error::ParseTdx)?;
+        let firmware = parser
+            .get("firmware")
+            .map(PathBuf::from)
+            .ok_or(Error::FirmwarePathMissing)?;
+        Ok(TdxConfig { firmware })
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct SgxEpcConfig {
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub prefault: bool,
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -1622,7 +2432,7 @@ pub struct NumaConfig {
     #[serde(default)]
     pub guest_numa_id: u32,
     #[serde(default)]
-    pub cpus: Option<Vec<u8>>,
+    pub cpus: Option<Vec<u32>>,
     #[serde(default)]
     pub distances: Option<Vec<NumaDistance>>,
     #[serde(default)]
@@ -1649,7 +2459,7 @@ impl NumaConfig {
         let cpus = parser
             .convert::<IntegerList>("cpus")
             .map_err(Error::ParseNuma)?
-            .map(|v| v.0.iter().map(|e| *e as u8).collect());
+            .map(|v| v.0.iter().map(|e| *e as u32).collect());
         let distances = parser
             .convert::<TupleTwoIntegers>("distances")
             .map_err(Error::ParseNuma)?
@@ -1675,6 +2485,61 @@ impl NumaConfig {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum LandlockAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug)]
+pub enum ParseLandlockAccessError {
+    InvalidValue(String),
+}
+
+impl FromStr for LandlockAccess {
+    type Err = ParseLandlockAccessError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "r" => Ok(LandlockAccess::Read),
+            "w" => Ok(LandlockAccess::Write),
+            "rw" => Ok(LandlockAccess::ReadWrite),
+            _ => Err(ParseLandlockAccessError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LandlockConfig {
+    pub path: PathBuf,
+    pub access: LandlockAccess,
+}
+
+impl LandlockConfig {
+    pub const SYNTAX: &'static str = "Landlock parameters \
+        \"path=<path>,access=r|w|rw\"";
+
+    pub fn parse(landlock_rule: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("path").add("access");
+        parser
+            .parse(landlock_rule)
+            .map_err(Error::ParseLandlockRules)?;
+
+        let path = parser
+            .get("path")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseLandlockRulesPathMissing)?;
+        let access = parser
+            .convert("access")
+            .map_err(Error::ParseLandlockRules)?
+            .ok_or(Error::ParseLandlockRulesAccessMissing)?;
+
+        Ok(LandlockConfig { path, access })
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
 pub struct RestoreConfig {
     pub source_url: PathBuf,
@@ -1732,6 +2597,8 @@ pub struct VmConfig {
     #[serde(default = "ConsoleConfig::default_console")]
     pub console: ConsoleConfig,
     pub devices: Option<Vec<DeviceConfig>>,
+    pub user_devices: Option<Vec<UserDeviceConfig>>,
+    pub generic_vhost_user: Option<Vec<GenericVhostUserConfig>>,
     pub vsock: Option<VsockConfig>,
     #[serde(default)]
     pub iommu: bool,
@@ -1742,6 +2609,12 @@ pub struct VmConfig {
     pub watchdog: bool,
     #[cfg(feature = "tdx")]
     pub tdx: Option<TdxConfig>,
+    #[cfg(feature = "gdb")]
+    #[serde(default)]
+    pub gdb: bool,
+    #[serde(default)]
+    pub landlock_enable: bool,
+    pub landlock_rules: Option<Vec<LandlockConfig>>,
 }
 
 impl VmConfig {
@@ -1776,9 +2649,7 @@ impl VmConfig {
             return Err(ValidationError::ConsoleFileMissing);
         }
 
-        if self.cpus.max_vcpus < self.cpus.boot_vcpus {
-            return Err(ValidationError::CpusMaxLowerThanBoot);
-        }
+        self.cpus.validate()?;
 
         if let Some(disks) = &self.disks {
             for disk in disks {
@@ -1813,6 +2684,31 @@ impl VmConfig {
             }
         }
 
+        if let Some(pmems) = &self.pmem {
+            for pmem in pmems {
+                pmem.validate(self)?;
+            }
+        }
+
+        if let Some(vsock) = &self.vsock {
+            vsock.validate(self)?;
+        }
+
+        if let Some(user_devices) = &self.user_devices {
+            if !user_devices.is_empty() && !self.memory.shared {
+                return Err(ValidationError::UserDevicesRequireSharedMemory);
+            }
+        }
+
+        if let Some(generic_vhost_user_devices) = &self.generic_vhost_user {
+            if !generic_vhost_user_devices.is_empty() && !self.memory.shared {
+                return Err(ValidationError::VhostUserRequiresSharedMemory);
+            }
+            for generic_vhost_user in generic_vhost_user_devices {
+                generic_vhost_user.validate(self)?;
+            }
+        }
+
         if let Some(t) = &self.cpus.topology {
             if t.threads_per_core == 0
                 || t.cores_per_die == 0
@@ -1822,7 +2718,10 @@ impl VmConfig {
                 return Err(ValidationError::CpuTopologyZeroPart);
             }
 
-            let total = t.threads_per_core * t.cores_per_die * t.dies_per_package * t.packages;
+            let total = u32::from(t.threads_per_core)
+                * u32::from(t.cores_per_die)
+                * u32::from(t.dies_per_package)
+                * u32::from(t.packages);
             if total != self.cpus.max_vcpus {
                 return Err(ValidationError::CpuTopologyCount);
             }
@@ -1837,6 +2736,10 @@ impl VmConfig {
             }
         }
 
+        if self.landlock_rules.is_some() && !self.landlock_enable {
+            return Err(ValidationError::LandlockRulesWithoutLandlock);
+        }
+
         Ok(())
     }
 
@@ -1920,6 +2823,26 @@ impl VmConfig {
             devices = Some(device_config_list);
         }
 
+        let mut user_devices: Option<Vec<UserDeviceConfig>> = None;
+        if let Some(user_device_list) = &vm_params.user_devices {
+            let mut user_device_config_list = Vec::new();
+            for item in user_device_list.iter() {
+                let user_device_config = UserDeviceConfig::parse(item)?;
+                user_device_config_list.push(user_device_config);
+            }
+            user_devices = Some(user_device_config_list);
+        }
+
+        let mut generic_vhost_user: Option<Vec<GenericVhostUserConfig>> = None;
+        if let Some(generic_vhost_user_list) = &vm_params.generic_vhost_user {
+            let mut generic_vhost_user_config_list = Vec::new();
+            for item in generic_vhost_user_list.iter() {
+                let generic_vhost_user_config = GenericVhostUserConfig::parse(item)?;
+                generic_vhost_user_config_list.push(generic_vhost_user_config);
+            }
+            generic_vhost_user = Some(generic_vhost_user_config_list);
+        }
+
         let mut vsock: Option<VsockConfig> = None;
         if let Some(vs) = &vm_params.vsock {
             let vsock_config = VsockConfig::parse(vs)?;
@@ -1970,6 +2893,16 @@ impl VmConfig {
         #[cfg(feature = "tdx")]
         let tdx = vm_params.tdx.map(TdxConfig::parse).transpose()?;
 
+        let mut landlock_rules: Option<Vec<LandlockConfig>> = None;
+        if let Some(landlock_rule_list) = &vm_params.landlock_rules {
+            let mut landlock_rule_config_list = Vec::new();
+            for item in landlock_rule_list.iter() {
+                let landlock_rule_config = LandlockConfig::parse(item)?;
+                landlock_rule_config_list.push(landlock_rule_config);
+            }
+            landlock_rules = Some(landlock_rule_config_list);
+        }
+
         let config = VmConfig {
             cpus: CpusConfig::parse(vm_params.cpus)?,
             memory: MemoryConfig::parse(vm_params.memory, vm_params.memory_zones)?,
@@ -1985,6 +2918,8 @@ impl VmConfig {
             serial,
             console,
             devices,
+            user_devices,
+            generic_vhost_user,
             vsock,
             iommu,
             #[cfg(target_arch = "x86_64")]
@@ -1993,6 +2928,10 @@ impl VmConfig {
             watchdog: vm_params.watchdog,
             #[cfg(feature = "tdx")]
             tdx,
+            #[cfg(feature = "gdb")]
+            gdb: vm_params.gdb,
+            landlock_enable: vm_params.landlock_enable,
+            landlock_rules,
         };
         config.validate().map_err(Error::Validation)?;
         Ok(config)
@@ -2042,6 +2981,14 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            CpusConfig::parse("boot=256,max=384")?,
+            CpusConfig {
+                boot_vcpus: 256,
+                max_vcpus: 384,
+                ..Default::default()
+            }
+        );
         assert_eq!(
             CpusConfig::parse("boot=8,topology=2:2:1:2")?,
             CpusConfig {
@@ -2068,6 +3015,60 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            CpusConfig::parse(
+                "boot=2,affinity=0@4-5:1@6:0@8,vmm_affinity=0-1:3,io_affinity=2,fifo_priority=10"
+            )?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                affinity: Some(vec![
+                    CpuAffinity {
+                        vcpu: 0,
+                        host_cpus: vec![4, 5, 8],
+                    },
+                    CpuAffinity {
+                        vcpu: 1,
+                        host_cpus: vec![6],
+                    },
+                ]),
+                vmm_affinity: Some(vec![0, 1, 3]),
+                io_affinity: Some(vec![2]),
+                fifo_priority: Some(10),
+                ..Default::default()
+            }
+        );
+        assert!(CpusConfig::parse("boot=2,affinity=0").is_err());
+        assert!(CpusConfig::parse("boot=2,affinity=0@x").is_err());
+        assert!(CpusConfig::parse("boot=2,affinity=4294967296@0").is_err());
+        assert_eq!(
+            CpusConfig::parse("boot=1,model=skylake,features=-avx512f:+rtm")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                model: Some("skylake".to_owned()),
+                features: Some(vec!["-avx512f".to_owned(), "+rtm".to_owned()]),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=1,exit_stats=on")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                exit_stats: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=1,exit_trace=/tmp/exits.log")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                exit_trace: Some(PathBuf::from("/tmp/exits.log")),
+                ..Default::default()
+            }
+        );
         Ok(())
     }
 
@@ -2300,6 +3301,75 @@ mod tests {
             }
         );
 
+        assert_eq!(
+            NetConfig::parse(
+                "mac=de:ad:be:ef:12:34,mode=user,tcp_forward=2222@22:0.0.0.0@8080@80,\
+                 udp_forward=192.168.1.1@5353@53"
+            )?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                mode: NetMode::User,
+                tcp_forward: vec![
+                    PortForwardConfig {
+                        host_addr: Ipv4Addr::LOCALHOST,
+                        host_port: 2222,
+                        guest_port: 22,
+                    },
+                    PortForwardConfig {
+                        host_addr: Ipv4Addr::UNSPECIFIED,
+                        host_port: 8080,
+                        guest_port: 80,
+                    },
+                ],
+                udp_forward: vec![PortForwardConfig {
+                    host_addr: Ipv4Addr::new(192, 168, 1, 1),
+                    host_port: 5353,
+                    guest_port: 53,
+                }],
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("tap=tap0,vhost=on,num_queues=4")?,
+            NetConfig {
+                tap: Some("tap0".to_owned()),
+                vhost: true,
+                num_queues: 4,
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("tap=tap0,addresses=192.168.1.1/24:fd00::1/64,mtu=1500")?,
+            NetConfig {
+                tap: Some("tap0".to_owned()),
+                addresses: vec![
+                    IpAddrConfig {
+                        addr: "192.168.1.1".parse().unwrap(),
+                        prefix_len: 24,
+                    },
+                    IpAddrConfig {
+                        addr: "fd00::1".parse().unwrap(),
+                        prefix_len: 64,
+                    },
+                ],
+                mtu: Some(1500),
+                ..Default::default()
+            }
+        );
+
+        assert!(NetConfig::parse("mode=slirp").is_err());
+        assert!(NetConfig::parse("mode=user,tcp_forward=65536@22").is_err());
+        assert!(NetConfig::parse("mode=user,tcp_forward=2222").is_err());
+        assert!(NetConfig::parse("mode=user,tcp_forward=::1@2222@22").is_err());
+        assert!(NetConfig::parse("mode=user,tcp_forward=localhost@2222@22").is_err());
+        assert!(NetConfig::parse("mode=user,tcp_forward=1@2@3@4").is_err());
+        assert!(NetConfig::parse("addresses=fd00::1").is_err());
+        assert!(NetConfig::parse("addresses=fd00::1/64:").is_ok());
+        assert!(NetConfig::parse("addresses=fd00::1/64:10.0.0.1").is_err());
+        assert!(NetConfig::parse("addresses=fd00::1/x64").is_err());
+
         Ok(())
     }
 
@@ -2421,6 +3491,14 @@ mod tests {
                 ..Default::default()
             }
         );
+        assert_eq!(
+            PmemConfig::parse("file=/tmp/pmem,readonly=on")?,
+            PmemConfig {
+                file: PathBuf::from("/tmp/pmem"),
+                readonly: true,
+                ..Default::default()
+            }
+        );
 
         Ok(())
     }
@@ -2522,6 +3600,65 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_user_device_parsing() -> Result<()> {
+        // User device must have a socket provided
+        assert!(UserDeviceConfig::parse("").is_err());
+        assert!(UserDeviceConfig::parse("id=mydevice0").is_err());
+        assert_eq!(
+            UserDeviceConfig::parse("socket=/tmp/vfio-user.sock")?,
+            UserDeviceConfig {
+                socket: PathBuf::from("/tmp/vfio-user.sock"),
+                id: None,
+            }
+        );
+        assert_eq!(
+            UserDeviceConfig::parse("socket=/tmp/vfio-user.sock,id=mydevice0")?,
+            UserDeviceConfig {
+                socket: PathBuf::from("/tmp/vfio-user.sock"),
+                id: Some("mydevice0".to_owned()),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generic_vhost_user_parsing() -> Result<()> {
+        // Socket, virtio device type and queue sizes are required
+        assert!(GenericVhostUserConfig::parse("").is_err());
+        assert!(GenericVhostUserConfig::parse("virtio_id=4,queue_sizes=256").is_err());
+        assert!(GenericVhostUserConfig::parse("socket=/tmp/sock,queue_sizes=256").is_err());
+        assert!(GenericVhostUserConfig::parse("socket=/tmp/sock,virtio_id=4").is_err());
+        // Queue sizes must fit on 16 bits
+        assert!(
+            GenericVhostUserConfig::parse("socket=/tmp/sock,virtio_id=4,queue_sizes=65536")
+                .is_err()
+        );
+        assert_eq!(
+            GenericVhostUserConfig::parse("socket=/tmp/sock,virtio_id=4,queue_sizes=1024")?,
+            GenericVhostUserConfig {
+                socket: PathBuf::from("/tmp/sock"),
+                virtio_id: 4,
+                queue_sizes: vec![1024],
+                id: None,
+            }
+        );
+        assert_eq!(
+            GenericVhostUserConfig::parse(
+                "socket=/tmp/sock,virtio_id=19,queue_sizes=128:128:64,id=myvsock0"
+            )?,
+            GenericVhostUserConfig {
+                socket: PathBuf::from("/tmp/sock"),
+                virtio_id: 19,
+                queue_sizes: vec![128, 128, 64],
+                id: Some("myvsock0".to_owned()),
+            }
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_vsock_parsing() -> Result<()> {
         // socket and cid is required
@@ -2533,6 +3670,7 @@ mod tests {
                 socket: PathBuf::from("/tmp/sock"),
                 iommu: false,
                 id: None,
+                ..Default::default()
             }
         );
         assert_eq!(
@@ -2542,6 +3680,67 @@ mod tests {
                 socket: PathBuf::from("/tmp/sock"),
                 iommu: true,
                 id: None,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            VsockConfig::parse(
+                "socket=/tmp/sock,cid=1,tcp_listen=2222@22:8080@80,tcp_connect=5000@1234"
+            )?,
+            VsockConfig {
+                cid: 1,
+                socket: PathBuf::from("/tmp/sock"),
+                tcp_listen: vec![
+                    VsockPortForwardConfig {
+                        host_port: 2222,
+                        vsock_port: 22,
+                    },
+                    VsockPortForwardConfig {
+                        host_port: 8080,
+                        vsock_port: 80,
+                    },
+                ],
+                tcp_connect: vec![VsockPortForwardConfig {
+                    host_port: 5000,
+                    vsock_port: 1234,
+                }],
+                ..Default::default()
+            }
+        );
+        assert!(VsockConfig::parse("socket=/tmp/sock,cid=1,tcp_listen=65536@22").is_err());
+        assert!(VsockConfig::parse("socket=/tmp/sock,cid=1,tcp_connect=22").is_err());
+        // No socket is needed with vhost-vsock
+        assert!(VsockConfig::parse("cid=1").is_err());
+        assert_eq!(
+            VsockConfig::parse("cid=3,vhost=on")?,
+            VsockConfig {
+                cid: 3,
+                vhost: true,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_landlock_rules_parsing() -> Result<()> {
+        // path and access are required
+        assert!(LandlockConfig::parse("").is_err());
+        assert!(LandlockConfig::parse("path=/images").is_err());
+        assert!(LandlockConfig::parse("access=rw").is_err());
+        assert!(LandlockConfig::parse("path=/images,access=x").is_err());
+        assert_eq!(
+            LandlockConfig::parse("path=/images,access=r")?,
+            LandlockConfig {
+                path: PathBuf::from("/images"),
+                access: LandlockAccess::Read,
+            }
+        );
+        assert_eq!(
+            LandlockConfig::parse("path=/run/ch,access=rw")?,
+            LandlockConfig {
+                path: PathBuf::from("/run/ch"),
+                access: LandlockAccess::ReadWrite,
             }
         );
         Ok(())
@@ -2593,6 +3792,8 @@ mod tests {
                 iommu: false,
             },
             devices: None,
+            user_devices: None,
+            generic_vhost_user: None,
             vsock: None,
             iommu: false,
             #[cfg(target_arch = "x86_64")]
@@ -2601,6 +3802,10 @@ mod tests {
             watchdog: false,
             #[cfg(feature = "tdx")]
             tdx: None,
+            #[cfg(feature = "gdb")]
+            gdb: false,
+            landlock_enable: false,
+            landlock_rules: None,
         };
 
         assert!(valid_config.validate().is_ok());
@@ -2624,6 +3829,14 @@ mod tests {
         invalid_config.cpus.boot_vcpus = 32;
         assert!(invalid_config.validate().is_err());
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.max_vcpus = MAX_VCPUS + 1;
+        assert!(invalid_config.validate().is_err());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.cpus.max_vcpus = MAX_VCPUS;
+        assert!(still_valid_config.validate().is_ok());
+
         let mut invalid_config = valid_config.clone();
         invalid_config.cpus.max_vcpus = 16;
         invalid_config.cpus.boot_vcpus = 16;
@@ -2635,6 +3848,88 @@ mod tests {
         });
         assert!(invalid_config.validate().is_err());
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.cpus.max_vcpus = 384;
+        still_valid_config.cpus.boot_vcpus = 384;
+        still_valid_config.cpus.topology = Some(CpuTopology {
+            threads_per_core: 2,
+            cores_per_die: 96,
+            dies_per_package: 1,
+            packages: 2,
+        });
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.affinity = Some(vec![CpuAffinity {
+            vcpu: 1,
+            host_cpus: vec![0],
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.affinity = Some(vec![CpuAffinity {
+            vcpu: 0,
+            host_cpus: Vec::new(),
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.affinity = Some(vec![CpuAffinity {
+            vcpu: 0,
+            host_cpus: vec![libc::CPU_SETSIZE as usize],
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.vmm_affinity = Some(Vec::new());
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.vmm_affinity = Some(vec![0, libc::CPU_SETSIZE as usize]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.io_affinity = Some(Vec::new());
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.io_affinity = Some(vec![libc::CPU_SETSIZE as usize]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.fifo_priority = Some(100);
+        assert!(invalid_config.validate().is_err());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.cpus.affinity = Some(vec![CpuAffinity {
+            vcpu: 0,
+            host_cpus: vec![2, 3],
+        }]);
+        still_valid_config.cpus.vmm_affinity = Some(vec![0, 1]);
+        still_valid_config.cpus.io_affinity = Some(vec![4, 5]);
+        still_valid_config.cpus.fifo_priority = Some(1);
+        assert!(still_valid_config.validate().is_ok());
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.model = Some("pentium".to_owned());
+            assert!(invalid_config.validate().is_err());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.features = Some(vec!["avx2".to_owned()]);
+            assert!(invalid_config.validate().is_err());
+
+            let mut invalid_config = valid_config.clone();
+            invalid_config.cpus.features = Some(vec!["-avx1024".to_owned()]);
+            assert!(invalid_config.validate().is_err());
+
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.cpus.model = Some("haswell".to_owned());
+            still_valid_config.cpus.features = Some(vec!["-avx2".to_owned(), "+rdseed".to_owned()]);
+            assert!(still_valid_config.validate().is_ok());
+        }
+
         let mut invalid_config = valid_config.clone();
         invalid_config.disks = Some(vec![DiskConfig {
             vhost_socket: Some("/path/to/sock".to_owned()),
@@ -2690,6 +3985,107 @@ mod tests {
         }]);
         assert!(invalid_config.validate().is_err());
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            mode: NetMode::User,
+            tap: Some("tap0".to_owned()),
+            ..Default::default()
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            tcp_forward: vec![PortForwardConfig {
+                host_addr: Ipv4Addr::LOCALHOST,
+                host_port: 2222,
+                guest_port: 22,
+            }],
+            ..Default::default()
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            mode: NetMode::User,
+            tcp_forward: vec![PortForwardConfig {
+                host_addr: Ipv4Addr::LOCALHOST,
+                host_port: 2222,
+                guest_port: 22,
+            }],
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            vhost: true,
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vhost: true,
+            rate_limiter_config: Some(RateLimiterConfig {
+                bandwidth: Some(TokenBucketConfig {
+                    size: 1000,
+                    one_time_burst: None,
+                    refill_time: 100,
+                }),
+                ops: None,
+            }),
+            ..Default::default()
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vhost: true,
+            mode: NetMode::User,
+            ..Default::default()
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            addresses: vec![IpAddrConfig {
+                addr: "fd00::1".parse().unwrap(),
+                prefix_len: 128,
+            }],
+            mtu: Some(1280),
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            addresses: vec![IpAddrConfig {
+                addr: "10.0.0.1".parse().unwrap(),
+                prefix_len: 33,
+            }],
+            ..Default::default()
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            addresses: vec![IpAddrConfig {
+                addr: "fd00::1".parse().unwrap(),
+                prefix_len: 64,
+            }],
+            mtu: Some(1000),
+            ..Default::default()
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            mode: NetMode::User,
+            mtu: Some(1500),
+            ..Default::default()
+        }]);
+        assert!(invalid_config.validate().is_err());
+
         let mut invalid_config = valid_config.clone();
         invalid_config.fs = Some(vec![FsConfig {
             ..Default::default()
@@ -2714,9 +4110,90 @@ mod tests {
         invalid_config.memory.hugepage_size = Some(2 << 20);
         assert!(invalid_config.validate().is_err());
 
-        let mut invalid_config = valid_config;
+        let mut invalid_config = valid_config.clone();
         invalid_config.memory.hugepages = true;
         invalid_config.memory.hugepage_size = Some(3 << 20);
         assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.landlock_rules = Some(vec![LandlockConfig {
+            path: PathBuf::from("/images"),
+            access: LandlockAccess::Read,
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut still_valid_config = invalid_config.clone();
+        still_valid_config.landlock_enable = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.user_devices = Some(vec![UserDeviceConfig {
+            socket: PathBuf::from("/tmp/vfio-user.sock"),
+            id: None,
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut still_valid_config = invalid_config;
+        still_valid_config.memory.shared = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.generic_vhost_user = Some(vec![GenericVhostUserConfig {
+            socket: PathBuf::from("/tmp/vhost-user-rng.sock"),
+            virtio_id: 4,
+            queue_sizes: vec![256],
+            id: None,
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut still_valid_config = invalid_config;
+        still_valid_config.memory.shared = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = still_valid_config;
+        invalid_config.generic_vhost_user = Some(vec![GenericVhostUserConfig {
+            socket: PathBuf::from("/tmp/vhost-user-rng.sock"),
+            virtio_id: 4,
+            queue_sizes: Vec::new(),
+            id: None,
+        }]);
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.vsock = Some(VsockConfig {
+            cid: 3,
+            vhost: true,
+            tcp_listen: vec![VsockPortForwardConfig {
+                host_port: 2222,
+                vsock_port: 22,
+            }],
+            ..Default::default()
+        });
+        assert!(invalid_config.validate().is_err());
+
+        let mut still_valid_config = invalid_config;
+        still_valid_config
+            .vsock
+            .as_mut()
+            .unwrap()
+            .tcp_listen
+            .clear();
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.vsock = Some(VsockConfig {
+            cid: 3,
+            ..Default::default()
+        });
+        assert!(invalid_config.validate().is_err());
+
+        let mut invalid_config = valid_config;
+        invalid_config.pmem = Some(vec![PmemConfig {
+            file: PathBuf::from("/path/to/pmem"),
+            readonly: true,
+            discard_writes: true,
+            ..Default::default()
+        }]);
+        assert!(invalid_config.validate().is_err());
     }
 }
@@ -0,0 +1,402 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! ELF core file generation, for post-mortem analysis of the guest with
+//! standard tools such as `crash` or `gdb`.
+//!
+//! The layout follows the one of the QEMU `dump-guest-memory` command: each
+//! RAM region is described by a `PT_LOAD` segment, identity mapping its guest
+//! physical addresses, while the vCPUs registers are stored in a `PT_NOTE`
+//! segment, as one `NT_PRSTATUS` note and one `QEMU` note per vCPU.
+
+use hypervisor::CpuState;
+use std::io::{self, Write};
+use std::result;
+use thiserror::Error;
+use vm_memory::{
+    Address, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap, GuestMemoryRegion,
+};
+
+const ELF_HEADER_SIZE: u16 = 64;
+const ELF_PROGRAM_HEADER_SIZE: u16 = 56;
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_CORE: u16 = 4;
+#[cfg(target_arch = "x86_64")]
+const ELF_MACHINE: u16 = 62; // EM_X86_64
+#[cfg(target_arch = "aarch64")]
+const ELF_MACHINE: u16 = 183; // EM_AARCH64
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+// Type of the notes carrying the QEMUCPUState structure.
+const NT_QEMU: u32 = 0;
+
+const CORE_NOTE_NAME: &str = "CORE";
+const QEMU_NOTE_NAME: &str = "QEMU";
+
+// Version of the QEMUCPUState structure understood by crash.
+#[cfg(target_arch = "x86_64")]
+const QEMU_CPU_STATE_VERSION: u32 = 1;
+
+/// Errors associated with the coredump generation
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Cannot write the coredump file
+    #[error("Error writing coredump: {0}")]
+    Write(#[source] io::Error),
+
+    /// Cannot read the guest memory
+    #[error("Error reading guest memory: {0}")]
+    GuestMemory(#[source] GuestMemoryError),
+
+    /// The vCPUs registers cannot be dumped on this architecture
+    #[error("Coredump is not supported on this architecture")]
+    UnsupportedArchitecture,
+}
+pub type Result<T> = result::Result<T, Error>;
+
+/// Check the vCPUs registers can be dumped on this architecture, before
+/// creating the core file.
+pub fn check_architecture() -> Result<()> {
+    if cfg!(target_arch = "x86_64") {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedArchitecture)
+    }
+}
+
+/// Write an ELF core file, made of the registers of each vCPU, as saved
+/// when pausing them, and of the whole guest RAM.
+pub fn write_coredump<W: Write>(
+    writer: &mut W,
+    cpu_states: &[CpuState],
+    guest_memory: &GuestMemoryMmap,
+) -> Result<()> {
+    let notes = cpu_notes(cpu_states)?;
+    let regions: Vec<(u64, u64)> = guest_memory
+        .iter()
+        .map(|region| (region.start_addr().raw_value(), region.len()))
+        .collect();
+
+    let phnum = regions.len() + 1;
+    let notes_offset = ELF_HEADER_SIZE as u64 + phnum as u64 * ELF_PROGRAM_HEADER_SIZE as u64;
+
+    let mut headers = elf_header(phnum as u16);
+    headers.extend(program_header(
+        PT_NOTE,
+        0,
+        notes_offset,
+        0,
+        notes.len() as u64,
+    ));
+    let mut offset = notes_offset + notes.len() as u64;
+    for (start, len) in regions.iter() {
+        headers.extend(program_header(
+            PT_LOAD,
+            PF_R | PF_W | PF_X,
+            offset,
+            *start,
+            *len,
+        ));
+        offset += len;
+    }
+
+    writer.write_all(&headers).map_err(Error::Write)?;
+    writer.write_all(&notes).map_err(Error::Write)?;
+    for (start, len) in regions {
+        guest_memory
+            .write_all_to(GuestAddress(start), writer, len as usize)
+            .map_err(Error::GuestMemory)?;
+    }
+    writer.flush().map_err(Error::Write)
+}
+
+fn elf_header(phnum: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(ELF_HEADER_SIZE as usize);
+
+    // e_ident
+    header.extend_from_slice(b"\x7fELF");
+    header.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, EV_CURRENT]);
+    header.resize(16, 0);
+
+    header.extend_from_slice(&ET_CORE.to_le_bytes());
+    header.extend_from_slice(&ELF_MACHINE.to_le_bytes());
+    header.extend_from_slice(&(EV_CURRENT as u32).to_le_bytes());
+    // e_entry
+    header.extend_from_slice(&0u64.to_le_bytes());
+    // e_phoff
+    header.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes());
+    // e_shoff
+    header.extend_from_slice(&0u64.to_le_bytes());
+    // e_flags
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&ELF_PROGRAM_HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&phnum.to_le_bytes());
+    // e_shentsize, e_shnum and e_shstrndx, as there is no section header
+    header.extend_from_slice(&[0u8; 6]);
+
+    header
+}
+
+fn program_header(p_type: u32, flags: u32, offset: u64, addr: u64, size: u64) -> Vec<u8> {
+    let mut header = Vec::with_capacity(ELF_PROGRAM_HEADER_SIZE as usize);
+
+    header.extend_from_slice(&p_type.to_le_bytes());
+    header.extend_from_slice(&flags.to_le_bytes());
+    header.extend_from_slice(&offset.to_le_bytes());
+    // p_vaddr and p_paddr
+    header.extend_from_slice(&addr.to_le_bytes());
+    header.extend_from_slice(&addr.to_le_bytes());
+    // p_filesz and p_memsz
+    header.extend_from_slice(&size.to_le_bytes());
+    header.extend_from_slice(&size.to_le_bytes());
+    // p_align
+    header.extend_from_slice(&0u64.to_le_bytes());
+
+    header
+}
+
+// Both the name and the descriptor of a note are padded to 4 bytes.
+fn note(name: &str, note_type: u32, desc: &[u8]) -> Vec<u8> {
+    let namesz = name.len() + 1;
+    let mut note = Vec::with_capacity(12 + align4(namesz) + align4(desc.len()));
+
+    note.extend_from_slice(&(namesz as u32).to_le_bytes());
+    note.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    note.extend_from_slice(&note_type.to_le_bytes());
+    note.extend_from_slice(name.as_bytes());
+    note.resize(12 + align4(namesz), 0);
+    note.extend_from_slice(desc);
+    note.resize(12 + align4(namesz) + align4(desc.len()), 0);
+
+    note
+}
+
+fn align4(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cpu_notes(cpu_states: &[CpuState]) -> Result<Vec<u8>> {
+    let mut notes = Vec::new();
+
+    // All the NT_PRSTATUS notes come first, as gdb expects, the QEMU notes
+    // only being relevant to crash.
+    for (cpu_id, state) in cpu_states.iter().enumerate() {
+        notes.extend(note(
+            CORE_NOTE_NAME,
+            NT_PRSTATUS,
+            &x86_64::prstatus(cpu_id as u32, state),
+        ));
+    }
+    for state in cpu_states.iter() {
+        notes.extend(note(
+            QEMU_NOTE_NAME,
+            NT_QEMU,
+            &x86_64::qemu_cpu_state(state),
+        ));
+    }
+
+    Ok(notes)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn cpu_notes(_cpu_states: &[CpuState]) -> Result<Vec<u8>> {
+    Err(Error::UnsupportedArchitecture)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+    use super::QEMU_CPU_STATE_VERSION;
+    use hypervisor::arch::x86::SegmentRegisterOps;
+    use hypervisor::x86_64::SegmentRegister;
+    use hypervisor::CpuState;
+
+    // Size of the x86_64 struct elf_prstatus.
+    const PRSTATUS_SIZE: usize = 336;
+    // Offset of pr_pid in struct elf_prstatus.
+    const PRSTATUS_PID_OFFSET: usize = 32;
+    // Offset of pr_reg in struct elf_prstatus.
+    const PRSTATUS_REG_OFFSET: usize = 112;
+
+    // Size of the QEMUCPUState structure, without the kernel_gs_base field
+    // introduced by later versions of QEMU.
+    const QEMU_CPU_STATE_SIZE: usize = 8 + 18 * 8 + 10 * 24 + 5 * 8;
+
+    /// Build the struct elf_prstatus of a vCPU, pr_reg holding its registers
+    /// in the struct user_regs_struct order.
+    pub fn prstatus(cpu_id: u32, state: &CpuState) -> Vec<u8> {
+        let regs = &state.regs;
+        let sregs = &state.sregs;
+        let mut prstatus = vec![0u8; PRSTATUS_REG_OFFSET];
+
+        // Like QEMU, vCPUs are reported as processes numbered from 1.
+        prstatus[PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + 4]
+            .copy_from_slice(&(cpu_id + 1).to_le_bytes());
+
+        for reg in [
+            regs.r15,
+            regs.r14,
+            regs.r13,
+            regs.r12,
+            regs.rbp,
+            regs.rbx,
+            regs.r11,
+            regs.r10,
+            regs.r9,
+            regs.r8,
+            regs.rax,
+            regs.rcx,
+            regs.rdx,
+            regs.rsi,
+            regs.rdi,
+            // orig_rax
+            0,
+            regs.rip,
+            sregs.cs.selector as u64,
+            regs.rflags,
+            regs.rsp,
+            sregs.ss.selector as u64,
+            sregs.fs.base,
+            sregs.gs.base,
+            sregs.ds.selector as u64,
+            sregs.es.selector as u64,
+            sregs.fs.selector as u64,
+            sregs.gs.selector as u64,
+        ]
+        .iter()
+        {
+            prstatus.extend_from_slice(&reg.to_le_bytes());
+        }
+        // pr_fpvalid and padding
+        prstatus.resize(PRSTATUS_SIZE, 0);
+
+        prstatus
+    }
+
+    /// Build the QEMUCPUState structure of a vCPU, which crash relies on to
+    /// find out the control registers and the descriptor tables.
+    pub fn qemu_cpu_state(state: &CpuState) -> Vec<u8> {
+        let regs = &state.regs;
+        let sregs = &state.sregs;
+        let mut cpu_state = Vec::with_capacity(QEMU_CPU_STATE_SIZE);
+
+        cpu_state.extend_from_slice(&QEMU_CPU_STATE_VERSION.to_le_bytes());
+        cpu_state.extend_from_slice(&(QEMU_CPU_STATE_SIZE as u32).to_le_bytes());
+        for reg in [
+            regs.rax,
+            regs.rbx,
+            regs.rcx,
+            regs.rdx,
+            regs.rsi,
+            regs.rdi,
+            regs.rsp,
+            regs.rbp,
+            regs.r8,
+            regs.r9,
+            regs.r10,
+            regs.r11,
+            regs.r12,
+            regs.r13,
+            regs.r14,
+            regs.r15,
+            regs.rip,
+            regs.rflags,
+        ]
+        .iter()
+        {
+            cpu_state.extend_from_slice(&reg.to_le_bytes());
+        }
+        for segment in [
+            &sregs.cs, &sregs.ds, &sregs.es, &sregs.fs, &sregs.gs, &sregs.ss, &sregs.ldt, &sregs.tr,
+        ]
+        .iter()
+        {
+            cpu_state.extend(qemu_segment(
+                segment.selector,
+                segment.limit,
+                segment_flags(segment),
+                segment.base,
+            ));
+        }
+        for table in [&sregs.gdt, &sregs.idt].iter() {
+            cpu_state.extend(qemu_segment(0, table.limit as u32, 0, table.base));
+        }
+        for cr in [sregs.cr0, 0, sregs.cr2, sregs.cr3, sregs.cr4].iter() {
+            cpu_state.extend_from_slice(&cr.to_le_bytes());
+        }
+
+        cpu_state
+    }
+
+    fn qemu_segment(selector: u16, limit: u32, flags: u32, base: u64) -> Vec<u8> {
+        let mut segment = Vec::with_capacity(24);
+
+        segment.extend_from_slice(&(selector as u32).to_le_bytes());
+        segment.extend_from_slice(&limit.to_le_bytes());
+        segment.extend_from_slice(&flags.to_le_bytes());
+        // Padding
+        segment.extend_from_slice(&0u32.to_le_bytes());
+        segment.extend_from_slice(&base.to_le_bytes());
+
+        segment
+    }
+
+    // Segment attributes, laid out as in the high dword of the descriptor.
+    fn segment_flags(segment: &SegmentRegister) -> u32 {
+        (segment.segment_type() as u32) << 8
+            | (segment.desc_type() as u32) << 12
+            | (segment.dpl() as u32) << 13
+            | (segment.present() as u32) << 15
+            | (segment.avl() as u32) << 20
+            | (segment.long() as u32) << 21
+            | (segment.db() as u32) << 22
+            | (segment.granularity() as u32) << 23
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elf_headers() {
+        let header = elf_header(3);
+        assert_eq!(header.len(), ELF_HEADER_SIZE as usize);
+        assert_eq!(&header[0..4], b"\x7fELF");
+        assert_eq!(header[4], ELFCLASS64);
+        assert_eq!(u16::from_le_bytes([header[16], header[17]]), ET_CORE);
+        assert_eq!(u16::from_le_bytes([header[56], header[57]]), 3);
+
+        let header = program_header(PT_LOAD, PF_R, 0x1000, 0x10_0000, 0x2000);
+        assert_eq!(header.len(), ELF_PROGRAM_HEADER_SIZE as usize);
+        assert_eq!(&header[0..4], &PT_LOAD.to_le_bytes());
+        assert_eq!(&header[8..16], &0x1000u64.to_le_bytes());
+        assert_eq!(&header[16..24], &0x10_0000u64.to_le_bytes());
+        assert_eq!(&header[24..32], &0x10_0000u64.to_le_bytes());
+        assert_eq!(&header[40..48], &0x2000u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_note() {
+        let n = note(CORE_NOTE_NAME, NT_PRSTATUS, &[1, 2, 3, 4, 5]);
+        // 12 bytes of header, "CORE\0" padded to 8 and 5 bytes padded to 8
+        assert_eq!(n.len(), 28);
+        assert_eq!(&n[0..4], &5u32.to_le_bytes());
+        assert_eq!(&n[4..8], &5u32.to_le_bytes());
+        assert_eq!(&n[8..12], &NT_PRSTATUS.to_le_bytes());
+        assert_eq!(&n[12..20], b"CORE\0\0\0\0");
+        assert_eq!(&n[20..28], &[1, 2, 3, 4, 5, 0, 0, 0]);
+    }
+}
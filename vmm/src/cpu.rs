@@ -11,10 +11,11 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
-#[cfg(target_arch = "x86_64")]
-use crate::config::CpuTopology;
-use crate::config::CpusConfig;
+use crate::config::{CpuAffinity, CpusConfig};
 use crate::device_manager::DeviceManager;
+use crate::exit_stats::{ExitTracer, VcpuExit, VcpuExitCounters, VcpuExitOps, VcpuExitStats};
+#[cfg(feature = "gdb")]
+use crate::gdb::CoreRegs;
 use crate::memory_manager::MemoryManager;
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 #[cfg(target_arch = "x86_64")]
@@ -31,18 +32,25 @@ use arch::EntryPoint;
 use devices::interrupt_controller::InterruptController;
 #[cfg(target_arch = "aarch64")]
 use hypervisor::kvm::kvm_bindings;
+#[cfg(feature = "gdb")]
+use hypervisor::x86_64::StandardRegisters;
 use hypervisor::{vm::VmmOps, CpuState, HypervisorCpuError, VmExit};
 #[cfg(target_arch = "x86_64")]
 use hypervisor::{CpuId, CpuIdEntry};
 use libc::{c_void, siginfo_t};
 use seccomp::{SeccompAction, SeccompFilter};
+use std::collections::BTreeMap;
 use std::os::unix::thread::JoinHandleExt;
+#[cfg(feature = "gdb")]
+use std::sync::atomic::AtomicU32;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Barrier, Mutex};
+use std::sync::{mpsc, Arc, Barrier, Mutex};
 use std::{cmp, io, result, thread};
-use vm_device::BusDevice;
-#[cfg(feature = "acpi")]
+use vm_device::{Bus, BusDevice};
+#[cfg(any(feature = "acpi", feature = "gdb"))]
 use vm_memory::GuestAddress;
+#[cfg(feature = "gdb")]
+use vm_memory::{Bytes, GuestAddressSpace};
 use vm_memory::{GuestMemoryAtomic, GuestMemoryMmap};
 use vm_migration::{
     Migratable, MigratableError, Pausable, Snapshot, SnapshotDataSection, Snapshottable,
@@ -62,10 +70,21 @@ const MTRR_EDX_BIT: u8 = 12; // Hypervisor ecx bit.
 // KVM feature bits
 #[cfg(target_arch = "x86_64")]
 const KVM_FEATURE_ASYNC_PF_INT_BIT: u8 = 14;
+#[cfg(target_arch = "x86_64")]
+const KVM_FEATURE_MSI_EXT_DEST_ID_BIT: u8 = 15;
+
+// APIC ID 0xff is the xAPIC broadcast address, vCPUs from this one onwards
+// can only be addressed through their x2APIC ID.
+#[cfg(any(target_arch = "x86_64", feature = "acpi"))]
+const MAX_XAPIC_ID: u32 = 0xfe;
 
 #[cfg(feature = "acpi")]
 pub const CPU_MANAGER_ACPI_SIZE: usize = 0xc;
 
+// Guest virtual to physical translations are done one page at a time.
+#[cfg(feature = "gdb")]
+const GUEST_PAGE_SIZE: u64 = 0x1000;
+
 #[derive(Debug)]
 pub enum Error {
     /// Cannot create the vCPU.
@@ -127,11 +146,140 @@ pub enum Error {
     #[cfg(target_arch = "x86_64")]
     CpuidIdentification(vmm_sys_util::fam::Error),
 
+    /// Error masking CPUID according to the CPU model
+    #[cfg(target_arch = "x86_64")]
+    CpuModel(arch::x86_64::Error),
+
+    /// Error enabling 32-bit x2APIC IDs
+    #[cfg(target_arch = "x86_64")]
+    EnableX2ApicApi(hypervisor::HypervisorVmError),
+
     #[cfg(feature = "tdx")]
     InitializeTdx(hypervisor::HypervisorCpuError),
+
+    /// Cannot set the host CPU affinity of a vCPU thread
+    VcpuAffinity(io::Error),
+
+    /// Cannot set the scheduling policy of a vCPU thread
+    VcpuSchedPolicy(io::Error),
+
+    /// CPU affinity given for a vCPU beyond max
+    InvalidAffinityVcpu(u32),
+
+    /// CPU affinity without any host CPU
+    EmptyAffinity(u32),
+
+    /// Debug request for a vCPU that does not exist
+    #[cfg(feature = "gdb")]
+    InvalidDebugVcpu(u32),
+
+    /// Debug request while the vCPUs are running
+    #[cfg(feature = "gdb")]
+    VcpusNotStopped,
+
+    /// Error accessing the vCPU registers for debugging
+    #[cfg(feature = "gdb")]
+    DebugRegisters(hypervisor::HypervisorCpuError),
+
+    /// Error translating a guest virtual address for debugging
+    #[cfg(feature = "gdb")]
+    TranslateVirtualAddress(hypervisor::HypervisorCpuError),
+
+    /// Error accessing guest memory for debugging
+    #[cfg(feature = "gdb")]
+    DebugMemory(vm_memory::GuestMemoryError),
+
+    /// Error setting up guest debugging on a vCPU
+    #[cfg(feature = "gdb")]
+    SetGuestDebug(hypervisor::HypervisorCpuError),
+
+    /// Cannot notify the VMM thread of a debug stop
+    #[cfg(feature = "gdb")]
+    DebugEvent(io::Error),
+
+    /// Cannot create the vCPU exit trace file
+    ExitTraceFile(io::Error),
 }
 pub type Result<T> = result::Result<T, Error>;
 
+/// Restrict `thread` to the given host CPUs.
+pub fn set_thread_affinity(thread: libc::pthread_t, host_cpus: &[usize]) -> io::Result<()> {
+    let mut cpuset: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for host_cpu in host_cpus {
+        if *host_cpu >= libc::CPU_SETSIZE as usize {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        unsafe { libc::CPU_SET(*host_cpu, &mut cpuset) };
+    }
+
+    let ret = unsafe {
+        libc::pthread_setaffinity_np(thread, std::mem::size_of::<libc::cpu_set_t>(), &cpuset)
+    };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+
+    Ok(())
+}
+
+/// Host CPUs the process started with, as the affinity of its main thread is
+/// never changed.
+pub fn default_host_cpus() -> io::Result<Vec<usize>> {
+    let mut cpuset: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        libc::sched_getaffinity(
+            libc::getpid(),
+            std::mem::size_of::<libc::cpu_set_t>(),
+            &mut cpuset,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((0..libc::CPU_SETSIZE as usize)
+        .filter(|host_cpu| unsafe { libc::CPU_ISSET(*host_cpu, &cpuset) })
+        .collect())
+}
+
+/// Restrict the calling thread to the VMM host CPUs. As the VMM thread
+/// outlives the VM, its affinity is reset to the default host CPUs if none is
+/// specified, in case it had been pinned by a previous VM.
+pub fn set_vmm_thread_affinity(vmm_affinity: &Option<Vec<usize>>) -> io::Result<()> {
+    let host_cpus = match vmm_affinity {
+        Some(host_cpus) => host_cpus.clone(),
+        None => default_host_cpus()?,
+    };
+
+    // Safe because pthread_self() has no precondition and always succeeds.
+    set_thread_affinity(unsafe { libc::pthread_self() }, &host_cpus)
+}
+
+// Restrict the calling vCPU thread to the given host CPUs and switch it to
+// the SCHED_FIFO policy at the given priority, when specified.
+fn set_vcpu_thread_scheduling(
+    host_cpus: Option<&[usize]>,
+    fifo_priority: Option<u8>,
+) -> Result<()> {
+    // Safe because pthread_self() has no precondition and always succeeds.
+    let thread = unsafe { libc::pthread_self() };
+
+    if let Some(host_cpus) = host_cpus {
+        set_thread_affinity(thread, host_cpus).map_err(Error::VcpuAffinity)?;
+    }
+
+    if let Some(priority) = fifo_priority {
+        let mut param: libc::sched_param = unsafe { std::mem::zeroed() };
+        param.sched_priority = i32::from(priority);
+        let ret = unsafe { libc::pthread_setschedparam(thread, libc::SCHED_FIFO, &param) };
+        if ret != 0 {
+            return Err(Error::VcpuSchedPolicy(io::Error::from_raw_os_error(ret)));
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "acpi")]
 #[repr(packed)]
 struct LocalApic {
@@ -142,6 +290,49 @@ struct LocalApic {
     pub flags: u32,
 }
 
+#[cfg(feature = "acpi")]
+#[repr(packed)]
+#[derive(Default)]
+struct LocalX2Apic {
+    pub r#type: u8,
+    pub length: u8,
+    _reserved: u16,
+    pub x2apic_id: u32,
+    pub flags: u32,
+    pub processor_uid: u32,
+}
+
+// Appends the local APIC structures of the vCPUs to the MADT, the ones whose
+// IDs don't fit in a legacy local APIC structure being described as x2APIC.
+#[cfg(all(feature = "acpi", target_arch = "x86_64"))]
+fn append_madt_cpus(madt: &mut Sdt, boot_vcpus: u32, max_vcpus: u32) {
+    for cpu in 0..max_vcpus {
+        let flags = if cpu < boot_vcpus {
+            1 << MADT_CPU_ENABLE_FLAG
+        } else {
+            0
+        };
+        if cpu <= MAX_XAPIC_ID {
+            madt.append(LocalApic {
+                r#type: 0,
+                length: 8,
+                processor_id: cpu as u8,
+                apic_id: cpu as u8,
+                flags,
+            });
+        } else {
+            madt.append(LocalX2Apic {
+                r#type: 9,
+                length: 16,
+                x2apic_id: cpu,
+                flags,
+                processor_uid: cpu,
+                ..Default::default()
+            });
+        }
+    }
+}
+
 #[repr(packed)]
 #[derive(Default)]
 struct Ioapic {
@@ -168,7 +359,7 @@ struct InterruptSourceOverride {
 pub struct Vcpu {
     // The hypervisor abstracted CPU.
     vcpu: Arc<dyn hypervisor::Vcpu>,
-    id: u8,
+    id: u32,
     #[cfg(target_arch = "aarch64")]
     mpidr: u64,
     saved_state: Option<CpuState>,
@@ -183,7 +374,7 @@ impl Vcpu {
     /// * `vm` - The virtual machine this vcpu will get attached to.
     /// * `vmmops` - Optional object for exit handling.
     pub fn new(
-        id: u8,
+        id: u32,
         vm: &Arc<dyn hypervisor::Vm>,
         vmmops: Option<Arc<Box<dyn VmmOps>>>,
     ) -> Result<Arc<Mutex<Self>>> {
@@ -331,11 +522,18 @@ pub struct CpuManager {
     exit_evt: EventFd,
     #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
     reset_evt: EventFd,
+    #[cfg(feature = "gdb")]
+    vm_debug_evt: EventFd,
+    #[cfg(feature = "gdb")]
+    debug_stopped_vcpu: Arc<AtomicU32>,
     vcpu_states: Vec<VcpuState>,
-    selected_cpu: u8,
+    selected_cpu: u32,
     vcpus: Vec<Arc<Mutex<Vcpu>>>,
     seccomp_action: SeccompAction,
     vmmops: Arc<Box<dyn VmmOps>>,
+    mmio_bus: Arc<Bus>,
+    exit_stats: Vec<Arc<VcpuExitStats>>,
+    exit_tracer: Option<Arc<ExitTracer>>,
     #[cfg(feature = "acpi")]
     acpi_address: GuestAddress,
 }
@@ -355,11 +553,12 @@ impl BusDevice for CpuManager {
 
         match offset {
             CPU_SELECTION_OFFSET => {
-                data[0] = self.selected_cpu;
+                let len = data.len().min(4);
+                data[..len].copy_from_slice(&self.selected_cpu.to_le_bytes()[..len]);
             }
             CPU_STATUS_OFFSET => {
                 if self.selected_cpu < self.present_vcpus() {
-                    let state = &self.vcpu_states[usize::from(self.selected_cpu)];
+                    let state = &self.vcpu_states[self.selected_cpu as usize];
                     if state.active() {
                         data[0] |= 1 << CPU_ENABLE_FLAG;
                     }
@@ -383,10 +582,17 @@ impl BusDevice for CpuManager {
     fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
         match offset {
             CPU_SELECTION_OFFSET => {
-                self.selected_cpu = data[0];
+                let mut selected_cpu = [0u8; 4];
+                let len = data.len().min(4);
+                selected_cpu[..len].copy_from_slice(&data[..len]);
+                self.selected_cpu = u32::from_le_bytes(selected_cpu);
             }
             CPU_STATUS_OFFSET => {
-                let state = &mut self.vcpu_states[usize::from(self.selected_cpu)];
+                if self.selected_cpu >= self.present_vcpus() {
+                    warn!("Invalid CPU selected: {}", self.selected_cpu);
+                    return None;
+                }
+                let state = &mut self.vcpu_states[self.selected_cpu as usize];
                 // The ACPI code writes back a 1 to acknowledge the insertion
                 if (data[0] & (1 << CPU_INSERTING_FLAG) == 1 << CPU_INSERTING_FLAG)
                     && state.inserting
@@ -472,13 +678,14 @@ impl CpuManager {
         vm: Arc<dyn hypervisor::Vm>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
         seccomp_action: SeccompAction,
         vmmops: Arc<Box<dyn VmmOps>>,
     ) -> Result<Arc<Mutex<CpuManager>>> {
         let guest_memory = memory_manager.lock().unwrap().guest_memory();
-        let mut vcpu_states = Vec::with_capacity(usize::from(config.max_vcpus));
-        vcpu_states.resize_with(usize::from(config.max_vcpus), VcpuState::default);
+        let mut vcpu_states = Vec::with_capacity(config.max_vcpus as usize);
+        vcpu_states.resize_with(config.max_vcpus as usize, VcpuState::default);
 
         #[cfg(target_arch = "x86_64")]
         let sgx_epc_sections = memory_manager
@@ -490,14 +697,26 @@ impl CpuManager {
         #[cfg(target_arch = "x86_64")]
         let cpuid = {
             let phys_bits = physical_bits(config.max_phys_bits);
-            CpuManager::generate_common_cpuid(
-                hypervisor,
-                &config.topology,
-                sgx_epc_sections,
-                phys_bits,
-                config.kvm_hyperv,
-            )?
+            CpuManager::generate_common_cpuid(hypervisor, config, sgx_epc_sections, phys_bits)?
+        };
+
+        // The vCPU ID is used as APIC ID, which needs 32 bits beyond the
+        // xAPIC range. This must be enabled before any vCPU is created.
+        #[cfg(target_arch = "x86_64")]
+        if config.max_vcpus > MAX_XAPIC_ID + 1 {
+            vm.enable_x2apic_api().map_err(Error::EnableX2ApicApi)?;
+        }
+
+        let exit_tracer = if let Some(path) = &config.exit_trace {
+            Some(Arc::new(
+                ExitTracer::new(path).map_err(Error::ExitTraceFile)?,
+            ))
+        } else {
+            None
         };
+        let exit_stats = (0..config.max_vcpus)
+            .map(|cpu_id| Arc::new(VcpuExitStats::new(cpu_id, exit_tracer.clone())))
+            .collect();
 
         let device_manager = device_manager.lock().unwrap();
         #[cfg(feature = "acpi")]
@@ -519,10 +738,17 @@ impl CpuManager {
             vcpu_states,
             exit_evt,
             reset_evt,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt,
+            #[cfg(feature = "gdb")]
+            debug_stopped_vcpu: Arc::new(AtomicU32::new(0)),
             selected_cpu: 0,
-            vcpus: Vec::with_capacity(usize::from(config.max_vcpus)),
+            vcpus: Vec::with_capacity(config.max_vcpus as usize),
             seccomp_action,
             vmmops,
+            mmio_bus: device_manager.mmio_bus().clone(),
+            exit_stats,
+            exit_tracer,
             #[cfg(feature = "acpi")]
             acpi_address,
         }));
@@ -543,10 +769,9 @@ impl CpuManager {
     #[cfg(target_arch = "x86_64")]
     fn generate_common_cpuid(
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
-        topology: &Option<CpuTopology>,
+        config: &CpusConfig,
         sgx_epc_sections: Option<Vec<SgxEpcSection>>,
         phys_bits: u8,
-        kvm_hyperv: bool,
     ) -> Result<CpuId> {
         let cpuid_patches = vec![
             // Patch tsc deadline timer bit
@@ -586,9 +811,20 @@ impl CpuManager {
             .get_cpuid()
             .map_err(|e| Error::PatchCpuId(e.into()))?;
 
+        // Mask the host features down to the CPU model, before forcing the
+        // features the VMM always exposes.
+        if config.model.is_some() || config.features.is_some() {
+            arch::x86_64::cpu_model::apply_cpu_model(
+                &mut cpuid,
+                config.model.as_deref(),
+                config.features.as_deref().unwrap_or(&[]),
+            )
+            .map_err(Error::CpuModel)?;
+        }
+
         CpuidPatch::patch_cpuid(&mut cpuid, cpuid_patches);
 
-        if let Some(t) = topology {
+        if let Some(t) = &config.topology {
             arch::x86_64::update_cpuid_topology(
                 &mut cpuid,
                 t.threads_per_core,
@@ -616,6 +852,11 @@ impl CpuManager {
                 // TODO: Re-enable KVM_FEATURE_ASYNC_PF_INT (#2277)
                 0x4000_0001 => {
                     entry.eax &= !(1 << KVM_FEATURE_ASYNC_PF_INT_BIT);
+                    // Let the guest route MSIs and IOAPIC interrupts to x2APIC
+                    // IDs above 255 without interrupt remapping.
+                    if config.max_vcpus > MAX_XAPIC_ID + 1 {
+                        entry.eax |= 1 << KVM_FEATURE_MSI_EXT_DEST_ID_BIT;
+                    }
                 }
                 _ => {}
             }
@@ -637,7 +878,7 @@ impl CpuManager {
                 .map_err(Error::CpuidIdentification)?;
         }
 
-        if kvm_hyperv {
+        if config.kvm_hyperv {
             // Remove conflicting entries
             cpuid.retain(|c| c.function != 0x4000_0000);
             cpuid.retain(|c| c.function != 0x4000_0001);
@@ -699,15 +940,32 @@ impl CpuManager {
         Ok(cpuid)
     }
 
+    // Whether the vCPU exits are counted or traced, which is opt-in.
+    fn exit_stats_enabled(&self) -> bool {
+        self.config.exit_stats || self.exit_tracer.is_some()
+    }
+
     fn create_vcpu(
         &mut self,
-        cpu_id: u8,
+        cpu_id: u32,
         entry_point: Option<EntryPoint>,
         snapshot: Option<Snapshot>,
     ) -> Result<Arc<Mutex<Vcpu>>> {
         info!("Creating vCPU: cpu_id = {}", cpu_id);
 
-        let vcpu = Vcpu::new(cpu_id, &self.vm, Some(self.vmmops.clone()))?;
+        // Port I/O and MMIO exits are accounted for before being handled,
+        // which costs an extra lookup on the MMIO bus, hence only when asked.
+        let vmmops = if self.exit_stats_enabled() {
+            let vmmops: Box<dyn VmmOps> = Box::new(VcpuExitOps::new(
+                self.vmmops.clone(),
+                self.mmio_bus.clone(),
+                self.exit_stats[cpu_id as usize].clone(),
+            ));
+            Arc::new(vmmops)
+        } else {
+            self.vmmops.clone()
+        };
+        let vcpu = Vcpu::new(cpu_id, &self.vm, Some(vmmops))?;
 
         if let Some(snapshot) = snapshot {
             // AArch64 vCPUs should be initialized after created.
@@ -746,7 +1004,7 @@ impl CpuManager {
     }
 
     /// Only create new vCPUs if there aren't any inactive ones to reuse
-    fn create_vcpus(&mut self, desired_vcpus: u8, entry_point: Option<EntryPoint>) -> Result<()> {
+    fn create_vcpus(&mut self, desired_vcpus: u32, entry_point: Option<EntryPoint>) -> Result<()> {
         info!(
             "Request to create new vCPUs: desired = {}, max = {}, allocated = {}, present = {}",
             desired_vcpus,
@@ -760,7 +1018,7 @@ impl CpuManager {
         }
 
         // Only create vCPUs in excess of all the allocated vCPUs.
-        for cpu_id in self.vcpus.len() as u8..desired_vcpus {
+        for cpu_id in self.vcpus.len() as u32..desired_vcpus {
             self.create_vcpu(cpu_id, entry_point, None)?;
         }
 
@@ -776,14 +1034,29 @@ impl CpuManager {
         let cpu_id = vcpu.lock().unwrap().id;
         let reset_evt = self.reset_evt.try_clone().unwrap();
         let exit_evt = self.exit_evt.try_clone().unwrap();
+        #[cfg(feature = "gdb")]
+        let vm_debug_evt = self.vm_debug_evt.try_clone().unwrap();
+        #[cfg(feature = "gdb")]
+        let debug_stopped_vcpu = self.debug_stopped_vcpu.clone();
         let vcpu_kill_signalled = self.vcpus_kill_signalled.clone();
         let vcpu_pause_signalled = self.vcpus_pause_signalled.clone();
 
-        let vcpu_kill = self.vcpu_states[usize::from(cpu_id)].kill.clone();
-        let vcpu_run_interrupted = self.vcpu_states[usize::from(cpu_id)]
+        let vcpu_kill = self.vcpu_states[cpu_id as usize].kill.clone();
+        // The exits handled here are only accounted for when asked, like the
+        // ones handled through the VmmOps.
+        let exit_stats = if self.exit_stats_enabled() {
+            Some(self.exit_stats[cpu_id as usize].clone())
+        } else {
+            None
+        };
+        let vcpu_run_interrupted = self.vcpu_states[cpu_id as usize]
             .vcpu_run_interrupted
             .clone();
 
+        let host_cpus = self.vcpu_host_cpus(cpu_id).map_err(Error::VcpuAffinity)?;
+        let fifo_priority = self.config.fifo_priority;
+        let (sched_sender, sched_receiver) = mpsc::channel();
+
         info!("Starting vCPU: cpu_id = {}", cpu_id);
 
         // Retrieve seccomp filter for vcpu thread
@@ -797,6 +1070,17 @@ impl CpuManager {
             thread::Builder::new()
                 .name(format!("vcpu{}", cpu_id))
                 .spawn(move || {
+                    // Set the affinity and scheduling policy of the thread
+                    // before it runs any guest code, which is also the case
+                    // of the hotplugged vCPUs.
+                    let sched_result =
+                        set_vcpu_thread_scheduling(host_cpus.as_deref(), fifo_priority);
+                    let sched_failed = sched_result.is_err();
+                    sched_sender.send(sched_result).ok();
+                    if sched_failed {
+                        return;
+                    }
+
                     // Apply seccomp filter for vcpu thread.
                     if let Err(e) =
                         SeccompFilter::apply(vcpu_seccomp_filter).map_err(Error::ApplySeccompFilter)
@@ -847,6 +1131,9 @@ impl CpuManager {
                             Ok(run) => match run {
                                 #[cfg(target_arch = "x86_64")]
                                 VmExit::IoapicEoi(vector) => {
+                                    if let Some(exit_stats) = &exit_stats {
+                                        exit_stats.account(VcpuExit::IoapicEoi { vector });
+                                    }
                                     if let Some(interrupt_controller) = &interrupt_controller_clone
                                     {
                                         interrupt_controller
@@ -856,7 +1143,11 @@ impl CpuManager {
                                     }
                                 }
                                 VmExit::Ignore => {}
-                                VmExit::Hyperv => {}
+                                VmExit::Hyperv => {
+                                    if let Some(exit_stats) = &exit_stats {
+                                        exit_stats.account(VcpuExit::Hyperv);
+                                    }
+                                }
                                 VmExit::Reset => {
                                     debug!("VmExit::Reset");
                                     vcpu_run_interrupted.store(true, Ordering::SeqCst);
@@ -869,6 +1160,16 @@ impl CpuManager {
                                     exit_evt.write(1).unwrap();
                                     break;
                                 }
+                                #[cfg(feature = "gdb")]
+                                VmExit::Debug => {
+                                    debug!("VmExit::Debug");
+                                    // Stop this vCPU right away, the VMM thread
+                                    // takes care of stopping the other ones
+                                    // before notifying the debugger.
+                                    vcpu_pause_signalled.store(true, Ordering::SeqCst);
+                                    debug_stopped_vcpu.store(cpu_id, Ordering::SeqCst);
+                                    vm_debug_evt.write(1).unwrap();
+                                }
                                 _ => {
                                     error!("VCPU generated error: {:?}", Error::UnexpectedVmExit);
                                     break;
@@ -893,16 +1194,77 @@ impl CpuManager {
                 .map_err(Error::VcpuSpawn)?,
         );
 
+        // Don't go any further if the thread couldn't be scheduled as
+        // requested, it has exited without running any guest code.
+        sched_receiver.recv().unwrap_or_else(|_| {
+            Err(Error::VcpuSchedPolicy(io::Error::from_raw_os_error(
+                libc::ESRCH,
+            )))
+        })?;
+
         // On hot plug calls into this function entry_point is None. It is for
         // those hotplug CPU additions that we need to set the inserting flag.
-        self.vcpu_states[usize::from(cpu_id)].handle = handle;
-        self.vcpu_states[usize::from(cpu_id)].inserting = inserting;
+        self.vcpu_states[cpu_id as usize].handle = handle;
+        self.vcpu_states[cpu_id as usize].inserting = inserting;
+
+        Ok(())
+    }
+
+    /// Host CPUs the vCPU thread should be restricted to, if any. A vCPU
+    /// without explicit affinity would otherwise inherit the affinity of the
+    /// VMM thread spawning it, which is reset to the default host CPUs if the
+    /// VMM thread itself is pinned.
+    fn vcpu_host_cpus(&self, cpu_id: u32) -> io::Result<Option<Vec<usize>>> {
+        if let Some(affinity) = self
+            .config
+            .affinity
+            .as_ref()
+            .and_then(|affinity| affinity.iter().find(|a| a.vcpu == cpu_id))
+        {
+            return Ok(Some(affinity.host_cpus.clone()));
+        }
+
+        if self.config.vmm_affinity.is_some() {
+            return default_host_cpus().map(Some);
+        }
+
+        Ok(None)
+    }
+
+    /// Change the host CPUs of the given vCPUs, applying it immediately to the
+    /// running ones. The new affinity is also used when vCPUs are hotplugged.
+    pub fn set_affinity(&mut self, affinity: &[CpuAffinity]) -> Result<()> {
+        for a in affinity {
+            if a.vcpu >= self.config.max_vcpus {
+                return Err(Error::InvalidAffinityVcpu(a.vcpu));
+            }
+            if a.host_cpus.is_empty() {
+                return Err(Error::EmptyAffinity(a.vcpu));
+            }
+        }
+
+        for a in affinity {
+            if let Some(handle) = self.vcpu_states[a.vcpu as usize].handle.as_ref() {
+                set_thread_affinity(handle.as_pthread_t() as _, &a.host_cpus)
+                    .map_err(Error::VcpuAffinity)?;
+            }
+
+            let config_affinity = self.config.affinity.get_or_insert_with(Vec::new);
+            match config_affinity.iter_mut().find(|c| c.vcpu == a.vcpu) {
+                Some(c) => c.host_cpus = a.host_cpus.clone(),
+                None => config_affinity.push(a.clone()),
+            }
+        }
 
         Ok(())
     }
 
+    pub fn affinity(&self) -> Option<Vec<CpuAffinity>> {
+        self.config.affinity.clone()
+    }
+
     /// Start up as many vCPUs threads as needed to reach `desired_vcpus`
-    fn activate_vcpus(&mut self, desired_vcpus: u8, inserting: bool) -> Result<()> {
+    fn activate_vcpus(&mut self, desired_vcpus: u32, inserting: bool) -> Result<()> {
         if desired_vcpus > self.config.max_vcpus {
             return Err(Error::DesiredVCpuCountExceedsMax);
         }
@@ -929,16 +1291,16 @@ impl CpuManager {
         Ok(())
     }
 
-    fn mark_vcpus_for_removal(&mut self, desired_vcpus: u8) {
+    fn mark_vcpus_for_removal(&mut self, desired_vcpus: u32) {
         // Mark vCPUs for removal, actual removal happens on ejection
         for cpu_id in desired_vcpus..self.present_vcpus() {
-            self.vcpu_states[usize::from(cpu_id)].removing = true;
+            self.vcpu_states[cpu_id as usize].removing = true;
         }
     }
 
-    fn remove_vcpu(&mut self, cpu_id: u8) -> Result<()> {
+    fn remove_vcpu(&mut self, cpu_id: u32) -> Result<()> {
         info!("Removing vCPU: cpu_id = {}", cpu_id);
-        let mut state = &mut self.vcpu_states[usize::from(cpu_id)];
+        let mut state = &mut self.vcpu_states[cpu_id as usize];
         state.kill.store(true, Ordering::SeqCst);
         state.signal_thread();
         state.join_thread()?;
@@ -955,8 +1317,22 @@ impl CpuManager {
     }
 
     // Starts all the vCPUs that the VM is booting with. Blocks until all vCPUs are running.
-    pub fn start_boot_vcpus(&mut self) -> Result<()> {
-        self.activate_vcpus(self.boot_vcpus(), false)
+    // With `paused`, the vCPU threads are parked before running any guest code.
+    pub fn start_boot_vcpus(&mut self, paused: bool) -> Result<()> {
+        if paused {
+            self.vcpus_pause_signalled.store(true, Ordering::SeqCst);
+        }
+        self.activate_vcpus(self.boot_vcpus(), false)?;
+
+        // Report the stop to the debugger, as if the first vCPU had hit a
+        // breakpoint.
+        #[cfg(feature = "gdb")]
+        if paused {
+            self.debug_stopped_vcpu.store(0, Ordering::SeqCst);
+            self.vm_debug_evt.write(1).map_err(Error::DebugEvent)?;
+        }
+
+        Ok(())
     }
 
     pub fn start_restored_vcpus(&mut self) -> Result<()> {
@@ -978,7 +1354,7 @@ impl CpuManager {
         Ok(())
     }
 
-    pub fn resize(&mut self, desired_vcpus: u8) -> Result<bool> {
+    pub fn resize(&mut self, desired_vcpus: u32) -> Result<bool> {
         match desired_vcpus.cmp(&self.present_vcpus()) {
             cmp::Ordering::Greater => {
                 self.create_vcpus(desired_vcpus, None)?;
@@ -1017,6 +1393,10 @@ impl CpuManager {
             state.join_thread()?;
         }
 
+        if let Some(exit_tracer) = &self.exit_tracer {
+            exit_tracer.flush();
+        }
+
         Ok(())
     }
 
@@ -1032,11 +1412,11 @@ impl CpuManager {
         Ok(())
     }
 
-    pub fn boot_vcpus(&self) -> u8 {
+    pub fn boot_vcpus(&self) -> u32 {
         self.config.boot_vcpus
     }
 
-    pub fn max_vcpus(&self) -> u8 {
+    pub fn max_vcpus(&self) -> u32 {
         self.config.max_vcpus
     }
 
@@ -1045,10 +1425,10 @@ impl CpuManager {
         self.cpuid.clone()
     }
 
-    fn present_vcpus(&self) -> u8 {
+    fn present_vcpus(&self) -> u32 {
         self.vcpu_states
             .iter()
-            .fold(0, |acc, state| acc + state.active() as u8)
+            .fold(0, |acc, state| acc + state.active() as u32)
     }
 
     #[cfg(target_arch = "aarch64")]
@@ -1059,7 +1439,13 @@ impl CpuManager {
             .collect()
     }
 
-    #[cfg(target_arch = "aarch64")]
+    /// Exit counters of the present vCPUs, indexed by vCPU id.
+    pub fn exit_counters(&self) -> BTreeMap<u32, VcpuExitCounters> {
+        (0..self.present_vcpus())
+            .map(|cpu_id| (cpu_id, self.exit_stats[cpu_id as usize].counters()))
+            .collect()
+    }
+
     pub fn get_saved_states(&self) -> Vec<CpuState> {
         self.vcpus
             .iter()
@@ -1077,20 +1463,7 @@ impl CpuManager {
         {
             madt.write(36, arch::layout::APIC_START);
 
-            for cpu in 0..self.config.max_vcpus {
-                let lapic = LocalApic {
-                    r#type: 0,
-                    length: 8,
-                    processor_id: cpu,
-                    apic_id: cpu,
-                    flags: if cpu < self.config.boot_vcpus {
-                        1 << MADT_CPU_ENABLE_FLAG
-                    } else {
-                        0
-                    },
-                };
-                madt.append(lapic);
-            }
+            append_madt_cpus(&mut madt, self.config.boot_vcpus, self.config.max_vcpus);
 
             madt.append(Ioapic {
                 r#type: 1,
@@ -1122,7 +1495,7 @@ impl CpuManager {
 
 #[cfg(feature = "acpi")]
 struct Cpu {
-    cpu_id: u8,
+    cpu_id: u32,
 }
 
 #[cfg(feature = "acpi")]
@@ -1131,20 +1504,32 @@ const MADT_CPU_ENABLE_FLAG: usize = 0;
 #[cfg(feature = "acpi")]
 impl Aml for Cpu {
     fn to_aml_bytes(&self) -> Vec<u8> {
-        let lapic = LocalApic {
-            r#type: 0,
-            length: 8,
-            processor_id: self.cpu_id,
-            apic_id: self.cpu_id,
-            flags: 1 << MADT_CPU_ENABLE_FLAG,
-        };
-
         let mut mat_data: Vec<u8> = Vec::new();
-        mat_data.resize(std::mem::size_of_val(&lapic), 0);
-        unsafe { *(mat_data.as_mut_ptr() as *mut LocalApic) = lapic };
+        if self.cpu_id <= MAX_XAPIC_ID {
+            let lapic = LocalApic {
+                r#type: 0,
+                length: 8,
+                processor_id: self.cpu_id as u8,
+                apic_id: self.cpu_id as u8,
+                flags: 1 << MADT_CPU_ENABLE_FLAG,
+            };
+            mat_data.resize(std::mem::size_of_val(&lapic), 0);
+            unsafe { *(mat_data.as_mut_ptr() as *mut LocalApic) = lapic };
+        } else {
+            let x2apic = LocalX2Apic {
+                r#type: 9,
+                length: 16,
+                x2apic_id: self.cpu_id,
+                flags: 1 << MADT_CPU_ENABLE_FLAG,
+                processor_uid: self.cpu_id,
+                ..Default::default()
+            };
+            mat_data.resize(std::mem::size_of_val(&x2apic), 0);
+            unsafe { *(mat_data.as_mut_ptr() as *mut LocalX2Apic) = x2apic };
+        }
 
         aml::Device::new(
-            format!("C{:03}", self.cpu_id).as_str().into(),
+            format!("C{:03X}", self.cpu_id).as_str().into(),
             vec![
                 &aml::Name::new("_HID".into(), &"ACPI0007"),
                 &aml::Name::new("_UID".into(), &self.cpu_id),
@@ -1187,13 +1572,13 @@ impl Aml for Cpu {
 
 #[cfg(feature = "acpi")]
 struct CpuNotify {
-    cpu_id: u8,
+    cpu_id: u32,
 }
 
 #[cfg(feature = "acpi")]
 impl Aml for CpuNotify {
     fn to_aml_bytes(&self) -> Vec<u8> {
-        let object = aml::Path::new(&format!("C{:03}", self.cpu_id));
+        let object = aml::Path::new(&format!("C{:03X}", self.cpu_id));
         aml::If::new(
             &aml::Equal::new(&aml::Arg(0), &self.cpu_id),
             vec![&aml::Notify::new(&object, &aml::Arg(1))],
@@ -1204,7 +1589,7 @@ impl Aml for CpuNotify {
 
 #[cfg(feature = "acpi")]
 struct CpuMethods {
-    max_vcpus: u8,
+    max_vcpus: u32,
 }
 
 #[cfg(feature = "acpi")]
@@ -1244,7 +1629,7 @@ impl Aml for CpuMethods {
 
         let mut cpu_notifies_refs: Vec<&dyn aml::Aml> = Vec::new();
         for cpu_id in 0..self.max_vcpus {
-            cpu_notifies_refs.push(&cpu_notifies[usize::from(cpu_id)]);
+            cpu_notifies_refs.push(&cpu_notifies[cpu_id as usize]);
         }
 
         bytes.extend_from_slice(
@@ -1412,6 +1797,139 @@ impl Aml for CpuManager {
     }
 }
 
+#[cfg(feature = "gdb")]
+impl CpuManager {
+    /// Stops all the vCPUs on behalf of the debugger, and returns the id of
+    /// the last vCPU which stopped on a debug exit. Unlike a regular pause,
+    /// the vCPU state is not saved so that the debugger can modify it.
+    pub fn debug_pause(&self) -> u32 {
+        self.vcpus_pause_signalled.store(true, Ordering::SeqCst);
+        for state in self.vcpu_states.iter() {
+            state.signal_thread();
+        }
+
+        self.debug_stopped_vcpu.load(Ordering::SeqCst)
+    }
+
+    pub fn debug_resume(&self) {
+        self.vcpus_pause_signalled.store(false, Ordering::SeqCst);
+        for state in self.vcpu_states.iter() {
+            state.unpark_thread();
+        }
+    }
+
+    pub fn active_vcpus(&self) -> u32 {
+        self.present_vcpus()
+    }
+
+    // The vCPU threads only hold the vCPU lock while running guest code,
+    // hence debug requests are only served while the vCPUs are stopped.
+    fn debug_vcpu(&self, cpu_id: u32) -> Result<Arc<dyn hypervisor::Vcpu>> {
+        if !self.vcpus_pause_signalled.load(Ordering::SeqCst) {
+            return Err(Error::VcpusNotStopped);
+        }
+
+        self.vcpus
+            .get(cpu_id as usize)
+            .map(|vcpu| vcpu.lock().unwrap().vcpu.clone())
+            .ok_or(Error::InvalidDebugVcpu(cpu_id))
+    }
+
+    // Splits the guest virtual range into guest physical chunks, as seen
+    // from the vCPU page tables.
+    fn debug_translate(
+        vcpu: &Arc<dyn hypervisor::Vcpu>,
+        vaddr: GuestAddress,
+        len: usize,
+    ) -> Result<Vec<(GuestAddress, usize)>> {
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        while offset < len {
+            let gva = vaddr.0 + offset as u64;
+            let chunk_len = cmp::min(
+                (len - offset) as u64,
+                GUEST_PAGE_SIZE - (gva & (GUEST_PAGE_SIZE - 1)),
+            ) as usize;
+            let gpa = vcpu
+                .translate_gva_to_gpa(gva)
+                .map_err(Error::TranslateVirtualAddress)?;
+            chunks.push((GuestAddress(gpa), chunk_len));
+            offset += chunk_len;
+        }
+
+        Ok(chunks)
+    }
+
+    pub fn read_regs(&self, cpu_id: u32) -> Result<CoreRegs> {
+        let vcpu = self.debug_vcpu(cpu_id)?;
+        let regs = vcpu.get_regs().map_err(Error::DebugRegisters)?;
+        let sregs = vcpu.get_sregs().map_err(Error::DebugRegisters)?;
+
+        Ok(CoreRegs { regs, sregs })
+    }
+
+    pub fn write_regs(&self, cpu_id: u32, regs: &StandardRegisters) -> Result<()> {
+        self.debug_vcpu(cpu_id)?
+            .set_regs(regs)
+            .map_err(Error::DebugRegisters)
+    }
+
+    pub fn read_mem(&self, cpu_id: u32, vaddr: GuestAddress, len: usize) -> Result<Vec<u8>> {
+        let vcpu = self.debug_vcpu(cpu_id)?;
+        let memory = self.vm_memory.memory();
+        let mut data = Vec::with_capacity(len);
+        for (gpa, chunk_len) in Self::debug_translate(&vcpu, vaddr, len)? {
+            let mut chunk = vec![0u8; chunk_len];
+            memory
+                .read_slice(&mut chunk, gpa)
+                .map_err(Error::DebugMemory)?;
+            data.extend_from_slice(&chunk);
+        }
+
+        Ok(data)
+    }
+
+    pub fn write_mem(&self, cpu_id: u32, vaddr: GuestAddress, data: &[u8]) -> Result<()> {
+        let vcpu = self.debug_vcpu(cpu_id)?;
+        let memory = self.vm_memory.memory();
+        let mut offset = 0;
+        for (gpa, chunk_len) in Self::debug_translate(&vcpu, vaddr, data.len())? {
+            memory
+                .write_slice(&data[offset..offset + chunk_len], gpa)
+                .map_err(Error::DebugMemory)?;
+            offset += chunk_len;
+        }
+
+        Ok(())
+    }
+
+    /// Breakpoints apply to all the vCPUs, while only the given vCPU is
+    /// single-stepped.
+    pub fn set_guest_debug(
+        &self,
+        cpu_id: u32,
+        hw_breakpoints: &[u64],
+        sw_breakpoints: bool,
+        single_step: bool,
+    ) -> Result<()> {
+        // Only check the vCPUs are stopped.
+        self.debug_vcpu(cpu_id)?;
+
+        for vcpu in self.vcpus.iter() {
+            let vcpu = vcpu.lock().unwrap();
+            vcpu.vcpu
+                .set_guest_debug(
+                    hw_breakpoints,
+                    sw_breakpoints,
+                    single_step && vcpu.id == cpu_id,
+                )
+                .map_err(Error::SetGuestDebug)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl Pausable for CpuManager {
     fn pause(&mut self) -> std::result::Result<(), MigratableError> {
         // Tell the vCPUs to pause themselves next time they exit
@@ -1438,6 +1956,11 @@ impl Pausable for CpuManager {
             }
         }
 
+        // Make the trace complete up to the pause point.
+        if let Some(exit_tracer) = &self.exit_tracer {
+            exit_tracer.flush();
+        }
+
         Ok(())
     }
 
@@ -1480,8 +2003,12 @@ impl Snapshottable for CpuManager {
     fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         for (cpu_id, snapshot) in snapshot.snapshots.iter() {
             debug!("Restoring VCPU {}", cpu_id);
-            self.create_vcpu(cpu_id.parse::<u8>().unwrap(), None, Some(*snapshot.clone()))
-                .map_err(|e| MigratableError::Restore(anyhow!("Could not create vCPU {:?}", e)))?;
+            self.create_vcpu(
+                cpu_id.parse::<u32>().unwrap(),
+                None,
+                Some(*snapshot.clone()),
+            )
+            .map_err(|e| MigratableError::Restore(anyhow!("Could not create vCPU {:?}", e)))?;
         }
 
         Ok(())
@@ -1594,6 +2121,34 @@ mod tests {
         let actual_regs: StandardRegisters = vcpu.get_regs().unwrap();
         assert_eq!(actual_regs, expected_regs);
     }
+
+    #[cfg(feature = "acpi")]
+    #[test]
+    fn test_madt_cpus() {
+        use acpi_tables::sdt::Sdt;
+
+        let mut madt = Sdt::new(*b"APIC", 44, 5, *b"CLOUDH", *b"CHMADT  ", 1);
+        super::append_madt_cpus(&mut madt, 256, 300);
+        let data = madt.as_slice();
+
+        // Local APIC entries up to ID 254, then x2APIC ones.
+        assert_eq!(data.len(), 44 + 255 * 8 + 45 * 16);
+        for cpu in 0..255usize {
+            let entry = &data[44 + cpu * 8..44 + (cpu + 1) * 8];
+            assert_eq!(&entry[..4], &[0, 8, cpu as u8, cpu as u8]);
+            assert_eq!(&entry[4..], &1u32.to_le_bytes());
+        }
+        for cpu in 255..300usize {
+            let offset = 44 + 255 * 8 + (cpu - 255) * 16;
+            let entry = &data[offset..offset + 16];
+            assert_eq!(&entry[..4], &[9, 16, 0, 0]);
+            assert_eq!(&entry[4..8], &(cpu as u32).to_le_bytes());
+            // Only the boot vCPUs are enabled.
+            let flags = if cpu < 256 { 1u32 } else { 0 };
+            assert_eq!(&entry[8..12], &flags.to_le_bytes());
+            assert_eq!(&entry[12..], &(cpu as u32).to_le_bytes());
+        }
+    }
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -10,9 +10,10 @@
 //
 
 use crate::config::{
-    ConsoleOutputMode, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, VhostMode,
-    VmConfig, VsockConfig,
+    ConsoleOutputMode, DeviceConfig, DiskConfig, FsConfig, GenericVhostUserConfig, NetConfig,
+    NetMode, PmemConfig, UserDeviceConfig, VhostMode, VmConfig, VsockConfig,
 };
+use crate::cpu;
 use crate::device_tree::{DeviceNode, DeviceTree};
 #[cfg(feature = "kvm")]
 use crate::interrupt::kvm::KvmMsiInterruptManager as MsiInterruptManager;
@@ -60,13 +61,13 @@ use hypervisor::CpuState;
 #[cfg(feature = "mshv")]
 use hypervisor::IoEventAddress;
 use libc::{
-    isatty, tcgetattr, tcsetattr, termios, ECHO, ICANON, ISIG, MAP_NORESERVE, MAP_PRIVATE,
-    MAP_SHARED, O_TMPFILE, PROT_READ, PROT_WRITE, TCSANOW, TIOCGWINSZ,
+    isatty, tcgetattr, tcsetattr, termios, ECHO, ICANON, ISIG, O_TMPFILE, TCSANOW, TIOCGWINSZ,
 };
 #[cfg(feature = "kvm")]
 use pci::VfioPciDevice;
 use pci::{
     DeviceRelocation, PciBarRegionType, PciBus, PciConfigIo, PciConfigMmio, PciDevice, PciRoot,
+    VfioUserPciDevice,
 };
 use seccomp::SeccompAction;
 use std::collections::HashMap;
@@ -74,10 +75,11 @@ use std::convert::TryInto;
 use std::fs::{read_link, File, OpenOptions};
 use std::io::{self, sink, stdout, Seek, SeekFrom};
 use std::mem::zeroed;
+use std::net::IpAddr;
 use std::num::Wrapping;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::{Arc, Barrier, Mutex};
 #[cfg(feature = "kvm")]
@@ -90,11 +92,11 @@ use virtio_devices::{VirtioSharedMemory, VirtioSharedMemoryList};
 use vm_allocator::SystemAllocator;
 #[cfg(feature = "kvm")]
 use vm_device::dma_mapping::vfio::VfioDmaMapping;
+use vm_device::dma_mapping::vfio_user::VfioUserDmaMapping;
 use vm_device::interrupt::{
     InterruptIndex, InterruptManager, LegacyIrqGroupConfig, MsiIrqGroupConfig,
 };
 use vm_device::{Bus, BusDevice, Resource};
-use vm_memory::guest_memory::FileOffset;
 #[cfg(feature = "kvm")]
 use vm_memory::GuestMemoryRegion;
 use vm_memory::{Address, GuestAddress, GuestRegionMmap, GuestUsize, MmapRegion};
@@ -112,6 +114,7 @@ const MMIO_LEN: u64 = 0x1000;
 
 #[cfg(feature = "kvm")]
 const VFIO_DEVICE_NAME_PREFIX: &str = "_vfio";
+const VFIO_USER_DEVICE_NAME_PREFIX: &str = "_vfio_user";
 
 #[cfg(target_arch = "x86_64")]
 const IOAPIC_DEVICE_NAME: &str = "_ioapic";
@@ -123,6 +126,7 @@ const GPIO_DEVICE_NAME_PREFIX: &str = "_gpio";
 const CONSOLE_DEVICE_NAME: &str = "_console";
 const DISK_DEVICE_NAME_PREFIX: &str = "_disk";
 const FS_DEVICE_NAME_PREFIX: &str = "_fs";
+const GENERIC_VHOST_USER_DEVICE_NAME_PREFIX: &str = "_generic_vhost_user";
 const MEM_DEVICE_NAME_PREFIX: &str = "_mem";
 const BALLOON_DEVICE_NAME: &str = "_balloon";
 const NET_DEVICE_NAME_PREFIX: &str = "_net";
@@ -165,6 +169,12 @@ pub enum DeviceManagerError {
     /// Virtio-fs device was created without a socket.
     NoVirtioFsSock,
 
+    /// Cannot create generic vhost-user device
+    CreateGenericVhostUser(virtio_devices::vhost_user::Error),
+
+    /// Generic vhost-user device was created without a socket.
+    NoGenericVhostUserSock,
+
     /// Cannot create vhost-user-blk device
     CreateVhostUserBlk(virtio_devices::vhost_user::Error),
 
@@ -180,6 +190,9 @@ pub enum DeviceManagerError {
     /// Cannot create virtio-vsock backend
     CreateVsockBackend(virtio_devices::vsock::VsockUnixError),
 
+    /// Cannot create kernel vhost-vsock device
+    CreateVhostVsock(virtio_devices::vsock::VhostVsockError),
+
     /// Cannot create virtio-iommu device
     CreateVirtioIommu(io::Error),
 
@@ -267,6 +280,21 @@ pub enum DeviceManagerError {
     /// Failed to DMA unmap VFIO device.
     VfioDmaUnmap(pci::VfioPciError),
 
+    /// Cannot connect to the vfio-user device
+    VfioUserCreateClient(vfio_user::Error),
+
+    /// Cannot create a vfio-user PCI device
+    VfioUserCreate(pci::VfioUserPciError),
+
+    /// Failed to map vfio-user MMIO region.
+    VfioUserMapRegion(pci::VfioUserPciError),
+
+    /// Failed to DMA map vfio-user device.
+    VfioUserDmaMap(pci::VfioUserPciError),
+
+    /// Failed to DMA unmap vfio-user device.
+    VfioUserDmaUnmap(pci::VfioUserPciError),
+
     /// Failed to create the passthrough device.
     CreatePassthroughDevice(anyhow::Error),
 
@@ -339,6 +367,21 @@ pub enum DeviceManagerError {
     /// Failed to find device corresponding to the given identifier.
     UnknownDeviceId(String),
 
+    /// Failed to start capturing the network device traffic.
+    StartNetCapture(net_util::PacketCaptureError),
+
+    /// The network device traffic can't be captured with vhost-net.
+    NetCaptureUnsupported(String),
+
+    /// Failed to offload the network device to the kernel vhost-net driver.
+    EnableVhostNet(virtio_devices::net::Error),
+
+    /// Failed to set the addresses and MTU of the TAP interface.
+    ConfigureTap(virtio_devices::net::Error),
+
+    /// Failed to change the link state of the network device.
+    SetNetLink(virtio_devices::net::Error),
+
     /// Failed to find an available PCI device ID.
     NextPciDeviceId(pci::PciRootError),
 
@@ -369,6 +412,9 @@ pub enum DeviceManagerError {
     /// Failed updating guest memory for VFIO PCI device.
     UpdateMemoryForVfioPciDevice(pci::VfioPciError),
 
+    /// Failed updating guest memory for vfio-user PCI device.
+    UpdateMemoryForVfioUserPciDevice(pci::VfioUserPciError),
+
     /// Trying to use a directory for pmem but no size specified
     PmemWithDirectorySizeMissing,
 
@@ -420,6 +466,9 @@ pub enum DeviceManagerError {
 
     /// Failed removing DMA mapping handler from virtio-mem device.
     RemoveDmaMappingHandlerVirtioMem(virtio_devices::mem::Error),
+
+    /// Cannot set the host CPU affinity of the device threads.
+    IoThreadAffinity(io::Error),
 }
 pub type DeviceManagerResult<T> = result::Result<T, DeviceManagerError>;
 
@@ -807,6 +856,7 @@ pub enum PciDeviceHandle {
     #[cfg(feature = "kvm")]
     Vfio(Arc<Mutex<VfioPciDevice>>),
     Virtio(Arc<Mutex<VirtioPciDevice>>),
+    VfioUser(Arc<Mutex<VfioUserPciDevice>>),
 }
 
 pub struct DeviceManager {
@@ -915,6 +965,9 @@ pub struct DeviceManager {
     // Possible handle to the virtio-balloon device
     virtio_mem_devices: Vec<Arc<Mutex<virtio_devices::Mem>>>,
 
+    // virtio-net devices, identified by device id
+    net_devices: HashMap<String, Arc<Mutex<virtio_devices::Net>>>,
+
     #[cfg(target_arch = "aarch64")]
     // GPIO device for AArch64
     gpio_device: Option<Arc<Mutex<devices::legacy::Gpio>>>,
@@ -1002,6 +1055,7 @@ impl DeviceManager {
             serial_pty: None,
             console_pty: None,
             virtio_mem_devices: Vec::new(),
+            net_devices: HashMap::new(),
             #[cfg(target_arch = "aarch64")]
             gpio_device: None,
         };
@@ -1201,6 +1255,8 @@ impl DeviceManager {
 
         iommu_attached_devices.append(&mut vfio_iommu_device_ids);
 
+        self.add_vfio_user_devices(&mut pci_bus)?;
+
         if let Some(iommu_device) = iommu_device {
             iommu_device
                 .lock()
@@ -1849,6 +1905,9 @@ impl DeviceManager {
         // Add virtio-fs if required
         devices.append(&mut self.make_virtio_fs_devices()?);
 
+        // Add generic vhost-user devices if required
+        devices.append(&mut self.make_generic_vhost_user_devices()?);
+
         // Add virtio-pmem if required
         devices.append(&mut self.make_virtio_pmem_devices()?);
 
@@ -2066,7 +2125,45 @@ impl DeviceManager {
                 id,
             ))
         } else {
-            let virtio_net_device = if let Some(ref tap_if_name) = net_cfg.tap {
+            let virtio_net_device = if net_cfg.mode == NetMode::User {
+                let user_net_cfg = net_util::UserNetConfig {
+                    ip: net_cfg.ip,
+                    netmask: net_cfg.mask,
+                    tcp_forwards: net_cfg
+                        .tcp_forward
+                        .iter()
+                        .map(|f| net_util::PortForward {
+                            host_addr: f.host_addr,
+                            host_port: f.host_port,
+                            guest_port: f.guest_port,
+                        })
+                        .collect(),
+                    udp_forwards: net_cfg
+                        .udp_forward
+                        .iter()
+                        .map(|f| net_util::PortForward {
+                            host_addr: f.host_addr,
+                            host_port: f.host_port,
+                            guest_port: f.guest_port,
+                        })
+                        .collect(),
+                };
+                // The userspace network stack runs in its own thread.
+                Arc::new(Mutex::new(
+                    self.with_io_affinity(|| {
+                        virtio_devices::Net::new_user_net(
+                            id.clone(),
+                            &user_net_cfg,
+                            Some(net_cfg.mac),
+                            net_cfg.iommu,
+                            net_cfg.queue_size,
+                            self.seccomp_action.clone(),
+                            net_cfg.rate_limiter_config,
+                        )
+                    })?
+                    .map_err(DeviceManagerError::CreateVirtioNet)?,
+                ))
+            } else if let Some(ref tap_if_name) = net_cfg.tap {
                 Arc::new(Mutex::new(
                     virtio_devices::Net::new(
                         id.clone(),
@@ -2115,6 +2212,27 @@ impl DeviceManager {
                 ))
             };
 
+            if !net_cfg.addresses.is_empty() || net_cfg.mtu.is_some() {
+                let addresses: Vec<(IpAddr, u8)> = net_cfg
+                    .addresses
+                    .iter()
+                    .map(|a| (a.addr, a.prefix_len))
+                    .collect();
+                virtio_net_device
+                    .lock()
+                    .unwrap()
+                    .configure_tap(&addresses, net_cfg.mtu)
+                    .map_err(DeviceManagerError::ConfigureTap)?;
+            }
+
+            if net_cfg.vhost {
+                virtio_net_device
+                    .lock()
+                    .unwrap()
+                    .enable_vhost_net()
+                    .map_err(DeviceManagerError::EnableVhostNet)?;
+            }
+
             // Fill the device tree with a new node. In case of restore, we
             // know there is nothing to do, so we can simply override the
             // existing entry.
@@ -2123,6 +2241,9 @@ impl DeviceManager {
                 .unwrap()
                 .insert(id.clone(), device_node!(id, virtio_net_device));
 
+            self.net_devices
+                .insert(id.clone(), virtio_net_device.clone());
+
             Ok((
                 Arc::clone(&virtio_net_device) as VirtioDeviceArc,
                 net_cfg.iommu,
@@ -2345,6 +2466,70 @@ impl DeviceManager {
         Ok(devices)
     }
 
+    fn make_generic_vhost_user_device(
+        &mut self,
+        generic_vhost_user_cfg: &mut GenericVhostUserConfig,
+    ) -> DeviceManagerResult<(VirtioDeviceArc, bool, String)> {
+        let id = if let Some(id) = &generic_vhost_user_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(GENERIC_VHOST_USER_DEVICE_NAME_PREFIX)?;
+            generic_vhost_user_cfg.id = Some(id.clone());
+            id
+        };
+
+        info!(
+            "Creating generic vhost-user device: {:?}",
+            generic_vhost_user_cfg
+        );
+
+        let socket = generic_vhost_user_cfg
+            .socket
+            .to_str()
+            .ok_or(DeviceManagerError::NoGenericVhostUserSock)?;
+
+        let generic_vhost_user_device = Arc::new(Mutex::new(
+            virtio_devices::vhost_user::Generic::new(
+                id.clone(),
+                generic_vhost_user_cfg.virtio_id,
+                socket,
+                generic_vhost_user_cfg.queue_sizes.clone(),
+                self.seccomp_action.clone(),
+            )
+            .map_err(DeviceManagerError::CreateGenericVhostUser)?,
+        ));
+
+        // Fill the device tree with a new node. In case of restore, we
+        // know there is nothing to do, so we can simply override the
+        // existing entry.
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, generic_vhost_user_device));
+
+        Ok((
+            Arc::clone(&generic_vhost_user_device) as VirtioDeviceArc,
+            false,
+            id,
+        ))
+    }
+
+    fn make_generic_vhost_user_devices(
+        &mut self,
+    ) -> DeviceManagerResult<Vec<(VirtioDeviceArc, bool, String)>> {
+        let mut devices = Vec::new();
+
+        let mut generic_vhost_user_devices = self.config.lock().unwrap().generic_vhost_user.clone();
+        if let Some(generic_vhost_user_list_cfg) = &mut generic_vhost_user_devices {
+            for generic_vhost_user_cfg in generic_vhost_user_list_cfg.iter_mut() {
+                devices.push(self.make_generic_vhost_user_device(generic_vhost_user_cfg)?);
+            }
+        }
+        self.config.lock().unwrap().generic_vhost_user = generic_vhost_user_devices;
+
+        Ok(devices)
+    }
+
     fn make_virtio_pmem_device(
         &mut self,
         pmem_cfg: &mut PmemConfig,
@@ -2402,7 +2587,7 @@ impl DeviceManager {
 
         let mut file = OpenOptions::new()
             .read(true)
-            .write(!pmem_cfg.discard_writes)
+            .write(!pmem_cfg.discard_writes && !pmem_cfg.readonly)
             .custom_flags(custom_flags)
             .open(&pmem_cfg.file)
             .map_err(DeviceManagerError::PmemFileOpen)?;
@@ -2451,17 +2636,11 @@ impl DeviceManager {
             (base.raw_value(), size)
         };
 
-        let cloned_file = file.try_clone().map_err(DeviceManagerError::CloneFile)?;
-        let mmap_region = MmapRegion::build(
-            Some(FileOffset::new(cloned_file, 0)),
+        let mmap_region = virtio_devices::Pmem::mmap_region(
+            file,
             region_size as usize,
-            PROT_READ | PROT_WRITE,
-            MAP_NORESERVE
-                | if pmem_cfg.discard_writes {
-                    MAP_PRIVATE
-                } else {
-                    MAP_SHARED
-                },
+            pmem_cfg.readonly,
+            pmem_cfg.discard_writes,
         )
         .map_err(DeviceManagerError::NewMmapRegion)?;
         let host_addr: u64 = mmap_region.as_ptr() as u64;
@@ -2475,7 +2654,7 @@ impl DeviceManager {
                 region_size,
                 host_addr,
                 pmem_cfg.mergeable,
-                false,
+                pmem_cfg.readonly,
                 false,
             )
             .map_err(DeviceManagerError::MemoryManager)?;
@@ -2491,11 +2670,12 @@ impl DeviceManager {
         let virtio_pmem_device = Arc::new(Mutex::new(
             virtio_devices::Pmem::new(
                 id.clone(),
-                file,
                 GuestAddress(region_base),
                 mapping,
                 mmap_region,
                 pmem_cfg.iommu,
+                pmem_cfg.readonly,
+                pmem_cfg.discard_writes,
                 self.seccomp_action.clone(),
             )
             .map_err(DeviceManagerError::CreateVirtioPmem)?,
@@ -2547,13 +2727,50 @@ impl DeviceManager {
 
         info!("Creating virtio-vsock device: {:?}", vsock_cfg);
 
+        if vsock_cfg.vhost {
+            let vsock_device = Arc::new(Mutex::new(
+                virtio_devices::VhostVsock::new(id.clone(), vsock_cfg.cid)
+                    .map_err(DeviceManagerError::CreateVhostVsock)?,
+            ));
+
+            self.device_tree
+                .lock()
+                .unwrap()
+                .insert(id.clone(), device_node!(id, vsock_device));
+
+            return Ok((
+                Arc::clone(&vsock_device) as VirtioDeviceArc,
+                vsock_cfg.iommu,
+                id,
+            ));
+        }
+
         let socket_path = vsock_cfg
             .socket
             .to_str()
             .ok_or(DeviceManagerError::CreateVsockConvertPath)?;
-        let backend =
-            virtio_devices::vsock::VsockUnixBackend::new(vsock_cfg.cid, socket_path.to_string())
-                .map_err(DeviceManagerError::CreateVsockBackend)?;
+        let tcp_listen: Vec<(u16, u32)> = vsock_cfg
+            .tcp_listen
+            .iter()
+            .map(|f| (f.host_port, f.vsock_port))
+            .collect();
+        let tcp_connect: Vec<(u16, u32)> = vsock_cfg
+            .tcp_connect
+            .iter()
+            .map(|f| (f.host_port, f.vsock_port))
+            .collect();
+        // A stale socket left behind by the VMM the VM is restored from can
+        // be replaced. The device is being restored when its id can be found
+        // in the device tree.
+        let restoring = self.device_tree.lock().unwrap().get(&id).is_some();
+        let backend = virtio_devices::vsock::VsockUnixBackend::new(
+            vsock_cfg.cid,
+            socket_path.to_string(),
+            &tcp_listen,
+            &tcp_connect,
+            restoring,
+        )
+        .map_err(DeviceManagerError::CreateVsockBackend)?;
 
         let vsock_device = Arc::new(Mutex::new(
             virtio_devices::Vsock::new(
@@ -2972,6 +3189,133 @@ impl DeviceManager {
         Ok(iommu_attached_device_ids)
     }
 
+    fn add_vfio_user_device(
+        &mut self,
+        pci: &mut PciBus,
+        device_cfg: &mut UserDeviceConfig,
+    ) -> DeviceManagerResult<(u32, String)> {
+        let pci_device_bdf = pci
+            .next_device_id()
+            .map_err(DeviceManagerError::NextPciDeviceId)?
+            << 3;
+
+        let legacy_interrupt_group = if let Some(legacy_interrupt_manager) =
+            &self.legacy_interrupt_manager
+        {
+            Some(
+                legacy_interrupt_manager
+                    .create_group(LegacyIrqGroupConfig {
+                        irq: self.pci_irq_slots[(pci_device_bdf >> 3) as usize] as InterruptIndex,
+                    })
+                    .map_err(DeviceManagerError::CreateInterruptGroup)?,
+            )
+        } else {
+            None
+        };
+
+        let client = Arc::new(Mutex::new(
+            vfio_user::Client::new(&device_cfg.socket)
+                .map_err(DeviceManagerError::VfioUserCreateClient)?,
+        ));
+
+        let memory = self.memory_manager.lock().unwrap().guest_memory();
+        let vfio_user_mapping = Arc::new(VfioUserDmaMapping::new(
+            Arc::clone(&client),
+            Arc::new(memory),
+        ));
+        for virtio_mem_device in self.virtio_mem_devices.iter() {
+            virtio_mem_device
+                .lock()
+                .unwrap()
+                .add_dma_mapping_handler(pci_device_bdf, vfio_user_mapping.clone())
+                .map_err(DeviceManagerError::AddDmaMappingHandlerVirtioMem)?;
+        }
+
+        let vfio_user_pci_device = VfioUserPciDevice::new(
+            &self.address_manager.vm,
+            client,
+            &self.msi_interrupt_manager,
+            legacy_interrupt_group,
+        )
+        .map_err(DeviceManagerError::VfioUserCreate)?;
+
+        let vfio_user_name = if let Some(id) = &device_cfg.id {
+            if self.device_tree.lock().unwrap().contains_key(id) {
+                return Err(DeviceManagerError::DeviceIdAlreadyInUse);
+            }
+
+            id.clone()
+        } else {
+            let id = self.next_device_name(VFIO_USER_DEVICE_NAME_PREFIX)?;
+            device_cfg.id = Some(id.clone());
+            id
+        };
+
+        // Share the guest RAM with the device so that it can perform DMA.
+        // Do not register virtio-mem regions, as they are handled directly by
+        // virtio-mem device itself.
+        for (_, zone) in self.memory_manager.lock().unwrap().memory_zones().iter() {
+            for region in zone.regions() {
+                vfio_user_pci_device
+                    .dma_map(region)
+                    .map_err(DeviceManagerError::VfioUserDmaMap)?;
+            }
+        }
+
+        let vfio_user_pci_device = Arc::new(Mutex::new(vfio_user_pci_device));
+
+        self.add_pci_device(
+            pci,
+            vfio_user_pci_device.clone(),
+            vfio_user_pci_device.clone(),
+            pci_device_bdf,
+        )?;
+
+        // The BARs are only known once they have been allocated, which is
+        // why the mappable regions can only be mapped at this point.
+        vfio_user_pci_device
+            .lock()
+            .unwrap()
+            .map_mmio_regions(&self.address_manager.vm, || {
+                self.memory_manager.lock().unwrap().allocate_memory_slot()
+            })
+            .map_err(DeviceManagerError::VfioUserMapRegion)?;
+
+        let mut node = device_node!(vfio_user_name);
+
+        for region in vfio_user_pci_device.lock().unwrap().mmio_regions() {
+            node.resources.push(Resource::MmioAddressRange {
+                base: region.start.0,
+                size: region.length as u64,
+            });
+        }
+
+        node.pci_bdf = Some(pci_device_bdf);
+        node.pci_device_handle = Some(PciDeviceHandle::VfioUser(vfio_user_pci_device));
+
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(vfio_user_name.clone(), node);
+
+        Ok((pci_device_bdf, vfio_user_name))
+    }
+
+    fn add_vfio_user_devices(&mut self, pci: &mut PciBus) -> DeviceManagerResult<()> {
+        let mut user_devices = self.config.lock().unwrap().user_devices.clone();
+
+        if let Some(device_list_cfg) = &mut user_devices {
+            for device_cfg in device_list_cfg.iter_mut() {
+                self.add_vfio_user_device(pci, device_cfg)?;
+            }
+        }
+
+        // Update the list of devices
+        self.config.lock().unwrap().user_devices = user_devices;
+
+        Ok(())
+    }
+
     fn add_virtio_pci_device(
         &mut self,
         virtio_device: VirtioDeviceArc,
@@ -3170,25 +3514,63 @@ impl DeviceManager {
             }
         }
 
-        Ok(())
-    }
-
-    pub fn activate_virtio_devices(&self) -> DeviceManagerResult<()> {
-        // Find virtio pci devices and activate any pending ones
+        // Take care of updating the memory for vfio-user PCI devices.
         let device_tree = self.device_tree.lock().unwrap();
         for pci_device_node in device_tree.pci_devices() {
-            #[allow(irrefutable_let_patterns)]
-            if let PciDeviceHandle::Virtio(virtio_pci_device) = &pci_device_node
+            if let PciDeviceHandle::VfioUser(vfio_user_pci_device) = pci_device_node
                 .pci_device_handle
                 .as_ref()
                 .ok_or(DeviceManagerError::MissingPciDevice)?
             {
-                virtio_pci_device.lock().unwrap().maybe_activate();
+                vfio_user_pci_device
+                    .lock()
+                    .unwrap()
+                    .dma_map(new_region)
+                    .map_err(DeviceManagerError::UpdateMemoryForVfioUserPciDevice)?;
             }
         }
+
         Ok(())
     }
 
+    pub fn activate_virtio_devices(&self) -> DeviceManagerResult<()> {
+        // Find virtio pci devices and activate any pending ones
+        self.with_io_affinity(|| {
+            let device_tree = self.device_tree.lock().unwrap();
+            for pci_device_node in device_tree.pci_devices() {
+                if let PciDeviceHandle::Virtio(virtio_pci_device) = &pci_device_node
+                    .pci_device_handle
+                    .as_ref()
+                    .ok_or(DeviceManagerError::MissingPciDevice)?
+                {
+                    virtio_pci_device.lock().unwrap().maybe_activate();
+                }
+            }
+            Ok(())
+        })?
+    }
+
+    // The device worker and I/O threads inherit the affinity of the VMM
+    // thread spawning them, which is switched to the I/O affinity, if any,
+    // for the time `f` runs.
+    fn with_io_affinity<T>(&self, f: impl FnOnce() -> T) -> DeviceManagerResult<T> {
+        let cpus_config = self.config.lock().unwrap().cpus.clone();
+        let io_affinity = match &cpus_config.io_affinity {
+            Some(io_affinity) => io_affinity,
+            None => return Ok(f()),
+        };
+
+        // Safe because pthread_self() has no precondition and always succeeds.
+        let vmm_thread = unsafe { libc::pthread_self() };
+        cpu::set_thread_affinity(vmm_thread, io_affinity)
+            .map_err(DeviceManagerError::IoThreadAffinity)?;
+        let result = f();
+        cpu::set_vmm_thread_affinity(&cpus_config.vmm_affinity)
+            .map_err(DeviceManagerError::IoThreadAffinity)?;
+
+        Ok(result)
+    }
+
     pub fn notify_hotplug(
         &self,
         _notification_type: AcpiNotificationFlags,
@@ -3228,7 +3610,40 @@ impl DeviceManager {
         })
     }
 
+    pub fn add_user_device(
+        &mut self,
+        device_cfg: &mut UserDeviceConfig,
+    ) -> DeviceManagerResult<PciDeviceInfo> {
+        let pci = if let Some(pci_bus) = &self.pci_bus {
+            Arc::clone(&pci_bus)
+        } else {
+            return Err(DeviceManagerError::NoPciBus);
+        };
+
+        let (device_id, device_name) =
+            self.add_vfio_user_device(&mut pci.lock().unwrap(), device_cfg)?;
+
+        // Update the PCIU bitmap
+        self.pci_devices_up |= 1 << (device_id >> 3);
+
+        Ok(PciDeviceInfo {
+            id: device_name,
+            bdf: device_id,
+        })
+    }
+
     pub fn remove_device(&mut self, id: String) -> DeviceManagerResult<()> {
+        // Generic vhost-user devices can always be removed, no matter the
+        // type of virtio device they expose.
+        let generic_vhost_user = self
+            .config
+            .lock()
+            .unwrap()
+            .generic_vhost_user
+            .as_ref()
+            .map(|devices| devices.iter().any(|dev| dev.id.as_ref() == Some(&id)))
+            .unwrap_or(false);
+
         // The node can be directly a PCI node in case the 'id' refers to a
         // VFIO device or a virtio-pci one.
         // In case the 'id' refers to a virtio device, we must find the PCI
@@ -3257,7 +3672,6 @@ impl DeviceManager {
             .pci_device_handle
             .as_ref()
             .ok_or(DeviceManagerError::MissingPciDevice)?;
-        #[allow(irrefutable_let_patterns)]
         if let PciDeviceHandle::Virtio(virtio_pci_device) = pci_device_handle {
             let device_type = VirtioDeviceType::from(
                 virtio_pci_device
@@ -3274,6 +3688,7 @@ impl DeviceManager {
                 | VirtioDeviceType::Pmem
                 | VirtioDeviceType::Fs
                 | VirtioDeviceType::Vsock => {}
+                _ if generic_vhost_user => {}
                 _ => return Err(DeviceManagerError::RemovalNotAllowed(device_type)),
             }
         }
@@ -3308,6 +3723,11 @@ impl DeviceManager {
             .ok_or(DeviceManagerError::MissingPciDevice)?;
         for child in pci_device_node.children.iter() {
             device_tree.remove(child);
+            if let Some(net) = self.net_devices.remove(child) {
+                if let Some(capture) = net.lock().unwrap().packet_capture() {
+                    capture.stop();
+                }
+            }
         }
 
         let pci_device_handle = pci_device_node
@@ -3347,6 +3767,36 @@ impl DeviceManager {
                     None as Option<VirtioDeviceArc>,
                 )
             }
+            PciDeviceHandle::VfioUser(vfio_user_pci_device) => {
+                {
+                    // Stop sharing the guest RAM with the device.
+                    // Do not unregister the virtio-mem region, as it is
+                    // directly handled by the virtio-mem device.
+                    let dev = vfio_user_pci_device.lock().unwrap();
+                    for (_, zone) in self.memory_manager.lock().unwrap().memory_zones().iter() {
+                        for region in zone.regions() {
+                            dev.dma_unmap(region)
+                                .map_err(DeviceManagerError::VfioUserDmaUnmap)?;
+                        }
+                    }
+
+                    // Unregister the vfio-user mapping handler from all
+                    // virtio-mem devices.
+                    for virtio_mem_device in self.virtio_mem_devices.iter() {
+                        virtio_mem_device
+                            .lock()
+                            .unwrap()
+                            .remove_dma_mapping_handler(pci_device_bdf)
+                            .map_err(DeviceManagerError::RemoveDmaMappingHandlerVirtioMem)?;
+                    }
+                }
+
+                (
+                    Arc::clone(&vfio_user_pci_device) as Arc<Mutex<dyn PciDevice>>,
+                    Arc::clone(&vfio_user_pci_device) as Arc<Mutex<dyn BusDevice>>,
+                    None as Option<VirtioDeviceArc>,
+                )
+            }
             PciDeviceHandle::Virtio(virtio_pci_device) => {
                 let bar_addr = virtio_pci_device.lock().unwrap().config_bar_addr();
                 for (event, addr) in virtio_pci_device.lock().unwrap().ioeventfds(bar_addr) {
@@ -3464,6 +3914,15 @@ impl DeviceManager {
         self.hotplug_virtio_pci_device(device, iommu_attached, id)
     }
 
+    pub fn add_generic_vhost_user(
+        &mut self,
+        generic_vhost_user_cfg: &mut GenericVhostUserConfig,
+    ) -> DeviceManagerResult<PciDeviceInfo> {
+        let (device, iommu_attached, id) =
+            self.make_generic_vhost_user_device(generic_vhost_user_cfg)?;
+        self.hotplug_virtio_pci_device(device, iommu_attached, id)
+    }
+
     pub fn add_pmem(&mut self, pmem_cfg: &mut PmemConfig) -> DeviceManagerResult<PciDeviceInfo> {
         let (device, iommu_attached, id) = self.make_virtio_pmem_device(pmem_cfg)?;
         self.hotplug_virtio_pci_device(device, iommu_attached, id)
@@ -3505,6 +3964,45 @@ impl DeviceManager {
         Err(DeviceManagerError::MissingVirtioBalloon)
     }
 
+    /// Start capturing the traffic of a virtio-net device into the file at
+    /// `path`, or stop the ongoing capture if no path is provided.
+    pub fn net_capture(
+        &self,
+        id: &str,
+        path: Option<&Path>,
+        max_size: Option<u64>,
+    ) -> DeviceManagerResult<()> {
+        let capture = self
+            .net_devices
+            .get(id)
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.to_owned()))?
+            .lock()
+            .unwrap()
+            .packet_capture()
+            .ok_or_else(|| DeviceManagerError::NetCaptureUnsupported(id.to_owned()))?;
+
+        if let Some(path) = path {
+            capture
+                .start(path, max_size)
+                .map_err(DeviceManagerError::StartNetCapture)?;
+        } else {
+            capture.stop();
+        }
+
+        Ok(())
+    }
+
+    /// Report the link of a virtio-net device as up or down to the guest.
+    pub fn net_link(&self, id: &str, up: bool) -> DeviceManagerResult<()> {
+        self.net_devices
+            .get(id)
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.to_owned()))?
+            .lock()
+            .unwrap()
+            .set_link_up(up)
+            .map_err(DeviceManagerError::SetNetLink)
+    }
+
     pub fn balloon_size(&self) -> u64 {
         if let Some(balloon) = &self.balloon {
             return balloon.lock().unwrap().get_actual();
@@ -3902,30 +4400,33 @@ impl Snapshottable for DeviceManager {
         // Finally, restore all devices associated with the DeviceManager.
         // It's important to restore devices in the right order, that's why
         // the device tree is the right way to ensure we restore a child before
-        // its parent node.
-        for node in self
-            .device_tree
-            .lock()
-            .unwrap()
-            .breadth_first_traversal()
-            .rev()
-        {
-            // Restore the node
-            if let Some(migratable) = &node.migratable {
-                debug!("Restoring {} from DeviceManager", node.id);
-                if let Some(snapshot) = snapshot.snapshots.get(&node.id) {
-                    migratable.lock().unwrap().pause()?;
-                    migratable.lock().unwrap().restore(*snapshot.clone())?;
-                } else {
-                    return Err(MigratableError::Restore(anyhow!(
-                        "Missing device {}",
-                        node.id
-                    )));
+        // its parent node. Restoring the devices activates them.
+        self.with_io_affinity(|| {
+            for node in self
+                .device_tree
+                .lock()
+                .unwrap()
+                .breadth_first_traversal()
+                .rev()
+            {
+                // Restore the node
+                if let Some(migratable) = &node.migratable {
+                    debug!("Restoring {} from DeviceManager", node.id);
+                    if let Some(snapshot) = snapshot.snapshots.get(&node.id) {
+                        migratable.lock().unwrap().pause()?;
+                        migratable.lock().unwrap().restore(*snapshot.clone())?;
+                    } else {
+                        return Err(MigratableError::Restore(anyhow!(
+                            "Missing device {}",
+                            node.id
+                        )));
+                    }
                 }
             }
-        }
 
-        Ok(())
+            Ok(())
+        })
+        .map_err(|e| MigratableError::Restore(anyhow!("Could not restore devices {:?}", e)))?
     }
 }
 
@@ -0,0 +1,381 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Per-vCPU exit accounting.
+//!
+//! Every vCPU counts its exits by type, port I/O and MMIO accesses being
+//! further broken down by port and by device. The exits can also be logged
+//! along with a timestamp to a trace file, shared by all the vCPUs.
+
+use hypervisor::vm::{Result as HypervisorVmResult, VmmOps};
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use vm_device::Bus;
+
+/// Exits accounted for by the vCPUs
+#[derive(Clone, Copy, Debug)]
+pub enum VcpuExit {
+    #[cfg(target_arch = "x86_64")]
+    IoIn {
+        port: u64,
+        len: usize,
+    },
+    #[cfg(target_arch = "x86_64")]
+    IoOut {
+        port: u64,
+        len: usize,
+    },
+    MmioRead {
+        addr: u64,
+        device: Option<u64>,
+        len: usize,
+    },
+    MmioWrite {
+        addr: u64,
+        device: Option<u64>,
+        len: usize,
+    },
+    #[cfg(target_arch = "x86_64")]
+    IoapicEoi {
+        vector: u8,
+    },
+    Hyperv,
+}
+
+impl fmt::Display for VcpuExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VcpuExit::*;
+
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            IoIn { port, len } => write!(f, "io_in port={:#x} len={}", port, len),
+            #[cfg(target_arch = "x86_64")]
+            IoOut { port, len } => write!(f, "io_out port={:#x} len={}", port, len),
+            MmioRead { addr, device, len } => write!(
+                f,
+                "mmio_read addr={:#x} device={} len={}",
+                addr,
+                DeviceKey(device),
+                len
+            ),
+            MmioWrite { addr, device, len } => write!(
+                f,
+                "mmio_write addr={:#x} device={} len={}",
+                addr,
+                DeviceKey(device),
+                len
+            ),
+            #[cfg(target_arch = "x86_64")]
+            IoapicEoi { vector } => write!(f, "ioapic_eoi vector={:#x}", vector),
+            Hyperv => write!(f, "hyperv"),
+        }
+    }
+}
+
+// Base address of the device region an MMIO access hit, if any
+struct DeviceKey<'a>(&'a Option<u64>);
+
+impl<'a> fmt::Display for DeviceKey<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(base) => write!(f, "{:#x}", base),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+fn serialize_device_keys<S>(
+    map: &BTreeMap<Option<u64>, u64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_map(map.iter().map(|(k, v)| (DeviceKey(k).to_string(), v)))
+}
+
+fn serialize_hex_keys<S>(map: &BTreeMap<u64, u64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_map(map.iter().map(|(k, v)| (format!("{:#x}", k), v)))
+}
+
+/// Exit counters of a vCPU
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct VcpuExitCounters {
+    /// Port I/O reads, indexed by port
+    #[serde(serialize_with = "serialize_hex_keys")]
+    pub io_in: BTreeMap<u64, u64>,
+    /// Port I/O writes, indexed by port
+    #[serde(serialize_with = "serialize_hex_keys")]
+    pub io_out: BTreeMap<u64, u64>,
+    /// MMIO reads, indexed by the base address of the device region, the
+    /// accesses to unregistered addresses being counted together
+    #[serde(serialize_with = "serialize_device_keys")]
+    pub mmio_read: BTreeMap<Option<u64>, u64>,
+    /// MMIO writes, indexed by the base address of the device region, the
+    /// accesses to unregistered addresses being counted together
+    #[serde(serialize_with = "serialize_device_keys")]
+    pub mmio_write: BTreeMap<Option<u64>, u64>,
+    pub ioapic_eoi: u64,
+    pub hyperv: u64,
+}
+
+impl VcpuExitCounters {
+    fn account(&mut self, exit: &VcpuExit) {
+        use self::VcpuExit::*;
+
+        match *exit {
+            #[cfg(target_arch = "x86_64")]
+            IoIn { port, .. } => *self.io_in.entry(port).or_default() += 1,
+            #[cfg(target_arch = "x86_64")]
+            IoOut { port, .. } => *self.io_out.entry(port).or_default() += 1,
+            MmioRead { device, .. } => *self.mmio_read.entry(device).or_default() += 1,
+            MmioWrite { device, .. } => *self.mmio_write.entry(device).or_default() += 1,
+            #[cfg(target_arch = "x86_64")]
+            IoapicEoi { .. } => self.ioapic_eoi += 1,
+            Hyperv => self.hyperv += 1,
+        }
+    }
+}
+
+/// Trace file the vCPU exits are logged to, one per line, prefixed with the
+/// time elapsed since the trace was started and the vCPU id.
+pub struct ExitTracer {
+    start: Instant,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl ExitTracer {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(ExitTracer {
+            start: Instant::now(),
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    fn trace(&self, cpu_id: u32, exit: &VcpuExit) {
+        let elapsed = self.start.elapsed();
+        let mut file = self.file.lock().unwrap();
+
+        if let Err(e) = writeln!(
+            file,
+            "{}.{:06} vcpu{} {}",
+            elapsed.as_secs(),
+            elapsed.subsec_micros(),
+            cpu_id,
+            exit
+        ) {
+            warn!("Error writing vCPU exit trace: {}", e);
+        }
+    }
+
+    /// Write out the buffered trace.
+    pub fn flush(&self) {
+        if let Err(e) = self.file.lock().unwrap().flush() {
+            warn!("Error flushing vCPU exit trace: {}", e);
+        }
+    }
+}
+
+/// Exit accounting of a single vCPU
+pub struct VcpuExitStats {
+    cpu_id: u32,
+    counters: Mutex<VcpuExitCounters>,
+    tracer: Option<Arc<ExitTracer>>,
+}
+
+impl VcpuExitStats {
+    pub fn new(cpu_id: u32, tracer: Option<Arc<ExitTracer>>) -> Self {
+        VcpuExitStats {
+            cpu_id,
+            counters: Mutex::new(VcpuExitCounters::default()),
+            tracer,
+        }
+    }
+
+    pub fn account(&self, exit: VcpuExit) {
+        self.counters.lock().unwrap().account(&exit);
+
+        if let Some(tracer) = &self.tracer {
+            tracer.trace(self.cpu_id, &exit);
+        }
+    }
+
+    pub fn counters(&self) -> VcpuExitCounters {
+        self.counters.lock().unwrap().clone()
+    }
+}
+
+/// Accounts for the port I/O and MMIO exits of a vCPU, which are handled by
+/// the hypervisor layer through the VmmOps of the vCPU, before forwarding them
+/// to the VmmOps shared by all the vCPUs.
+pub struct VcpuExitOps {
+    vmmops: Arc<Box<dyn VmmOps>>,
+    mmio_bus: Arc<Bus>,
+    stats: Arc<VcpuExitStats>,
+}
+
+impl VcpuExitOps {
+    pub fn new(
+        vmmops: Arc<Box<dyn VmmOps>>,
+        mmio_bus: Arc<Bus>,
+        stats: Arc<VcpuExitStats>,
+    ) -> Self {
+        VcpuExitOps {
+            vmmops,
+            mmio_bus,
+            stats,
+        }
+    }
+
+    // MMIO accesses are accounted to the device region they hit. The ones to
+    // unregistered addresses are counted together, so that a guest probing
+    // the address space can't grow the counters, their address only being
+    // logged to the trace.
+    fn mmio_device(&self, addr: u64) -> Option<u64> {
+        self.mmio_bus.resolve(addr).map(|(base, _, _)| base)
+    }
+}
+
+impl VmmOps for VcpuExitOps {
+    fn guest_mem_write(&self, gpa: u64, buf: &[u8]) -> HypervisorVmResult<usize> {
+        self.vmmops.guest_mem_write(gpa, buf)
+    }
+
+    fn guest_mem_read(&self, gpa: u64, buf: &mut [u8]) -> HypervisorVmResult<usize> {
+        self.vmmops.guest_mem_read(gpa, buf)
+    }
+
+    fn mmio_read(&self, gpa: u64, data: &mut [u8]) -> HypervisorVmResult<()> {
+        self.stats.account(VcpuExit::MmioRead {
+            addr: gpa,
+            device: self.mmio_device(gpa),
+            len: data.len(),
+        });
+        self.vmmops.mmio_read(gpa, data)
+    }
+
+    fn mmio_write(&self, gpa: u64, data: &[u8]) -> HypervisorVmResult<()> {
+        self.stats.account(VcpuExit::MmioWrite {
+            addr: gpa,
+            device: self.mmio_device(gpa),
+            len: data.len(),
+        });
+        self.vmmops.mmio_write(gpa, data)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pio_read(&self, port: u64, data: &mut [u8]) -> HypervisorVmResult<()> {
+        self.stats.account(VcpuExit::IoIn {
+            port,
+            len: data.len(),
+        });
+        self.vmmops.pio_read(port, data)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pio_write(&self, port: u64, data: &[u8]) -> HypervisorVmResult<()> {
+        self.stats.account(VcpuExit::IoOut {
+            port,
+            len: data.len(),
+        });
+        self.vmmops.pio_write(port, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_counters() {
+        let stats = VcpuExitStats::new(0, None);
+
+        stats.account(VcpuExit::MmioWrite {
+            addr: 0xd000_0010,
+            device: Some(0xd000_0000),
+            len: 4,
+        });
+        stats.account(VcpuExit::MmioWrite {
+            addr: 0xd000_0020,
+            device: Some(0xd000_0000),
+            len: 4,
+        });
+        stats.account(VcpuExit::MmioRead {
+            addr: 0xd000_1000,
+            device: Some(0xd000_1000),
+            len: 2,
+        });
+        stats.account(VcpuExit::MmioRead {
+            addr: 0xe000_0000,
+            device: None,
+            len: 4,
+        });
+        stats.account(VcpuExit::MmioRead {
+            addr: 0xe000_1000,
+            device: None,
+            len: 4,
+        });
+        stats.account(VcpuExit::Hyperv);
+
+        let counters = stats.counters();
+        assert_eq!(counters.mmio_write.get(&Some(0xd000_0000)), Some(&2));
+        assert_eq!(counters.mmio_read.get(&Some(0xd000_1000)), Some(&1));
+        assert_eq!(counters.mmio_read.get(&None), Some(&2));
+        assert_eq!(counters.hyperv, 1);
+
+        assert_eq!(
+            serde_json::to_string(&counters).unwrap(),
+            "{\"io_in\":{},\"io_out\":{},\
+             \"mmio_read\":{\"unknown\":2,\"0xd0001000\":1},\
+             \"mmio_write\":{\"0xd0000000\":2},\"ioapic_eoi\":0,\"hyperv\":1}"
+        );
+    }
+
+    #[test]
+    fn test_exit_format() {
+        assert_eq!(
+            VcpuExit::MmioRead {
+                addr: 0xd000_0010,
+                device: Some(0xd000_0000),
+                len: 4
+            }
+            .to_string(),
+            "mmio_read addr=0xd0000010 device=0xd0000000 len=4"
+        );
+        assert_eq!(
+            VcpuExit::MmioWrite {
+                addr: 0xe000_0000,
+                device: None,
+                len: 4
+            }
+            .to_string(),
+            "mmio_write addr=0xe0000000 device=unknown len=4"
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            VcpuExit::IoOut {
+                port: 0x3f8,
+                len: 1
+            }
+            .to_string(),
+            "io_out port=0x3f8 len=1"
+        );
+    }
+}
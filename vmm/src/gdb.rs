@@ -0,0 +1,811 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Minimal GDB remote serial protocol server, giving access to the guest
+//! registers and memory, and controlling its execution through software
+//! and hardware breakpoints and single-stepping.
+//!
+//! The server runs in its own thread and forwards each request to the VMM
+//! thread, which owns the VM. vCPUs stopping on a debug event are reported
+//! back by the VMM thread through a dedicated channel.
+
+use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::vm::Error as VmError;
+use hypervisor::x86_64::{SpecialRegisters, StandardRegisters};
+use seccomp::{SeccompAction, SeccompFilter};
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::result;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+use vm_memory::GuestAddress;
+use vmm_sys_util::eventfd::EventFd;
+
+// Signals reported to the debugger when the guest stops.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+// Instruction used to implement software breakpoints.
+const INT3: u8 = 0xcc;
+
+// Maximum size of the packets received from the debugger, as advertised to
+// it. Memory reads are limited to what fits in a reply of the same size.
+const PACKET_SIZE: usize = 0x1000;
+const MAX_MEM_LEN: u64 = (PACKET_SIZE / 2) as u64;
+
+// Number of x86 debug address registers.
+const MAX_HW_BREAKPOINTS: usize = 4;
+
+// How often the debugger connection is checked for an interruption while
+// the guest is running.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Errors associated with the GDB stub
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Cannot read from or write to the debugger connection
+    #[error("Error on GDB connection: {0}")]
+    Connection(#[source] io::Error),
+
+    /// Cannot send a request to the VMM thread
+    #[error("Error sending GDB request to the VMM thread")]
+    RequestSend,
+
+    /// Cannot receive a response from the VMM thread
+    #[error("Error receiving GDB response from the VMM thread")]
+    ResponseRecv,
+
+    /// Cannot notify the VMM thread
+    #[error("Error notifying the VMM thread: {0}")]
+    EventFdWrite(#[source] io::Error),
+
+    /// The VMM thread stopped reporting debug events
+    #[error("VMM thread stopped reporting debug events")]
+    VmEventRecv,
+}
+pub type Result<T> = result::Result<T, Error>;
+
+/// vCPU registers reported to the debugger.
+pub struct CoreRegs {
+    pub regs: StandardRegisters,
+    pub sregs: SpecialRegisters,
+}
+
+pub enum GdbRequestPayload {
+    ReadRegs,
+    WriteRegs(Box<StandardRegisters>),
+    ReadMem(GuestAddress, usize),
+    WriteMem(GuestAddress, Vec<u8>),
+    Pause,
+    Resume,
+    SetGuestDebug {
+        hw_breakpoints: Vec<u64>,
+        sw_breakpoints: bool,
+        single_step: bool,
+    },
+    ActiveVcpus,
+}
+
+pub enum GdbResponsePayload {
+    CommandComplete,
+    RegValues(Box<CoreRegs>),
+    MemoryRegion(Vec<u8>),
+    ActiveVcpus(u32),
+}
+
+pub type GdbResponse = result::Result<GdbResponsePayload, VmError>;
+
+pub struct GdbRequest {
+    pub sender: Sender<GdbResponse>,
+    pub payload: GdbRequestPayload,
+    /// The vCPU the request applies to
+    pub cpu_id: u32,
+}
+
+enum Packet {
+    Command(Vec<u8>),
+    Interrupt,
+}
+
+enum Action {
+    Reply(String),
+    Resume { single_step: bool },
+    Detach { reply: bool },
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() % 2 != 0 {
+        return None;
+    }
+
+    data.chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+        })
+        .collect()
+}
+
+fn parse_hex(data: &[u8]) -> Option<u64> {
+    std::str::from_utf8(data)
+        .ok()
+        .and_then(|s| u64::from_str_radix(s, 16).ok())
+}
+
+// Parses "<addr>,<len>" as found in memory and breakpoint packets.
+fn parse_addr_len(data: &[u8]) -> Option<(u64, u64)> {
+    let mut fields = data.splitn(2, |b| *b == b',');
+    let addr = parse_hex(fields.next()?)?;
+    let len = parse_hex(fields.next()?)?;
+    Some((addr, len))
+}
+
+// Register layout of the "g" packet for the i386:x86-64 architecture, up to
+// the segment selectors. Floating point and vector registers are omitted,
+// which the debugger reports as unavailable.
+fn encode_regs(core_regs: &CoreRegs) -> String {
+    let regs = &core_regs.regs;
+    let sregs = &core_regs.sregs;
+    let mut data = Vec::new();
+    for reg in [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+    ]
+    .iter()
+    {
+        data.extend_from_slice(&reg.to_le_bytes());
+    }
+    data.extend_from_slice(&(regs.rflags as u32).to_le_bytes());
+    for selector in [
+        sregs.cs.selector,
+        sregs.ss.selector,
+        sregs.ds.selector,
+        sregs.es.selector,
+        sregs.fs.selector,
+        sregs.gs.selector,
+    ]
+    .iter()
+    {
+        data.extend_from_slice(&u32::from(*selector).to_le_bytes());
+    }
+
+    hex_encode(&data)
+}
+
+// Updates the general purpose registers, the instruction pointer and the
+// flags from a "G" packet. Segment selectors can't be changed without
+// reloading the matching descriptors, hence they are ignored.
+fn decode_regs(data: &[u8], regs: &mut StandardRegisters) -> Option<()> {
+    let data = hex_decode(data)?;
+    if data.len() < 17 * 8 + 4 {
+        return None;
+    }
+
+    let mut values = data.chunks_exact(8).map(|b| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(b);
+        u64::from_le_bytes(bytes)
+    });
+    for reg in [
+        &mut regs.rax,
+        &mut regs.rbx,
+        &mut regs.rcx,
+        &mut regs.rdx,
+        &mut regs.rsi,
+        &mut regs.rdi,
+        &mut regs.rbp,
+        &mut regs.rsp,
+        &mut regs.r8,
+        &mut regs.r9,
+        &mut regs.r10,
+        &mut regs.r11,
+        &mut regs.r12,
+        &mut regs.r13,
+        &mut regs.r14,
+        &mut regs.r15,
+        &mut regs.rip,
+    ]
+    .iter_mut()
+    {
+        **reg = values.next()?;
+    }
+    let mut rflags = [0u8; 4];
+    rflags.copy_from_slice(&data[17 * 8..17 * 8 + 4]);
+    regs.rflags = u64::from(u32::from_le_bytes(rflags));
+
+    Some(())
+}
+
+struct GdbStub {
+    debug_evt: EventFd,
+    request_sender: Sender<GdbRequest>,
+    vm_event_receiver: Receiver<u32>,
+    // Per connection state
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    cpu_id: u32,
+    sw_breakpoints: HashMap<u64, u8>,
+    hw_breakpoints: Vec<u64>,
+    swbreak: bool,
+    hwbreak: bool,
+}
+
+impl GdbStub {
+    fn new(
+        stream: UnixStream,
+        debug_evt: EventFd,
+        request_sender: Sender<GdbRequest>,
+        vm_event_receiver: Receiver<u32>,
+    ) -> Result<Self> {
+        let writer = stream.try_clone().map_err(Error::Connection)?;
+
+        Ok(GdbStub {
+            debug_evt,
+            request_sender,
+            vm_event_receiver,
+            reader: BufReader::new(stream),
+            writer,
+            cpu_id: 0,
+            sw_breakpoints: HashMap::new(),
+            hw_breakpoints: Vec::new(),
+            swbreak: false,
+            hwbreak: false,
+        })
+    }
+
+    // Forwards the request to the VMM thread. The outer error is fatal to
+    // the connection, the inner one is reported to the debugger.
+    fn request(&self, payload: GdbRequestPayload) -> Result<GdbResponse> {
+        let (sender, receiver) = channel();
+        self.request_sender
+            .send(GdbRequest {
+                sender,
+                payload,
+                cpu_id: self.cpu_id,
+            })
+            .map_err(|_| Error::RequestSend)?;
+        self.debug_evt.write(1).map_err(Error::EventFdWrite)?;
+
+        let response = receiver.recv().map_err(|_| Error::ResponseRecv)?;
+        if let Err(e) = &response {
+            warn!("GDB request failed: {:?}", e);
+        }
+
+        Ok(response)
+    }
+
+    fn read_mem(&self, addr: u64, len: usize) -> Result<Option<Vec<u8>>> {
+        Ok(
+            match self.request(GdbRequestPayload::ReadMem(GuestAddress(addr), len))? {
+                Ok(GdbResponsePayload::MemoryRegion(data)) => Some(data),
+                _ => None,
+            },
+        )
+    }
+
+    fn write_mem(&self, addr: u64, data: Vec<u8>) -> Result<bool> {
+        Ok(self
+            .request(GdbRequestPayload::WriteMem(GuestAddress(addr), data))?
+            .is_ok())
+    }
+
+    fn active_vcpus(&self) -> Result<u32> {
+        Ok(match self.request(GdbRequestPayload::ActiveVcpus)? {
+            Ok(GdbResponsePayload::ActiveVcpus(count)) => count,
+            _ => 0,
+        })
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        match self.reader.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(e) => Err(Error::Connection(e)),
+        }
+    }
+
+    // Reads the next packet, acknowledging it. Returns None once the
+    // debugger disconnected. Packets larger than the advertised size are
+    // rejected.
+    fn read_packet(&mut self) -> Result<Option<Packet>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(0x03) => return Ok(Some(Packet::Interrupt)),
+                Some(b'$') => {
+                    let mut data = Vec::new();
+                    let mut oversized = false;
+                    loop {
+                        match self.read_byte()? {
+                            None => return Ok(None),
+                            Some(b'#') => break,
+                            Some(_) if data.len() == PACKET_SIZE => oversized = true,
+                            Some(b) => data.push(b),
+                        }
+                    }
+                    let mut sum = [0u8; 2];
+                    for b in sum.iter_mut() {
+                        match self.read_byte()? {
+                            None => return Ok(None),
+                            Some(c) => *b = c,
+                        }
+                    }
+
+                    if !oversized && parse_hex(&sum) == Some(u64::from(checksum(&data))) {
+                        self.writer.write_all(b"+").map_err(Error::Connection)?;
+                        return Ok(Some(Packet::Command(data)));
+                    }
+                    self.writer.write_all(b"-").map_err(Error::Connection)?;
+                }
+                // Acknowledgments and anything outside a packet
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.writer
+            .write_all(packet.as_bytes())
+            .map_err(Error::Connection)
+    }
+
+    fn stop_reply(&self, signal: u8) -> Result<String> {
+        let mut reply = format!("T{:02x}thread:{:x};", signal, self.cpu_id + 1);
+        if signal == SIGTRAP {
+            if let Ok(GdbResponsePayload::RegValues(regs)) =
+                self.request(GdbRequestPayload::ReadRegs)?
+            {
+                let rip = regs.regs.rip;
+                if self.swbreak && self.sw_breakpoints.contains_key(&rip) {
+                    reply.push_str("swbreak:;");
+                } else if self.hwbreak && self.hw_breakpoints.contains(&rip) {
+                    reply.push_str("hwbreak:;");
+                }
+            }
+        }
+
+        Ok(reply)
+    }
+
+    fn set_guest_debug(&self, single_step: bool) -> Result<bool> {
+        Ok(self
+            .request(GdbRequestPayload::SetGuestDebug {
+                hw_breakpoints: self.hw_breakpoints.clone(),
+                sw_breakpoints: !self.sw_breakpoints.is_empty(),
+                single_step,
+            })?
+            .is_ok())
+    }
+
+    // Waits for a vCPU to stop on a debug event, or for the debugger to
+    // interrupt the guest. Returns None once the debugger disconnected.
+    fn wait_for_stop(&mut self) -> Result<Option<String>> {
+        loop {
+            match self.vm_event_receiver.recv_timeout(STOP_POLL_INTERVAL) {
+                Ok(cpu_id) => {
+                    self.cpu_id = cpu_id;
+                    return self.stop_reply(SIGTRAP).map(Some);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::VmEventRecv),
+            }
+
+            self.reader
+                .get_ref()
+                .set_nonblocking(true)
+                .map_err(Error::Connection)?;
+            let mut byte = [0u8; 1];
+            let read = self.reader.read(&mut byte);
+            self.reader
+                .get_ref()
+                .set_nonblocking(false)
+                .map_err(Error::Connection)?;
+            match read {
+                Ok(0) => return Ok(None),
+                Ok(_) if byte[0] == 0x03 => {
+                    let _ = self.request(GdbRequestPayload::Pause)?;
+                    return self.stop_reply(SIGINT).map(Some);
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(Error::Connection(e)),
+            }
+        }
+    }
+
+    fn resume(&mut self, single_step: bool) -> Result<bool> {
+        // Drop any stop which raced with an interruption from the debugger.
+        while self.vm_event_receiver.try_recv().is_ok() {}
+
+        if !self.set_guest_debug(single_step)? {
+            return Ok(false);
+        }
+
+        Ok(self.request(GdbRequestPayload::Resume)?.is_ok())
+    }
+
+    // Removes all breakpoints and lets the guest run freely.
+    fn detach(&mut self) -> Result<()> {
+        for (addr, byte) in std::mem::take(&mut self.sw_breakpoints) {
+            self.write_mem(addr, vec![byte])?;
+        }
+        self.hw_breakpoints.clear();
+        self.resume(false)?;
+
+        Ok(())
+    }
+
+    fn select_thread(&mut self, data: &[u8]) -> Result<String> {
+        // "0" and "-1" leave the choice to the stub.
+        if data == b"0" || data == b"-1" {
+            return Ok("OK".to_string());
+        }
+
+        match parse_hex(data) {
+            Some(tid) if tid > 0 && tid <= u64::from(self.active_vcpus()?) => {
+                self.cpu_id = (tid - 1) as u32;
+                Ok("OK".to_string())
+            }
+            _ => Ok("E01".to_string()),
+        }
+    }
+
+    fn insert_breakpoint(&mut self, kind: u8, addr: u64) -> Result<String> {
+        match kind {
+            b'0' => {
+                if !self.sw_breakpoints.contains_key(&addr) {
+                    let byte = match self.read_mem(addr, 1)? {
+                        Some(data) => data[0],
+                        None => return Ok("E01".to_string()),
+                    };
+                    if !self.write_mem(addr, vec![INT3])? {
+                        return Ok("E01".to_string());
+                    }
+                    self.sw_breakpoints.insert(addr, byte);
+                }
+                Ok("OK".to_string())
+            }
+            b'1' => {
+                if !self.hw_breakpoints.contains(&addr) {
+                    if self.hw_breakpoints.len() == MAX_HW_BREAKPOINTS {
+                        return Ok("E01".to_string());
+                    }
+                    self.hw_breakpoints.push(addr);
+                }
+                Ok("OK".to_string())
+            }
+            // Watchpoints are not supported.
+            _ => Ok(String::new()),
+        }
+    }
+
+    fn remove_breakpoint(&mut self, kind: u8, addr: u64) -> Result<String> {
+        match kind {
+            b'0' => {
+                if let Some(byte) = self.sw_breakpoints.remove(&addr) {
+                    if !self.write_mem(addr, vec![byte])? {
+                        return Ok("E01".to_string());
+                    }
+                }
+                Ok("OK".to_string())
+            }
+            b'1' => {
+                self.hw_breakpoints.retain(|a| *a != addr);
+                Ok("OK".to_string())
+            }
+            _ => Ok(String::new()),
+        }
+    }
+
+    fn handle_command(&mut self, cmd: &[u8]) -> Result<Action> {
+        let reply = match cmd.split_first() {
+            Some((b'?', _)) => self.stop_reply(SIGTRAP)?,
+            Some((b'g', _)) => match self.request(GdbRequestPayload::ReadRegs)? {
+                Ok(GdbResponsePayload::RegValues(regs)) => encode_regs(&regs),
+                _ => "E01".to_string(),
+            },
+            Some((b'G', data)) => match self.request(GdbRequestPayload::ReadRegs)? {
+                Ok(GdbResponsePayload::RegValues(core_regs)) => {
+                    let mut regs = core_regs.regs;
+                    if decode_regs(data, &mut regs).is_none() {
+                        "E01".to_string()
+                    } else if self
+                        .request(GdbRequestPayload::WriteRegs(Box::new(regs)))?
+                        .is_ok()
+                    {
+                        "OK".to_string()
+                    } else {
+                        "E01".to_string()
+                    }
+                }
+                _ => "E01".to_string(),
+            },
+            Some((b'm', data)) => match parse_addr_len(data) {
+                Some((addr, len)) if len <= MAX_MEM_LEN => {
+                    match self.read_mem(addr, len as usize)? {
+                        Some(data) => hex_encode(&data),
+                        None => "E01".to_string(),
+                    }
+                }
+                _ => "E01".to_string(),
+            },
+            Some((b'M', data)) => {
+                let mut fields = data.splitn(2, |b| *b == b':');
+                let range = fields.next().and_then(parse_addr_len);
+                let bytes = fields.next().and_then(hex_decode);
+                match (range, bytes) {
+                    (Some((addr, len)), Some(bytes)) if len as usize == bytes.len() => {
+                        if self.write_mem(addr, bytes)? {
+                            "OK".to_string()
+                        } else {
+                            "E01".to_string()
+                        }
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            Some((op @ b'Z', data)) | Some((op @ b'z', data)) => {
+                // "<type>,<addr>,<kind>"
+                let mut fields = data.splitn(3, |b| *b == b',');
+                let kind = fields.next().and_then(|f| f.first().copied());
+                let addr = fields.next().and_then(parse_hex);
+                match (kind, addr) {
+                    (Some(kind), Some(addr)) if *op == b'Z' => {
+                        self.insert_breakpoint(kind, addr)?
+                    }
+                    (Some(kind), Some(addr)) => self.remove_breakpoint(kind, addr)?,
+                    _ => "E01".to_string(),
+                }
+            }
+            // Resuming from a different address is not supported.
+            Some((b'c', _)) => return Ok(Action::Resume { single_step: false }),
+            Some((b's', _)) => return Ok(Action::Resume { single_step: true }),
+            Some((b'H', data)) if data.len() > 1 => self.select_thread(&data[1..])?,
+            Some((b'T', data)) => match parse_hex(data) {
+                Some(tid) if tid > 0 && tid <= u64::from(self.active_vcpus()?) => "OK".to_string(),
+                _ => "E01".to_string(),
+            },
+            Some((b'D', _)) => return Ok(Action::Detach { reply: true }),
+            Some((b'k', _)) => return Ok(Action::Detach { reply: false }),
+            _ if cmd.starts_with(b"qSupported") => {
+                let features = String::from_utf8_lossy(cmd);
+                self.swbreak = features.contains("swbreak+");
+                self.hwbreak = features.contains("hwbreak+");
+                format!("PacketSize={:x};swbreak+;hwbreak+", PACKET_SIZE)
+            }
+            _ if cmd == b"qAttached" => "1".to_string(),
+            _ if cmd == b"qC" => format!("QC{:x}", self.cpu_id + 1),
+            _ if cmd == b"qfThreadInfo" => {
+                let threads: Vec<String> = (1..=self.active_vcpus()?)
+                    .map(|tid| format!("{:x}", tid))
+                    .collect();
+                format!("m{}", threads.join(","))
+            }
+            _ if cmd == b"qsThreadInfo" => "l".to_string(),
+            // Unsupported packets get an empty reply.
+            _ => String::new(),
+        };
+
+        Ok(Action::Reply(reply))
+    }
+
+    fn serve(&mut self) -> Result<()> {
+        // The guest is stopped while the debugger is attached.
+        let _ = self.request(GdbRequestPayload::Pause)?;
+
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                None => break,
+            };
+
+            let action = match packet {
+                // The guest is already stopped.
+                Packet::Interrupt => continue,
+                Packet::Command(cmd) => self.handle_command(&cmd)?,
+            };
+
+            match action {
+                Action::Reply(reply) => self.send_packet(&reply)?,
+                Action::Resume { single_step } => {
+                    if !self.resume(single_step)? {
+                        self.send_packet("E01")?;
+                        continue;
+                    }
+                    match self.wait_for_stop()? {
+                        Some(reply) => self.send_packet(&reply)?,
+                        None => break,
+                    }
+                }
+                Action::Detach { reply } => {
+                    if reply {
+                        self.send_packet("OK")?;
+                    }
+                    return self.detach();
+                }
+            }
+        }
+
+        // The debugger went away without detaching.
+        let _ = self.request(GdbRequestPayload::Pause)?;
+        self.detach()
+    }
+}
+
+/// Serves debugger connections on the UNIX socket at `path`, one at a time.
+pub fn start_gdb_thread(
+    path: PathBuf,
+    debug_evt: EventFd,
+    request_sender: Sender<GdbRequest>,
+    vm_event_receiver: Receiver<u32>,
+    seccomp_action: &SeccompAction,
+) -> crate::Result<()> {
+    let listener = UnixListener::bind(&path).map_err(crate::Error::Bind)?;
+
+    // Retrieve seccomp filter for GDB thread
+    let gdb_seccomp_filter = get_seccomp_filter(seccomp_action, Thread::Gdb)
+        .map_err(crate::Error::CreateSeccompFilter)?;
+
+    thread::Builder::new()
+        .name("gdb".to_string())
+        .spawn(move || {
+            // Apply seccomp filter for GDB thread.
+            if let Err(e) = SeccompFilter::apply(gdb_seccomp_filter) {
+                error!("Error applying seccomp filter: {:?}", e);
+                return;
+            }
+
+            let mut vm_event_receiver = vm_event_receiver;
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("Error accepting GDB connection: {}", e);
+                        continue;
+                    }
+                };
+
+                info!("GDB connected on {:?}", path);
+                let debug_evt = match debug_evt.try_clone() {
+                    Ok(debug_evt) => debug_evt,
+                    Err(e) => {
+                        error!("Error cloning EventFd: {}", e);
+                        break;
+                    }
+                };
+                let mut stub = match GdbStub::new(
+                    stream,
+                    debug_evt,
+                    request_sender.clone(),
+                    vm_event_receiver,
+                ) {
+                    Ok(stub) => stub,
+                    Err(e) => {
+                        error!("Error setting up GDB connection: {}", e);
+                        break;
+                    }
+                };
+                if let Err(e) = stub.serve() {
+                    error!("Error serving GDB connection: {}", e);
+                }
+                info!("GDB disconnected");
+                vm_event_receiver = stub.vm_event_receiver;
+            }
+        })
+        .map_err(crate::Error::GdbThreadSpawn)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(b"qSupported"), 0x37);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex_encode(&[0x00, 0xcc, 0x7f]), "00cc7f");
+        assert_eq!(hex_decode(b"00cc7f"), Some(vec![0x00, 0xcc, 0x7f]));
+        assert_eq!(hex_decode(b"00c"), None);
+        assert_eq!(hex_decode(b"zz"), None);
+        assert_eq!(parse_hex(b"ffffffff81000000"), Some(0xffff_ffff_8100_0000));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_addr_len(b"1000,4"), Some((0x1000, 4)));
+        assert_eq!(parse_addr_len(b"1000"), None);
+    }
+
+    #[test]
+    fn test_regs() {
+        let mut core_regs = CoreRegs {
+            regs: StandardRegisters::default(),
+            sregs: SpecialRegisters::default(),
+        };
+        core_regs.regs.rax = 0x1122_3344_5566_7788;
+        core_regs.regs.rip = 0xffff_ffff_8100_0000;
+        core_regs.regs.rflags = 0x246;
+        core_regs.sregs.cs.selector = 0x10;
+
+        let encoded = encode_regs(&core_regs);
+        // 17 64-bit registers, eflags and 6 segment selectors
+        assert_eq!(encoded.len(), 2 * (17 * 8 + 7 * 4));
+        assert!(encoded.starts_with("8877665544332211"));
+
+        let mut regs = StandardRegisters::default();
+        assert!(decode_regs(encoded.as_bytes(), &mut regs).is_some());
+        assert_eq!(regs.rax, core_regs.regs.rax);
+        assert_eq!(regs.rip, core_regs.regs.rip);
+        assert_eq!(regs.rflags, 0x246);
+
+        assert!(decode_regs(&encoded.as_bytes()[..16], &mut regs).is_none());
+    }
+
+    fn create_stub() -> (GdbStub, UnixStream) {
+        let (stream, debugger) = UnixStream::pair().unwrap();
+        let (request_sender, _) = channel();
+        let (_, vm_event_receiver) = channel();
+        let stub = GdbStub::new(
+            stream,
+            EventFd::new(0).unwrap(),
+            request_sender,
+            vm_event_receiver,
+        )
+        .unwrap();
+
+        (stub, debugger)
+    }
+
+    #[test]
+    fn test_read_packet_size() {
+        let (mut stub, mut debugger) = create_stub();
+
+        let mut oversized = vec![b'$'];
+        oversized.extend(vec![b'a'; PACKET_SIZE + 1]);
+        oversized.extend(b"#00$OK#9a");
+        debugger.write_all(&oversized).unwrap();
+
+        match stub.read_packet().unwrap() {
+            Some(Packet::Command(data)) => assert_eq!(data, b"OK"),
+            _ => panic!("Expected a command packet"),
+        }
+        let mut acks = [0u8; 2];
+        debugger.read_exact(&mut acks).unwrap();
+        assert_eq!(&acks, b"-+");
+    }
+
+    #[test]
+    fn test_read_mem_len() {
+        let (mut stub, _debugger) = create_stub();
+
+        // Rejected without reaching the VMM thread, which is not there.
+        for cmd in [&b"m0,ffffffffffffffff"[..], &b"m1000,801"[..]].iter() {
+            match stub.handle_command(cmd).unwrap() {
+                Action::Reply(reply) => assert_eq!(reply, "E01"),
+                _ => panic!("Expected a reply"),
+            }
+        }
+        assert!(stub.handle_command(b"m1000,800").is_err());
+    }
+}
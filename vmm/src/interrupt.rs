@@ -341,6 +341,30 @@ pub mod kvm {
     type KvmRoutingEntry = RoutingEntry<kvm_irq_routing_entry>;
     pub type KvmMsiInterruptManager = MsiInterruptManager<kvm_irq_routing_entry>;
 
+    // Bits [11:5] of the MSI address hold the bits [14:8] of the destination
+    // APIC ID when the guest uses the extended destination ID.
+    #[cfg(target_arch = "x86_64")]
+    const MSI_EXT_DEST_ID_SHIFT: u32 = 5;
+    #[cfg(target_arch = "x86_64")]
+    const MSI_EXT_DEST_ID_MASK: u32 = 0x7f;
+
+    // With the x2APIC API enabled, KVM expects the bits [31:8] of the
+    // destination APIC ID in the high address, and rejects routes carrying
+    // anything in the low byte of it. Move the extended destination ID there,
+    // the same way QEMU does before handing the route to KVM.
+    #[cfg(target_arch = "x86_64")]
+    fn fixup_msi_address(address_lo: u32, address_hi: u32) -> (u32, u32) {
+        let ext_dest_id = (address_lo >> MSI_EXT_DEST_ID_SHIFT) & MSI_EXT_DEST_ID_MASK;
+        if ext_dest_id == 0 {
+            return (address_lo, address_hi);
+        }
+
+        (
+            address_lo & !(MSI_EXT_DEST_ID_MASK << MSI_EXT_DEST_ID_SHIFT),
+            address_hi | ext_dest_id << 8,
+        )
+    }
+
     impl KvmRoutingEntry {
         pub fn make_entry(
             vm: &Arc<dyn hypervisor::Vm>,
@@ -354,8 +378,13 @@ pub mod kvm {
                     ..Default::default()
                 };
 
-                kvm_route.u.msi.address_lo = cfg.low_addr;
-                kvm_route.u.msi.address_hi = cfg.high_addr;
+                #[cfg(target_arch = "x86_64")]
+                let (address_lo, address_hi) = fixup_msi_address(cfg.low_addr, cfg.high_addr);
+                #[cfg(not(target_arch = "x86_64"))]
+                let (address_lo, address_hi) = (cfg.low_addr, cfg.high_addr);
+
+                kvm_route.u.msi.address_lo = address_lo;
+                kvm_route.u.msi.address_hi = address_hi;
                 kvm_route.u.msi.data = cfg.data;
 
                 if vm.check_extension(hypervisor::Cap::MsiDevid) {
@@ -391,6 +420,29 @@ pub mod kvm {
             ))
         }
     }
+
+    #[cfg(target_arch = "x86_64")]
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_fixup_msi_address() {
+            // Fixed delivery to APIC ID 300, i.e. 0x12c: the low 8 bits go in
+            // the bits [19:12] of the address, the next ones in [11:5].
+            let apic_id: u32 = 300;
+            let address_lo = 0xfee0_0000 | (apic_id & 0xff) << 12 | (apic_id >> 8) << 5;
+            assert_eq!(
+                fixup_msi_address(address_lo, 0),
+                (0xfee0_0000 | (apic_id & 0xff) << 12, (apic_id >> 8) << 8)
+            );
+            assert_eq!(fixup_msi_address(address_lo, 0).1 & 0xff, 0);
+
+            // Nothing to move for an APIC ID below 256.
+            let address_lo = 0xfee0_0000 | 0x2a << 12;
+            assert_eq!(fixup_msi_address(address_lo, 0), (address_lo, 0));
+        }
+    }
 }
 
 #[cfg(feature = "mshv")]
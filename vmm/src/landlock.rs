@@ -0,0 +1,727 @@
+// Copyright © 2021 Intel Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Landlock filesystem sandboxing of the VMM.
+//!
+//! Once the VM configuration is known, the VMM thread restricts its own
+//! filesystem accesses, and the ones of every thread it spawns afterwards
+//! (vCPUs, virtio devices, ...), to the paths referenced by the configuration
+//! along with the ones explicitly granted by the user.
+//!
+//! A Landlock domain can only be further restricted, never widened. The paths
+//! used by devices hotplugged later on must thus be covered by the ruleset
+//! enforced when the VM was created, which is checked before adding them so
+//! that the request fails with a clear error rather than with a permission
+//! denied deep down in the device creation.
+
+use crate::config::{
+    ConsoleConfig, ConsoleOutputMode, DeviceConfig, DiskConfig, FsConfig, GenericVhostUserConfig,
+    LandlockAccess, LandlockConfig, NetConfig, PmemConfig, UserDeviceConfig, VhostMode, VmConfig,
+    VsockConfig,
+};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::result;
+use thiserror::Error;
+
+// Same system call numbers on all the architectures.
+const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const LANDLOCK_ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const LANDLOCK_ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const LANDLOCK_ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const LANDLOCK_ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const LANDLOCK_ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const LANDLOCK_ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const LANDLOCK_ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const LANDLOCK_ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const LANDLOCK_ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const LANDLOCK_ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const LANDLOCK_ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const LANDLOCK_ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+
+// Accesses handled by the first version of the Landlock ABI, which are all
+// denied unless granted by a rule.
+const ACCESS_FS_ALL: u64 = (1 << 13) - 1;
+
+// Only these accesses can be granted on a file, the other ones being about
+// the content of directories.
+const ACCESS_FS_FILE: u64 =
+    LANDLOCK_ACCESS_FS_EXECUTE | LANDLOCK_ACCESS_FS_WRITE_FILE | LANDLOCK_ACCESS_FS_READ_FILE;
+
+const ACCESS_FS_READ: u64 = LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_READ_DIR;
+const ACCESS_FS_WRITE: u64 = LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_REMOVE_DIR
+    | LANDLOCK_ACCESS_FS_REMOVE_FILE
+    | LANDLOCK_ACCESS_FS_MAKE_CHAR
+    | LANDLOCK_ACCESS_FS_MAKE_DIR
+    | LANDLOCK_ACCESS_FS_MAKE_REG
+    | LANDLOCK_ACCESS_FS_MAKE_SOCK
+    | LANDLOCK_ACCESS_FS_MAKE_FIFO
+    | LANDLOCK_ACCESS_FS_MAKE_BLOCK
+    | LANDLOCK_ACCESS_FS_MAKE_SYM;
+
+const TUN_PATH: &str = "/dev/net/tun";
+const VHOST_NET_PATH: &str = "/dev/vhost-net";
+const VHOST_VSOCK_PATH: &str = "/dev/vhost-vsock";
+const VFIO_PATH: &str = "/dev/vfio";
+const PTMX_PATH: &str = "/dev/ptmx";
+const PTS_PTMX_PATH: &str = "/dev/pts/ptmx";
+const SYSFS_NET_PATH: &str = "/sys/class/net";
+#[cfg(target_arch = "x86_64")]
+const SGX_VEPC_PATH: &str = "/dev/sgx_vepc";
+
+#[repr(C)]
+#[allow(dead_code)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+#[allow(dead_code)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Errors associated with the Landlock sandbox
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Landlock is not available
+    #[error("Landlock is not supported or not enabled by the host kernel: {0}")]
+    NotSupported(#[source] io::Error),
+
+    /// Cannot create the ruleset
+    #[error("Error creating Landlock ruleset: {0}")]
+    CreateRuleset(#[source] io::Error),
+
+    /// Cannot open a path to add a rule for
+    #[error("Error opening {0:?} for Landlock rule: {1}")]
+    OpenPath(PathBuf, #[source] io::Error),
+
+    /// Cannot create an output file to add a rule for
+    #[error("Error creating {0:?} for Landlock rule: {1}")]
+    CreateOutput(PathBuf, #[source] io::Error),
+
+    /// Cannot add a rule to the ruleset
+    #[error("Error adding Landlock rule for {0:?}: {1}")]
+    AddRule(PathBuf, #[source] io::Error),
+
+    /// Cannot set the no_new_privs attribute
+    #[error("Error setting no_new_privs: {0}")]
+    NoNewPrivs(#[source] io::Error),
+
+    /// Cannot enforce the ruleset
+    #[error("Error enforcing Landlock ruleset: {0}")]
+    RestrictSelf(#[source] io::Error),
+
+    /// Access not granted by the enforced ruleset
+    #[error("Access to {0:?} is not allowed by the Landlock ruleset")]
+    NotAllowed(PathBuf),
+}
+pub type Result<T> = result::Result<T, Error>;
+
+fn access_bits(access: &LandlockAccess) -> u64 {
+    match access {
+        LandlockAccess::Read => ACCESS_FS_READ,
+        LandlockAccess::Write => ACCESS_FS_WRITE,
+        LandlockAccess::ReadWrite => ACCESS_FS_READ | ACCESS_FS_WRITE,
+    }
+}
+
+// Paths are resolved so that the rules can be matched against each other. A
+// path which does not exist yet is resolved through its parent directory,
+// which must exist.
+fn resolve_path(path: &Path) -> Result<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Ok(path);
+    }
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::OpenPath(path.to_path_buf(), io::ErrorKind::NotFound.into()))?;
+    parent_dir(path)
+        .canonicalize()
+        .map(|parent| parent.join(file_name))
+        .map_err(|e| Error::OpenPath(path.to_path_buf(), e))
+}
+
+// Directory a file or a socket is created in, relative paths being relative
+// to the working directory of the VMM.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+// Accesses that can actually be granted on a path, depending on it being a
+// directory or not.
+fn path_access(path: &Path, access: u64) -> u64 {
+    if path.is_dir() {
+        access
+    } else {
+        access & ACCESS_FS_FILE
+    }
+}
+
+/// Filesystem access needed by the VMM
+#[derive(Clone, Debug, PartialEq)]
+pub enum LandlockRule {
+    /// Access to an existing file, or to a directory and everything beneath it
+    Path(LandlockConfig),
+    /// File written by the VMM, created before enforcing the ruleset so that
+    /// only the file itself is granted
+    Output(PathBuf),
+    /// Socket created by the VMM, which is only allowed to create and remove
+    /// it in its parent directory
+    Socket(PathBuf),
+}
+
+impl From<LandlockConfig> for LandlockRule {
+    fn from(config: LandlockConfig) -> Self {
+        LandlockRule::Path(config)
+    }
+}
+
+impl LandlockRule {
+    // Accesses to grant, or to check, for the rule. An output file created
+    // once the ruleset is enforced also needs to be created in its parent
+    // directory.
+    fn accesses(&self) -> Result<Vec<(PathBuf, u64)>> {
+        match self {
+            LandlockRule::Path(config) => {
+                let path = resolve_path(&config.path)?;
+                let access = path_access(&path, access_bits(&config.access));
+                Ok(vec![(path, access)])
+            }
+            LandlockRule::Output(path) => {
+                let path = resolve_path(path)?;
+                let mut accesses = Vec::new();
+                if !path.exists() {
+                    accesses.push((parent_dir(&path).to_path_buf(), LANDLOCK_ACCESS_FS_MAKE_REG));
+                }
+                accesses.push((path, LANDLOCK_ACCESS_FS_WRITE_FILE));
+                Ok(accesses)
+            }
+            LandlockRule::Socket(path) => {
+                let path = resolve_path(path)?;
+                Ok(vec![(
+                    parent_dir(&path).to_path_buf(),
+                    LANDLOCK_ACCESS_FS_MAKE_SOCK | LANDLOCK_ACCESS_FS_REMOVE_FILE,
+                )])
+            }
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            LandlockRule::Path(config) => &config.path,
+            LandlockRule::Output(path) | LandlockRule::Socket(path) => path,
+        }
+    }
+}
+
+/// Landlock ruleset, enforced on the calling thread and the threads it
+/// spawns afterwards.
+pub struct Landlock {
+    ruleset: File,
+    rules: Vec<(PathBuf, u64)>,
+}
+
+impl Landlock {
+    pub fn new() -> Result<Self> {
+        // Safe because no attribute is passed when querying the ABI version.
+        let abi = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                std::ptr::null::<LandlockRulesetAttr>(),
+                0 as libc::size_t,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            return Err(Error::NotSupported(io::Error::last_os_error()));
+        }
+
+        let attr = LandlockRulesetAttr {
+            handled_access_fs: ACCESS_FS_ALL,
+        };
+        // Safe because the attribute is valid and its size is correct.
+        let fd = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                &attr as *const LandlockRulesetAttr,
+                std::mem::size_of::<LandlockRulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::CreateRuleset(io::Error::last_os_error()));
+        }
+
+        Ok(Landlock {
+            // Safe because the file descriptor was just created and nothing
+            // else owns it.
+            ruleset: unsafe { File::from_raw_fd(fd as i32) },
+            rules: Vec::new(),
+        })
+    }
+
+    /// Grant the access needed by the rule. The paths must exist, except
+    /// for the output files, which are created if needed.
+    pub fn add_rule(&mut self, rule: &LandlockRule) -> Result<()> {
+        if let LandlockRule::Output(path) = rule {
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .open(path)
+                .map_err(|e| Error::CreateOutput(path.clone(), e))?;
+        }
+
+        for (path, access) in rule.accesses()? {
+            self.add_access(path, access)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_access(&mut self, path: PathBuf, allowed_access: u64) -> Result<()> {
+        if self
+            .rules
+            .iter()
+            .any(|(p, a)| *p == path && a & allowed_access == allowed_access)
+        {
+            return Ok(());
+        }
+
+        let mut c_path = path.as_os_str().as_bytes().to_vec();
+        c_path.push(0);
+        // Safe because the path is NUL terminated and the returned file
+        // descriptor is checked.
+        let fd = unsafe {
+            libc::open(
+                c_path.as_ptr() as *const libc::c_char,
+                libc::O_PATH | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::OpenPath(path, io::Error::last_os_error()));
+        }
+        // Safe because the file descriptor was just opened and nothing else
+        // owns it. This makes sure it is closed once the rule is added.
+        let parent = unsafe { File::from_raw_fd(fd) };
+
+        let attr = LandlockPathBeneathAttr {
+            allowed_access,
+            parent_fd: parent.as_raw_fd(),
+        };
+        // Safe because the attribute is valid for the rule type and the
+        // ruleset file descriptor is owned by this structure.
+        let ret = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_ADD_RULE,
+                self.ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const LandlockPathBeneathAttr,
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::AddRule(path, io::Error::last_os_error()));
+        }
+
+        self.rules.push((path, allowed_access));
+
+        Ok(())
+    }
+
+    pub fn add_rules(&mut self, rules: &[LandlockRule]) -> Result<()> {
+        for rule in rules {
+            self.add_rule(rule)?;
+        }
+
+        Ok(())
+    }
+
+    /// Enforce the ruleset on the calling thread. Its accesses to the
+    /// filesystem are limited to the granted ones from now on, and so are
+    /// the ones of the threads it spawns.
+    pub fn restrict_self(&self) -> Result<()> {
+        // Safe because this only sets a flag on the calling thread.
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } < 0 {
+            return Err(Error::NoNewPrivs(io::Error::last_os_error()));
+        }
+
+        // Safe because the ruleset file descriptor is owned by this structure.
+        let ret = unsafe { libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, self.ruleset.as_raw_fd(), 0) };
+        if ret < 0 {
+            return Err(Error::RestrictSelf(io::Error::last_os_error()));
+        }
+
+        Ok(())
+    }
+
+    /// Check the given accesses are granted by the ruleset, Landlock
+    /// combining the accesses granted to a path and to all its parents.
+    pub fn check(&self, rules: &[LandlockRule]) -> Result<()> {
+        for rule in rules {
+            for (path, wanted) in rule.accesses()? {
+                let granted = self
+                    .rules
+                    .iter()
+                    .filter(|(p, _)| path.starts_with(p))
+                    .fold(0, |granted, (_, a)| granted | *a);
+
+                if granted & wanted != wanted {
+                    return Err(Error::NotAllowed(rule.path().to_path_buf()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn rule(path: impl Into<PathBuf>, access: LandlockAccess) -> LandlockRule {
+    LandlockRule::Path(LandlockConfig {
+        path: path.into(),
+        access,
+    })
+}
+
+// Connecting to an existing socket is not restricted, only the sockets
+// created by the VMM need a rule.
+fn socket_rule(path: impl Into<PathBuf>) -> LandlockRule {
+    LandlockRule::Socket(path.into())
+}
+
+fn output_rule(path: impl Into<PathBuf>) -> LandlockRule {
+    LandlockRule::Output(path.into())
+}
+
+// The flags of an existing TAP interface are read to check it supports the
+// requested number of queues. The check is skipped if the interface is
+// created by the VMM, in which case nothing needs to be granted.
+fn tun_flags_rule(net_path: &Path, tap: &str) -> Option<LandlockRule> {
+    let path = net_path.join(tap).join("tun_flags");
+    if path.exists() {
+        Some(rule(path, LandlockAccess::Read))
+    } else {
+        None
+    }
+}
+
+/// Filesystem accesses needed by a VM or a device configuration
+pub trait LandlockPaths {
+    fn landlock_paths(&self) -> Vec<LandlockRule>;
+}
+
+impl LandlockPaths for DiskConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        match &self.path {
+            Some(path) if !self.vhost_user => {
+                let access = if self.readonly {
+                    LandlockAccess::Read
+                } else {
+                    LandlockAccess::ReadWrite
+                };
+                vec![rule(path, access)]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl LandlockPaths for NetConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        if self.vhost_user {
+            match (&self.vhost_socket, &self.vhost_mode) {
+                (Some(socket), VhostMode::Server) => vec![socket_rule(socket)],
+                _ => Vec::new(),
+            }
+        } else {
+            let mut rules = Vec::new();
+            if self.fds.is_none() {
+                rules.push(rule(TUN_PATH, LandlockAccess::ReadWrite));
+                if let Some(tap) = &self.tap {
+                    rules.extend(tun_flags_rule(Path::new(SYSFS_NET_PATH), tap));
+                }
+            }
+            if self.vhost {
+                rules.push(rule(VHOST_NET_PATH, LandlockAccess::ReadWrite));
+            }
+            rules
+        }
+    }
+}
+
+impl LandlockPaths for FsConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        Vec::new()
+    }
+}
+
+impl LandlockPaths for PmemConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        let access = if self.readonly {
+            LandlockAccess::Read
+        } else {
+            LandlockAccess::ReadWrite
+        };
+
+        vec![rule(&self.file, access)]
+    }
+}
+
+impl LandlockPaths for ConsoleConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        match (&self.mode, &self.file) {
+            (ConsoleOutputMode::File, Some(file)) => vec![output_rule(file)],
+            // The subsidiary side of the pty is retrieved from the main one
+            // with TIOCGPTPEER, hence only the multiplexers the VMM opens the
+            // pty from are needed, and none of the /dev/pts entries.
+            (ConsoleOutputMode::Pty, _) => {
+                let mut rules = vec![rule(PTMX_PATH, LandlockAccess::ReadWrite)];
+                if Path::new(PTS_PTMX_PATH).exists() {
+                    rules.push(rule(PTS_PTMX_PATH, LandlockAccess::ReadWrite));
+                }
+                rules
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl LandlockPaths for DeviceConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        vec![
+            rule(&self.path, LandlockAccess::Read),
+            rule(VFIO_PATH, LandlockAccess::ReadWrite),
+        ]
+    }
+}
+
+impl LandlockPaths for UserDeviceConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        Vec::new()
+    }
+}
+
+impl LandlockPaths for GenericVhostUserConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        Vec::new()
+    }
+}
+
+impl LandlockPaths for VsockConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        if self.vhost {
+            vec![rule(VHOST_VSOCK_PATH, LandlockAccess::ReadWrite)]
+        } else {
+            vec![socket_rule(&self.socket)]
+        }
+    }
+}
+
+impl<T: LandlockPaths> LandlockPaths for Option<Vec<T>> {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        self.iter()
+            .flatten()
+            .flat_map(LandlockPaths::landlock_paths)
+            .collect()
+    }
+}
+
+impl LandlockPaths for VmConfig {
+    fn landlock_paths(&self) -> Vec<LandlockRule> {
+        let mut rules = Vec::new();
+
+        if let Some(kernel) = &self.kernel {
+            rules.push(rule(&kernel.path, LandlockAccess::Read));
+        }
+        if let Some(initramfs) = &self.initramfs {
+            rules.push(rule(&initramfs.path, LandlockAccess::Read));
+        }
+        #[cfg(feature = "tdx")]
+        if let Some(tdx) = &self.tdx {
+            rules.push(rule(&tdx.firmware, LandlockAccess::Read));
+        }
+        if let Some(exit_trace) = &self.cpus.exit_trace {
+            rules.push(output_rule(exit_trace));
+        }
+        for zone in self.memory.zones.iter().flatten() {
+            if let Some(file) = &zone.file {
+                rules.push(rule(file, LandlockAccess::ReadWrite));
+            }
+        }
+        #[cfg(target_arch = "x86_64")]
+        if self.sgx_epc.is_some() {
+            rules.push(rule(SGX_VEPC_PATH, LandlockAccess::ReadWrite));
+        }
+        rules.push(rule(&self.rng.src, LandlockAccess::Read));
+
+        rules.extend(self.disks.landlock_paths());
+        rules.extend(self.net.landlock_paths());
+        rules.extend(self.fs.landlock_paths());
+        rules.extend(self.pmem.landlock_paths());
+        rules.extend(self.serial.landlock_paths());
+        rules.extend(self.console.landlock_paths());
+        rules.extend(self.devices.landlock_paths());
+        rules.extend(self.user_devices.landlock_paths());
+        rules.extend(self.generic_vhost_user.landlock_paths());
+        if let Some(vsock) = &self.vsock {
+            rules.extend(vsock.landlock_paths());
+        }
+        rules.extend(
+            self.landlock_rules
+                .iter()
+                .flatten()
+                .cloned()
+                .map(LandlockRule::from),
+        );
+
+        rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_device_rules() {
+        let disk = DiskConfig {
+            path: Some(PathBuf::from("/images/disk.raw")),
+            readonly: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            disk.landlock_paths(),
+            vec![rule("/images/disk.raw", LandlockAccess::Read)]
+        );
+        assert_eq!(Some(vec![disk.clone(), disk]).landlock_paths().len(), 2);
+
+        let vsock = VsockConfig {
+            cid: 3,
+            socket: PathBuf::from("/run/ch/vsock.sock"),
+            ..Default::default()
+        };
+        assert_eq!(
+            vsock.landlock_paths(),
+            vec![LandlockRule::Socket(PathBuf::from("/run/ch/vsock.sock"))]
+        );
+
+        let console = ConsoleConfig {
+            file: Some(PathBuf::from("/run/ch/console.log")),
+            mode: ConsoleOutputMode::File,
+            iommu: false,
+        };
+        assert_eq!(
+            console.landlock_paths(),
+            vec![LandlockRule::Output(PathBuf::from("/run/ch/console.log"))]
+        );
+    }
+
+    #[test]
+    fn test_net_rules() {
+        let net = NetConfig {
+            tap: Some("ch-test-missing0".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            net.landlock_paths(),
+            vec![rule(TUN_PATH, LandlockAccess::ReadWrite)]
+        );
+
+        let net = NetConfig {
+            fds: Some(vec![3]),
+            ..net
+        };
+        assert!(net.landlock_paths().is_empty());
+
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        std::fs::create_dir(dir.as_path().join("tap0")).unwrap();
+        assert!(tun_flags_rule(dir.as_path(), "tap1").is_none());
+        std::fs::write(dir.as_path().join("tap0/tun_flags"), b"0x1002").unwrap();
+        assert_eq!(
+            tun_flags_rule(dir.as_path(), "tap0"),
+            Some(rule(
+                dir.as_path().join("tap0/tun_flags"),
+                LandlockAccess::Read
+            ))
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let dir_path = dir.as_path().canonicalize().unwrap();
+        let file = dir_path.join("file");
+
+        // A path that does not exist yet is resolved through its parent
+        // directory, which must exist.
+        assert_eq!(resolve_path(&file).unwrap(), file);
+        assert!(resolve_path(&dir_path.join("missing/file")).is_err());
+        assert_eq!(
+            LandlockRule::Socket(file.clone()).accesses().unwrap(),
+            vec![(
+                dir_path.clone(),
+                LANDLOCK_ACCESS_FS_MAKE_SOCK | LANDLOCK_ACCESS_FS_REMOVE_FILE
+            )]
+        );
+        assert_eq!(
+            LandlockRule::Output(file.clone()).accesses().unwrap(),
+            vec![
+                (dir_path.clone(), LANDLOCK_ACCESS_FS_MAKE_REG),
+                (file.clone(), LANDLOCK_ACCESS_FS_WRITE_FILE)
+            ]
+        );
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(resolve_path(&file).unwrap(), file);
+        assert_eq!(
+            LandlockRule::Output(file.clone()).accesses().unwrap(),
+            vec![(file.clone(), LANDLOCK_ACCESS_FS_WRITE_FILE)]
+        );
+        assert_eq!(
+            path_access(&file, ACCESS_FS_READ | ACCESS_FS_WRITE),
+            LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_WRITE_FILE
+        );
+
+        let landlock = Landlock {
+            ruleset: File::open("/dev/null").unwrap(),
+            rules: vec![(dir_path.clone(), ACCESS_FS_READ)],
+        };
+        assert!(landlock.check(&[rule(&file, LandlockAccess::Read)]).is_ok());
+        assert!(landlock
+            .check(&[rule(&file, LandlockAccess::ReadWrite)])
+            .is_err());
+        assert!(landlock
+            .check(&[rule(dir_path.join("new"), LandlockAccess::Write)])
+            .is_err());
+        assert!(landlock.check(&[output_rule(&file)]).is_err());
+        assert!(landlock.check(&[socket_rule(&file)]).is_err());
+
+        let landlock = Landlock {
+            ruleset: File::open("/dev/null").unwrap(),
+            rules: vec![(dir_path.clone(), ACCESS_FS_READ | ACCESS_FS_WRITE)],
+        };
+        assert!(landlock.check(&[output_rule(dir_path.join("new"))]).is_ok());
+        assert!(landlock.check(&[socket_rule(dir_path.join("new"))]).is_ok());
+        assert!(landlock
+            .check(&[rule("/etc/passwd", LandlockAccess::Read)])
+            .is_err());
+    }
+}
@@ -16,23 +16,26 @@ extern crate serde_derive;
 extern crate credibility;
 
 use crate::api::{
-    ApiError, ApiRequest, ApiResponse, ApiResponsePayload, VmInfo, VmReceiveMigrationData,
-    VmSendMigrationData, VmmPingResponse,
+    ApiError, ApiRequest, ApiResponse, ApiResponsePayload, VmCpuAffinityData, VmInfo,
+    VmNetCaptureData, VmNetLinkData, VmReceiveMigrationData, VmSendMigrationData, VmmPingResponse,
 };
 use crate::config::{
-    DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig, VmConfig, VsockConfig,
+    DeviceConfig, DiskConfig, FsConfig, GenericVhostUserConfig, LandlockAccess, LandlockConfig,
+    NetConfig, PmemConfig, RestoreConfig, UserDeviceConfig, VmConfig, VsockConfig,
 };
-use crate::migration::{get_vm_snapshot, recv_vm_snapshot};
+use crate::landlock::{Landlock, LandlockPaths, LandlockRule};
+use crate::migration::{get_vm_snapshot, recv_vm_snapshot, url_to_path};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::vm::{Error as VmError, Vm, VmState};
 use anyhow::anyhow;
 use libc::EFD_NONBLOCK;
 use seccomp::{SeccompAction, SeccompFilter};
 use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::os::unix::net::UnixListener;
 use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
@@ -46,10 +49,15 @@ use vmm_sys_util::eventfd::EventFd;
 
 pub mod api;
 pub mod config;
+pub mod coredump;
 pub mod cpu;
 pub mod device_manager;
 pub mod device_tree;
+pub mod exit_stats;
+#[cfg(feature = "gdb")]
+pub mod gdb;
 pub mod interrupt;
+pub mod landlock;
 pub mod memory_manager;
 pub mod migration;
 pub mod seccomp_filters;
@@ -58,6 +66,9 @@ pub mod vm;
 #[cfg(feature = "acpi")]
 mod acpi;
 
+#[cfg(all(feature = "gdb", not(target_arch = "x86_64")))]
+compile_error!("The GDB stub is only supported on x86_64");
+
 /// Errors associated with VMM management
 #[derive(Debug, Error)]
 #[allow(clippy::large_enum_variant)]
@@ -133,9 +144,17 @@ pub enum Error {
     /// Error binding API server socket
     #[error("Error creation API server's socket {0:?}")]
     CreateApiServerSocket(#[source] io::Error),
+
+    /// Cannot create GDB thread
+    #[cfg(feature = "gdb")]
+    #[error("Error spawning GDB thread: {0}")]
+    GdbThreadSpawn(#[source] io::Error),
 }
 pub type Result<T> = result::Result<T, Error>;
 
+// Maximum number of passed file descriptors held open by the VMM
+const MAX_OWNED_FDS: usize = 256;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EpollDispatch {
     Exit,
@@ -144,6 +163,10 @@ pub enum EpollDispatch {
     Api,
     ActivateVirtioDevices,
     Pty,
+    #[cfg(feature = "gdb")]
+    Debug,
+    #[cfg(feature = "gdb")]
+    VmDebug,
 }
 
 pub struct EpollContext {
@@ -213,6 +236,25 @@ pub struct PciDeviceInfo {
     pub bdf: u32,
 }
 
+// Version of the config message sent at the start of a migration. The source
+// VMMs predating the versioning sent a bare VmConfig, which carries no version
+// and is therefore seen as version 0.
+const VM_MIGRATION_CONFIG_VERSION: u16 = 1;
+
+#[derive(Deserialize)]
+struct VmMigrationConfigVersion {
+    #[serde(default)]
+    version: u16,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct VmMigrationConfig {
+    version: u16,
+    vm_config: Arc<Mutex<VmConfig>>,
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    common_cpuid: hypervisor::CpuId,
+}
+
 impl Serialize for PciDeviceInfo {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -246,9 +288,19 @@ pub fn start_vmm_thread(
     api_receiver: Receiver<ApiRequest>,
     seccomp_action: &SeccompAction,
     hypervisor: Arc<dyn hypervisor::Hypervisor>,
+    #[cfg(feature = "gdb")] gdb_socket_path: Option<PathBuf>,
 ) -> Result<thread::JoinHandle<Result<()>>> {
     let http_api_event = api_event.try_clone().map_err(Error::EventFdClone)?;
 
+    #[cfg(feature = "gdb")]
+    let (gdb_sender, gdb_receiver) = std::sync::mpsc::channel();
+    #[cfg(feature = "gdb")]
+    let (gdb_vm_event_sender, gdb_vm_event_receiver) = std::sync::mpsc::channel();
+    #[cfg(feature = "gdb")]
+    let debug_event = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+    #[cfg(feature = "gdb")]
+    let gdb_debug_event = debug_event.try_clone().map_err(Error::EventFdClone)?;
+
     // Retrieve seccomp filter
     let vmm_seccomp_filter =
         get_seccomp_filter(seccomp_action, Thread::Vmm).map_err(Error::CreateSeccompFilter)?;
@@ -263,14 +315,33 @@ pub fn start_vmm_thread(
             let mut vmm = Vmm::new(
                 vmm_version.to_string(),
                 api_event,
+                #[cfg(feature = "gdb")]
+                debug_event,
+                #[cfg(feature = "gdb")]
+                gdb_vm_event_sender,
                 vmm_seccomp_action,
                 hypervisor,
             )?;
 
-            vmm.control_loop(Arc::new(api_receiver))
+            vmm.control_loop(
+                Arc::new(api_receiver),
+                #[cfg(feature = "gdb")]
+                Arc::new(gdb_receiver),
+            )
         })
         .map_err(Error::VmmThreadSpawn)?;
 
+    #[cfg(feature = "gdb")]
+    if let Some(gdb_socket_path) = gdb_socket_path {
+        gdb::start_gdb_thread(
+            gdb_socket_path,
+            gdb_debug_event,
+            gdb_sender,
+            gdb_vm_event_receiver,
+            seccomp_action,
+        )?;
+    }
+
     // The VMM thread is started, we can start serving HTTP requests
     if let Some(http_path) = http_path {
         api::start_http_path_thread(http_path, http_api_event, api_sender, seccomp_action)?;
@@ -291,12 +362,22 @@ pub struct Vmm {
     seccomp_action: SeccompAction,
     hypervisor: Arc<dyn hypervisor::Hypervisor>,
     activate_evt: EventFd,
+    #[cfg(feature = "gdb")]
+    debug_evt: EventFd,
+    #[cfg(feature = "gdb")]
+    vm_debug_evt: EventFd,
+    #[cfg(feature = "gdb")]
+    gdb_vm_event_sender: Sender<u32>,
+    landlock: Option<Landlock>,
+    passed_fds: BTreeMap<RawFd, File>,
 }
 
 impl Vmm {
     fn new(
         vmm_version: String,
         api_evt: EventFd,
+        #[cfg(feature = "gdb")] debug_evt: EventFd,
+        #[cfg(feature = "gdb")] gdb_vm_event_sender: Sender<u32>,
         seccomp_action: SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
     ) -> Result<Self> {
@@ -304,6 +385,8 @@ impl Vmm {
         let exit_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
         let reset_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
         let activate_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        #[cfg(feature = "gdb")]
+        let vm_debug_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
 
         if unsafe { libc::isatty(libc::STDIN_FILENO as i32) } != 0 {
             epoll.add_stdin().map_err(Error::Epoll)?;
@@ -325,6 +408,16 @@ impl Vmm {
             .add_event(&api_evt, EpollDispatch::Api)
             .map_err(Error::Epoll)?;
 
+        #[cfg(feature = "gdb")]
+        epoll
+            .add_event(&debug_evt, EpollDispatch::Debug)
+            .map_err(Error::Epoll)?;
+
+        #[cfg(feature = "gdb")]
+        epoll
+            .add_event(&vm_debug_evt, EpollDispatch::VmDebug)
+            .map_err(Error::Epoll)?;
+
         Ok(Vmm {
             epoll,
             exit_evt,
@@ -336,9 +429,96 @@ impl Vmm {
             seccomp_action,
             hypervisor,
             activate_evt,
+            #[cfg(feature = "gdb")]
+            debug_evt,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt,
+            #[cfg(feature = "gdb")]
+            gdb_vm_event_sender,
+            landlock: None,
+            passed_fds: BTreeMap::new(),
         })
     }
 
+    // Keep the passed file descriptors open for the API requests to refer to
+    // them, either directly or through their /proc/self/fd/<fd> path, the VMM
+    // possibly not being able to open the files by itself when jailed.
+    fn vmm_send_fds(&mut self, files: Vec<File>) -> io::Result<Vec<RawFd>> {
+        if self.passed_fds.len() + files.len() > MAX_OWNED_FDS {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "too many file descriptors held open",
+            ));
+        }
+
+        let fds: Vec<RawFd> = files.iter().map(|file| file.as_raw_fd()).collect();
+        info!("Received file descriptors {:?}", fds);
+        self.passed_fds
+            .extend(files.into_iter().map(|file| (file.as_raw_fd(), file)));
+
+        Ok(fds)
+    }
+
+    fn vmm_close_fds(&mut self, fds: Vec<RawFd>) -> Vec<RawFd> {
+        let fds: Vec<RawFd> = fds
+            .into_iter()
+            .filter(|fd| self.passed_fds.remove(fd).is_some())
+            .collect();
+        info!("Closed file descriptors {:?}", fds);
+
+        fds
+    }
+
+    // The TAP file descriptors of a network device are owned by the device
+    // once created, hence must no longer be closed by the VMM.
+    fn net_take_passed_fds(&mut self, net_cfg: &NetConfig) {
+        for fd in net_cfg.fds.iter().flatten() {
+            if let Some(file) = self.passed_fds.remove(fd) {
+                let _ = file.into_raw_fd();
+            }
+        }
+    }
+
+    // Restrict the filesystem accesses of the VMM thread, and of all the
+    // threads it spawns from now on, to the paths used by the VM if its
+    // configuration asks for it. Once enforced, the ruleset cannot be widened
+    // anymore, hence any later configuration is only checked against it.
+    fn vm_landlock(
+        &mut self,
+        config: &VmConfig,
+        extra_paths: &[LandlockRule],
+    ) -> result::Result<(), VmError> {
+        let mut paths = config.landlock_paths();
+        paths.extend_from_slice(extra_paths);
+
+        if self.landlock.is_some() {
+            return self.vm_landlock_check(&paths);
+        }
+        if !config.landlock_enable {
+            return Ok(());
+        }
+
+        let mut landlock = Landlock::new().map_err(VmError::Landlock)?;
+        landlock.add_rules(&paths).map_err(VmError::Landlock)?;
+        landlock.restrict_self().map_err(VmError::Landlock)?;
+        info!("Landlock sandbox enforced");
+
+        self.landlock = Some(landlock);
+
+        Ok(())
+    }
+
+    fn vm_landlock_check(&self, paths: &[LandlockRule]) -> result::Result<(), VmError> {
+        if let Some(landlock) = &self.landlock {
+            landlock.check(paths).map_err(|e| {
+                error!("Error checking Landlock sandbox: {}", e);
+                VmError::Landlock(e)
+            })
+        } else {
+            Ok(())
+        }
+    }
+
     fn vm_boot(&mut self) -> result::Result<(), VmError> {
         // Create a new VM if we don't have one yet.
         if self.vm.is_none() {
@@ -348,12 +528,19 @@ impl Vmm {
                 .activate_evt
                 .try_clone()
                 .map_err(VmError::EventFdClone)?;
+            #[cfg(feature = "gdb")]
+            let vm_debug_evt = self
+                .vm_debug_evt
+                .try_clone()
+                .map_err(VmError::EventFdClone)?;
 
             if let Some(ref vm_config) = self.vm_config {
                 let vm = Vm::new(
                     Arc::clone(vm_config),
                     exit_evt,
                     reset_evt,
+                    #[cfg(feature = "gdb")]
+                    vm_debug_evt,
                     &self.seccomp_action,
                     self.hypervisor.clone(),
                     activate_evt,
@@ -399,6 +586,13 @@ impl Vmm {
     }
 
     fn vm_snapshot(&mut self, destination_url: &str) -> result::Result<(), VmError> {
+        if let Ok(path) = url_to_path(destination_url) {
+            self.vm_landlock_check(&[LandlockRule::Path(LandlockConfig {
+                path,
+                access: LandlockAccess::Write,
+            })])?;
+        }
+
         if let Some(ref mut vm) = self.vm {
             vm.snapshot()
                 .map_err(VmError::Snapshot)
@@ -411,6 +605,21 @@ impl Vmm {
         }
     }
 
+    fn vm_coredump(&mut self, destination_url: &str) -> result::Result<(), VmError> {
+        if let Some(path) = destination_url.strip_prefix("file://") {
+            self.vm_landlock_check(&[LandlockRule::Output(PathBuf::from(path))])?;
+        }
+
+        if let Some(ref mut vm) = self.vm {
+            vm.coredump(destination_url).map_err(|e| {
+                error!("Error when writing coredump: {:?}", e);
+                e
+            })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_restore(&mut self, restore_cfg: RestoreConfig) -> result::Result<(), VmError> {
         if self.vm.is_some() || self.vm_config.is_some() {
             return Err(VmError::VmAlreadyCreated);
@@ -426,6 +635,16 @@ impl Vmm {
         let snapshot = recv_vm_snapshot(source_url).map_err(VmError::Restore)?;
         let vm_snapshot = get_vm_snapshot(&snapshot).map_err(VmError::Restore)?;
 
+        // The memory of the VM is restored from the snapshot directory.
+        let source_path = url_to_path(source_url).map_err(VmError::Restore)?;
+        self.vm_landlock(
+            &vm_snapshot.config.lock().unwrap(),
+            &[LandlockRule::Path(LandlockConfig {
+                path: source_path,
+                access: LandlockAccess::Read,
+            })],
+        )?;
+
         self.vm_config = Some(Arc::clone(&vm_snapshot.config));
 
         let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
@@ -434,17 +653,38 @@ impl Vmm {
             .activate_evt
             .try_clone()
             .map_err(VmError::EventFdClone)?;
+        #[cfg(feature = "gdb")]
+        let vm_debug_evt = self
+            .vm_debug_evt
+            .try_clone()
+            .map_err(VmError::EventFdClone)?;
 
         let vm = Vm::new_from_snapshot(
             &snapshot,
             exit_evt,
             reset_evt,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt,
             Some(source_url),
             restore_cfg.prefault,
             &self.seccomp_action,
             self.hypervisor.clone(),
             activate_evt,
         )?;
+
+        // The guest must not lose any CPU feature it was relying on. The
+        // snapshots predating the check don't carry the CPUID.
+        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+        if let Some(common_cpuid) = &vm_snapshot.common_cpuid {
+            arch::x86_64::cpu_model::check_cpuid_compatibility(common_cpuid, &vm.common_cpuid())
+                .map_err(|e| {
+                    VmError::Restore(MigratableError::Restore(anyhow!(
+                        "Error checking CPUID compatibility: {:?}",
+                        e
+                    )))
+                })?;
+        }
+
         self.vm = Some(vm);
 
         // Now we can restore the rest of the VM.
@@ -487,6 +727,11 @@ impl Vmm {
                 .activate_evt
                 .try_clone()
                 .map_err(VmError::EventFdClone)?;
+            #[cfg(feature = "gdb")]
+            let vm_debug_evt = self
+                .vm_debug_evt
+                .try_clone()
+                .map_err(VmError::EventFdClone)?;
 
             // The Linux kernel fires off an i8042 reset after doing the ACPI reset so there may be
             // an event sitting in the shared reset_evt. Without doing this we get very early reboots
@@ -498,6 +743,8 @@ impl Vmm {
                 config,
                 exit_evt,
                 reset_evt,
+                #[cfg(feature = "gdb")]
+                vm_debug_evt,
                 &self.seccomp_action,
                 self.hypervisor.clone(),
                 activate_evt,
@@ -573,7 +820,7 @@ impl Vmm {
 
     fn vm_resize(
         &mut self,
-        desired_vcpus: Option<u8>,
+        desired_vcpus: Option<u32>,
         desired_ram: Option<u64>,
         desired_balloon: Option<u64>,
     ) -> result::Result<(), VmError> {
@@ -603,6 +850,8 @@ impl Vmm {
     }
 
     fn vm_add_device(&mut self, device_cfg: DeviceConfig) -> result::Result<Vec<u8>, VmError> {
+        self.vm_landlock_check(&device_cfg.landlock_paths())?;
+
         if let Some(ref mut vm) = self.vm {
             let info = vm.add_device(device_cfg).map_err(|e| {
                 error!("Error when adding new device to the VM: {:?}", e);
@@ -614,6 +863,23 @@ impl Vmm {
         }
     }
 
+    fn vm_add_user_device(
+        &mut self,
+        device_cfg: UserDeviceConfig,
+    ) -> result::Result<Vec<u8>, VmError> {
+        self.vm_landlock_check(&device_cfg.landlock_paths())?;
+
+        if let Some(ref mut vm) = self.vm {
+            let info = vm.add_user_device(device_cfg).map_err(|e| {
+                error!("Error when adding new user device to the VM: {:?}", e);
+                e
+            })?;
+            serde_json::to_vec(&info).map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_remove_device(&mut self, id: String) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
             if let Err(e) = vm.remove_device(id) {
@@ -628,6 +894,8 @@ impl Vmm {
     }
 
     fn vm_add_disk(&mut self, disk_cfg: DiskConfig) -> result::Result<Vec<u8>, VmError> {
+        self.vm_landlock_check(&disk_cfg.landlock_paths())?;
+
         if let Some(ref mut vm) = self.vm {
             let info = vm.add_disk(disk_cfg).map_err(|e| {
                 error!("Error when adding new disk to the VM: {:?}", e);
@@ -640,6 +908,8 @@ impl Vmm {
     }
 
     fn vm_add_fs(&mut self, fs_cfg: FsConfig) -> result::Result<Vec<u8>, VmError> {
+        self.vm_landlock_check(&fs_cfg.landlock_paths())?;
+
         if let Some(ref mut vm) = self.vm {
             let info = vm.add_fs(fs_cfg).map_err(|e| {
                 error!("Error when adding new fs to the VM: {:?}", e);
@@ -651,7 +921,31 @@ impl Vmm {
         }
     }
 
+    fn vm_add_generic_vhost_user(
+        &mut self,
+        generic_vhost_user_cfg: GenericVhostUserConfig,
+    ) -> result::Result<Vec<u8>, VmError> {
+        self.vm_landlock_check(&generic_vhost_user_cfg.landlock_paths())?;
+
+        if let Some(ref mut vm) = self.vm {
+            let info = vm
+                .add_generic_vhost_user(generic_vhost_user_cfg)
+                .map_err(|e| {
+                    error!(
+                        "Error when adding new generic vhost-user device to the VM: {:?}",
+                        e
+                    );
+                    e
+                })?;
+            serde_json::to_vec(&info).map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_add_pmem(&mut self, pmem_cfg: PmemConfig) -> result::Result<Vec<u8>, VmError> {
+        self.vm_landlock_check(&pmem_cfg.landlock_paths())?;
+
         if let Some(ref mut vm) = self.vm {
             let info = vm.add_pmem(pmem_cfg).map_err(|e| {
                 error!("Error when adding new pmem device to the VM: {:?}", e);
@@ -664,11 +958,14 @@ impl Vmm {
     }
 
     fn vm_add_net(&mut self, net_cfg: NetConfig) -> result::Result<Vec<u8>, VmError> {
+        self.vm_landlock_check(&net_cfg.landlock_paths())?;
+
         if let Some(ref mut vm) = self.vm {
-            let info = vm.add_net(net_cfg).map_err(|e| {
+            let info = vm.add_net(net_cfg.clone()).map_err(|e| {
                 error!("Error when adding new network device to the VM: {:?}", e);
                 e
             })?;
+            self.net_take_passed_fds(&net_cfg);
             serde_json::to_vec(&info).map_err(VmError::SerializeJson)
         } else {
             Err(VmError::VmNotRunning)
@@ -676,6 +973,8 @@ impl Vmm {
     }
 
     fn vm_add_vsock(&mut self, vsock_cfg: VsockConfig) -> result::Result<Vec<u8>, VmError> {
+        self.vm_landlock_check(&vsock_cfg.landlock_paths())?;
+
         if let Some(ref mut vm) = self.vm {
             let info = vm.add_vsock(vsock_cfg).map_err(|e| {
                 error!("Error when adding new vsock device to the VM: {:?}", e);
@@ -699,6 +998,52 @@ impl Vmm {
         }
     }
 
+    fn vm_vcpu_counters(&mut self) -> result::Result<Vec<u8>, VmError> {
+        if let Some(ref vm) = self.vm {
+            serde_json::to_vec(&vm.vcpu_counters()).map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_net_capture(&mut self, data: &VmNetCaptureData) -> result::Result<(), VmError> {
+        if let Some(path) = &data.path {
+            self.vm_landlock_check(&[LandlockRule::Output(path.clone())])?;
+        }
+
+        if let Some(ref vm) = self.vm {
+            vm.net_capture(&data.id, data.path.as_deref(), data.max_size)
+                .map_err(|e| {
+                    error!("Error when capturing network traffic: {:?}", e);
+                    e
+                })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_net_link(&mut self, data: &VmNetLinkData) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            vm.net_link(&data.id, data.up).map_err(|e| {
+                error!("Error when changing network link state: {:?}", e);
+                e
+            })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_cpu_affinity(&mut self, data: &VmCpuAffinityData) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            vm.set_cpu_affinity(&data.affinity).map_err(|e| {
+                error!("Error when changing vCPUs affinity: {:?}", e);
+                e
+            })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_power_button(&mut self) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
             vm.power_button()
@@ -723,9 +1068,22 @@ impl Vmm {
         socket
             .read_exact(&mut data)
             .map_err(MigratableError::MigrateSocket)?;
-        let config: VmConfig = serde_json::from_slice(&data).map_err(|e| {
-            MigratableError::MigrateReceive(anyhow!("Error deserialising config: {}", e))
-        })?;
+        let version = serde_json::from_slice::<VmMigrationConfigVersion>(&data)
+            .map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error deserialising config: {}", e))
+            })?
+            .version;
+        if version != VM_MIGRATION_CONFIG_VERSION {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "Unsupported migration config version {}, expected {}",
+                version,
+                VM_MIGRATION_CONFIG_VERSION
+            )));
+        }
+        let vm_migration_config: VmMigrationConfig =
+            serde_json::from_slice(&data).map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error deserialising config: {}", e))
+            })?;
 
         let exit_evt = self.exit_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning exit EventFd: {}", e))
@@ -736,12 +1094,23 @@ impl Vmm {
         let activate_evt = self.activate_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning activate EventFd: {}", e))
         })?;
+        #[cfg(feature = "gdb")]
+        let vm_debug_evt = self.vm_debug_evt.try_clone().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error cloning VM debug EventFd: {}", e))
+        })?;
+
+        self.vm_landlock(&vm_migration_config.vm_config.lock().unwrap(), &[])
+            .map_err(|e| {
+                MigratableError::MigrateReceive(anyhow!("Error enforcing Landlock: {:?}", e))
+            })?;
 
-        self.vm_config = Some(Arc::new(Mutex::new(config)));
+        self.vm_config = Some(vm_migration_config.vm_config);
         let vm = Vm::new_from_migration(
             self.vm_config.clone().unwrap(),
             exit_evt,
             reset_evt,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt,
             &self.seccomp_action,
             self.hypervisor.clone(),
             activate_evt,
@@ -750,6 +1119,16 @@ impl Vmm {
             MigratableError::MigrateReceive(anyhow!("Error creating VM from snapshot: {:?}", e))
         })?;
 
+        // The guest must not lose any CPU feature it was relying on
+        #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+        arch::x86_64::cpu_model::check_cpuid_compatibility(
+            &vm_migration_config.common_cpuid,
+            &vm.common_cpuid(),
+        )
+        .map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error checking CPUID compatibility: {:?}", e))
+        })?;
+
         Response::ok().write_to(socket)?;
 
         Ok(vm)
@@ -956,6 +1335,21 @@ impl Vmm {
             send_data_migration.destination_url
         );
         if let Some(ref mut vm) = self.vm {
+            // The vhost-user-blk backends can't log the pages they write to,
+            // which would be left stale on the destination.
+            if vm
+                .get_config()
+                .lock()
+                .unwrap()
+                .disks
+                .as_ref()
+                .map_or(false, |disks| disks.iter().any(|disk| disk.vhost_user))
+            {
+                return Err(MigratableError::MigrateSend(anyhow!(
+                    "Live migration of VMs using vhost-user-blk devices is not supported"
+                )));
+            }
+
             let path = Self::socket_url_to_path(&send_data_migration.destination_url)?;
             let mut socket = UnixStream::connect(&path).map_err(|e| {
                 MigratableError::MigrateSend(anyhow!("Error connecting to UNIX socket: {}", e))
@@ -974,7 +1368,13 @@ impl Vmm {
             }
 
             // Send config
-            let config_data = serde_json::to_vec(&vm.get_config()).unwrap();
+            let vm_migration_config = VmMigrationConfig {
+                version: VM_MIGRATION_CONFIG_VERSION,
+                vm_config: vm.get_config(),
+                #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+                common_cpuid: vm.common_cpuid(),
+            };
+            let config_data = serde_json::to_vec(&vm_migration_config).unwrap();
             Request::config(config_data.len() as u64).write_to(&mut socket)?;
             socket
                 .write_all(&config_data)
@@ -1060,7 +1460,11 @@ impl Vmm {
         }
     }
 
-    fn control_loop(&mut self, api_receiver: Arc<Receiver<ApiRequest>>) -> Result<()> {
+    fn control_loop(
+        &mut self,
+        api_receiver: Arc<Receiver<ApiRequest>>,
+        #[cfg(feature = "gdb")] gdb_receiver: Arc<Receiver<gdb::GdbRequest>>,
+    ) -> Result<()> {
         const EPOLL_EVENTS_LEN: usize = 100;
 
         let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); EPOLL_EVENTS_LEN];
@@ -1124,6 +1528,44 @@ impl Vmm {
                                 vm.handle_pty().map_err(Error::Pty)?;
                             }
                         }
+                        #[cfg(feature = "gdb")]
+                        EpollDispatch::Debug => {
+                            // Consume the event.
+                            self.debug_evt.read().map_err(Error::EventFdRead)?;
+
+                            // Read from the GDB receiver channel. The GDB
+                            // thread may have gone away along with the
+                            // debugger, which must not stop the VMM.
+                            let gdb_request = match gdb_receiver.try_recv() {
+                                Ok(gdb_request) => gdb_request,
+                                Err(_) => {
+                                    warn!("Error receiving request from the GDB thread");
+                                    continue;
+                                }
+                            };
+
+                            let response = if let Some(ref vm) = self.vm {
+                                vm.debug_request(&gdb_request.payload, gdb_request.cpu_id)
+                            } else {
+                                Err(VmError::VmNotRunning)
+                            };
+
+                            if gdb_request.sender.send(response).is_err() {
+                                warn!("Error sending response to the GDB thread");
+                            }
+                        }
+                        #[cfg(feature = "gdb")]
+                        EpollDispatch::VmDebug => {
+                            // Consume the event.
+                            self.vm_debug_evt.read().map_err(Error::EventFdRead)?;
+
+                            if let Some(ref vm) = self.vm {
+                                let cpu_id = vm.debug_pause();
+                                info!("vCPU {} stopped on a debug event", cpu_id);
+                                // Nobody is listening without a GDB socket.
+                                let _ = self.gdb_vm_event_sender.send(cpu_id);
+                            }
+                        }
                         EpollDispatch::Api => {
                             // Consume the event.
                             self.api_evt.read().map_err(Error::EventFdRead)?;
@@ -1134,11 +1576,21 @@ impl Vmm {
                             info!("API request event: {:?}", api_request);
                             match api_request {
                                 ApiRequest::VmCreate(config, sender) => {
-                                    // We only store the passed VM config.
+                                    // We only store the passed VM config, restricting the
+                                    // filesystem accesses to the paths it uses if needed.
                                     // The VM will be created when being asked to boot it.
                                     let response = if self.vm_config.is_none() {
-                                        self.vm_config = Some(config);
-                                        Ok(ApiResponsePayload::Empty)
+                                        let landlock =
+                                            self.vm_landlock(&config.lock().unwrap(), &[]);
+                                        landlock.map_err(ApiError::VmCreate).map(|_| {
+                                            for net_cfg in
+                                                config.lock().unwrap().net.iter().flatten()
+                                            {
+                                                self.net_take_passed_fds(net_cfg);
+                                            }
+                                            self.vm_config = Some(config);
+                                            ApiResponsePayload::Empty
+                                        })
                                     } else {
                                         Err(ApiError::VmAlreadyCreated)
                                     };
@@ -1222,6 +1674,14 @@ impl Vmm {
 
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmCoredump(coredump_data, sender) => {
+                                    let response = self
+                                        .vm_coredump(&coredump_data.destination_url)
+                                        .map_err(ApiError::VmCoredump)
+                                        .map(|_| ApiResponsePayload::Empty);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmRestore(restore_data, sender) => {
                                     let response = self
                                         .vm_restore(restore_data.as_ref().clone())
@@ -1230,6 +1690,20 @@ impl Vmm {
 
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmmSendFds(files, sender) => {
+                                    let response = self
+                                        .vmm_send_fds(files)
+                                        .map_err(ApiError::VmmSendFds)
+                                        .map(ApiResponsePayload::VmmFds);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmmCloseFds(fds, sender) => {
+                                    let response =
+                                        ApiResponsePayload::VmmFds(self.vmm_close_fds(fds));
+
+                                    sender.send(Ok(response)).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmmShutdown(sender) => {
                                     let response = self
                                         .vmm_shutdown()
@@ -1261,6 +1735,27 @@ impl Vmm {
                                         .map(|_| ApiResponsePayload::Empty);
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmNetCapture(net_capture_data, sender) => {
+                                    let response = self
+                                        .vm_net_capture(net_capture_data.as_ref())
+                                        .map_err(ApiError::VmNetCapture)
+                                        .map(|_| ApiResponsePayload::Empty);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmNetLink(net_link_data, sender) => {
+                                    let response = self
+                                        .vm_net_link(net_link_data.as_ref())
+                                        .map_err(ApiError::VmNetLink)
+                                        .map(|_| ApiResponsePayload::Empty);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
+                                ApiRequest::VmCpuAffinity(cpu_affinity_data, sender) => {
+                                    let response = self
+                                        .vm_cpu_affinity(cpu_affinity_data.as_ref())
+                                        .map_err(ApiError::VmCpuAffinity)
+                                        .map(|_| ApiResponsePayload::Empty);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmAddDevice(add_device_data, sender) => {
                                     let response = self
                                         .vm_add_device(add_device_data.as_ref().clone())
@@ -1268,6 +1763,13 @@ impl Vmm {
                                         .map(ApiResponsePayload::VmAction);
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmAddUserDevice(add_device_data, sender) => {
+                                    let response = self
+                                        .vm_add_user_device(add_device_data.as_ref().clone())
+                                        .map_err(ApiError::VmAddUserDevice)
+                                        .map(ApiResponsePayload::VmAction);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmRemoveDevice(remove_device_data, sender) => {
                                     let response = self
                                         .vm_remove_device(remove_device_data.id.clone())
@@ -1289,6 +1791,18 @@ impl Vmm {
                                         .map(ApiResponsePayload::VmAction);
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmAddGenericVhostUser(
+                                    add_generic_vhost_user_data,
+                                    sender,
+                                ) => {
+                                    let response = self
+                                        .vm_add_generic_vhost_user(
+                                            add_generic_vhost_user_data.as_ref().clone(),
+                                        )
+                                        .map_err(ApiError::VmAddGenericVhostUser)
+                                        .map(ApiResponsePayload::VmAction);
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmAddPmem(add_pmem_data, sender) => {
                                     let response = self
                                         .vm_add_pmem(add_pmem_data.as_ref().clone())
@@ -1318,6 +1832,14 @@ impl Vmm {
 
                                     sender.send(response).map_err(Error::ApiResponseSend)?;
                                 }
+                                ApiRequest::VmVcpuCounters(sender) => {
+                                    let response = self
+                                        .vm_vcpu_counters()
+                                        .map_err(ApiError::VmInfo)
+                                        .map(ApiResponsePayload::VmAction);
+
+                                    sender.send(response).map_err(Error::ApiResponseSend)?;
+                                }
                                 ApiRequest::VmReceiveMigration(receive_migration_data, sender) => {
                                     let response = self
                                         .vm_receive_migration(
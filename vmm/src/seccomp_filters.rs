@@ -10,14 +10,32 @@ use seccomp::{
     SyscallRuleSet,
 };
 use std::convert::TryInto;
+pub use virtio_devices::seccomp_profile;
+use virtio_devices::seccomp_profile::{apply_profile, tag_trap};
 
 pub enum Thread {
     Api,
+    #[cfg(feature = "gdb")]
+    Gdb,
     SignalHandler,
     Vcpu,
     Vmm,
 }
 
+impl Thread {
+    /// Thread type the seccomp profile refers to.
+    fn name(&self) -> &'static str {
+        match self {
+            Thread::Api => "api",
+            #[cfg(feature = "gdb")]
+            Thread::Gdb => "gdb",
+            Thread::SignalHandler => "signal_handler",
+            Thread::Vcpu => "vcpu",
+            Thread::Vmm => "vmm",
+        }
+    }
+}
+
 /// Shorthand for chaining `SeccompCondition`s with the `and` operator  in a `SeccompRule`.
 /// The rule will take the `Allow` action if _all_ the conditions are true.
 ///
@@ -62,10 +80,26 @@ const TUNGETFEATURES: u64 = 0x8004_54cf;
 // See include/uapi/linux/sockios.h in the kernel code.
 const SIOCGIFFLAGS: u64 = 0x8913;
 const SIOCGIFHWADDR: u64 = 0x8927;
+const SIOCGIFINDEX: u64 = 0x8933;
 const SIOCSIFFLAGS: u64 = 0x8914;
 const SIOCSIFADDR: u64 = 0x8916;
 const SIOCSIFHWADDR: u64 = 0x8924;
 const SIOCSIFNETMASK: u64 = 0x891c;
+const SIOCSIFMTU: u64 = 0x8922;
+
+// See include/uapi/linux/vhost.h in the kernel code.
+const VHOST_GET_FEATURES: u64 = 0x8008_af00;
+const VHOST_SET_FEATURES: u64 = 0x4008_af00;
+const VHOST_SET_OWNER: u64 = 0xaf01;
+const VHOST_SET_MEM_TABLE: u64 = 0x4008_af03;
+const VHOST_SET_VRING_NUM: u64 = 0x4008_af10;
+const VHOST_SET_VRING_ADDR: u64 = 0x4028_af11;
+const VHOST_SET_VRING_BASE: u64 = 0x4008_af12;
+const VHOST_SET_VRING_KICK: u64 = 0x4008_af20;
+const VHOST_SET_VRING_CALL: u64 = 0x4008_af21;
+const VHOST_NET_SET_BACKEND: u64 = 0x4008_af30;
+const VHOST_VSOCK_SET_GUEST_CID: u64 = 0x4008_af60;
+const VHOST_VSOCK_SET_RUNNING: u64 = 0x4004_af61;
 
 // See include/uapi/linux/vfio.h in the kernel code.
 const VFIO_GET_API_VERSION: u64 = 0x3b64;
@@ -152,10 +186,12 @@ fn create_vmm_ioctl_seccomp_rule_common() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_VCPU_EVENTS,)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, SIOCGIFFLAGS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, SIOCGIFHWADDR)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, SIOCGIFINDEX)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, SIOCSIFADDR)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, SIOCSIFFLAGS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, SIOCSIFHWADDR)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, SIOCSIFNETMASK)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, SIOCSIFMTU)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TCSETS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TCGETS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TIOCGWINSZ)?],
@@ -166,6 +202,18 @@ fn create_vmm_ioctl_seccomp_rule_common() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETIFF)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETOFFLOAD)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETVNETHDRSZ)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_GET_FEATURES)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_FEATURES)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_OWNER)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_MEM_TABLE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_NUM)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_ADDR)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_BASE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_KICK)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_CALL)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_NET_SET_BACKEND)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_VSOCK_SET_GUEST_CID)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_VSOCK_SET_RUNNING)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_GET_API_VERSION)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_CHECK_EXTENSION)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_SET_IOMMU)?],
@@ -205,12 +253,16 @@ fn create_vmm_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
     const KVM_SET_CLOCK: u64 = 0x4030_ae7b;
     const KVM_SET_CPUID2: u64 = 0x4008_ae90;
     const KVM_SET_FPU: u64 = 0x41a0_ae8d;
+    #[cfg(feature = "gdb")]
+    const KVM_SET_GUEST_DEBUG: u64 = 0x4048_ae9b;
     const KVM_SET_LAPIC: u64 = 0x4400_ae8f;
     const KVM_SET_MSRS: u64 = 0x4008_ae89;
     const KVM_SET_SREGS: u64 = 0x4138_ae84;
     const KVM_SET_TSS_ADDR: u64 = 0xae47;
     const KVM_SET_XCRS: u64 = 0x4188_aea7;
     const KVM_SET_XSAVE: u64 = 0x5000_aea5;
+    #[cfg(feature = "gdb")]
+    const KVM_TRANSLATE: u64 = 0xc018_ae85;
 
     let common_rules = create_vmm_ioctl_seccomp_rule_common()?;
     let mut arch_rules = or![
@@ -235,6 +287,11 @@ fn create_vmm_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_XCRS,)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_XSAVE,)?],
     ];
+    #[cfg(feature = "gdb")]
+    arch_rules.extend(or![
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_GUEST_DEBUG)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_TRANSLATE)?],
+    ]);
     arch_rules.extend(common_rules);
 
     Ok(arch_rules)
@@ -366,16 +423,20 @@ fn vmm_thread_rules() -> Result<Vec<SyscallRuleSet>, Error> {
         allow_syscall(libc::SYS_rt_sigprocmask),
         allow_syscall(libc::SYS_rt_sigreturn),
         allow_syscall(libc::SYS_sched_getaffinity),
+        allow_syscall(libc::SYS_sched_setaffinity),
+        allow_syscall(libc::SYS_sched_setscheduler),
         allow_syscall(libc::SYS_sendmsg),
         allow_syscall(libc::SYS_sendto),
         allow_syscall(libc::SYS_set_robust_list),
         allow_syscall(libc::SYS_set_tid_address),
+        allow_syscall(libc::SYS_setsockopt),
         allow_syscall(libc::SYS_sigaltstack),
         allow_syscall_if(
             libc::SYS_socket,
             or![
                 and![Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?],
                 and![Cond::new(0, ArgLen::DWORD, Eq, libc::AF_INET as u64)?],
+                and![Cond::new(0, ArgLen::DWORD, Eq, libc::AF_NETLINK as u64)?],
             ],
         ),
         allow_syscall(libc::SYS_socketpair),
@@ -412,6 +473,8 @@ fn create_vcpu_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_DEVICE_SET_IRQS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_GROUP_UNSET_CONTAINER)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_IOMMU_UNMAP_DMA)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_NET_SET_BACKEND)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_VSOCK_SET_RUNNING)?],
     ])
 }
 
@@ -470,6 +533,7 @@ fn api_thread_rules() -> Result<Vec<SyscallRuleSet>, Error> {
         #[cfg(target_arch = "x86_64")]
         allow_syscall(libc::SYS_epoll_wait),
         allow_syscall(libc::SYS_exit),
+        allow_syscall(libc::SYS_fcntl),
         allow_syscall(libc::SYS_futex),
         allow_syscall(libc::SYS_getrandom),
         allow_syscall_if(libc::SYS_ioctl, create_api_ioctl_seccomp_rule()?),
@@ -477,31 +541,71 @@ fn api_thread_rules() -> Result<Vec<SyscallRuleSet>, Error> {
         allow_syscall(libc::SYS_mprotect),
         allow_syscall(libc::SYS_munmap),
         allow_syscall(libc::SYS_recvfrom),
+        allow_syscall(libc::SYS_recvmsg),
+        allow_syscall(libc::SYS_sigaltstack),
+        allow_syscall(libc::SYS_write),
+    ])
+}
+
+#[cfg(feature = "gdb")]
+fn create_gdb_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
+    Ok(or![and![Cond::new(1, ArgLen::DWORD, Eq, FIONBIO)?],])
+}
+
+// The filter containing the white listed syscall rules required by the GDB
+// stub to function.
+#[cfg(feature = "gdb")]
+fn gdb_thread_rules() -> Result<Vec<SyscallRuleSet>, Error> {
+    Ok(vec![
+        allow_syscall(libc::SYS_accept4),
+        allow_syscall(libc::SYS_brk),
+        allow_syscall(libc::SYS_close),
+        allow_syscall(libc::SYS_exit),
+        allow_syscall(libc::SYS_fcntl),
+        allow_syscall(libc::SYS_futex),
+        allow_syscall(libc::SYS_getrandom),
+        allow_syscall_if(libc::SYS_ioctl, create_gdb_ioctl_seccomp_rule()?),
+        allow_syscall(libc::SYS_madvise),
+        allow_syscall(libc::SYS_mmap),
+        allow_syscall(libc::SYS_mprotect),
+        allow_syscall(libc::SYS_munmap),
+        allow_syscall(libc::SYS_read),
+        allow_syscall(libc::SYS_recvfrom),
         allow_syscall(libc::SYS_sigaltstack),
         allow_syscall(libc::SYS_write),
     ])
 }
 
-fn get_seccomp_filter_trap(thread_type: Thread) -> Result<SeccompFilter, Error> {
+fn get_seccomp_filter_trap(thread_type: &Thread) -> Result<SeccompFilter, Error> {
     let rules = match thread_type {
         Thread::Api => api_thread_rules()?,
+        #[cfg(feature = "gdb")]
+        Thread::Gdb => gdb_thread_rules()?,
         Thread::SignalHandler => signal_handler_thread_rules()?,
         Thread::Vcpu => vcpu_thread_rules()?,
         Thread::Vmm => vmm_thread_rules()?,
     };
 
-    SeccompFilter::new(rules.into_iter().collect(), SeccompAction::Trap)
+    SeccompFilter::new(
+        apply_profile(thread_type.name(), rules)?,
+        SeccompAction::Trap,
+    )
 }
 
-fn get_seccomp_filter_log(thread_type: Thread) -> Result<SeccompFilter, Error> {
+fn get_seccomp_filter_log(thread_type: &Thread) -> Result<SeccompFilter, Error> {
     let rules = match thread_type {
         Thread::Api => api_thread_rules()?,
+        #[cfg(feature = "gdb")]
+        Thread::Gdb => gdb_thread_rules()?,
         Thread::SignalHandler => signal_handler_thread_rules()?,
         Thread::Vcpu => vcpu_thread_rules()?,
         Thread::Vmm => vmm_thread_rules()?,
     };
 
-    SeccompFilter::new(rules.into_iter().collect(), SeccompAction::Log)
+    SeccompFilter::new(
+        apply_profile(thread_type.name(), rules)?,
+        SeccompAction::Log,
+    )
 }
 
 /// Generate a BPF program based on the seccomp_action value
@@ -511,11 +615,15 @@ pub fn get_seccomp_filter(
 ) -> Result<BpfProgram, SeccompError> {
     match seccomp_action {
         SeccompAction::Allow => Ok(vec![]),
-        SeccompAction::Log => get_seccomp_filter_log(thread_type)
+        SeccompAction::Log => get_seccomp_filter_log(&thread_type)
             .and_then(|filter| filter.try_into())
             .map_err(SeccompError::SeccompFilter),
-        _ => get_seccomp_filter_trap(thread_type)
+        _ => get_seccomp_filter_trap(&thread_type)
             .and_then(|filter| filter.try_into())
+            .map(|mut program: BpfProgram| {
+                tag_trap(&mut program, thread_type.name());
+                program
+            })
             .map_err(SeccompError::SeccompFilter),
     }
 }
@@ -14,14 +14,19 @@
 #[cfg(feature = "acpi")]
 use crate::config::NumaConfig;
 use crate::config::{
-    DeviceConfig, DiskConfig, FsConfig, HotplugMethod, NetConfig, PmemConfig, ValidationError,
-    VmConfig, VsockConfig,
+    CpuAffinity, DeviceConfig, DiskConfig, FsConfig, GenericVhostUserConfig, HotplugMethod,
+    NetConfig, PmemConfig, UserDeviceConfig, ValidationError, VmConfig, VsockConfig,
 };
+use crate::coredump;
 use crate::cpu;
 use crate::device_manager::{
     self, get_win_size, Console, DeviceManager, DeviceManagerError, PtyPair,
 };
 use crate::device_tree::DeviceTree;
+use crate::exit_stats::VcpuExitCounters;
+#[cfg(feature = "gdb")]
+use crate::gdb::{GdbRequestPayload, GdbResponsePayload};
+use crate::landlock;
 use crate::memory_manager::{Error as MemoryManagerError, MemoryManager};
 use crate::migration::{get_vm_snapshot, url_to_path, VM_SNAPSHOT_FILE};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
@@ -51,11 +56,12 @@ use std::convert::TryInto;
 use std::ffi::CString;
 #[cfg(target_arch = "x86_64")]
 use std::fmt;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Write};
 use std::io::{Seek, SeekFrom};
 use std::num::Wrapping;
 use std::ops::Deref;
+use std::path::Path;
 use std::sync::{Arc, Mutex, RwLock};
 use std::{result, str, thread};
 use vm_device::Bus;
@@ -150,6 +156,9 @@ pub enum Error {
     /// Error from CPU handling
     CpuManager(cpu::Error),
 
+    /// Cannot set the host CPU affinity of the VMM thread
+    VmmThreadAffinity(io::Error),
+
     /// Cannot pause devices
     PauseDevices(MigratableError),
 
@@ -246,6 +255,22 @@ pub enum Error {
     /// Error finalizing TDX setup
     #[cfg(feature = "tdx")]
     FinalizeTdx(hypervisor::HypervisorVmError),
+
+    /// Error handling a debugger request
+    #[cfg(feature = "gdb")]
+    Debug(cpu::Error),
+
+    /// Coredump destination is not a file URL
+    InvalidCoredumpUrl(String),
+
+    /// Cannot create the coredump file
+    CoredumpFile(io::Error),
+
+    /// Cannot write the coredump
+    Coredump(coredump::Error),
+
+    /// Error enforcing or checking the Landlock sandbox
+    Landlock(landlock::Error),
 }
 pub type Result<T> = result::Result<T, Error>;
 
@@ -253,7 +278,7 @@ pub type Result<T> = result::Result<T, Error>;
 pub struct NumaNode {
     memory_regions: Vec<Arc<GuestRegionMmap>>,
     hotplug_regions: Vec<Arc<GuestRegionMmap>>,
-    cpus: Vec<u8>,
+    cpus: Vec<u32>,
     distances: BTreeMap<u32, u8>,
     memory_zones: Vec<String>,
 }
@@ -267,7 +292,7 @@ impl NumaNode {
         &self.hotplug_regions
     }
 
-    pub fn cpus(&self) -> &Vec<u8> {
+    pub fn cpus(&self) -> &Vec<u32> {
         &self.cpus
     }
 
@@ -496,6 +521,7 @@ impl Vm {
         vm: Arc<dyn hypervisor::Vm>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
         #[cfg(feature = "kvm")] _saved_clock: Option<hypervisor::ClockData>,
@@ -509,6 +535,11 @@ impl Vm {
 
         info!("Booting VM from config: {:?}", &config);
 
+        // The device worker and I/O threads are spawned from the VMM thread,
+        // inheriting its affinity unless an I/O affinity is specified.
+        cpu::set_vmm_thread_affinity(&config.lock().unwrap().cpus.vmm_affinity)
+            .map_err(Error::VmmThreadAffinity)?;
+
         // Create NUMA nodes based on NumaConfig.
         #[cfg(feature = "acpi")]
         let numa_nodes =
@@ -550,6 +581,8 @@ impl Vm {
             vm.clone(),
             exit_evt_clone,
             reset_evt,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt,
             hypervisor,
             seccomp_action.clone(),
             vm_ops,
@@ -666,6 +699,7 @@ impl Vm {
         config: Arc<Mutex<VmConfig>>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
         activate_evt: EventFd,
@@ -716,6 +750,8 @@ impl Vm {
             vm,
             exit_evt,
             reset_evt,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt,
             seccomp_action,
             hypervisor,
             #[cfg(feature = "kvm")]
@@ -739,6 +775,7 @@ impl Vm {
         snapshot: &Snapshot,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
         source_url: Option<&str>,
         prefault: bool,
         seccomp_action: &SeccompAction,
@@ -781,6 +818,8 @@ impl Vm {
             vm,
             exit_evt,
             reset_evt,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt,
             seccomp_action,
             hypervisor,
             #[cfg(feature = "kvm")]
@@ -793,6 +832,7 @@ impl Vm {
         config: Arc<Mutex<VmConfig>>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
         activate_evt: EventFd,
@@ -819,6 +859,8 @@ impl Vm {
             vm,
             exit_evt,
             reset_evt,
+            #[cfg(feature = "gdb")]
+            vm_debug_evt,
             seccomp_action,
             hypervisor,
             #[cfg(feature = "kvm")]
@@ -1102,7 +1144,7 @@ impl Vm {
 
     pub fn resize(
         &mut self,
-        desired_vcpus: Option<u8>,
+        desired_vcpus: Option<u32>,
         desired_memory: Option<u64>,
         desired_balloon: Option<u64>,
     ) -> Result<()> {
@@ -1263,6 +1305,37 @@ impl Vm {
         Ok(pci_device_info)
     }
 
+    pub fn add_user_device(&mut self, mut device_cfg: UserDeviceConfig) -> Result<PciDeviceInfo> {
+        {
+            // Validate on a clone of the config
+            let mut config = self.config.lock().unwrap().clone();
+            Self::add_to_config(&mut config.user_devices, device_cfg.clone());
+            config.validate().map_err(Error::ConfigValidation)?;
+        }
+
+        let pci_device_info = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .add_user_device(&mut device_cfg)
+            .map_err(Error::DeviceManager)?;
+
+        // Update VmConfig by adding the new device. This is important to
+        // ensure the device would be created in case of a reboot.
+        {
+            let mut config = self.config.lock().unwrap();
+            Self::add_to_config(&mut config.user_devices, device_cfg);
+        }
+
+        self.device_manager
+            .lock()
+            .unwrap()
+            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+            .map_err(Error::DeviceManager)?;
+
+        Ok(pci_device_info)
+    }
+
     pub fn remove_device(&mut self, _id: String) -> Result<()> {
         self.device_manager
             .lock()
@@ -1279,6 +1352,11 @@ impl Vm {
             devices.retain(|dev| dev.id.as_ref() != Some(&_id));
         }
 
+        // Remove if vfio-user device
+        if let Some(user_devices) = config.user_devices.as_mut() {
+            user_devices.retain(|dev| dev.id.as_ref() != Some(&_id));
+        }
+
         // Remove if disk device
         if let Some(disks) = config.disks.as_mut() {
             disks.retain(|dev| dev.id.as_ref() != Some(&_id));
@@ -1294,6 +1372,11 @@ impl Vm {
             pmem.retain(|dev| dev.id.as_ref() != Some(&_id));
         }
 
+        // Remove if generic vhost-user device
+        if let Some(generic_vhost_user) = config.generic_vhost_user.as_mut() {
+            generic_vhost_user.retain(|dev| dev.id.as_ref() != Some(&_id));
+        }
+
         // Remove if vsock device
         if let Some(vsock) = config.vsock.as_ref() {
             if vsock.id.as_ref() == Some(&_id) {
@@ -1371,6 +1454,43 @@ impl Vm {
         Ok(pci_device_info)
     }
 
+    pub fn add_generic_vhost_user(
+        &mut self,
+        mut _generic_vhost_user_cfg: GenericVhostUserConfig,
+    ) -> Result<PciDeviceInfo> {
+        {
+            // Validate on a clone of the config
+            let mut config = self.config.lock().unwrap().clone();
+            Self::add_to_config(
+                &mut config.generic_vhost_user,
+                _generic_vhost_user_cfg.clone(),
+            );
+            config.validate().map_err(Error::ConfigValidation)?;
+        }
+
+        let pci_device_info = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .add_generic_vhost_user(&mut _generic_vhost_user_cfg)
+            .map_err(Error::DeviceManager)?;
+
+        // Update VmConfig by adding the new device. This is important to
+        // ensure the device would be created in case of a reboot.
+        {
+            let mut config = self.config.lock().unwrap();
+            Self::add_to_config(&mut config.generic_vhost_user, _generic_vhost_user_cfg);
+        }
+
+        self.device_manager
+            .lock()
+            .unwrap()
+            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+            .map_err(Error::DeviceManager)?;
+
+        Ok(pci_device_info)
+    }
+
     pub fn add_pmem(&mut self, mut _pmem_cfg: PmemConfig) -> Result<PciDeviceInfo> {
         {
             // Validate on a clone of the config
@@ -1472,6 +1592,88 @@ impl Vm {
         Ok(self.device_manager.lock().unwrap().counters())
     }
 
+    pub fn vcpu_counters(&self) -> BTreeMap<u32, VcpuExitCounters> {
+        self.cpu_manager.lock().unwrap().exit_counters()
+    }
+
+    pub fn net_capture(&self, id: &str, path: Option<&Path>, max_size: Option<u64>) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .net_capture(id, path, max_size)
+            .map_err(Error::DeviceManager)
+    }
+
+    pub fn net_link(&self, id: &str, up: bool) -> Result<()> {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .net_link(id, up)
+            .map_err(Error::DeviceManager)
+    }
+
+    pub fn set_cpu_affinity(&mut self, affinity: &[CpuAffinity]) -> Result<()> {
+        let mut cpu_manager = self.cpu_manager.lock().unwrap();
+        cpu_manager
+            .set_affinity(affinity)
+            .map_err(Error::CpuManager)?;
+        self.config.lock().unwrap().cpus.affinity = cpu_manager.affinity();
+
+        Ok(())
+    }
+
+    /// Write an ELF core file of the guest, pausing it for the time of the
+    /// dump if it is running.
+    pub fn coredump(&mut self, destination_url: &str) -> Result<()> {
+        let path = destination_url
+            .strip_prefix("file://")
+            .ok_or_else(|| Error::InvalidCoredumpUrl(destination_url.to_string()))?;
+        coredump::check_architecture().map_err(Error::Coredump)?;
+
+        let was_running = match self.get_state()? {
+            VmState::Running => true,
+            VmState::Paused => false,
+            _ => return Err(Error::VmNotRunning),
+        };
+        if was_running {
+            self.pause().map_err(Error::Pause)?;
+        }
+
+        let result = self.write_coredump(Path::new(path));
+
+        if was_running {
+            self.resume().map_err(Error::Resume)?;
+        }
+
+        result
+    }
+
+    fn write_coredump(&self, path: &Path) -> Result<()> {
+        event!("vm", "coredumping");
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(Error::CoredumpFile)?;
+
+        // The vCPUs states have been saved when pausing them.
+        let cpu_states = self.cpu_manager.lock().unwrap().get_saved_states();
+        let guest_memory = self.memory_manager.lock().unwrap().guest_memory().memory();
+        if let Err(e) =
+            coredump::write_coredump(&mut io::BufWriter::new(file), &cpu_states, &guest_memory)
+        {
+            // Don't leave a truncated core file behind.
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Error removing {}: {}", path.display(), e);
+            }
+            return Err(Error::Coredump(e));
+        }
+
+        event!("vm", "coredumped");
+        Ok(())
+    }
+
     fn os_signal_handler(
         mut signals: Signals,
         console_input_clone: Arc<Console>,
@@ -1503,7 +1705,7 @@ impl Vm {
     #[cfg(feature = "tdx")]
     fn init_tdx(&mut self) -> Result<()> {
         let cpuid = self.cpu_manager.lock().unwrap().common_cpuid();
-        let max_vcpus = self.cpu_manager.lock().unwrap().max_vcpus() as u32;
+        let max_vcpus = self.cpu_manager.lock().unwrap().max_vcpus();
         self.vm
             .tdx_init(&cpuid, max_vcpus)
             .map_err(Error::InitializeTdxVm)?;
@@ -1705,10 +1907,16 @@ impl Vm {
             self.vm.tdx_finalize().map_err(Error::FinalizeTdx)?;
         }
 
+        // Under a debugger, the vCPUs wait for it before running any guest code.
+        #[cfg(feature = "gdb")]
+        let stop_on_boot = self.config.lock().unwrap().gdb;
+        #[cfg(not(feature = "gdb"))]
+        let stop_on_boot = false;
+
         self.cpu_manager
             .lock()
             .unwrap()
-            .start_boot_vcpus()
+            .start_boot_vcpus(stop_on_boot)
             .map_err(Error::CpuManager)?;
 
         if self
@@ -1823,6 +2031,12 @@ impl Vm {
         Arc::clone(&self.config)
     }
 
+    /// Get the CPUID shared by all the vCPUs, after the CPU model is applied.
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    pub fn common_cpuid(&self) -> hypervisor::CpuId {
+        self.cpu_manager.lock().unwrap().common_cpuid()
+    }
+
     /// Get the VM state. Returns an error if the state is poisoned.
     pub fn get_state(&self) -> Result<VmState> {
         self.state
@@ -2032,6 +2246,59 @@ impl Vm {
             .notify_power_button()
             .map_err(Error::PowerButton)
     }
+
+    /// Stops all the vCPUs after one of them exited on a debug event, and
+    /// returns the id of that vCPU.
+    #[cfg(feature = "gdb")]
+    pub fn debug_pause(&self) -> u32 {
+        self.cpu_manager.lock().unwrap().debug_pause()
+    }
+
+    #[cfg(feature = "gdb")]
+    pub fn debug_request(
+        &self,
+        gdb_request: &GdbRequestPayload,
+        cpu_id: u32,
+    ) -> Result<GdbResponsePayload> {
+        let cpu_manager = self.cpu_manager.lock().unwrap();
+        match gdb_request {
+            GdbRequestPayload::Pause => {
+                cpu_manager.debug_pause();
+                Ok(GdbResponsePayload::CommandComplete)
+            }
+            GdbRequestPayload::Resume => {
+                cpu_manager.debug_resume();
+                Ok(GdbResponsePayload::CommandComplete)
+            }
+            GdbRequestPayload::SetGuestDebug {
+                hw_breakpoints,
+                sw_breakpoints,
+                single_step,
+            } => cpu_manager
+                .set_guest_debug(cpu_id, hw_breakpoints, *sw_breakpoints, *single_step)
+                .map(|_| GdbResponsePayload::CommandComplete)
+                .map_err(Error::Debug),
+            GdbRequestPayload::ReadRegs => cpu_manager
+                .read_regs(cpu_id)
+                .map(|regs| GdbResponsePayload::RegValues(Box::new(regs)))
+                .map_err(Error::Debug),
+            GdbRequestPayload::WriteRegs(regs) => cpu_manager
+                .write_regs(cpu_id, regs)
+                .map(|_| GdbResponsePayload::CommandComplete)
+                .map_err(Error::Debug),
+            GdbRequestPayload::ReadMem(vaddr, len) => cpu_manager
+                .read_mem(cpu_id, *vaddr, *len)
+                .map(GdbResponsePayload::MemoryRegion)
+                .map_err(Error::Debug),
+            GdbRequestPayload::WriteMem(vaddr, data) => cpu_manager
+                .write_mem(cpu_id, *vaddr, data)
+                .map(|_| GdbResponsePayload::CommandComplete)
+                .map_err(Error::Debug),
+            GdbRequestPayload::ActiveVcpus => {
+                Ok(GdbResponsePayload::ActiveVcpus(cpu_manager.active_vcpus()))
+            }
+        }
+    }
 }
 
 impl Pausable for Vm {
@@ -2102,6 +2369,9 @@ pub struct VmSnapshot {
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
     pub clock: Option<hypervisor::ClockData>,
     pub state: Option<hypervisor::VmState>,
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    #[serde(default)]
+    pub common_cpuid: Option<hypervisor::CpuId>,
 }
 
 pub const VM_SNAPSHOT_ID: &str = "vm";
@@ -2139,6 +2409,8 @@ impl Snapshottable for Vm {
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
             clock: self.saved_clock,
             state: Some(vm_state),
+            #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+            common_cpuid: Some(self.common_cpuid()),
         })
         .map_err(|e| MigratableError::Snapshot(e.into()))?;
 